@@ -47,12 +47,13 @@ impl<A: Anchor, I: Indexer> IndexedTxGraph<A, I> {
     pub fn apply_changeset(&mut self, changeset: ChangeSet<A, I::ChangeSet>) {
         self.index.apply_changeset(changeset.indexer);
 
-        for tx in &changeset.graph.txs {
-            self.index.index_tx(tx);
-        }
+        // floating txouts are indexed first, as they may be spent by the transactions
         for (&outpoint, txout) in &changeset.graph.txouts {
             self.index.index_txout(outpoint, txout);
         }
+        for tx in &changeset.graph.txs {
+            self.index.index_tx(tx);
+        }
 
         self.graph.apply_changeset(changeset.graph);
     }
@@ -74,12 +75,13 @@ where
         tx_graph_changeset: &tx_graph::ChangeSet<A>,
     ) -> I::ChangeSet {
         let mut changeset = I::ChangeSet::default();
-        for added_tx in &tx_graph_changeset.txs {
-            changeset.append(self.index.index_tx(added_tx));
-        }
+        // floating txouts are indexed first, as they may be spent by the added transactions
         for (&added_outpoint, added_txout) in &tx_graph_changeset.txouts {
             changeset.append(self.index.index_txout(added_outpoint, added_txout));
         }
+        for added_tx in &tx_graph_changeset.txs {
+            changeset.append(self.index.index_tx(added_tx));
+        }
         changeset
     }
 
@@ -29,6 +29,7 @@ pub mod indexed_tx_graph;
 pub use indexed_tx_graph::IndexedTxGraph;
 pub mod keychain;
 pub mod local_chain;
+pub mod silent_payments;
 mod tx_data_traits;
 pub mod tx_graph;
 pub use tx_data_traits::*;
@@ -0,0 +1,518 @@
+//! Module for detecting [BIP352] silent payment outputs.
+//!
+//! The outputs paying a silent payment address don't have a script pubkey known in advance: their
+//! key is derived from the scan and spend keys of the receiver, and from the public keys of the
+//! inputs of the transaction. [`SilentPaymentIndex`] is an [`Indexer`] that scans transactions for
+//! the outputs paying a scan and a spend key, and records the tweak of the spend key needed to
+//! spend them.
+//!
+//! The public keys of the inputs are taken from the outputs they spend, so the prevouts of a
+//! transaction must be known by the index to scan the transaction. They are the outputs of the
+//! transactions indexed with [`Indexer::index_tx`], the floating txouts indexed with
+//! [`Indexer::index_txout`], e.g. by [`IndexedTxGraph::insert_txout`], and the outputs inserted
+//! with [`SilentPaymentIndex::insert_prevouts`]. The prevouts are forgotten once the transaction
+//! spending them is scanned: those of a transaction replacing a scanned one must be inserted
+//! again.
+//!
+//! The transactions spending an unknown output are reported by
+//! [`SilentPaymentIndex::skipped_txs`], and are scanned as soon as their prevouts are known, so
+//! transactions can be indexed in any order.
+//!
+//! Like the [`ChangeSet`] of a [`KeychainTxOutIndex`], the [`ChangeSet`] of the index is part of
+//! the [`indexed_tx_graph::ChangeSet`] of the graph, and is persisted with it.
+//!
+//! [BIP352]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+//! [`IndexedTxGraph::insert_txout`]: crate::IndexedTxGraph::insert_txout
+//! [`KeychainTxOutIndex`]: crate::keychain::KeychainTxOutIndex
+//! [`indexed_tx_graph::ChangeSet`]: crate::indexed_tx_graph::ChangeSet
+
+use crate::{collections::*, indexed_tx_graph::Indexer, Append};
+use alloc::vec::Vec;
+use bitcoin::{
+    consensus::encode::serialize,
+    hashes::{hash160, sha256, Hash, HashEngine},
+    key::Parity,
+    script::Instruction,
+    secp256k1::{All, PublicKey, Scalar, Secp256k1, SecretKey, XOnlyPublicKey},
+    OutPoint, Script, Transaction, TxIn, TxOut, Txid,
+};
+
+/// The x-coordinate of the point `H` of BIP341, which has no known discrete logarithm.
+///
+/// Inputs spending a taproot script path with this internal key are not used by BIP352.
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// Represents updates to a [`SilentPaymentIndex`].
+///
+/// It can be applied to [`SilentPaymentIndex`] with [`Indexer::apply_changeset`].
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(crate = "serde_crate")
+)]
+#[must_use]
+pub struct ChangeSet {
+    /// The labels added to the index.
+    pub labels: BTreeSet<u32>,
+    /// The outputs found by the index.
+    pub outputs: BTreeMap<OutPoint, SilentPaymentOutput>,
+}
+
+impl Append for ChangeSet {
+    fn append(&mut self, other: Self) {
+        self.labels.extend(other.labels);
+        self.outputs.extend(other.outputs);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.outputs.is_empty()
+    }
+}
+
+/// An output paying the keys of a [`SilentPaymentIndex`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(crate = "serde_crate")
+)]
+pub struct SilentPaymentOutput {
+    /// The tweak added to the private spend key to get the private key of the output.
+    pub tweak: SecretKey,
+    /// The label of the address paid by the output, if any.
+    pub label: Option<u32>,
+}
+
+impl SilentPaymentOutput {
+    /// The private key of the output, given the private spend key of the receiver.
+    ///
+    /// The output is spent with a key path spend signed by this key, without the taproot tweak of
+    /// BIP341.
+    pub fn secret_key(&self, spend_secret: &SecretKey) -> SecretKey {
+        spend_secret
+            .add_tweak(&Scalar::from(self.tweak))
+            .expect("the tweak is a hash, it can't be the negation of the key")
+    }
+}
+
+/// An [`Indexer`] of the outputs paying a scan and a spend key as described by [BIP352].
+///
+/// Outputs paying a labelled address are found once the label is added with
+/// [`add_label`](Self::add_label). A transaction is relevant to the index if it has an output
+/// found by the index, or if it spends one.
+///
+/// See the [module-level documentation](self) for the prevouts required to scan a transaction.
+///
+/// [BIP352]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+#[derive(Clone, Debug)]
+pub struct SilentPaymentIndex {
+    scan_secret: SecretKey,
+    spend_key: PublicKey,
+    // public key of the tweak of a label -> label
+    labels: BTreeMap<PublicKey, u32>,
+    // outputs found by the index
+    outputs: BTreeMap<OutPoint, SilentPaymentOutput>,
+    // txouts of the outputs found by the index
+    txouts: HashMap<OutPoint, TxOut>,
+    // outputs that may be spent by the transactions to scan
+    prevouts: HashMap<OutPoint, TxOut>,
+    // transactions not scanned because an output they spend is unknown
+    skipped: BTreeMap<Txid, Transaction>,
+    secp: Secp256k1<All>,
+}
+
+impl Indexer for SilentPaymentIndex {
+    type ChangeSet = ChangeSet;
+
+    fn index_txout(&mut self, outpoint: OutPoint, txout: &TxOut) -> Self::ChangeSet {
+        self.prevouts.insert(outpoint, txout.clone());
+        self.scan_skipped()
+    }
+
+    fn index_tx(&mut self, tx: &Transaction) -> Self::ChangeSet {
+        let mut changeset = self.index_tx_outputs(tx);
+        changeset.append(self.scan_skipped());
+        changeset
+    }
+
+    fn apply_changeset(&mut self, changeset: Self::ChangeSet) {
+        for label in changeset.labels {
+            let _ = self.add_label(label);
+        }
+        self.outputs.extend(changeset.outputs);
+    }
+
+    fn initial_changeset(&self) -> Self::ChangeSet {
+        ChangeSet {
+            labels: self.labels.values().copied().collect(),
+            outputs: self.outputs.clone(),
+        }
+    }
+
+    fn is_tx_relevant(&self, tx: &Transaction) -> bool {
+        let txid = tx.txid();
+        (0..tx.output.len() as u32)
+            .any(|vout| self.outputs.contains_key(&OutPoint::new(txid, vout)))
+            || tx
+                .input
+                .iter()
+                .any(|txin| self.outputs.contains_key(&txin.previous_output))
+    }
+}
+
+impl SilentPaymentIndex {
+    /// Construct a [`SilentPaymentIndex`] of the outputs paying `spend_key`, scanned with
+    /// `scan_secret`.
+    pub fn new(scan_secret: SecretKey, spend_key: PublicKey) -> Self {
+        Self {
+            scan_secret,
+            spend_key,
+            labels: BTreeMap::new(),
+            outputs: BTreeMap::new(),
+            txouts: HashMap::new(),
+            prevouts: HashMap::new(),
+            skipped: BTreeMap::new(),
+            secp: Secp256k1::new(),
+        }
+    }
+
+    /// The public scan key of the index.
+    pub fn scan_key(&self) -> PublicKey {
+        self.scan_secret.public_key(&self.secp)
+    }
+
+    /// The public spend key of the index.
+    pub fn spend_key(&self) -> PublicKey {
+        self.spend_key
+    }
+
+    /// Add a `label`, so that outputs paying the address labelled with it are found.
+    ///
+    /// BIP352 reserves the label `0` for change outputs.
+    pub fn add_label(&mut self, label: u32) -> ChangeSet {
+        let mut changeset = ChangeSet::default();
+        let label_key = self.label_tweak(label).public_key(&self.secp);
+        if self.labels.insert(label_key, label).is_none() {
+            changeset.labels.insert(label);
+        }
+        changeset
+    }
+
+    /// The spend key of the address labelled with `label`.
+    pub fn label_spend_key(&self, label: u32) -> PublicKey {
+        self.spend_key
+            .add_exp_tweak(&self.secp, &Scalar::from(self.label_tweak(label)))
+            .expect("the tweak is a hash, it can't be the negation of the key")
+    }
+
+    /// Iterate over the labels of the index.
+    pub fn labels(&self) -> impl Iterator<Item = u32> + '_ {
+        self.labels.values().copied()
+    }
+
+    /// Insert the outputs spent by transactions to scan.
+    ///
+    /// The skipped transactions spending them are scanned.
+    pub fn insert_prevouts(
+        &mut self,
+        prevouts: impl IntoIterator<Item = (OutPoint, TxOut)>,
+    ) -> ChangeSet {
+        self.prevouts.extend(prevouts);
+        self.scan_skipped()
+    }
+
+    /// Iterate over the transactions that were not scanned because an output they spend is
+    /// unknown.
+    ///
+    /// They are scanned once their prevouts are known, e.g. when they are inserted with
+    /// [`insert_prevouts`](Self::insert_prevouts).
+    pub fn skipped_txs(&self) -> impl DoubleEndedIterator<Item = Txid> + '_ {
+        self.skipped.keys().copied()
+    }
+
+    /// Iterate over the outputs found by the index.
+    pub fn outputs(&self) -> impl DoubleEndedIterator<Item = (OutPoint, &SilentPaymentOutput)> {
+        self.outputs
+            .iter()
+            .map(|(outpoint, output)| (*outpoint, output))
+    }
+
+    /// Returns the output found by the index at `outpoint`, if any.
+    pub fn output(&self, outpoint: OutPoint) -> Option<&SilentPaymentOutput> {
+        self.outputs.get(&outpoint)
+    }
+
+    /// Returns the txout of the output found by the index at `outpoint`, if its transaction was
+    /// indexed.
+    pub fn txout(&self, outpoint: OutPoint) -> Option<&TxOut> {
+        self.txouts.get(&outpoint)
+    }
+
+    /// Record the outputs of `tx` as prevouts and scan it, unless its outputs were already found.
+    ///
+    /// Returns the outputs found by the scan.
+    fn index_tx_outputs(&mut self, tx: &Transaction) -> ChangeSet {
+        let txid = tx.txid();
+        let mut changeset = ChangeSet::default();
+        let mut scanned = false;
+        for (vout, txout) in tx.output.iter().enumerate() {
+            let outpoint = OutPoint::new(txid, vout as u32);
+            if self.outputs.contains_key(&outpoint) {
+                self.txouts.insert(outpoint, txout.clone());
+                scanned = true;
+            } else {
+                self.prevouts.insert(outpoint, txout.clone());
+            }
+        }
+        let found = if scanned {
+            Vec::new()
+        } else {
+            match self.scan(tx) {
+                Some(found) => found,
+                None => {
+                    self.skipped.insert(txid, tx.clone());
+                    return changeset;
+                }
+            }
+        };
+        self.skipped.remove(&txid);
+        // the spent outputs are no longer needed once the transaction is scanned
+        for txin in &tx.input {
+            self.prevouts.remove(&txin.previous_output);
+        }
+        for (vout, output) in found {
+            let outpoint = OutPoint::new(txid, vout);
+            let txout = self
+                .prevouts
+                .remove(&outpoint)
+                .expect("outputs of the transaction are recorded");
+            self.txouts.insert(outpoint, txout);
+            self.outputs.insert(outpoint, output);
+            changeset.outputs.insert(outpoint, output);
+        }
+        changeset
+    }
+
+    /// Scan the skipped transactions whose prevouts are now known.
+    fn scan_skipped(&mut self) -> ChangeSet {
+        let mut changeset = ChangeSet::default();
+        loop {
+            let ready = self
+                .skipped
+                .values()
+                .filter(|tx| {
+                    tx.input.iter().all(|txin| {
+                        self.prevouts.contains_key(&txin.previous_output)
+                            || self.txouts.contains_key(&txin.previous_output)
+                    })
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            if ready.is_empty() {
+                return changeset;
+            }
+            for tx in ready {
+                changeset.append(self.index_tx_outputs(&tx));
+            }
+        }
+    }
+
+    /// The tweak of the spend key of the address labelled with `label`.
+    fn label_tweak(&self, label: u32) -> SecretKey {
+        let hash = tagged_hash(
+            "BIP0352/Label",
+            &[&self.scan_secret.secret_bytes(), &label.to_be_bytes()],
+        );
+        SecretKey::from_slice(&hash).expect("a hash is a valid secret key")
+    }
+
+    /// Scan `tx`, returning the outputs paying the keys of the index by their vout.
+    ///
+    /// Returns `None` if an output spent by `tx` is unknown.
+    fn scan(&self, tx: &Transaction) -> Option<Vec<(u32, SilentPaymentOutput)>> {
+        let mut found = Vec::new();
+        let mut outputs = tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, txout)| txout.script_pubkey.is_p2tr())
+            .filter_map(|(vout, txout)| {
+                let key = XOnlyPublicKey::from_slice(&txout.script_pubkey.as_bytes()[2..]).ok()?;
+                Some((vout as u32, key))
+            })
+            .collect::<Vec<_>>();
+        // coinbase transactions don't spend any output
+        if outputs.is_empty() || tx.is_coinbase() {
+            return Some(found);
+        }
+
+        let mut input_keys = Vec::new();
+        for txin in &tx.input {
+            let prevout = self
+                .prevouts
+                .get(&txin.previous_output)
+                .or_else(|| self.txouts.get(&txin.previous_output))?;
+            // transactions spending future segwit versions are not used by BIP352
+            if matches!(prevout.script_pubkey.witness_version(), Some(version) if version.to_num() > 1)
+            {
+                return Some(found);
+            }
+            input_keys.extend(input_public_key(txin, prevout));
+        }
+        let input_key = match PublicKey::combine_keys(&input_keys.iter().collect::<Vec<_>>()) {
+            Ok(input_key) => input_key,
+            Err(_) => return Some(found),
+        };
+        let shared_secret = input_hash(
+            tx.input.iter().map(|txin| &txin.previous_output),
+            &input_key,
+        )
+        .and_then(|input_hash| input_key.mul_tweak(&self.secp, &input_hash).ok())
+        .and_then(|key| {
+            key.mul_tweak(&self.secp, &Scalar::from(self.scan_secret))
+                .ok()
+        });
+        let shared_secret = match shared_secret {
+            Some(shared_secret) => shared_secret,
+            None => return Some(found),
+        };
+
+        for k in 0.. {
+            let tweak = match output_tweak(&shared_secret, k) {
+                Some(tweak) => tweak,
+                None => break,
+            };
+            let output_key = self
+                .spend_key
+                .add_exp_tweak(&self.secp, &Scalar::from(tweak))
+                .expect("the tweak is a hash, it can't be the negation of the key");
+            let negated_output_key = output_key.negate(&self.secp);
+
+            let matched = outputs.iter().enumerate().find_map(|(i, (_, key))| {
+                if *key == output_key.x_only_public_key().0 {
+                    return Some((i, tweak, None));
+                }
+                // the key of a labelled output is `output_key + label_key`, the output only
+                // commits to its x-coordinate
+                let key = PublicKey::from_x_only_public_key(*key, Parity::Even);
+                [key, key.negate(&self.secp)].iter().find_map(|key| {
+                    let label_key = key.combine(&negated_output_key).ok()?;
+                    let &label = self.labels.get(&label_key)?;
+                    let label_tweak = Scalar::from(self.label_tweak(label));
+                    Some((i, tweak.add_tweak(&label_tweak).ok()?, Some(label)))
+                })
+            });
+            match matched {
+                Some((i, tweak, label)) => {
+                    let (vout, _) = outputs.remove(i);
+                    found.push((vout, SilentPaymentOutput { tweak, label }));
+                }
+                None => break,
+            }
+        }
+        Some(found)
+    }
+}
+
+/// The `input_hash` of BIP352, committing to the smallest of the `outpoints` spent by a
+/// transaction and to `input_key`, the sum of the public keys of its inputs.
+///
+/// Returns `None` if `outpoints` is empty, or in the negligible case the hash is not a valid
+/// scalar.
+pub fn input_hash<'a>(
+    outpoints: impl IntoIterator<Item = &'a OutPoint>,
+    input_key: &PublicKey,
+) -> Option<Scalar> {
+    let smallest_outpoint = outpoints.into_iter().map(serialize).min()?;
+    let hash = tagged_hash(
+        "BIP0352/Inputs",
+        &[&smallest_outpoint, &input_key.serialize()],
+    );
+    Scalar::from_be_bytes(hash).ok()
+}
+
+/// The tweak `t_k` of BIP352, added to the spend key of the receiver to get the key of the `k`-th
+/// output paying it with `shared_secret`.
+///
+/// Returns `None` in the negligible case the hash is not a valid private key.
+pub fn output_tweak(shared_secret: &PublicKey, k: u32) -> Option<SecretKey> {
+    let hash = tagged_hash(
+        "BIP0352/SharedSecret",
+        &[&shared_secret.serialize(), &k.to_be_bytes()],
+    );
+    SecretKey::from_slice(&hash).ok()
+}
+
+/// The public key of `txin` used by BIP352, if it spends an eligible `prevout`.
+fn input_public_key(txin: &TxIn, prevout: &TxOut) -> Option<PublicKey> {
+    let script_pubkey = &prevout.script_pubkey;
+    if script_pubkey.is_p2tr() {
+        let mut witness = txin.witness.iter().collect::<Vec<_>>();
+        // the annex starts with `0x50`
+        if witness.len() > 1 && witness.last()?.first() == Some(&0x50) {
+            witness.pop();
+        }
+        // a script path spend, whose control block contains the internal key
+        if witness.len() > 1 && witness.last()?.get(1..33) == Some(&NUMS_H[..]) {
+            return None;
+        }
+        let key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..]).ok()?;
+        Some(PublicKey::from_x_only_public_key(key, Parity::Even))
+    } else if script_pubkey.is_p2wpkh() {
+        compressed_key(txin.witness.last()?)
+    } else if script_pubkey.is_p2sh() {
+        let mut instructions = txin.script_sig.instructions();
+        let redeem_script = match (instructions.next(), instructions.next()) {
+            (Some(Ok(Instruction::PushBytes(bytes))), None) => Script::from_bytes(bytes.as_bytes()),
+            _ => return None,
+        };
+        if redeem_script.is_p2wpkh() {
+            compressed_key(txin.witness.last()?)
+        } else {
+            None
+        }
+    } else if script_pubkey.is_p2pkh() {
+        // the script sig may be malleated, the key is the last push matching the key hash
+        let pubkey_hash = &script_pubkey.as_bytes()[3..23];
+        let pushes = txin
+            .script_sig
+            .instructions()
+            .filter_map(|instruction| match instruction {
+                Ok(Instruction::PushBytes(bytes)) => Some(bytes.as_bytes()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        pushes
+            .into_iter()
+            .rev()
+            .filter(|bytes| hash160::Hash::hash(bytes).as_byte_array()[..] == *pubkey_hash)
+            .find_map(compressed_key)
+    } else {
+        None
+    }
+}
+
+/// Parse `bytes` as a compressed public key.
+fn compressed_key(bytes: &[u8]) -> Option<PublicKey> {
+    if bytes.len() == 33 {
+        PublicKey::from_slice(bytes).ok()
+    } else {
+        None
+    }
+}
+
+/// The BIP340 tagged hash of the concatenation of `data`.
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    for data in data {
+        engine.input(data);
+    }
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
@@ -0,0 +1,487 @@
+use bdk_chain::{
+    indexed_tx_graph::Indexer,
+    silent_payments::{input_hash, output_tweak, SilentPaymentIndex},
+    Append, BlockId, IndexedTxGraph,
+};
+use bitcoin::{
+    absolute,
+    hashes::Hash,
+    key::{Parity, TweakedPublicKey},
+    script::{Builder, PushBytesBuf},
+    secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey, XOnlyPublicKey},
+    transaction, Amount, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Txid, Witness,
+};
+
+const SCAN_SECRET: [u8; 32] = [0x02; 32];
+const SPEND_SECRET: [u8; 32] = [0x03; 32];
+
+fn secret_key(byte: u8) -> SecretKey {
+    SecretKey::from_slice(&[byte; 32]).unwrap()
+}
+
+fn new_index() -> SilentPaymentIndex {
+    let secp = Secp256k1::new();
+    SilentPaymentIndex::new(
+        SecretKey::from_slice(&SCAN_SECRET).unwrap(),
+        SecretKey::from_slice(&SPEND_SECRET)
+            .unwrap()
+            .public_key(&secp),
+    )
+}
+
+/// The script pubkey of the `k`-th output paying `spend_key` from a transaction spending
+/// `outpoints` with the private keys `input_keys`, as derived by the sender.
+fn output_spk(
+    input_keys: &[SecretKey],
+    outpoints: &[OutPoint],
+    spend_key: PublicKey,
+    k: u32,
+) -> ScriptBuf {
+    let secp = Secp256k1::new();
+    let input_key = input_keys[1..].iter().fold(input_keys[0], |sum, key| {
+        sum.add_tweak(&Scalar::from(*key)).unwrap()
+    });
+    let input_hash = input_hash(outpoints, &input_key.public_key(&secp)).unwrap();
+    let shared_secret = SecretKey::from_slice(&SCAN_SECRET)
+        .unwrap()
+        .public_key(&secp)
+        .mul_tweak(&secp, &Scalar::from(input_key))
+        .unwrap()
+        .mul_tweak(&secp, &input_hash)
+        .unwrap();
+    let tweak = output_tweak(&shared_secret, k).unwrap();
+    let output_key = spend_key
+        .add_exp_tweak(&secp, &Scalar::from(tweak))
+        .unwrap()
+        .x_only_public_key()
+        .0;
+    ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(output_key))
+}
+
+/// The key used by BIP352 for a P2TR output of `key`.
+fn taproot_key(key: SecretKey) -> SecretKey {
+    match key.x_only_public_key(&Secp256k1::new()).1 {
+        Parity::Even => key,
+        Parity::Odd => key.negate(),
+    }
+}
+
+struct Spend {
+    txin: TxIn,
+    prevout: TxOut,
+    // the private key used by BIP352, if the input is eligible
+    key: Option<SecretKey>,
+}
+
+/// The inputs spending a P2WPKH, P2PKH, P2SH-P2WPKH and P2TR output, with dummy signatures.
+fn spends() -> Vec<Spend> {
+    let secp = Secp256k1::new();
+    let signature = [0x30; 71];
+    let keys = [secret_key(0x10), secret_key(0x11), secret_key(0x12)];
+    let pubkeys = keys.map(|key| bitcoin::PublicKey::new(key.public_key(&secp)));
+    let p2wpkh = ScriptBuf::new_p2wpkh(&pubkeys[0].wpubkey_hash().unwrap());
+    let p2sh_p2wpkh_redeem = ScriptBuf::new_p2wpkh(&pubkeys[2].wpubkey_hash().unwrap());
+    let taproot = secret_key(0x13);
+
+    let spend = |vout: u32, prevout: ScriptBuf, script_sig: ScriptBuf, witness: Vec<Vec<u8>>| {
+        (
+            TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([0xaa; 32]), vout),
+                script_sig,
+                witness: Witness::from_slice(&witness),
+                ..Default::default()
+            },
+            TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: prevout,
+            },
+        )
+    };
+    let spends = [
+        spend(
+            0,
+            p2wpkh,
+            ScriptBuf::new(),
+            vec![signature.to_vec(), pubkeys[0].to_bytes()],
+        ),
+        spend(
+            1,
+            ScriptBuf::new_p2pkh(&pubkeys[1].pubkey_hash()),
+            Builder::new()
+                .push_slice(signature)
+                .push_slice(pubkeys[1].inner.serialize())
+                .into_script(),
+            vec![],
+        ),
+        spend(
+            2,
+            ScriptBuf::new_p2sh(&p2sh_p2wpkh_redeem.script_hash()),
+            Builder::new()
+                .push_slice(PushBytesBuf::try_from(p2sh_p2wpkh_redeem.to_bytes()).unwrap())
+                .into_script(),
+            vec![signature.to_vec(), pubkeys[2].to_bytes()],
+        ),
+        spend(
+            3,
+            ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(
+                taproot.x_only_public_key(&secp).0,
+            )),
+            ScriptBuf::new(),
+            vec![[0x01; 64].to_vec()],
+        ),
+    ];
+    spends
+        .into_iter()
+        .zip([keys[0], keys[1], keys[2], taproot_key(taproot)])
+        .map(|((txin, prevout), key)| Spend {
+            txin,
+            prevout,
+            key: Some(key),
+        })
+        .collect()
+}
+
+fn new_tx(spends: &[Spend], output: Vec<TxOut>) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: spends.iter().map(|spend| spend.txin.clone()).collect(),
+        output,
+    }
+}
+
+fn txout(sats: u64, script_pubkey: ScriptBuf) -> TxOut {
+    TxOut {
+        value: Amount::from_sat(sats),
+        script_pubkey,
+    }
+}
+
+#[test]
+fn test_scan_outputs() {
+    let secp = Secp256k1::new();
+    let mut index = new_index();
+    let _ = index.add_label(1);
+    let spends = spends();
+    let keys = spends
+        .iter()
+        .filter_map(|spend| spend.key)
+        .collect::<Vec<_>>();
+    let outpoints = spends
+        .iter()
+        .map(|spend| spend.txin.previous_output)
+        .collect::<Vec<_>>();
+    let _ = index.insert_prevouts(
+        spends
+            .iter()
+            .map(|spend| (spend.txin.previous_output, spend.prevout.clone())),
+    );
+
+    let unrelated = ScriptBuf::new_p2tr(&secp, secret_key(0x20).x_only_public_key(&secp).0, None);
+    let tx = new_tx(
+        &spends,
+        vec![
+            txout(10_000, unrelated),
+            txout(20_000, output_spk(&keys, &outpoints, index.spend_key(), 0)),
+            txout(
+                30_000,
+                output_spk(&keys, &outpoints, index.label_spend_key(1), 1),
+            ),
+        ],
+    );
+    let txid = tx.txid();
+    let changeset = index.index_tx(&tx);
+    assert_eq!(
+        changeset.outputs.keys().copied().collect::<Vec<_>>(),
+        vec![OutPoint::new(txid, 1), OutPoint::new(txid, 2)]
+    );
+    assert_eq!(index.outputs().count(), 2);
+    assert!(index.is_tx_relevant(&tx));
+
+    // the outputs are spent with the private spend key and the tweak
+    let spend_secret = SecretKey::from_slice(&SPEND_SECRET).unwrap();
+    for (vout, label) in [(1, None), (2, Some(1))] {
+        let outpoint = OutPoint::new(txid, vout);
+        let output = index.output(outpoint).unwrap();
+        assert_eq!(output.label, label);
+        let key = output.secret_key(&spend_secret).x_only_public_key(&secp).0;
+        assert_eq!(
+            index.txout(outpoint).unwrap().script_pubkey.as_bytes()[2..],
+            key.serialize()
+        );
+    }
+
+    // scanning again doesn't find the outputs twice
+    assert!(index.index_tx(&tx).outputs.is_empty());
+
+    // a transaction spending an output found by the index is relevant
+    let spending_tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(txid, 1),
+            ..Default::default()
+        }],
+        output: vec![],
+    };
+    assert!(index.is_tx_relevant(&spending_tx));
+}
+
+#[test]
+fn test_scan_requires_labels_and_prevouts() {
+    let spends = spends();
+    let keys = spends
+        .iter()
+        .filter_map(|spend| spend.key)
+        .collect::<Vec<_>>();
+    let outpoints = spends
+        .iter()
+        .map(|spend| spend.txin.previous_output)
+        .collect::<Vec<_>>();
+    let index = new_index();
+    let tx = new_tx(
+        &spends,
+        vec![
+            txout(20_000, output_spk(&keys, &outpoints, index.spend_key(), 0)),
+            txout(
+                30_000,
+                output_spk(&keys, &outpoints, index.label_spend_key(1), 1),
+            ),
+        ],
+    );
+
+    // without the prevouts, the public keys of the inputs are unknown
+    let mut index = new_index();
+    assert!(index.index_tx(&tx).outputs.is_empty());
+    assert!(!index.is_tx_relevant(&tx));
+    assert_eq!(index.skipped_txs().collect::<Vec<_>>(), vec![tx.txid()]);
+
+    // the transaction is scanned once the prevouts are inserted, without the label only the
+    // first output is found
+    let changeset = index.insert_prevouts(
+        spends
+            .iter()
+            .map(|spend| (spend.txin.previous_output, spend.prevout.clone())),
+    );
+    assert_eq!(
+        changeset.outputs.keys().copied().collect::<Vec<_>>(),
+        vec![OutPoint::new(tx.txid(), 0)]
+    );
+    assert_eq!(index.skipped_txs().count(), 0);
+}
+
+#[test]
+fn test_ineligible_inputs() {
+    let secp = Secp256k1::new();
+    let mut spends = spends();
+    // a P2WSH input is not used
+    spends.push(Spend {
+        txin: TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([0xbb; 32]), 0),
+            witness: Witness::from_slice(&[vec![0x30; 71], vec![0x51]]),
+            ..Default::default()
+        },
+        prevout: txout(
+            100_000,
+            ScriptBuf::new_p2wsh(&ScriptBuf::from_bytes(vec![0x51]).wscript_hash()),
+        ),
+        key: None,
+    });
+    // neither is a taproot script path spend with the internal key `H`
+    let nums_h = XOnlyPublicKey::from_slice(&[
+        0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a,
+        0x5e, 0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80,
+        0x3a, 0xc0,
+    ])
+    .unwrap();
+    let control_block = [&[0xc0][..], &nums_h.serialize()].concat();
+    spends.push(Spend {
+        txin: TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([0xbb; 32]), 1),
+            witness: Witness::from_slice(&[vec![0x51], control_block]),
+            ..Default::default()
+        },
+        prevout: txout(
+            100_000,
+            ScriptBuf::new_p2tr(&secp, secret_key(0x30).x_only_public_key(&secp).0, None),
+        ),
+        key: None,
+    });
+
+    let keys = spends
+        .iter()
+        .filter_map(|spend| spend.key)
+        .collect::<Vec<_>>();
+    let outpoints = spends
+        .iter()
+        .map(|spend| spend.txin.previous_output)
+        .collect::<Vec<_>>();
+    let mut index = new_index();
+    let _ = index.insert_prevouts(
+        spends
+            .iter()
+            .map(|spend| (spend.txin.previous_output, spend.prevout.clone())),
+    );
+    let tx = new_tx(
+        &spends,
+        vec![txout(
+            20_000,
+            output_spk(&keys, &outpoints, index.spend_key(), 0),
+        )],
+    );
+    assert_eq!(index.index_tx(&tx).outputs.len(), 1);
+
+    // transactions spending a future segwit version are not scanned
+    spends.push(Spend {
+        txin: TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([0xbb; 32]), 2),
+            ..Default::default()
+        },
+        prevout: txout(
+            100_000,
+            Builder::new()
+                .push_opcode(bitcoin::opcodes::all::OP_PUSHNUM_2)
+                .push_slice([0x01; 32])
+                .into_script(),
+        ),
+        key: None,
+    });
+    let mut index = new_index();
+    let _ = index.insert_prevouts(
+        spends
+            .iter()
+            .map(|spend| (spend.txin.previous_output, spend.prevout.clone())),
+    );
+    let tx = new_tx(
+        &spends,
+        vec![txout(
+            20_000,
+            output_spk(&keys, &outpoints, index.spend_key(), 0),
+        )],
+    );
+    assert!(index.index_tx(&tx).outputs.is_empty());
+}
+
+#[test]
+fn test_scan_spending_unconfirmed_outputs() {
+    let mut spends = spends();
+    let keys = spends
+        .iter()
+        .filter_map(|spend| spend.key)
+        .collect::<Vec<_>>();
+    // the parent pays the outputs spent by the child, its own prevouts are unknown
+    let parent = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([0xbb; 32]), 0),
+            ..Default::default()
+        }],
+        output: spends.iter().map(|spend| spend.prevout.clone()).collect(),
+    };
+    for (vout, spend) in spends.iter_mut().enumerate() {
+        spend.txin.previous_output = OutPoint::new(parent.txid(), vout as u32);
+    }
+    let outpoints = spends
+        .iter()
+        .map(|spend| spend.txin.previous_output)
+        .collect::<Vec<_>>();
+    let mut graph = IndexedTxGraph::<BlockId, _>::new(new_index());
+    let spend_key = graph.index.spend_key();
+    let child = new_tx(
+        &spends,
+        vec![txout(20_000, output_spk(&keys, &outpoints, spend_key, 0))],
+    );
+
+    // the child is scanned once the outputs of its parent are known, whatever their order
+    let changeset = graph.batch_insert_relevant([(&child, []), (&parent, [])]);
+    assert_eq!(
+        changeset
+            .indexer
+            .outputs
+            .keys()
+            .copied()
+            .collect::<Vec<_>>(),
+        vec![OutPoint::new(child.txid(), 0)]
+    );
+    // the parent has a taproot output, but the outputs it spends are unknown
+    assert_eq!(
+        graph.index.skipped_txs().collect::<Vec<_>>(),
+        vec![parent.txid()]
+    );
+    assert!(graph.graph().get_tx(child.txid()).is_some());
+    assert!(graph.graph().get_tx(parent.txid()).is_none());
+
+    // the outputs of the parent are forgotten once spent and scanned, a transaction spending them
+    // again needs them to be inserted
+    let replacement = new_tx(
+        &spends,
+        vec![txout(19_000, output_spk(&keys, &outpoints, spend_key, 0))],
+    );
+    let changeset = graph.index.index_tx(&replacement);
+    assert!(changeset.outputs.is_empty());
+    assert!(graph
+        .index
+        .skipped_txs()
+        .any(|txid| txid == replacement.txid()));
+    let changeset = graph.index.insert_prevouts(
+        spends
+            .iter()
+            .map(|spend| (spend.txin.previous_output, spend.prevout.clone())),
+    );
+    assert_eq!(
+        changeset.outputs.keys().copied().collect::<Vec<_>>(),
+        vec![OutPoint::new(replacement.txid(), 0)]
+    );
+    assert!(graph
+        .index
+        .skipped_txs()
+        .all(|txid| txid != replacement.txid()));
+}
+
+#[test]
+fn test_indexed_tx_graph() {
+    let spends = spends();
+    let keys = spends
+        .iter()
+        .filter_map(|spend| spend.key)
+        .collect::<Vec<_>>();
+    let outpoints = spends
+        .iter()
+        .map(|spend| spend.txin.previous_output)
+        .collect::<Vec<_>>();
+    let mut graph = IndexedTxGraph::<BlockId, _>::new(new_index());
+    let _ = graph.index.add_label(0);
+    let spend_key = graph.index.spend_key();
+    let change_key = graph.index.label_spend_key(0);
+    let tx = new_tx(
+        &spends,
+        vec![
+            txout(20_000, output_spk(&keys, &outpoints, spend_key, 0)),
+            txout(30_000, output_spk(&keys, &outpoints, change_key, 1)),
+        ],
+    );
+
+    // the prevouts are inserted as floating txouts
+    let mut changeset = graph.initial_changeset();
+    for spend in &spends {
+        changeset.append(graph.insert_txout(spend.txin.previous_output, spend.prevout.clone()));
+    }
+    let tx_changeset = graph.batch_insert_relevant([(&tx, [])]);
+    assert_eq!(tx_changeset.indexer.outputs.len(), 2);
+    assert!(graph.graph().get_tx(tx.txid()).is_some());
+    changeset.append(tx_changeset);
+
+    // the outputs and labels are restored from the changeset
+    let mut restored = IndexedTxGraph::<BlockId, _>::new(new_index());
+    restored.apply_changeset(changeset);
+    assert_eq!(restored.index.labels().collect::<Vec<_>>(), vec![0]);
+    assert_eq!(
+        restored.index.outputs().collect::<Vec<_>>(),
+        graph.index.outputs().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        restored.index.txout(OutPoint::new(tx.txid(), 1)),
+        Some(&tx.output[1])
+    );
+}
@@ -18,6 +18,9 @@ pub enum FileError {
     Io(io::Error),
     /// Magic bytes do not match what is expected.
     InvalidMagicBytes { got: Vec<u8>, expected: Vec<u8> },
+    /// The magic bytes are not followed by the [`FORMAT_HEADER`], as the file was written by an
+    /// earlier version of [`Store`] and must be migrated with [`Store::migrate`].
+    MissingFormatHeader,
 }
 
 impl core::fmt::Display for FileError {
@@ -29,6 +32,7 @@ impl core::fmt::Display for FileError {
                 "file has invalid magic bytes: expected={:?} got={:?}",
                 expected, got,
             ),
+            Self::MissingFormatHeader => write!(f, "file has no format header"),
         }
     }
 }
@@ -4,13 +4,21 @@ use bdk_chain::Append;
 use bdk_persist::PersistBackend;
 use bincode::Options;
 use std::{
+    ffi::OsString,
     fmt::{self, Debug},
-    fs::{File, OpenOptions},
+    fs::{self, File, OpenOptions},
     io::{self, Read, Seek, Write},
     marker::PhantomData,
     path::Path,
 };
 
+/// The bytes following the magic bytes of a [`Store`] file, before its changesets.
+///
+/// The header tells the files of this version of [`Store`] apart from the files written before it
+/// was introduced, whose changesets directly follow the magic bytes and may have been encoded from
+/// an earlier version of the changeset type. Those files are migrated with [`Store::migrate`].
+pub const FORMAT_HEADER: [u8; 16] = *b"\xffbdk_file_store\x01";
+
 /// Persists an append-only list of changesets (`C`) to a single file.
 #[derive(Debug)]
 pub struct Store<C>
@@ -74,6 +82,7 @@ where
             .truncate(true)
             .open(file_path)?;
         f.write_all(magic)?;
+        f.write_all(&FORMAT_HEADER)?;
         Ok(Self {
             magic_len: magic.len(),
             db_file: f,
@@ -88,9 +97,13 @@ where
     /// # Errors
     ///
     /// If the prefixed bytes of the opened file does not match the provided `magic`, the
-    /// [`FileError::InvalidMagicBytes`] error variant will be returned.
+    /// [`FileError::InvalidMagicBytes`] error variant will be returned. If they are not followed by
+    /// the [`FORMAT_HEADER`], the [`FileError::MissingFormatHeader`] error variant will be returned
+    /// and the file must be opened with [`migrate`]. This is the case of every file written before
+    /// the header was introduced, which is not changed by `open`.
     ///
     /// [`create_new`]: Store::create_new
+    /// [`migrate`]: Store::migrate
     pub fn open<P>(magic: &[u8], file_path: P) -> Result<Self, FileError>
     where
         P: AsRef<Path>,
@@ -106,6 +119,15 @@ where
             });
         }
 
+        // legacy files are left untouched until they are migrated
+        let mut header = Vec::with_capacity(FORMAT_HEADER.len());
+        (&mut f)
+            .take(FORMAT_HEADER.len() as u64)
+            .read_to_end(&mut header)?;
+        if header != FORMAT_HEADER {
+            return Err(FileError::MissingFormatHeader);
+        }
+
         Ok(Self {
             magic_len: magic.len(),
             db_file: f,
@@ -130,6 +152,60 @@ where
         }
     }
 
+    /// Open a [`Store`] file written before the [`FORMAT_HEADER`] was introduced, whose
+    /// changesets were encoded as `L`s.
+    ///
+    /// The changesets of the file are aggregated and converted into a single `C` changeset, which
+    /// replaces the content of the file. Files that already have a format header are opened with
+    /// [`open`] without being changed.
+    ///
+    /// This is how a changeset type adding fields is read from files written before the fields
+    /// were added, e.g. the files of `bdk_persist::CombinedChangeSet` written before its `labels`
+    /// and `utxo_locks` fields were added are migrated with
+    /// `bdk_persist::LegacyCombinedChangeSet`.
+    ///
+    /// [`open`]: Store::open
+    pub fn migrate<L, P>(magic: &[u8], file_path: P) -> Result<Self, MigrateError>
+    where
+        L: Append + serde::de::DeserializeOwned,
+        C: From<L>,
+        P: AsRef<Path>,
+    {
+        let file_path = file_path.as_ref();
+        match Self::open(magic, file_path) {
+            Err(FileError::MissingFormatHeader) => {}
+            result => return result.map_err(MigrateError::File),
+        }
+
+        let mut legacy_file = File::open(file_path).map_err(FileError::from)?;
+        let mut changeset = Option::<L>::None;
+        for next_changeset in EntryIter::<L>::new(magic.len() as u64, &mut legacy_file) {
+            let next_changeset = next_changeset.map_err(MigrateError::Iter)?;
+            match &mut changeset {
+                Some(changeset) => changeset.append(next_changeset),
+                changeset => *changeset = Some(next_changeset),
+            }
+        }
+
+        // the migrated file replaces the legacy one only once it is complete
+        let mut migrated_path = OsString::from(file_path);
+        migrated_path.push(".migrated");
+        if Path::new(&migrated_path).exists() {
+            fs::remove_file(&migrated_path).map_err(FileError::from)?;
+        }
+        let mut migrated = Self::create_new(magic, &migrated_path)?;
+        if let Some(changeset) = changeset {
+            migrated
+                .append_changeset(&C::from(changeset))
+                .map_err(FileError::from)?;
+        }
+        migrated.db_file.sync_all().map_err(FileError::from)?;
+        drop(migrated);
+        fs::rename(&migrated_path, file_path).map_err(FileError::from)?;
+
+        Ok(Self::open(magic, file_path)?)
+    }
+
     /// Iterates over the stored changeset from first to last, changing the seek position at each
     /// iteration.
     ///
@@ -140,7 +216,10 @@ where
     /// always iterate over all entries until `None` is returned if you want your next write to go
     /// at the end; otherwise, you will write over existing entries.
     pub fn iter_changesets(&mut self) -> EntryIter<C> {
-        EntryIter::new(self.magic_len as u64, &mut self.db_file)
+        EntryIter::new(
+            (self.magic_len + FORMAT_HEADER.len()) as u64,
+            &mut self.db_file,
+        )
     }
 
     /// Loads all the changesets that have been stored as one giant changeset.
@@ -221,6 +300,32 @@ impl<C> std::fmt::Display for AggregateChangesetsError<C> {
 
 impl<C: fmt::Debug> std::error::Error for AggregateChangesetsError<C> {}
 
+/// Error type for [`Store::migrate`].
+#[derive(Debug)]
+pub enum MigrateError {
+    /// Failure to open, create or replace the file.
+    File(FileError),
+    /// Failure to read the changesets of the file being migrated.
+    Iter(IterError),
+}
+
+impl std::fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(e) => std::fmt::Display::fmt(e, f),
+            Self::Iter(e) => std::fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl From<FileError> for MigrateError {
+    fn from(value: FileError) -> Self {
+        Self::File(value)
+    }
+}
+
+impl std::error::Error for MigrateError {}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -304,11 +409,101 @@ mod test {
         };
     }
 
+    /// Write a file as written before the format header was introduced.
+    fn write_legacy_file(file_path: &Path, changesets: &[TestChangeSet]) {
+        let mut file = File::create(file_path).unwrap();
+        file.write_all(&TEST_MAGIC_BYTES).unwrap();
+        for changeset in changesets {
+            bincode_options()
+                .serialize_into(&mut file, changeset)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn open_fails_if_format_header_is_missing() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        write_legacy_file(&file_path, &[TestChangeSet::from(["1".into()])]);
+        let legacy_bytes = std::fs::read(&file_path).unwrap();
+
+        match Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path) {
+            Err(FileError::MissingFormatHeader) => {}
+            unexpected => panic!("unexpected result: {:?}", unexpected),
+        };
+        assert_eq!(std::fs::read(&file_path).unwrap(), legacy_bytes);
+    }
+
+    #[test]
+    fn open_does_not_change_empty_legacy_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        write_legacy_file(&file_path, &[]);
+        let changeset = TestChangeSet::from(["1".into()]);
+
+        match Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path) {
+            Err(FileError::MissingFormatHeader) => {}
+            unexpected => panic!("unexpected result: {:?}", unexpected),
+        };
+        assert_eq!(std::fs::read(&file_path).unwrap(), TEST_MAGIC_BYTES);
+
+        let mut db =
+            Store::<TestChangeSet>::migrate::<TestChangeSet, _>(&TEST_MAGIC_BYTES, &file_path)
+                .unwrap();
+        db.append_changeset(&changeset).unwrap();
+        drop(db);
+
+        let mut db = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).unwrap();
+        assert_eq!(db.aggregate_changesets().unwrap(), Some(changeset));
+    }
+
+    #[test]
+    fn migrate_legacy_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("db_file");
+        write_legacy_file(
+            &file_path,
+            &[
+                TestChangeSet::from(["1".into()]),
+                TestChangeSet::from(["2".into(), "3".into()]),
+            ],
+        );
+
+        let mut db =
+            Store::<TestChangeSet>::migrate::<TestChangeSet, _>(&TEST_MAGIC_BYTES, &file_path)
+                .expect("must migrate");
+        assert_eq!(
+            db.aggregate_changesets().unwrap(),
+            Some(TestChangeSet::from(["1".into(), "2".into(), "3".into()]))
+        );
+        db.append_changeset(&TestChangeSet::from(["4".into()]))
+            .unwrap();
+        drop(db);
+        assert!(!temp_dir.path().join("db_file.migrated").exists());
+
+        // a migrated file is opened as is
+        let _ = Store::<TestChangeSet>::open(&TEST_MAGIC_BYTES, &file_path).expect("must open");
+        let mut db =
+            Store::<TestChangeSet>::migrate::<TestChangeSet, _>(&TEST_MAGIC_BYTES, &file_path)
+                .expect("must open");
+        assert_eq!(
+            db.aggregate_changesets().unwrap(),
+            Some(TestChangeSet::from([
+                "1".into(),
+                "2".into(),
+                "3".into(),
+                "4".into()
+            ]))
+        );
+    }
+
     #[test]
     fn append_changeset_truncates_invalid_bytes() {
-        // initial data to write to file (magic bytes + invalid data)
+        // initial data to write to file (magic bytes + format header + invalid data)
         let mut data = [255_u8; 2000];
         data[..TEST_MAGIC_BYTES_LEN].copy_from_slice(&TEST_MAGIC_BYTES);
+        data[TEST_MAGIC_BYTES_LEN..TEST_MAGIC_BYTES_LEN + FORMAT_HEADER.len()]
+            .copy_from_slice(&FORMAT_HEADER);
 
         let changeset = TestChangeSet::from(["one".into(), "two".into(), "three!".into()]);
 
@@ -337,6 +532,7 @@ mod test {
 
         let expected_bytes = {
             let mut buf = TEST_MAGIC_BYTES.to_vec();
+            buf.extend_from_slice(&FORMAT_HEADER);
             DefaultOptions::new()
                 .with_varint_encoding()
                 .serialize_into(&mut buf, &changeset)
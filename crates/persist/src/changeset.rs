@@ -1,8 +1,16 @@
 #![cfg(feature = "miniscript")]
 
+use crate::{labels, utxo_locks};
 use bdk_chain::{bitcoin::Network, indexed_tx_graph, keychain, local_chain, Anchor, Append};
 
 /// Changes from a combination of [`bdk_chain`] structures.
+///
+/// ## Encoding
+///
+/// The `labels` and `utxo_locks` fields were added after the other fields, they default to empty
+/// when missing from self-describing formats such as JSON. Formats encoding fields by position
+/// can't tell a missing field apart from the next value, the files of `bdk_file_store` written
+/// before the fields were added must be migrated with `bdk_file_store::Store::migrate`.
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(
     feature = "serde",
@@ -22,6 +30,12 @@ pub struct CombinedChangeSet<K, A> {
     pub indexed_tx_graph: indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<K>>,
     /// Stores the network type of the transaction data.
     pub network: Option<Network>,
+    /// Changes to the wallet's [BIP329](labels) labels.
+    #[cfg_attr(feature = "serde", serde(default, with = "labels::serde_entries"))]
+    pub labels: labels::ChangeSet,
+    /// Changes to the wallet's locked unspent outputs.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub utxo_locks: utxo_locks::ChangeSet,
 }
 
 impl<K, A> Default for CombinedChangeSet<K, A> {
@@ -30,6 +44,8 @@ impl<K, A> Default for CombinedChangeSet<K, A> {
             chain: Default::default(),
             indexed_tx_graph: Default::default(),
             network: None,
+            labels: Default::default(),
+            utxo_locks: Default::default(),
         }
     }
 }
@@ -45,10 +61,16 @@ impl<K: Ord, A: Anchor> Append for CombinedChangeSet<K, A> {
             );
             self.network = other.network;
         }
+        Append::append(&mut self.labels, other.labels);
+        Append::append(&mut self.utxo_locks, other.utxo_locks);
     }
 
     fn is_empty(&self) -> bool {
-        self.chain.is_empty() && self.indexed_tx_graph.is_empty() && self.network.is_none()
+        self.chain.is_empty()
+            && self.indexed_tx_graph.is_empty()
+            && self.network.is_none()
+            && self.labels.is_empty()
+            && self.utxo_locks.is_empty()
     }
 }
 
@@ -71,3 +93,85 @@ impl<K, A> From<indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<K>>>
         }
     }
 }
+
+impl<K, A> From<labels::ChangeSet> for CombinedChangeSet<K, A> {
+    fn from(labels: labels::ChangeSet) -> Self {
+        Self {
+            labels,
+            ..Default::default()
+        }
+    }
+}
+
+impl<K, A> From<utxo_locks::ChangeSet> for CombinedChangeSet<K, A> {
+    fn from(utxo_locks: utxo_locks::ChangeSet) -> Self {
+        Self {
+            utxo_locks,
+            ..Default::default()
+        }
+    }
+}
+
+/// A [`CombinedChangeSet`] as encoded before its `labels` and `utxo_locks` fields were added.
+///
+/// This is the changeset to read the files of `bdk_file_store` written before the fields were
+/// added with, when migrating them with `bdk_file_store::Store::migrate`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(bdk_chain::serde::Deserialize, bdk_chain::serde::Serialize),
+    serde(
+        crate = "bdk_chain::serde",
+        bound(
+            deserialize = "A: Ord + bdk_chain::serde::Deserialize<'de>, K: Ord + bdk_chain::serde::Deserialize<'de>",
+            serialize = "A: Ord + bdk_chain::serde::Serialize, K: Ord + bdk_chain::serde::Serialize",
+        ),
+    )
+)]
+pub struct LegacyCombinedChangeSet<K, A> {
+    /// Changes to the [`LocalChain`](local_chain::LocalChain).
+    pub chain: local_chain::ChangeSet,
+    /// Changes to [`IndexedTxGraph`](indexed_tx_graph::IndexedTxGraph).
+    pub indexed_tx_graph: indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<K>>,
+    /// Stores the network type of the transaction data.
+    pub network: Option<Network>,
+}
+
+impl<K, A> Default for LegacyCombinedChangeSet<K, A> {
+    fn default() -> Self {
+        Self {
+            chain: Default::default(),
+            indexed_tx_graph: Default::default(),
+            network: None,
+        }
+    }
+}
+
+impl<K: Ord, A: Anchor> Append for LegacyCombinedChangeSet<K, A> {
+    fn append(&mut self, other: Self) {
+        Append::append(&mut self.chain, other.chain);
+        Append::append(&mut self.indexed_tx_graph, other.indexed_tx_graph);
+        if other.network.is_some() {
+            debug_assert!(
+                self.network.is_none() || self.network == other.network,
+                "network type must either be just introduced or remain the same"
+            );
+            self.network = other.network;
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.chain.is_empty() && self.indexed_tx_graph.is_empty() && self.network.is_none()
+    }
+}
+
+impl<K, A> From<LegacyCombinedChangeSet<K, A>> for CombinedChangeSet<K, A> {
+    fn from(legacy: LegacyCombinedChangeSet<K, A>) -> Self {
+        Self {
+            chain: legacy.chain,
+            indexed_tx_graph: legacy.indexed_tx_graph,
+            network: legacy.network,
+            ..Default::default()
+        }
+    }
+}
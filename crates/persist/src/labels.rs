@@ -0,0 +1,87 @@
+//! Types that describe wallet labels as defined in [BIP329].
+//!
+//! [BIP329]: https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki
+
+use alloc::string::String;
+use bdk_chain::bitcoin::{bip32::Xpub, OutPoint, PublicKey, ScriptBuf, Txid};
+use bdk_chain::collections::BTreeMap;
+
+/// The [`ChangeSet`] represents changes to the labels of a wallet.
+///
+/// The key is the labelled item, and the value either represents a new or updated [`Label`] (if
+/// [`Some`]), or the removal of the label (if [`None`]).
+///
+/// As [`LabelRef`]s aren't strings, the changeset is serialized as a sequence of entries by
+/// [`CombinedChangeSet`](crate::CombinedChangeSet), so that it can be encoded as JSON.
+pub type ChangeSet = BTreeMap<LabelRef, Option<Label>>;
+
+/// The item a [`Label`] is attached to.
+///
+/// Each variant corresponds to one of the record `type`s defined in BIP329.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(bdk_chain::serde::Deserialize, bdk_chain::serde::Serialize),
+    serde(crate = "bdk_chain::serde", rename_all = "snake_case")
+)]
+pub enum LabelRef {
+    /// A transaction, referenced by its txid.
+    Tx(Txid),
+    /// An address, referenced by its script pubkey.
+    Addr(ScriptBuf),
+    /// A public key.
+    PubKey(PublicKey),
+    /// A transaction input, referenced by the outpoint it spends.
+    Input(OutPoint),
+    /// A transaction output.
+    Output(OutPoint),
+    /// An extended public key.
+    Xpub(Xpub),
+}
+
+/// A label attached to a [`LabelRef`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(bdk_chain::serde::Deserialize, bdk_chain::serde::Serialize),
+    serde(crate = "bdk_chain::serde")
+)]
+pub struct Label {
+    /// The label text.
+    pub label: String,
+    /// The key origin (e.g. `wpkh([d34db33f/84'/0'/0'])`) of the descriptor the item belongs to.
+    pub origin: Option<String>,
+    /// Whether an output may be spent. Only meaningful for [`LabelRef::Output`].
+    pub spendable: Option<bool>,
+}
+
+impl Label {
+    /// Create a [`Label`] with the given text and no other fields set.
+    pub fn new(label: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Serialize a [`ChangeSet`] as a sequence of `(LabelRef, Option<Label>)` entries, as the keys of
+/// maps must be strings in formats such as JSON.
+#[cfg(feature = "serde")]
+pub(crate) mod serde_entries {
+    use super::{ChangeSet, Label, LabelRef};
+    use alloc::vec::Vec;
+    use bdk_chain::serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        changeset: &ChangeSet,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(changeset)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ChangeSet, D::Error> {
+        let entries = Vec::<(LabelRef, Option<Label>)>::deserialize(deserializer)?;
+        Ok(entries.into_iter().collect())
+    }
+}
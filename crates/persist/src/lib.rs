@@ -2,7 +2,11 @@
 #![no_std]
 #![warn(missing_docs)]
 
+extern crate alloc;
+
 mod changeset;
+pub mod labels;
 mod persist;
+pub mod utxo_locks;
 pub use changeset::*;
 pub use persist::*;
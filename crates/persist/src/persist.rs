@@ -1,4 +1,3 @@
-extern crate alloc;
 use alloc::boxed::Box;
 use bdk_chain::Append;
 use core::fmt;
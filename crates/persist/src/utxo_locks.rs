@@ -0,0 +1,31 @@
+//! Types that describe the unspent outputs a wallet won't select when building transactions.
+
+use bdk_chain::bitcoin::OutPoint;
+use bdk_chain::collections::BTreeMap;
+
+/// The [`ChangeSet`] represents changes to the locked unspent outputs of a wallet.
+///
+/// The key is the locked output, and the value either represents a new or updated [`UtxoLock`]
+/// (if [`Some`]), or the removal of the lock (if [`None`]).
+pub type ChangeSet = BTreeMap<OutPoint, Option<UtxoLock>>;
+
+/// A lock on an unspent output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(bdk_chain::serde::Deserialize, bdk_chain::serde::Serialize),
+    serde(crate = "bdk_chain::serde")
+)]
+pub struct UtxoLock {
+    /// The height of the chain tip at which the lock expires, or [`None`] if the lock never
+    /// expires.
+    pub expiry_height: Option<u32>,
+}
+
+impl UtxoLock {
+    /// Whether the lock is still active when the chain tip is at `tip_height`.
+    pub fn is_active(&self, tip_height: u32) -> bool {
+        self.expiry_height
+            .map_or(true, |expiry_height| tip_height < expiry_height)
+    }
+}
@@ -0,0 +1,11 @@
+-- label_ref is the json serialized BIP329 label reference structure as JSONB,
+-- label is the label text,
+-- origin is an optional key origin string,
+-- spendable is an optional boolean stored as 0 or 1
+CREATE TABLE label
+(
+    label_ref BLOB PRIMARY KEY NOT NULL,
+    label     TEXT             NOT NULL,
+    origin    TEXT,
+    spendable INTEGER
+) STRICT;
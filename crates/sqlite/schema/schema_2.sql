@@ -0,0 +1,10 @@
+-- Outpoint txid hash hex string (reversed)
+-- Outpoint vout
+-- expiry height is an optional u32 chain tip height at which the lock expires
+CREATE TABLE utxo_lock
+(
+    txid          TEXT    NOT NULL,
+    vout          INTEGER NOT NULL,
+    expiry_height INTEGER,
+    PRIMARY KEY (txid, vout)
+) STRICT;
@@ -2,7 +2,9 @@ use crate::Store;
 use rusqlite::{named_params, Connection, Error};
 
 const SCHEMA_0: &str = include_str!("../schema/schema_0.sql");
-const MIGRATIONS: &[&str] = &[SCHEMA_0];
+const SCHEMA_1: &str = include_str!("../schema/schema_1.sql");
+const SCHEMA_2: &str = include_str!("../schema/schema_2.sql");
+const MIGRATIONS: &[&str] = &[SCHEMA_0, SCHEMA_1, SCHEMA_2];
 
 /// Schema migration related functions.
 impl<K, A> Store<K, A> {
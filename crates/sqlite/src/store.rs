@@ -15,7 +15,7 @@ use crate::Error;
 use bdk_chain::{
     indexed_tx_graph, keychain, local_chain, tx_graph, Anchor, Append, DescriptorExt, DescriptorId,
 };
-use bdk_persist::CombinedChangeSet;
+use bdk_persist::{labels, utxo_locks, CombinedChangeSet};
 
 /// Persists data in to a relational schema based [SQLite] database file.
 ///
@@ -480,6 +480,126 @@ where
     }
 }
 
+/// Label table related functions.
+///
+/// The label references are stored as [`JSONB`] data.
+/// [`JSONB`]: https://sqlite.org/json1.html#jsonb
+impl<K, A> Store<K, A> {
+    /// Insert, update or delete labels.
+    fn insert_or_delete_labels(
+        db_transaction: &rusqlite::Transaction,
+        labels_changeset: &labels::ChangeSet,
+    ) -> Result<(), Error> {
+        for (label_ref, label) in labels_changeset.iter() {
+            let label_ref = serde_json::to_string(label_ref).expect("label ref json");
+            match label {
+                // insert or replace label
+                Some(label) => {
+                    let insert_label_stmt = &mut db_transaction
+                        .prepare_cached("INSERT INTO label (label_ref, label, origin, spendable) VALUES (jsonb(:label_ref), :label, :origin, :spendable) ON CONFLICT (label_ref) DO UPDATE SET label = :label, origin = :origin, spendable = :spendable")
+                        .expect("insert or update label statement");
+                    insert_label_stmt.execute(named_params! {":label_ref": label_ref, ":label": label.label, ":origin": label.origin, ":spendable": label.spendable })
+                        .map_err(Error::Sqlite)?;
+                }
+                // delete label
+                None => {
+                    let delete_label_stmt = &mut db_transaction
+                        .prepare_cached("DELETE FROM label WHERE label_ref = jsonb(:label_ref)")
+                        .expect("delete label statement");
+                    delete_label_stmt
+                        .execute(named_params! {":label_ref": label_ref })
+                        .map_err(Error::Sqlite)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Select all labels.
+    fn select_labels(db_transaction: &rusqlite::Transaction) -> Result<labels::ChangeSet, Error> {
+        let mut select_labels_stmt = db_transaction
+            .prepare_cached("SELECT json(label_ref), label, origin, spendable FROM label")
+            .expect("select labels statement");
+
+        let labels = select_labels_stmt
+            .query_map([], |row| {
+                let label_ref = row.get_unwrap::<usize, String>(0);
+                let label_ref: labels::LabelRef =
+                    serde_json::from_str(label_ref.as_str()).expect("label ref");
+                let label = labels::Label {
+                    label: row.get_unwrap::<usize, String>(1),
+                    origin: row.get_unwrap::<usize, Option<String>>(2),
+                    spendable: row.get_unwrap::<usize, Option<bool>>(3),
+                };
+                Ok((label_ref, Some(label)))
+            })
+            .map_err(Error::Sqlite)?;
+        labels
+            .into_iter()
+            .map(|row| row.map_err(Error::Sqlite))
+            .collect()
+    }
+}
+
+/// Unspent output lock table related functions.
+impl<K, A> Store<K, A> {
+    /// Insert, update or delete unspent output locks.
+    fn insert_or_delete_utxo_locks(
+        db_transaction: &rusqlite::Transaction,
+        utxo_locks_changeset: &utxo_locks::ChangeSet,
+    ) -> Result<(), Error> {
+        for (outpoint, lock) in utxo_locks_changeset.iter() {
+            let txid = outpoint.txid.to_string();
+            let vout = outpoint.vout;
+            match lock {
+                // insert or replace lock
+                Some(lock) => {
+                    let insert_lock_stmt = &mut db_transaction
+                        .prepare_cached("INSERT INTO utxo_lock (txid, vout, expiry_height) VALUES (:txid, :vout, :expiry_height) ON CONFLICT (txid, vout) DO UPDATE SET expiry_height = :expiry_height")
+                        .expect("insert or update utxo lock statement");
+                    insert_lock_stmt.execute(named_params! {":txid": txid, ":vout": vout, ":expiry_height": lock.expiry_height })
+                        .map_err(Error::Sqlite)?;
+                }
+                // delete lock
+                None => {
+                    let delete_lock_stmt = &mut db_transaction
+                        .prepare_cached("DELETE FROM utxo_lock WHERE txid = :txid AND vout = :vout")
+                        .expect("delete utxo lock statement");
+                    delete_lock_stmt
+                        .execute(named_params! {":txid": txid, ":vout": vout })
+                        .map_err(Error::Sqlite)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Select all unspent output locks.
+    fn select_utxo_locks(
+        db_transaction: &rusqlite::Transaction,
+    ) -> Result<utxo_locks::ChangeSet, Error> {
+        let mut select_locks_stmt = db_transaction
+            .prepare_cached("SELECT txid, vout, expiry_height FROM utxo_lock")
+            .expect("select utxo locks statement");
+
+        let locks = select_locks_stmt
+            .query_map([], |row| {
+                let txid = row.get_unwrap::<usize, String>(0);
+                let txid = Txid::from_str(&txid).expect("txid");
+                let vout = row.get_unwrap::<usize, u32>(1);
+                let lock = utxo_locks::UtxoLock {
+                    expiry_height: row.get_unwrap::<usize, Option<u32>>(2),
+                };
+                Ok((OutPoint::new(txid, vout), Some(lock)))
+            })
+            .map_err(Error::Sqlite)?;
+        locks
+            .into_iter()
+            .map(|row| row.map_err(Error::Sqlite))
+            .collect()
+    }
+}
+
 /// Functions to read and write all [`ChangeSet`] data.
 impl<K, A> Store<K, A>
 where
@@ -508,6 +628,12 @@ where
         Self::insert_txouts(&db_transaction, tx_graph_changeset)?;
         Self::insert_anchors(&db_transaction, tx_graph_changeset)?;
         Self::update_last_seen(&db_transaction, tx_graph_changeset)?;
+
+        let labels_changeset = &changeset.labels;
+        Self::insert_or_delete_labels(&db_transaction, labels_changeset)?;
+
+        let utxo_locks_changeset = &changeset.utxo_locks;
+        Self::insert_or_delete_utxo_locks(&db_transaction, utxo_locks_changeset)?;
         db_transaction.commit().map_err(Error::Sqlite)
     }
 
@@ -522,6 +648,8 @@ where
         let last_seen = Self::select_last_seen(&db_transaction)?;
         let txouts = Self::select_txouts(&db_transaction)?;
         let anchors = Self::select_anchors(&db_transaction)?;
+        let labels = Self::select_labels(&db_transaction)?;
+        let utxo_locks = Self::select_utxo_locks(&db_transaction)?;
 
         let graph: tx_graph::ChangeSet<A> = tx_graph::ChangeSet {
             txs,
@@ -538,13 +666,20 @@ where
         let indexed_tx_graph: indexed_tx_graph::ChangeSet<A, keychain::ChangeSet<K>> =
             indexed_tx_graph::ChangeSet { graph, indexer };
 
-        if network.is_none() && chain.is_empty() && indexed_tx_graph.is_empty() {
+        if network.is_none()
+            && chain.is_empty()
+            && indexed_tx_graph.is_empty()
+            && labels.is_empty()
+            && utxo_locks.is_empty()
+        {
             Ok(None)
         } else {
             Ok(Some(CombinedChangeSet {
                 chain,
                 indexed_tx_graph,
                 network,
+                labels,
+                utxo_locks,
             }))
         }
     }
@@ -640,6 +775,63 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn insert_and_delete_labels() -> anyhow::Result<()> {
+        let conn = Connection::open_in_memory().expect("in memory connection");
+        let mut store = Store::<Keychain, BlockId>::new(conn).expect("create new memory db store");
+
+        let tx_ref = labels::LabelRef::Tx(Txid::all_zeros());
+        let spk_ref = labels::LabelRef::Addr(ScriptBuf::new_op_return([0x42]));
+        let changeset = CombinedChangeSet::<Keychain, BlockId>::from(labels::ChangeSet::from([
+            (tx_ref.clone(), Some(labels::Label::new("tx"))),
+            (spk_ref.clone(), Some(labels::Label::new("addr"))),
+        ]));
+        store.write_changes(&changeset).expect("write changeset");
+
+        let changeset =
+            CombinedChangeSet::<Keychain, BlockId>::from(labels::ChangeSet::from([(tx_ref, None)]));
+        store.write_changes(&changeset).expect("write changeset");
+
+        let loaded: Option<CombinedChangeSet<Keychain, BlockId>> =
+            store.load_from_persistence().expect("aggregated changeset");
+        assert_eq!(
+            loaded.expect("labels must be loaded").labels,
+            labels::ChangeSet::from([(spk_ref, Some(labels::Label::new("addr")))])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn insert_and_delete_utxo_locks() -> anyhow::Result<()> {
+        let conn = Connection::open_in_memory().expect("in memory connection");
+        let mut store = Store::<Keychain, BlockId>::new(conn).expect("create new memory db store");
+
+        let outpoint0 = OutPoint::new(Txid::all_zeros(), 0);
+        let outpoint1 = OutPoint::new(Txid::all_zeros(), 1);
+        let lock = utxo_locks::UtxoLock {
+            expiry_height: Some(42),
+        };
+        let changeset =
+            CombinedChangeSet::<Keychain, BlockId>::from(utxo_locks::ChangeSet::from([
+                (outpoint0, Some(utxo_locks::UtxoLock::default())),
+                (outpoint1, Some(lock)),
+            ]));
+        store.write_changes(&changeset).expect("write changeset");
+
+        let changeset = CombinedChangeSet::<Keychain, BlockId>::from(utxo_locks::ChangeSet::from(
+            [(outpoint0, None)],
+        ));
+        store.write_changes(&changeset).expect("write changeset");
+
+        let loaded: Option<CombinedChangeSet<Keychain, BlockId>> =
+            store.load_from_persistence().expect("aggregated changeset");
+        assert_eq!(
+            loaded.expect("utxo locks must be loaded").utxo_locks,
+            utxo_locks::ChangeSet::from([(outpoint1, Some(lock))])
+        );
+        Ok(())
+    }
+
     fn create_test_changesets<A: Anchor + Copy>(
         anchor_fn: &dyn Fn(u32, u64, BlockHash) -> A,
     ) -> (
@@ -719,10 +911,39 @@ mod test {
         // test changesets to write to db
         let mut changesets = Vec::new();
 
+        let labels_changeset: labels::ChangeSet = [
+            (
+                labels::LabelRef::Tx(tx0.txid()),
+                Some(labels::Label::new("genesis")),
+            ),
+            (
+                labels::LabelRef::Output(outpoint0_0),
+                Some(labels::Label {
+                    label: "unspendable coinbase".to_string(),
+                    origin: Some("wpkh([d34db33f/84'/1'/0'])".to_string()),
+                    spendable: Some(false),
+                }),
+            ),
+        ]
+        .into();
+
+        let utxo_locks_changeset: utxo_locks::ChangeSet = [
+            (outpoint0_0, Some(utxo_locks::UtxoLock::default())),
+            (
+                OutPoint::new(tx1.txid(), 0),
+                Some(utxo_locks::UtxoLock {
+                    expiry_height: Some(100),
+                }),
+            ),
+        ]
+        .into();
+
         changesets.push(CombinedChangeSet {
             chain: block_changeset,
             indexed_tx_graph: graph_changeset,
             network: network_changeset,
+            labels: labels_changeset,
+            utxo_locks: utxo_locks_changeset,
         });
 
         // create changeset that sets the whole tx2 and updates it's lastseen where before there was only the txid and last_seen
@@ -739,10 +960,28 @@ mod test {
                 indexer: keychain::ChangeSet::default(),
             };
 
+        // create changeset that updates the label of tx0
+        let labels_changeset2: labels::ChangeSet = [(
+            labels::LabelRef::Tx(tx0.txid()),
+            Some(labels::Label::new("satoshi's coinbase")),
+        )]
+        .into();
+
+        // create changeset that extends the lock of tx1's output
+        let utxo_locks_changeset2: utxo_locks::ChangeSet = [(
+            OutPoint::new(tx1.txid(), 0),
+            Some(utxo_locks::UtxoLock {
+                expiry_height: Some(200),
+            }),
+        )]
+        .into();
+
         changesets.push(CombinedChangeSet {
             chain: local_chain::ChangeSet::default(),
             indexed_tx_graph: graph_changeset2,
             network: None,
+            labels: labels_changeset2,
+            utxo_locks: utxo_locks_changeset2,
         });
 
         // create changeset that adds a new anchor2 for tx0 and tx1
@@ -763,6 +1002,8 @@ mod test {
             chain: local_chain::ChangeSet::default(),
             indexed_tx_graph: graph_changeset3,
             network: None,
+            labels: labels::ChangeSet::default(),
+            utxo_locks: utxo_locks::ChangeSet::default(),
         });
 
         // aggregated test changesets
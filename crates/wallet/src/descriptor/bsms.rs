@@ -0,0 +1,811 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Bitcoin Secure Multisig Setup
+//!
+//! This module implements the [BIP129] multisig setup between a coordinator and its signers:
+//!
+//! 1. the coordinator picks a session [`Token`] and shares it with the signers;
+//! 2. each signer creates a [`KeyRecord`] with its extended key, signed by the key itself, and
+//!    sends it to the coordinator;
+//! 3. the coordinator verifies the key records and assembles a `wsh(sortedmulti())` descriptor,
+//!    sent back to the signers as a [`DescriptorRecord`];
+//! 4. each signer checks that the descriptor contains its key and that the first address matches
+//!    the one it derives, before creating a [`Wallet`] from it with [`Wallet::new_single`].
+//!
+//! Records are exchanged in plain text. With the `bsms-encryption` feature, they can be encrypted
+//! with a non-empty token using `Token::encrypt` and `Token::decrypt`.
+//!
+//! ## Example
+//!
+//! ```
+//! # use core::str::FromStr;
+//! # use bitcoin::bip32::DerivationPath;
+//! # use bitcoin::secp256k1::Secp256k1;
+//! # use bitcoin::Network;
+//! # use bdk_wallet::bitcoin::bip32::Xpriv;
+//! # use bdk_wallet::descriptor::bsms::{DescriptorRecord, KeyRecord, Token};
+//! # use bdk_wallet::Wallet;
+//! let secp = Secp256k1::new();
+//! let path = DerivationPath::from_str("m/48h/1h/0h/2h")?;
+//! let token = Token::none();
+//!
+//! // each signer sends a key record to the coordinator
+//! let records = ["tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS", "tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy"]
+//!     .iter()
+//!     .map(|tprv| {
+//!         let key = Xpriv::from_str(tprv)?;
+//!         let record = KeyRecord::new(key, &path, token.clone(), "signer", Network::Testnet, &secp)?;
+//!         Ok(record.to_string())
+//!     })
+//!     .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+//!
+//! // the coordinator assembles the descriptor
+//! let records = records
+//!     .iter()
+//!     .map(|record| KeyRecord::from_str(record))
+//!     .collect::<Result<Vec<_>, _>>()?;
+//! let descriptor_record = DescriptorRecord::new(2, &records, Network::Testnet, &secp)?;
+//!
+//! // and each signer verifies it before creating its wallet
+//! let descriptor_record = DescriptorRecord::from_str(&descriptor_record.to_string())?;
+//! descriptor_record.verify(records[0].key(), Network::Testnet)?;
+//! let wallet = Wallet::new_single_no_persist(&descriptor_record, Network::Testnet)?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BIP129]: https://github.com/bitcoin/bips/blob/master/bip-0129.mediawiki
+//! [`Wallet`]: crate::Wallet
+//! [`Wallet::new_single`]: crate::Wallet::new_single
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::bip32::{DerivationPath, Xpub};
+use bitcoin::hashes::Hash;
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::secp256k1;
+use bitcoin::sign_message::{signed_msg_hash, MessageSignature};
+use bitcoin::{Address, Network};
+use miniscript::descriptor::{DescriptorPublicKey, DescriptorXKey, KeyMap, Wildcard};
+use miniscript::{ForEachKey, Segwitv0};
+
+use super::{DescriptorError, ExtendedDescriptor, IntoWalletDescriptor};
+use crate::keys::{DerivableKey, KeyError};
+use crate::wallet::utils::SecpCtx;
+
+/// The version of BSMS implemented by this module
+const VERSION: &str = "BSMS 1.0";
+
+/// The path restrictions of the descriptor records, one path for each keychain
+const PATH_RESTRICTIONS: &str = "/0/*,/1/*";
+
+/// The maximum length of the description of a key record
+const MAX_DESCRIPTION_LEN: usize = 80;
+
+/// Errors related to the Bitcoin Secure Multisig Setup
+#[derive(Debug)]
+pub enum BsmsError {
+    /// The record is malformed
+    InvalidRecord,
+    /// The record has an unsupported version
+    UnsupportedVersion(String),
+    /// The token is not empty, 8 or 16 bytes long
+    InvalidToken,
+    /// The key records don't all use the same token
+    TokenMismatch,
+    /// The description is longer than 80 characters or contains a line break
+    InvalidDescription,
+    /// The key is not an extended private key
+    MissingPrivateKey,
+    /// The signature of a key record is invalid
+    InvalidSignature,
+    /// The threshold is zero or larger than the number of keys
+    InvalidThreshold,
+    /// The first address of a descriptor record doesn't match its descriptor
+    FirstAddressMismatch,
+    /// The key of the signer is not part of the descriptor
+    MissingKey,
+    /// The encrypted record is not authentic
+    Decryption,
+    /// Error while deriving the key
+    Key(KeyError),
+    /// Error while parsing or deriving the descriptor
+    Descriptor(DescriptorError),
+}
+
+impl fmt::Display for BsmsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidRecord => write!(f, "malformed record"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported version `{}`", v),
+            Self::InvalidToken => write!(f, "the token must be empty, 8 or 16 bytes long"),
+            Self::TokenMismatch => write!(f, "the key records use different tokens"),
+            Self::InvalidDescription => write!(
+                f,
+                "the description must be a single line of at most 80 characters"
+            ),
+            Self::MissingPrivateKey => write!(f, "the key is not an extended private key"),
+            Self::InvalidSignature => write!(f, "invalid key record signature"),
+            Self::InvalidThreshold => write!(f, "invalid threshold"),
+            Self::FirstAddressMismatch => {
+                write!(f, "the first address doesn't match the descriptor")
+            }
+            Self::MissingKey => write!(f, "the key is not part of the descriptor"),
+            Self::Decryption => write!(f, "the encrypted record is not authentic"),
+            Self::Key(e) => e.fmt(f),
+            Self::Descriptor(e) => e.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BsmsError {}
+
+impl From<KeyError> for BsmsError {
+    fn from(e: KeyError) -> Self {
+        Self::Key(e)
+    }
+}
+
+impl From<DescriptorError> for BsmsError {
+    fn from(e: DescriptorError) -> Self {
+        Self::Descriptor(e)
+    }
+}
+
+impl From<miniscript::Error> for BsmsError {
+    fn from(e: miniscript::Error) -> Self {
+        Self::Descriptor(e.into())
+    }
+}
+
+/// The token of a BSMS session
+///
+/// The token identifies the session in the key records, and is used as the secret to encrypt the
+/// records. The empty token, written as `00`, means that the records are not encrypted.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Token(Vec<u8>);
+
+impl Token {
+    /// The empty token, used when the records are not encrypted
+    pub fn none() -> Self {
+        Token(Vec::new())
+    }
+
+    /// Generate a random 8 bytes token
+    pub fn standard() -> Self {
+        Self::random(8)
+    }
+
+    /// Generate a random 16 bytes token
+    pub fn extended() -> Self {
+        Self::random(16)
+    }
+
+    /// Create a token from its bytes
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, BsmsError> {
+        match bytes.len() {
+            0 | 8 | 16 => Ok(Token(bytes)),
+            _ => Err(BsmsError::InvalidToken),
+        }
+    }
+
+    /// The bytes of the token
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Whether this is the empty token
+    pub fn is_none(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn random(len: usize) -> Self {
+        use rand::{thread_rng, Rng};
+
+        let mut bytes = vec![0u8; len];
+        thread_rng().fill(bytes.as_mut_slice());
+        Token(bytes)
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_none() {
+            write!(f, "00")
+        } else {
+            write!(f, "{}", self.0.as_hex())
+        }
+    }
+}
+
+impl FromStr for Token {
+    type Err = BsmsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "00" {
+            return Ok(Token::none());
+        }
+        let bytes = Vec::<u8>::from_hex(s).map_err(|_| BsmsError::InvalidToken)?;
+        if bytes.is_empty() {
+            return Err(BsmsError::InvalidToken);
+        }
+        Token::from_bytes(bytes)
+    }
+}
+
+/// The record a signer sends to the coordinator, containing its extended public key
+///
+/// See the [module-level documentation](self) for an example.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRecord {
+    token: Token,
+    key: DescriptorPublicKey,
+    description: String,
+    signature: MessageSignature,
+}
+
+impl KeyRecord {
+    /// Create a key record for the key derived from `key` at `path`
+    ///
+    /// The record is signed with the derived private key. [BIP48] recommends the
+    /// `m/48'/<coin>'/<account>'/2'` path for `wsh()` multisig wallets.
+    ///
+    /// [BIP48]: https://github.com/bitcoin/bips/blob/master/bip-0048.mediawiki
+    pub fn new<K: DerivableKey<Segwitv0>>(
+        key: K,
+        path: &DerivationPath,
+        token: Token,
+        description: &str,
+        network: Network,
+        secp: &SecpCtx,
+    ) -> Result<Self, BsmsError> {
+        if description.len() > MAX_DESCRIPTION_LEN || description.contains(['\r', '\n']) {
+            return Err(BsmsError::InvalidDescription);
+        }
+        let xprv = key
+            .into_extended_key()?
+            .into_xprv(network)
+            .ok_or(BsmsError::MissingPrivateKey)?;
+        let derived = xprv.derive_priv(secp, path).map_err(KeyError::Bip32)?;
+        let key = DescriptorPublicKey::XPub(DescriptorXKey {
+            origin: Some((xprv.fingerprint(secp), path.clone())),
+            xkey: Xpub::from_priv(secp, &derived),
+            derivation_path: DerivationPath::master(),
+            wildcard: Wildcard::None,
+        });
+
+        let data = signed_data(&token, &key, description);
+        let msg = secp256k1::Message::from_digest(signed_msg_hash(&data).to_byte_array());
+        let signature = MessageSignature::new(
+            secp.sign_ecdsa_recoverable(&msg, &derived.private_key),
+            true,
+        );
+
+        Ok(KeyRecord {
+            token,
+            key,
+            description: description.to_string(),
+            signature,
+        })
+    }
+
+    /// The token of the session
+    pub fn token(&self) -> &Token {
+        &self.token
+    }
+
+    /// The extended public key of the signer, with its origin
+    pub fn key(&self) -> &DescriptorPublicKey {
+        &self.key
+    }
+
+    /// The description of the signer
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Verify that the record is signed by its key
+    pub fn verify(&self, secp: &SecpCtx) -> Result<(), BsmsError> {
+        let xpub = match &self.key {
+            DescriptorPublicKey::XPub(xkey) => xkey.xkey,
+            _ => return Err(BsmsError::InvalidRecord),
+        };
+        let data = signed_data(&self.token, &self.key, &self.description);
+        match self.signature.recover_pubkey(secp, signed_msg_hash(&data)) {
+            Ok(pk) if pk.inner == xpub.public_key => Ok(()),
+            _ => Err(BsmsError::InvalidSignature),
+        }
+    }
+}
+
+impl fmt::Display for KeyRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\n{}",
+            signed_data(&self.token, &self.key, &self.description),
+            self.signature.to_base64()
+        )
+    }
+}
+
+impl FromStr for KeyRecord {
+    type Err = BsmsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lines = s.trim_end().lines().collect::<Vec<_>>();
+        let (version, token, key, description, signature) = match lines.as_slice() {
+            [version, token, key, description, signature] => {
+                (version, token, key, description, signature)
+            }
+            _ => return Err(BsmsError::InvalidRecord),
+        };
+        check_version(version)?;
+
+        let key = DescriptorPublicKey::from_str(key).map_err(|_| BsmsError::InvalidRecord)?;
+        match &key {
+            DescriptorPublicKey::XPub(xkey)
+                if xkey.derivation_path.is_master() && xkey.wildcard == Wildcard::None => {}
+            _ => return Err(BsmsError::InvalidRecord),
+        }
+        if description.len() > MAX_DESCRIPTION_LEN {
+            return Err(BsmsError::InvalidDescription);
+        }
+
+        Ok(KeyRecord {
+            token: token.parse()?,
+            key,
+            description: description.to_string(),
+            signature: MessageSignature::from_base64(signature)
+                .map_err(|_| BsmsError::InvalidSignature)?,
+        })
+    }
+}
+
+/// The record the coordinator sends to the signers, containing the multisig descriptor
+///
+/// The descriptor is a [BIP389] multipath descriptor for the `/0/*` and `/1/*` paths of each key,
+/// which can be used to create a wallet with [`Wallet::new_single`].
+///
+/// See the [module-level documentation](self) for an example.
+///
+/// [BIP389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+/// [`Wallet::new_single`]: crate::Wallet::new_single
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorRecord {
+    descriptor: ExtendedDescriptor,
+    first_address: Address<NetworkUnchecked>,
+}
+
+impl DescriptorRecord {
+    /// Assemble a `wsh(sortedmulti())` descriptor requiring `threshold` signatures of the keys of
+    /// `key_records`
+    ///
+    /// The key records are verified and must all use the same token.
+    pub fn new(
+        threshold: usize,
+        key_records: &[KeyRecord],
+        network: Network,
+        secp: &SecpCtx,
+    ) -> Result<Self, BsmsError> {
+        if threshold == 0 || threshold > key_records.len() {
+            return Err(BsmsError::InvalidThreshold);
+        }
+        for record in key_records {
+            if record.token != key_records[0].token {
+                return Err(BsmsError::TokenMismatch);
+            }
+            record.verify(secp)?;
+        }
+
+        let keys = key_records
+            .iter()
+            .map(|record| format!("{}/<0;1>/*", record.key))
+            .collect::<Vec<_>>();
+        let descriptor = ExtendedDescriptor::from_str(&format!(
+            "wsh(sortedmulti({},{}))",
+            threshold,
+            keys.join(",")
+        ))?;
+        let first_address = first_address(&descriptor, network)?;
+
+        Ok(DescriptorRecord {
+            descriptor,
+            first_address: first_address.as_unchecked().clone(),
+        })
+    }
+
+    /// The multipath descriptor of the wallet
+    pub fn descriptor(&self) -> &ExtendedDescriptor {
+        &self.descriptor
+    }
+
+    /// The first address of the wallet, as claimed by the record
+    pub fn first_address(&self) -> &Address<NetworkUnchecked> {
+        &self.first_address
+    }
+
+    /// Verify that the descriptor contains `key` and derives the first address of the record on
+    /// `network`
+    ///
+    /// `key` is the key of the signer verifying the record, e.g. [`KeyRecord::key`].
+    pub fn verify(&self, key: &DescriptorPublicKey, network: Network) -> Result<(), BsmsError> {
+        let key = match key {
+            DescriptorPublicKey::XPub(xkey) => xkey,
+            _ => return Err(BsmsError::MissingKey),
+        };
+        let has_key = self.descriptor.for_any_key(|pk| match pk {
+            DescriptorPublicKey::MultiXPub(xkey) => {
+                xkey.origin == key.origin && xkey.xkey == key.xkey
+            }
+            _ => false,
+        });
+        if !has_key {
+            return Err(BsmsError::MissingKey);
+        }
+
+        let address = first_address(&self.descriptor, network)?;
+        if !self.first_address.is_valid_for_network(network)
+            || *self.first_address.assume_checked_ref() != address
+        {
+            return Err(BsmsError::FirstAddressMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for DescriptorRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let descriptor = self.descriptor.to_string();
+        let template = descriptor
+            .split_once('#')
+            .map_or(descriptor.as_str(), |(template, _)| template)
+            .replace("/<0;1>/*", "/**");
+        write!(
+            f,
+            "{}\n{}\n{}\n{}",
+            VERSION,
+            template,
+            PATH_RESTRICTIONS,
+            self.first_address.assume_checked_ref()
+        )
+    }
+}
+
+impl FromStr for DescriptorRecord {
+    type Err = BsmsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lines = s.trim_end().lines().collect::<Vec<_>>();
+        let (version, template, path_restrictions, first_address) = match lines.as_slice() {
+            [version, template, path_restrictions, first_address] => {
+                (version, template, path_restrictions, first_address)
+            }
+            _ => return Err(BsmsError::InvalidRecord),
+        };
+        check_version(version)?;
+        if *path_restrictions != PATH_RESTRICTIONS {
+            return Err(BsmsError::InvalidRecord);
+        }
+
+        let descriptor = ExtendedDescriptor::from_str(&template.replace("/**", "/<0;1>/*"))?;
+        let first_address = first_address
+            .parse::<Address<NetworkUnchecked>>()
+            .map_err(|_| BsmsError::InvalidRecord)?;
+
+        Ok(DescriptorRecord {
+            descriptor,
+            first_address,
+        })
+    }
+}
+
+impl IntoWalletDescriptor for &DescriptorRecord {
+    fn into_wallet_descriptor(
+        self,
+        secp: &SecpCtx,
+        network: Network,
+    ) -> Result<(ExtendedDescriptor, KeyMap), DescriptorError> {
+        self.descriptor
+            .clone()
+            .into_wallet_descriptor(secp, network)
+    }
+}
+
+/// The first four lines of a key record, signed by its key
+fn signed_data(token: &Token, key: &DescriptorPublicKey, description: &str) -> String {
+    format!("{}\n{}\n{}\n{}", VERSION, token, key, description)
+}
+
+fn check_version(version: &str) -> Result<(), BsmsError> {
+    if version != VERSION {
+        return Err(BsmsError::UnsupportedVersion(version.to_string()));
+    }
+    Ok(())
+}
+
+/// The first external address of a multipath descriptor
+fn first_address(descriptor: &ExtendedDescriptor, network: Network) -> Result<Address, BsmsError> {
+    let external = descriptor
+        .clone()
+        .into_single_descriptors()?
+        .into_iter()
+        .next()
+        .expect("at least one descriptor");
+    let derived = external
+        .at_derivation_index(0)
+        .map_err(|_| DescriptorError::HardenedDerivationXpub)?;
+    Ok(derived.address(network)?)
+}
+
+#[cfg(feature = "bsms-encryption")]
+mod encryption {
+    use aes::cipher::generic_array::GenericArray;
+    use aes::cipher::{KeyIvInit, StreamCipher};
+    use aes::Aes256;
+    use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+    use bitcoin::hashes::{sha256, Hash, HashEngine};
+    use sha2::Sha512;
+
+    use super::*;
+
+    /// The salt of the key derivation
+    const SALT: &[u8] = b"No SPOF";
+
+    /// The iterations of the key derivation
+    const ITERATIONS: u32 = 2048;
+
+    /// AES-256 in counter mode, with a 128 bits big endian counter
+    type Aes256Ctr = ctr::Ctr128BE<Aes256>;
+
+    impl Token {
+        /// Encrypt a record with the token
+        ///
+        /// The encryption key is derived from the token with PBKDF2-HMAC-SHA512, the record is
+        /// encrypted with AES-256-CTR and authenticated with HMAC-SHA256, as specified by BIP129.
+        /// Records are returned unchanged by the empty token.
+        pub fn encrypt(&self, record: &str) -> String {
+            if self.is_none() {
+                return record.to_string();
+            }
+            let key = self.encryption_key();
+            let mac = self.mac(&key, record.as_bytes());
+            let mut data = mac.to_vec();
+            data.extend(aes_ctr(&key, &mac[..16], record.as_bytes()));
+            data.to_lower_hex_string()
+        }
+
+        /// Decrypt a record encrypted with [`Token::encrypt`]
+        pub fn decrypt(&self, encrypted: &str) -> Result<String, BsmsError> {
+            if self.is_none() {
+                return Ok(encrypted.to_string());
+            }
+            let data = Vec::<u8>::from_hex(encrypted.trim()).map_err(|_| BsmsError::Decryption)?;
+            if data.len() < 32 {
+                return Err(BsmsError::Decryption);
+            }
+            let (mac, ciphertext) = data.split_at(32);
+            let key = self.encryption_key();
+            let record = aes_ctr(&key, &mac[..16], ciphertext);
+            if self.mac(&key, &record)[..] != *mac {
+                return Err(BsmsError::Decryption);
+            }
+            String::from_utf8(record).map_err(|_| BsmsError::Decryption)
+        }
+
+        /// PBKDF2-HMAC-SHA512 with the token as password, truncated to the 32 bytes of an AES-256
+        /// key
+        pub(super) fn encryption_key(&self) -> [u8; 32] {
+            let mut key = [0u8; 32];
+            pbkdf2::pbkdf2_hmac::<Sha512>(&self.0, SALT, ITERATIONS, &mut key);
+            key
+        }
+
+        /// HMAC-SHA256 of the hex token and the record, keyed by the hash of the encryption key
+        pub(super) fn mac(&self, key: &[u8; 32], record: &[u8]) -> [u8; 32] {
+            let mac_key = sha256::Hash::hash(key);
+            let mut engine = HmacEngine::<sha256::Hash>::new(mac_key.as_byte_array());
+            engine.input(self.to_string().as_bytes());
+            engine.input(record);
+            Hmac::from_engine(engine).to_byte_array()
+        }
+    }
+
+    /// Encrypt or decrypt `data` with AES-256 in counter mode
+    fn aes_ctr(key: &[u8; 32], iv: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut out = data.to_vec();
+        Aes256Ctr::new(key.into(), GenericArray::from_slice(iv)).apply_keystream(&mut out);
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::bip32::Xpriv;
+    use bitcoin::secp256k1::Secp256k1;
+
+    const TPRV_0: &str = "tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS";
+    const TPRV_1: &str = "tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy";
+
+    fn key_record(tprv: &str, token: &Token) -> KeyRecord {
+        let secp = Secp256k1::new();
+        KeyRecord::new(
+            Xpriv::from_str(tprv).unwrap(),
+            &DerivationPath::from_str("m/48h/1h/0h/2h").unwrap(),
+            token.clone(),
+            "signer",
+            Network::Testnet,
+            &secp,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_key_record() {
+        let secp = Secp256k1::new();
+        let token = Token::standard();
+        let record = key_record(TPRV_0, &token);
+        record.verify(&secp).unwrap();
+
+        let parsed = KeyRecord::from_str(&record.to_string()).unwrap();
+        assert_eq!(parsed, record);
+        assert_eq!(parsed.token(), &token);
+        parsed.verify(&secp).unwrap();
+
+        // the signature covers the description
+        let tampered = record.to_string().replace("\nsigner\n", "\nsigned\n");
+        let tampered = KeyRecord::from_str(&tampered).unwrap();
+        assert!(matches!(
+            tampered.verify(&secp),
+            Err(BsmsError::InvalidSignature)
+        ));
+
+        let xpub = record.key().to_string();
+        let err = KeyRecord::new(
+            Xpub::from_str(xpub.split_once(']').unwrap().1).unwrap(),
+            &DerivationPath::master(),
+            token,
+            "signer",
+            Network::Testnet,
+            &secp,
+        );
+        assert!(matches!(err, Err(BsmsError::MissingPrivateKey)));
+        assert!(matches!(
+            KeyRecord::from_str(&record.to_string().replace("BSMS 1.0", "BSMS 2.0")),
+            Err(BsmsError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_token() {
+        assert_eq!(Token::none().to_string(), "00");
+        assert_eq!(Token::from_str("00").unwrap(), Token::none());
+        assert_eq!(Token::standard().to_string().len(), 16);
+        assert_eq!(Token::extended().to_string().len(), 32);
+        let token = Token::from_str("a54044308ceac9b7").unwrap();
+        assert_eq!(token.to_string(), "a54044308ceac9b7");
+        assert!(matches!(
+            Token::from_str("a54044"),
+            Err(BsmsError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn test_descriptor_record() {
+        let secp = Secp256k1::new();
+        let token = Token::none();
+        let records = [key_record(TPRV_0, &token), key_record(TPRV_1, &token)];
+
+        let record = DescriptorRecord::new(2, &records, Network::Testnet, &secp).unwrap();
+        let text = record.to_string();
+        assert!(text.starts_with("BSMS 1.0\nwsh(sortedmulti(2,["));
+        assert!(text.contains("/**,["));
+        let parsed = DescriptorRecord::from_str(&text).unwrap();
+        assert_eq!(parsed, record);
+        for key_record in &records {
+            parsed.verify(key_record.key(), Network::Testnet).unwrap();
+        }
+
+        // a coordinator could swap the first address, or use another key
+        let lines = text.lines().collect::<Vec<_>>();
+        let swapped = text.replace(lines[3], "tb1q4525hmgw265tl3drrl8jjta7ayffu6jf68ltjd");
+        let swapped = DescriptorRecord::from_str(&swapped).unwrap();
+        assert!(matches!(
+            swapped.verify(records[0].key(), Network::Testnet),
+            Err(BsmsError::FirstAddressMismatch)
+        ));
+        let other = DescriptorRecord::new(1, &records[1..], Network::Testnet, &secp).unwrap();
+        assert!(matches!(
+            other.verify(records[0].key(), Network::Testnet),
+            Err(BsmsError::MissingKey)
+        ));
+
+        assert!(matches!(
+            DescriptorRecord::new(3, &records, Network::Testnet, &secp),
+            Err(BsmsError::InvalidThreshold)
+        ));
+        let mismatch = [
+            key_record(TPRV_0, &token),
+            key_record(TPRV_1, &Token::standard()),
+        ];
+        assert!(matches!(
+            DescriptorRecord::new(2, &mismatch, Network::Testnet, &secp),
+            Err(BsmsError::TokenMismatch)
+        ));
+    }
+
+    #[cfg(feature = "bsms-encryption")]
+    #[test]
+    fn test_encryption() {
+        let token = Token::extended();
+        let record = key_record(TPRV_0, &token).to_string();
+
+        let encrypted = token.encrypt(&record);
+        assert_ne!(encrypted, record);
+        assert_eq!(token.decrypt(&encrypted).unwrap(), record);
+
+        // decrypting with another token or a tampered record fails
+        assert!(matches!(
+            Token::extended().decrypt(&encrypted),
+            Err(BsmsError::Decryption)
+        ));
+        let mut tampered = encrypted.into_bytes();
+        let last = tampered.len() - 1;
+        tampered[last] = if tampered[last] == b'0' { b'1' } else { b'0' };
+        assert!(matches!(
+            token.decrypt(&String::from_utf8(tampered).unwrap()),
+            Err(BsmsError::Decryption)
+        ));
+
+        assert_eq!(Token::none().encrypt(&record), record);
+    }
+
+    #[cfg(feature = "bsms-encryption")]
+    #[test]
+    fn test_encryption_vector() {
+        // the key record of `TPRV_0` for the token, encrypted with the PBKDF2, HMAC and AES-CTR of
+        // Python's hashlib and cryptography:
+        //
+        //     key = hashlib.pbkdf2_hmac("sha512", bytes.fromhex(token), b"No SPOF", 2048, 32)
+        //     mac = hmac.new(sha256(key).digest(), token.encode() + record, "sha256").digest()
+        //     encryptor = Cipher(algorithms.AES(key), modes.CTR(mac[:16])).encryptor()
+        //     encrypted = mac + encryptor.update(record) + encryptor.finalize()
+        let token = Token::from_str("a54044308ceac9b7").unwrap();
+        let record = key_record(TPRV_0, &token).to_string();
+        assert_eq!(
+            record,
+            "BSMS 1.0\n\
+             a54044308ceac9b7\n\
+             [b6dff990/48'/1'/0'/2']tpubDEYNGD172pDL2SiB6bfaZ1DJKby3EW7du9Wuuxs6HgvsaE1p8sWobVY528eycGSGPDjoayyevzaoEfYz7d6YxiDotkCJhxnSC6fopgD2sGC\n\
+             signer\n\
+             H7YNydMcQfrkbPiiY1L6sbbgZv/QTMcZQLfKUMgy/g3dAxpQHWjQg2DSudn8box1Sm06ClOzBXYI3TmPmVvbIro="
+        );
+
+        let key = token.encryption_key();
+        assert_eq!(
+            key.to_lower_hex_string(),
+            "847fba0c67fac00c7db56b106ac5040de236765fbb7e3bf7dedcbb6b3baeaa61"
+        );
+        assert_eq!(
+            token.mac(&key, record.as_bytes()).to_lower_hex_string(),
+            "2b1271216919be495d18390848ccd9a3c76157bb24a891d6fc59b18031364902"
+        );
+        let encrypted = "2b1271216919be495d18390848ccd9a3c76157bb24a891d6fc59b1803136490201dd5a5d7f2f5bfd59851017822394aebad1288586cae9d14d362f15f8fc5114d06cce097389ff60314a0b4f6b2d737c4bc15e7efa93401dff52ae331b42405b3ea2107985bea0cd6a232b33a0697cda2bd11eee74c40005bf549b6688247b37059e26362058335096af7c3a3ac9533d1fd136b1ae04e0dd1fa2a1ab1857b1745a67ac549af21e2fde68200c83b7ef3650261fb5689a85674e03738614a45a5b1665ca85af92cc5948082ab267e05711000923b38ed4405253017efcd844fa1703f9cb3c7f3789c8352f4c0e36dde4db951b9ccdd7d89da8e6e4f259dea85267cb1c59920a8a80419da6db6dc28fc7a49b0983c6b67b8c3f3e609b708d44b09f";
+        assert_eq!(token.encrypt(&record), encrypted);
+        assert_eq!(token.decrypt(encrypted).unwrap(), record);
+    }
+}
@@ -23,6 +23,9 @@ pub enum Error {
     HardenedDerivationXpub,
     /// The descriptor contains multipath keys
     MultiPath,
+    /// The multipath descriptor doesn't have exactly two derivation paths, one for the external
+    /// keychain and one for the internal keychain
+    MultiPathLength(usize),
 
     /// Error thrown while working with [`keys`](crate::keys)
     Key(crate::keys::KeyError),
@@ -44,6 +47,9 @@ pub enum Error {
     Hex(bitcoin::hex::HexToBytesError),
     /// The provided wallet descriptors are identical
     ExternalAndInternalAreTheSame,
+    /// The descriptor templates can't derive custom keychains, which don't have a standard
+    /// derivation path
+    CustomKeychain,
 }
 
 impl From<crate::keys::KeyError> for Error {
@@ -69,7 +75,12 @@ impl fmt::Display for Error {
             ),
             Self::MultiPath => write!(
                 f,
-                "The descriptor contains multipath keys, which are not supported yet"
+                "The descriptor contains multipath keys, which are only supported when creating a wallet from a multipath descriptor"
+            ),
+            Self::MultiPathLength(len) => write!(
+                f,
+                "The multipath descriptor has {} derivation paths, expected 2",
+                len
             ),
             Self::Key(err) => write!(f, "Key error: {}", err),
             Self::Policy(err) => write!(f, "Policy error: {}", err),
@@ -84,6 +95,10 @@ impl fmt::Display for Error {
             Self::ExternalAndInternalAreTheSame => {
                 write!(f, "External and internal descriptors are the same")
             }
+            Self::CustomKeychain => write!(
+                f,
+                "Custom keychains don't have a standard derivation path for descriptor templates"
+            ),
         }
     }
 }
@@ -24,7 +24,7 @@ use bitcoin::{psbt, taproot};
 use bitcoin::{Network, TxOut};
 
 use miniscript::descriptor::{
-    DefiniteDescriptorKey, DescriptorMultiXKey, DescriptorSecretKey, DescriptorType,
+    DefiniteDescriptorKey, DerivPaths, DescriptorMultiXKey, DescriptorSecretKey, DescriptorType,
     DescriptorXKey, InnerXKey, KeyMap, SinglePubKey, Wildcard,
 };
 pub use miniscript::{
@@ -34,18 +34,21 @@ use miniscript::{ForEachKey, MiniscriptKey, TranslatePk};
 
 use crate::descriptor::policy::BuildSatisfaction;
 
+pub mod bsms;
 pub mod checksum;
 #[doc(hidden)]
 pub mod dsl;
 pub mod error;
 pub mod policy;
 pub mod template;
+pub mod wallet_policy;
 
 pub use self::checksum::calc_checksum;
 use self::checksum::calc_checksum_bytes;
 pub use self::error::Error as DescriptorError;
 pub use self::policy::Policy;
 use self::template::DescriptorTemplateOut;
+pub use self::wallet_policy::{WalletPolicy, WalletPolicyError};
 use crate::keys::{IntoDescriptorKey, KeyError};
 use crate::wallet::signer::SignersContainer;
 use crate::wallet::utils::SecpCtx;
@@ -319,6 +322,135 @@ pub(crate) fn into_wallet_descriptor_checked<T: IntoWalletDescriptor>(
     Ok((descriptor, keymap))
 }
 
+/// Split a [BIP389] multipath descriptor into the external and internal descriptors of a wallet
+///
+/// The descriptor must have exactly two derivation paths (e.g. `<0;1>`): the first one is used
+/// for the external keychain and the second one for the internal keychain. Multipath secret keys
+/// in the key map are split the same way.
+///
+/// The returned descriptors are not checked yet, see [`into_wallet_descriptor_checked`].
+///
+/// [BIP389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+pub(crate) fn split_multipath(
+    descriptor: ExtendedDescriptor,
+    keymap: KeyMap,
+) -> Result<[(ExtendedDescriptor, KeyMap); 2], DescriptorError> {
+    let mut descriptors = descriptor.into_single_descriptors()?;
+    if descriptors.len() != 2 {
+        return Err(DescriptorError::MultiPathLength(descriptors.len()));
+    }
+
+    let mut keymaps = [KeyMap::new(), KeyMap::new()];
+    for (pk, sk) in keymap {
+        if pk.is_multipath() {
+            let keys = pk.into_single_keys().into_iter().zip(sk.into_single_keys());
+            for (keymap, (pk, sk)) in keymaps.iter_mut().zip(keys) {
+                keymap.insert(pk, sk);
+            }
+        } else {
+            for keymap in keymaps.iter_mut() {
+                keymap.insert(pk.clone(), sk.clone());
+            }
+        }
+    }
+
+    let [external_keymap, internal_keymap] = keymaps;
+    let internal = descriptors.pop().expect("checked length");
+    let external = descriptors.pop().expect("checked length");
+
+    Ok([(external, external_keymap), (internal, internal_keymap)])
+}
+
+/// Turn the extended keys of a descriptor deriving `/0/*` into [BIP389] multipath keys deriving
+/// `/<0;1>/*`, in the descriptor and in its key map
+///
+/// The other keys are left untouched, so the descriptor is not a multipath descriptor if none of
+/// its keys derives `/0/*`.
+///
+/// [BIP389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+pub(crate) fn to_multipath(
+    descriptor: &ExtendedDescriptor,
+    keymap: &KeyMap,
+) -> Option<(ExtendedDescriptor, KeyMap)> {
+    struct MultipathTranslator;
+
+    impl miniscript::Translator<DescriptorPublicKey, DescriptorPublicKey, ()> for MultipathTranslator {
+        fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<DescriptorPublicKey, ()> {
+            Ok(multipath_pk(pk))
+        }
+        miniscript::translate_hash_clone!(DescriptorPublicKey, DescriptorPublicKey, ());
+    }
+
+    let descriptor = descriptor.translate_pk(&mut MultipathTranslator).ok()?;
+    let keymap = keymap
+        .iter()
+        .map(|(pk, sk)| {
+            let sk = match sk {
+                DescriptorSecretKey::XPrv(xkey) => {
+                    multipath_xkey(xkey).map_or_else(|| sk.clone(), DescriptorSecretKey::MultiXPrv)
+                }
+                other => other.clone(),
+            };
+            (multipath_pk(pk), sk)
+        })
+        .collect();
+
+    Some((descriptor, keymap))
+}
+
+/// The multipath key deriving `/<0;1>/*` of a public key deriving `/0/*`, or the key itself
+fn multipath_pk(pk: &DescriptorPublicKey) -> DescriptorPublicKey {
+    match pk {
+        DescriptorPublicKey::XPub(xkey) => {
+            multipath_xkey(xkey).map_or_else(|| pk.clone(), DescriptorPublicKey::MultiXPub)
+        }
+        other => other.clone(),
+    }
+}
+
+/// The multipath key deriving `/<0;1>/*` of an extended key deriving `/0/*`
+fn multipath_xkey<K: InnerXKey + Clone>(
+    xkey: &DescriptorXKey<K>,
+) -> Option<DescriptorMultiXKey<K>> {
+    let (last, parent) = xkey.derivation_path.as_ref().split_last()?;
+    if xkey.wildcard == Wildcard::None || *last != (ChildNumber::Normal { index: 0 }) {
+        return None;
+    }
+    let parent = DerivationPath::from(parent);
+    let paths = (0..2)
+        .map(|index| parent.child(ChildNumber::Normal { index }))
+        .collect();
+
+    Some(DescriptorMultiXKey {
+        origin: xkey.origin.clone(),
+        xkey: xkey.xkey.clone(),
+        derivation_paths: DerivPaths::new(paths)?,
+        wildcard: xkey.wildcard,
+    })
+}
+
+/// Join the external and internal descriptors of a wallet into a [BIP389] multipath descriptor
+/// deriving `/<0;1>/*`, along with the key map of the external descriptor
+///
+/// Returns `None` if the descriptors don't only differ by their extended keys deriving `/0/*` in
+/// the external descriptor and `/1/*` in the internal one.
+///
+/// [BIP389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+pub(crate) fn join_multipath_descriptors(
+    external: &ExtendedDescriptor,
+    external_keymap: &KeyMap,
+    internal: &ExtendedDescriptor,
+) -> Option<(ExtendedDescriptor, KeyMap)> {
+    let (multipath, keymap) = to_multipath(external, external_keymap)?;
+    let [(split_external, _), (split_internal, _)] =
+        split_multipath(multipath.clone(), KeyMap::new()).ok()?;
+    if split_external == *external && split_internal == *internal {
+        Some((multipath, keymap))
+    } else {
+        None
+    }
+}
+
 #[doc(hidden)]
 /// Used internally mainly by the `descriptor!()` and `fragment!()` macros
 pub trait CheckMiniscript<Ctx: miniscript::ScriptContext> {
@@ -875,6 +1007,110 @@ mod test {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_split_multipath_descriptor() {
+        let secp = Secp256k1::new();
+        let split_multipath_descriptor = |descriptor: &str| {
+            let (descriptor, keymap) = descriptor
+                .into_wallet_descriptor(&secp, Network::Testnet)
+                .unwrap();
+            split_multipath(descriptor, keymap)
+        };
+
+        let descriptor = "wpkh([73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M/<0;1>/*)";
+        let [(external, _), (internal, _)] = split_multipath_descriptor(descriptor).unwrap();
+        assert_eq!(
+            external.to_string(),
+            "wpkh([73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M/0/*)#2ag6nxcd"
+        );
+        assert_eq!(
+            internal.to_string(),
+            "wpkh([73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M/1/*)#mfdmwng4"
+        );
+
+        // multipath secret keys are split too
+        let xprv = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L").unwrap();
+        let derivation_paths = miniscript::descriptor::DerivPaths::new(vec![
+            DerivationPath::from_str("m/0").unwrap(),
+            DerivationPath::from_str("m/1").unwrap(),
+        ])
+        .unwrap();
+        let pk = DescriptorPublicKey::MultiXPub(DescriptorMultiXKey {
+            origin: None,
+            xkey: Xpub::from_priv(&secp, &xprv),
+            derivation_paths: derivation_paths.clone(),
+            wildcard: Wildcard::Unhardened,
+        });
+        let sk = DescriptorSecretKey::MultiXPrv(DescriptorMultiXKey {
+            origin: None,
+            xkey: xprv,
+            derivation_paths,
+            wildcard: Wildcard::Unhardened,
+        });
+        let descriptor = Descriptor::new_wpkh(pk.clone()).unwrap();
+        let keymap: KeyMap = [(pk, sk)].into_iter().collect();
+        let [(external, external_keymap), (internal, internal_keymap)] =
+            split_multipath(descriptor, keymap).unwrap();
+        assert_eq!(
+            external.to_string_with_secret(&external_keymap),
+            format!("wpkh({}/0/*)#u9elyq85", xprv)
+        );
+        assert_eq!(
+            internal.to_string_with_secret(&internal_keymap),
+            format!("wpkh({}/1/*)#d3u7e4hv", xprv)
+        );
+
+        // descriptors without exactly two paths can't be split
+        let descriptor = "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*)";
+        let result = split_multipath_descriptor(descriptor);
+        assert_matches!(result, Err(DescriptorError::MultiPathLength(1)));
+
+        let descriptor = "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/<0;1;2>/*)";
+        let result = split_multipath_descriptor(descriptor);
+        assert_matches!(result, Err(DescriptorError::MultiPathLength(3)));
+    }
+
+    #[test]
+    fn test_join_multipath_descriptors() {
+        let secp = Secp256k1::new();
+        let xprv = "tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L";
+        let tpub = "[d34db33f/48'/1'/0'/2']tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK";
+        let descriptor = |xprv_steps: &str, tpub_steps: &str| {
+            let descriptor = format!(
+                "wsh(multi(1,{}/{},{}/{}))",
+                xprv, xprv_steps, tpub, tpub_steps
+            );
+            ExtendedDescriptor::parse_descriptor(&secp, &descriptor).unwrap()
+        };
+
+        // the keys deriving `/0/*` become multipath keys, whatever their other steps
+        let (external, external_keymap) = descriptor("0/*", "7/0/*");
+        let (internal, internal_keymap) = descriptor("1/*", "7/1/*");
+        let (multipath, keymap) =
+            join_multipath_descriptors(&external, &external_keymap, &internal).unwrap();
+        assert_eq!(
+            multipath
+                .to_string_with_secret(&keymap)
+                .split_once('#')
+                .unwrap()
+                .0,
+            format!("wsh(multi(1,{}/<0;1>/*,{}/7/<0;1>/*))", xprv, tpub)
+        );
+        let [(split_external, split_external_keymap), (split_internal, split_internal_keymap)] =
+            split_multipath(multipath, keymap).unwrap();
+        assert_eq!(split_external, external);
+        assert_eq!(split_external_keymap, external_keymap);
+        assert_eq!(split_internal, internal);
+        assert_eq!(split_internal_keymap, internal_keymap);
+
+        // every key must derive `/1/*` instead of `/0/*` in the internal descriptor
+        let (internal, _) = descriptor("1/*", "7/0/*");
+        assert!(join_multipath_descriptors(&external, &external_keymap, &internal).is_none());
+        let (external, external_keymap) = descriptor("2/*", "7/2/*");
+        let (internal, _) = descriptor("3/*", "7/3/*");
+        assert!(join_multipath_descriptors(&external, &external_keymap, &internal).is_none());
+    }
+
     #[test]
     fn test_sh_wsh_sortedmulti_redeemscript() {
         use miniscript::psbt::PsbtInputExt;
@@ -14,6 +14,8 @@
 //! This module contains the definition of various common script templates that are ready to be
 //! used. See the documentation of each template for an example.
 
+use alloc::vec::Vec;
+
 use bitcoin::bip32;
 use bitcoin::Network;
 
@@ -203,6 +205,254 @@ impl<K: IntoDescriptorKey<Tap>> DescriptorTemplate for P2TR<K> {
     }
 }
 
+/// Taproot multisig template. Expands to a descriptor `tr(internal_key,multi_a(threshold,key,...))`
+///
+/// The keys can only spend together through the `multi_a` script path. The key path can be used as
+/// a cheaper fallback by setting `internal_key`, otherwise it's made unspendable with the "nothing
+/// up my sleeve" point of [BIP341].
+///
+/// ## Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use bdk_wallet::bitcoin::Network;
+/// # use bdk_wallet::{KeychainKind, Wallet};
+/// use bdk_wallet::miniscript::DescriptorPublicKey;
+/// use bdk_wallet::template::TaprootMultisig;
+///
+/// let alice = "[b6dff990/87'/1'/0']tpubDCYPm5BVAwpgRqW7mLMgWKtxQ8mEWyrHDRfsGo3GNL633A7UaYZ1pTjk8aYBAMguGB5LvpEftGxEK3TafLmS6NeqNj9TMmszXNosdN3CV7K";
+/// let bob = "[34b00776/87'/1'/0']tpubDDGQbtYCb2fGRJx4TgnbjrJxvwfpoGMMJ8dkMubJoi8kbypaLqe8eePSBuYqxumKaXkvDyCcGnnR9SxUYRdfmtqR4ERjJjt1tXgfSGcphf2";
+/// let carol = "[6834a63c/87'/1'/0']tpubDDc8aiJQ8murbQHC1bPgFHWar4UUTMyWqAX4HuctGxiDP3xk6QB3d13nERroyBpuV42kkMh18dJMbhrxM4c9cJ1bAkruvFEwGSYKe1yv5am";
+/// let key = |xpub: &str, keychain: u32| {
+///     DescriptorPublicKey::from_str(&format!("{}/{}/*", xpub, keychain)).unwrap()
+/// };
+/// let mut wallet = Wallet::new_no_persist(
+///     TaprootMultisig {
+///         internal_key: None,
+///         threshold: 2,
+///         keys: vec![key(alice, 0), key(bob, 0), key(carol, 0)],
+///     },
+///     TaprootMultisig {
+///         internal_key: None,
+///         threshold: 2,
+///         keys: vec![key(alice, 1), key(bob, 1), key(carol, 1)],
+///     },
+///     Network::Testnet,
+/// )?;
+///
+/// assert_eq!(wallet.next_unused_address(KeychainKind::External)?.to_string(), "tb1p40sglspw020k0x8ehzt0ymec08wh6f8x64vq4rul4sy566t8a9qs9wgzsx");
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+///
+/// [BIP341]: https://github.com/bitcoin/bips/blob/master/bip-0341.mediawiki#constructing-and-spending-taproot-outputs
+pub struct TaprootMultisig<K: IntoDescriptorKey<Tap>> {
+    /// The key spending through the key path, if any
+    pub internal_key: Option<K>,
+    /// The number of signatures required in the script path
+    pub threshold: usize,
+    /// The keys of the script path
+    pub keys: Vec<K>,
+}
+
+impl<K: IntoDescriptorKey<Tap>> DescriptorTemplate for TaprootMultisig<K> {
+    fn build(self, _network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
+        let internal_key = taproot::internal_key(self.internal_key)?;
+        descriptor!(tr(internal_key, multi_a_vec(self.threshold, self.keys)))
+    }
+}
+
+/// Taproot decaying multisig template. Expands to a descriptor
+/// `tr(internal_key,{multi_a(threshold,key,...),and_v(v:multi_a(decayed_threshold,key,...),older(blocks))})`
+///
+/// Once the output is `blocks` blocks deep, only `decayed_threshold` of the keys are required to
+/// spend it, for instance a 2-of-3 that becomes a 1-of-3. The key path is handled like in
+/// [`TaprootMultisig`].
+///
+/// ## Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use bdk_wallet::bitcoin::Network;
+/// # use bdk_wallet::{KeychainKind, Wallet};
+/// use bdk_wallet::miniscript::DescriptorPublicKey;
+/// use bdk_wallet::template::DecayingMultisig;
+///
+/// let alice = "[b6dff990/87'/1'/0']tpubDCYPm5BVAwpgRqW7mLMgWKtxQ8mEWyrHDRfsGo3GNL633A7UaYZ1pTjk8aYBAMguGB5LvpEftGxEK3TafLmS6NeqNj9TMmszXNosdN3CV7K";
+/// let bob = "[34b00776/87'/1'/0']tpubDDGQbtYCb2fGRJx4TgnbjrJxvwfpoGMMJ8dkMubJoi8kbypaLqe8eePSBuYqxumKaXkvDyCcGnnR9SxUYRdfmtqR4ERjJjt1tXgfSGcphf2";
+/// let carol = "[6834a63c/87'/1'/0']tpubDDc8aiJQ8murbQHC1bPgFHWar4UUTMyWqAX4HuctGxiDP3xk6QB3d13nERroyBpuV42kkMh18dJMbhrxM4c9cJ1bAkruvFEwGSYKe1yv5am";
+/// let key = |xpub: &str, keychain: u32| {
+///     DescriptorPublicKey::from_str(&format!("{}/{}/*", xpub, keychain)).unwrap()
+/// };
+/// let mut wallet = Wallet::new_no_persist(
+///     DecayingMultisig {
+///         internal_key: None,
+///         threshold: 2,
+///         decayed_threshold: 1,
+///         blocks: 52560,
+///         keys: vec![key(alice, 0), key(bob, 0), key(carol, 0)],
+///     },
+///     DecayingMultisig {
+///         internal_key: None,
+///         threshold: 2,
+///         decayed_threshold: 1,
+///         blocks: 52560,
+///         keys: vec![key(alice, 1), key(bob, 1), key(carol, 1)],
+///     },
+///     Network::Testnet,
+/// )?;
+///
+/// assert_eq!(wallet.next_unused_address(KeychainKind::External)?.to_string(), "tb1p65anmhn828qjvcn3fwf6rzxe5za9z8cnu7ehzd30ckfxtwe73xpqhwgrjg");
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub struct DecayingMultisig<K: IntoDescriptorKey<Tap> + Clone> {
+    /// The key spending through the key path, if any
+    pub internal_key: Option<K>,
+    /// The number of signatures required before the timelock expires
+    pub threshold: usize,
+    /// The number of signatures required after the timelock expires
+    pub decayed_threshold: usize,
+    /// The relative timelock, in blocks
+    pub blocks: u32,
+    /// The keys of the script paths
+    pub keys: Vec<K>,
+}
+
+impl<K: IntoDescriptorKey<Tap> + Clone> DescriptorTemplate for DecayingMultisig<K> {
+    fn build(self, _network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
+        let internal_key = taproot::internal_key(self.internal_key)?;
+        descriptor!(tr(
+            internal_key,
+            {
+                multi_a_vec(self.threshold, self.keys.clone()),
+                and_v(v:multi_a_vec(self.decayed_threshold, self.keys), older(self.blocks))
+            }
+        ))
+    }
+}
+
+/// Taproot multisig with a recovery key template. Expands to a descriptor
+/// `tr(internal_key,{multi_a(threshold,key,...),and_v(v:pk(recovery_key),after(lock_time))})`
+///
+/// The recovery key can spend alone once the absolute timelock `lock_time`, a block height or a
+/// UNIX timestamp, has expired. The key path is handled like in [`TaprootMultisig`].
+///
+/// ## Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use bdk_wallet::bitcoin::Network;
+/// # use bdk_wallet::{KeychainKind, Wallet};
+/// use bdk_wallet::miniscript::DescriptorPublicKey;
+/// use bdk_wallet::template::TimelockedRecovery;
+///
+/// let alice = "[b6dff990/87'/1'/0']tpubDCYPm5BVAwpgRqW7mLMgWKtxQ8mEWyrHDRfsGo3GNL633A7UaYZ1pTjk8aYBAMguGB5LvpEftGxEK3TafLmS6NeqNj9TMmszXNosdN3CV7K";
+/// let bob = "[34b00776/87'/1'/0']tpubDDGQbtYCb2fGRJx4TgnbjrJxvwfpoGMMJ8dkMubJoi8kbypaLqe8eePSBuYqxumKaXkvDyCcGnnR9SxUYRdfmtqR4ERjJjt1tXgfSGcphf2";
+/// let carol = "[6834a63c/87'/1'/0']tpubDDc8aiJQ8murbQHC1bPgFHWar4UUTMyWqAX4HuctGxiDP3xk6QB3d13nERroyBpuV42kkMh18dJMbhrxM4c9cJ1bAkruvFEwGSYKe1yv5am";
+/// let key = |xpub: &str, keychain: u32| {
+///     DescriptorPublicKey::from_str(&format!("{}/{}/*", xpub, keychain)).unwrap()
+/// };
+/// let mut wallet = Wallet::new_no_persist(
+///     TimelockedRecovery {
+///         internal_key: None,
+///         threshold: 2,
+///         keys: vec![key(alice, 0), key(bob, 0)],
+///         recovery_key: key(carol, 0),
+///         lock_time: 3_000_000,
+///     },
+///     TimelockedRecovery {
+///         internal_key: None,
+///         threshold: 2,
+///         keys: vec![key(alice, 1), key(bob, 1)],
+///         recovery_key: key(carol, 1),
+///         lock_time: 3_000_000,
+///     },
+///     Network::Testnet,
+/// )?;
+///
+/// assert_eq!(wallet.next_unused_address(KeychainKind::External)?.to_string(), "tb1p5ahqwl89z5hq2ynj9705ppsgtphapxkm6v0vljspt4zfx5xs37zsu8rxst");
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub struct TimelockedRecovery<K: IntoDescriptorKey<Tap>> {
+    /// The key spending through the key path, if any
+    pub internal_key: Option<K>,
+    /// The number of signatures required in the multisig script path
+    pub threshold: usize,
+    /// The keys of the multisig script path
+    pub keys: Vec<K>,
+    /// The key of the recovery script path
+    pub recovery_key: K,
+    /// The absolute timelock of the recovery script path
+    pub lock_time: u32,
+}
+
+impl<K: IntoDescriptorKey<Tap>> DescriptorTemplate for TimelockedRecovery<K> {
+    fn build(self, _network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
+        let internal_key = taproot::internal_key(self.internal_key)?;
+        descriptor!(tr(
+            internal_key,
+            {
+                multi_a_vec(self.threshold, self.keys),
+                and_v(v:pk(self.recovery_key), after(self.lock_time))
+            }
+        ))
+    }
+}
+
+/// Taproot inheritance template. Expands to a descriptor
+/// `tr(owner_key,and_v(v:pk(heir_key),older(blocks)))`
+///
+/// The owner spends through the key path, while the heir can only spend an output once it is
+/// `blocks` blocks deep. The owner has to move the funds periodically to keep the heir locked out.
+///
+/// ## Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use bdk_wallet::bitcoin::Network;
+/// # use bdk_wallet::{KeychainKind, Wallet};
+/// use bdk_wallet::miniscript::DescriptorPublicKey;
+/// use bdk_wallet::template::Inheritance;
+///
+/// let alice = "[b6dff990/87'/1'/0']tpubDCYPm5BVAwpgRqW7mLMgWKtxQ8mEWyrHDRfsGo3GNL633A7UaYZ1pTjk8aYBAMguGB5LvpEftGxEK3TafLmS6NeqNj9TMmszXNosdN3CV7K";
+/// let bob = "[34b00776/87'/1'/0']tpubDDGQbtYCb2fGRJx4TgnbjrJxvwfpoGMMJ8dkMubJoi8kbypaLqe8eePSBuYqxumKaXkvDyCcGnnR9SxUYRdfmtqR4ERjJjt1tXgfSGcphf2";
+/// let key = |xpub: &str, keychain: u32| {
+///     DescriptorPublicKey::from_str(&format!("{}/{}/*", xpub, keychain)).unwrap()
+/// };
+/// let mut wallet = Wallet::new_no_persist(
+///     Inheritance {
+///         owner_key: key(alice, 0),
+///         heir_key: key(bob, 0),
+///         blocks: 52560,
+///     },
+///     Inheritance {
+///         owner_key: key(alice, 1),
+///         heir_key: key(bob, 1),
+///         blocks: 52560,
+///     },
+///     Network::Testnet,
+/// )?;
+///
+/// assert_eq!(wallet.next_unused_address(KeychainKind::External)?.to_string(), "tb1p9d0lh9kskqn0l6az64leape98emxq5a3cff523qfrf7y7dp5a8yqwxtcx0");
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub struct Inheritance<K: IntoDescriptorKey<Tap>> {
+    /// The key of the owner, spending through the key path
+    pub owner_key: K,
+    /// The key of the heir, spending through the timelocked script path
+    pub heir_key: K,
+    /// The relative timelock of the heir, in blocks
+    pub blocks: u32,
+}
+
+impl<K: IntoDescriptorKey<Tap>> DescriptorTemplate for Inheritance<K> {
+    fn build(self, _network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
+        descriptor!(tr(
+            self.owner_key,
+            and_v(v:pk(self.heir_key), older(self.blocks))
+        ))
+    }
+}
+
 /// BIP44 template. Expands to `pkh(key/44'/{0,1}'/0'/{0,1}/*)`
 ///
 /// Since there are hardened derivation steps, this template requires a private derivable key (generally a `xprv`/`tprv`).
@@ -232,7 +482,13 @@ pub struct Bip44<K: DerivableKey<Legacy>>(pub K, pub KeychainKind);
 
 impl<K: DerivableKey<Legacy>> DescriptorTemplate for Bip44<K> {
     fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
-        P2Pkh(legacy::make_bipxx_private(44, self.0, self.1, network)?).build(network)
+        P2Pkh(legacy::make_bipxx_private(
+            44,
+            self.0,
+            change_index(self.1)?,
+            network,
+        )?)
+        .build(network)
     }
 }
 
@@ -270,7 +526,11 @@ pub struct Bip44Public<K: DerivableKey<Legacy>>(pub K, pub bip32::Fingerprint, p
 impl<K: DerivableKey<Legacy>> DescriptorTemplate for Bip44Public<K> {
     fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
         P2Pkh(legacy::make_bipxx_public(
-            44, self.0, self.1, self.2, network,
+            44,
+            self.0,
+            self.1,
+            change_index(self.2)?,
+            network,
         )?)
         .build(network)
     }
@@ -305,7 +565,13 @@ pub struct Bip49<K: DerivableKey<Segwitv0>>(pub K, pub KeychainKind);
 
 impl<K: DerivableKey<Segwitv0>> DescriptorTemplate for Bip49<K> {
     fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
-        P2Wpkh_P2Sh(segwit_v0::make_bipxx_private(49, self.0, self.1, network)?).build(network)
+        P2Wpkh_P2Sh(segwit_v0::make_bipxx_private(
+            49,
+            self.0,
+            change_index(self.1)?,
+            network,
+        )?)
+        .build(network)
     }
 }
 
@@ -343,7 +609,11 @@ pub struct Bip49Public<K: DerivableKey<Segwitv0>>(pub K, pub bip32::Fingerprint,
 impl<K: DerivableKey<Segwitv0>> DescriptorTemplate for Bip49Public<K> {
     fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
         P2Wpkh_P2Sh(segwit_v0::make_bipxx_public(
-            49, self.0, self.1, self.2, network,
+            49,
+            self.0,
+            self.1,
+            change_index(self.2)?,
+            network,
         )?)
         .build(network)
     }
@@ -378,7 +648,13 @@ pub struct Bip84<K: DerivableKey<Segwitv0>>(pub K, pub KeychainKind);
 
 impl<K: DerivableKey<Segwitv0>> DescriptorTemplate for Bip84<K> {
     fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
-        P2Wpkh(segwit_v0::make_bipxx_private(84, self.0, self.1, network)?).build(network)
+        P2Wpkh(segwit_v0::make_bipxx_private(
+            84,
+            self.0,
+            change_index(self.1)?,
+            network,
+        )?)
+        .build(network)
     }
 }
 
@@ -416,7 +692,11 @@ pub struct Bip84Public<K: DerivableKey<Segwitv0>>(pub K, pub bip32::Fingerprint,
 impl<K: DerivableKey<Segwitv0>> DescriptorTemplate for Bip84Public<K> {
     fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
         P2Wpkh(segwit_v0::make_bipxx_public(
-            84, self.0, self.1, self.2, network,
+            84,
+            self.0,
+            self.1,
+            change_index(self.2)?,
+            network,
         )?)
         .build(network)
     }
@@ -451,7 +731,13 @@ pub struct Bip86<K: DerivableKey<Tap>>(pub K, pub KeychainKind);
 
 impl<K: DerivableKey<Tap>> DescriptorTemplate for Bip86<K> {
     fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
-        P2TR(segwit_v1::make_bipxx_private(86, self.0, self.1, network)?).build(network)
+        P2TR(segwit_v1::make_bipxx_private(
+            86,
+            self.0,
+            change_index(self.1)?,
+            network,
+        )?)
+        .build(network)
     }
 }
 
@@ -489,12 +775,240 @@ pub struct Bip86Public<K: DerivableKey<Tap>>(pub K, pub bip32::Fingerprint, pub
 impl<K: DerivableKey<Tap>> DescriptorTemplate for Bip86Public<K> {
     fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
         P2TR(segwit_v1::make_bipxx_public(
-            86, self.0, self.1, self.2, network,
+            86,
+            self.0,
+            self.1,
+            change_index(self.2)?,
+            network,
         )?)
         .build(network)
     }
 }
 
+/// Script types of [BIP48] multisig accounts
+///
+/// The script type is the fourth, hardened, step of the account derivation path
+/// `m/48'/{0,1}'/0'/{1,2}'`.
+///
+/// [BIP48]: https://github.com/bitcoin/bips/blob/master/bip-0048.mediawiki
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Bip48ScriptType {
+    /// P2SH-P2WSH, with script type `1'`
+    P2shP2wsh,
+    /// P2WSH, with script type `2'`
+    P2wsh,
+}
+
+impl Bip48ScriptType {
+    fn index(&self) -> u32 {
+        match self {
+            Bip48ScriptType::P2shP2wsh => 1,
+            Bip48ScriptType::P2wsh => 2,
+        }
+    }
+}
+
+/// BIP48 multisig template. Expands to `sh(wsh(sortedmulti(thresh,key/48'/{0,1}'/0'/1'/{0,1}/*,...)))`
+/// or `wsh(sortedmulti(thresh,key/48'/{0,1}'/0'/2'/{0,1}/*,...))` depending on the script type
+///
+/// Since there are hardened derivation steps, this template requires private derivable keys (generally `xprv`/`tprv`).
+///
+/// See [`Bip48Public`] for a template that can work with `xpub`/`tpub`.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::str::FromStr;
+/// # use bdk_wallet::bitcoin::{PrivateKey, Network};
+/// # use bdk_wallet::{Wallet, KeychainKind};
+/// use bdk_wallet::template::{Bip48, Bip48ScriptType};
+///
+/// let key_a = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS")?;
+/// let key_b = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy")?;
+/// let keys = vec![key_a, key_b];
+/// let mut wallet = Wallet::new_no_persist(
+///     Bip48(1, keys.clone(), Bip48ScriptType::P2wsh, KeychainKind::External),
+///     Bip48(1, keys, Bip48ScriptType::P2wsh, KeychainKind::Internal),
+///     Network::Testnet,
+/// )?;
+///
+/// assert_eq!(wallet.next_unused_address(KeychainKind::External)?.to_string(), "tb1qz4yt48gn2fs3yr6lvchckstew64ekrlka5rrr56uln22x69keseq3vqnps");
+/// assert!(wallet.public_descriptor(KeychainKind::External).to_string().contains("/48'/1'/0'/2']"));
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub struct Bip48<K: DerivableKey<Segwitv0>>(
+    pub usize,
+    pub Vec<K>,
+    pub Bip48ScriptType,
+    pub KeychainKind,
+);
+
+impl<K: DerivableKey<Segwitv0>> DescriptorTemplate for Bip48<K> {
+    fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
+        let keys = multisig::make_bipxx_private(
+            48,
+            Some(self.2.index()),
+            self.1,
+            change_index(self.3)?,
+            network,
+        )?;
+        match self.2 {
+            Bip48ScriptType::P2shP2wsh => descriptor!(sh(wsh(sortedmulti_vec(self.0, keys)))),
+            Bip48ScriptType::P2wsh => descriptor!(wsh(sortedmulti_vec(self.0, keys))),
+        }
+    }
+}
+
+/// BIP48 multisig public template. Expands to `sh(wsh(sortedmulti(thresh,key/{0,1}/*,...)))` or
+/// `wsh(sortedmulti(thresh,key/{0,1}/*,...))` depending on the script type
+///
+/// This assumes that the keys used have already been derived with `m/48'/0'/0'/{1,2}'` for Mainnet
+/// or `m/48'/1'/0'/{1,2}'` for Testnet, which is what hardware wallets such as Coldcard export for
+/// multisig setups.
+///
+/// This template requires the parent fingerprint of each key to populate correctly the metadata of PSBTs.
+///
+/// See [`Bip48`] for a template that does the full derivation, but requires private data
+/// for the keys.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::str::FromStr;
+/// # use bdk_wallet::bitcoin::{PrivateKey, Network};
+/// # use bdk_wallet::{Wallet, KeychainKind};
+/// use bdk_wallet::template::{Bip48Public, Bip48ScriptType};
+///
+/// let key_a = bitcoin::bip32::Xpub::from_str("tpubDEYNGD172pDL2SiB6bfaZ1DJKby3EW7du9Wuuxs6HgvsaE1p8sWobVY528eycGSGPDjoayyevzaoEfYz7d6YxiDotkCJhxnSC6fopgD2sGC")?;
+/// let fingerprint_a = bitcoin::bip32::Fingerprint::from_str("b6dff990")?;
+/// let key_b = bitcoin::bip32::Xpub::from_str("tpubDE8WcdSH7SBJWrXiJYbWyLuYCoeFv925voAtzYssJsfXgi2WGA3kxMbdp1fP2zWX4sL14jXJyEPtTDjTAJfDrjpPZoTnK9UMcsgAbTD4c7W")?;
+/// let fingerprint_b = bitcoin::bip32::Fingerprint::from_str("34b00776")?;
+/// let keys = vec![(key_a, fingerprint_a), (key_b, fingerprint_b)];
+/// let mut wallet = Wallet::new_no_persist(
+///     Bip48Public(1, keys.clone(), Bip48ScriptType::P2wsh, KeychainKind::External),
+///     Bip48Public(1, keys, Bip48ScriptType::P2wsh, KeychainKind::Internal),
+///     Network::Testnet,
+/// )?;
+///
+/// assert_eq!(wallet.next_unused_address(KeychainKind::External)?.to_string(), "tb1qz4yt48gn2fs3yr6lvchckstew64ekrlka5rrr56uln22x69keseq3vqnps");
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub struct Bip48Public<K: DerivableKey<Segwitv0>>(
+    pub usize,
+    pub Vec<(K, bip32::Fingerprint)>,
+    pub Bip48ScriptType,
+    pub KeychainKind,
+);
+
+impl<K: DerivableKey<Segwitv0>> DescriptorTemplate for Bip48Public<K> {
+    fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
+        let keys = multisig::make_bipxx_public(
+            48,
+            Some(self.2.index()),
+            self.1,
+            change_index(self.3)?,
+            network,
+        )?;
+        match self.2 {
+            Bip48ScriptType::P2shP2wsh => descriptor!(sh(wsh(sortedmulti_vec(self.0, keys)))),
+            Bip48ScriptType::P2wsh => descriptor!(wsh(sortedmulti_vec(self.0, keys))),
+        }
+    }
+}
+
+/// BIP87 multisig template. Expands to `wsh(sortedmulti(thresh,key/87'/{0,1}'/0'/{0,1}/*,...))`
+///
+/// Since there are hardened derivation steps, this template requires private derivable keys (generally `xprv`/`tprv`).
+///
+/// See [`Bip87Public`] for a template that can work with `xpub`/`tpub`.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::str::FromStr;
+/// # use bdk_wallet::bitcoin::{PrivateKey, Network};
+/// # use bdk_wallet::{Wallet, KeychainKind};
+/// use bdk_wallet::template::Bip87;
+///
+/// let key_a = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS")?;
+/// let key_b = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy")?;
+/// let keys = vec![key_a, key_b];
+/// let mut wallet = Wallet::new_no_persist(
+///     Bip87(2, keys.clone(), KeychainKind::External),
+///     Bip87(2, keys, KeychainKind::Internal),
+///     Network::Testnet,
+/// )?;
+///
+/// assert_eq!(wallet.next_unused_address(KeychainKind::External)?.to_string(), "tb1qrx9ry78v6hz7wzms7kema4c9sw9f55jftrfpzm37xhw4cxn24d5sexgly3");
+/// assert!(wallet.public_descriptor(KeychainKind::External).to_string().contains("/87'/1'/0']"));
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub struct Bip87<K: DerivableKey<Segwitv0>>(pub usize, pub Vec<K>, pub KeychainKind);
+
+impl<K: DerivableKey<Segwitv0>> DescriptorTemplate for Bip87<K> {
+    fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
+        let keys = multisig::make_bipxx_private(87, None, self.1, change_index(self.2)?, network)?;
+        descriptor!(wsh(sortedmulti_vec(self.0, keys)))
+    }
+}
+
+/// BIP87 multisig public template. Expands to `wsh(sortedmulti(thresh,key/{0,1}/*,...))`
+///
+/// This assumes that the keys used have already been derived with `m/87'/0'/0'` for Mainnet or
+/// `m/87'/1'/0'` for Testnet.
+///
+/// This template requires the parent fingerprint of each key to populate correctly the metadata of PSBTs.
+///
+/// See [`Bip87`] for a template that does the full derivation, but requires private data
+/// for the keys.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::str::FromStr;
+/// # use bdk_wallet::bitcoin::{PrivateKey, Network};
+/// # use bdk_wallet::{Wallet, KeychainKind};
+/// use bdk_wallet::template::Bip87Public;
+///
+/// let key_a = bitcoin::bip32::Xpub::from_str("tpubDCYPm5BVAwpgRqW7mLMgWKtxQ8mEWyrHDRfsGo3GNL633A7UaYZ1pTjk8aYBAMguGB5LvpEftGxEK3TafLmS6NeqNj9TMmszXNosdN3CV7K")?;
+/// let fingerprint_a = bitcoin::bip32::Fingerprint::from_str("b6dff990")?;
+/// let key_b = bitcoin::bip32::Xpub::from_str("tpubDDGQbtYCb2fGRJx4TgnbjrJxvwfpoGMMJ8dkMubJoi8kbypaLqe8eePSBuYqxumKaXkvDyCcGnnR9SxUYRdfmtqR4ERjJjt1tXgfSGcphf2")?;
+/// let fingerprint_b = bitcoin::bip32::Fingerprint::from_str("34b00776")?;
+/// let keys = vec![(key_a, fingerprint_a), (key_b, fingerprint_b)];
+/// let mut wallet = Wallet::new_no_persist(
+///     Bip87Public(2, keys.clone(), KeychainKind::External),
+///     Bip87Public(2, keys, KeychainKind::Internal),
+///     Network::Testnet,
+/// )?;
+///
+/// assert_eq!(wallet.next_unused_address(KeychainKind::External)?.to_string(), "tb1qrx9ry78v6hz7wzms7kema4c9sw9f55jftrfpzm37xhw4cxn24d5sexgly3");
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub struct Bip87Public<K: DerivableKey<Segwitv0>>(
+    pub usize,
+    pub Vec<(K, bip32::Fingerprint)>,
+    pub KeychainKind,
+);
+
+impl<K: DerivableKey<Segwitv0>> DescriptorTemplate for Bip87Public<K> {
+    fn build(self, network: Network) -> Result<DescriptorTemplateOut, DescriptorError> {
+        let keys = multisig::make_bipxx_public(87, None, self.1, change_index(self.2)?, network)?;
+        descriptor!(wsh(sortedmulti_vec(self.0, keys)))
+    }
+}
+
+/// The index of the change level of the derivation paths of the BIP templates
+///
+/// The BIPs only define the external (`0`) and internal (`1`) chains, custom keychains don't have
+/// a standard derivation path.
+fn change_index(keychain: KeychainKind) -> Result<u32, DescriptorError> {
+    match keychain {
+        KeychainKind::External => Ok(0),
+        KeychainKind::Internal => Ok(1),
+        KeychainKind::Custom(_) => Err(DescriptorError::CustomKeychain),
+    }
+}
+
 macro_rules! expand_make_bipxx {
     ( $mod_name:ident, $ctx:ty ) => {
         mod $mod_name {
@@ -503,7 +1017,7 @@ macro_rules! expand_make_bipxx {
             pub(super) fn make_bipxx_private<K: DerivableKey<$ctx>>(
                 bip: u32,
                 key: K,
-                keychain: KeychainKind,
+                change: u32,
                 network: Network,
             ) -> Result<impl IntoDescriptorKey<$ctx>, DescriptorError> {
                 let mut derivation_path = alloc::vec::Vec::with_capacity(4);
@@ -519,14 +1033,7 @@ macro_rules! expand_make_bipxx {
                 }
                 derivation_path.push(bip32::ChildNumber::from_hardened_idx(0)?);
 
-                match keychain {
-                    KeychainKind::External => {
-                        derivation_path.push(bip32::ChildNumber::from_normal_idx(0)?)
-                    }
-                    KeychainKind::Internal => {
-                        derivation_path.push(bip32::ChildNumber::from_normal_idx(1)?)
-                    }
-                };
+                derivation_path.push(bip32::ChildNumber::from_normal_idx(change)?);
 
                 let derivation_path: bip32::DerivationPath = derivation_path.into();
 
@@ -536,13 +1043,11 @@ macro_rules! expand_make_bipxx {
                 bip: u32,
                 key: K,
                 parent_fingerprint: bip32::Fingerprint,
-                keychain: KeychainKind,
+                change: u32,
                 network: Network,
             ) -> Result<impl IntoDescriptorKey<$ctx>, DescriptorError> {
-                let derivation_path: bip32::DerivationPath = match keychain {
-                    KeychainKind::External => vec![bip32::ChildNumber::from_normal_idx(0)?].into(),
-                    KeychainKind::Internal => vec![bip32::ChildNumber::from_normal_idx(1)?].into(),
-                };
+                let derivation_path: bip32::DerivationPath =
+                    vec![bip32::ChildNumber::from_normal_idx(change)?].into();
 
                 let source_path = bip32::DerivationPath::from(vec![
                     bip32::ChildNumber::from_hardened_idx(bip)?,
@@ -563,6 +1068,98 @@ expand_make_bipxx!(legacy, Legacy);
 expand_make_bipxx!(segwit_v0, Segwitv0);
 expand_make_bipxx!(segwit_v1, Tap);
 
+mod multisig {
+    use super::*;
+
+    fn account_path(
+        bip: u32,
+        script_type: Option<u32>,
+        network: Network,
+    ) -> Result<bip32::DerivationPath, DescriptorError> {
+        let mut path = vec![
+            bip32::ChildNumber::from_hardened_idx(bip)?,
+            match network {
+                Network::Bitcoin => bip32::ChildNumber::from_hardened_idx(0)?,
+                _ => bip32::ChildNumber::from_hardened_idx(1)?,
+            },
+            bip32::ChildNumber::from_hardened_idx(0)?,
+        ];
+        if let Some(script_type) = script_type {
+            path.push(bip32::ChildNumber::from_hardened_idx(script_type)?);
+        }
+
+        Ok(path.into())
+    }
+
+    fn change_path(change: u32) -> Result<bip32::DerivationPath, DescriptorError> {
+        Ok(vec![bip32::ChildNumber::from_normal_idx(change)?].into())
+    }
+
+    pub(super) fn make_bipxx_private<K: DerivableKey<Segwitv0>>(
+        bip: u32,
+        script_type: Option<u32>,
+        keys: Vec<K>,
+        change: u32,
+        network: Network,
+    ) -> Result<Vec<impl IntoDescriptorKey<Segwitv0>>, DescriptorError> {
+        let derivation_path = account_path(bip, script_type, network)?.extend(change_path(change)?);
+
+        Ok(keys
+            .into_iter()
+            .map(|key| (key, derivation_path.clone()))
+            .collect())
+    }
+
+    pub(super) fn make_bipxx_public<K: DerivableKey<Segwitv0>>(
+        bip: u32,
+        script_type: Option<u32>,
+        keys: Vec<(K, bip32::Fingerprint)>,
+        change: u32,
+        network: Network,
+    ) -> Result<Vec<impl IntoDescriptorKey<Segwitv0>>, DescriptorError> {
+        let source_path = account_path(bip, script_type, network)?;
+        let derivation_path = change_path(change)?;
+
+        Ok(keys
+            .into_iter()
+            .map(|(key, parent_fingerprint)| {
+                (
+                    key,
+                    (parent_fingerprint, source_path.clone()),
+                    derivation_path.clone(),
+                )
+            })
+            .collect())
+    }
+}
+
+mod taproot {
+    use super::*;
+
+    use bitcoin::secp256k1::XOnlyPublicKey;
+
+    use crate::keys::DescriptorKey;
+
+    /// The "nothing up my sleeve" point of BIP341, which has no known private key
+    const UNSPENDABLE_KEY: [u8; 32] = [
+        0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a,
+        0x5e, 0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80,
+        0x3a, 0xc0,
+    ];
+
+    pub(super) fn internal_key<K: IntoDescriptorKey<Tap>>(
+        key: Option<K>,
+    ) -> Result<DescriptorKey<Tap>, DescriptorError> {
+        match key {
+            Some(key) => Ok(key.into_descriptor_key()?),
+            None => {
+                let key = XOnlyPublicKey::from_slice(&UNSPENDABLE_KEY).expect("valid point");
+                Ok(key.into_descriptor_key()?)
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     // test existing descriptor templates, make sure they are expanded to the right descriptors
@@ -571,9 +1168,14 @@ mod test {
     use core::str::FromStr;
 
     use super::*;
-    use crate::descriptor::{DescriptorError, DescriptorMeta};
+    use crate::descriptor::policy::{BuildSatisfaction, PkOrF, SatisfiableItem};
+    use crate::descriptor::{DescriptorError, DescriptorMeta, ExtractPolicy};
     use crate::keys::ValidNetworks;
+    use crate::wallet::signer::SignersContainer;
+    use alloc::sync::Arc;
     use assert_matches::assert_matches;
+    use bitcoin::secp256k1::{Secp256k1, XOnlyPublicKey};
+    use bitcoin::{absolute, Sequence};
     use miniscript::descriptor::{DescriptorPublicKey, KeyMap};
     use miniscript::Descriptor;
 
@@ -997,4 +1599,385 @@ mod test {
             ],
         );
     }
+
+    // custom keychains don't have a change level in the BIP derivation paths
+    #[test]
+    fn test_bip_templates_custom_keychain() {
+        let prvkey = bitcoin::bip32::Xpriv::from_str("xprv9s21ZrQH143K3GJpoapnV8SFfukcVBSfeCficPSGfubmSFDxo1kuHnLisriDvSnRRuL2Qrg5ggqHKNVpxR86QEC8w35uxmGoggxtQTPvfUu").unwrap();
+        let pubkey = bitcoin::bip32::Xpub::from_str("xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ").unwrap();
+        let fingerprint = bitcoin::bip32::Fingerprint::from_str("73c5da0a").unwrap();
+        let custom = KeychainKind::Custom(1);
+        assert_matches!(
+            Bip84(prvkey, custom).build(Network::Bitcoin),
+            Err(DescriptorError::CustomKeychain)
+        );
+        assert_matches!(
+            Bip86Public(pubkey, fingerprint, custom).build(Network::Bitcoin),
+            Err(DescriptorError::CustomKeychain)
+        );
+        assert_matches!(
+            Bip87(1, vec![prvkey], custom).build(Network::Bitcoin),
+            Err(DescriptorError::CustomKeychain)
+        );
+    }
+
+    // BIP48 `wsh(sortedmulti(thresh,key/48'/{0,1}'/0'/2'/{0,1}/*,...))`
+    #[test]
+    fn test_bip48_template() {
+        let prvkey_a = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS").unwrap();
+        let prvkey_b = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy").unwrap();
+        check(
+            Bip48(
+                1,
+                vec![prvkey_a, prvkey_b],
+                Bip48ScriptType::P2wsh,
+                KeychainKind::External,
+            )
+            .build(Network::Testnet),
+            true,
+            false,
+            false,
+            Network::Regtest,
+            &[
+                "bcrt1qz4yt48gn2fs3yr6lvchckstew64ekrlka5rrr56uln22x69kesequ42452",
+                "bcrt1q5k0287ykzy3np7fhf4j22ef59ragvy4lwygwy3nx53wzpfyd3q7snsujzz",
+                "bcrt1qu7uxrq9f5plnx7ewsfhgw6sfn6gkspuc6nx70k5re56wacsk36jqyf9a8s",
+            ],
+        );
+        check(
+            Bip48(
+                1,
+                vec![prvkey_a, prvkey_b],
+                Bip48ScriptType::P2shP2wsh,
+                KeychainKind::External,
+            )
+            .build(Network::Testnet),
+            true,
+            false,
+            false,
+            Network::Regtest,
+            &[
+                "2N5TczbXbAibzQ6BBUVLeX8DXcKgfsSXTCg",
+                "2NEG3YbRpYvWfkJZ9nTcq6aCA3FdqfDGxqs",
+                "2NEwta8P8yaYfJsyTuqyyUXzux6tB4ky5vJ",
+            ],
+        );
+
+        let (desc, _, _) = Bip48(
+            1,
+            vec![prvkey_a, prvkey_b],
+            Bip48ScriptType::P2shP2wsh,
+            KeychainKind::Internal,
+        )
+        .build(Network::Testnet)
+        .unwrap();
+        assert_eq!(desc.to_string(), "sh(wsh(sortedmulti(1,[b6dff990/48'/1'/0'/1']tpubDEYNGD172pDL1iGHVzRox52gs1eJZa9aWGCwXMhLhu97picjtNs7jq41d5xbXruAXRSkc93xn7x8jj8bZ9D8T1fv8zyKjoupGHm31xETEHf/1/*,[34b00776/48'/1'/0'/1']tpubDE8WcdSH7SBJTb7o8gC4cyBgwsPnuPjEdiBFAJd4zvietipew6m4fofTEsgc5cQhcTnfGDsTQyvq8wTMtDLW5fbBnXXvqQhV8Meug5Cdp3F/1/*)))#40hqwq9z");
+    }
+
+    // BIP48 public `wsh(sortedmulti(thresh,key/{0,1}/*,...))`
+    #[test]
+    fn test_bip48_public_template() {
+        let pubkey_a = bitcoin::bip32::Xpub::from_str("tpubDEYNGD172pDL2SiB6bfaZ1DJKby3EW7du9Wuuxs6HgvsaE1p8sWobVY528eycGSGPDjoayyevzaoEfYz7d6YxiDotkCJhxnSC6fopgD2sGC").unwrap();
+        let fingerprint_a = bitcoin::bip32::Fingerprint::from_str("b6dff990").unwrap();
+        let pubkey_b = bitcoin::bip32::Xpub::from_str("tpubDE8WcdSH7SBJWrXiJYbWyLuYCoeFv925voAtzYssJsfXgi2WGA3kxMbdp1fP2zWX4sL14jXJyEPtTDjTAJfDrjpPZoTnK9UMcsgAbTD4c7W").unwrap();
+        let fingerprint_b = bitcoin::bip32::Fingerprint::from_str("34b00776").unwrap();
+        let keys = vec![(pubkey_a, fingerprint_a), (pubkey_b, fingerprint_b)];
+        check(
+            Bip48Public(
+                1,
+                keys.clone(),
+                Bip48ScriptType::P2wsh,
+                KeychainKind::External,
+            )
+            .build(Network::Testnet),
+            true,
+            false,
+            false,
+            Network::Regtest,
+            &[
+                "bcrt1qz4yt48gn2fs3yr6lvchckstew64ekrlka5rrr56uln22x69kesequ42452",
+                "bcrt1q5k0287ykzy3np7fhf4j22ef59ragvy4lwygwy3nx53wzpfyd3q7snsujzz",
+                "bcrt1qu7uxrq9f5plnx7ewsfhgw6sfn6gkspuc6nx70k5re56wacsk36jqyf9a8s",
+            ],
+        );
+
+        let (desc, _, _) = Bip48Public(1, keys, Bip48ScriptType::P2wsh, KeychainKind::External)
+            .build(Network::Testnet)
+            .unwrap();
+        assert_eq!(desc.to_string(), "wsh(sortedmulti(1,[b6dff990/48'/1'/0'/2']tpubDEYNGD172pDL2SiB6bfaZ1DJKby3EW7du9Wuuxs6HgvsaE1p8sWobVY528eycGSGPDjoayyevzaoEfYz7d6YxiDotkCJhxnSC6fopgD2sGC/0/*,[34b00776/48'/1'/0'/2']tpubDE8WcdSH7SBJWrXiJYbWyLuYCoeFv925voAtzYssJsfXgi2WGA3kxMbdp1fP2zWX4sL14jXJyEPtTDjTAJfDrjpPZoTnK9UMcsgAbTD4c7W/0/*))#56t6xadk");
+    }
+
+    // BIP87 `wsh(sortedmulti(thresh,key/87'/{0,1}'/0'/{0,1}/*,...))`
+    #[test]
+    fn test_bip87_template() {
+        let prvkey_a = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS").unwrap();
+        let prvkey_b = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy").unwrap();
+        check(
+            Bip87(2, vec![prvkey_a, prvkey_b], KeychainKind::External).build(Network::Testnet),
+            true,
+            false,
+            false,
+            Network::Regtest,
+            &[
+                "bcrt1qrx9ry78v6hz7wzms7kema4c9sw9f55jftrfpzm37xhw4cxn24d5s5lze3t",
+                "bcrt1q8jzjnsg8tdm62gxy9cjh9ms0kw3wv6wetn2ph0n95n7rsv6nh7psphj700",
+                "bcrt1qvl0hf2cymuxrre7tczu9jmfqg0c0eu7n29423s3727g4zccecdvsdhuna9",
+            ],
+        );
+
+        // the threshold can't be higher than the number of keys
+        assert_matches!(
+            Bip87(3, vec![prvkey_a, prvkey_b], KeychainKind::External).build(Network::Testnet),
+            Err(DescriptorError::Miniscript(_))
+        );
+    }
+
+    // BIP87 public `wsh(sortedmulti(thresh,key/{0,1}/*,...))`
+    #[test]
+    fn test_bip87_public_template() {
+        let pubkey_a = bitcoin::bip32::Xpub::from_str("tpubDCYPm5BVAwpgRqW7mLMgWKtxQ8mEWyrHDRfsGo3GNL633A7UaYZ1pTjk8aYBAMguGB5LvpEftGxEK3TafLmS6NeqNj9TMmszXNosdN3CV7K").unwrap();
+        let fingerprint_a = bitcoin::bip32::Fingerprint::from_str("b6dff990").unwrap();
+        let pubkey_b = bitcoin::bip32::Xpub::from_str("tpubDDGQbtYCb2fGRJx4TgnbjrJxvwfpoGMMJ8dkMubJoi8kbypaLqe8eePSBuYqxumKaXkvDyCcGnnR9SxUYRdfmtqR4ERjJjt1tXgfSGcphf2").unwrap();
+        let fingerprint_b = bitcoin::bip32::Fingerprint::from_str("34b00776").unwrap();
+        check(
+            Bip87Public(
+                2,
+                vec![(pubkey_a, fingerprint_a), (pubkey_b, fingerprint_b)],
+                KeychainKind::External,
+            )
+            .build(Network::Testnet),
+            true,
+            false,
+            false,
+            Network::Regtest,
+            &[
+                "bcrt1qrx9ry78v6hz7wzms7kema4c9sw9f55jftrfpzm37xhw4cxn24d5s5lze3t",
+                "bcrt1q8jzjnsg8tdm62gxy9cjh9ms0kw3wv6wetn2ph0n95n7rsv6nh7psphj700",
+                "bcrt1qvl0hf2cymuxrre7tczu9jmfqg0c0eu7n29423s3727g4zccecdvsdhuna9",
+            ],
+        );
+    }
+
+    // extract the policy tree of a taproot template, whose root is a `Thresh` of the key path
+    // and the script paths
+    fn extract_tr_policy(
+        desc: Result<DescriptorTemplateOut, DescriptorError>,
+    ) -> Vec<SatisfiableItem> {
+        let secp = Secp256k1::new();
+        let (desc, keymap, _) = desc.unwrap();
+        let signers = Arc::new(SignersContainer::build(keymap, &desc, &secp));
+        let policy = desc
+            .extract_policy(&signers, BuildSatisfaction::None, &secp)
+            .unwrap()
+            .unwrap();
+        match policy.item {
+            SatisfiableItem::Thresh {
+                items,
+                threshold: 1,
+            } => items.into_iter().map(|policy| policy.item).collect(),
+            item => panic!("unexpected policy {:?}", item),
+        }
+    }
+
+    fn tr_keys() -> Vec<((bip32::Xpriv, bip32::DerivationPath), PkOrF)> {
+        let secp = Secp256k1::new();
+        [
+            "tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS",
+            "tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy",
+            "tprv8ZgxMBicQKsPdpkqS7Eair4YxjcuuvDPNYmKX3sCniCf16tHEVrjjiSXEkFRnUH77yXc6ZcwHHcLNfjdi5qUvw3VDfgYiH5mNsj5izuiu2N",
+        ]
+        .iter()
+        .map(|tprv| {
+            let tprv = bip32::Xpriv::from_str(tprv).unwrap();
+            let path = bip32::DerivationPath::from_str("m/0").unwrap();
+            let fingerprint = PkOrF::Fingerprint(tprv.fingerprint(&secp));
+            ((tprv, path), fingerprint)
+        })
+        .collect()
+    }
+
+    fn unspendable_key() -> PkOrF {
+        PkOrF::XOnlyPubkey(
+            XOnlyPublicKey::from_str(
+                "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0",
+            )
+            .unwrap(),
+        )
+    }
+
+    // TaprootMultisig `tr(internal_key,multi_a(threshold,key,...))`
+    #[test]
+    fn test_taproot_multisig_template() {
+        let (keys, fingerprints): (Vec<_>, Vec<_>) = tr_keys().into_iter().unzip();
+
+        let items = extract_tr_policy(
+            TaprootMultisig {
+                internal_key: None,
+                threshold: 2,
+                keys: keys.clone(),
+            }
+            .build(Network::Testnet),
+        );
+        assert_eq!(
+            items,
+            vec![
+                SatisfiableItem::SchnorrSignature(unspendable_key()),
+                SatisfiableItem::Multisig {
+                    keys: fingerprints.clone(),
+                    threshold: 2,
+                },
+            ]
+        );
+
+        let items = extract_tr_policy(
+            TaprootMultisig {
+                internal_key: Some(keys[0].clone()),
+                threshold: 2,
+                keys: keys[1..].to_vec(),
+            }
+            .build(Network::Testnet),
+        );
+        assert_eq!(
+            items,
+            vec![
+                SatisfiableItem::SchnorrSignature(fingerprints[0].clone()),
+                SatisfiableItem::Multisig {
+                    keys: fingerprints[1..].to_vec(),
+                    threshold: 2,
+                },
+            ]
+        );
+
+        assert_matches!(
+            TaprootMultisig {
+                internal_key: None,
+                threshold: 4,
+                keys,
+            }
+            .build(Network::Testnet),
+            Err(DescriptorError::Miniscript(_))
+        );
+    }
+
+    // DecayingMultisig
+    // `tr(internal_key,{multi_a(threshold,key,...),and_v(v:multi_a(decayed_threshold,key,...),older(blocks))})`
+    #[test]
+    fn test_decaying_multisig_template() {
+        let (keys, fingerprints): (Vec<_>, Vec<_>) = tr_keys().into_iter().unzip();
+
+        let items = extract_tr_policy(
+            DecayingMultisig {
+                internal_key: None,
+                threshold: 2,
+                decayed_threshold: 1,
+                blocks: 52560,
+                keys,
+            }
+            .build(Network::Testnet),
+        );
+        assert_eq!(items.len(), 3);
+        assert_eq!(
+            items[0],
+            SatisfiableItem::SchnorrSignature(unspendable_key())
+        );
+        assert_eq!(
+            items[1],
+            SatisfiableItem::Multisig {
+                keys: fingerprints.clone(),
+                threshold: 2,
+            }
+        );
+        assert_matches!(&items[2], SatisfiableItem::Thresh { items, threshold: 2 } if items.len() == 2);
+        if let SatisfiableItem::Thresh { items, .. } = &items[2] {
+            assert_eq!(
+                items[0].item,
+                SatisfiableItem::Multisig {
+                    keys: fingerprints,
+                    threshold: 1,
+                }
+            );
+            assert_eq!(
+                items[1].item,
+                SatisfiableItem::RelativeTimelock {
+                    value: Sequence::from_consensus(52560),
+                }
+            );
+        }
+    }
+
+    // TimelockedRecovery
+    // `tr(internal_key,{multi_a(threshold,key,...),and_v(v:pk(recovery_key),after(lock_time))})`
+    #[test]
+    fn test_timelocked_recovery_template() {
+        let (keys, fingerprints): (Vec<_>, Vec<_>) = tr_keys().into_iter().unzip();
+
+        let items = extract_tr_policy(
+            TimelockedRecovery {
+                internal_key: None,
+                threshold: 2,
+                keys: keys[..2].to_vec(),
+                recovery_key: keys[2].clone(),
+                lock_time: 3_000_000,
+            }
+            .build(Network::Testnet),
+        );
+        assert_eq!(items.len(), 3);
+        assert_eq!(
+            items[0],
+            SatisfiableItem::SchnorrSignature(unspendable_key())
+        );
+        assert_eq!(
+            items[1],
+            SatisfiableItem::Multisig {
+                keys: fingerprints[..2].to_vec(),
+                threshold: 2,
+            }
+        );
+        assert_matches!(&items[2], SatisfiableItem::Thresh { items, threshold: 2 } if items.len() == 2);
+        if let SatisfiableItem::Thresh { items, .. } = &items[2] {
+            assert_eq!(
+                items[0].item,
+                SatisfiableItem::SchnorrSignature(fingerprints[2].clone())
+            );
+            assert_eq!(
+                items[1].item,
+                SatisfiableItem::AbsoluteTimelock {
+                    value: absolute::LockTime::from_consensus(3_000_000),
+                }
+            );
+        }
+    }
+
+    // Inheritance `tr(owner_key,and_v(v:pk(heir_key),older(blocks)))`
+    #[test]
+    fn test_inheritance_template() {
+        let (keys, fingerprints): (Vec<_>, Vec<_>) = tr_keys().into_iter().unzip();
+
+        let items = extract_tr_policy(
+            Inheritance {
+                owner_key: keys[0].clone(),
+                heir_key: keys[1].clone(),
+                blocks: 52560,
+            }
+            .build(Network::Testnet),
+        );
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items[0],
+            SatisfiableItem::SchnorrSignature(fingerprints[0].clone())
+        );
+        assert_matches!(&items[1], SatisfiableItem::Thresh { items, threshold: 2 } if items.len() == 2);
+        if let SatisfiableItem::Thresh { items, .. } = &items[1] {
+            assert_eq!(
+                items[0].item,
+                SatisfiableItem::SchnorrSignature(fingerprints[1].clone())
+            );
+            assert_eq!(
+                items[1].item,
+                SatisfiableItem::RelativeTimelock {
+                    value: Sequence::from_consensus(52560),
+                }
+            );
+        }
+    }
 }
@@ -0,0 +1,505 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! BIP388 wallet policies
+//!
+//! Hardware wallets register multisig and miniscript wallets as [BIP388] wallet policies: a
+//! descriptor template where each key is replaced by a placeholder like `@0/**`, and the list of
+//! extended public keys the placeholders refer to. A [`WalletPolicy`] expands to a [BIP389]
+//! multipath descriptor, so it can be used to create a wallet with [`Wallet::new_single`], which
+//! splits it into the external and internal descriptors, and can be exported from a wallet with
+//! [`WalletPolicy::export_wallet`].
+//!
+//! ## Example
+//!
+//! ```
+//! # use bitcoin::Network;
+//! # use bdk_wallet::descriptor::WalletPolicy;
+//! # use bdk_wallet::Wallet;
+//! let policy = WalletPolicy::new(
+//!     "wsh(sortedmulti(2,@0/**,@1/**))",
+//!     vec![
+//!         "[c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe".parse()?,
+//!         "[73c5da0a/84h/1h/0h]tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M".parse()?,
+//!     ],
+//! )?;
+//! let wallet = Wallet::new_single_no_persist(&policy, Network::Testnet)?;
+//!
+//! let exported = WalletPolicy::export_wallet(&wallet)?;
+//! assert_eq!(exported, policy);
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BIP388]: https://github.com/bitcoin/bips/blob/master/bip-0388.mediawiki
+//! [BIP389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+//! [`Wallet::new_single`]: crate::Wallet::new_single
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use bdk_chain::collections::BTreeMap;
+use bitcoin::bip32::{ChildNumber, DerivationPath};
+use bitcoin::Network;
+use miniscript::descriptor::{DescriptorPublicKey, DescriptorXKey, KeyMap, Wildcard};
+use miniscript::{ForEachKey, TranslateErr, TranslatePk, Translator};
+
+use super::{split_multipath, DescriptorError, ExtendedDescriptor, IntoWalletDescriptor};
+use crate::types::KeychainKind;
+use crate::wallet::utils::SecpCtx;
+use crate::wallet::Wallet;
+
+/// Errors related to [`WalletPolicy`]s
+#[derive(Debug)]
+pub enum WalletPolicyError {
+    /// A key placeholder of the template is not `@i/**` or `@i/<M;N>/*`
+    InvalidPlaceholder,
+    /// A placeholder refers to a key that is missing or appears before the previous keys
+    KeyIndex(usize),
+    /// A key is not referenced by any placeholder of the template
+    UnusedKey(usize),
+    /// A key is not an extended public key without derivation steps
+    InvalidKey(usize),
+    /// A key appears more than once in the key list
+    DuplicateKey(usize),
+    /// The expanded template is not a valid descriptor
+    Descriptor(DescriptorError),
+    /// The wallet has no change descriptor, which a wallet policy requires
+    MissingChangeDescriptor,
+    /// The external and internal descriptors of the wallet can't be expressed as a single wallet
+    /// policy
+    IncompatibleDescriptors,
+}
+
+impl fmt::Display for WalletPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidPlaceholder => write!(f, "invalid key placeholder"),
+            Self::KeyIndex(i) => write!(f, "invalid or out of order key placeholder @{}", i),
+            Self::UnusedKey(i) => write!(f, "key @{} is not used in the template", i),
+            Self::InvalidKey(i) => write!(
+                f,
+                "key @{} is not an extended public key without derivation steps",
+                i
+            ),
+            Self::DuplicateKey(i) => write!(f, "key @{} appears more than once", i),
+            Self::Descriptor(e) => e.fmt(f),
+            Self::MissingChangeDescriptor => write!(f, "the wallet has no change descriptor"),
+            Self::IncompatibleDescriptors => write!(
+                f,
+                "the external and internal descriptors differ in more than their derivation step"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for WalletPolicyError {}
+
+impl From<DescriptorError> for WalletPolicyError {
+    fn from(e: DescriptorError) -> Self {
+        Self::Descriptor(e)
+    }
+}
+
+impl From<miniscript::Error> for WalletPolicyError {
+    fn from(e: miniscript::Error) -> Self {
+        Self::Descriptor(e.into())
+    }
+}
+
+/// A [BIP388] wallet policy
+///
+/// See the [module-level documentation](self) for an example.
+///
+/// [BIP388]: https://github.com/bitcoin/bips/blob/master/bip-0388.mediawiki
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletPolicy {
+    template: String,
+    keys: Vec<DescriptorPublicKey>,
+    descriptor: ExtendedDescriptor,
+}
+
+impl WalletPolicy {
+    /// Create a wallet policy from a descriptor template and its key list
+    ///
+    /// Each key must be an extended public key, optionally with its origin, and without
+    /// derivation steps or wildcard. The placeholders of `template` must refer to the keys in
+    /// order of first appearance, and each key must be used.
+    pub fn new(template: &str, keys: Vec<DescriptorPublicKey>) -> Result<Self, WalletPolicyError> {
+        for (i, key) in keys.iter().enumerate() {
+            match key {
+                DescriptorPublicKey::XPub(xkey)
+                    if xkey.derivation_path.is_master() && xkey.wildcard == Wildcard::None => {}
+                _ => return Err(WalletPolicyError::InvalidKey(i)),
+            }
+            if keys[..i].contains(key) {
+                return Err(WalletPolicyError::DuplicateKey(i));
+            }
+        }
+
+        let descriptor = ExtendedDescriptor::from_str(&expand_template(template, &keys)?)?;
+
+        Ok(WalletPolicy {
+            template: template.to_string(),
+            keys,
+            descriptor,
+        })
+    }
+
+    /// Export the descriptors of a wallet as a wallet policy
+    ///
+    /// The wallet must have a change descriptor, and its external and internal descriptors must
+    /// only differ in the derivation step preceding the wildcard of each key, e.g. `/0/*` and
+    /// `/1/*`. The secret keys of the wallet are not exported.
+    pub fn export_wallet(wallet: &Wallet) -> Result<Self, WalletPolicyError> {
+        if !wallet
+            .keychains()
+            .any(|(keychain, _)| *keychain == KeychainKind::Internal)
+        {
+            return Err(WalletPolicyError::MissingChangeDescriptor);
+        }
+        let external = wallet.get_descriptor_for_keychain(KeychainKind::External);
+        let internal = wallet.get_descriptor_for_keychain(KeychainKind::Internal);
+
+        let external_keys = descriptor_keys(external);
+        let internal_keys = descriptor_keys(internal);
+        if external_keys.len() != internal_keys.len() {
+            return Err(WalletPolicyError::IncompatibleDescriptors);
+        }
+
+        let mut keys = Vec::<DescriptorPublicKey>::new();
+        let mut placeholders = BTreeMap::new();
+        for (external_key, internal_key) in external_keys.into_iter().zip(internal_keys) {
+            let (key, external_step, internal_step) = match (&external_key, &internal_key) {
+                (DescriptorPublicKey::XPub(e), DescriptorPublicKey::XPub(i))
+                    if e.origin == i.origin
+                        && e.xkey == i.xkey
+                        && e.wildcard == Wildcard::Unhardened
+                        && i.wildcard == Wildcard::Unhardened
+                        && e.derivation_path.len() == 1
+                        && i.derivation_path.len() == 1 =>
+                {
+                    let key = DescriptorPublicKey::XPub(DescriptorXKey {
+                        origin: e.origin.clone(),
+                        xkey: e.xkey,
+                        derivation_path: DerivationPath::master(),
+                        wildcard: Wildcard::None,
+                    });
+                    (key, e.derivation_path[0], i.derivation_path[0])
+                }
+                _ => return Err(WalletPolicyError::IncompatibleDescriptors),
+            };
+
+            let index = match keys.iter().position(|k| *k == key) {
+                Some(index) => index,
+                None => {
+                    keys.push(key);
+                    keys.len() - 1
+                }
+            };
+            let placeholder = match (external_step, internal_step) {
+                (ChildNumber::Normal { index: 0 }, ChildNumber::Normal { index: 1 }) => {
+                    format!("@{}/**", index)
+                }
+                (ChildNumber::Normal { index: m }, ChildNumber::Normal { index: n }) => {
+                    format!("@{}/<{};{}>/*", index, m, n)
+                }
+                _ => return Err(WalletPolicyError::IncompatibleDescriptors),
+            };
+            placeholders.insert(external_key, placeholder);
+        }
+
+        let template = match external.translate_pk(&mut TemplateTranslator { placeholders }) {
+            Ok(template) => template.to_string(),
+            Err(TranslateErr::TranslatorErr(e)) => return Err(e),
+            Err(TranslateErr::OuterError(e)) => return Err(e.into()),
+        };
+        let template = template
+            .split_once('#')
+            .map_or(template.as_str(), |(template, _)| template);
+        let policy = WalletPolicy::new(template, keys)?;
+
+        // make sure the internal descriptor doesn't differ in anything else than the keys
+        let descriptors = policy.descriptor.clone().into_single_descriptors()?;
+        if descriptors != [external.clone(), internal.clone()] {
+            return Err(WalletPolicyError::IncompatibleDescriptors);
+        }
+
+        Ok(policy)
+    }
+
+    /// The descriptor template, with a placeholder like `@0/**` for each key
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// The keys the placeholders of the template refer to
+    pub fn keys(&self) -> &[DescriptorPublicKey] {
+        &self.keys
+    }
+
+    /// The [BIP389] multipath descriptor the wallet policy expands to
+    ///
+    /// [BIP389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+    pub fn descriptor(&self) -> &ExtendedDescriptor {
+        &self.descriptor
+    }
+
+    /// The external and internal descriptors the wallet policy expands to, deriving the first and
+    /// the second step of each placeholder respectively
+    pub fn into_descriptors(
+        self,
+    ) -> Result<(ExtendedDescriptor, ExtendedDescriptor), DescriptorError> {
+        let [(external, _), (internal, _)] = split_multipath(self.descriptor, KeyMap::new())?;
+        Ok((external, internal))
+    }
+}
+
+impl IntoWalletDescriptor for WalletPolicy {
+    fn into_wallet_descriptor(
+        self,
+        secp: &SecpCtx,
+        network: Network,
+    ) -> Result<(ExtendedDescriptor, KeyMap), DescriptorError> {
+        self.descriptor.into_wallet_descriptor(secp, network)
+    }
+}
+
+impl IntoWalletDescriptor for &WalletPolicy {
+    fn into_wallet_descriptor(
+        self,
+        secp: &SecpCtx,
+        network: Network,
+    ) -> Result<(ExtendedDescriptor, KeyMap), DescriptorError> {
+        self.descriptor
+            .clone()
+            .into_wallet_descriptor(secp, network)
+    }
+}
+
+/// Replace the placeholders of a template with their keys and multipath derivation steps
+fn expand_template(
+    template: &str,
+    keys: &[DescriptorPublicKey],
+) -> Result<String, WalletPolicyError> {
+    let mut expanded = String::new();
+    let mut used_keys = 0;
+    let mut rest = template;
+    while let Some(pos) = rest.find('@') {
+        expanded.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 || (digits > 1 && rest.starts_with('0')) {
+            return Err(WalletPolicyError::InvalidPlaceholder);
+        }
+        let index = rest[..digits]
+            .parse::<usize>()
+            .map_err(|_| WalletPolicyError::InvalidPlaceholder)?;
+        rest = &rest[digits..];
+
+        // keys must be referenced in order of first appearance
+        if index > used_keys || index >= keys.len() {
+            return Err(WalletPolicyError::KeyIndex(index));
+        }
+        if index == used_keys {
+            used_keys += 1;
+        }
+
+        let (m, n) = if let Some(after) = rest.strip_prefix("/**") {
+            rest = after;
+            (0, 1)
+        } else {
+            let (steps, after) = rest
+                .strip_prefix("/<")
+                .and_then(|r| r.split_once(">/*"))
+                .ok_or(WalletPolicyError::InvalidPlaceholder)?;
+            let (m, n) = steps
+                .split_once(';')
+                .ok_or(WalletPolicyError::InvalidPlaceholder)?;
+            let m = parse_step(m)?;
+            let n = parse_step(n)?;
+            if m == n {
+                return Err(WalletPolicyError::InvalidPlaceholder);
+            }
+            rest = after;
+            (m, n)
+        };
+        expanded.push_str(&format!("{}/<{};{}>/*", keys[index], m, n));
+    }
+    expanded.push_str(rest);
+
+    if used_keys < keys.len() {
+        return Err(WalletPolicyError::UnusedKey(used_keys));
+    }
+
+    Ok(expanded)
+}
+
+/// Parse an unhardened derivation step of a placeholder
+fn parse_step(step: &str) -> Result<u32, WalletPolicyError> {
+    match step.parse::<u32>() {
+        Ok(index) if index < (1 << 31) && step.bytes().all(|c| c.is_ascii_digit()) => Ok(index),
+        _ => Err(WalletPolicyError::InvalidPlaceholder),
+    }
+}
+
+/// The keys of a descriptor, in the order they appear in
+fn descriptor_keys(descriptor: &ExtendedDescriptor) -> Vec<DescriptorPublicKey> {
+    let mut keys = Vec::new();
+    descriptor.for_each_key(|key| {
+        keys.push(key.clone());
+        true
+    });
+    keys
+}
+
+/// Replaces the keys of a descriptor with their placeholders
+struct TemplateTranslator {
+    placeholders: BTreeMap<DescriptorPublicKey, String>,
+}
+
+impl Translator<DescriptorPublicKey, String, WalletPolicyError> for TemplateTranslator {
+    fn pk(&mut self, pk: &DescriptorPublicKey) -> Result<String, WalletPolicyError> {
+        self.placeholders
+            .get(pk)
+            .cloned()
+            .ok_or(WalletPolicyError::IncompatibleDescriptors)
+    }
+
+    fn sha256(
+        &mut self,
+        sha256: &bitcoin::hashes::sha256::Hash,
+    ) -> Result<String, WalletPolicyError> {
+        Ok(sha256.to_string())
+    }
+
+    fn hash256(
+        &mut self,
+        hash256: &miniscript::hash256::Hash,
+    ) -> Result<String, WalletPolicyError> {
+        Ok(hash256.to_string())
+    }
+
+    fn ripemd160(
+        &mut self,
+        ripemd160: &bitcoin::hashes::ripemd160::Hash,
+    ) -> Result<String, WalletPolicyError> {
+        Ok(ripemd160.to_string())
+    }
+
+    fn hash160(
+        &mut self,
+        hash160: &bitcoin::hashes::hash160::Hash,
+    ) -> Result<String, WalletPolicyError> {
+        Ok(hash160.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const XPUB_0: &str = "[c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe";
+    const XPUB_1: &str = "[73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M";
+
+    fn keys() -> Vec<DescriptorPublicKey> {
+        vec![XPUB_0.parse().unwrap(), XPUB_1.parse().unwrap()]
+    }
+
+    #[test]
+    fn test_wallet_policy_expansion() {
+        let policy = WalletPolicy::new("wsh(sortedmulti(2,@0/**,@1/<2;3>/*))", keys()).unwrap();
+        let expected = ExtendedDescriptor::from_str(&format!(
+            "wsh(sortedmulti(2,{}/<0;1>/*,{}/<2;3>/*))",
+            XPUB_0, XPUB_1
+        ))
+        .unwrap();
+        assert_eq!(policy.descriptor(), &expected);
+        assert_eq!(policy.template(), "wsh(sortedmulti(2,@0/**,@1/<2;3>/*))");
+        assert_eq!(policy.keys(), keys().as_slice());
+        let (descriptor, change_descriptor) = policy.into_descriptors().unwrap();
+        assert_eq!(
+            descriptor.to_string(),
+            ExtendedDescriptor::from_str(&format!(
+                "wsh(sortedmulti(2,{}/0/*,{}/2/*))",
+                XPUB_0, XPUB_1
+            ))
+            .unwrap()
+            .to_string()
+        );
+        assert_eq!(
+            change_descriptor.to_string(),
+            ExtendedDescriptor::from_str(&format!(
+                "wsh(sortedmulti(2,{}/1/*,{}/3/*))",
+                XPUB_0, XPUB_1
+            ))
+            .unwrap()
+            .to_string()
+        );
+
+        // the same key can be used with different derivation steps
+        let policy = WalletPolicy::new(
+            "wsh(or_d(pk(@0/**),and_v(v:pkh(@0/<2;3>/*),older(65535))))",
+            vec![keys()[0].clone()],
+        )
+        .unwrap();
+        assert!(policy.descriptor().is_multipath());
+    }
+
+    #[test]
+    fn test_wallet_policy_errors() {
+        let cases = [
+            ("wpkh(@0)", keys()[..1].to_vec()),
+            ("wpkh(@0/*)", keys()[..1].to_vec()),
+            ("wpkh(@0/<0;0>/*)", keys()[..1].to_vec()),
+            ("wpkh(@0/<0;1h>/*)", keys()[..1].to_vec()),
+            ("wpkh(@00/**)", keys()[..1].to_vec()),
+            ("wsh(multi(1,@1/**,@0/**))", keys()),
+            ("wsh(multi(1,@0/**,@2/**))", keys()),
+            ("wpkh(@0/**)", keys()),
+            (
+                "wsh(multi(1,@0/**,@1/**))",
+                vec![keys()[0].clone(), keys()[0].clone()],
+            ),
+            (
+                "wpkh(@0/**)",
+                vec![format!("{}/0", XPUB_0).parse().unwrap()],
+            ),
+            ("wsh(pk(@0/**)", keys()[..1].to_vec()),
+        ];
+        let expected = [
+            "InvalidPlaceholder",
+            "InvalidPlaceholder",
+            "InvalidPlaceholder",
+            "InvalidPlaceholder",
+            "InvalidPlaceholder",
+            "KeyIndex(1)",
+            "KeyIndex(2)",
+            "UnusedKey(1)",
+            "DuplicateKey(1)",
+            "InvalidKey(0)",
+            "Descriptor",
+        ];
+        for ((template, keys), expected) in cases.iter().zip(expected) {
+            let err = WalletPolicy::new(template, keys.clone()).unwrap_err();
+            assert!(
+                format!("{:?}", err).starts_with(expected),
+                "{}: {:?}",
+                template,
+                err
+            );
+        }
+    }
+}
@@ -10,7 +10,7 @@
 // licenses.
 
 use alloc::boxed::Box;
-use core::convert::AsRef;
+use alloc::vec::Vec;
 
 use bdk_chain::ConfirmationTime;
 use bitcoin::blockdata::transaction::{OutPoint, Sequence, TxOut};
@@ -22,26 +22,48 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub enum KeychainKind {
     /// External keychain, used for deriving recipient addresses.
-    External = 0,
+    External,
     /// Internal keychain, used for deriving change addresses.
-    Internal = 1,
+    Internal,
+    /// Additional keychain identified by an arbitrary number, e.g. to give each customer their
+    /// own deposit addresses.
+    ///
+    /// Custom keychains must be registered with [`Wallet::add_keychain`] before being used.
+    ///
+    /// [`Wallet::add_keychain`]: crate::Wallet::add_keychain
+    Custom(u32),
 }
 
 impl KeychainKind {
     /// Return [`KeychainKind`] as a byte
+    ///
+    /// Custom keychains are all encoded as `c`, use [`to_bytes`](Self::to_bytes) to tell them
+    /// apart.
+    #[deprecated(
+        since = "1.0.0-alpha.13",
+        note = "Please use `to_bytes` instead, which encodes the index of custom keychains"
+    )]
     pub fn as_byte(&self) -> u8 {
         match self {
             KeychainKind::External => b'e',
             KeychainKind::Internal => b'i',
+            KeychainKind::Custom(_) => b'c',
         }
     }
-}
 
-impl AsRef<[u8]> for KeychainKind {
-    fn as_ref(&self) -> &[u8] {
+    /// Return [`KeychainKind`] as bytes
+    ///
+    /// External and internal keychains are encoded as `e` and `i`, custom keychains as `c`
+    /// followed by their big endian index, so that every keychain has its own encoding.
+    pub fn to_bytes(&self) -> Vec<u8> {
         match self {
-            KeychainKind::External => b"e",
-            KeychainKind::Internal => b"i",
+            KeychainKind::External => b"e".to_vec(),
+            KeychainKind::Internal => b"i".to_vec(),
+            KeychainKind::Custom(index) => {
+                let mut bytes = b"c".to_vec();
+                bytes.extend_from_slice(&index.to_be_bytes());
+                bytes
+            }
         }
     }
 }
@@ -18,6 +18,11 @@
 //! [`TxBuilder`]. [`DefaultCoinSelectionAlgorithm`] aliases the coin selection algorithm that will
 //! be used if it is not explicitly set.
 //!
+//! [`PrivacyCoinSelection`] avoids linking the UTXOs of different keychains, sources and scripts.
+//!
+//! [`LowestWasteCoinSelection`] runs several algorithms and keeps the selection with the lowest
+//! waste, like Bitcoin Core.
+//!
 //! [`TxBuilder`]: super::tx_builder::TxBuilder
 //! [`coin_selection`]: super::tx_builder::TxBuilder::coin_selection
 //!
@@ -79,6 +84,7 @@
 //!             selected: all_utxos_selected,
 //!             fee_amount: additional_fees,
 //!             excess,
+//!             privacy_score: None,
 //!         })
 //!     }
 //! }
@@ -117,6 +123,12 @@ use core::convert::TryInto;
 use core::fmt::{self, Formatter};
 use rand::seq::SliceRandom;
 
+mod privacy;
+mod waste;
+
+pub use privacy::{PrivacyCoinSelection, PrivacyScore};
+pub use waste::LowestWasteCoinSelection;
+
 /// Default coin selection algorithm used by [`TxBuilder`](super::tx_builder::TxBuilder) if not
 /// overridden
 pub type DefaultCoinSelectionAlgorithm = BranchAndBoundCoinSelection;
@@ -188,6 +200,10 @@ pub struct CoinSelectionResult {
     pub fee_amount: u64,
     /// Remaining amount after deducing fees and outgoing outputs
     pub excess: Excess,
+    /// How much the selection links the UTXOs of the wallet, if reported by the algorithm
+    ///
+    /// It is reported by [`PrivacyCoinSelection`].
+    pub privacy_score: Option<PrivacyScore>,
 }
 
 impl CoinSelectionResult {
@@ -374,6 +390,7 @@ fn select_sorted_utxos(
         selected,
         fee_amount,
         excess,
+        privacy_score: None,
     })
 }
 
@@ -714,6 +731,7 @@ impl BranchAndBoundCoinSelection {
             selected,
             fee_amount,
             excess,
+            privacy_score: None,
         }
     }
 }
@@ -0,0 +1,510 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Privacy aware coin selection
+//!
+//! Spending UTXOs in the same transaction tells everyone that they belong to the same wallet.
+//! [`PrivacyCoinSelection`] groups the UTXOs in clusters of the same keychain and the same
+//! source, and tries to fund the transaction from as few clusters as possible. Within a cluster,
+//! the UTXOs of the same script are always spent together, so that no address is left
+//! half-spent and linked to a future transaction.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use bitcoin::{FeeRate, OutPoint, Script, ScriptBuf};
+
+use super::{
+    decide_change, BranchAndBoundCoinSelection, CoinSelectionAlgorithm, CoinSelectionResult, Error,
+    OutputGroup,
+};
+use crate::chain::collections::{BTreeMap, BTreeSet};
+use crate::types::KeychainKind;
+use crate::{Utxo, WeightedUtxo};
+
+/// How much a coin selection links the clusters of UTXOs of the wallet
+///
+/// A selection that spends a single cluster entirely, or the whole UTXOs of its scripts, has a
+/// score of zero links.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrivacyScore {
+    /// Number of scripts of the selected UTXOs whose other UTXOs are left unspent
+    pub partially_spent_scripts: usize,
+    /// Number of keychains of the selected local UTXOs
+    pub keychains: usize,
+    /// Number of sources of the selected UTXOs, the UTXOs without a source counting as one
+    pub sources: usize,
+}
+
+impl PrivacyScore {
+    /// Number of links created by the selection, the lower the better
+    ///
+    /// Each partially spent script, and each keychain and source beyond the first one count as a
+    /// link.
+    pub fn links(&self) -> usize {
+        self.partially_spent_scripts
+            + self.keychains.saturating_sub(1)
+            + self.sources.saturating_sub(1)
+    }
+
+    /// Whether the selection doesn't link any cluster of UTXOs
+    pub fn is_private(&self) -> bool {
+        self.links() == 0
+    }
+}
+
+/// Coin selection avoiding to link the UTXOs of different clusters
+///
+/// The UTXOs are clustered by keychain and by source. Sources are arbitrary names given to
+/// outpoints, e.g. to tell apart coins withdrawn from an exchange with KYC from the other ones.
+/// The UTXOs of the same script are selected together, including the ones sharing a script with
+/// the required UTXOs, even if their effective value is negative.
+///
+/// The optional UTXOs are first selected from the clusters of the required UTXOs. If more are
+/// needed, a cluster that can fund the rest of the transaction by itself is preferred, otherwise
+/// whole clusters are merged in descending order of value until the target is reached.
+///
+/// The [`PrivacyScore`] of the selection is reported in the
+/// [`privacy_score`](CoinSelectionResult::privacy_score) of its result, which
+/// [`TxBuilder::finish_with_coin_selection`] returns along with the PSBT.
+///
+/// [`TxBuilder::finish_with_coin_selection`]: crate::TxBuilder::finish_with_coin_selection
+///
+/// ## Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use bitcoin::*;
+/// # use bdk_wallet::*;
+/// use bdk_wallet::wallet::coin_selection::PrivacyCoinSelection;
+/// use bdk_wallet::wallet::labels::LabelRef;
+///
+/// # let mut wallet = doctest_wallet!();
+/// // use the output labels of the wallet as sources
+/// let sources = wallet
+///     .labels()
+///     .filter_map(|(label_ref, label)| match label_ref {
+///         LabelRef::Output(outpoint) => Some((*outpoint, label.label.clone())),
+///         _ => None,
+///     })
+///     .collect::<Vec<_>>();
+///
+/// let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")?
+///     .require_network(Network::Testnet)?;
+/// let psbt = {
+///     let mut builder = wallet
+///         .build_tx()
+///         .coin_selection(PrivacyCoinSelection::new().sources(sources));
+///     builder.add_recipient(to_address.script_pubkey(), Amount::from_sat(50_000));
+///     builder.finish()?
+/// };
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PrivacyCoinSelection {
+    sources: BTreeMap<OutPoint, String>,
+}
+
+/// The keychain and the source shared by the UTXOs of a cluster
+type ClusterKey<'a> = (Option<KeychainKind>, Option<&'a str>);
+
+/// The UTXOs of a cluster, grouped by script
+#[derive(Debug)]
+struct Cluster<'a> {
+    key: ClusterKey<'a>,
+    // sorted by descending effective value
+    scripts: Vec<ScriptGroup>,
+}
+
+/// The UTXOs of a cluster sharing the same script
+#[derive(Debug, Default)]
+struct ScriptGroup {
+    utxos: Vec<OutputGroup>,
+    effective_value: i64,
+}
+
+impl Cluster<'_> {
+    /// The number of script groups needed to reach `missing`, with their value and fee
+    fn needed(&self, missing: i64) -> (usize, i64, u64) {
+        let mut value = 0;
+        let mut fee = 0;
+        for (index, group) in self.scripts.iter().enumerate() {
+            if value >= missing {
+                return (index, value, fee);
+            }
+            value += group.effective_value;
+            fee += group.utxos.iter().map(|u| u.fee).sum::<u64>();
+        }
+        (self.scripts.len(), value, fee)
+    }
+}
+
+impl PrivacyCoinSelection {
+    /// Create a new instance without any source
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the source of the UTXO at `outpoint`
+    pub fn source(mut self, outpoint: OutPoint, source: impl Into<String>) -> Self {
+        self.sources.insert(outpoint, source.into());
+        self
+    }
+
+    /// Set the sources of several UTXOs
+    pub fn sources<S: Into<String>>(
+        mut self,
+        sources: impl IntoIterator<Item = (OutPoint, S)>,
+    ) -> Self {
+        self.sources.extend(
+            sources
+                .into_iter()
+                .map(|(outpoint, source)| (outpoint, source.into())),
+        );
+        self
+    }
+
+    fn cluster_key(&self, utxo: &Utxo) -> ClusterKey<'_> {
+        let keychain = match utxo {
+            Utxo::Local(local) => Some(local.keychain),
+            Utxo::Foreign { .. } => None,
+        };
+        let source = self.sources.get(&utxo.outpoint()).map(String::as_str);
+        (keychain, source)
+    }
+}
+
+impl CoinSelectionAlgorithm for PrivacyCoinSelection {
+    fn coin_select(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: u64,
+        drain_script: &Script,
+    ) -> Result<CoinSelectionResult, Error> {
+        let mut script_counts = BTreeMap::<ScriptBuf, usize>::new();
+        for weighted_utxo in required_utxos.iter().chain(&optional_utxos) {
+            *script_counts
+                .entry(weighted_utxo.utxo.txout().script_pubkey.clone())
+                .or_default() += 1;
+        }
+
+        let mut groups = BTreeMap::<ClusterKey, BTreeMap<ScriptBuf, ScriptGroup>>::new();
+        for weighted_utxo in optional_utxos {
+            let key = self.cluster_key(&weighted_utxo.utxo);
+            let script = weighted_utxo.utxo.txout().script_pubkey.clone();
+            let group = groups.entry(key).or_default().entry(script).or_default();
+            let utxo = OutputGroup::new(weighted_utxo, fee_rate);
+            group.effective_value += utxo.effective_value;
+            group.utxos.push(utxo);
+        }
+
+        let required = required_utxos
+            .into_iter()
+            .map(|u| OutputGroup::new(u, fee_rate))
+            .collect::<Vec<_>>();
+        let mut keychains = BTreeSet::new();
+        let mut sources = BTreeSet::new();
+        let mut selected = Vec::new();
+        let mut selected_value = required.iter().map(|u| u.effective_value).sum::<i64>();
+
+        // don't leave the scripts of the required UTXOs half-spent
+        for utxo in &required {
+            let (keychain, source) = self.cluster_key(&utxo.weighted_utxo.utxo);
+            keychains.extend(keychain);
+            sources.insert(source);
+            let script = &utxo.weighted_utxo.utxo.txout().script_pubkey;
+            if let Some(group) = groups
+                .get_mut(&(keychain, source))
+                .and_then(|scripts| scripts.remove(script))
+            {
+                selected_value += group.effective_value;
+                selected.extend(group.utxos);
+            }
+        }
+
+        let mut clusters = groups
+            .into_iter()
+            .map(|(key, scripts)| {
+                let mut scripts = scripts
+                    .into_values()
+                    .filter(|group| group.effective_value > 0)
+                    .collect::<Vec<_>>();
+                scripts.sort_by_key(|group| -group.effective_value);
+                Cluster { key, scripts }
+            })
+            .filter(|cluster| !cluster.scripts.is_empty())
+            .collect::<Vec<_>>();
+
+        let target_amount = target_amount as i64;
+        while selected_value < target_amount {
+            let missing = target_amount - selected_value;
+            // prefer the clusters that can fund the transaction by themselves, then the ones
+            // adding the fewest links, then the cheapest or the largest ones
+            let best =
+                clusters
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, cluster)| {
+                        let (keychain, source) = cluster.key;
+                        let new_links =
+                            usize::from(keychain.map_or(false, |k| {
+                                !keychains.is_empty() && !keychains.contains(&k)
+                            })) + usize::from(!sources.is_empty() && !sources.contains(&source));
+                        let (_, value, fee) = cluster.needed(missing);
+                        if value >= missing {
+                            (false, new_links, fee as i64)
+                        } else {
+                            (true, new_links, -value)
+                        }
+                    })
+                    .map(|(index, _)| index);
+
+            let cluster = match best {
+                Some(index) => clusters.swap_remove(index),
+                None => {
+                    let (fees, available) =
+                        required
+                            .iter()
+                            .chain(&selected)
+                            .fold((0, 0), |(fees, value), utxo| {
+                                (
+                                    fees + utxo.fee,
+                                    value + utxo.weighted_utxo.utxo.txout().value.to_sat(),
+                                )
+                            });
+                    return Err(Error::InsufficientFunds {
+                        needed: target_amount as u64 + fees,
+                        available,
+                    });
+                }
+            };
+            let (keychain, source) = cluster.key;
+            keychains.extend(keychain);
+            sources.insert(source);
+            let (needed, _, _) = cluster.needed(missing);
+            for group in cluster.scripts.into_iter().take(needed) {
+                selected_value += group.effective_value;
+                selected.extend(group.utxos);
+            }
+        }
+
+        let mut selected_counts = BTreeMap::<&Script, usize>::new();
+        for utxo in required.iter().chain(&selected) {
+            *selected_counts
+                .entry(&utxo.weighted_utxo.utxo.txout().script_pubkey)
+                .or_default() += 1;
+        }
+        let score = PrivacyScore {
+            partially_spent_scripts: selected_counts
+                .iter()
+                .filter(|(script, count)| script_counts.get(**script) != Some(*count))
+                .count(),
+            keychains: keychains.len(),
+            sources: sources.len(),
+        };
+
+        let remaining_amount = (selected_value - target_amount) as u64;
+        let excess = decide_change(remaining_amount, fee_rate, drain_script);
+
+        Ok(CoinSelectionResult {
+            privacy_score: Some(score),
+            ..BranchAndBoundCoinSelection::calculate_cs_result(selected, required, excess)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+    use bdk_chain::ConfirmationTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, TxOut, Txid, WPubkeyHash};
+
+    use super::*;
+    use crate::types::LocalOutput;
+    use crate::wallet::coin_selection::Excess;
+
+    // signature len (1WU) + signature and sighash (72WU)
+    // + pubkey len (1WU) + pubkey (33WU)
+    const P2WPKH_SATISFACTION_SIZE: usize = 1 + 72 + 1 + 33;
+
+    // at 10 sat/vb, an input costs 680 sats
+    const FEE_RATE: FeeRate = FeeRate::from_sat_per_kwu(2500);
+
+    fn script(index: u8) -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([index; 20]))
+    }
+
+    fn utxo(vout: u32, value: u64, script_index: u8, keychain: KeychainKind) -> WeightedUtxo {
+        WeightedUtxo {
+            satisfaction_weight: P2WPKH_SATISFACTION_SIZE,
+            utxo: Utxo::Local(LocalOutput {
+                outpoint: OutPoint::new(Txid::all_zeros(), vout),
+                txout: TxOut {
+                    value: Amount::from_sat(value),
+                    script_pubkey: script(script_index),
+                },
+                keychain,
+                is_spent: false,
+                derivation_index: script_index as u32,
+                confirmation_time: ConfirmationTime::Unconfirmed { last_seen: 0 },
+            }),
+        }
+    }
+
+    fn outpoint(vout: u32) -> OutPoint {
+        OutPoint::new(Txid::all_zeros(), vout)
+    }
+
+    fn selected_vouts(result: &CoinSelectionResult) -> Vec<u32> {
+        let mut vouts = result
+            .selected
+            .iter()
+            .map(|utxo| utxo.outpoint().vout)
+            .collect::<Vec<_>>();
+        vouts.sort();
+        vouts
+    }
+
+    #[test]
+    fn test_privacy_coin_selection_spends_whole_scripts() {
+        let optional = vec![
+            utxo(0, 40_000, 0, KeychainKind::External),
+            utxo(1, 10_000, 0, KeychainKind::External),
+            utxo(2, 45_000, 1, KeychainKind::External),
+        ];
+
+        let result = PrivacyCoinSelection::new()
+            .coin_select(vec![], optional, FEE_RATE, 48_000, &script(9))
+            .unwrap();
+        let score = result.privacy_score.unwrap();
+
+        assert_eq!(selected_vouts(&result), vec![0, 1]);
+        assert_eq!(result.fee_amount, 2 * 680);
+        assert_eq!(
+            score,
+            PrivacyScore {
+                partially_spent_scripts: 0,
+                keychains: 1,
+                sources: 1,
+            }
+        );
+        assert!(score.is_private());
+    }
+
+    #[test]
+    fn test_privacy_coin_selection_avoids_mixing_keychains() {
+        let optional = vec![
+            utxo(0, 50_000, 0, KeychainKind::External),
+            utxo(1, 30_000, 1, KeychainKind::Internal),
+            utxo(2, 30_000, 2, KeychainKind::Internal),
+        ];
+
+        // the external UTXO is too small, but the internal ones are enough together
+        let result = PrivacyCoinSelection::new()
+            .coin_select(vec![], optional, FEE_RATE, 55_000, &script(9))
+            .unwrap();
+        let score = result.privacy_score.unwrap();
+
+        assert_eq!(selected_vouts(&result), vec![1, 2]);
+        assert_eq!(score.keychains, 1);
+        assert!(score.is_private());
+    }
+
+    #[test]
+    fn test_privacy_coin_selection_avoids_mixing_sources() {
+        let required = vec![utxo(0, 20_000, 0, KeychainKind::External)];
+        let optional = vec![
+            utxo(1, 100_000, 1, KeychainKind::External),
+            utxo(2, 40_000, 2, KeychainKind::External),
+        ];
+
+        let coin_selection = PrivacyCoinSelection::new()
+            .sources([(outpoint(0), "kyc"), (outpoint(2), "kyc")])
+            .source(outpoint(1), "p2p");
+        let result = coin_selection
+            .coin_select(required, optional, FEE_RATE, 50_000, &script(9))
+            .unwrap();
+        let score = result.privacy_score.unwrap();
+
+        assert_eq!(selected_vouts(&result), vec![0, 2]);
+        assert_eq!(score.sources, 1);
+        assert!(score.is_private());
+    }
+
+    #[test]
+    fn test_privacy_coin_selection_merges_clusters() {
+        let optional = vec![
+            utxo(0, 30_000, 0, KeychainKind::External),
+            utxo(1, 40_000, 1, KeychainKind::External),
+            utxo(2, 5_000, 2, KeychainKind::Internal),
+        ];
+
+        let coin_selection = PrivacyCoinSelection::new().source(outpoint(1), "kyc");
+        let result = coin_selection
+            .coin_select(vec![], optional, FEE_RATE, 60_000, &script(9))
+            .unwrap();
+        let score = result.privacy_score.unwrap();
+
+        // the two largest clusters are merged, leaving out the internal one
+        assert_eq!(selected_vouts(&result), vec![0, 1]);
+        assert_eq!(
+            score,
+            PrivacyScore {
+                partially_spent_scripts: 0,
+                keychains: 1,
+                sources: 2,
+            }
+        );
+        assert_eq!(score.links(), 1);
+    }
+
+    #[test]
+    fn test_privacy_coin_selection_required_partially_spent() {
+        let required = vec![utxo(0, 60_000, 0, KeychainKind::External)];
+        let optional = vec![
+            utxo(1, 10_000, 0, KeychainKind::External),
+            utxo(2, 20_000, 0, KeychainKind::External),
+        ];
+
+        // the unlabelled UTXO of the required script is spent with it, unlike the labelled one
+        let coin_selection = PrivacyCoinSelection::new().source(outpoint(2), "kyc");
+        let result = coin_selection
+            .coin_select(required, optional, FEE_RATE, 10_000, &script(9))
+            .unwrap();
+        let score = result.privacy_score.unwrap();
+
+        assert_eq!(selected_vouts(&result), vec![0, 1]);
+        assert_matches!(result.excess, Excess::Change { .. });
+        assert_eq!(score.partially_spent_scripts, 1);
+        assert!(!score.is_private());
+    }
+
+    #[test]
+    fn test_privacy_coin_selection_insufficient_funds() {
+        let optional = vec![
+            utxo(0, 30_000, 0, KeychainKind::External),
+            utxo(1, 40_000, 1, KeychainKind::Internal),
+        ];
+
+        let result =
+            PrivacyCoinSelection::new().coin_select(vec![], optional, FEE_RATE, 80_000, &script(9));
+
+        assert_matches!(
+            result,
+            Err(Error::InsufficientFunds {
+                needed: 81_360,
+                available: 70_000,
+            })
+        );
+    }
+}
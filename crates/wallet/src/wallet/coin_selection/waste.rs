@@ -0,0 +1,672 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Lowest waste coin selection
+//!
+//! Like the wallet of Bitcoin Core, [`LowestWasteCoinSelection`] runs several coin selection
+//! algorithms and keeps the selection with the lowest waste:
+//!
+//! - branch and bound, looking for a selection without change,
+//! - a branch and bound search minimizing the weight of the inputs when the feerate is more than
+//!   three times the long term feerate, similar to Bitcoin Core's CoinGrinder,
+//! - the knapsack solver of Bitcoin Core,
+//! - single random draw.
+
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::cmp::Reverse;
+use core::fmt;
+
+use bitcoin::consensus::encode::serialize;
+use bitcoin::{FeeRate, Script, TxIn, Weight};
+use rand::rngs::ThreadRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
+
+use super::{
+    decide_change, BranchAndBoundCoinSelection, CoinSelectionAlgorithm, CoinSelectionResult, Error,
+    Excess, OutputGroup, BNB_TOTAL_TRIES,
+};
+use crate::chain::collections::HashSet;
+use crate::WeightedUtxo;
+
+/// Number of iterations of the knapsack solver, as in Bitcoin Core
+const KNAPSACK_ITERATIONS: usize = 1000;
+
+/// Coin selection running several algorithms and keeping the result with the lowest waste
+///
+/// The waste of a selection is the difference between the fee paid for its inputs at the current
+/// feerate and at the long term feerate, plus the cost of creating and later spending the change
+/// output, or the excess given to the miners if there's no change. Among selections of the same
+/// waste, the one with the most inputs is preferred.
+///
+/// The knapsack solver and single random draw use the random number generator of the instance,
+/// [`rand::thread_rng`] by default. Set a seeded generator with
+/// [`LowestWasteCoinSelection::rng`] to make the selection deterministic.
+///
+/// ## Example
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use bitcoin::*;
+/// # use bdk_wallet::*;
+/// use bdk_wallet::wallet::coin_selection::LowestWasteCoinSelection;
+/// use rand::rngs::StdRng;
+/// use rand::SeedableRng;
+///
+/// # let mut wallet = doctest_wallet!();
+/// let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")?
+///     .require_network(Network::Testnet)?;
+/// let coin_selection = LowestWasteCoinSelection::new(FeeRate::from_sat_per_vb_unchecked(10))
+///     .rng(StdRng::seed_from_u64(42));
+/// let psbt = {
+///     let mut builder = wallet.build_tx().coin_selection(coin_selection);
+///     builder.add_recipient(to_address.script_pubkey(), Amount::from_sat(50_000));
+///     builder.finish()?
+/// };
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct LowestWasteCoinSelection<R = ThreadRng> {
+    long_term_feerate: FeeRate,
+    drain_satisfaction_weight: usize,
+    rng: RefCell<R>,
+}
+
+impl LowestWasteCoinSelection {
+    /// Create a new instance comparing the fees to `long_term_feerate`
+    pub fn new(long_term_feerate: FeeRate) -> Self {
+        Self {
+            long_term_feerate,
+            // signature len (1WU) + signature and sighash (72WU)
+            // + pubkey len (1WU) + pubkey (33WU)
+            drain_satisfaction_weight: 1 + 72 + 1 + 33,
+            rng: RefCell::new(rand::thread_rng()),
+        }
+    }
+}
+
+impl Default for LowestWasteCoinSelection {
+    /// Create a new instance with a long term feerate of 10 sat/vB, the default consolidation
+    /// feerate of Bitcoin Core
+    fn default() -> Self {
+        Self::new(FeeRate::from_sat_per_vb_unchecked(10))
+    }
+}
+
+impl<R> LowestWasteCoinSelection<R> {
+    /// Use `rng` as the random number generator of the randomized algorithms
+    pub fn rng<S: RngCore>(self, rng: S) -> LowestWasteCoinSelection<S> {
+        LowestWasteCoinSelection {
+            long_term_feerate: self.long_term_feerate,
+            drain_satisfaction_weight: self.drain_satisfaction_weight,
+            rng: RefCell::new(rng),
+        }
+    }
+
+    /// Set the satisfaction weight of the change output, used to estimate the cost of spending
+    /// it in the future
+    ///
+    /// Defaults to the satisfaction weight of a P2WPKH output.
+    pub fn drain_satisfaction_weight(mut self, weight: usize) -> Self {
+        self.drain_satisfaction_weight = weight;
+        self
+    }
+}
+
+impl<R: RngCore + fmt::Debug> CoinSelectionAlgorithm for LowestWasteCoinSelection<R> {
+    fn coin_select(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: u64,
+        drain_script: &Script,
+    ) -> Result<CoinSelectionResult, Error> {
+        let required_utxos = required_utxos
+            .into_iter()
+            .map(|u| OutputGroup::new(u, fee_rate))
+            .collect::<Vec<_>>();
+        let optional_utxos = optional_utxos
+            .into_iter()
+            .map(|u| OutputGroup::new(u, fee_rate))
+            .filter(|u| u.effective_value.is_positive())
+            .collect::<Vec<_>>();
+
+        let curr_value = required_utxos
+            .iter()
+            .map(|u| u.effective_value)
+            .sum::<i64>();
+        let curr_available_value = optional_utxos
+            .iter()
+            .map(|u| u.effective_value)
+            .sum::<i64>();
+        let target_amount = target_amount as i64;
+
+        if curr_value + curr_available_value < target_amount {
+            let (utxo_fees, utxo_value) =
+                required_utxos
+                    .iter()
+                    .chain(&optional_utxos)
+                    .fold((0, 0), |(fees, value), utxo| {
+                        (
+                            fees + utxo.fee,
+                            value + utxo.weighted_utxo.utxo.txout().value.to_sat(),
+                        )
+                    });
+            return Err(Error::InsufficientFunds {
+                needed: target_amount as u64 + utxo_fees,
+                available: utxo_value,
+            });
+        }
+
+        if curr_value >= target_amount {
+            let remaining_amount = (curr_value - target_amount) as u64;
+            let excess = decide_change(remaining_amount, fee_rate, drain_script);
+            return Ok(BranchAndBoundCoinSelection::calculate_cs_result(
+                vec![],
+                required_utxos,
+                excess,
+            ));
+        }
+
+        // the value still needed from the optional UTXOs
+        let missing = target_amount - curr_value;
+        let drain_output_len = serialize(drain_script).len() + 8;
+        let change_fee = (fee_rate
+            * Weight::from_vb(drain_output_len as u64).expect("overflow occurred"))
+        .to_sat();
+        let change_spend_fee =
+            (self.long_term_feerate * input_weight(self.drain_satisfaction_weight)).to_sat();
+        let min_change = drain_script.dust_value().to_sat();
+
+        let mut selections = Vec::new();
+        if let Ok(result) = BranchAndBoundCoinSelection::default().bnb(
+            vec![],
+            optional_utxos.clone(),
+            0,
+            curr_available_value,
+            missing,
+            change_fee + change_spend_fee,
+            drain_script,
+            fee_rate,
+        ) {
+            let outpoints = result
+                .selected
+                .iter()
+                .map(|utxo| utxo.outpoint())
+                .collect::<HashSet<_>>();
+            selections.push(
+                optional_utxos
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, u)| outpoints.contains(&u.weighted_utxo.utxo.outpoint()))
+                    .map(|(index, _)| index)
+                    .collect(),
+            );
+        }
+        // with a change output, the selection must also pay for it
+        let change_target = missing + (change_fee + min_change) as i64;
+        if fee_rate.to_sat_per_kwu() > 3 * self.long_term_feerate.to_sat_per_kwu() {
+            selections.extend(minimize_weight(&optional_utxos, change_target));
+        }
+        {
+            let mut rng = self.rng.borrow_mut();
+            selections.extend(knapsack(
+                &optional_utxos,
+                missing,
+                (change_fee + min_change) as i64,
+                &mut *rng,
+            ));
+            selections.extend(single_random_draw(
+                &optional_utxos,
+                change_target,
+                &mut *rng,
+            ));
+        }
+
+        let waste = |selection: &Vec<usize>| {
+            let inputs_waste = selection
+                .iter()
+                .map(|&index| {
+                    let utxo = &optional_utxos[index];
+                    let long_term_fee = (self.long_term_feerate
+                        * input_weight(utxo.weighted_utxo.satisfaction_weight))
+                    .to_sat();
+                    utxo.fee as i64 - long_term_fee as i64
+                })
+                .sum::<i64>();
+            let remaining_amount = selected_value(&optional_utxos, selection) - missing;
+            match decide_change(remaining_amount as u64, fee_rate, drain_script) {
+                Excess::NoChange {
+                    remaining_amount, ..
+                } => inputs_waste + remaining_amount as i64,
+                Excess::Change { fee, .. } => inputs_waste + (fee + change_spend_fee) as i64,
+            }
+        };
+        let best = selections
+            .into_iter()
+            .min_by_key(|selection| (waste(selection), Reverse(selection.len())))
+            // the knapsack solver always finds a selection when there are enough funds
+            .unwrap_or_else(|| (0..optional_utxos.len()).collect());
+
+        let remaining_amount = (selected_value(&optional_utxos, &best) - missing) as u64;
+        let excess = decide_change(remaining_amount, fee_rate, drain_script);
+        let selected_utxos = optional_utxos
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| best.contains(index))
+            .map(|(_, utxo)| utxo)
+            .collect();
+
+        Ok(BranchAndBoundCoinSelection::calculate_cs_result(
+            selected_utxos,
+            required_utxos,
+            excess,
+        ))
+    }
+}
+
+/// Weight of an input spent with `satisfaction_weight`, as counted by the wallet
+fn input_weight(satisfaction_weight: usize) -> Weight {
+    Weight::from_wu(TxIn::default().segwit_weight().to_wu() + satisfaction_weight as u64)
+}
+
+fn selected_value(utxos: &[OutputGroup], selection: &[usize]) -> i64 {
+    selection
+        .iter()
+        .map(|&index| utxos[index].effective_value)
+        .sum()
+}
+
+/// Branch and bound search of the selection of lowest weight reaching `target`, preferring the
+/// lowest value among selections of the same weight
+fn minimize_weight(utxos: &[OutputGroup], target: i64) -> Option<Vec<usize>> {
+    let mut order = (0..utxos.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&index| Reverse(utxos[index].effective_value));
+    let weights = order
+        .iter()
+        .map(|&index| input_weight(utxos[index].weighted_utxo.satisfaction_weight).to_wu())
+        .collect::<Vec<_>>();
+    let values = order
+        .iter()
+        .map(|&index| utxos[index].effective_value)
+        .collect::<Vec<_>>();
+
+    // current_selection[i] is true if we are using the i-th largest UTXO
+    let mut current_selection: Vec<bool> = Vec::with_capacity(order.len());
+    let mut curr_value = 0;
+    let mut curr_weight = 0;
+    let mut curr_available_value = values.iter().sum::<i64>();
+    let mut best: Option<(Vec<bool>, u64, i64)> = None;
+
+    for _ in 0..BNB_TOTAL_TRIES {
+        let beaten =
+            |weight: u64| matches!(&best, Some((_, best_weight, _)) if weight >= *best_weight);
+        let mut backtrack = false;
+        // Cannot reach the target anymore, or adding UTXOs can only make the selection heavier
+        // than the best one
+        if curr_value + curr_available_value < target
+            || (curr_value < target && beaten(curr_weight))
+        {
+            backtrack = true;
+        } else if curr_value >= target {
+            backtrack = true;
+            let is_better = match &best {
+                Some((_, best_weight, best_value)) => {
+                    (curr_weight, curr_value) < (*best_weight, *best_value)
+                }
+                None => true,
+            };
+            if is_better {
+                best = Some((current_selection.clone(), curr_weight, curr_value));
+            }
+        }
+
+        if backtrack {
+            while let Some(false) = current_selection.last() {
+                current_selection.pop();
+                curr_available_value += values[current_selection.len()];
+            }
+            match current_selection.last_mut() {
+                // Output was included on previous iterations, try excluding now
+                Some(included) => *included = false,
+                None => break,
+            }
+            let index = current_selection.len() - 1;
+            curr_value -= values[index];
+            curr_weight -= weights[index];
+        } else {
+            let index = current_selection.len();
+            curr_available_value -= values[index];
+            current_selection.push(true);
+            curr_value += values[index];
+            curr_weight += weights[index];
+        }
+    }
+
+    best.map(|(selection, _, _)| {
+        order
+            .into_iter()
+            .zip(selection)
+            .filter(|(_, included)| *included)
+            .map(|(index, _)| index)
+            .collect()
+    })
+}
+
+/// The knapsack solver of Bitcoin Core
+///
+/// Looks for a subset of the UTXOs smaller than `target + change_target` reaching `target`
+/// exactly, or otherwise `target + change_target`, and falls back on the smallest UTXO larger
+/// than that.
+fn knapsack<R: RngCore>(
+    utxos: &[OutputGroup],
+    target: i64,
+    change_target: i64,
+    rng: &mut R,
+) -> Option<Vec<usize>> {
+    let mut indexes = (0..utxos.len()).collect::<Vec<_>>();
+    indexes.shuffle(rng);
+
+    let mut lower = Vec::new();
+    let mut total_lower = 0;
+    let mut lowest_larger: Option<usize> = None;
+    for index in indexes {
+        let value = utxos[index].effective_value;
+        if value == target {
+            return Some(vec![index]);
+        } else if value < target + change_target {
+            lower.push(index);
+            total_lower += value;
+        } else if lowest_larger.map_or(true, |larger| value < utxos[larger].effective_value) {
+            lowest_larger = Some(index);
+        }
+    }
+
+    if total_lower == target {
+        return Some(lower);
+    }
+    if total_lower < target {
+        return lowest_larger.map(|index| vec![index]);
+    }
+
+    lower.sort_by_key(|&index| Reverse(utxos[index].effective_value));
+    let values = lower
+        .iter()
+        .map(|&index| utxos[index].effective_value)
+        .collect::<Vec<_>>();
+    let (mut best, mut best_value) = approximate_best_subset(&values, total_lower, target, rng);
+    if best_value != target && total_lower >= target + change_target {
+        (best, best_value) =
+            approximate_best_subset(&values, total_lower, target + change_target, rng);
+    }
+
+    // the smallest larger UTXO is better if the subset doesn't leave enough change, or if it's
+    // smaller than the subset
+    if let Some(larger) = lowest_larger {
+        if (best_value != target && best_value < target + change_target)
+            || utxos[larger].effective_value <= best_value
+        {
+            return Some(vec![larger]);
+        }
+    }
+
+    Some(
+        lower
+            .into_iter()
+            .zip(best)
+            .filter(|(_, included)| *included)
+            .map(|(index, _)| index)
+            .collect(),
+    )
+}
+
+/// Stochastic approximation of the smallest subset of `values` reaching `target`
+fn approximate_best_subset<R: RngCore>(
+    values: &[i64],
+    total_value: i64,
+    target: i64,
+    rng: &mut R,
+) -> (Vec<bool>, i64) {
+    let mut best = vec![true; values.len()];
+    let mut best_value = total_value;
+
+    for _ in 0..KNAPSACK_ITERATIONS {
+        if best_value == target {
+            break;
+        }
+        let mut included = vec![false; values.len()];
+        let mut total = 0;
+        let mut reached_target = false;
+        for pass in 0..2 {
+            if reached_target {
+                break;
+            }
+            for (index, value) in values.iter().enumerate() {
+                // randomly include UTXOs on the first pass, and all the remaining ones on the
+                // second pass
+                let include = if pass == 0 {
+                    rng.gen_bool(0.5)
+                } else {
+                    !included[index]
+                };
+                if include {
+                    total += value;
+                    included[index] = true;
+                    if total >= target {
+                        reached_target = true;
+                        if total < best_value {
+                            best_value = total;
+                            best.clone_from(&included);
+                        }
+                        total -= value;
+                        included[index] = false;
+                    }
+                }
+            }
+        }
+    }
+
+    (best, best_value)
+}
+
+/// Select UTXOs in a random order until `target` is reached
+fn single_random_draw<R: RngCore>(
+    utxos: &[OutputGroup],
+    target: i64,
+    rng: &mut R,
+) -> Option<Vec<usize>> {
+    let mut indexes = (0..utxos.len()).collect::<Vec<_>>();
+    indexes.shuffle(rng);
+
+    let mut value = 0;
+    let selection = indexes
+        .into_iter()
+        .take_while(|&index| {
+            let reached = value >= target;
+            value += utxos[index].effective_value;
+            !reached
+        })
+        .collect::<Vec<_>>();
+
+    Some(selection).filter(|selection| selected_value(utxos, selection) >= target)
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+    use bdk_chain::ConfirmationTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, TxOut, Txid, WPubkeyHash};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::types::*;
+
+    // signature len (1WU) + signature and sighash (72WU)
+    // + pubkey len (1WU) + pubkey (33WU)
+    const P2WPKH_SATISFACTION_SIZE: usize = 1 + 72 + 1 + 33;
+
+    fn utxos(values: &[u64]) -> Vec<WeightedUtxo> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| WeightedUtxo {
+                satisfaction_weight: P2WPKH_SATISFACTION_SIZE,
+                utxo: Utxo::Local(LocalOutput {
+                    outpoint: OutPoint::new(Txid::all_zeros(), index as u32),
+                    txout: TxOut {
+                        value: Amount::from_sat(*value),
+                        script_pubkey: ScriptBuf::new(),
+                    },
+                    keychain: KeychainKind::External,
+                    is_spent: false,
+                    derivation_index: 42,
+                    confirmation_time: ConfirmationTime::Unconfirmed { last_seen: 0 },
+                }),
+            })
+            .collect()
+    }
+
+    fn drain_script() -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros())
+    }
+
+    fn coin_selection(seed: u64) -> LowestWasteCoinSelection<StdRng> {
+        LowestWasteCoinSelection::new(FeeRate::from_sat_per_vb_unchecked(10))
+            .rng(StdRng::seed_from_u64(seed))
+    }
+
+    #[test]
+    fn test_lowest_waste_coin_selection_exact_match() {
+        // at 10 sat/vb, an input costs 680 sats
+        let result = coin_selection(0)
+            .coin_select(
+                vec![],
+                utxos(&[100_000, 50_000, 30_000, 20_000]),
+                FeeRate::from_sat_per_vb_unchecked(10),
+                80_000 - 2 * 680,
+                &drain_script(),
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 2);
+        assert_eq!(result.selected_amount(), 80_000);
+        assert_matches!(
+            result.excess,
+            Excess::NoChange {
+                remaining_amount: 0,
+                ..
+            }
+        );
+    }
+
+    #[test]
+    fn test_lowest_waste_coin_selection_high_feerate() {
+        // inputs are expensive, spend as few of them as possible
+        let result = coin_selection(0)
+            .coin_select(
+                vec![],
+                utxos(&[60_000, 60_000, 200_000, 60_000, 60_000]),
+                FeeRate::from_sat_per_vb_unchecked(100),
+                150_000,
+                &drain_script(),
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 1);
+        assert_eq!(result.selected_amount(), 200_000);
+        assert_matches!(result.excess, Excess::Change { .. });
+    }
+
+    #[test]
+    fn test_lowest_waste_coin_selection_low_feerate() {
+        // inputs are cheaper than in the long term, consolidate them
+        let result = coin_selection(0)
+            .coin_select(
+                vec![],
+                utxos(&[60_000, 60_000, 200_000, 60_000, 60_000]),
+                FeeRate::from_sat_per_vb_unchecked(1),
+                150_000,
+                &drain_script(),
+            )
+            .unwrap();
+
+        assert!(result.selected.len() >= 3);
+        assert_matches!(result.excess, Excess::Change { .. });
+    }
+
+    #[test]
+    fn test_lowest_waste_coin_selection_is_deterministic() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for seed in 0..20 {
+            let values = (0..30)
+                .map(|_| rng.gen_range(1_000..1_000_000))
+                .collect::<Vec<_>>();
+            let target_amount = rng.gen_range(100_000..2_000_000);
+            let fee_rate = FeeRate::from_sat_per_vb_unchecked(rng.gen_range(1..100));
+
+            let select = || {
+                coin_selection(seed)
+                    .coin_select(
+                        vec![],
+                        utxos(&values),
+                        fee_rate,
+                        target_amount,
+                        &drain_script(),
+                    )
+                    .unwrap()
+            };
+            let result = select();
+            assert!(result.selected_amount() >= target_amount + result.fee_amount);
+            assert_eq!(result.selected, select().selected);
+        }
+    }
+
+    #[test]
+    fn test_lowest_waste_coin_selection_required() {
+        let required = utxos(&[100_000]);
+        let optional = utxos(&[50_000, 30_000]);
+
+        let result = coin_selection(0)
+            .coin_select(
+                required,
+                optional,
+                FeeRate::from_sat_per_vb_unchecked(10),
+                50_000,
+                &drain_script(),
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 1);
+        assert_eq!(result.selected_amount(), 100_000);
+    }
+
+    #[test]
+    fn test_lowest_waste_coin_selection_insufficient_funds() {
+        let result = coin_selection(0).coin_select(
+            vec![],
+            utxos(&[50_000, 30_000]),
+            FeeRate::from_sat_per_vb_unchecked(10),
+            80_000,
+            &drain_script(),
+        );
+
+        assert_matches!(
+            result,
+            Err(Error::InsufficientFunds {
+                needed: 81_360,
+                available: 80_000,
+            })
+        );
+    }
+}
@@ -0,0 +1,203 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! UTXO consolidation
+//!
+//! Receiving many small payments leaves the wallet with many small UTXOs, which are expensive to
+//! spend when feerates are high. When feerates are low, [`Wallet::plan_consolidations`] groups
+//! these UTXOs in transactions of bounded weight, each spending them to a single output of the
+//! wallet, and [`Wallet::build_consolidation`] builds them.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bitcoin::*;
+//! # use bdk_wallet::*;
+//! # let mut wallet = doctest_wallet!();
+//! let consolidations = wallet.plan_consolidations(
+//!     FeeRate::from_sat_per_vb_unchecked(2),
+//!     FeeRate::from_sat_per_vb_unchecked(20),
+//!     Weight::from_wu(100_000),
+//! );
+//!
+//! let mut psbts = Vec::new();
+//! for consolidation in &consolidations {
+//!     let mut builder = wallet.build_consolidation(consolidation)?;
+//!     psbts.push(builder.finish()?);
+//! }
+//! // sign and broadcast the consolidation transactions
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`Wallet::plan_consolidations`]: crate::Wallet::plan_consolidations
+//! [`Wallet::build_consolidation`]: crate::Wallet::build_consolidation
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use bitcoin::consensus::encode::VarInt;
+use bitcoin::{
+    absolute, transaction, Amount, FeeRate, OutPoint, Script, Transaction, TxIn, TxOut, Weight,
+};
+
+use super::utils::IsDust;
+use crate::WeightedUtxo;
+
+/// A consolidation transaction planned by [`Wallet::plan_consolidations`]
+///
+/// [`Wallet::plan_consolidations`]: crate::Wallet::plan_consolidations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Consolidation {
+    /// The UTXOs spent by the transaction
+    pub utxos: Vec<OutPoint>,
+    /// The feerate of the transaction
+    pub fee_rate: FeeRate,
+    /// The estimated weight of the signed transaction
+    pub weight: Weight,
+    /// The estimated fee of the transaction
+    pub fee: Amount,
+    /// The estimated fee saved by spending the UTXOs now rather than at the long term feerate,
+    /// including the cost of spending the output of the transaction at the long term feerate
+    pub savings: Amount,
+}
+
+/// Errors that can be returned when building a consolidation transaction.
+#[derive(Debug)]
+pub enum ConsolidationError {
+    /// A UTXO of the consolidation is not an unspent output of the wallet anymore.
+    UnknownUtxo(OutPoint),
+}
+
+impl fmt::Display for ConsolidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownUtxo(outpoint) => {
+                write!(f, "{} is not an unspent output of the wallet", outpoint)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ConsolidationError {}
+
+/// The feerates and the weight cap of the consolidation transactions
+pub(crate) struct ConsolidationParams<'a> {
+    pub fee_rate: FeeRate,
+    pub long_term_feerate: FeeRate,
+    pub max_weight: Weight,
+    /// The script of the consolidated outputs
+    pub drain_script: &'a Script,
+    /// The satisfaction weight of the consolidated outputs
+    pub drain_satisfaction_weight: usize,
+}
+
+/// The UTXOs of a consolidation being planned
+struct Batch {
+    utxos: Vec<OutPoint>,
+    value: u64,
+    inputs_weight: Weight,
+}
+
+impl Batch {
+    fn new() -> Self {
+        Self {
+            utxos: Vec::new(),
+            value: 0,
+            inputs_weight: Weight::ZERO,
+        }
+    }
+}
+
+impl ConsolidationParams<'_> {
+    /// Weight of a transaction spending `input_count` inputs of `inputs_weight` to the drain
+    /// script
+    fn weight(&self, input_count: usize, inputs_weight: Weight) -> Weight {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![TxOut {
+                value: Amount::ZERO,
+                script_pubkey: self.drain_script.into(),
+            }],
+        };
+        // the segwit marker and flag, and the size of the input count
+        let header_weight = 2 + 4 * (VarInt(input_count as u64).size() as u64 - 1);
+        tx.weight() + Weight::from_wu(header_weight) + inputs_weight
+    }
+
+    fn consolidation(&self, batch: Batch) -> Option<Consolidation> {
+        if batch.utxos.len() < 2 {
+            return None;
+        }
+        let weight = self.weight(batch.utxos.len(), batch.inputs_weight);
+        let fee = self.fee_rate * weight;
+        if batch
+            .value
+            .saturating_sub(fee.to_sat())
+            .is_dust(self.drain_script)
+        {
+            return None;
+        }
+        let long_term_fee = self.long_term_feerate * batch.inputs_weight;
+        let drain_spend_fee = self.long_term_feerate * input_weight(self.drain_satisfaction_weight);
+        let savings = long_term_fee.checked_sub(fee + drain_spend_fee)?;
+        if savings == Amount::ZERO {
+            return None;
+        }
+
+        Some(Consolidation {
+            utxos: batch.utxos,
+            fee_rate: self.fee_rate,
+            weight,
+            fee,
+            savings,
+        })
+    }
+}
+
+/// Weight of an input spent with `satisfaction_weight`, as counted by the wallet
+fn input_weight(satisfaction_weight: usize) -> Weight {
+    TxIn::default().segwit_weight() + Weight::from_wu(satisfaction_weight as u64)
+}
+
+/// Group `utxos` in consolidation transactions, from the smallest to the largest
+///
+/// The UTXOs whose effective value isn't positive at the feerate of `params` are skipped.
+pub(crate) fn plan(
+    mut utxos: Vec<WeightedUtxo>,
+    params: &ConsolidationParams<'_>,
+) -> Vec<Consolidation> {
+    utxos.sort_by_key(|u| u.utxo.txout().value);
+
+    let mut consolidations = Vec::new();
+    let mut batch = Batch::new();
+    for weighted_utxo in utxos {
+        let weight = input_weight(weighted_utxo.satisfaction_weight);
+        let value = weighted_utxo.utxo.txout().value.to_sat();
+        if value <= (params.fee_rate * weight).to_sat()
+            || params.weight(1, weight) > params.max_weight
+        {
+            continue;
+        }
+
+        if params.weight(batch.utxos.len() + 1, batch.inputs_weight + weight) > params.max_weight {
+            consolidations
+                .extend(params.consolidation(core::mem::replace(&mut batch, Batch::new())));
+        }
+        batch.utxos.push(weighted_utxo.utxo.outpoint());
+        batch.value += value;
+        batch.inputs_weight += weight;
+    }
+    consolidations.extend(params.consolidation(batch));
+
+    consolidations
+}
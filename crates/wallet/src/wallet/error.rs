@@ -14,6 +14,7 @@
 use crate::descriptor::policy::PolicyError;
 use crate::descriptor::DescriptorError;
 use crate::wallet::coin_selection;
+use crate::wallet::silent_payments::SilentPaymentError;
 use crate::{descriptor, KeychainKind};
 use alloc::string::String;
 use bitcoin::{absolute, psbt, Amount, OutPoint, Sequence, Txid};
@@ -88,8 +89,17 @@ pub enum CreateTxError {
     },
     /// `manually_selected_only` option is selected but no utxo has been passed
     NoUtxosSelected,
+    /// The input would be added to a transaction whose inputs were fixed with
+    /// [`TxBuilder::fixed_inputs`]
+    ///
+    /// [`TxBuilder::fixed_inputs`]: crate::wallet::tx_builder::TxBuilder::fixed_inputs
+    FixedInputs(OutPoint),
     /// Output created is under the dust limit, 546 satoshis
     OutputBelowDustLimit(usize),
+    /// Output is under the dust limit once its share of the fee is subtracted
+    OutputBelowDustLimitAfterFee(usize),
+    /// Requested to subtract the fee from a recipient that doesn't exist
+    InvalidSubtractFeeIndex(usize),
     /// There was an error with coin selection
     CoinSelection(coin_selection::Error),
     /// Cannot build a tx without recipients
@@ -108,6 +118,8 @@ pub enum CreateTxError {
     MissingNonWitnessUtxo(OutPoint),
     /// Miniscript PSBT error
     MiniscriptPsbt(MiniscriptPsbtError),
+    /// Error deriving the outputs of silent payment recipients
+    SilentPayment(SilentPaymentError),
 }
 
 impl fmt::Display for CreateTxError {
@@ -165,9 +177,30 @@ impl fmt::Display for CreateTxError {
             CreateTxError::NoUtxosSelected => {
                 write!(f, "No UTXO selected")
             }
+            CreateTxError::FixedInputs(outpoint) => {
+                write!(
+                    f,
+                    "Input {} can't be added to a transaction whose inputs are fixed",
+                    outpoint
+                )
+            }
             CreateTxError::OutputBelowDustLimit(limit) => {
                 write!(f, "Output below the dust limit: {}", limit)
             }
+            CreateTxError::OutputBelowDustLimitAfterFee(index) => {
+                write!(
+                    f,
+                    "Output below the dust limit after subtracting the fee: {}",
+                    index
+                )
+            }
+            CreateTxError::InvalidSubtractFeeIndex(index) => {
+                write!(
+                    f,
+                    "Cannot subtract the fee from missing recipient {}",
+                    index
+                )
+            }
             CreateTxError::CoinSelection(e) => e.fmt(f),
             CreateTxError::NoRecipients => {
                 write!(f, "Cannot build tx without recipients")
@@ -185,6 +218,9 @@ impl fmt::Display for CreateTxError {
             CreateTxError::MiniscriptPsbt(err) => {
                 write!(f, "Miniscript PSBT error: {}", err)
             }
+            CreateTxError::SilentPayment(err) => {
+                write!(f, "Silent payment error: {}", err)
+            }
         }
     }
 }
@@ -213,6 +249,12 @@ impl From<psbt::Error> for CreateTxError {
     }
 }
 
+impl From<SilentPaymentError> for CreateTxError {
+    fn from(err: SilentPaymentError) -> Self {
+        CreateTxError::SilentPayment(err)
+    }
+}
+
 impl From<coin_selection::Error> for CreateTxError {
     fn from(err: coin_selection::Error) -> Self {
         CreateTxError::CoinSelection(err)
@@ -267,3 +309,92 @@ impl fmt::Display for BuildFeeBumpError {
 
 #[cfg(feature = "std")]
 impl std::error::Error for BuildFeeBumpError {}
+
+#[derive(Debug)]
+/// Error returned from [`Wallet::build_cpfp`]
+///
+/// [`Wallet::build_cpfp`]: super::Wallet::build_cpfp
+pub enum BuildCpfpError {
+    /// Thrown when a tx is not found in the internal database
+    TransactionNotFound(Txid),
+    /// Happens when trying to bump a transaction that is already confirmed
+    TransactionConfirmed(Txid),
+    /// The parent transaction doesn't have any output the wallet can spend
+    NoSpendableOutput(Txid),
+    /// The fee of the parent transaction or of one of its unconfirmed ancestors can't be computed,
+    /// the outputs it spends must be inserted with [`Wallet::insert_txout`]
+    ///
+    /// [`Wallet::insert_txout`]: super::Wallet::insert_txout
+    FeeUnavailable(Txid),
+}
+
+impl fmt::Display for BuildCpfpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TransactionNotFound(txid) => {
+                write!(
+                    f,
+                    "Transaction not found in the internal database with txid: {}",
+                    txid
+                )
+            }
+            Self::TransactionConfirmed(txid) => {
+                write!(f, "Transaction already confirmed with txid: {}", txid)
+            }
+            Self::NoSpendableOutput(txid) => {
+                write!(f, "Transaction has no spendable output with txid: {}", txid)
+            }
+            Self::FeeUnavailable(txid) => {
+                write!(
+                    f,
+                    "Fee unavailable, the outputs spent by the transaction with txid {} are unknown",
+                    txid
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildCpfpError {}
+
+#[derive(Debug)]
+/// Error returned from [`Wallet::build_cancel`]
+///
+/// [`Wallet::build_cancel`]: super::Wallet::build_cancel
+pub enum BuildCancelError {
+    /// The transaction can't be replaced
+    FeeBump(BuildFeeBumpError),
+    /// None of the inputs of the transaction belong to the wallet
+    NoLocalInput(Txid),
+    /// The fee of this descendant of the transaction, which the replacement must pay for, is
+    /// unknown
+    FeeUnavailable(Txid),
+}
+
+impl fmt::Display for BuildCancelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FeeBump(e) => e.fmt(f),
+            Self::NoLocalInput(txid) => {
+                write!(
+                    f,
+                    "Transaction has no input from the wallet with txid: {}",
+                    txid
+                )
+            }
+            Self::FeeUnavailable(txid) => {
+                write!(f, "Fee of descendant transaction {} is unknown", txid)
+            }
+        }
+    }
+}
+
+impl From<BuildFeeBumpError> for BuildCancelError {
+    fn from(err: BuildFeeBumpError) -> Self {
+        BuildCancelError::FeeBump(err)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildCancelError {}
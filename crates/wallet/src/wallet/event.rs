@@ -0,0 +1,420 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Wallet events
+//!
+//! This module defines the [`WalletEvent`]s returned by [`Wallet::apply_update_events`],
+//! [`Wallet::apply_block_events`], [`Wallet::apply_block_connected_to_events`] and
+//! [`Wallet::apply_unconfirmed_txs_events`]. They describe how the wallet's canonical view of its
+//! transactions, unspent outputs and balance changed while applying the data, so callers don't
+//! have to diff [`Wallet::transactions`] and [`Wallet::list_unspent`] themselves.
+//!
+//! ## Example
+//!
+//! ```
+//! # use std::str::FromStr;
+//! # use bitcoin::*;
+//! # use bdk_wallet::*;
+//! # use bdk_wallet::wallet::event::WalletEvent;
+//! # let mut wallet = doctest_wallet!();
+//! # let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap().assume_checked();
+//! let mut builder = wallet.build_tx();
+//! builder.add_recipient(to_address.script_pubkey(), Amount::from_sat(50_000));
+//! let mut psbt = builder.finish()?;
+//! wallet.sign(&mut psbt, SignOptions::default())?;
+//! let tx = psbt.extract_tx()?;
+//!
+//! // the transaction is broadcast, and later seen in the mempool
+//! let events = wallet.apply_unconfirmed_txs_events([(&tx, 100)]);
+//! for event in &events {
+//!     match event {
+//!         WalletEvent::TxReceived { txid, .. } => println!("new transaction {}", txid),
+//!         WalletEvent::UtxoSpent { outpoint, .. } => println!("spent {}", outpoint),
+//!         WalletEvent::BalanceChanged { new, .. } => println!("new balance {}", new.total()),
+//!         _ => {}
+//!     }
+//! }
+//! assert!(events.contains(&WalletEvent::TxReceived {
+//!     txid: tx.txid(),
+//!     confirmation_time: chain::ConfirmationTime::Unconfirmed { last_seen: 100 },
+//! }));
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`Wallet::apply_update_events`]: crate::Wallet::apply_update_events
+//! [`Wallet::apply_block_events`]: crate::Wallet::apply_block_events
+//! [`Wallet::apply_block_connected_to_events`]: crate::Wallet::apply_block_connected_to_events
+//! [`Wallet::apply_unconfirmed_txs_events`]: crate::Wallet::apply_unconfirmed_txs_events
+//! [`Wallet::transactions`]: crate::Wallet::transactions
+//! [`Wallet::list_unspent`]: crate::Wallet::list_unspent
+
+use alloc::vec::Vec;
+
+use bdk_chain::collections::{BTreeMap, BTreeSet};
+use bdk_chain::keychain::Balance;
+use bdk_chain::{ConfirmationTime, TxGraph};
+use bitcoin::{OutPoint, Txid};
+
+/// An event describing a change applied to a [`Wallet`](crate::Wallet).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalletEvent {
+    /// A transaction became part of the wallet's canonical history.
+    ///
+    /// Transactions that are already confirmed when they are first seen are also reported with
+    /// [`WalletEvent::TxConfirmed`].
+    TxReceived {
+        /// The txid of the transaction.
+        txid: Txid,
+        /// Where the transaction is in the chain.
+        confirmation_time: ConfirmationTime,
+    },
+    /// A transaction was confirmed, or moved to a different block.
+    TxConfirmed {
+        /// The txid of the transaction.
+        txid: Txid,
+        /// The block the transaction is confirmed in.
+        confirmation_time: ConfirmationTime,
+        /// Where the transaction was before, or `None` if it wasn't canonical.
+        previous: Option<ConfirmationTime>,
+    },
+    /// A confirmed transaction was reorganized out of the chain and is unconfirmed again.
+    TxReorgedOut {
+        /// The txid of the transaction.
+        txid: Txid,
+        /// The block the transaction was confirmed in.
+        previous: ConfirmationTime,
+    },
+    /// A transaction is no longer canonical because a conflicting transaction replaced it.
+    TxReplaced {
+        /// The txid of the replaced transaction.
+        txid: Txid,
+        /// The canonical transactions spending the same outputs.
+        replaced_by: Vec<Txid>,
+    },
+    /// A transaction is no longer canonical, but no conflicting transaction spends the same
+    /// outputs. This happens when an ancestor of the transaction was replaced.
+    TxEvicted {
+        /// The txid of the evicted transaction.
+        txid: Txid,
+    },
+    /// An unspent output of the wallet was spent.
+    UtxoSpent {
+        /// The spent output.
+        outpoint: OutPoint,
+        /// The txid of the canonical transaction spending it.
+        spent_by: Txid,
+    },
+    /// The balance of the wallet changed.
+    BalanceChanged {
+        /// The balance before the change.
+        old: Balance,
+        /// The balance after the change.
+        new: Balance,
+    },
+}
+
+/// The canonical view of a wallet the [`WalletEvent`]s are derived from.
+#[derive(Debug)]
+pub(crate) struct Snapshot {
+    pub(crate) txs: BTreeMap<Txid, ConfirmationTime>,
+    pub(crate) utxos: BTreeSet<OutPoint>,
+    pub(crate) balance: Balance,
+}
+
+/// Compute the events that describe the changes between the `before` and `after` snapshots.
+///
+/// `graph` must be the graph the `after` snapshot was taken from.
+pub(crate) fn wallet_events<A>(
+    before: Snapshot,
+    after: Snapshot,
+    graph: &TxGraph<A>,
+) -> Vec<WalletEvent> {
+    let mut events = Vec::new();
+
+    for (&txid, &confirmation_time) in &after.txs {
+        let previous = before.txs.get(&txid).copied();
+        if previous.is_none() {
+            events.push(WalletEvent::TxReceived {
+                txid,
+                confirmation_time,
+            });
+        }
+        match (previous, confirmation_time) {
+            (Some(previous), _) if previous == confirmation_time => {}
+            (Some(previous @ ConfirmationTime::Confirmed { .. }), _)
+                if !confirmation_time.is_confirmed() =>
+            {
+                events.push(WalletEvent::TxReorgedOut { txid, previous })
+            }
+            (previous, ConfirmationTime::Confirmed { .. }) => {
+                events.push(WalletEvent::TxConfirmed {
+                    txid,
+                    confirmation_time,
+                    previous,
+                })
+            }
+            // only the last seen time of an unconfirmed transaction changed
+            _ => {}
+        }
+    }
+
+    for &txid in before
+        .txs
+        .keys()
+        .filter(|&txid| !after.txs.contains_key(txid))
+    {
+        let replaced_by = graph
+            .get_tx(txid)
+            .map(|tx| {
+                graph
+                    .direct_conflicts(&tx)
+                    .map(|(_, txid)| txid)
+                    .filter(|txid| after.txs.contains_key(txid))
+                    .collect::<BTreeSet<_>>()
+            })
+            .unwrap_or_default();
+        if replaced_by.is_empty() {
+            events.push(WalletEvent::TxEvicted { txid });
+        } else {
+            events.push(WalletEvent::TxReplaced {
+                txid,
+                replaced_by: replaced_by.into_iter().collect(),
+            });
+        }
+    }
+
+    for &outpoint in before.utxos.difference(&after.utxos) {
+        let spent_by = graph
+            .outspends(outpoint)
+            .iter()
+            .find(|&txid| after.txs.contains_key(txid));
+        if let Some(&spent_by) = spent_by {
+            events.push(WalletEvent::UtxoSpent { outpoint, spent_by });
+        }
+    }
+
+    if before.balance != after.balance {
+        events.push(WalletEvent::BalanceChanged {
+            old: before.balance,
+            new: after.balance,
+        });
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use alloc::vec;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{absolute, transaction, Amount, ScriptBuf, Transaction, TxIn, TxOut};
+
+    fn tx(previous_outputs: &[OutPoint], value: u64) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: previous_outputs
+                .iter()
+                .map(|&previous_output| TxIn {
+                    previous_output,
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    fn snapshot(txs: &[(Txid, ConfirmationTime)], utxos: &[OutPoint]) -> Snapshot {
+        Snapshot {
+            txs: txs.iter().copied().collect(),
+            utxos: utxos.iter().copied().collect(),
+            balance: Balance::default(),
+        }
+    }
+
+    fn confirmed(height: u32) -> ConfirmationTime {
+        ConfirmationTime::Confirmed {
+            height,
+            time: height as u64 * 100,
+        }
+    }
+
+    fn unconfirmed(last_seen: u64) -> ConfirmationTime {
+        ConfirmationTime::Unconfirmed { last_seen }
+    }
+
+    #[test]
+    fn test_tx_received() {
+        let graph = TxGraph::<()>::default();
+        let txid = tx(&[OutPoint::new(Txid::all_zeros(), 0)], 50_000).txid();
+        let outpoint = OutPoint::new(txid, 0);
+
+        let before = snapshot(&[], &[]);
+        let mut after = snapshot(&[(txid, unconfirmed(1))], &[outpoint]);
+        after.balance.untrusted_pending = Amount::from_sat(50_000);
+        assert_eq!(
+            wallet_events(before, after, &graph),
+            vec![
+                WalletEvent::TxReceived {
+                    txid,
+                    confirmation_time: unconfirmed(1),
+                },
+                WalletEvent::BalanceChanged {
+                    old: Balance::default(),
+                    new: Balance {
+                        untrusted_pending: Amount::from_sat(50_000),
+                        ..Default::default()
+                    },
+                },
+            ]
+        );
+
+        // a transaction confirmed when it is first seen is also reported as confirmed
+        let before = snapshot(&[], &[]);
+        let after = snapshot(&[(txid, confirmed(1))], &[]);
+        assert_eq!(
+            wallet_events(before, after, &graph),
+            vec![
+                WalletEvent::TxReceived {
+                    txid,
+                    confirmation_time: confirmed(1),
+                },
+                WalletEvent::TxConfirmed {
+                    txid,
+                    confirmation_time: confirmed(1),
+                    previous: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tx_confirmed() {
+        let graph = TxGraph::<()>::default();
+        let txid = tx(&[OutPoint::new(Txid::all_zeros(), 0)], 50_000).txid();
+
+        let before = snapshot(&[(txid, unconfirmed(1))], &[]);
+        let after = snapshot(&[(txid, confirmed(1))], &[]);
+        assert_eq!(
+            wallet_events(before, after, &graph),
+            vec![WalletEvent::TxConfirmed {
+                txid,
+                confirmation_time: confirmed(1),
+                previous: Some(unconfirmed(1)),
+            }]
+        );
+
+        // the transaction moved to a different block
+        let before = snapshot(&[(txid, confirmed(1))], &[]);
+        let after = snapshot(&[(txid, confirmed(2))], &[]);
+        assert_eq!(
+            wallet_events(before, after, &graph),
+            vec![WalletEvent::TxConfirmed {
+                txid,
+                confirmation_time: confirmed(2),
+                previous: Some(confirmed(1)),
+            }]
+        );
+
+        // nothing is reported when only the last seen time changes
+        let before = snapshot(&[(txid, unconfirmed(1))], &[]);
+        let after = snapshot(&[(txid, unconfirmed(2))], &[]);
+        assert_eq!(wallet_events(before, after, &graph), vec![]);
+    }
+
+    #[test]
+    fn test_tx_reorged_out() {
+        let graph = TxGraph::<()>::default();
+        let txid = tx(&[OutPoint::new(Txid::all_zeros(), 0)], 50_000).txid();
+
+        let before = snapshot(&[(txid, confirmed(1))], &[]);
+        let after = snapshot(&[(txid, unconfirmed(2))], &[]);
+        assert_eq!(
+            wallet_events(before, after, &graph),
+            vec![WalletEvent::TxReorgedOut {
+                txid,
+                previous: confirmed(1),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_tx_replaced_and_evicted() {
+        let funding = OutPoint::new(Txid::all_zeros(), 0);
+        let parent_tx = tx(&[funding], 50_000);
+        let replacement_tx = tx(&[funding], 49_000);
+        let child_tx = tx(&[OutPoint::new(parent_tx.txid(), 0)], 48_000);
+        let (parent, replacement, child) =
+            (parent_tx.txid(), replacement_tx.txid(), child_tx.txid());
+        let mut graph = TxGraph::<()>::default();
+        for tx in [parent_tx, replacement_tx, child_tx] {
+            let _ = graph.insert_tx(tx);
+        }
+
+        // the parent conflicts with the replacement, while the child is evicted with its parent
+        let before = snapshot(&[(parent, unconfirmed(1)), (child, unconfirmed(1))], &[]);
+        let after = snapshot(&[(replacement, unconfirmed(2))], &[]);
+        let events = wallet_events(before, after, &graph);
+        assert_eq!(events.len(), 3);
+        assert!(events.contains(&WalletEvent::TxReceived {
+            txid: replacement,
+            confirmation_time: unconfirmed(2),
+        }));
+        assert!(events.contains(&WalletEvent::TxReplaced {
+            txid: parent,
+            replaced_by: vec![replacement],
+        }));
+        assert!(events.contains(&WalletEvent::TxEvicted { txid: child }));
+
+        // a conflicting transaction that isn't canonical doesn't replace the transaction
+        let before = snapshot(&[(parent, unconfirmed(1))], &[]);
+        let after = snapshot(&[], &[]);
+        assert_eq!(
+            wallet_events(before, after, &graph),
+            vec![WalletEvent::TxEvicted { txid: parent }]
+        );
+    }
+
+    #[test]
+    fn test_utxo_spent() {
+        let funding = tx(&[OutPoint::new(Txid::all_zeros(), 0)], 50_000);
+        let outpoint = OutPoint::new(funding.txid(), 0);
+        let spending_tx = tx(&[outpoint], 49_000);
+        let (funding, spending) = (funding.txid(), spending_tx.txid());
+        let mut graph = TxGraph::<()>::default();
+        let _ = graph.insert_tx(spending_tx);
+
+        let before = snapshot(&[(funding, confirmed(1))], &[outpoint]);
+        let after = snapshot(&[(funding, confirmed(1)), (spending, unconfirmed(2))], &[]);
+        let events = wallet_events(before, after, &graph);
+        assert_eq!(events.len(), 2);
+        assert!(events.contains(&WalletEvent::TxReceived {
+            txid: spending,
+            confirmation_time: unconfirmed(2),
+        }));
+        assert!(events.contains(&WalletEvent::UtxoSpent {
+            outpoint,
+            spent_by: spending,
+        }));
+
+        // the output is gone because its transaction was evicted, not because it was spent
+        let before = snapshot(&[(funding, unconfirmed(1))], &[outpoint]);
+        let after = snapshot(&[], &[]);
+        assert_eq!(
+            wallet_events(before, after, &graph),
+            vec![WalletEvent::TxEvicted { txid: funding }]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Coldcard generic JSON exports
+//!
+//! The generic JSON export of a Coldcard contains one section for each single signature account
+//! type of its seed: `bip44` for P2PKH, `bip49` for P2SH-P2WPKH, `bip84` for P2WPKH and `bip86`
+//! for P2TR. One of them is imported, selected by its [`AddressType`].
+//!
+//! ## Example
+//!
+//! ```
+//! # use bitcoin::{AddressType, Network};
+//! # use bdk_wallet::wallet::export::coldcard;
+//! # use bdk_wallet::Wallet;
+//! let file = r#"{
+//!     "chain": "XTN",
+//!     "xfp": "73C5DA0A",
+//!     "account": 0,
+//!     "bip84": {
+//!         "name": "p2wpkh",
+//!         "deriv": "m/84'/1'/0'",
+//!         "xpub": "tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M"
+//!     }
+//! }"#;
+//! let import = coldcard::import_wallet(file, AddressType::P2wpkh)?;
+//! let wallet = Wallet::new_no_persist(
+//!     &import.descriptor,
+//!     &import.change_descriptor.expect("change descriptor"),
+//!     Network::Testnet,
+//! )?;
+//!
+//! let exported = coldcard::export_wallet(&wallet)?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use alloc::string::{String, ToString};
+use core::str::FromStr;
+
+use bdk_chain::collections::BTreeMap;
+use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint};
+use bitcoin::{AddressType, Network};
+use miniscript::descriptor::{DescriptorPublicKey, ShInner};
+use miniscript::Descriptor;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{public_descriptor, WalletFileError, WalletImport};
+use crate::types::KeychainKind;
+use crate::wallet::Wallet;
+
+/// A Coldcard generic JSON export
+#[derive(Debug, Serialize, Deserialize)]
+struct ColdcardExport {
+    chain: String,
+    xfp: String,
+    #[serde(default)]
+    account: u32,
+    #[serde(flatten)]
+    sections: BTreeMap<String, Value>,
+}
+
+/// The section of an account type
+#[derive(Debug, Serialize, Deserialize)]
+struct Section {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    deriv: String,
+    xpub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    desc: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    first: Option<String>,
+}
+
+/// The section name and the script type name of an address type
+fn section_names(address_type: AddressType) -> Option<(&'static str, &'static str)> {
+    match address_type {
+        AddressType::P2pkh => Some(("bip44", "p2pkh")),
+        AddressType::P2sh => Some(("bip49", "p2sh-p2wpkh")),
+        AddressType::P2wpkh => Some(("bip84", "p2wpkh")),
+        AddressType::P2tr => Some(("bip86", "p2tr")),
+        _ => None,
+    }
+}
+
+/// Import the account of a Coldcard generic JSON export for `address_type`
+///
+/// [`AddressType::P2sh`] selects the P2SH-P2WPKH account.
+pub fn import_wallet(
+    file: &str,
+    address_type: AddressType,
+) -> Result<WalletImport, WalletFileError> {
+    let (section_name, _) =
+        section_names(address_type).ok_or(WalletFileError::Unsupported("address type"))?;
+    let export: ColdcardExport = serde_json::from_str(file)?;
+    let section: Section = serde_json::from_value(
+        export
+            .sections
+            .get(section_name)
+            .cloned()
+            .ok_or(WalletFileError::InvalidField(section_name))?,
+    )?;
+
+    // recent firmware versions include the descriptor of the account
+    if let Some(desc) = section.desc {
+        return WalletImport::from_descriptor(&desc);
+    }
+
+    let fingerprint =
+        Fingerprint::from_str(&export.xfp).map_err(|_| WalletFileError::InvalidField("xfp"))?;
+    let deriv = DerivationPath::from_str(&section.deriv)
+        .map_err(|_| WalletFileError::InvalidField("deriv"))?;
+    let key = format!(
+        "[{}{}]{}",
+        fingerprint,
+        &deriv.to_string()[1..],
+        section.xpub
+    );
+    let descriptor = match address_type {
+        AddressType::P2pkh => format!("pkh({}/0/*)", key),
+        AddressType::P2sh => format!("sh(wpkh({}/0/*))", key),
+        AddressType::P2wpkh => format!("wpkh({}/0/*)", key),
+        _ => format!("tr({}/0/*)", key),
+    };
+
+    WalletImport::from_descriptor(&descriptor)
+}
+
+/// Export a wallet as a Coldcard generic JSON export
+///
+/// The wallet must use a single signature `pkh()`, `sh(wpkh())`, `wpkh()` or `tr()` descriptor,
+/// whose extended key has an origin, with `/0/*` and `/1/*` derivation steps.
+pub fn export_wallet(wallet: &Wallet) -> Result<String, WalletFileError> {
+    let (descriptor, multipath) = public_descriptor(wallet)?;
+    let (address_type, key) = match &descriptor {
+        Descriptor::Pkh(pkh) => (AddressType::P2pkh, pkh.as_inner()),
+        Descriptor::Wpkh(wpkh) => (AddressType::P2wpkh, wpkh.as_inner()),
+        Descriptor::Sh(sh) => match sh.as_inner() {
+            ShInner::Wpkh(wpkh) => (AddressType::P2sh, wpkh.as_inner()),
+            _ => return Err(WalletFileError::Unsupported("descriptor")),
+        },
+        Descriptor::Tr(tr) if tr.tap_tree().is_none() => (AddressType::P2tr, tr.internal_key()),
+        _ => return Err(WalletFileError::Unsupported("descriptor")),
+    };
+    let (xkey, fingerprint, deriv) = match key {
+        DescriptorPublicKey::XPub(xkey) => match &xkey.origin {
+            Some((fingerprint, deriv)) => (xkey, fingerprint, deriv),
+            None => return Err(WalletFileError::Unsupported("key without origin")),
+        },
+        _ => return Err(WalletFileError::Unsupported("key")),
+    };
+    let account = match deriv.into_iter().last() {
+        Some(ChildNumber::Hardened { index }) => *index,
+        _ => 0,
+    };
+
+    let (section_name, name) = section_names(address_type).expect("supported address type");
+    let section = Section {
+        name: Some(name.to_string()),
+        deriv: deriv.to_string(),
+        xpub: xkey.xkey.to_string(),
+        desc: Some(multipath.to_string()),
+        first: Some(
+            wallet
+                .peek_address(KeychainKind::External, 0)
+                .address
+                .to_string(),
+        ),
+    };
+    let chain = match wallet.network() {
+        Network::Bitcoin => "BTC",
+        Network::Regtest => "XRT",
+        _ => "XTN",
+    };
+    let mut sections = BTreeMap::new();
+    sections.insert(section_name.to_string(), serde_json::to_value(section)?);
+    let export = ColdcardExport {
+        chain: chain.to_string(),
+        xfp: fingerprint.to_string().to_uppercase(),
+        account,
+        sections,
+    };
+
+    Ok(serde_json::to_string(&export)?)
+}
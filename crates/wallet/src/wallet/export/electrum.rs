@@ -0,0 +1,411 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Electrum wallet files
+//!
+//! Electrum stores a wallet as a JSON file with one keystore for standard wallets, or one keystore
+//! per cosigner (`x1/`, `x2/`, ...) for `sortedmulti` wallets. The script type is encoded in the
+//! [SLIP132] version of the extended public keys, e.g. `zpub` for P2WPKH. Labels of transactions
+//! and addresses are imported and exported too.
+//!
+//! Only unencrypted wallet files with BIP32 or hardware wallet keystores are supported.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bitcoin::Network;
+//! # use bdk_wallet::wallet::export::electrum;
+//! # use bdk_wallet::Wallet;
+//! let file = r#"{
+//!     "keystore": {
+//!         "type": "bip32",
+//!         "xpub": "vpub5Y6cjg78GGuNLsaPhmYsiw4gYX3HoQiRBiSwDaBXKUafCt9bNwWQiitDk5VZ5BVxYnQdwoTyXSs2JHRPAgjAvtbBrf8ZhDYe2jWAqvZVnsc",
+//!         "derivation": "m/84'/1'/0'",
+//!         "root_fingerprint": "73c5da0a"
+//!     },
+//!     "wallet_type": "standard",
+//!     "labels": {}
+//! }"#;
+//! let import = electrum::import_wallet(file, Network::Testnet)?;
+//! let wallet = Wallet::new_no_persist(
+//!     &import.descriptor,
+//!     &import.change_descriptor.expect("change descriptor"),
+//!     Network::Testnet,
+//! )?;
+//!
+//! let exported = electrum::export_wallet(&wallet)?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [SLIP132]: https://github.com/satoshilabs/slips/blob/master/slip-0132.md
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::bip32::{DerivationPath, Fingerprint, Xpub};
+use bitcoin::{base58, Address, Network, Txid};
+use miniscript::descriptor::{DescriptorPublicKey, ShInner, Wildcard, WshInner};
+use miniscript::Descriptor;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::{public_descriptor, WalletFileError, WalletImport};
+use crate::wallet::labels::{Label, LabelRef};
+use crate::wallet::Wallet;
+
+/// The seed version of the exported wallet files
+///
+/// Electrum upgrades the files with an older version when opening them.
+const SEED_VERSION: u32 = 17;
+
+/// A keystore of an Electrum wallet file
+#[derive(Debug, Serialize, Deserialize)]
+struct Keystore {
+    #[serde(rename = "type")]
+    type_: String,
+    xpub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    derivation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    root_fingerprint: Option<String>,
+}
+
+/// The script types of [SLIP132] extended public keys
+///
+/// [SLIP132]: https://github.com/satoshilabs/slips/blob/master/slip-0132.md
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScriptType {
+    /// `pkh()` or `sh(sortedmulti())`
+    Legacy,
+    /// `sh(wpkh())` or `sh(wsh(sortedmulti()))`
+    NestedSegwit,
+    /// `wpkh()` or `wsh(sortedmulti())`
+    Segwit,
+}
+
+/// The [SLIP132] versions of mainnet and testnet extended public keys, for single signature and
+/// multisig wallets
+///
+/// [SLIP132]: https://github.com/satoshilabs/slips/blob/master/slip-0132.md
+const VERSIONS: [(ScriptType, bool, [u8; 4], [u8; 4]); 6] = [
+    (
+        ScriptType::Legacy,
+        false,
+        [0x04, 0x88, 0xb2, 0x1e],
+        [0x04, 0x35, 0x87, 0xcf],
+    ),
+    (
+        ScriptType::Legacy,
+        true,
+        [0x04, 0x88, 0xb2, 0x1e],
+        [0x04, 0x35, 0x87, 0xcf],
+    ),
+    (
+        ScriptType::NestedSegwit,
+        false,
+        [0x04, 0x9d, 0x7c, 0xb2],
+        [0x04, 0x4a, 0x52, 0x62],
+    ),
+    (
+        ScriptType::NestedSegwit,
+        true,
+        [0x02, 0x95, 0xb4, 0x3f],
+        [0x02, 0x42, 0x89, 0xef],
+    ),
+    (
+        ScriptType::Segwit,
+        false,
+        [0x04, 0xb2, 0x47, 0x46],
+        [0x04, 0x5f, 0x1c, 0xf6],
+    ),
+    (
+        ScriptType::Segwit,
+        true,
+        [0x02, 0xaa, 0x7e, 0xd3],
+        [0x02, 0x57, 0x54, 0x83],
+    ),
+];
+
+/// Decode a [SLIP132] extended public key into a standard one and its script type
+///
+/// [SLIP132]: https://github.com/satoshilabs/slips/blob/master/slip-0132.md
+fn decode_slip132(key: &str) -> Option<(Xpub, ScriptType)> {
+    let mut data = base58::decode_check(key).ok()?;
+    if data.len() != 78 {
+        return None;
+    }
+    let (script_type, mainnet) = VERSIONS.iter().find_map(|(script_type, _, main, test)| {
+        if data[..4] == main[..] {
+            Some((*script_type, true))
+        } else if data[..4] == test[..] {
+            Some((*script_type, false))
+        } else {
+            None
+        }
+    })?;
+    let (_, _, main, test) = VERSIONS[0];
+    data[..4].copy_from_slice(if mainnet { &main } else { &test });
+    Some((Xpub::decode(&data).ok()?, script_type))
+}
+
+/// Encode an extended public key with the [SLIP132] version of its script type
+///
+/// [SLIP132]: https://github.com/satoshilabs/slips/blob/master/slip-0132.md
+fn encode_slip132(xpub: &Xpub, script_type: ScriptType, multisig: bool) -> String {
+    let (_, _, main, test) = VERSIONS
+        .iter()
+        .find(|(t, m, _, _)| *t == script_type && *m == multisig)
+        .expect("all script types have a version");
+    let mut data = xpub.encode();
+    data[..4].copy_from_slice(if xpub.network == Network::Bitcoin {
+        main
+    } else {
+        test
+    });
+    base58::encode_check(&data)
+}
+
+/// Parse the `<m>of<n>` type of a multisig wallet
+fn parse_multisig(wallet_type: &str) -> Option<(usize, usize)> {
+    let (m, n) = wallet_type.split_once("of")?;
+    let (m, n) = (m.parse().ok()?, n.parse().ok()?);
+    if m == 0 || m > n {
+        return None;
+    }
+    Some((m, n))
+}
+
+/// Import an Electrum wallet file
+///
+/// The labels of addresses that are not valid for `network` are ignored.
+pub fn import_wallet(file: &str, network: Network) -> Result<WalletImport, WalletFileError> {
+    let file: Map<String, Value> = serde_json::from_str(file)?;
+    let wallet_type = file
+        .get("wallet_type")
+        .and_then(Value::as_str)
+        .ok_or(WalletFileError::InvalidField("wallet_type"))?;
+    let (threshold, keystores) = if wallet_type == "standard" {
+        (None, vec![keystore_key(&file, "keystore")?])
+    } else {
+        let (m, n) =
+            parse_multisig(wallet_type).ok_or(WalletFileError::Unsupported("wallet type"))?;
+        let keystores = (1..=n)
+            .map(|i| keystore_key(&file, &format!("x{}/", i)))
+            .collect::<Result<Vec<_>, _>>()?;
+        (Some(m), keystores)
+    };
+
+    let script_type = keystores[0].1;
+    if keystores.iter().any(|(_, t)| *t != script_type) {
+        return Err(WalletFileError::InvalidField("xpub"));
+    }
+    let descriptor = |step: u32| {
+        let keys = keystores
+            .iter()
+            .map(|(key, _)| format!("{}/{}/*", key, step))
+            .collect::<Vec<_>>();
+        match (threshold, script_type) {
+            (None, ScriptType::Legacy) => format!("pkh({})", keys[0]),
+            (None, ScriptType::NestedSegwit) => format!("sh(wpkh({}))", keys[0]),
+            (None, ScriptType::Segwit) => format!("wpkh({})", keys[0]),
+            (Some(m), ScriptType::Legacy) => format!("sh(sortedmulti({},{}))", m, keys.join(",")),
+            (Some(m), ScriptType::NestedSegwit) => {
+                format!("sh(wsh(sortedmulti({},{})))", m, keys.join(","))
+            }
+            (Some(m), ScriptType::Segwit) => format!("wsh(sortedmulti({},{}))", m, keys.join(",")),
+        }
+    };
+
+    let mut labels = Vec::new();
+    if let Some(file_labels) = file.get("labels") {
+        let file_labels = file_labels
+            .as_object()
+            .ok_or(WalletFileError::InvalidField("labels"))?;
+        for (label_ref, label) in file_labels {
+            let label = label
+                .as_str()
+                .ok_or(WalletFileError::InvalidField("labels"))?;
+            let label_ref = if let Ok(txid) = Txid::from_str(label_ref) {
+                LabelRef::Tx(txid)
+            } else {
+                match Address::<NetworkUnchecked>::from_str(label_ref)
+                    .map_err(|_| WalletFileError::InvalidField("labels"))?
+                    .require_network(network)
+                {
+                    Ok(address) => LabelRef::Addr(address.script_pubkey()),
+                    Err(_) => continue,
+                }
+            };
+            labels.push((label_ref, Label::new(label)));
+        }
+    }
+
+    Ok(WalletImport {
+        descriptor: descriptor(0),
+        change_descriptor: Some(descriptor(1)),
+        blockheight: None,
+        labels,
+    })
+}
+
+/// The key of a keystore, with its origin, and its script type
+fn keystore_key(
+    file: &Map<String, Value>,
+    field: &str,
+) -> Result<(String, ScriptType), WalletFileError> {
+    let keystore: Keystore = serde_json::from_value(
+        file.get(field)
+            .cloned()
+            .ok_or(WalletFileError::InvalidField("keystore"))?,
+    )?;
+    if keystore.type_ != "bip32" && keystore.type_ != "hardware" {
+        return Err(WalletFileError::Unsupported("keystore type"));
+    }
+    let (xpub, script_type) =
+        decode_slip132(&keystore.xpub).ok_or(WalletFileError::InvalidField("xpub"))?;
+
+    let origin = match (keystore.root_fingerprint, keystore.derivation) {
+        (Some(fingerprint), Some(derivation)) => {
+            let fingerprint = Fingerprint::from_str(&fingerprint)
+                .map_err(|_| WalletFileError::InvalidField("root_fingerprint"))?;
+            let derivation = DerivationPath::from_str(&derivation)
+                .map_err(|_| WalletFileError::InvalidField("derivation"))?;
+            format!("[{}{}]", fingerprint, &derivation.to_string()[1..])
+        }
+        _ => String::new(),
+    };
+
+    Ok((format!("{}{}", origin, xpub), script_type))
+}
+
+/// Export a wallet as an Electrum wallet file
+///
+/// The wallet must use a single signature `pkh()`, `wpkh()` or `sh(wpkh())` descriptor, or a
+/// `sortedmulti()` descriptor, with `/0/*` and `/1/*` derivation steps. The labels of
+/// transactions and addresses are exported too.
+pub fn export_wallet(wallet: &Wallet) -> Result<String, WalletFileError> {
+    let (descriptor, _) = public_descriptor(wallet)?;
+    let unsupported = WalletFileError::Unsupported("descriptor");
+    let (script_type, threshold, keys) = match &descriptor {
+        Descriptor::Pkh(pkh) => (ScriptType::Legacy, None, vec![pkh.as_inner()]),
+        Descriptor::Wpkh(wpkh) => (ScriptType::Segwit, None, vec![wpkh.as_inner()]),
+        Descriptor::Sh(sh) => match sh.as_inner() {
+            ShInner::Wpkh(wpkh) => (ScriptType::NestedSegwit, None, vec![wpkh.as_inner()]),
+            ShInner::SortedMulti(multi) => (
+                ScriptType::Legacy,
+                Some(multi.k),
+                multi.pks.iter().collect(),
+            ),
+            ShInner::Wsh(wsh) => match wsh.as_inner() {
+                WshInner::SortedMulti(multi) => (
+                    ScriptType::NestedSegwit,
+                    Some(multi.k),
+                    multi.pks.iter().collect(),
+                ),
+                _ => return Err(unsupported),
+            },
+            _ => return Err(unsupported),
+        },
+        Descriptor::Wsh(wsh) => match wsh.as_inner() {
+            WshInner::SortedMulti(multi) => (
+                ScriptType::Segwit,
+                Some(multi.k),
+                multi.pks.iter().collect(),
+            ),
+            _ => return Err(unsupported),
+        },
+        _ => return Err(unsupported),
+    };
+
+    let mut file = Map::new();
+    for (i, key) in keys.iter().enumerate() {
+        let xkey = match key {
+            DescriptorPublicKey::XPub(xkey)
+                if xkey.wildcard == Wildcard::Unhardened && xkey.derivation_path.len() == 1 =>
+            {
+                xkey
+            }
+            _ => return Err(WalletFileError::Unsupported("key")),
+        };
+        let keystore = Keystore {
+            type_: "bip32".to_string(),
+            xpub: encode_slip132(&xkey.xkey, script_type, threshold.is_some()),
+            derivation: xkey.origin.as_ref().map(|(_, path)| path.to_string()),
+            root_fingerprint: xkey
+                .origin
+                .as_ref()
+                .map(|(fingerprint, _)| fingerprint.to_string()),
+        };
+        let field = match threshold {
+            Some(_) => format!("x{}/", i + 1),
+            None => "keystore".to_string(),
+        };
+        file.insert(field, serde_json::to_value(keystore)?);
+    }
+
+    let wallet_type = match threshold {
+        Some(m) => format!("{}of{}", m, keys.len()),
+        None => "standard".to_string(),
+    };
+    let labels = wallet
+        .labels()
+        .filter_map(|(label_ref, label)| {
+            let label_ref = match label_ref {
+                LabelRef::Tx(txid) => txid.to_string(),
+                LabelRef::Addr(spk) => Address::from_script(spk, wallet.network())
+                    .ok()?
+                    .to_string(),
+                _ => return None,
+            };
+            Some((label_ref, Value::from(label.label.clone())))
+        })
+        .collect::<Map<_, _>>();
+
+    file.insert("wallet_type".to_string(), Value::from(wallet_type));
+    file.insert("labels".to_string(), Value::from(labels));
+    file.insert("seed_version".to_string(), Value::from(SEED_VERSION));
+    file.insert("use_encryption".to_string(), Value::from(false));
+
+    Ok(serde_json::to_string(&file)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_slip132() {
+        let zpub = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+        let (xpub, script_type) = decode_slip132(zpub).unwrap();
+        assert_eq!(script_type, ScriptType::Segwit);
+        assert_eq!(xpub.network, Network::Bitcoin);
+        assert_eq!(encode_slip132(&xpub, ScriptType::Segwit, false), zpub);
+        assert_eq!(
+            xpub.to_string(),
+            "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V"
+        );
+
+        let (xpub, script_type) =
+            decode_slip132(&encode_slip132(&xpub, ScriptType::NestedSegwit, true)).unwrap();
+        assert_eq!(script_type, ScriptType::NestedSegwit);
+        assert_eq!(xpub.network, Network::Bitcoin);
+        assert!(decode_slip132("not a key").is_none());
+    }
+
+    #[test]
+    fn test_parse_multisig() {
+        assert_eq!(parse_multisig("2of3"), Some((2, 3)));
+        assert_eq!(parse_multisig("0of3"), None);
+        assert_eq!(parse_multisig("4of3"), None);
+        assert_eq!(parse_multisig("imported"), None);
+    }
+}
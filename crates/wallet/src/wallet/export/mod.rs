@@ -0,0 +1,776 @@
+// Bitcoin Dev Kit
+// Written in 2020 by Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020-2021 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Wallet export
+//!
+//! This modules implements the wallet export format used by [FullyNoded](https://github.com/Fonta1n3/FullyNoded/blob/10b7808c8b929b171cca537fb50522d015168ac9/Docs/Wallets/Wallet-Export-Spec.md).
+//! Wallets can also be exported as [BIP388] wallet policies, used by hardware wallets to register
+//! multisig and miniscript wallets, with [`WalletPolicy::export_wallet`].
+//!
+//! The submodules import and export the wallet files of other wallet software, to migrate wallets
+//! in both directions: [`electrum`], [`sparrow`], [`specter`] and [`coldcard`]. Importers return a
+//! [`WalletImport`] with the descriptors of the wallet and, when the file has them, its birthday
+//! height and labels. Exporters only include the public keys of the wallet.
+//!
+//! ## Examples
+//!
+//! ### Import from JSON
+//!
+//! ```
+//! # use std::str::FromStr;
+//! # use bitcoin::*;
+//! # use bdk_wallet::wallet::export::*;
+//! # use bdk_wallet::*;
+//! let import = r#"{
+//!     "descriptor": "wpkh([c258d2e4\/84h\/1h\/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe\/0\/*)",
+//!     "blockheight":1782088,
+//!     "label":"testnet"
+//! }"#;
+//!
+//! let import = FullyNodedExport::from_str(import)?;
+//! let wallet = Wallet::new_no_persist(
+//!     &import.descriptor(),
+//!     &import.change_descriptor().expect("change descriptor"),
+//!     Network::Testnet,
+//! )?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! ### Export a `Wallet`
+//! ```
+//! # use bitcoin::*;
+//! # use bdk_wallet::wallet::export::*;
+//! # use bdk_wallet::*;
+//! let wallet = Wallet::new_no_persist(
+//!     "wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/0/*)",
+//!     "wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/1/*)",
+//!     Network::Testnet,
+//! )?;
+//! let export = FullyNodedExport::export_wallet(&wallet, "exported wallet", true).unwrap();
+//!
+//! println!("Exported: {}", export.to_string());
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BIP388]: https://github.com/bitcoin/bips/blob/master/bip-0388.mediawiki
+//! [`WalletPolicy::export_wallet`]: crate::descriptor::WalletPolicy::export_wallet
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+use bitcoin::secp256k1::Secp256k1;
+use miniscript::descriptor::{KeyMap, ShInner, WshInner};
+use miniscript::{Descriptor, ScriptContext, Terminal};
+
+use crate::descriptor::{
+    join_multipath_descriptors, split_multipath, to_multipath, ExtendedDescriptor,
+};
+use crate::types::KeychainKind;
+use crate::wallet::labels::{Label, LabelRef};
+use crate::wallet::Wallet;
+
+pub mod coldcard;
+pub mod electrum;
+pub mod sparrow;
+pub mod specter;
+
+/// Alias for [`FullyNodedExport`]
+#[deprecated(since = "0.18.0", note = "Please use [`FullyNodedExport`] instead")]
+pub type WalletExport = FullyNodedExport;
+
+/// Structure that contains the export of a wallet
+///
+/// For a usage example see [this module](crate::wallet::export)'s documentation.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FullyNodedExport {
+    descriptor: String,
+    /// Earliest block to rescan when looking for the wallet's transactions
+    pub blockheight: u32,
+    /// Arbitrary label for the wallet
+    pub label: String,
+}
+
+impl fmt::Display for FullyNodedExport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", serde_json::to_string(self).unwrap())
+    }
+}
+
+impl FromStr for FullyNodedExport {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+fn remove_checksum(s: String) -> String {
+    s.split_once('#').map(|(a, _)| String::from(a)).unwrap()
+}
+
+/// Errors that can be returned when importing or exporting the wallet files of other wallet
+/// software
+#[derive(Debug)]
+pub enum WalletFileError {
+    /// The file is not valid JSON or doesn't have the expected structure
+    Json(serde_json::Error),
+    /// A field of the file is missing or invalid
+    InvalidField(&'static str),
+    /// The wallet can't be represented in, or imported from, the file format
+    Unsupported(&'static str),
+    /// The external and internal descriptors of the wallet differ in more than their `/0/*` and
+    /// `/1/*` derivation steps
+    IncompatibleDescriptors,
+}
+
+impl fmt::Display for WalletFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json(e) => write!(f, "invalid wallet file: {}", e),
+            Self::InvalidField(field) => write!(f, "missing or invalid field `{}`", field),
+            Self::Unsupported(what) => write!(f, "unsupported {}", what),
+            Self::IncompatibleDescriptors => write!(f, "incompatible change descriptor"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for WalletFileError {}
+
+impl From<serde_json::Error> for WalletFileError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// A wallet imported from the wallet file of another wallet software
+///
+/// The descriptors can be used to create a [`Wallet`], and the labels added to it with
+/// [`Wallet::set_label`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletImport {
+    /// The external descriptor
+    pub descriptor: String,
+    /// The internal descriptor, if the wallet has one
+    pub change_descriptor: Option<String>,
+    /// The height of the block the wallet was created at, if the file has it
+    pub blockheight: Option<u32>,
+    /// The labels of the wallet, if the file has them
+    pub labels: Vec<(LabelRef, Label)>,
+}
+
+impl WalletImport {
+    /// Create an import of the descriptors in `descriptor`, without birthday height or labels
+    ///
+    /// `descriptor` is either a [BIP389] multipath descriptor or an external descriptor whose
+    /// extended keys derive `/0/*`, the internal descriptor deriving `/1/*` from them instead.
+    /// Its checksum, if any, is verified and removed.
+    ///
+    /// [BIP389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+    fn from_descriptor(descriptor: &str) -> Result<Self, WalletFileError> {
+        let (descriptor, keymap) = parse_multipath_descriptor(descriptor)?;
+
+        if !descriptor.is_multipath() {
+            return Ok(WalletImport {
+                descriptor: remove_checksum(descriptor.to_string_with_secret(&keymap)),
+                change_descriptor: None,
+                blockheight: None,
+                labels: Vec::new(),
+            });
+        }
+        let [(external, external_keymap), (internal, internal_keymap)] =
+            split_multipath(descriptor, keymap)
+                .map_err(|_| WalletFileError::InvalidField("descriptor"))?;
+        Ok(WalletImport {
+            descriptor: remove_checksum(external.to_string_with_secret(&external_keymap)),
+            change_descriptor: Some(remove_checksum(
+                internal.to_string_with_secret(&internal_keymap),
+            )),
+            blockheight: None,
+            labels: Vec::new(),
+        })
+    }
+}
+
+/// Parse a descriptor of a wallet file, verifying its checksum if it has one
+fn parse_descriptor(descriptor: &str) -> Result<(ExtendedDescriptor, KeyMap), WalletFileError> {
+    ExtendedDescriptor::parse_descriptor(&Secp256k1::new(), descriptor.trim())
+        .map_err(|_| WalletFileError::InvalidField("descriptor"))
+}
+
+/// Parse a descriptor of a wallet file into a [BIP389] multipath descriptor deriving `/<0;1>/*`
+/// if its extended keys derive `/0/*`
+///
+/// The descriptor is returned as is if it's already a multipath descriptor, or if none of its keys
+/// derives `/0/*`.
+///
+/// [BIP389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+fn parse_multipath_descriptor(
+    descriptor: &str,
+) -> Result<(ExtendedDescriptor, KeyMap), WalletFileError> {
+    let (descriptor, keymap) = parse_descriptor(descriptor)?;
+    if descriptor.is_multipath() {
+        return Ok((descriptor, keymap));
+    }
+    to_multipath(&descriptor, &keymap).ok_or(WalletFileError::InvalidField("descriptor"))
+}
+
+/// The public external descriptor of a wallet, and the multipath descriptor of its external and
+/// internal keychains
+///
+/// Returns an error if the wallet has no change descriptor, or if its change descriptor doesn't
+/// only differ by deriving `/1/*` instead of `/0/*` from its extended keys.
+fn public_descriptor(
+    wallet: &Wallet,
+) -> Result<(ExtendedDescriptor, ExtendedDescriptor), WalletFileError> {
+    if !wallet
+        .keychains()
+        .any(|(keychain, _)| *keychain == KeychainKind::Internal)
+    {
+        return Err(WalletFileError::Unsupported(
+            "wallet without change descriptor",
+        ));
+    }
+    let external = wallet.public_descriptor(KeychainKind::External);
+    let internal = wallet.public_descriptor(KeychainKind::Internal);
+    let (multipath, _) = join_multipath_descriptors(external, &KeyMap::new(), internal)
+        .ok_or(WalletFileError::IncompatibleDescriptors)?;
+
+    Ok((external.clone(), multipath))
+}
+
+/// The height of the block of the oldest confirmed transaction of a wallet, or `0`
+fn wallet_birthday(wallet: &Wallet) -> u32 {
+    wallet
+        .transactions()
+        .filter_map(|canonical_tx| match canonical_tx.chain_position {
+            bdk_chain::ChainPosition::Confirmed(a) => Some(a.confirmation_height),
+            bdk_chain::ChainPosition::Unconfirmed(_) => None,
+        })
+        .min()
+        .unwrap_or(0)
+}
+
+impl FullyNodedExport {
+    /// Export a wallet
+    ///
+    /// This function returns an error if it determines that the `wallet`'s descriptor(s) are not
+    /// supported by Bitcoin Core or don't follow the standard derivation paths defined by BIP44
+    /// and others.
+    ///
+    /// If `include_blockheight` is `true`, this function will look into the `wallet`'s database
+    /// for the confirmed transaction with the lowest height and use it as the earliest block to
+    /// rescan.
+    ///
+    /// If the database is empty or `include_blockheight` is false, the `blockheight` field
+    /// returned will be `0`.
+    pub fn export_wallet(
+        wallet: &Wallet,
+        label: &str,
+        include_blockheight: bool,
+    ) -> Result<Self, &'static str> {
+        let descriptor = wallet
+            .get_descriptor_for_keychain(KeychainKind::External)
+            .to_string_with_secret(
+                &wallet
+                    .get_signers(KeychainKind::External)
+                    .as_key_map(wallet.secp_ctx()),
+            );
+        let descriptor = remove_checksum(descriptor);
+        Self::is_compatible_with_core(&descriptor)?;
+
+        let blockheight = if include_blockheight {
+            wallet_birthday(wallet)
+        } else {
+            0
+        };
+
+        let export = FullyNodedExport {
+            descriptor,
+            label: label.into(),
+            blockheight,
+        };
+
+        // wallets with a single descriptor don't have a change descriptor to check
+        if wallet
+            .keychains()
+            .any(|(keychain, _)| *keychain == KeychainKind::Internal)
+        {
+            let change_descriptor = {
+                let descriptor = wallet
+                    .get_descriptor_for_keychain(KeychainKind::Internal)
+                    .to_string_with_secret(
+                        &wallet
+                            .get_signers(KeychainKind::Internal)
+                            .as_key_map(wallet.secp_ctx()),
+                    );
+                Some(remove_checksum(descriptor))
+            };
+
+            if export.change_descriptor() != change_descriptor {
+                return Err("Incompatible change descriptor");
+            }
+        }
+
+        Ok(export)
+    }
+
+    fn is_compatible_with_core(descriptor: &str) -> Result<(), &'static str> {
+        fn check_ms<Ctx: ScriptContext>(
+            terminal: &Terminal<String, Ctx>,
+        ) -> Result<(), &'static str> {
+            if let Terminal::Multi(_, _) = terminal {
+                Ok(())
+            } else {
+                Err("The descriptor contains operators not supported by Bitcoin Core")
+            }
+        }
+
+        // pkh(), wpkh(), sh(wpkh()) are always fine, as well as multi() and sortedmulti()
+        match Descriptor::<String>::from_str(descriptor).map_err(|_| "Invalid descriptor")? {
+            Descriptor::Pkh(_) | Descriptor::Wpkh(_) => Ok(()),
+            Descriptor::Sh(sh) => match sh.as_inner() {
+                ShInner::Wpkh(_) => Ok(()),
+                ShInner::SortedMulti(_) => Ok(()),
+                ShInner::Wsh(wsh) => match wsh.as_inner() {
+                    WshInner::SortedMulti(_) => Ok(()),
+                    WshInner::Ms(ms) => check_ms(&ms.node),
+                },
+                ShInner::Ms(ms) => check_ms(&ms.node),
+            },
+            Descriptor::Wsh(wsh) => match wsh.as_inner() {
+                WshInner::SortedMulti(_) => Ok(()),
+                WshInner::Ms(ms) => check_ms(&ms.node),
+            },
+            Descriptor::Tr(_) => Ok(()),
+            _ => Err("The descriptor is not compatible with Bitcoin Core"),
+        }
+    }
+
+    /// Return the external descriptor
+    ///
+    /// If the export contains a [BIP389] multipath descriptor, the external descriptor is
+    /// derived from its first path.
+    ///
+    /// [BIP389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+    pub fn descriptor(&self) -> String {
+        match parse_descriptor(&self.descriptor) {
+            Ok((descriptor, keymap)) if descriptor.is_multipath() => {
+                match split_multipath(descriptor, keymap) {
+                    Ok([(external, keymap), _]) => {
+                        remove_checksum(external.to_string_with_secret(&keymap))
+                    }
+                    Err(_) => self.descriptor.clone(),
+                }
+            }
+            _ => self.descriptor.clone(),
+        }
+    }
+
+    /// Return the internal descriptor, if present
+    ///
+    /// The internal descriptor is derived from the second path of a [BIP389] multipath
+    /// descriptor, or from the extended keys of the external descriptor deriving `/1/*` instead of
+    /// `/0/*`.
+    ///
+    /// [BIP389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+    pub fn change_descriptor(&self) -> Option<String> {
+        let (descriptor, keymap) = parse_multipath_descriptor(&self.descriptor).ok()?;
+        let [_, (internal, keymap)] = split_multipath(descriptor, keymap).ok()?;
+        Some(remove_checksum(internal.to_string_with_secret(&keymap)))
+    }
+
+    /// Return the external and internal descriptors as a single [BIP389] multipath descriptor,
+    /// if the export has a change descriptor
+    ///
+    /// The result can be used to create a wallet with [`Wallet::new_single`].
+    ///
+    /// [BIP389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+    pub fn multipath_descriptor(&self) -> Option<String> {
+        let (descriptor, keymap) = parse_multipath_descriptor(&self.descriptor).ok()?;
+        // a descriptor that can't be split doesn't have a change descriptor
+        split_multipath(descriptor.clone(), KeyMap::new()).ok()?;
+        Some(remove_checksum(descriptor.to_string_with_secret(&keymap)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::str::FromStr;
+
+    use crate::std::string::ToString;
+    use bdk_chain::{BlockId, ConfirmationTime};
+    use bitcoin::hashes::Hash;
+    use bitcoin::{transaction, BlockHash, Network, Transaction};
+
+    use super::*;
+    use crate::wallet::Wallet;
+
+    fn get_test_wallet(descriptor: &str, change_descriptor: &str, network: Network) -> Wallet {
+        let mut wallet = Wallet::new_no_persist(descriptor, change_descriptor, network).unwrap();
+        let transaction = Transaction {
+            input: vec![],
+            output: vec![],
+            version: transaction::Version::non_standard(0),
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+        };
+        wallet
+            .insert_checkpoint(BlockId {
+                height: 5001,
+                hash: BlockHash::all_zeros(),
+            })
+            .unwrap();
+        wallet
+            .insert_tx(
+                transaction,
+                ConfirmationTime::Confirmed {
+                    height: 5000,
+                    time: 0,
+                },
+            )
+            .unwrap();
+        wallet
+    }
+
+    #[test]
+    fn test_export_bip44() {
+        let descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/0/*)";
+        let change_descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/1/*)";
+
+        let wallet = get_test_wallet(descriptor, change_descriptor, Network::Bitcoin);
+        let export = FullyNodedExport::export_wallet(&wallet, "Test Label", true).unwrap();
+
+        assert_eq!(export.descriptor(), descriptor);
+        assert_eq!(export.change_descriptor(), Some(change_descriptor.into()));
+        assert_eq!(export.blockheight, 5000);
+        assert_eq!(export.label, "Test Label");
+    }
+
+    #[test]
+    #[should_panic(expected = "Incompatible change descriptor")]
+    fn test_export_no_change() {
+        // The wallet's change descriptor has no wildcard. It should be impossible to
+        // export, because exporting this kind of external descriptor normally implies the
+        // existence of a compatible internal descriptor
+
+        let descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/0/*)";
+        let change_descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/1/0)";
+
+        let wallet = get_test_wallet(descriptor, change_descriptor, Network::Bitcoin);
+        FullyNodedExport::export_wallet(&wallet, "Test Label", true).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Incompatible change descriptor")]
+    fn test_export_incompatible_change() {
+        // This wallet has a change descriptor, but the derivation path is not in the "standard"
+        // bip44/49/etc format
+
+        let descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/0/*)";
+        let change_descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/50'/0'/1/*)";
+
+        let wallet = get_test_wallet(descriptor, change_descriptor, Network::Bitcoin);
+        FullyNodedExport::export_wallet(&wallet, "Test Label", true).unwrap();
+    }
+
+    #[test]
+    fn test_export_single_descriptor() {
+        let descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/0/*)";
+
+        let wallet = Wallet::new_single_no_persist(descriptor, Network::Bitcoin).unwrap();
+        let export = FullyNodedExport::export_wallet(&wallet, "Test Label", false).unwrap();
+
+        assert_eq!(export.descriptor(), descriptor);
+        assert_eq!(export.blockheight, 0);
+    }
+
+    #[test]
+    fn test_export_multipath() {
+        let descriptor = "wpkh([c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/<0;1>/*)";
+
+        let wallet = Wallet::new_single_no_persist(descriptor, Network::Testnet).unwrap();
+        let export = FullyNodedExport::export_wallet(&wallet, "Test Label", false).unwrap();
+
+        assert_eq!(export.descriptor(), descriptor.replace("<0;1>", "0"));
+        assert_eq!(
+            export.change_descriptor(),
+            Some(descriptor.replace("<0;1>", "1"))
+        );
+        assert_eq!(export.multipath_descriptor(), Some(descriptor.into()));
+
+        // multipath descriptors can be imported too
+        let import_str = format!(
+            "{{\"descriptor\":\"{}\",\"blockheight\":0,\"label\":\"Test Label\"}}",
+            descriptor
+        );
+        let import = FullyNodedExport::from_str(&import_str).unwrap();
+        assert_eq!(import.descriptor(), export.descriptor());
+        assert_eq!(import.change_descriptor(), export.change_descriptor());
+        assert_eq!(import.multipath_descriptor(), Some(descriptor.into()));
+
+        // descriptors whose keys don't derive `/0/*` have no change descriptor
+        let descriptor = "wpkh([c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/10/*)";
+        let import_str = format!(
+            "{{\"descriptor\":\"{}\",\"blockheight\":0,\"label\":\"Test Label\"}}",
+            descriptor
+        );
+        let import = FullyNodedExport::from_str(&import_str).unwrap();
+        assert_eq!(import.descriptor(), descriptor);
+        assert_eq!(import.change_descriptor(), None);
+        assert_eq!(import.multipath_descriptor(), None);
+    }
+
+    #[test]
+    fn test_export_multi() {
+        let descriptor = "wsh(multi(2,\
+                                [73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*,\
+                                [f9f62194/48'/0'/0'/2']tpubDDp3ZSH1yCwusRppH7zgSxq2t1VEUyXSeEp8E5aFS8m43MknUjiF1bSLo3CGWAxbDyhF1XowA5ukPzyJZjznYk3kYi6oe7QxtX2euvKWsk4/0/*,\
+                                [c98b1535/48'/0'/0'/2']tpubDCDi5W4sP6zSnzJeowy8rQDVhBdRARaPhK1axABi8V1661wEPeanpEXj4ZLAUEoikVtoWcyK26TKKJSecSfeKxwHCcRrge9k1ybuiL71z4a/0/*\
+                          ))";
+        let change_descriptor = "wsh(multi(2,\
+                                       [73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/1/*,\
+                                       [f9f62194/48'/0'/0'/2']tpubDDp3ZSH1yCwusRppH7zgSxq2t1VEUyXSeEp8E5aFS8m43MknUjiF1bSLo3CGWAxbDyhF1XowA5ukPzyJZjznYk3kYi6oe7QxtX2euvKWsk4/1/*,\
+                                       [c98b1535/48'/0'/0'/2']tpubDCDi5W4sP6zSnzJeowy8rQDVhBdRARaPhK1axABi8V1661wEPeanpEXj4ZLAUEoikVtoWcyK26TKKJSecSfeKxwHCcRrge9k1ybuiL71z4a/1/*\
+                                 ))";
+
+        let wallet = get_test_wallet(descriptor, change_descriptor, Network::Testnet);
+        let export = FullyNodedExport::export_wallet(&wallet, "Test Label", true).unwrap();
+
+        assert_eq!(export.descriptor(), descriptor);
+        assert_eq!(export.change_descriptor(), Some(change_descriptor.into()));
+        assert_eq!(export.blockheight, 5000);
+        assert_eq!(export.label, "Test Label");
+    }
+
+    #[test]
+    fn test_export_tr() {
+        let descriptor = "tr([73c5da0a/86'/0'/0']tprv8fMn4hSKPRC1oaCPqxDb1JWtgkpeiQvZhsr8W2xuy3GEMkzoArcAWTfJxYb6Wj8XNNDWEjfYKK4wGQXh3ZUXhDF2NcnsALpWTeSwarJt7Vc/0/*)";
+        let change_descriptor = "tr([73c5da0a/86'/0'/0']tprv8fMn4hSKPRC1oaCPqxDb1JWtgkpeiQvZhsr8W2xuy3GEMkzoArcAWTfJxYb6Wj8XNNDWEjfYKK4wGQXh3ZUXhDF2NcnsALpWTeSwarJt7Vc/1/*)";
+        let wallet = get_test_wallet(descriptor, change_descriptor, Network::Testnet);
+        let export = FullyNodedExport::export_wallet(&wallet, "Test Label", true).unwrap();
+        assert_eq!(export.descriptor(), descriptor);
+        assert_eq!(export.change_descriptor(), Some(change_descriptor.into()));
+        assert_eq!(export.blockheight, 5000);
+        assert_eq!(export.label, "Test Label");
+    }
+
+    #[test]
+    fn test_export_to_json() {
+        let descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/0/*)";
+        let change_descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/1/*)";
+
+        let wallet = get_test_wallet(descriptor, change_descriptor, Network::Bitcoin);
+        let export = FullyNodedExport::export_wallet(&wallet, "Test Label", true).unwrap();
+
+        assert_eq!(export.to_string(), "{\"descriptor\":\"wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44\'/0\'/0\'/0/*)\",\"blockheight\":5000,\"label\":\"Test Label\"}");
+    }
+
+    #[test]
+    fn test_export_from_json() {
+        let descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/0/*)";
+        let change_descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/1/*)";
+
+        let import_str = "{\"descriptor\":\"wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44\'/0\'/0\'/0/*)\",\"blockheight\":5000,\"label\":\"Test Label\"}";
+        let export = FullyNodedExport::from_str(import_str).unwrap();
+
+        assert_eq!(export.descriptor(), descriptor);
+        assert_eq!(export.change_descriptor(), Some(change_descriptor.into()));
+        assert_eq!(export.blockheight, 5000);
+        assert_eq!(export.label, "Test Label");
+    }
+
+    #[test]
+    fn test_export_birthday() {
+        let descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/0/*)";
+        let change_descriptor = "wpkh(xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/44'/0'/0'/1/*)";
+        let mut wallet = get_test_wallet(descriptor, change_descriptor, Network::Bitcoin);
+
+        // the birthday is the lowest confirmation height, not the one of the lowest txid
+        let first_txid = Transaction {
+            input: vec![],
+            output: vec![],
+            version: transaction::Version::non_standard(0),
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+        }
+        .txid();
+        let transaction = (1..)
+            .map(|version| Transaction {
+                input: vec![],
+                output: vec![],
+                version: transaction::Version::non_standard(version),
+                lock_time: bitcoin::absolute::LockTime::ZERO,
+            })
+            .find(|transaction| transaction.txid() < first_txid)
+            .unwrap();
+        wallet
+            .insert_tx(
+                transaction,
+                ConfirmationTime::Confirmed {
+                    height: 5001,
+                    time: 0,
+                },
+            )
+            .unwrap();
+
+        let export = FullyNodedExport::export_wallet(&wallet, "Test Label", true).unwrap();
+        assert_eq!(export.blockheight, 5000);
+    }
+
+    const WPKH: &str = "wpkh([c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/0/*)";
+    const SORTEDMULTI: &str = "wsh(sortedmulti(2,\
+                                 [73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*,\
+                                 [f9f62194/48'/0'/0'/2']tpubDDp3ZSH1yCwusRppH7zgSxq2t1VEUyXSeEp8E5aFS8m43MknUjiF1bSLo3CGWAxbDyhF1XowA5ukPzyJZjznYk3kYi6oe7QxtX2euvKWsk4/0/*))";
+    const TR: &str = "tr([73c5da0a/86'/0'/0']tprv8fMn4hSKPRC1oaCPqxDb1JWtgkpeiQvZhsr8W2xuy3GEMkzoArcAWTfJxYb6Wj8XNNDWEjfYKK4wGQXh3ZUXhDF2NcnsALpWTeSwarJt7Vc/0/*)";
+
+    fn get_test_wallet_with_change(descriptor: &str) -> Wallet {
+        get_test_wallet(
+            descriptor,
+            &descriptor.replace("/0/*", "/1/*"),
+            Network::Testnet,
+        )
+    }
+
+    /// Check that a wallet created from `import` has the same public descriptors as `wallet`
+    fn assert_same_descriptors(import: &WalletImport, wallet: &Wallet) {
+        let imported = Wallet::new_no_persist(
+            &import.descriptor,
+            import
+                .change_descriptor
+                .as_ref()
+                .expect("change descriptor"),
+            wallet.network(),
+        )
+        .unwrap();
+        for keychain in [KeychainKind::External, KeychainKind::Internal] {
+            assert_eq!(
+                imported.public_descriptor(keychain),
+                wallet.public_descriptor(keychain)
+            );
+        }
+    }
+
+    #[test]
+    fn test_electrum_round_trip() {
+        let mut wallet = get_test_wallet_with_change(WPKH);
+        let txid = wallet.transactions().next().unwrap().tx_node.txid;
+        let address = wallet.peek_address(KeychainKind::External, 0);
+        wallet.set_label(LabelRef::Tx(txid), Label::new("salary"));
+        wallet.set_label(
+            LabelRef::Addr(address.script_pubkey()),
+            Label::new("donations"),
+        );
+
+        let file = electrum::export_wallet(&wallet).unwrap();
+        assert!(file.contains(r#""wallet_type":"standard""#));
+        assert!(file.contains(r#""xpub":"vpub"#));
+        let import = electrum::import_wallet(&file, Network::Testnet).unwrap();
+        assert_same_descriptors(&import, &wallet);
+        assert_eq!(import.blockheight, None);
+        assert_eq!(import.labels.len(), 2);
+        assert!(import
+            .labels
+            .contains(&(LabelRef::Tx(txid), Label::new("salary"))));
+        assert!(import.labels.contains(&(
+            LabelRef::Addr(address.script_pubkey()),
+            Label::new("donations")
+        )));
+
+        // sortedmulti wallets have one keystore for each cosigner
+        let wallet = get_test_wallet_with_change(SORTEDMULTI);
+        let file = electrum::export_wallet(&wallet).unwrap();
+        assert!(file.contains(r#""wallet_type":"2of2""#));
+        assert!(file.contains(r#""x2/":{"#));
+        assert!(file.contains(r#""xpub":"Vpub"#));
+        let import = electrum::import_wallet(&file, Network::Testnet).unwrap();
+        assert_same_descriptors(&import, &wallet);
+
+        let wallet = get_test_wallet_with_change(TR);
+        assert!(matches!(
+            electrum::export_wallet(&wallet),
+            Err(WalletFileError::Unsupported(_))
+        ));
+        assert!(matches!(
+            electrum::import_wallet(r#"{"wallet_type":"imported"}"#, Network::Testnet),
+            Err(WalletFileError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_coldcard_round_trip() {
+        for (descriptor, address_type) in [
+            (WPKH, bitcoin::AddressType::P2wpkh),
+            (TR, bitcoin::AddressType::P2tr),
+        ] {
+            let wallet = get_test_wallet_with_change(descriptor);
+            let file = coldcard::export_wallet(&wallet).unwrap();
+            assert!(file.contains(r#""chain":"XTN""#));
+            let import = coldcard::import_wallet(&file, address_type).unwrap();
+            assert_same_descriptors(&import, &wallet);
+        }
+
+        // only the exported account type can be imported
+        let wallet = get_test_wallet_with_change(WPKH);
+        let file = coldcard::export_wallet(&wallet).unwrap();
+        assert!(matches!(
+            coldcard::import_wallet(&file, bitcoin::AddressType::P2tr),
+            Err(WalletFileError::InvalidField("bip86"))
+        ));
+        let wallet = get_test_wallet_with_change(SORTEDMULTI);
+        assert!(matches!(
+            coldcard::export_wallet(&wallet),
+            Err(WalletFileError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_specter_round_trip() {
+        let wallet = get_test_wallet_with_change(SORTEDMULTI);
+        let file = specter::export_wallet(&wallet, "Test Label", true).unwrap();
+        assert!(file.contains(r#""label":"Test Label""#));
+        assert!(file.contains(r#"{"type":"other","label":"f9f62194"}"#));
+
+        let import = specter::import_wallet(&file).unwrap();
+        assert_same_descriptors(&import, &wallet);
+        assert_eq!(import.blockheight, Some(5000));
+
+        let file = specter::export_wallet(&wallet, "Test Label", false).unwrap();
+        assert_eq!(specter::import_wallet(&file).unwrap().blockheight, None);
+    }
+
+    #[test]
+    fn test_sparrow_round_trip() {
+        let wallet = get_test_wallet_with_change(WPKH);
+        let file = sparrow::export_wallet(&wallet).unwrap();
+        assert_eq!(file.lines().filter(|l| l.starts_with("wpkh(")).count(), 3);
+        let import = sparrow::import_wallet(&file).unwrap();
+        assert_same_descriptors(&import, &wallet);
+
+        // separate external and internal descriptors
+        let file = file.lines().skip(3).collect::<Vec<_>>().join("\n");
+        let import = sparrow::import_wallet(&file).unwrap();
+        assert_same_descriptors(&import, &wallet);
+
+        // the checksums are verified
+        let tampered = file.replacen("/0/*)#", "/2/*)#", 1);
+        assert!(matches!(
+            sparrow::import_wallet(&tampered),
+            Err(WalletFileError::InvalidField("descriptor"))
+        ));
+        let mismatch = format!("{}\n{}", WPKH, WPKH.replace("/0/*", "/2/*"));
+        assert!(matches!(
+            sparrow::import_wallet(&mismatch),
+            Err(WalletFileError::IncompatibleDescriptors)
+        ));
+    }
+}
@@ -0,0 +1,92 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Sparrow output descriptor files
+//!
+//! Sparrow exports the descriptors of a wallet as a text file, with a [BIP389] multipath
+//! descriptor followed by the external and internal descriptors, each preceded by a comment.
+//! Sparrow exports labels separately in the [BIP329] format, which can be imported with
+//! [`Wallet::import_labels`].
+//!
+//! ## Example
+//!
+//! ```
+//! # use bitcoin::Network;
+//! # use bdk_wallet::wallet::export::sparrow;
+//! # use bdk_wallet::Wallet;
+//! let file = "# Receive and change descriptor (BIP389):
+//! wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/<0;1>/*)
+//! ";
+//! let import = sparrow::import_wallet(file)?;
+//! let wallet = Wallet::new_no_persist(
+//!     &import.descriptor,
+//!     &import.change_descriptor.expect("change descriptor"),
+//!     Network::Testnet,
+//! )?;
+//!
+//! let exported = sparrow::export_wallet(&wallet)?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BIP329]: https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki
+//! [BIP389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+//! [`Wallet::import_labels`]: crate::Wallet::import_labels
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::{parse_descriptor, public_descriptor, WalletFileError, WalletImport};
+use crate::types::KeychainKind;
+use crate::wallet::Wallet;
+
+/// Import a Sparrow output descriptor file
+///
+/// The first descriptor of the file is imported: if it is not a multipath descriptor, the
+/// following one, if any, must be its change descriptor.
+pub fn import_wallet(file: &str) -> Result<WalletImport, WalletFileError> {
+    let descriptors = file
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect::<Vec<_>>();
+    let first = descriptors
+        .first()
+        .ok_or(WalletFileError::InvalidField("descriptor"))?;
+
+    let import = WalletImport::from_descriptor(first)?;
+    if parse_descriptor(first)?.0.is_multipath() {
+        return Ok(import);
+    }
+    match descriptors.get(1) {
+        Some(second) => {
+            let change = WalletImport::from_descriptor(second)?;
+            if Some(change.descriptor) != import.change_descriptor {
+                return Err(WalletFileError::IncompatibleDescriptors);
+            }
+            Ok(import)
+        }
+        None => Ok(import),
+    }
+}
+
+/// Export a wallet as a Sparrow output descriptor file
+///
+/// The change descriptor of the wallet must only differ from its external descriptor by using
+/// `/1/*` instead of `/0/*`.
+pub fn export_wallet(wallet: &Wallet) -> Result<String, WalletFileError> {
+    let (descriptor, multipath) = public_descriptor(wallet)?;
+
+    Ok(format!(
+        "# Receive and change descriptor (BIP389):\n{}\n\n# Receive descriptor (Bitcoin Core):\n{}\n\n# Change descriptor (Bitcoin Core):\n{}\n",
+        multipath,
+        descriptor,
+        wallet.public_descriptor(KeychainKind::Internal),
+    ))
+}
@@ -0,0 +1,113 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Specter wallet backups
+//!
+//! Specter backs up a wallet as a JSON file with its label, its birthday height, its external
+//! descriptor and the devices holding its keys.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bitcoin::Network;
+//! # use bdk_wallet::wallet::export::specter;
+//! # use bdk_wallet::Wallet;
+//! let file = r#"{
+//!     "label": "Savings",
+//!     "blockheight": 2100000,
+//!     "descriptor": "wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/0/*)",
+//!     "devices": [{"type": "coldcard", "label": "Coldcard"}]
+//! }"#;
+//! let import = specter::import_wallet(file)?;
+//! assert_eq!(import.blockheight, Some(2100000));
+//! let wallet = Wallet::new_no_persist(
+//!     &import.descriptor,
+//!     &import.change_descriptor.expect("change descriptor"),
+//!     Network::Testnet,
+//! )?;
+//!
+//! let exported = specter::export_wallet(&wallet, "Savings", true)?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use miniscript::ForEachKey;
+use serde::{Deserialize, Serialize};
+
+use super::{public_descriptor, wallet_birthday, WalletFileError, WalletImport};
+use crate::wallet::Wallet;
+
+/// A Specter wallet backup
+#[derive(Debug, Serialize, Deserialize)]
+struct SpecterBackup {
+    label: String,
+    #[serde(default)]
+    blockheight: u32,
+    descriptor: String,
+    #[serde(default)]
+    devices: Vec<Device>,
+}
+
+/// A device holding a key of a Specter wallet
+#[derive(Debug, Serialize, Deserialize)]
+struct Device {
+    #[serde(rename = "type")]
+    type_: String,
+    label: String,
+}
+
+/// Import a Specter wallet backup
+pub fn import_wallet(file: &str) -> Result<WalletImport, WalletFileError> {
+    let backup: SpecterBackup = serde_json::from_str(file)?;
+    let mut import = WalletImport::from_descriptor(&backup.descriptor)?;
+    import.blockheight = Some(backup.blockheight).filter(|height| *height > 0);
+    Ok(import)
+}
+
+/// Export a wallet as a Specter wallet backup
+///
+/// The change descriptor of the wallet must only differ from its external descriptor by using
+/// `/1/*` instead of `/0/*`. Each key is listed as a device labeled with its fingerprint.
+///
+/// If `include_blockheight` is `true`, the lowest confirmation height of the transactions of the
+/// wallet is used as its birthday, like [`FullyNodedExport::export_wallet`].
+///
+/// [`FullyNodedExport::export_wallet`]: super::FullyNodedExport::export_wallet
+pub fn export_wallet(
+    wallet: &Wallet,
+    label: &str,
+    include_blockheight: bool,
+) -> Result<String, WalletFileError> {
+    let (descriptor, _) = public_descriptor(wallet)?;
+
+    let mut devices = Vec::new();
+    descriptor.for_each_key(|key| {
+        devices.push(Device {
+            type_: "other".to_string(),
+            label: key.master_fingerprint().to_string(),
+        });
+        true
+    });
+
+    let backup = SpecterBackup {
+        label: label.to_string(),
+        blockheight: if include_blockheight {
+            wallet_birthday(wallet)
+        } else {
+            0
+        },
+        descriptor: descriptor.to_string(),
+        devices,
+    };
+
+    Ok(serde_json::to_string(&backup)?)
+}
@@ -0,0 +1,260 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Wallet labels
+//!
+//! This module implements the [BIP329] label export format. Labels are attached to transactions,
+//! addresses, public keys, inputs, outputs and extended public keys with [`Wallet::set_label`],
+//! and are persisted together with the rest of the wallet's [`ChangeSet`].
+//!
+//! ## Example
+//!
+//! ```
+//! # use bitcoin::*;
+//! # use bdk_wallet::*;
+//! # use bdk_wallet::wallet::labels::{Label, LabelRef};
+//! # let mut wallet = doctest_wallet!();
+//! let address = wallet.peek_address(KeychainKind::External, 0);
+//! wallet.set_label(LabelRef::Addr(address.script_pubkey()), Label::new("donations"));
+//!
+//! let exported = wallet.export_labels();
+//! assert_eq!(
+//!     exported,
+//!     format!(r#"{{"type":"addr","ref":"{}","label":"donations"}}"#, address.address)
+//! );
+//!
+//! // labels can then be imported in another wallet
+//! let mut other_wallet = doctest_wallet!();
+//! assert_eq!(other_wallet.import_labels(&exported)?, 1);
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BIP329]: https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki
+//! [`Wallet::set_label`]: crate::Wallet::set_label
+//! [`ChangeSet`]: crate::wallet::ChangeSet
+
+use alloc::string::{String, ToString};
+use core::fmt;
+use core::str::FromStr;
+
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::bip32::Xpub;
+use bitcoin::{Address, Network, OutPoint, PublicKey, Txid};
+use serde::{Deserialize, Serialize};
+
+pub use bdk_persist::labels::{ChangeSet, Label, LabelRef};
+
+/// A single line of a BIP329 export.
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    #[serde(rename = "type")]
+    type_: String,
+    #[serde(rename = "ref")]
+    ref_: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spendable: Option<bool>,
+}
+
+/// Errors that can be returned when importing BIP329 labels.
+#[derive(Debug)]
+pub enum Bip329Error {
+    /// The line is not a valid JSON record.
+    Json {
+        /// Line number of the invalid record, starting from 1.
+        line: usize,
+        /// The JSON error.
+        error: serde_json::Error,
+    },
+    /// The `ref` field of the record is not valid for its `type`.
+    InvalidRef {
+        /// Line number of the invalid record, starting from 1.
+        line: usize,
+        /// The invalid reference.
+        reference: String,
+    },
+    /// The address of an `addr` record is not valid for the wallet's network.
+    NetworkMismatch {
+        /// Line number of the invalid record, starting from 1.
+        line: usize,
+        /// The wallet's network.
+        expected: Network,
+    },
+}
+
+impl fmt::Display for Bip329Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json { line, error } => write!(f, "invalid record at line {}: {}", line, error),
+            Self::InvalidRef { line, reference } => {
+                write!(f, "invalid reference `{}` at line {}", reference, line)
+            }
+            Self::NetworkMismatch { line, expected } => write!(
+                f,
+                "address at line {} is not valid for network {}",
+                line, expected
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Bip329Error {}
+
+/// Serialize a label as a BIP329 JSON line.
+///
+/// Returns `None` if the label is attached to a script that can't be represented as an address.
+pub(crate) fn to_bip329_line(
+    label_ref: &LabelRef,
+    label: &Label,
+    network: Network,
+) -> Option<String> {
+    let (type_, ref_) = match label_ref {
+        LabelRef::Tx(txid) => ("tx", txid.to_string()),
+        LabelRef::Addr(spk) => ("addr", Address::from_script(spk, network).ok()?.to_string()),
+        LabelRef::PubKey(pk) => ("pubkey", pk.to_string()),
+        LabelRef::Input(op) => ("input", op.to_string()),
+        LabelRef::Output(op) => ("output", op.to_string()),
+        LabelRef::Xpub(xpub) => ("xpub", xpub.to_string()),
+    };
+    let record = Record {
+        type_: type_.to_string(),
+        ref_,
+        label: Some(label.label.clone()).filter(|l| !l.is_empty()),
+        origin: label.origin.clone(),
+        spendable: label.spendable,
+    };
+    Some(serde_json::to_string(&record).expect("record must serialize"))
+}
+
+/// Parse a BIP329 JSON line.
+///
+/// `line` is the line number used in errors. Returns `Ok(None)` for record types that are not
+/// known, as importers are expected to ignore them.
+pub(crate) fn from_bip329_line(
+    s: &str,
+    line: usize,
+    network: Network,
+) -> Result<Option<(LabelRef, Label)>, Bip329Error> {
+    let record: Record =
+        serde_json::from_str(s).map_err(|error| Bip329Error::Json { line, error })?;
+    let invalid_ref = || Bip329Error::InvalidRef {
+        line,
+        reference: record.ref_.clone(),
+    };
+    let label_ref = match record.type_.as_str() {
+        "tx" => LabelRef::Tx(Txid::from_str(&record.ref_).map_err(|_| invalid_ref())?),
+        "addr" => {
+            let address = Address::<NetworkUnchecked>::from_str(&record.ref_)
+                .map_err(|_| invalid_ref())?
+                .require_network(network)
+                .map_err(|_| Bip329Error::NetworkMismatch {
+                    line,
+                    expected: network,
+                })?;
+            LabelRef::Addr(address.script_pubkey())
+        }
+        "pubkey" => LabelRef::PubKey(PublicKey::from_str(&record.ref_).map_err(|_| invalid_ref())?),
+        "input" => LabelRef::Input(OutPoint::from_str(&record.ref_).map_err(|_| invalid_ref())?),
+        "output" => LabelRef::Output(OutPoint::from_str(&record.ref_).map_err(|_| invalid_ref())?),
+        "xpub" => LabelRef::Xpub(Xpub::from_str(&record.ref_).map_err(|_| invalid_ref())?),
+        _ => return Ok(None),
+    };
+    let label = Label {
+        label: record.label.unwrap_or_default(),
+        origin: record.origin,
+        spendable: record.spendable,
+    };
+    Ok(Some((label_ref, label)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_bip329_vectors() {
+        let lines = [
+            r#"{"type":"tx","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd","label":"Transaction","origin":"wpkh([d34db33f/84'/0'/0'])"}"#,
+            r#"{"type":"addr","ref":"bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c","label":"Address"}"#,
+            r#"{"type":"pubkey","ref":"0283409659355b6d1cc3c32decd5d561abaac86c37a353b52895a5e6c196d6f448","label":"Public Key"}"#,
+            r#"{"type":"input","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:0","label":"Input"}"#,
+            r#"{"type":"output","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:1","label":"Output","spendable":false}"#,
+            r#"{"type":"xpub","ref":"xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8","label":"Extended Public Key"}"#,
+        ];
+
+        for (i, line) in lines.iter().enumerate() {
+            let (label_ref, label) = from_bip329_line(line, i + 1, Network::Bitcoin)
+                .unwrap()
+                .expect("known record type");
+            assert_eq!(
+                to_bip329_line(&label_ref, &label, Network::Bitcoin).as_deref(),
+                Some(*line)
+            );
+        }
+    }
+
+    #[test]
+    fn test_bip329_unknown_type_is_ignored() {
+        let line = r#"{"type":"unknown","ref":"something","label":"Label"}"#;
+        assert!(from_bip329_line(line, 1, Network::Bitcoin)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_bip329_errors() {
+        assert!(matches!(
+            from_bip329_line("not json", 1, Network::Bitcoin),
+            Err(Bip329Error::Json { line: 1, .. })
+        ));
+        assert!(matches!(
+            from_bip329_line(
+                r#"{"type":"tx","ref":"abc","label":"x"}"#,
+                2,
+                Network::Bitcoin
+            ),
+            Err(Bip329Error::InvalidRef { line: 2, .. })
+        ));
+        assert!(matches!(
+            from_bip329_line(
+                r#"{"type":"addr","ref":"bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c","label":"x"}"#,
+                3,
+                Network::Testnet
+            ),
+            Err(Bip329Error::NetworkMismatch {
+                line: 3,
+                expected: Network::Testnet
+            })
+        ));
+    }
+
+    #[test]
+    fn test_bip329_empty_label_is_omitted() {
+        let label_ref = LabelRef::Output(OutPoint::new(Txid::all_zeros(), 0));
+        let label = Label {
+            spendable: Some(false),
+            ..Default::default()
+        };
+        let line = to_bip329_line(&label_ref, &label, Network::Bitcoin).unwrap();
+        assert_eq!(
+            line,
+            r#"{"type":"output","ref":"0000000000000000000000000000000000000000000000000000000000000000:0","spendable":false}"#
+        );
+        assert_eq!(
+            from_bip329_line(&line, 1, Network::Bitcoin).unwrap(),
+            Some((label_ref, label))
+        );
+    }
+}
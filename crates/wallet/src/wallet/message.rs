@@ -0,0 +1,352 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Message signing
+//!
+//! This module implements the [BIP322] generic message signing format, which can prove the
+//! ownership of any address, and the legacy `signmessage` format for P2PKH addresses.
+//! Messages are signed with [`Wallet::sign_message`] and verified with [`verify_message`].
+//!
+//! BIP322 signatures are produced by signing a virtual transaction spending the address with the
+//! wallet's signers, so external signers are supported too. The legacy format needs the private
+//! key of the address to be known by the wallet.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bitcoin::*;
+//! # use bdk_wallet::*;
+//! # use bdk_wallet::wallet::message::{verify_message, MessageSignatureFormat};
+//! # let mut wallet = doctest_wallet!();
+//! let address = wallet.peek_address(KeychainKind::External, 0).address;
+//! let signature =
+//!     wallet.sign_message("Hello World", &address, MessageSignatureFormat::Simple)?;
+//!
+//! assert!(verify_message(&address, "Hello World", &signature)?);
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BIP322]: https://github.com/bitcoin/bips/blob/master/bip-0322.mediawiki
+//! [`Wallet::sign_message`]: crate::Wallet::sign_message
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use bitcoin::base64::prelude::{Engine as _, BASE64_STANDARD};
+use bitcoin::bip32::ChildNumber;
+use bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::opcodes::all::OP_RETURN;
+use bitcoin::opcodes::OP_0;
+use bitcoin::script::Builder;
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::sign_message::{signed_msg_hash, MessageSignature};
+use bitcoin::{
+    absolute, sighash, transaction, Address, AddressType, Amount, OutPoint, PrivateKey, ScriptBuf,
+    Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use miniscript::descriptor::{DescriptorSecretKey, Wildcard};
+use miniscript::interpreter::{Interpreter, KeySigPair, SatisfiedConstraint};
+
+use super::utils::SecpCtx;
+
+use crate::signer::SignerError;
+
+/// The format of a message signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageSignatureFormat {
+    /// The legacy `signmessage` format, only supported by P2PKH addresses.
+    Legacy,
+    /// The BIP322 simple format, the witness of the signing transaction. Only supported by
+    /// native segwit addresses.
+    Simple,
+    /// The BIP322 full format, the whole signing transaction.
+    Full,
+}
+
+/// Errors that can be returned when signing or verifying a message.
+#[derive(Debug)]
+pub enum MessageError {
+    /// The address doesn't support the signature format.
+    UnsupportedAddress,
+    /// The address doesn't belong to the wallet.
+    NotMine,
+    /// The wallet doesn't have the private key of the address, which is required by the legacy
+    /// format.
+    MissingKey,
+    /// The signers couldn't produce a complete signature.
+    NotFinalized,
+    /// Error while signing the message.
+    Signer(SignerError),
+    /// The signature is not a valid encoding of any format.
+    InvalidEncoding,
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedAddress => {
+                write!(f, "the signature format is not supported by the address")
+            }
+            Self::NotMine => write!(f, "the address doesn't belong to the wallet"),
+            Self::MissingKey => write!(f, "the private key of the address is missing"),
+            Self::NotFinalized => write!(f, "the signature is not complete"),
+            Self::Signer(e) => write!(f, "failed to sign the message: {}", e),
+            Self::InvalidEncoding => write!(f, "invalid signature encoding"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MessageError {}
+
+impl From<SignerError> for MessageError {
+    fn from(e: SignerError) -> Self {
+        MessageError::Signer(e)
+    }
+}
+
+/// Verify that `signature` is a valid signature of `message` by `address`.
+///
+/// The format of the signature is detected automatically. BIP322 signatures are verified with
+/// the miniscript interpreter, so scripts that are not miniscript can't be verified. Returns an
+/// error if the signature can't be decoded, and `Ok(false)` if it's not valid.
+pub fn verify_message(
+    address: &Address,
+    message: &str,
+    signature: &str,
+) -> Result<bool, MessageError> {
+    let bytes = BASE64_STANDARD
+        .decode(signature)
+        .map_err(|_| MessageError::InvalidEncoding)?;
+
+    if address.address_type() == Some(AddressType::P2pkh) {
+        if let Ok(signature) = MessageSignature::from_slice(&bytes) {
+            let secp = Secp256k1::verification_only();
+            return Ok(signature
+                .is_signed_by_address(&secp, address, signed_msg_hash(message))
+                .unwrap_or(false));
+        }
+    }
+
+    let script_pubkey = address.script_pubkey();
+    let to_spend = to_spend(&script_pubkey, message);
+    let to_sign = match deserialize::<Transaction>(&bytes) {
+        Ok(tx) => tx,
+        Err(_) => {
+            let witness =
+                deserialize::<Witness>(&bytes).map_err(|_| MessageError::InvalidEncoding)?;
+            to_sign(to_spend.txid(), ScriptBuf::new(), witness)
+        }
+    };
+    if to_sign.input.len() != 1
+        || to_sign.input[0].previous_output != OutPoint::new(to_spend.txid(), 0)
+        || to_sign.output != to_sign_output()
+    {
+        return Ok(false);
+    }
+
+    Ok(verify_input(&to_sign, 0, &to_spend.output).is_some())
+}
+
+/// Verify the satisfaction of the input at `input_index` of `tx` with the miniscript
+/// interpreter, `prevouts` being the outputs spent by all the inputs of `tx`.
+///
+/// Returns the signatures of the input, or `None` if the input is not valid.
+pub(crate) fn verify_input(
+    tx: &Transaction,
+    input_index: usize,
+    prevouts: &[TxOut],
+) -> Option<Vec<KeySigPair>> {
+    let txin = tx.input.get(input_index)?;
+    let interpreter = Interpreter::from_txdata(
+        &prevouts.get(input_index)?.script_pubkey,
+        &txin.script_sig,
+        &txin.witness,
+        txin.sequence,
+        tx.lock_time,
+    )
+    .ok()?;
+    let secp = Secp256k1::verification_only();
+    let prevouts = sighash::Prevouts::All(prevouts);
+    let mut signatures = Vec::new();
+    let mut satisfied = false;
+    for constraint in interpreter.iter(&secp, tx, input_index, &prevouts) {
+        match constraint.ok()? {
+            SatisfiedConstraint::PublicKey { key_sig }
+            | SatisfiedConstraint::PublicKeyHash { key_sig, .. } => signatures.push(key_sig),
+            _ => {}
+        }
+        satisfied = true;
+    }
+    Some(signatures).filter(|_| satisfied)
+}
+
+/// Sign `message` with `key` in the legacy `signmessage` format.
+pub(crate) fn sign_legacy(key: &PrivateKey, message: &str, secp: &SecpCtx) -> String {
+    let msg = secp256k1::Message::from_digest(signed_msg_hash(message).to_byte_array());
+    let signature = secp.sign_ecdsa_recoverable(&msg, &key.inner);
+    MessageSignature::new(signature, key.compressed).to_base64()
+}
+
+/// Derive the private key of `secret` at the derivation `index`.
+///
+/// Returns `None` for multipath keys, which are split into single path keys by the wallet.
+pub(crate) fn derive_private_key(
+    secret: &DescriptorSecretKey,
+    index: u32,
+    secp: &SecpCtx,
+) -> Option<PrivateKey> {
+    match secret {
+        DescriptorSecretKey::Single(single) => Some(single.key),
+        DescriptorSecretKey::XPrv(xprv) => {
+            let path = match xprv.wildcard {
+                Wildcard::None => xprv.derivation_path.clone(),
+                Wildcard::Unhardened => xprv
+                    .derivation_path
+                    .child(ChildNumber::from_normal_idx(index).ok()?),
+                Wildcard::Hardened => xprv
+                    .derivation_path
+                    .child(ChildNumber::from_hardened_idx(index).ok()?),
+            };
+            let derived = xprv.xkey.derive_priv(secp, &path).ok()?;
+            Some(derived.to_priv())
+        }
+        DescriptorSecretKey::MultiXPrv(_) => None,
+    }
+}
+
+/// The BIP322 tagged hash of `message`.
+pub(crate) fn message_hash(message: &str) -> sha256::Hash {
+    let tag = sha256::Hash::hash(b"BIP0322-signed-message");
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    engine.input(message.as_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+/// The BIP322 virtual transaction with an output locked by `script_pubkey`, spent by the
+/// signature of `message`.
+pub(crate) fn to_spend(script_pubkey: &ScriptBuf, message: &str) -> Transaction {
+    Transaction {
+        version: transaction::Version(0),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0xFFFFFFFF),
+            script_sig: Builder::new()
+                .push_opcode(OP_0)
+                .push_slice(message_hash(message).to_byte_array())
+                .into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+/// The BIP322 virtual transaction spending the output of the `to_spend` transaction.
+pub(crate) fn to_sign(to_spend: Txid, script_sig: ScriptBuf, witness: Witness) -> Transaction {
+    Transaction {
+        version: transaction::Version(0),
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend, 0),
+            script_sig,
+            sequence: Sequence::ZERO,
+            witness,
+        }],
+        output: to_sign_output(),
+    }
+}
+
+fn to_sign_output() -> Vec<TxOut> {
+    vec![TxOut {
+        value: Amount::ZERO,
+        script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+    }]
+}
+
+/// Encode the BIP322 signature of the signed `to_sign` transaction in `format`.
+pub(crate) fn encode_signature(to_sign: &Transaction, format: MessageSignatureFormat) -> String {
+    match format {
+        MessageSignatureFormat::Simple => {
+            BASE64_STANDARD.encode(serialize(&to_sign.input[0].witness))
+        }
+        _ => BASE64_STANDARD.encode(serialize(to_sign)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::ToString;
+    use core::str::FromStr;
+
+    #[test]
+    fn test_message_hash() {
+        assert_eq!(
+            message_hash("").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            message_hash("Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+    }
+
+    #[test]
+    fn test_bip322_vectors() {
+        let address = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l")
+            .unwrap()
+            .assume_checked();
+        let to_spend = to_spend(&address.script_pubkey(), "");
+        assert_eq!(
+            to_spend.txid().to_string(),
+            "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7"
+        );
+        assert_eq!(
+            to_sign(to_spend.txid(), ScriptBuf::new(), Witness::new())
+                .txid()
+                .to_string(),
+            "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6"
+        );
+
+        let signature = "AkcwRAIgM2gBAQqvZX15ZiysmKmQpDrG83avLIT492QBzLnQIxYCIBaTpOaD20qRlEylyxFSeEA2ba9YOixpX8z46TSDtS40ASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+        assert!(verify_message(&address, "", signature).unwrap());
+        assert!(!verify_message(&address, "Hello World", signature).unwrap());
+
+        let signature = "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+        assert!(verify_message(&address, "Hello World", signature).unwrap());
+
+        let address =
+            Address::from_str("bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3")
+                .unwrap()
+                .assume_checked();
+        let signature = "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ==";
+        assert!(verify_message(&address, "Hello World", signature).unwrap());
+        assert!(!verify_message(&address, "", signature).unwrap());
+    }
+
+    #[test]
+    fn test_verify_invalid_encoding() {
+        let address = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l")
+            .unwrap()
+            .assume_checked();
+        assert!(matches!(
+            verify_message(&address, "", "not base64!"),
+            Err(MessageError::InvalidEncoding)
+        ));
+    }
+}
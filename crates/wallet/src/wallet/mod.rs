@@ -12,7 +12,7 @@
 //! Wallet
 //!
 //! This module defines the [`Wallet`].
-use crate::collections::{BTreeMap, HashMap};
+use crate::collections::{BTreeMap, BTreeSet, HashMap};
 use alloc::{
     boxed::Box,
     string::{String, ToString},
@@ -21,36 +21,50 @@ use alloc::{
 };
 pub use bdk_chain::keychain::Balance;
 use bdk_chain::{
-    indexed_tx_graph,
+    indexed_tx_graph::{self, Indexer},
     keychain::KeychainTxOutIndex,
     local_chain::{
         self, ApplyHeaderError, CannotConnectError, CheckPoint, CheckPointIter, LocalChain,
     },
     spk_client::{FullScanRequest, FullScanResult, SyncRequest, SyncResult},
     tx_graph::{CanonicalTx, TxGraph},
-    Append, BlockId, ChainPosition, ConfirmationTime, ConfirmationTimeHeightAnchor, FullTxOut,
-    IndexedTxGraph,
+    Anchor, Append, BlockId, ChainPosition, ConfirmationTime, ConfirmationTimeHeightAnchor,
+    FullTxOut, IndexedTxGraph,
 };
-use bdk_persist::{Persist, PersistBackend};
-use bitcoin::secp256k1::{All, Secp256k1};
+pub use bdk_persist::utxo_locks::UtxoLock;
+use bdk_persist::{utxo_locks, Persist, PersistBackend};
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::consensus::encode::{serialize, VarInt};
+use bitcoin::key::TweakedPublicKey;
+use bitcoin::secp256k1::{All, Secp256k1, SecretKey};
 use bitcoin::sighash::{EcdsaSighashType, TapSighashType};
 use bitcoin::{
     absolute, psbt, Address, Block, FeeRate, Network, OutPoint, Script, ScriptBuf, Sequence,
-    Transaction, TxOut, Txid, Witness,
+    Transaction, TxIn, TxOut, Txid, Weight, Witness,
 };
-use bitcoin::{consensus::encode::serialize, transaction, BlockHash, Psbt};
 use bitcoin::{constants::genesis_block, Amount};
+use bitcoin::{transaction, BlockHash, Psbt};
 use core::fmt;
 use core::ops::Deref;
 use descriptor::error::Error as DescriptorError;
+use miniscript::descriptor::{Descriptor, KeyMap, ShInner};
 use miniscript::psbt::{PsbtExt, PsbtInputExt, PsbtInputSatisfier};
 
 use bdk_chain::tx_graph::CalculateFeeError;
 
 pub mod coin_selection;
+pub mod consolidation;
+pub mod event;
 pub mod export;
+pub mod labels;
+pub mod message;
+pub mod payjoin;
+pub mod reserves;
 pub mod signer;
+pub mod silent_payments;
+pub mod sweep;
 pub mod tx_builder;
+pub mod uri;
 pub(crate) mod utils;
 
 pub mod error;
@@ -58,11 +72,19 @@ pub mod error;
 pub use utils::IsDust;
 
 use coin_selection::DefaultCoinSelectionAlgorithm;
+use consolidation::{Consolidation, ConsolidationError, ConsolidationParams};
+use event::{Snapshot, WalletEvent};
+use labels::{Bip329Error, Label, LabelRef};
+use message::{MessageError, MessageSignatureFormat};
+use payjoin::{OriginalPsbtError, PayjoinParams, ProposalError};
+use reserves::ProofError;
 use signer::{SignOptions, SignerOrdering, SignersContainer, TransactionSigner};
+use silent_payments::SilentPaymentError;
+use sweep::{Sweep, SweepError};
 use tx_builder::{FeePolicy, TxBuilder, TxParams};
 use utils::{check_nsequence_rbf, After, Older, SecpCtx};
 
-use crate::descriptor::policy::BuildSatisfaction;
+use crate::descriptor::policy::{BuildSatisfaction, Condition};
 use crate::descriptor::{
     self, calc_checksum, into_wallet_descriptor_checked, DerivedDescriptor, DescriptorMeta,
     ExtendedDescriptor, ExtractPolicy, IntoWalletDescriptor, Policy, XKeyUtils,
@@ -71,7 +93,9 @@ use crate::psbt::PsbtUtils;
 use crate::signer::SignerError;
 use crate::types::*;
 use crate::wallet::coin_selection::Excess::{Change, NoChange};
-use crate::wallet::error::{BuildFeeBumpError, CreateTxError, MiniscriptPsbtError};
+use crate::wallet::error::{
+    BuildCancelError, BuildCpfpError, BuildFeeBumpError, CreateTxError, MiniscriptPsbtError,
+};
 
 use self::coin_selection::Error;
 
@@ -88,10 +112,11 @@ const COINBASE_MATURITY: u32 = 100;
 /// [`signer`]: crate::signer
 #[derive(Debug)]
 pub struct Wallet {
-    signers: Arc<SignersContainer>,
-    change_signers: Arc<SignersContainer>,
+    signers: BTreeMap<KeychainKind, Arc<SignersContainer>>,
     chain: LocalChain,
     indexed_graph: IndexedTxGraph<ConfirmationTimeHeightAnchor, KeychainTxOutIndex<KeychainKind>>,
+    labels: BTreeMap<LabelRef, Label>,
+    utxo_locks: BTreeMap<OutPoint, UtxoLock>,
     persist: Persist<ChangeSet>,
     network: Network,
     secp: SecpCtx,
@@ -138,6 +163,12 @@ impl From<SyncResult> for Update {
 /// The changes made to a wallet by applying an [`Update`].
 pub type ChangeSet = bdk_persist::CombinedChangeSet<KeychainKind, ConfirmationTimeHeightAnchor>;
 
+/// The [`ChangeSet`] written before the labels and the UTXO locks were added.
+///
+/// The files of `bdk_file_store` written with it are migrated with `Store::migrate`.
+pub type LegacyChangeSet =
+    bdk_persist::LegacyCombinedChangeSet<KeychainKind, ConfirmationTimeHeightAnchor>;
+
 /// A derived address and the index it was found at.
 /// For convenience this automatically derefs to `Address`
 #[derive(Debug, PartialEq, Eq)]
@@ -192,14 +223,31 @@ impl Wallet {
                 NewError::Persist(_) => unreachable!("mock-write must always succeed"),
             })
     }
+
+    /// Creates a wallet with a single descriptor that does not persist data.
+    ///
+    /// See [`Wallet::new_single`] for more information.
+    pub fn new_single_no_persist<E: IntoWalletDescriptor>(
+        descriptor: E,
+        network: Network,
+    ) -> Result<Self, DescriptorError> {
+        Self::new_single(descriptor, (), network).map_err(|e| match e {
+            NewError::NonEmptyDatabase => unreachable!("mock-database cannot have data"),
+            NewError::Descriptor(e) => e,
+            NewError::Persist(_) => unreachable!("mock-write must always succeed"),
+        })
+    }
 }
 
 /// The error type when constructing a fresh [`Wallet`].
 ///
-/// Methods [`new`] and [`new_with_genesis_hash`] may return this error.
+/// Methods [`new`], [`new_with_genesis_hash`], [`new_single`] and
+/// [`new_single_with_genesis_hash`] may return this error.
 ///
 /// [`new`]: Wallet::new
 /// [`new_with_genesis_hash`]: Wallet::new_with_genesis_hash
+/// [`new_single`]: Wallet::new_single
+/// [`new_single_with_genesis_hash`]: Wallet::new_single_with_genesis_hash
 #[derive(Debug)]
 pub enum NewError {
     /// Database already has data.
@@ -269,10 +317,13 @@ impl std::error::Error for LoadError {}
 
 /// Error type for when we try load a [`Wallet`] from persistence and creating it if non-existent.
 ///
-/// Methods [`new_or_load`] and [`new_or_load_with_genesis_hash`] may return this error.
+/// Methods [`new_or_load`], [`new_or_load_with_genesis_hash`], [`new_or_load_single`] and
+/// [`new_or_load_single_with_genesis_hash`] may return this error.
 ///
 /// [`new_or_load`]: Wallet::new_or_load
 /// [`new_or_load_with_genesis_hash`]: Wallet::new_or_load_with_genesis_hash
+/// [`new_or_load_single`]: Wallet::new_or_load_single
+/// [`new_or_load_single_with_genesis_hash`]: Wallet::new_or_load_single_with_genesis_hash
 #[derive(Debug)]
 pub enum NewOrLoadError {
     /// There is a problem with the passed-in descriptor.
@@ -298,7 +349,7 @@ pub enum NewOrLoadError {
     /// The loaded desccriptor does not match what was provided.
     LoadedDescriptorDoesNotMatch {
         /// The descriptor loaded from persistence.
-        got: Option<ExtendedDescriptor>,
+        got: Option<Box<ExtendedDescriptor>>,
         /// The keychain of the descriptor not matching
         keychain: KeychainKind,
     },
@@ -398,6 +449,36 @@ impl fmt::Display for ApplyBlockError {
 #[cfg(feature = "std")]
 impl std::error::Error for ApplyBlockError {}
 
+/// An error that may occur when adding a keychain with [`Wallet::add_keychain`].
+#[derive(Debug)]
+pub enum AddKeychainError {
+    /// There was a problem with the passed-in descriptor.
+    Descriptor(crate::descriptor::DescriptorError),
+    /// The keychain already exists with a different descriptor.
+    KeychainAlreadyExists(KeychainKind),
+    /// The descriptor is already used by another keychain.
+    DescriptorAlreadyExists(KeychainKind),
+}
+
+impl fmt::Display for AddKeychainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddKeychainError::Descriptor(e) => e.fmt(f),
+            AddKeychainError::KeychainAlreadyExists(keychain) => write!(
+                f,
+                "keychain {:?} already exists with a different descriptor",
+                keychain
+            ),
+            AddKeychainError::DescriptorAlreadyExists(keychain) => {
+                write!(f, "descriptor is already used by keychain {:?}", keychain)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AddKeychainError {}
+
 impl Wallet {
     /// Initialize an empty [`Wallet`].
     pub fn new<E: IntoWalletDescriptor>(
@@ -417,6 +498,82 @@ impl Wallet {
     pub fn new_with_genesis_hash<E: IntoWalletDescriptor>(
         descriptor: E,
         change_descriptor: E,
+        db: impl PersistBackend<ChangeSet> + Send + Sync + 'static,
+        network: Network,
+        genesis_hash: BlockHash,
+    ) -> Result<Self, NewError> {
+        Self::create(
+            descriptor,
+            Some(change_descriptor),
+            db,
+            network,
+            genesis_hash,
+        )
+    }
+
+    /// Initialize an empty [`Wallet`] with a single descriptor.
+    ///
+    /// This is useful to monitor a single descriptor (for instance an imported xpub), or a
+    /// descriptor that has no change branch. The wallet doesn't have a
+    /// [`KeychainKind::Internal`] keychain: the change of the transactions it creates goes back
+    /// to the [`KeychainKind::External`] keychain, unless it's sent somewhere else with
+    /// [`TxBuilder::drain_to`]. Every method taking a keychain treats
+    /// [`KeychainKind::Internal`] as [`KeychainKind::External`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use bdk_wallet::{KeychainKind, Wallet};
+    /// # use bdk_wallet::bitcoin::Network;
+    /// let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+    /// let mut wallet = Wallet::new_single_no_persist(descriptor, Network::Testnet)?;
+    /// let change = wallet.reveal_next_address(KeychainKind::Internal)?;
+    /// assert_eq!(change.keychain, KeychainKind::External);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    ///
+    /// A [BIP389] multipath descriptor with two derivation paths, like `<0;1>`, is split instead:
+    /// the first path is used for the [`KeychainKind::External`] keychain and the second one for
+    /// the [`KeychainKind::Internal`] keychain.
+    ///
+    /// ```
+    /// # use bdk_wallet::{KeychainKind, Wallet};
+    /// # use bdk_wallet::bitcoin::Network;
+    /// let descriptor = "wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/<0;1>/*)";
+    /// let wallet = Wallet::new_single_no_persist(descriptor, Network::Testnet)?;
+    /// assert_eq!(
+    ///     wallet.public_descriptor(KeychainKind::Internal).to_string(),
+    ///     "wpkh([c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/1/*)#lv67cwwm",
+    /// );
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    ///
+    /// [`TxBuilder::drain_to`]: crate::TxBuilder::drain_to
+    /// [BIP389]: https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki
+    pub fn new_single<E: IntoWalletDescriptor>(
+        descriptor: E,
+        db: impl PersistBackend<ChangeSet> + Send + Sync + 'static,
+        network: Network,
+    ) -> Result<Self, NewError> {
+        let genesis_hash = genesis_block(network).block_hash();
+        Self::new_single_with_genesis_hash(descriptor, db, network, genesis_hash)
+    }
+
+    /// Initialize an empty [`Wallet`] with a single descriptor and a custom genesis hash.
+    ///
+    /// This is like [`Wallet::new_single`] with an additional `genesis_hash` parameter.
+    pub fn new_single_with_genesis_hash<E: IntoWalletDescriptor>(
+        descriptor: E,
+        db: impl PersistBackend<ChangeSet> + Send + Sync + 'static,
+        network: Network,
+        genesis_hash: BlockHash,
+    ) -> Result<Self, NewError> {
+        Self::create(descriptor, None, db, network, genesis_hash)
+    }
+
+    fn create<E: IntoWalletDescriptor>(
+        descriptor: E,
+        change_descriptor: Option<E>,
         mut db: impl PersistBackend<ChangeSet> + Send + Sync + 'static,
         network: Network,
         genesis_hash: BlockHash,
@@ -430,9 +587,8 @@ impl Wallet {
         let (chain, chain_changeset) = LocalChain::from_genesis_hash(genesis_hash);
         let mut index = KeychainTxOutIndex::<KeychainKind>::default();
 
-        let (signers, change_signers) =
-            create_signers(&mut index, &secp, descriptor, change_descriptor, network)
-                .map_err(NewError::Descriptor)?;
+        let signers = create_signers(&mut index, &secp, descriptor, change_descriptor, network)
+            .map_err(NewError::Descriptor)?;
 
         let indexed_graph = IndexedTxGraph::new(index);
 
@@ -441,15 +597,18 @@ impl Wallet {
             chain: chain_changeset,
             indexed_tx_graph: indexed_graph.initial_changeset(),
             network: Some(network),
+            labels: labels::ChangeSet::default(),
+            utxo_locks: utxo_locks::ChangeSet::default(),
         });
         persist.commit().map_err(NewError::Persist)?;
 
         Ok(Wallet {
             signers,
-            change_signers,
             network,
             chain,
             indexed_graph,
+            labels: BTreeMap::new(),
+            utxo_locks: BTreeMap::new(),
             persist,
             secp,
         })
@@ -523,28 +682,39 @@ impl Wallet {
             .get(&KeychainKind::External)
             .ok_or(LoadError::MissingDescriptor(KeychainKind::External))?
             .clone();
+        // single descriptor wallets don't have an internal keychain
         let change_descriptor = changeset
             .indexed_tx_graph
             .indexer
             .keychains_added
             .get(&KeychainKind::Internal)
-            .ok_or(LoadError::MissingDescriptor(KeychainKind::Internal))?
-            .clone();
+            .cloned();
 
-        let (signers, change_signers) =
-            create_signers(&mut index, &secp, descriptor, change_descriptor, network)
-                .expect("Can't fail: we passed in valid descriptors, recovered from the changeset");
+        let signers = create_signers(&mut index, &secp, descriptor, change_descriptor, network)
+            .expect("Can't fail: we passed in valid descriptors, recovered from the changeset");
 
         let mut indexed_graph = IndexedTxGraph::new(index);
         indexed_graph.apply_changeset(changeset.indexed_tx_graph);
 
+        let labels = changeset
+            .labels
+            .into_iter()
+            .filter_map(|(label_ref, label)| Some((label_ref, label?)))
+            .collect();
+        let utxo_locks = changeset
+            .utxo_locks
+            .into_iter()
+            .filter_map(|(outpoint, lock)| Some((outpoint, lock?)))
+            .collect();
+
         let persist = Persist::new(db);
 
         Ok(Wallet {
             signers,
-            change_signers,
             chain,
             indexed_graph,
+            labels,
+            utxo_locks,
             persist,
             network,
             secp,
@@ -579,6 +749,50 @@ impl Wallet {
     pub fn new_or_load_with_genesis_hash<E: IntoWalletDescriptor>(
         descriptor: E,
         change_descriptor: E,
+        db: impl PersistBackend<ChangeSet> + Send + Sync + 'static,
+        network: Network,
+        genesis_hash: BlockHash,
+    ) -> Result<Self, NewOrLoadError> {
+        Self::new_or_load_inner(
+            descriptor,
+            Some(change_descriptor),
+            db,
+            network,
+            genesis_hash,
+        )
+    }
+
+    /// Either loads [`Wallet`] from persistence, or initializes it with a single descriptor if it
+    /// does not exist.
+    ///
+    /// This method will fail if the loaded [`Wallet`] has different parameters to those provided,
+    /// including if it has a change descriptor. See [`Wallet::new_single`] for wallets with a
+    /// single descriptor.
+    pub fn new_or_load_single<E: IntoWalletDescriptor>(
+        descriptor: E,
+        db: impl PersistBackend<ChangeSet> + Send + Sync + 'static,
+        network: Network,
+    ) -> Result<Self, NewOrLoadError> {
+        let genesis_hash = genesis_block(network).block_hash();
+        Self::new_or_load_single_with_genesis_hash(descriptor, db, network, genesis_hash)
+    }
+
+    /// Either loads [`Wallet`] from persistence, or initializes it with a single descriptor and a
+    /// custom genesis hash if it does not exist.
+    ///
+    /// This is like [`Wallet::new_or_load_single`] with an additional `genesis_hash` parameter.
+    pub fn new_or_load_single_with_genesis_hash<E: IntoWalletDescriptor>(
+        descriptor: E,
+        db: impl PersistBackend<ChangeSet> + Send + Sync + 'static,
+        network: Network,
+        genesis_hash: BlockHash,
+    ) -> Result<Self, NewOrLoadError> {
+        Self::new_or_load_inner(descriptor, None, db, network, genesis_hash)
+    }
+
+    fn new_or_load_inner<E: IntoWalletDescriptor>(
+        descriptor: E,
+        change_descriptor: Option<E>,
         mut db: impl PersistBackend<ChangeSet> + Send + Sync + 'static,
         network: Network,
         genesis_hash: BlockHash,
@@ -620,75 +834,56 @@ impl Wallet {
                     });
                 }
 
-                let (expected_descriptor, expected_descriptor_keymap) = descriptor
-                    .into_wallet_descriptor(&wallet.secp, network)
-                    .map_err(NewOrLoadError::Descriptor)?;
-                let wallet_descriptor = wallet.public_descriptor(KeychainKind::External);
-                if wallet_descriptor != &expected_descriptor {
-                    return Err(NewOrLoadError::LoadedDescriptorDoesNotMatch {
-                        got: Some(wallet_descriptor.clone()),
-                        keychain: KeychainKind::External,
-                    });
-                }
-                // if expected descriptor has private keys add them as new signers
-                if !expected_descriptor_keymap.is_empty() {
-                    let signer_container = SignersContainer::build(
-                        expected_descriptor_keymap,
-                        &expected_descriptor,
-                        &wallet.secp,
-                    );
-                    signer_container.signers().into_iter().for_each(|signer| {
-                        wallet.add_signer(
-                            KeychainKind::External,
-                            SignerOrdering::default(),
-                            signer.clone(),
-                        )
-                    });
-                }
-
-                let (expected_change_descriptor, expected_change_descriptor_keymap) =
-                    change_descriptor
-                        .into_wallet_descriptor(&wallet.secp, network)
+                let descriptors =
+                    keychain_descriptors(descriptor, change_descriptor, &wallet.secp, network)
                         .map_err(NewOrLoadError::Descriptor)?;
-                let wallet_change_descriptor = wallet.public_descriptor(KeychainKind::Internal);
-                if wallet_change_descriptor != &expected_change_descriptor {
+                // a wallet created with a single descriptor must not have a change descriptor
+                let wallet_change_descriptor = wallet
+                    .indexed_graph
+                    .index
+                    .get_descriptor(&KeychainKind::Internal);
+                if descriptors.len() == 1 && wallet_change_descriptor.is_some() {
                     return Err(NewOrLoadError::LoadedDescriptorDoesNotMatch {
-                        got: Some(wallet_change_descriptor.clone()),
+                        got: wallet_change_descriptor.cloned().map(Box::new),
                         keychain: KeychainKind::Internal,
                     });
                 }
-                // if expected change descriptor has private keys add them as new signers
-                if !expected_change_descriptor_keymap.is_empty() {
-                    let signer_container = SignersContainer::build(
-                        expected_change_descriptor_keymap,
-                        &expected_change_descriptor,
-                        &wallet.secp,
-                    );
-                    signer_container.signers().into_iter().for_each(|signer| {
-                        wallet.add_signer(
-                            KeychainKind::Internal,
-                            SignerOrdering::default(),
-                            signer.clone(),
-                        )
-                    });
+
+                let keychains = [KeychainKind::External, KeychainKind::Internal];
+                for (keychain, (expected_descriptor, expected_keymap)) in
+                    keychains.into_iter().zip(descriptors)
+                {
+                    let wallet_descriptor = wallet.indexed_graph.index.get_descriptor(&keychain);
+                    if wallet_descriptor != Some(&expected_descriptor) {
+                        return Err(NewOrLoadError::LoadedDescriptorDoesNotMatch {
+                            got: wallet_descriptor.cloned().map(Box::new),
+                            keychain,
+                        });
+                    }
+                    // if expected descriptor has private keys add them as new signers
+                    if !expected_keymap.is_empty() {
+                        let signer_container = SignersContainer::build(
+                            expected_keymap,
+                            &expected_descriptor,
+                            &wallet.secp,
+                        );
+                        signer_container.signers().into_iter().for_each(|signer| {
+                            wallet.add_signer(keychain, SignerOrdering::default(), signer.clone())
+                        });
+                    }
                 }
 
                 Ok(wallet)
             }
-            None => Self::new_with_genesis_hash(
-                descriptor,
-                change_descriptor,
-                db,
-                network,
-                genesis_hash,
-            )
-            .map_err(|e| match e {
-                NewError::NonEmptyDatabase => {
-                    unreachable!("database is already checked to have no data")
-                }
-                NewError::Descriptor(e) => NewOrLoadError::Descriptor(e),
-                NewError::Persist(e) => NewOrLoadError::Persist(e),
-            }),
+            None => Self::create(descriptor, change_descriptor, db, network, genesis_hash).map_err(
+                |e| match e {
+                    NewError::NonEmptyDatabase => {
+                        unreachable!("database is already checked to have no data")
+                    }
+                    NewError::Descriptor(e) => NewOrLoadError::Descriptor(e),
+                    NewError::Persist(e) => NewOrLoadError::Persist(e),
+                },
+            ),
         }
     }
 
@@ -702,6 +897,79 @@ impl Wallet {
         self.indexed_graph.index.keychains()
     }
 
+    /// Add a `descriptor` to the wallet under the given `keychain`.
+    ///
+    /// This is how [`KeychainKind::Custom`] keychains are registered, e.g. to track deposit
+    /// addresses for many customers, or to keep descriptors of different script types side by
+    /// side. Outputs of the transactions already in the wallet are indexed against the new
+    /// keychain.
+    ///
+    /// Adding the same descriptor again under the same keychain only adds its private keys to the
+    /// keychain's signers. As secret keys are not persisted, this can be used to restore them after
+    /// the wallet is loaded.
+    ///
+    /// The changes are staged, and must be persisted with [`Wallet::commit`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use bitcoin::*;
+    /// # use bdk_wallet::*;
+    /// # let mut wallet = doctest_wallet!();
+    /// let customer = KeychainKind::Custom(42);
+    /// wallet.add_keychain(
+    ///     customer,
+    ///     "tr([73c5da0a/86'/0'/0']tprv8fMn4hSKPRC1oaCPqxDb1JWtgkpeiQvZhsr8W2xuy3GEMkzoArcAWTfJxYb6Wj8XNNDWEjfYKK4wGQXh3ZUXhDF2NcnsALpWTeSwarJt7Vc/42/*)",
+    /// )?;
+    /// let address = wallet.reveal_next_address(customer)?;
+    /// assert_eq!(address.keychain, customer);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn add_keychain<E: IntoWalletDescriptor>(
+        &mut self,
+        keychain: KeychainKind,
+        descriptor: E,
+    ) -> Result<(), AddKeychainError> {
+        let (descriptor, keymap) =
+            into_wallet_descriptor_checked(descriptor, &self.secp, self.network)
+                .map_err(AddKeychainError::Descriptor)?;
+        for (existing_keychain, existing_descriptor) in self.indexed_graph.index.keychains() {
+            let is_same_descriptor = existing_descriptor == &descriptor;
+            if *existing_keychain == keychain && !is_same_descriptor {
+                return Err(AddKeychainError::KeychainAlreadyExists(keychain));
+            }
+            if *existing_keychain != keychain && is_same_descriptor {
+                return Err(AddKeychainError::DescriptorAlreadyExists(
+                    *existing_keychain,
+                ));
+            }
+        }
+
+        let signer_container = SignersContainer::build(keymap, &descriptor, &self.secp);
+        signer_container.signers().into_iter().for_each(|signer| {
+            self.add_signer(keychain, SignerOrdering::default(), signer.clone())
+        });
+
+        let mut changeset = self
+            .indexed_graph
+            .index
+            .insert_descriptor(keychain, descriptor);
+        let txouts = self
+            .indexed_graph
+            .graph()
+            .all_txouts()
+            .map(|(outpoint, txout)| (outpoint, txout.clone()))
+            .collect::<Vec<_>>();
+        for (outpoint, txout) in txouts {
+            changeset.append(self.indexed_graph.index.index_txout(outpoint, &txout));
+        }
+        self.persist
+            .stage(ChangeSet::from(indexed_tx_graph::ChangeSet::from(
+                changeset,
+            )));
+        Ok(())
+    }
+
     /// Peek an address of the given `keychain` at `index` without revealing it.
     ///
     /// For non-wildcard descriptors this returns the same address at every provided index.
@@ -711,6 +979,7 @@ impl Wallet {
     /// This panics when the caller requests for an address of derivation index greater than the
     /// [BIP32](https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki) max index.
     pub fn peek_address(&self, keychain: KeychainKind, mut index: u32) -> AddressInfo {
+        let keychain = self.map_keychain(keychain);
         let mut spk_iter = self
             .indexed_graph
             .index
@@ -741,6 +1010,7 @@ impl Wallet {
     ///
     /// If writing to persistent storage fails.
     pub fn reveal_next_address(&mut self, keychain: KeychainKind) -> anyhow::Result<AddressInfo> {
+        let keychain = self.map_keychain(keychain);
         let ((index, spk), index_changeset) = self
             .indexed_graph
             .index
@@ -772,6 +1042,7 @@ impl Wallet {
         keychain: KeychainKind,
         index: u32,
     ) -> anyhow::Result<impl Iterator<Item = AddressInfo> + '_> {
+        let keychain = self.map_keychain(keychain);
         let (spk_iter, index_changeset) = self
             .indexed_graph
             .index
@@ -798,6 +1069,7 @@ impl Wallet {
     ///
     /// If writing to persistent storage fails.
     pub fn next_unused_address(&mut self, keychain: KeychainKind) -> anyhow::Result<AddressInfo> {
+        let keychain = self.map_keychain(keychain);
         let ((index, spk), index_changeset) = self
             .indexed_graph
             .index
@@ -818,6 +1090,7 @@ impl Wallet {
     ///
     /// Returns whether the given index was present and then removed from the unused set.
     pub fn mark_used(&mut self, keychain: KeychainKind, index: u32) -> bool {
+        let keychain = self.map_keychain(keychain);
         self.indexed_graph.index.mark_used(keychain, index)
     }
 
@@ -830,6 +1103,7 @@ impl Wallet {
     ///
     /// [`mark_used`]: Self::mark_used
     pub fn unmark_used(&mut self, keychain: KeychainKind, index: u32) -> bool {
+        let keychain = self.map_keychain(keychain);
         self.indexed_graph.index.unmark_used(keychain, index)
     }
 
@@ -842,6 +1116,7 @@ impl Wallet {
         &self,
         keychain: KeychainKind,
     ) -> impl DoubleEndedIterator<Item = AddressInfo> + '_ {
+        let keychain = self.map_keychain(keychain);
         self.indexed_graph
             .index
             .unused_keychain_spks(&keychain)
@@ -876,6 +1151,16 @@ impl Wallet {
             .map(|((k, i), full_txo)| new_local_utxo(k, i, full_txo))
     }
 
+    /// Return the list of unspent outputs of the given `keychains`
+    pub fn list_unspent_of_keychains(
+        &self,
+        keychains: impl IntoIterator<Item = KeychainKind>,
+    ) -> impl Iterator<Item = LocalOutput> + '_ {
+        let keychains = keychains.into_iter().collect::<BTreeSet<_>>();
+        self.list_unspent()
+            .filter(move |utxo| keychains.contains(&utxo.keychain))
+    }
+
     /// List all relevant outputs (includes both spent and unspent, confirmed and unconfirmed).
     ///
     /// To list only unspent outputs (UTXOs), use [`Wallet::list_unspent`] instead.
@@ -923,6 +1208,7 @@ impl Wallet {
         &self,
         keychain: KeychainKind,
     ) -> impl Iterator<Item = (u32, ScriptBuf)> + Clone {
+        let keychain = self.map_keychain(keychain);
         self.indexed_graph
             .index
             .unbounded_spk_iter(&keychain)
@@ -1187,6 +1473,7 @@ impl Wallet {
         }
 
         let changed = !changeset.is_empty();
+        changeset.append(self.release_utxo_locks(&changeset).into());
         self.persist.stage(changeset);
         Ok(changed)
     }
@@ -1204,11 +1491,55 @@ impl Wallet {
     /// Return the balance, separated into available, trusted-pending, untrusted-pending and immature
     /// values.
     pub fn balance(&self) -> Balance {
-        self.indexed_graph.graph().balance(
+        self.balance_of_outpoints(self.indexed_graph.index.outpoints())
+    }
+
+    /// Return the balance of the given `keychains`, separated into available, trusted-pending,
+    /// untrusted-pending and immature values.
+    pub fn balance_of_keychains(
+        &self,
+        keychains: impl IntoIterator<Item = KeychainKind>,
+    ) -> Balance {
+        let keychains = keychains.into_iter().collect::<BTreeSet<_>>();
+        self.balance_of_outpoints(
+            self.indexed_graph
+                .index
+                .outpoints()
+                .filter(|((k, _), _)| keychains.contains(k)),
+        )
+    }
+
+    /// The balance of the given indexed outpoints, the pending ones being trusted if they are
+    /// change outputs.
+    ///
+    /// Wallets with a single descriptor send their change to the external keychain: its pending
+    /// outputs are then trusted if their transaction spends outputs of the wallet.
+    fn balance_of_outpoints(
+        &self,
+        outpoints: impl IntoIterator<Item = ((KeychainKind, u32), OutPoint)>,
+    ) -> Balance {
+        let graph = self.indexed_graph.graph();
+        let index = &self.indexed_graph.index;
+        let change_keychain = self.map_keychain(KeychainKind::Internal);
+        let outpoints = outpoints.into_iter().map(|((keychain, _), outpoint)| {
+            let trusted = match keychain {
+                KeychainKind::Internal => true,
+                keychain if keychain == change_keychain => {
+                    graph.get_tx(outpoint.txid).map_or(false, |tx| {
+                        tx.input
+                            .iter()
+                            .any(|txin| index.txout(txin.previous_output).is_some())
+                    })
+                }
+                _ => false,
+            };
+            (trusted, outpoint)
+        });
+        graph.balance(
             &self.chain,
             self.chain.tip().block_id(),
-            self.indexed_graph.index.outpoints(),
-            |&(k, _), _| k == KeychainKind::Internal,
+            outpoints,
+            |trusted, _| *trusted,
         )
     }
 
@@ -1221,10 +1552,8 @@ impl Wallet {
         ordering: SignerOrdering,
         signer: Arc<dyn TransactionSigner>,
     ) {
-        let signers = match keychain {
-            KeychainKind::External => Arc::make_mut(&mut self.signers),
-            KeychainKind::Internal => Arc::make_mut(&mut self.change_signers),
-        };
+        let keychain = self.map_keychain(keychain);
+        let signers = Arc::make_mut(self.signers.entry(keychain).or_default());
 
         signers.add_external(signer.id(&self.secp), ordering, signer);
     }
@@ -1247,16 +1576,20 @@ impl Wallet {
     /// Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
     pub fn get_signers(&self, keychain: KeychainKind) -> Arc<SignersContainer> {
-        match keychain {
-            KeychainKind::External => Arc::clone(&self.signers),
-            KeychainKind::Internal => Arc::clone(&self.change_signers),
-        }
+        let keychain = self.map_keychain(keychain);
+        self.signers.get(&keychain).cloned().unwrap_or_default()
     }
 
     /// Start building a transaction.
     ///
     /// This returns a blank [`TxBuilder`] from which you can specify the parameters for the transaction.
     ///
+    /// The outputs locked with [`lock_utxo`] are not selected, but the inputs of the transaction
+    /// are not locked unless [`TxBuilder::lock_selected_utxos`] is called: a second transaction
+    /// built before the first one is added to the wallet may otherwise spend the same outputs.
+    /// The locks are staged with the other changes of the wallet, and must be persisted with
+    /// [`commit`].
+    ///
     /// ## Example
     ///
     /// ```
@@ -1282,6 +1615,9 @@ impl Wallet {
     /// ```
     ///
     /// [`TxBuilder`]: crate::TxBuilder
+    /// [`TxBuilder::lock_selected_utxos`]: crate::TxBuilder::lock_selected_utxos
+    /// [`lock_utxo`]: Self::lock_utxo
+    /// [`commit`]: Self::commit
     pub fn build_tx(&mut self) -> TxBuilder<'_, DefaultCoinSelectionAlgorithm> {
         TxBuilder {
             wallet: alloc::rc::Rc::new(core::cell::RefCell::new(self)),
@@ -1294,52 +1630,40 @@ impl Wallet {
         &mut self,
         coin_selection: Cs,
         params: TxParams,
-    ) -> Result<Psbt, CreateTxError> {
-        let keychains: BTreeMap<_, _> = self.indexed_graph.index.keychains().collect();
-        let external_descriptor = keychains.get(&KeychainKind::External).expect("must exist");
-        let internal_descriptor = keychains.get(&KeychainKind::Internal).expect("must exist");
-
-        let external_policy = external_descriptor
-            .extract_policy(&self.signers, BuildSatisfaction::None, &self.secp)?
-            .unwrap();
-        let internal_policy = internal_descriptor
-            .extract_policy(&self.change_signers, BuildSatisfaction::None, &self.secp)?
-            .unwrap();
-
-        // The policy allows spending external outputs, but it requires a policy path that hasn't been
-        // provided
-        if params.change_policy != tx_builder::ChangeSpendPolicy::OnlyChange
-            && external_policy.requires_path()
-            && params.external_policy_path.is_none()
-        {
-            return Err(CreateTxError::SpendingPolicyRequired(
-                KeychainKind::External,
-            ));
-        };
-        // Same for the internal_policy path
-        if params.change_policy != tx_builder::ChangeSpendPolicy::ChangeForbidden
-            && internal_policy.requires_path()
-            && params.internal_policy_path.is_none()
-        {
-            return Err(CreateTxError::SpendingPolicyRequired(
-                KeychainKind::Internal,
-            ));
-        };
-
-        let external_requirements = external_policy.get_condition(
-            params
-                .external_policy_path
-                .as_ref()
-                .unwrap_or(&BTreeMap::new()),
-        )?;
-        let internal_requirements = internal_policy.get_condition(
-            params
-                .internal_policy_path
-                .as_ref()
-                .unwrap_or(&BTreeMap::new()),
-        )?;
+    ) -> Result<(Psbt, coin_selection::CoinSelectionResult), CreateTxError> {
+        let mut requirements = Condition::default();
+        for (keychain, descriptor) in self.indexed_graph.index.keychains() {
+            if let Some(spend_keychains) = &params.spend_keychains {
+                if !spend_keychains.contains(keychain) {
+                    continue;
+                }
+            }
+            let policy = descriptor
+                .extract_policy(
+                    &self.get_signers(*keychain),
+                    BuildSatisfaction::None,
+                    &self.secp,
+                )?
+                .unwrap();
+            let policy_path = params.policy_paths.get(keychain);
+
+            // The policy allows spending the keychain's outputs, but it requires a policy path
+            // that hasn't been provided
+            let may_spend = match params.change_policy {
+                tx_builder::ChangeSpendPolicy::ChangeAllowed => true,
+                tx_builder::ChangeSpendPolicy::OnlyChange => *keychain == KeychainKind::Internal,
+                tx_builder::ChangeSpendPolicy::ChangeForbidden => {
+                    *keychain != KeychainKind::Internal
+                }
+            };
+            if may_spend && policy.requires_path() && policy_path.is_none() {
+                return Err(CreateTxError::SpendingPolicyRequired(*keychain));
+            }
 
-        let requirements = external_requirements.merge(&internal_requirements)?;
+            let keychain_requirements =
+                policy.get_condition(policy_path.unwrap_or(&BTreeMap::new()))?;
+            requirements = requirements.merge(&keychain_requirements)?;
+        }
 
         let version = match params.version {
             Some(tx_builder::Version(0)) => return Err(CreateTxError::Version0),
@@ -1403,6 +1727,11 @@ impl Wallet {
 
         // The nSequence to be by default for inputs unless an explicit sequence is specified.
         let n_sequence = match (params.rbf, requirements.csv) {
+            // No RBF or CSV, but the inputs must have the given nSequence, e.g. the one of the
+            // sender's inputs when building a payjoin proposal
+            (None, None) if params.sequence.is_some() => {
+                params.sequence.expect("the sequence is set")
+            }
             // No RBF or CSV but there's an nLockTime, so the nSequence cannot be final
             (None, None) if lock_time != absolute::LockTime::ZERO => {
                 Sequence::ENABLE_LOCKTIME_NO_RBF
@@ -1457,7 +1786,13 @@ impl Wallet {
                         });
                     }
                 }
-                (rate, 0)
+                // when paying for the unconfirmed ancestors of the transaction, the fee must
+                // bring the whole package to the target feerate
+                let package_fee = params
+                    .ancestor_package
+                    .map(|package| (rate * package.weight).to_sat().saturating_sub(package.fee))
+                    .unwrap_or(0);
+                (rate, package_fee)
             }
         };
 
@@ -1471,6 +1806,15 @@ impl Wallet {
         if params.manually_selected_only && params.utxos.is_empty() {
             return Err(CreateTxError::NoUtxosSelected);
         }
+        if let Some(fixed_inputs) = &params.fixed_inputs {
+            if let Some(weighted_utxo) = params
+                .utxos
+                .iter()
+                .find(|weighted_utxo| !fixed_inputs.contains(&weighted_utxo.utxo.outpoint()))
+            {
+                return Err(CreateTxError::FixedInputs(weighted_utxo.utxo.outpoint()));
+            }
+        }
 
         let mut outgoing = Amount::ZERO;
         let mut received = Amount::ZERO;
@@ -1499,16 +1843,42 @@ impl Wallet {
             outgoing += Amount::from_sat(value);
         }
 
+        // the outputs of silent payment recipients are derived once the inputs are selected
+        let silent_payment_outputs = tx.output.len();
+        for (index, (address, value)) in params.silent_payments.iter().enumerate() {
+            let index = params.recipients.len() + index;
+            if !address.is_valid_for_network(self.network) {
+                return Err(SilentPaymentError::NetworkMismatch(index).into());
+            }
+            let script_pubkey = silent_payments::placeholder_script();
+            if !params.allow_dust && value.is_dust(&script_pubkey) {
+                return Err(CreateTxError::OutputBelowDustLimit(index));
+            }
+            tx.output.push(TxOut {
+                script_pubkey,
+                value: Amount::from_sat(*value),
+            });
+            outgoing += Amount::from_sat(*value);
+        }
+
+        if let Some(index) = params.subtract_fee_from.range(tx.output.len()..).next() {
+            return Err(CreateTxError::InvalidSubtractFeeIndex(*index));
+        }
+
         fee_amount += (fee_rate * tx.weight()).to_sat();
 
-        let (required_utxos, optional_utxos) =
+        let (required_utxos, mut optional_utxos) =
             self.preselect_utxos(&params, Some(current_height.to_consensus_u32()));
+        if !params.silent_payments.is_empty() {
+            optional_utxos.retain(|u| self.silent_payment_input_key(&u.utxo).is_ok());
+        }
 
         // get drain script
         let drain_script = match params.drain_to {
             Some(ref drain_recipient) => drain_recipient.clone(),
             None => {
-                let change_keychain = KeychainKind::Internal;
+                let change_keychain =
+                    self.map_keychain(params.change_keychain.unwrap_or(KeychainKind::Internal));
                 let ((index, spk), index_changeset) = self
                     .indexed_graph
                     .index
@@ -1528,13 +1898,23 @@ impl Wallet {
         let (required_utxos, optional_utxos) =
             coin_selection::filter_duplicates(required_utxos, optional_utxos);
 
+        // when the recipients pay the fee, the coins are selected at the target feerate to cover
+        // their amounts, like Bitcoin Core, and the fee is subtracted from them afterwards
+        let subtract_fee = !params.subtract_fee_from.is_empty();
+        let target_amount = if subtract_fee {
+            outgoing.to_sat()
+        } else {
+            outgoing.to_sat() + fee_amount
+        };
         let coin_selection = coin_selection.coin_select(
             required_utxos,
             optional_utxos,
             fee_rate,
-            outgoing.to_sat() + fee_amount,
+            target_amount,
             &drain_script,
         )?;
+        // the fee of the transaction without its inputs and change output
+        let base_fee = fee_amount;
         fee_amount += coin_selection.fee_amount;
         let excess = &coin_selection.excess;
 
@@ -1574,6 +1954,18 @@ impl Wallet {
             }
         }
 
+        // when the recipients pay the fee, the wallet only sends their amounts: the change is
+        // what's left of the selected coins, as the recipients also pay for the inputs and the
+        // change output
+        let subtract_fee_excess;
+        let excess = if subtract_fee {
+            let leftover = coin_selection.selected_amount() - outgoing.to_sat();
+            subtract_fee_excess = coin_selection::decide_change(leftover, fee_rate, &drain_script);
+            &subtract_fee_excess
+        } else {
+            excess
+        };
+
         match excess {
             NoChange {
                 remaining_amount, ..
@@ -1597,11 +1989,90 @@ impl Wallet {
             }
         };
 
+        if subtract_fee {
+            // like Bitcoin Core, the recipients pay the whole fee of the transaction, and receive
+            // the leftover of the selected coins when it's too small for a change output
+            let (change_fee, leftover) = match excess {
+                NoChange {
+                    remaining_amount, ..
+                } => (0, *remaining_amount),
+                Change { fee, .. } => {
+                    let drain_output = tx.output.last_mut().expect("drain output");
+                    drain_output.value += Amount::from_sat(*fee);
+                    if self.is_mine(&drain_output.script_pubkey) {
+                        received += Amount::from_sat(*fee);
+                    }
+                    (*fee, 0)
+                }
+            };
+            let fee = (base_fee + coin_selection.fee_amount + change_fee).saturating_sub(leftover);
+
+            let payers = params.subtract_fee_from.len() as u64;
+            for (i, index) in params.subtract_fee_from.iter().enumerate() {
+                let share = fee / payers + if i == 0 { fee % payers } else { 0 };
+                let txout = &mut tx.output[*index];
+                let value = txout
+                    .value
+                    .to_sat()
+                    .checked_sub(share)
+                    .filter(|value| {
+                        params.allow_dust
+                            || !value.is_dust(&txout.script_pubkey)
+                            || txout.script_pubkey.is_provably_unspendable()
+                    })
+                    .ok_or(CreateTxError::OutputBelowDustLimitAfterFee(*index))?;
+                if self.is_mine(&txout.script_pubkey) {
+                    received -= Amount::from_sat(share);
+                }
+                txout.value = Amount::from_sat(value);
+            }
+        }
+
+        if !params.silent_payments.is_empty() {
+            let input_keys = coin_selection
+                .selected
+                .iter()
+                .map(|utxo| self.silent_payment_input_key(utxo))
+                .collect::<Result<Vec<_>, _>>()?;
+            let recipients = params
+                .silent_payments
+                .iter()
+                .map(|(address, _)| *address)
+                .collect::<Vec<_>>();
+            let output_keys = silent_payments::sender_output_keys(
+                &self.secp,
+                &input_keys,
+                tx.input.iter().map(|txin| &txin.previous_output),
+                &recipients,
+            )?;
+            let outputs = silent_payment_outputs..silent_payment_outputs + recipients.len();
+            for (txout, output_key) in tx.output[outputs].iter_mut().zip(output_keys) {
+                txout.script_pubkey = ScriptBuf::new_p2tr_tweaked(
+                    TweakedPublicKey::dangerous_assume_tweaked(output_key),
+                );
+            }
+        }
+
         // sort input/outputs according to the chosen algorithm
         params.ordering.sort_tx(&mut tx);
 
-        let psbt = self.complete_transaction(tx, coin_selection.selected, params)?;
-        Ok(psbt)
+        // only the utxos of the wallet are locked, not the foreign ones
+        let lock = params.lock_selected_utxos;
+        let local_utxos = coin_selection
+            .selected
+            .iter()
+            .filter_map(|utxo| match utxo {
+                Utxo::Local(local) => Some(local.outpoint),
+                Utxo::Foreign { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        let psbt = self.complete_transaction(tx, coin_selection.selected.clone(), params)?;
+        if let Some(lock) = lock {
+            for outpoint in local_utxos {
+                self.lock_utxo(outpoint, lock.expiry_height);
+            }
+        }
+        Ok((psbt, coin_selection))
     }
 
     /// Bump the fee of a transaction previously created with this wallet.
@@ -1610,6 +2081,12 @@ impl Wallet {
     /// *replace by fee* (RBF). If the transaction can be fee bumped then it returns a [`TxBuilder`]
     /// pre-populated with the inputs and outputs of the original transaction.
     ///
+    /// If the transaction pays [silent payment] addresses, which are derived from the inputs of
+    /// the transaction, call [`TxBuilder::fixed_inputs`] so that the fee is only paid from the
+    /// change of the transaction and no input is added.
+    ///
+    /// [silent payment]: silent_payments
+    ///
     /// ## Example
     ///
     /// ```no_run
@@ -1651,17 +2128,152 @@ impl Wallet {
         &mut self,
         txid: Txid,
     ) -> Result<TxBuilder<'_, DefaultCoinSelectionAlgorithm>, BuildFeeBumpError> {
-        let graph = self.indexed_graph.graph();
-        let txout_index = &self.indexed_graph.index;
-        let chain_tip = self.chain.tip().block_id();
+        let params = self.fee_bump_params(txid)?;
 
-        let mut tx = graph
-            .get_tx(txid)
-            .ok_or(BuildFeeBumpError::TransactionNotFound(txid))?
-            .as_ref()
-            .clone();
+        Ok(TxBuilder {
+            wallet: alloc::rc::Rc::new(core::cell::RefCell::new(self)),
+            params,
+            coin_selection: DefaultCoinSelectionAlgorithm::default(),
+        })
+    }
 
-        let pos = graph
+    /// Cancel an unconfirmed transaction by replacing it using *replace by fee* (RBF).
+    ///
+    /// Returns a [`TxBuilder`] that spends the wallet's inputs of the original transaction back
+    /// to a new change address. The fee of the replacement is set so that it pays for the
+    /// original transaction, for its unconfirmed descendants which are evicted along with it, and
+    /// for its own relay, as required by the BIP125 rules 3 and 4. Once it is signed and
+    /// broadcast, the original transaction can't be confirmed anymore.
+    ///
+    /// Unlike [`cancel_tx`], which only frees the change address of a transaction that was never
+    /// broadcast, this is meant for transactions that are already in the mempool. The new change
+    /// address is staged but not [`commit`]ted.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// # use std::str::FromStr;
+    /// # use bitcoin::*;
+    /// # use bdk_wallet::*;
+    /// # let mut wallet = doctest_wallet!();
+    /// # let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap().assume_checked();
+    /// let mut psbt = {
+    ///     let mut builder = wallet.build_tx();
+    ///     builder
+    ///         .add_recipient(to_address.script_pubkey(), Amount::from_sat(50_000))
+    ///         .enable_rbf();
+    ///     builder.finish()?
+    /// };
+    /// let _ = wallet.sign(&mut psbt, SignOptions::default())?;
+    /// let tx = psbt.clone().extract_tx().expect("tx");
+    /// // broadcast tx but we changed our mind and want to cancel the payment
+    /// let mut psbt = wallet.build_cancel(tx.txid())?.finish()?;
+    /// let _ = wallet.sign(&mut psbt, SignOptions::default())?;
+    /// let cancel_tx = psbt.extract_tx();
+    /// // broadcast cancel_tx to replace original
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    ///
+    /// [`cancel_tx`]: Self::cancel_tx
+    /// [`commit`]: Self::commit
+    pub fn build_cancel(
+        &mut self,
+        txid: Txid,
+    ) -> Result<TxBuilder<'_, DefaultCoinSelectionAlgorithm>, BuildCancelError> {
+        let mut params = self.fee_bump_params(txid)?;
+        let previous_fee = params
+            .bumping_fee
+            .expect("fee bump params must have a previous fee");
+
+        // we can only sign for our own inputs, spending any of them is enough to invalidate the
+        // original transaction
+        params
+            .utxos
+            .retain(|weighted_utxo| matches!(weighted_utxo.utxo, Utxo::Local(_)));
+        if params.utxos.is_empty() {
+            return Err(BuildCancelError::NoLocalInput(txid));
+        }
+
+        // the unconfirmed descendants of the transaction are evicted along with it
+        let graph = self.indexed_graph.graph();
+        let chain_tip = self.chain.tip().block_id();
+        let descendants_fee = graph
+            .walk_descendants(txid, |_, descendant| Some(descendant))
+            .filter(|&descendant| {
+                graph
+                    .get_chain_position(&self.chain, chain_tip, descendant)
+                    .is_some()
+            })
+            .map(|descendant| {
+                let tx = graph
+                    .get_tx(descendant)
+                    .expect("descendants must be in the graph");
+                self.calculate_fee(&tx)
+                    .map(|fee| fee.to_sat())
+                    .map_err(|_| BuildCancelError::FeeUnavailable(descendant))
+            })
+            .sum::<Result<u64, _>>()?;
+
+        let drain_to = self.next_change_spk();
+
+        // BIP125 rule 3: the replacement must pay at least the fees of the original transaction
+        // and of its descendants.
+        // BIP125 rule 4: it must also pay for its own bandwidth at the minimum relay feerate.
+        // We also keep the feerate higher than the original one, as it's required when the
+        // feerate of a fee bump is set with `TxBuilder::fee_rate`.
+        let weight = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: Amount::ZERO,
+                script_pubkey: drain_to.clone(),
+            }],
+        }
+        .weight()
+            // the segwit marker and flag, and the size of the input count
+            + Weight::from_wu(2 + 4 * (VarInt(params.utxos.len() as u64).size() as u64 - 1))
+            + params
+                .utxos
+                .iter()
+                .map(|weighted_utxo| {
+                    TxIn::default().segwit_weight()
+                        + Weight::from_wu_usize(weighted_utxo.satisfaction_weight)
+                })
+                .sum::<Weight>();
+        let required_feerate = FeeRate::from_sat_per_kwu(
+            previous_fee.rate.to_sat_per_kwu() + FeeRate::BROADCAST_MIN.to_sat_per_kwu(),
+        );
+        let fee = core::cmp::max(
+            previous_fee.absolute + descendants_fee + (FeeRate::BROADCAST_MIN * weight).to_sat(),
+            (required_feerate * weight).to_sat(),
+        );
+
+        params.recipients.clear();
+        params.drain_to = Some(drain_to);
+        params.manually_selected_only = true;
+        params.fee_policy = Some(FeePolicy::FeeAmount(fee));
+
+        Ok(TxBuilder {
+            wallet: alloc::rc::Rc::new(core::cell::RefCell::new(self)),
+            params,
+            coin_selection: DefaultCoinSelectionAlgorithm::default(),
+        })
+    }
+
+    /// Returns the [`TxParams`] of a transaction replacing the unconfirmed transaction `txid`.
+    fn fee_bump_params(&self, txid: Txid) -> Result<TxParams, BuildFeeBumpError> {
+        let graph = self.indexed_graph.graph();
+        let txout_index = &self.indexed_graph.index;
+        let chain_tip = self.chain.tip().block_id();
+
+        let mut tx = graph
+            .get_tx(txid)
+            .ok_or(BuildFeeBumpError::TransactionNotFound(txid))?
+            .as_ref()
+            .clone();
+
+        let pos = graph
             .get_chain_position(&self.chain, chain_tip, txid)
             .ok_or(BuildFeeBumpError::TransactionNotFound(txid))?;
         if let ChainPosition::Confirmed(_) = pos {
@@ -1742,7 +2354,7 @@ impl Wallet {
         if tx.output.len() > 1 {
             let mut change_index = None;
             for (index, txout) in tx.output.iter().enumerate() {
-                let change_keychain = KeychainKind::Internal;
+                let change_keychain = self.map_keychain(KeychainKind::Internal);
                 match txout_index.index_of_spk(&txout.script_pubkey) {
                     Some((keychain, _)) if keychain == change_keychain => {
                         change_index = Some(index)
@@ -1772,6 +2384,116 @@ impl Wallet {
             ..Default::default()
         };
 
+        Ok(params)
+    }
+
+    /// Bump the fee of an unconfirmed transaction paying to this wallet using *child pays for
+    /// parent* (CPFP).
+    ///
+    /// Returns a [`TxBuilder`] that spends the wallet's outputs of the parent transaction to a new
+    /// change address. The fee of the child is computed so that the parent, its unconfirmed
+    /// ancestors and the child reach `target_package_feerate` once combined. Additional
+    /// confirmed UTXOs are selected if the parent outputs are not enough to pay for the fee, and
+    /// recipients can be added to the returned builder.
+    ///
+    /// The fee of the parent and of its ancestors must be known to the wallet: an incoming
+    /// payment spends outputs of other wallets, which must be inserted with [`insert_txout`]
+    /// first. Otherwise [`BuildCpfpError::FeeUnavailable`] is returned, as a child paying only
+    /// for itself wouldn't speed up the confirmation of the parent.
+    ///
+    /// The new change address is staged but not [`commit`]ted.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// # use std::str::FromStr;
+    /// # use bitcoin::*;
+    /// # use bdk_wallet::*;
+    /// # let mut wallet = doctest_wallet!();
+    /// # let parent_txid = Txid::from_str("4d8ca6c6e8fd1ecb1bbb4e5a2d6e89e8ac54fb3c06a2ffb8ec0bd5ee5f9dbbd7").unwrap();
+    /// # let (spent_outpoint, spent_txout) = (OutPoint::null(), TxOut::NULL);
+    /// // an incoming payment is stuck with a low fee, spend it to get it confirmed faster
+    /// // the outputs it spends are fetched from the chain source so that its fee is known
+    /// wallet.insert_txout(spent_outpoint, spent_txout);
+    /// let mut psbt = {
+    ///     let mut builder =
+    ///         wallet.build_cpfp(parent_txid, FeeRate::from_sat_per_vb(10).expect("valid feerate"))?;
+    ///     builder.finish()?
+    /// };
+    /// let _ = wallet.sign(&mut psbt, SignOptions::default())?;
+    /// let child_tx = psbt.extract_tx();
+    /// // broadcast child_tx along with the parent
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    ///
+    /// [`insert_txout`]: Self::insert_txout
+    /// [`commit`]: Self::commit
+    pub fn build_cpfp(
+        &mut self,
+        parent_txid: Txid,
+        target_package_feerate: FeeRate,
+    ) -> Result<TxBuilder<'_, DefaultCoinSelectionAlgorithm>, BuildCpfpError> {
+        let graph = self.indexed_graph.graph();
+        let chain_tip = self.chain.tip().block_id();
+
+        let parent = graph
+            .get_tx(parent_txid)
+            .ok_or(BuildCpfpError::TransactionNotFound(parent_txid))?;
+        let pos = graph
+            .get_chain_position(&self.chain, chain_tip, parent_txid)
+            .ok_or(BuildCpfpError::TransactionNotFound(parent_txid))?;
+        if let ChainPosition::Confirmed(_) = pos {
+            return Err(BuildCpfpError::TransactionConfirmed(parent_txid));
+        }
+
+        let utxos = self
+            .list_unspent()
+            .filter(|utxo| utxo.outpoint.txid == parent_txid)
+            .map(|utxo| {
+                let satisfaction_weight = self
+                    .get_descriptor_for_keychain(utxo.keychain)
+                    .max_weight_to_satisfy()
+                    .unwrap();
+                WeightedUtxo {
+                    utxo: Utxo::Local(utxo),
+                    satisfaction_weight,
+                }
+            })
+            .collect::<Vec<_>>();
+        if utxos.is_empty() {
+            return Err(BuildCpfpError::NoSpendableOutput(parent_txid));
+        }
+
+        // the package is made of the parent and all of its unconfirmed ancestors
+        let mut package = vec![parent.clone()];
+        package.extend(graph.walk_ancestors(parent, |_, tx| {
+            match graph.get_chain_position(&self.chain, chain_tip, tx.txid()) {
+                Some(ChainPosition::Unconfirmed(_)) => Some(tx),
+                _ => None,
+            }
+        }));
+        let mut ancestor_package = tx_builder::AncestorPackage {
+            weight: Weight::ZERO,
+            fee: 0,
+        };
+        for tx in package {
+            let fee = graph
+                .calculate_fee(&tx)
+                .map_err(|_| BuildCpfpError::FeeUnavailable(tx.txid()))?;
+            ancestor_package.weight += tx.weight();
+            ancestor_package.fee += fee.to_sat();
+        }
+
+        let drain_to = self.next_change_spk();
+
+        let params = TxParams {
+            utxos,
+            drain_to: Some(drain_to),
+            fee_policy: Some(FeePolicy::FeeRate(target_package_feerate)),
+            ancestor_package: Some(ancestor_package),
+            ..Default::default()
+        };
+
         Ok(TxBuilder {
             wallet: alloc::rc::Rc::new(core::cell::RefCell::new(self)),
             params,
@@ -1779,6 +2501,24 @@ impl Wallet {
         })
     }
 
+    /// Reveal the next unused script pubkey of the change keychain and mark it as used, for the
+    /// transactions sending the coins of the wallet back to itself.
+    fn next_change_spk(&mut self) -> ScriptBuf {
+        let change_keychain = self.map_keychain(KeychainKind::Internal);
+        let ((index, spk), index_changeset) = self
+            .indexed_graph
+            .index
+            .next_unused_spk(&change_keychain)
+            .expect("keychain must exist");
+        let spk = spk.into();
+        self.indexed_graph.index.mark_used(change_keychain, index);
+        self.persist
+            .stage(ChangeSet::from(indexed_tx_graph::ChangeSet::from(
+                index_changeset,
+            )));
+        spk
+    }
+
     /// Sign a transaction with all the wallet's signers, in the order specified by every signer's
     /// [`SignerOrdering`]. This function returns the `Result` type with an encapsulated `bool` that has the value true if the PSBT was finalized, or false otherwise.
     ///
@@ -1813,39 +2553,22 @@ impl Wallet {
         self.update_psbt_with_descriptor(psbt)
             .map_err(SignerError::MiniscriptPsbt)?;
 
-        // If we aren't allowed to use `witness_utxo`, ensure that every input (except p2tr and finalized ones)
-        // has the `non_witness_utxo`
-        if !sign_options.trust_witness_utxo
-            && psbt
-                .inputs
-                .iter()
-                .filter(|i| i.final_script_witness.is_none() && i.final_script_sig.is_none())
-                .filter(|i| i.tap_internal_key.is_none() && i.tap_merkle_root.is_none())
-                .any(|i| i.non_witness_utxo.is_none())
-        {
-            return Err(SignerError::MissingNonWitnessUtxo);
-        }
-
-        // If the user hasn't explicitly opted-in, refuse to sign the transaction unless every input
-        // is using `SIGHASH_ALL` or `SIGHASH_DEFAULT` for taproot
-        if !sign_options.allow_all_sighashes
-            && !psbt.inputs.iter().all(|i| {
-                i.sighash_type.is_none()
-                    || i.sighash_type == Some(EcdsaSighashType::All.into())
-                    || i.sighash_type == Some(TapSighashType::All.into())
-                    || i.sighash_type == Some(TapSighashType::Default.into())
-            })
-        {
-            return Err(SignerError::NonStandardSighash);
-        }
-
-        for signer in self
-            .signers
-            .signers()
-            .iter()
-            .chain(self.change_signers.signers().iter())
-        {
-            signer.sign_transaction(psbt, &sign_options, &self.secp)?;
+        check_sign_options(psbt, &sign_options)?;
+
+        // the signers of a keychain only sign its inputs, and the inputs the wallet doesn't own
+        for (keychain, signers) in &self.signers {
+            let input_indexes = (0..psbt.inputs.len())
+                .filter(|&index| {
+                    psbt.get_utxo_for(index)
+                        .and_then(|txout| {
+                            self.indexed_graph.index.index_of_spk(&txout.script_pubkey)
+                        })
+                        .map_or(true, |(input_keychain, _)| input_keychain == *keychain)
+                })
+                .collect::<Vec<_>>();
+            for signer in signers.signers() {
+                signer.sign_inputs(psbt, &input_indexes, &sign_options, &self.secp)?;
+            }
         }
 
         // attempt to finalize
@@ -1858,13 +2581,8 @@ impl Wallet {
 
     /// Return the spending policies for the wallet's descriptor
     pub fn policies(&self, keychain: KeychainKind) -> Result<Option<Policy>, DescriptorError> {
-        let signers = match keychain {
-            KeychainKind::External => &self.signers,
-            KeychainKind::Internal => &self.change_signers,
-        };
-
         self.public_descriptor(keychain).extract_policy(
-            signers,
+            &self.get_signers(keychain),
             BuildSatisfaction::None,
             &self.secp,
         )
@@ -1875,6 +2593,7 @@ impl Wallet {
     ///
     /// This can be used to build a watch-only version of a wallet
     pub fn public_descriptor(&self, keychain: KeychainKind) -> &ExtendedDescriptor {
+        let keychain = self.map_keychain(keychain);
         self.indexed_graph
             .index
             .keychains()
@@ -1947,23 +2666,7 @@ impl Wallet {
                             Older::new(Some(current_height), confirmation_height, false),
                         ),
                     ) {
-                        Ok(_) => {
-                            let psbt_input = &mut psbt.inputs[n];
-                            psbt_input.final_script_sig = Some(tmp_input.script_sig);
-                            psbt_input.final_script_witness = Some(tmp_input.witness);
-                            if sign_options.remove_partial_sigs {
-                                psbt_input.partial_sigs.clear();
-                            }
-                            if sign_options.remove_taproot_extras {
-                                // We just constructed the final witness, clear these fields.
-                                psbt_input.tap_key_sig = None;
-                                psbt_input.tap_script_sigs.clear();
-                                psbt_input.tap_scripts.clear();
-                                psbt_input.tap_key_origins.clear();
-                                psbt_input.tap_internal_key = None;
-                                psbt_input.tap_merkle_root = None;
-                            }
-                        }
+                        Ok(_) => finalize_input(&mut psbt.inputs[n], tmp_input, &sign_options),
                         Err(_) => finished = false,
                     }
                 }
@@ -1993,11 +2696,13 @@ impl Wallet {
     /// The derivation index of this wallet. It will return `None` if it has not derived any addresses.
     /// Otherwise, it will return the index of the highest address it has derived.
     pub fn derivation_index(&self, keychain: KeychainKind) -> Option<u32> {
+        let keychain = self.map_keychain(keychain);
         self.indexed_graph.index.last_revealed_index(&keychain)
     }
 
     /// The index of the next address that you would get if you were to ask the wallet for a new address
     pub fn next_derivation_index(&self, keychain: KeychainKind) -> u32 {
+        let keychain = self.map_keychain(keychain);
         self.indexed_graph
             .index
             .next_index(&keychain)
@@ -2020,6 +2725,23 @@ impl Wallet {
         }
     }
 
+    /// Returns the keychain used in place of `keychain`.
+    ///
+    /// Wallets created with a single descriptor use their [`KeychainKind::External`] keychain in
+    /// place of the missing [`KeychainKind::Internal`] one.
+    fn map_keychain(&self, keychain: KeychainKind) -> KeychainKind {
+        if keychain == KeychainKind::Internal
+            && self
+                .indexed_graph
+                .index
+                .get_descriptor(&KeychainKind::Internal)
+                .is_none()
+        {
+            return KeychainKind::External;
+        }
+        keychain
+    }
+
     fn get_descriptor_for_txout(&self, txout: &TxOut) -> Option<DerivedDescriptor> {
         let (keychain, child) = self
             .indexed_graph
@@ -2029,6 +2751,53 @@ impl Wallet {
         descriptor.at_derivation_index(child).ok()
     }
 
+    /// The private key of `utxo` used to derive the outputs of silent payment recipients.
+    fn silent_payment_input_key(&self, utxo: &Utxo) -> Result<SecretKey, SilentPaymentError> {
+        let (outpoint, keychain, index) = match utxo {
+            Utxo::Local(utxo) => (utxo.outpoint, utxo.keychain, utxo.derivation_index),
+            Utxo::Foreign { outpoint, .. } => {
+                return Err(SilentPaymentError::IneligibleInput(*outpoint))
+            }
+        };
+        let descriptor = self
+            .get_descriptor_for_keychain(keychain)
+            .at_derivation_index(index)
+            .expect("child can't be hardened")
+            .derived_descriptor(&self.secp)
+            .expect("child can't be hardened");
+        let (public_key, merkle_root) = match &descriptor {
+            Descriptor::Pkh(pkh) if pkh.as_inner().compressed => (*pkh.as_inner(), None),
+            Descriptor::Wpkh(wpkh) => (*wpkh.as_inner(), None),
+            Descriptor::Sh(sh) => match sh.as_inner() {
+                ShInner::Wpkh(wpkh) => (*wpkh.as_inner(), None),
+                _ => return Err(SilentPaymentError::IneligibleInput(outpoint)),
+            },
+            Descriptor::Tr(tr) => (*tr.internal_key(), tr.spend_info().merkle_root()),
+            _ => return Err(SilentPaymentError::IneligibleInput(outpoint)),
+        };
+
+        let key = self
+            .get_signers(keychain)
+            .signers()
+            .iter()
+            .filter_map(|signer| signer.descriptor_secret_key())
+            .filter_map(|secret| message::derive_private_key(&secret, index, &self.secp))
+            .find(|key| match descriptor {
+                Descriptor::Tr(_) => {
+                    key.public_key(&self.secp).inner.x_only_public_key().0
+                        == public_key.inner.x_only_public_key().0
+                }
+                _ => key.public_key(&self.secp).inner == public_key.inner,
+            })
+            .ok_or(SilentPaymentError::MissingKey(outpoint))?;
+        Ok(match descriptor {
+            Descriptor::Tr(_) => {
+                silent_payments::taproot_input_key(&self.secp, key.inner, merkle_root)
+            }
+            _ => key.inner,
+        })
+    }
+
     fn get_available_utxos(&self) -> Vec<(LocalOutput, usize)> {
         self.list_unspent()
             .map(|utxo| {
@@ -2056,12 +2825,15 @@ impl Wallet {
             drain_wallet,
             manually_selected_only,
             bumping_fee,
+            ancestor_package,
+            spend_keychains,
             ..
         } = params;
 
         let manually_selected = utxos.clone();
-        // we mandate confirmed transactions if we're bumping the fee
-        let must_only_use_confirmed_tx = bumping_fee.is_some();
+        // we mandate confirmed transactions if we're bumping the fee, either by replacing the
+        // transaction or by spending its outputs
+        let must_only_use_confirmed_tx = bumping_fee.is_some() || ancestor_package.is_some();
         let must_use_all_available = *drain_wallet;
 
         let chain_tip = self.chain.tip().block_id();
@@ -2127,7 +2899,11 @@ impl Wallet {
         let mut i = 0;
         may_spend.retain(|u| {
             let retain = change_policy.is_satisfied_by(&u.0)
+                && spend_keychains
+                    .as_ref()
+                    .map_or(true, |keychains| keychains.contains(&u.0.keychain))
                 && !unspendable.contains(&u.0.outpoint)
+                && !self.is_utxo_locked(u.0.outpoint)
                 && satisfies_confirmed[i];
             i += 1;
             retain
@@ -2340,10 +3116,50 @@ impl Wallet {
         changeset.append(ChangeSet::from(
             self.indexed_graph.apply_update(update.graph),
         ));
-        self.persist.stage(changeset);
+        self.stage_applied(changeset);
         Ok(())
     }
 
+    /// Applies an update like [`apply_update`], returning the [`WalletEvent`]s describing what
+    /// the update changed.
+    ///
+    /// The events are computed by comparing the canonical transactions, unspent outputs and
+    /// balance of the wallet before and after the update, which costs as much as listing them
+    /// twice. Use [`apply_update`] if you don't need the events.
+    ///
+    /// [`apply_update`]: Self::apply_update
+    pub fn apply_update_events(
+        &mut self,
+        update: impl Into<Update>,
+    ) -> Result<Vec<WalletEvent>, CannotConnectError> {
+        let before = self.snapshot();
+        self.apply_update(update)?;
+        Ok(self.events_since(before))
+    }
+
+    /// Stage the `changeset` of applied chain data, releasing the locks of the spent UTXOs.
+    fn stage_applied(&mut self, mut changeset: ChangeSet) {
+        changeset.append(self.release_utxo_locks(&changeset).into());
+        self.persist.stage(changeset);
+    }
+
+    /// Take a snapshot of the canonical view of the wallet, to compute [`WalletEvent`]s later.
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            txs: self
+                .transactions()
+                .map(|tx| (tx.tx_node.txid, tx.chain_position.cloned().into()))
+                .collect(),
+            utxos: self.list_unspent().map(|utxo| utxo.outpoint).collect(),
+            balance: self.balance(),
+        }
+    }
+
+    /// The [`WalletEvent`]s describing the changes since the `before` snapshot.
+    fn events_since(&self, before: Snapshot) -> Vec<WalletEvent> {
+        event::wallet_events(before, self.snapshot(), self.indexed_graph.graph())
+    }
+
     /// Commits all currently [`staged`] changed to the persistence backend returning and error when
     /// this fails.
     ///
@@ -2403,6 +3219,23 @@ impl Wallet {
             })
     }
 
+    /// Introduces a `block` like [`apply_block`], returning the [`WalletEvent`]s describing what
+    /// the block changed.
+    ///
+    /// See [`apply_update_events`] for the cost of computing the events.
+    ///
+    /// [`apply_block`]: Self::apply_block
+    /// [`apply_update_events`]: Self::apply_update_events
+    pub fn apply_block_events(
+        &mut self,
+        block: &Block,
+        height: u32,
+    ) -> Result<Vec<WalletEvent>, CannotConnectError> {
+        let before = self.snapshot();
+        self.apply_block(block, height)?;
+        Ok(self.events_since(before))
+    }
+
     /// Applies relevant transactions from `block` of `height` to the wallet, and connects the
     /// block to the internal chain.
     ///
@@ -2426,10 +3259,28 @@ impl Wallet {
                 .apply_block_relevant(block, height)
                 .into(),
         );
-        self.persist.stage(changeset);
+        self.stage_applied(changeset);
         Ok(())
     }
 
+    /// Applies the transactions of a `block` like [`apply_block_connected_to`], returning the
+    /// [`WalletEvent`]s describing what the block changed.
+    ///
+    /// See [`apply_update_events`] for the cost of computing the events.
+    ///
+    /// [`apply_block_connected_to`]: Self::apply_block_connected_to
+    /// [`apply_update_events`]: Self::apply_update_events
+    pub fn apply_block_connected_to_events(
+        &mut self,
+        block: &Block,
+        height: u32,
+        connected_to: BlockId,
+    ) -> Result<Vec<WalletEvent>, ApplyHeaderError> {
+        let before = self.snapshot();
+        self.apply_block_connected_to(block, height, connected_to)?;
+        Ok(self.events_since(before))
+    }
+
     /// Apply relevant unconfirmed transactions to the wallet.
     ///
     /// Transactions that are not relevant are filtered out.
@@ -2445,7 +3296,629 @@ impl Wallet {
         let indexed_graph_changeset = self
             .indexed_graph
             .batch_insert_relevant_unconfirmed(unconfirmed_txs);
-        self.persist.stage(ChangeSet::from(indexed_graph_changeset));
+        self.stage_applied(ChangeSet::from(indexed_graph_changeset));
+    }
+
+    /// Applies unconfirmed transactions like [`apply_unconfirmed_txs`], returning the
+    /// [`WalletEvent`]s describing what the transactions changed.
+    ///
+    /// See [`apply_update_events`] for the cost of computing the events.
+    ///
+    /// [`apply_unconfirmed_txs`]: Self::apply_unconfirmed_txs
+    /// [`apply_update_events`]: Self::apply_update_events
+    pub fn apply_unconfirmed_txs_events<'t>(
+        &mut self,
+        unconfirmed_txs: impl IntoIterator<Item = (&'t Transaction, u64)>,
+    ) -> Vec<WalletEvent> {
+        let before = self.snapshot();
+        self.apply_unconfirmed_txs(unconfirmed_txs);
+        self.events_since(before)
+    }
+}
+
+/// Methods to manage the wallet's [BIP329](labels) labels.
+impl Wallet {
+    /// Attach a `label` to `label_ref`, replacing any existing label.
+    ///
+    /// This stages but does not [`commit`] the change.
+    ///
+    /// [`commit`]: Self::commit
+    pub fn set_label(&mut self, label_ref: LabelRef, label: Label) {
+        if self.labels.get(&label_ref) == Some(&label) {
+            return;
+        }
+        self.labels.insert(label_ref.clone(), label.clone());
+        self.persist
+            .stage(labels::ChangeSet::from([(label_ref, Some(label))]).into());
+    }
+
+    /// Remove the label attached to `label_ref`, returning it if it existed.
+    ///
+    /// This stages but does not [`commit`] the change.
+    ///
+    /// [`commit`]: Self::commit
+    pub fn remove_label(&mut self, label_ref: &LabelRef) -> Option<Label> {
+        let label = self.labels.remove(label_ref)?;
+        self.persist
+            .stage(labels::ChangeSet::from([(label_ref.clone(), None)]).into());
+        Some(label)
+    }
+
+    /// Get the label attached to `label_ref`.
+    pub fn get_label(&self, label_ref: &LabelRef) -> Option<&Label> {
+        self.labels.get(label_ref)
+    }
+
+    /// Iterate over all the labels of the wallet.
+    pub fn labels(&self) -> impl Iterator<Item = (&LabelRef, &Label)> {
+        self.labels.iter()
+    }
+
+    /// Export all the labels of the wallet in the BIP329 JSON Lines format.
+    ///
+    /// Address labels whose script pubkey has no address form are skipped.
+    pub fn export_labels(&self) -> String {
+        self.labels
+            .iter()
+            .filter_map(|(label_ref, label)| labels::to_bip329_line(label_ref, label, self.network))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Import labels in the BIP329 JSON Lines format, replacing the existing labels of the same
+    /// items.
+    ///
+    /// Records of unknown types and empty lines are ignored. Returns the number of imported labels.
+    /// Nothing is imported if any of the records is invalid.
+    ///
+    /// This stages but does not [`commit`] the changes.
+    ///
+    /// [`commit`]: Self::commit
+    pub fn import_labels(&mut self, jsonl: &str) -> Result<usize, Bip329Error> {
+        let imported = jsonl
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .filter_map(|(i, line)| labels::from_bip329_line(line, i + 1, self.network).transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+        let count = imported.len();
+        for (label_ref, label) in imported {
+            self.set_label(label_ref, label);
+        }
+        Ok(count)
+    }
+}
+
+/// Methods to manage the wallet's locked unspent outputs.
+///
+/// Locked outputs are not selected by the [`TxBuilder`], unless they are added manually with
+/// [`TxBuilder::add_utxo`]. This prevents transactions that are built but not broadcast yet from
+/// spending the same outputs.
+impl Wallet {
+    /// Lock `outpoint` until the chain tip reaches `expiry_height`, or until it's unlocked if
+    /// `expiry_height` is `None`. Locking an outpoint again replaces its expiry.
+    ///
+    /// The lock is released when a transaction spending `outpoint` is added to the wallet.
+    ///
+    /// This stages but does not [`commit`] the change.
+    ///
+    /// [`commit`]: Self::commit
+    pub fn lock_utxo(&mut self, outpoint: OutPoint, expiry_height: Option<u32>) {
+        let lock = UtxoLock { expiry_height };
+        if self.utxo_locks.get(&outpoint) == Some(&lock) {
+            return;
+        }
+        self.utxo_locks.insert(outpoint, lock);
+        self.persist
+            .stage(utxo_locks::ChangeSet::from([(outpoint, Some(lock))]).into());
+    }
+
+    /// Unlock `outpoint`, returning whether it was locked.
+    ///
+    /// This stages but does not [`commit`] the change.
+    ///
+    /// [`commit`]: Self::commit
+    pub fn unlock_utxo(&mut self, outpoint: OutPoint) -> bool {
+        if self.utxo_locks.remove(&outpoint).is_none() {
+            return false;
+        }
+        self.persist
+            .stage(utxo_locks::ChangeSet::from([(outpoint, None)]).into());
+        true
+    }
+
+    /// Whether `outpoint` is locked and the lock hasn't expired.
+    pub fn is_utxo_locked(&self, outpoint: OutPoint) -> bool {
+        let tip_height = self.chain.tip().height();
+        self.utxo_locks
+            .get(&outpoint)
+            .map_or(false, |lock| lock.is_active(tip_height))
+    }
+
+    /// Iterate over the locked outpoints whose lock hasn't expired.
+    pub fn locked_utxos(&self) -> impl Iterator<Item = (OutPoint, UtxoLock)> + '_ {
+        let tip_height = self.chain.tip().height();
+        self.utxo_locks
+            .iter()
+            .filter(move |(_, lock)| lock.is_active(tip_height))
+            .map(|(&outpoint, &lock)| (outpoint, lock))
+    }
+
+    /// Release the locks of the outpoints spent by the transactions of `changeset`, and the
+    /// locks that expired.
+    fn release_utxo_locks(&mut self, changeset: &ChangeSet) -> utxo_locks::ChangeSet {
+        let tip_height = self.chain.tip().height();
+        let spent = changeset
+            .indexed_tx_graph
+            .graph
+            .txs
+            .iter()
+            .flat_map(|tx| tx.input.iter().map(|txin| txin.previous_output))
+            .collect::<BTreeSet<_>>();
+        let released = self
+            .utxo_locks
+            .iter()
+            .filter(|(outpoint, lock)| spent.contains(outpoint) || !lock.is_active(tip_height))
+            .map(|(&outpoint, _)| (outpoint, None))
+            .collect::<utxo_locks::ChangeSet>();
+        for outpoint in released.keys() {
+            self.utxo_locks.remove(outpoint);
+        }
+        released
+    }
+}
+
+/// Methods to sign messages with the wallet's keys, see the [`message`] module.
+impl Wallet {
+    /// Sign `message` with the key of `address`, returning the base64 encoded signature.
+    ///
+    /// The [`MessageSignatureFormat::Legacy`] format is only supported by P2PKH addresses, and
+    /// requires the private key to be known by the wallet. The BIP322 formats are signed with the
+    /// wallet's signers, [`MessageSignatureFormat::Simple`] being only supported by native segwit
+    /// addresses. Signatures can be checked with [`message::verify_message`].
+    pub fn sign_message(
+        &self,
+        message: &str,
+        address: &Address,
+        format: MessageSignatureFormat,
+    ) -> Result<String, MessageError> {
+        let script_pubkey = address.script_pubkey();
+        let (keychain, index) = self
+            .derivation_of_spk(&script_pubkey)
+            .ok_or(MessageError::NotMine)?;
+
+        match format {
+            MessageSignatureFormat::Legacy => {
+                if !script_pubkey.is_p2pkh() {
+                    return Err(MessageError::UnsupportedAddress);
+                }
+                let key = self
+                    .get_signers(keychain)
+                    .signers()
+                    .iter()
+                    .filter_map(|signer| signer.descriptor_secret_key())
+                    .filter_map(|secret| message::derive_private_key(&secret, index, &self.secp))
+                    .find(|key| {
+                        ScriptBuf::new_p2pkh(&key.public_key(&self.secp).pubkey_hash())
+                            == script_pubkey
+                    })
+                    .ok_or(MessageError::MissingKey)?;
+                return Ok(message::sign_legacy(&key, message, &self.secp));
+            }
+            MessageSignatureFormat::Simple if !script_pubkey.is_witness_program() => {
+                return Err(MessageError::UnsupportedAddress);
+            }
+            _ => {}
+        }
+
+        let to_spend = message::to_spend(&script_pubkey, message);
+        let to_sign = message::to_sign(to_spend.txid(), ScriptBuf::new(), Witness::new());
+        let mut psbt = Psbt::from_unsigned_tx(to_sign).expect("the transaction is unsigned");
+        psbt.inputs[0].witness_utxo = Some(to_spend.output[0].clone());
+        psbt.inputs[0].non_witness_utxo = Some(to_spend);
+        if !self.sign(&mut psbt, SignOptions::default())? {
+            return Err(MessageError::NotFinalized);
+        }
+
+        Ok(message::encode_signature(
+            &psbt.extract_tx_unchecked_fee_rate(),
+            format,
+        ))
+    }
+}
+
+/// Methods to create [BIP127](reserves) proofs of reserves.
+impl Wallet {
+    /// Create an unsigned proof of reserves of `message`, spending the unspent outputs
+    /// `outpoints`, or all the unspent outputs of the wallet if `None`.
+    ///
+    /// The proof is signed with [`sign`] like any other PSBT, and can then be checked with
+    /// [`reserves::verify_proof_of_reserves`].
+    ///
+    /// [`sign`]: Self::sign
+    pub fn create_proof_of_reserves(
+        &self,
+        message: &str,
+        outpoints: Option<&[OutPoint]>,
+    ) -> Result<Psbt, ProofError> {
+        let utxos = match outpoints {
+            None => self.list_unspent().collect::<Vec<_>>(),
+            Some(outpoints) => outpoints
+                .iter()
+                .map(|&outpoint| {
+                    self.get_utxo(outpoint)
+                        .ok_or(ProofError::UnknownUtxo(outpoint))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+        let utxos = utxos
+            .into_iter()
+            .map(|utxo| {
+                let outpoint = utxo.outpoint;
+                let value = utxo.txout.value;
+                let input = self
+                    .get_psbt_input(utxo, None, false)
+                    .map_err(|_| ProofError::UnknownUtxo(outpoint))?;
+                Ok((outpoint, value, input))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        reserves::proof_psbt(message, utxos)
+    }
+}
+
+/// Methods to sweep the funds of external keys into the wallet, see the [`sweep`] module.
+impl Wallet {
+    /// Start building a transaction spending all the unspent outputs of `sweep` to the wallet's
+    /// next unused external address.
+    ///
+    /// The outputs are found in `graph`, usually the graph of a chain source's result for the
+    /// [`Sweep::spks`], and canonicalized with the wallet's chain. The transaction doesn't spend
+    /// any output of the wallet, and must be signed with [`Sweep::sign`].
+    ///
+    /// # Errors
+    ///
+    /// If no unspent output of `sweep` is found, or if writing to persistent storage fails.
+    pub fn build_sweep<A: Anchor>(
+        &mut self,
+        sweep: &Sweep,
+        graph: &TxGraph<A>,
+    ) -> Result<TxBuilder<'_, DefaultCoinSelectionAlgorithm>, SweepError> {
+        let utxos = sweep.find_utxos(graph, &self.chain, self.chain.tip().block_id());
+        if utxos.is_empty() {
+            return Err(SweepError::NoUtxos);
+        }
+        let address = self
+            .next_unused_address(KeychainKind::External)
+            .map_err(SweepError::Persist)?;
+
+        let mut params = TxParams {
+            drain_to: Some(address.script_pubkey()),
+            manually_selected_only: true,
+            ..Default::default()
+        };
+        for utxo in utxos {
+            params.utxos.push(WeightedUtxo {
+                satisfaction_weight: utxo.satisfaction_weight,
+                utxo: Utxo::Foreign {
+                    outpoint: utxo.outpoint,
+                    sequence: None,
+                    psbt_input: Box::new(utxo.psbt_input),
+                },
+            });
+        }
+
+        Ok(TxBuilder {
+            wallet: alloc::rc::Rc::new(core::cell::RefCell::new(self)),
+            params,
+            coin_selection: DefaultCoinSelectionAlgorithm::default(),
+        })
+    }
+}
+
+/// Methods to consolidate the UTXOs of the wallet, see the [`consolidation`] module.
+impl Wallet {
+    /// Plan the consolidation of the wallet's UTXOs at `fee_rate`.
+    ///
+    /// The confirmed UTXOs whose effective value is positive at `fee_rate` are grouped, from the
+    /// smallest to the largest, in transactions of at most `max_weight`. The weight of the inputs
+    /// is estimated with the `max_weight_to_satisfy` of the wallet's descriptors. A transaction is
+    /// only planned if it spends at least two UTXOs, and if it saves fees compared to spending its
+    /// UTXOs at `long_term_feerate`, including the cost of spending its output later. Locked
+    /// UTXOs and immature coinbase outputs are not consolidated.
+    ///
+    /// No consolidation is planned if `fee_rate` isn't lower than `long_term_feerate`.
+    pub fn plan_consolidations(
+        &self,
+        fee_rate: FeeRate,
+        long_term_feerate: FeeRate,
+        max_weight: Weight,
+    ) -> Vec<Consolidation> {
+        let (_, utxos) =
+            self.preselect_utxos(&TxParams::default(), Some(self.chain.tip().height()));
+        let utxos = utxos
+            .into_iter()
+            .filter(|u| match &u.utxo {
+                Utxo::Local(local) => local.confirmation_time.is_confirmed(),
+                Utxo::Foreign { .. } => false,
+            })
+            .collect();
+
+        let change_keychain = self.map_keychain(KeychainKind::Internal);
+        let drain_script = self.peek_address(change_keychain, 0).script_pubkey();
+        let drain_satisfaction_weight = self
+            .get_descriptor_for_keychain(change_keychain)
+            .max_weight_to_satisfy()
+            .unwrap();
+
+        consolidation::plan(
+            utxos,
+            &ConsolidationParams {
+                fee_rate,
+                long_term_feerate,
+                max_weight,
+                drain_script: &drain_script,
+                drain_satisfaction_weight,
+            },
+        )
+    }
+
+    /// Start building the transaction of `consolidation`, spending its UTXOs to the wallet's next
+    /// unused internal address. The address is staged but not [`commit`]ted.
+    ///
+    /// # Errors
+    ///
+    /// If a UTXO of `consolidation` isn't an unspent output of the wallet anymore.
+    ///
+    /// [`commit`]: Self::commit
+    pub fn build_consolidation(
+        &mut self,
+        consolidation: &Consolidation,
+    ) -> Result<TxBuilder<'_, DefaultCoinSelectionAlgorithm>, ConsolidationError> {
+        let utxos = consolidation
+            .utxos
+            .iter()
+            .map(|&outpoint| {
+                let utxo = self
+                    .get_utxo(outpoint)
+                    .filter(|utxo| !utxo.is_spent)
+                    .ok_or(ConsolidationError::UnknownUtxo(outpoint))?;
+                let satisfaction_weight = self
+                    .get_descriptor_for_keychain(utxo.keychain)
+                    .max_weight_to_satisfy()
+                    .unwrap();
+                Ok(WeightedUtxo {
+                    utxo: Utxo::Local(utxo),
+                    satisfaction_weight,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let change_keychain = self.map_keychain(KeychainKind::Internal);
+        let ((index, spk), index_changeset) = self
+            .indexed_graph
+            .index
+            .next_unused_spk(&change_keychain)
+            .expect("keychain must exist");
+        let drain_to = spk.into();
+        self.indexed_graph.index.mark_used(change_keychain, index);
+        self.persist
+            .stage(ChangeSet::from(indexed_tx_graph::ChangeSet::from(
+                index_changeset,
+            )));
+
+        let params = TxParams {
+            utxos,
+            manually_selected_only: true,
+            drain_to: Some(drain_to),
+            fee_policy: Some(FeePolicy::FeeRate(consolidation.fee_rate)),
+            ..Default::default()
+        };
+
+        Ok(TxBuilder {
+            wallet: alloc::rc::Rc::new(core::cell::RefCell::new(self)),
+            params,
+            coin_selection: DefaultCoinSelectionAlgorithm::default(),
+        })
+    }
+}
+
+/// Methods to send and receive [BIP78](payjoin) payjoin payments.
+impl Wallet {
+    /// Build a payjoin proposal from the `original` PSBT of a payjoin sender.
+    ///
+    /// The original PSBT must be finalized and have the utxos of its inputs, must not spend any
+    /// output of this wallet and must pay to it. The returned [`TxBuilder`] spends the inputs of
+    /// the original PSBT with the same sequence, version and locktime, and keeps its outputs.
+    /// The output paying to this wallet receives the value of the inputs contributed with
+    /// [`TxBuilder::add_utxo`], minus the fee they require at the feerate of the original
+    /// transaction. Once built, the proposal is signed with [`finalize_payjoin_proposal`].
+    ///
+    /// [`finalize_payjoin_proposal`]: Self::finalize_payjoin_proposal
+    pub fn build_payjoin_proposal(
+        &mut self,
+        original: &Psbt,
+    ) -> Result<TxBuilder<'_, DefaultCoinSelectionAlgorithm>, OriginalPsbtError> {
+        let (receiver_output, fee) = payjoin::check_original(original, |spk| self.is_mine(spk))?;
+        let original_tx = &original.unsigned_tx;
+
+        let utxos = original
+            .inputs
+            .iter()
+            .zip(&original_tx.input)
+            .map(|(input, txin)| {
+                let script_sig = input.final_script_sig.clone().unwrap_or_default();
+                let witness = input.final_script_witness.clone().unwrap_or_default();
+                WeightedUtxo {
+                    utxo: Utxo::Foreign {
+                        outpoint: txin.previous_output,
+                        sequence: Some(txin.sequence),
+                        psbt_input: Box::new(psbt::Input {
+                            witness_utxo: input.witness_utxo.clone(),
+                            non_witness_utxo: input.non_witness_utxo.clone(),
+                            ..Default::default()
+                        }),
+                    },
+                    satisfaction_weight: serialize(&script_sig).len() * 4
+                        + serialize(&witness).len(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let only_witness_utxo = original.inputs.iter().any(|input| {
+            input.non_witness_utxo.is_none()
+                && !input
+                    .witness_utxo
+                    .as_ref()
+                    .map_or(false, |txout| txout.script_pubkey.is_p2tr())
+        });
+
+        // round the feerate up, so that the fee of the proposal can't be lower than the original
+        let weight = original.clone().extract_tx_unchecked_fee_rate().weight();
+        let fee_rate =
+            FeeRate::from_sat_per_kwu((fee.to_sat() * 1000 + weight.to_wu() - 1) / weight.to_wu());
+        let sequence = original_tx.input[0].sequence;
+
+        let params = TxParams {
+            recipients: original_tx
+                .output
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| *index != receiver_output)
+                .map(|(_, txout)| (txout.script_pubkey.clone(), txout.value.to_sat()))
+                .collect(),
+            drain_to: Some(original_tx.output[receiver_output].script_pubkey.clone()),
+            fee_policy: Some(FeePolicy::FeeRate(fee_rate)),
+            utxos,
+            manually_selected_only: true,
+            locktime: Some(original_tx.lock_time),
+            rbf: Some(tx_builder::RbfValue::Value(sequence)).filter(|_| sequence.is_rbf()),
+            // the inputs contributed by the receiver must have the same sequence as the sender's
+            sequence: Some(sequence),
+            version: Some(tx_builder::Version(original_tx.version.0)),
+            only_witness_utxo,
+            ..Default::default()
+        };
+
+        Ok(TxBuilder {
+            wallet: alloc::rc::Rc::new(core::cell::RefCell::new(self)),
+            params,
+            coin_selection: DefaultCoinSelectionAlgorithm::default(),
+        })
+    }
+
+    /// Sign the inputs this wallet contributed to a payjoin `proposal`, and remove the fields
+    /// BIP78 forbids before the proposal is sent back to the sender.
+    ///
+    /// The inputs of the sender are left empty, and the key paths of the outputs are removed.
+    /// Returns whether all the inputs of this wallet were finalized, which is required by the
+    /// sender. The `sign_options` are used to sign the proposal, see [`sign`].
+    ///
+    /// [`sign`]: Self::sign
+    pub fn finalize_payjoin_proposal(
+        &self,
+        proposal: &mut Psbt,
+        sign_options: SignOptions,
+    ) -> Result<bool, SignerError> {
+        let is_receiver_input = (0..proposal.inputs.len())
+            .map(|index| {
+                proposal
+                    .get_utxo_for(index)
+                    .map_or(false, |txout| self.is_mine(&txout.script_pubkey))
+            })
+            .collect::<Vec<_>>();
+
+        self.sign(proposal, sign_options)?;
+
+        let mut finalized = true;
+        for (input, is_receiver_input) in proposal.inputs.iter_mut().zip(is_receiver_input) {
+            if !is_receiver_input {
+                *input = psbt::Input::default();
+                continue;
+            }
+            finalized &= input.final_script_sig.is_some() || input.final_script_witness.is_some();
+            *input = psbt::Input {
+                witness_utxo: input.witness_utxo.take(),
+                non_witness_utxo: input.non_witness_utxo.take(),
+                final_script_sig: input.final_script_sig.take(),
+                final_script_witness: input.final_script_witness.take(),
+                ..Default::default()
+            };
+        }
+        for output in &mut proposal.outputs {
+            output.bip32_derivation.clear();
+            output.tap_key_origins.clear();
+        }
+        proposal.xpub.clear();
+
+        Ok(finalized)
+    }
+
+    /// Check a payjoin `proposal` against the `original` PSBT sent to the receiver, and sign it.
+    ///
+    /// `payee` is the script pubkey of the receiver's output, and `params` the optional
+    /// parameters sent to the receiver. The proposal must pass the checks listed in BIP78: among
+    /// others, the inputs and outputs of the original PSBT must be kept, the inputs added by
+    /// the receiver must be finalized and of the same type as the sender's, and the fee can only
+    /// be increased with the [`PayjoinParams::additional_fee_output_index`] output. The utxos
+    /// of the sender's inputs are then copied from the original PSBT, and the proposal is signed
+    /// with `sign_options`, see [`sign`].
+    ///
+    /// [`sign`]: Self::sign
+    pub fn sign_payjoin_proposal(
+        &self,
+        original: &Psbt,
+        mut proposal: Psbt,
+        payee: &Script,
+        params: &PayjoinParams,
+        sign_options: SignOptions,
+    ) -> Result<Psbt, ProposalError> {
+        payjoin::check_proposal(original, &proposal, payee, params, |spk| self.is_mine(spk))?;
+
+        for (input, txin) in proposal.inputs.iter_mut().zip(&proposal.unsigned_tx.input) {
+            if let Some(original_input) = original
+                .unsigned_tx
+                .input
+                .iter()
+                .position(|original_txin| original_txin.previous_output == txin.previous_output)
+                .map(|index| &original.inputs[index])
+            {
+                *input = psbt::Input {
+                    witness_utxo: original_input.witness_utxo.clone(),
+                    non_witness_utxo: original_input.non_witness_utxo.clone(),
+                    sighash_type: original_input.sighash_type,
+                    ..Default::default()
+                };
+            }
+        }
+
+        self.sign(&mut proposal, sign_options)?;
+
+        if let Some(required) = params.min_fee_rate {
+            let actual = proposal
+                .fee_rate()
+                .unwrap_or_else(|| FeeRate::from_sat_per_kwu(0));
+            if actual < required {
+                return Err(ProposalError::FeeRateTooLow { required, actual });
+            }
+        }
+
+        Ok(proposal)
+    }
+}
+
+/// Methods to parse [BIP21](uri) payment URIs.
+impl Wallet {
+    /// Parse a `bitcoin:` payment URI, checking that its address is valid for the network of the
+    /// wallet.
+    ///
+    /// The returned [`PaymentUri`] is paid with [`TxBuilder::add_uri_recipient`]. Its label and
+    /// message are not used by the wallet, but may be shown to the user or stored as a
+    /// [label](labels) of the transaction.
+    ///
+    /// [`PaymentUri`]: uri::PaymentUri
+    pub fn parse_payment_uri(&self, uri: &str) -> Result<uri::PaymentUri, uri::PaymentUriError> {
+        uri.parse::<uri::PaymentUri<NetworkUnchecked>>()?
+            .require_network(self.network)
     }
 }
 
@@ -2526,28 +3999,106 @@ fn new_local_utxo(
     }
 }
 
+/// Check that `psbt` can be signed with `sign_options`
+fn check_sign_options(psbt: &Psbt, sign_options: &SignOptions) -> Result<(), SignerError> {
+    // If we aren't allowed to use `witness_utxo`, ensure that every input (except p2tr and finalized ones)
+    // has the `non_witness_utxo`
+    if !sign_options.trust_witness_utxo
+        && psbt
+            .inputs
+            .iter()
+            .filter(|i| i.final_script_witness.is_none() && i.final_script_sig.is_none())
+            .filter(|i| i.tap_internal_key.is_none() && i.tap_merkle_root.is_none())
+            .any(|i| i.non_witness_utxo.is_none())
+    {
+        return Err(SignerError::MissingNonWitnessUtxo);
+    }
+
+    // If the user hasn't explicitly opted-in, refuse to sign the transaction unless every input
+    // is using `SIGHASH_ALL` or `SIGHASH_DEFAULT` for taproot
+    if !sign_options.allow_all_sighashes
+        && !psbt.inputs.iter().all(|i| {
+            i.sighash_type.is_none()
+                || i.sighash_type == Some(EcdsaSighashType::All.into())
+                || i.sighash_type == Some(TapSighashType::All.into())
+                || i.sighash_type == Some(TapSighashType::Default.into())
+        })
+    {
+        return Err(SignerError::NonStandardSighash);
+    }
+
+    Ok(())
+}
+
+/// Move the satisfaction of `txin` to the final fields of `psbt_input`
+fn finalize_input(psbt_input: &mut psbt::Input, txin: TxIn, sign_options: &SignOptions) {
+    psbt_input.final_script_sig = Some(txin.script_sig);
+    psbt_input.final_script_witness = Some(txin.witness);
+    if sign_options.remove_partial_sigs {
+        psbt_input.partial_sigs.clear();
+    }
+    if sign_options.remove_taproot_extras {
+        // We just constructed the final witness, clear these fields.
+        psbt_input.tap_key_sig = None;
+        psbt_input.tap_script_sigs.clear();
+        psbt_input.tap_scripts.clear();
+        psbt_input.tap_key_origins.clear();
+        psbt_input.tap_internal_key = None;
+        psbt_input.tap_merkle_root = None;
+    }
+}
+
+/// The descriptors of the external keychain and, if there is one, of the internal keychain, a
+/// multipath descriptor given without a change descriptor being split between them
+fn keychain_descriptors<E: IntoWalletDescriptor>(
+    descriptor: E,
+    change_descriptor: Option<E>,
+    secp: &Secp256k1<All>,
+    network: Network,
+) -> Result<Vec<(ExtendedDescriptor, KeyMap)>, DescriptorError> {
+    let (descriptor, keymap) = descriptor.into_wallet_descriptor(secp, network)?;
+    match change_descriptor {
+        Some(change_descriptor) => Ok(vec![
+            (descriptor, keymap),
+            change_descriptor.into_wallet_descriptor(secp, network)?,
+        ]),
+        None if descriptor.is_multipath() => {
+            Ok(descriptor::split_multipath(descriptor, keymap)?.to_vec())
+        }
+        None => Ok(vec![(descriptor, keymap)]),
+    }
+}
+
 fn create_signers<E: IntoWalletDescriptor>(
     index: &mut KeychainTxOutIndex<KeychainKind>,
     secp: &Secp256k1<All>,
     descriptor: E,
-    change_descriptor: E,
+    change_descriptor: Option<E>,
     network: Network,
-) -> Result<(Arc<SignersContainer>, Arc<SignersContainer>), DescriptorError> {
-    let descriptor = into_wallet_descriptor_checked(descriptor, secp, network)?;
-    let change_descriptor = into_wallet_descriptor_checked(change_descriptor, secp, network)?;
-    if descriptor.0 == change_descriptor.0 {
-        return Err(DescriptorError::ExternalAndInternalAreTheSame);
+) -> Result<BTreeMap<KeychainKind, Arc<SignersContainer>>, DescriptorError> {
+    let descriptors = keychain_descriptors(descriptor, change_descriptor, secp, network)?
+        .into_iter()
+        .map(|descriptor| into_wallet_descriptor_checked(descriptor, secp, network))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let [(descriptor, _), (change_descriptor, _)] = descriptors.as_slice() {
+        if change_descriptor == descriptor {
+            return Err(DescriptorError::ExternalAndInternalAreTheSame);
+        }
     }
 
-    let (descriptor, keymap) = descriptor;
-    let signers = Arc::new(SignersContainer::build(keymap, &descriptor, secp));
-    let _ = index.insert_descriptor(KeychainKind::External, descriptor);
-
-    let (descriptor, keymap) = change_descriptor;
-    let change_signers = Arc::new(SignersContainer::build(keymap, &descriptor, secp));
-    let _ = index.insert_descriptor(KeychainKind::Internal, descriptor);
+    let mut signers = BTreeMap::new();
+    let keychains = [KeychainKind::External, KeychainKind::Internal]
+        .into_iter()
+        .zip(descriptors);
+    for (keychain, (descriptor, keymap)) in keychains {
+        signers.insert(
+            keychain,
+            Arc::new(SignersContainer::build(keymap, &descriptor, secp)),
+        );
+        let _ = index.insert_descriptor(keychain, descriptor);
+    }
 
-    Ok((signers, change_signers))
+    Ok(signers)
 }
 
 /// Transforms a [`FeeRate`] to `f64` with unit as sat/vb.
@@ -0,0 +1,858 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Payjoin
+//!
+//! This module implements the PSBT side of [BIP78] payjoin, for both the sender and the receiver
+//! of a payment:
+//!
+//! 1. The sender builds, signs and finalizes a regular transaction paying the receiver, the
+//!    *original PSBT*, and sends it to the receiver's payjoin endpoint.
+//! 2. The receiver validates it and gets a [`TxBuilder`] reproducing it with
+//!    [`Wallet::build_payjoin_proposal`], contributes some of its own inputs with
+//!    [`TxBuilder::add_utxo`], and signs the resulting *proposal PSBT* with
+//!    [`Wallet::finalize_payjoin_proposal`] before answering the sender with it.
+//! 3. The sender checks the proposal against the original PSBT and signs it with
+//!    [`Wallet::sign_payjoin_proposal`], and broadcasts the resulting transaction.
+//!
+//! How the PSBTs are exchanged is left to the caller, so any HTTP client or server can be used.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::str::FromStr;
+//! # use bitcoin::*;
+//! # use bdk_wallet::*;
+//! # use bdk_wallet::wallet::payjoin::PayjoinParams;
+//! # let mut sender = doctest_wallet!();
+//! # let mut receiver = doctest_wallet!();
+//! # let receiver_utxo = receiver.list_unspent().next().unwrap().outpoint;
+//! let payee = receiver.reveal_next_address(KeychainKind::External)?.script_pubkey();
+//!
+//! // the sender builds the original PSBT
+//! let mut original = {
+//!     let mut builder = sender.build_tx();
+//!     builder.add_recipient(payee.clone(), Amount::from_sat(50_000));
+//!     builder.finish()?
+//! };
+//! sender.sign(&mut original, SignOptions::default())?;
+//!
+//! // the receiver contributes one of its inputs
+//! let mut proposal = {
+//!     let mut builder = receiver.build_payjoin_proposal(&original)?;
+//!     builder.add_utxo(receiver_utxo)?;
+//!     builder.finish()?
+//! };
+//! receiver.finalize_payjoin_proposal(&mut proposal, SignOptions::default())?;
+//!
+//! // the sender checks and signs the proposal
+//! let payjoin = sender.sign_payjoin_proposal(
+//!     &original,
+//!     proposal,
+//!     &payee,
+//!     &PayjoinParams::default(),
+//!     SignOptions::default(),
+//! )?;
+//! let tx = payjoin.extract_tx()?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BIP78]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+//! [`TxBuilder`]: crate::wallet::tx_builder::TxBuilder
+//! [`TxBuilder::add_utxo`]: crate::wallet::tx_builder::TxBuilder::add_utxo
+//! [`Wallet::build_payjoin_proposal`]: crate::Wallet::build_payjoin_proposal
+//! [`Wallet::finalize_payjoin_proposal`]: crate::Wallet::finalize_payjoin_proposal
+//! [`Wallet::sign_payjoin_proposal`]: crate::Wallet::sign_payjoin_proposal
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::fmt;
+
+use bitcoin::psbt::{self, Psbt};
+use bitcoin::{Address, AddressType, Amount, FeeRate, Network, OutPoint, Script, Weight};
+
+use crate::psbt::PsbtUtils;
+use crate::signer::SignerError;
+
+/// The optional parameters a payjoin sender passes to the receiver.
+///
+/// They have the same meaning as the query parameters defined in BIP78, and are used by
+/// [`Wallet::sign_payjoin_proposal`](crate::Wallet::sign_payjoin_proposal) to check the proposal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayjoinParams {
+    /// The index of the output of the original PSBT the receiver may decrease to increase the
+    /// fee, usually the sender's change output.
+    pub additional_fee_output_index: Option<usize>,
+    /// The maximum amount the receiver may take from the additional fee output.
+    pub max_additional_fee_contribution: Amount,
+    /// The minimum feerate of the proposal once it's signed.
+    pub min_fee_rate: Option<FeeRate>,
+    /// Whether the receiver is forbidden from changing the script pubkey of its output.
+    pub disable_output_substitution: bool,
+}
+
+impl Default for PayjoinParams {
+    fn default() -> Self {
+        Self {
+            additional_fee_output_index: None,
+            max_additional_fee_contribution: Amount::ZERO,
+            min_fee_rate: None,
+            disable_output_substitution: false,
+        }
+    }
+}
+
+/// Errors returned by [`Wallet::build_payjoin_proposal`](crate::Wallet::build_payjoin_proposal)
+/// when the original PSBT is not valid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OriginalPsbtError {
+    /// The original PSBT doesn't have any input.
+    NoInputs,
+    /// The input at this index is not finalized, so the original transaction can't be broadcast.
+    NotFinalized(usize),
+    /// The input at this index has neither a `witness_utxo` nor a `non_witness_utxo`.
+    MissingUtxo(usize),
+    /// The input spends an output of the wallet.
+    InputOwned(OutPoint),
+    /// None of the outputs pays to the wallet.
+    NoReceiverOutput,
+    /// The outputs are worth more than the inputs.
+    NegativeFee,
+}
+
+impl fmt::Display for OriginalPsbtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoInputs => write!(f, "the original PSBT doesn't have any input"),
+            Self::NotFinalized(index) => write!(f, "input {} is not finalized", index),
+            Self::MissingUtxo(index) => write!(f, "input {} is missing its utxo", index),
+            Self::InputOwned(outpoint) => {
+                write!(f, "input {} spends an output of the wallet", outpoint)
+            }
+            Self::NoReceiverOutput => write!(f, "no output pays to the wallet"),
+            Self::NegativeFee => write!(f, "the outputs are worth more than the inputs"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for OriginalPsbtError {}
+
+/// Errors returned by [`Wallet::sign_payjoin_proposal`](crate::Wallet::sign_payjoin_proposal)
+/// when the proposal doesn't pass the BIP78 checks, or can't be signed.
+#[derive(Debug)]
+pub enum ProposalError {
+    /// The transaction version was changed.
+    VersionChanged,
+    /// The transaction locktime was changed.
+    LockTimeChanged,
+    /// An input of the original PSBT is missing.
+    MissingSenderInput(OutPoint),
+    /// The proposal spends this outpoint more than once.
+    DuplicateInput(OutPoint),
+    /// The sequence of an input of the original PSBT was changed.
+    SequenceChanged(OutPoint),
+    /// The inputs don't all have the same sequence.
+    MixedSequence,
+    /// An input has key paths or signatures, or an input of the original PSBT was finalized or
+    /// has its utxo filled in.
+    UnexpectedInputFields(OutPoint),
+    /// An input added by the receiver is not finalized.
+    ReceiverInputNotFinalized(OutPoint),
+    /// An input added by the receiver is missing its utxo.
+    ReceiverInputMissingUtxo(OutPoint),
+    /// An input added by the receiver spends an output of the wallet.
+    ReceiverInputOwned(OutPoint),
+    /// An input added by the receiver has a different script type than the sender's inputs.
+    MixedInputTypes(OutPoint),
+    /// The output at this index has key paths.
+    UnexpectedOutputFields(usize),
+    /// The output at this index of the original PSBT is missing, or its value was decreased.
+    MissingOutput(usize),
+    /// The receiver took more from the additional fee output than allowed.
+    FeeContributionTooHigh(Amount),
+    /// The fee of the original PSBT can't be computed, as an input is missing its utxo or the
+    /// outputs are worth more than the inputs.
+    OriginalFeeUnavailable,
+    /// The fee of the proposal is lower than the fee of the original PSBT.
+    FeeDecreased {
+        /// The fee of the original PSBT.
+        original: Amount,
+        /// The fee of the proposal.
+        proposal: Amount,
+    },
+    /// The feerate of the signed proposal is below [`PayjoinParams::min_fee_rate`].
+    FeeRateTooLow {
+        /// The required feerate.
+        required: FeeRate,
+        /// The feerate of the signed proposal.
+        actual: FeeRate,
+    },
+    /// Error while signing the proposal.
+    Signer(SignerError),
+}
+
+impl fmt::Display for ProposalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::VersionChanged => write!(f, "the transaction version was changed"),
+            Self::LockTimeChanged => write!(f, "the transaction locktime was changed"),
+            Self::MissingSenderInput(outpoint) => write!(f, "input {} is missing", outpoint),
+            Self::DuplicateInput(outpoint) => write!(f, "input {} is spent twice", outpoint),
+            Self::SequenceChanged(outpoint) => {
+                write!(f, "the sequence of input {} was changed", outpoint)
+            }
+            Self::MixedSequence => write!(f, "the inputs don't have the same sequence"),
+            Self::UnexpectedInputFields(outpoint) => {
+                write!(f, "input {} has unexpected fields", outpoint)
+            }
+            Self::ReceiverInputNotFinalized(outpoint) => {
+                write!(f, "receiver input {} is not finalized", outpoint)
+            }
+            Self::ReceiverInputMissingUtxo(outpoint) => {
+                write!(f, "receiver input {} is missing its utxo", outpoint)
+            }
+            Self::ReceiverInputOwned(outpoint) => {
+                write!(
+                    f,
+                    "receiver input {} spends an output of the wallet",
+                    outpoint
+                )
+            }
+            Self::MixedInputTypes(outpoint) => write!(
+                f,
+                "receiver input {} has a different script type than the sender inputs",
+                outpoint
+            ),
+            Self::UnexpectedOutputFields(index) => {
+                write!(f, "output {} has unexpected fields", index)
+            }
+            Self::MissingOutput(index) => {
+                write!(f, "output {} is missing or its value was decreased", index)
+            }
+            Self::FeeContributionTooHigh(contribution) => write!(
+                f,
+                "the additional fee contribution of {} is too high",
+                contribution
+            ),
+            Self::OriginalFeeUnavailable => {
+                write!(f, "the fee of the original PSBT can't be computed")
+            }
+            Self::FeeDecreased { original, proposal } => {
+                write!(f, "the fee was decreased from {} to {}", original, proposal)
+            }
+            Self::FeeRateTooLow { required, actual } => write!(
+                f,
+                "the feerate of {} sat/kwu is below the required {} sat/kwu",
+                actual.to_sat_per_kwu(),
+                required.to_sat_per_kwu()
+            ),
+            Self::Signer(e) => write!(f, "failed to sign the proposal: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ProposalError {}
+
+impl From<SignerError> for ProposalError {
+    fn from(e: SignerError) -> Self {
+        ProposalError::Signer(e)
+    }
+}
+
+/// Check the original PSBT received by a payjoin receiver, returning the index of the output
+/// paying to the receiver and the fee of the original transaction.
+pub(crate) fn check_original(
+    original: &Psbt,
+    is_mine: impl Fn(&Script) -> bool,
+) -> Result<(usize, Amount), OriginalPsbtError> {
+    if original.unsigned_tx.input.is_empty() {
+        return Err(OriginalPsbtError::NoInputs);
+    }
+    let mut input_value = Amount::ZERO;
+    for (index, (input, txin)) in original
+        .inputs
+        .iter()
+        .zip(&original.unsigned_tx.input)
+        .enumerate()
+    {
+        if !is_finalized(input) {
+            return Err(OriginalPsbtError::NotFinalized(index));
+        }
+        let txout = original
+            .get_utxo_for(index)
+            .ok_or(OriginalPsbtError::MissingUtxo(index))?;
+        if is_mine(&txout.script_pubkey) {
+            return Err(OriginalPsbtError::InputOwned(txin.previous_output));
+        }
+        input_value += txout.value;
+    }
+
+    let receiver_output = original
+        .unsigned_tx
+        .output
+        .iter()
+        .position(|txout| is_mine(&txout.script_pubkey))
+        .ok_or(OriginalPsbtError::NoReceiverOutput)?;
+    let output_value = original
+        .unsigned_tx
+        .output
+        .iter()
+        .map(|txout| txout.value)
+        .sum();
+    let fee = input_value
+        .checked_sub(output_value)
+        .ok_or(OriginalPsbtError::NegativeFee)?;
+
+    Ok((receiver_output, fee))
+}
+
+/// Check a payjoin proposal against the original PSBT built by the sender, as described in the
+/// "Senders payjoin proposal checklist" of BIP78.
+///
+/// `original` must be finalized, and `payee` is the script pubkey of the receiver's output.
+pub(crate) fn check_proposal(
+    original: &Psbt,
+    proposal: &Psbt,
+    payee: &Script,
+    params: &PayjoinParams,
+    is_mine: impl Fn(&Script) -> bool,
+) -> Result<(), ProposalError> {
+    let original_tx = &original.unsigned_tx;
+    let proposal_tx = &proposal.unsigned_tx;
+    if proposal_tx.version != original_tx.version {
+        return Err(ProposalError::VersionChanged);
+    }
+    if proposal_tx.lock_time != original_tx.lock_time {
+        return Err(ProposalError::LockTimeChanged);
+    }
+
+    let sender_type = original
+        .get_utxo_for(0)
+        .and_then(|txout| address_type(&txout.script_pubkey));
+    let sequence = proposal_tx.input.first().map(|txin| txin.sequence);
+    let mut seen_inputs = BTreeSet::new();
+    let mut input_value = Amount::ZERO;
+    for (index, (input, txin)) in proposal.inputs.iter().zip(&proposal_tx.input).enumerate() {
+        let outpoint = txin.previous_output;
+        if !seen_inputs.insert(outpoint) {
+            return Err(ProposalError::DuplicateInput(outpoint));
+        }
+        if has_keypaths_or_signatures(input) {
+            return Err(ProposalError::UnexpectedInputFields(outpoint));
+        }
+        if Some(txin.sequence) != sequence {
+            return Err(ProposalError::MixedSequence);
+        }
+
+        match original_tx
+            .input
+            .iter()
+            .position(|original_txin| original_txin.previous_output == outpoint)
+        {
+            Some(original_index) => {
+                if txin.sequence != original_tx.input[original_index].sequence {
+                    return Err(ProposalError::SequenceChanged(outpoint));
+                }
+                if is_finalized(input)
+                    || input.witness_utxo.is_some()
+                    || input.non_witness_utxo.is_some()
+                {
+                    return Err(ProposalError::UnexpectedInputFields(outpoint));
+                }
+                let txout = original
+                    .get_utxo_for(original_index)
+                    .ok_or(ProposalError::MissingSenderInput(outpoint))?;
+                input_value += txout.value;
+            }
+            None => {
+                if !is_finalized(input) {
+                    return Err(ProposalError::ReceiverInputNotFinalized(outpoint));
+                }
+                let txout = proposal
+                    .get_utxo_for(index)
+                    .ok_or(ProposalError::ReceiverInputMissingUtxo(outpoint))?;
+                if is_mine(&txout.script_pubkey) {
+                    return Err(ProposalError::ReceiverInputOwned(outpoint));
+                }
+                if address_type(&txout.script_pubkey) != sender_type {
+                    return Err(ProposalError::MixedInputTypes(outpoint));
+                }
+                input_value += txout.value;
+            }
+        }
+    }
+    if let Some(missing) = original_tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .find(|outpoint| !seen_inputs.contains(outpoint))
+    {
+        return Err(ProposalError::MissingSenderInput(missing));
+    }
+
+    if let Some(index) = proposal.outputs.iter().position(|output| {
+        !output.bip32_derivation.is_empty() || !output.tap_key_origins.is_empty()
+    }) {
+        return Err(ProposalError::UnexpectedOutputFields(index));
+    }
+
+    // match the outputs of the original PSBT by script pubkey, as the receiver may reorder them
+    let mut matched = proposal_tx.output.iter().map(|_| false).collect::<Vec<_>>();
+    let mut contribution = Amount::ZERO;
+    for (index, original_txout) in original_tx.output.iter().enumerate() {
+        let is_payee = original_txout.script_pubkey.as_script() == payee;
+        if is_payee && !params.disable_output_substitution {
+            continue;
+        }
+        let proposal_index = proposal_tx
+            .output
+            .iter()
+            .enumerate()
+            .position(|(i, txout)| {
+                !matched[i] && txout.script_pubkey == original_txout.script_pubkey
+            })
+            .ok_or(ProposalError::MissingOutput(index))?;
+        matched[proposal_index] = true;
+
+        let value = proposal_tx.output[proposal_index].value;
+        if value < original_txout.value {
+            if params.additional_fee_output_index != Some(index) {
+                return Err(ProposalError::MissingOutput(index));
+            }
+            contribution = original_txout.value - value;
+        }
+    }
+
+    let original_fee = (0..original.inputs.len())
+        .map(|index| original.get_utxo_for(index).map(|txout| txout.value))
+        .sum::<Option<Amount>>()
+        .and_then(|value| value.checked_sub(original_tx.output.iter().map(|o| o.value).sum()))
+        .ok_or(ProposalError::OriginalFeeUnavailable)?;
+    let output_value = proposal_tx.output.iter().map(|txout| txout.value).sum();
+    let proposal_fee = input_value
+        .checked_sub(output_value)
+        .unwrap_or(Amount::ZERO);
+    if proposal_fee < original_fee {
+        return Err(ProposalError::FeeDecreased {
+            original: original_fee,
+            proposal: proposal_fee,
+        });
+    }
+
+    if contribution > Amount::ZERO {
+        // the contribution can only pay for the fee of the inputs added by the receiver, at the
+        // feerate of the original transaction
+        let signed_tx = original.clone().extract_tx_unchecked_fee_rate();
+        let original_fee_rate = original_fee / signed_tx.weight();
+        let input_weight = signed_tx
+            .input
+            .first()
+            .map(|txin| txin.segwit_weight())
+            .unwrap_or(Weight::ZERO);
+        let added_inputs = (proposal_tx.input.len() - original_tx.input.len()) as u64;
+        let max_contribution = original_fee_rate * (input_weight * added_inputs);
+        if contribution > params.max_additional_fee_contribution
+            || contribution > proposal_fee - original_fee
+            || contribution > max_contribution
+        {
+            return Err(ProposalError::FeeContributionTooHigh(contribution));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `input` has a final `scriptSig` or witness.
+fn is_finalized(input: &psbt::Input) -> bool {
+    input.final_script_sig.is_some() || input.final_script_witness.is_some()
+}
+
+fn has_keypaths_or_signatures(input: &psbt::Input) -> bool {
+    !input.bip32_derivation.is_empty()
+        || !input.tap_key_origins.is_empty()
+        || !input.partial_sigs.is_empty()
+        || input.tap_key_sig.is_some()
+        || !input.tap_script_sigs.is_empty()
+}
+
+fn address_type(script_pubkey: &Script) -> Option<AddressType> {
+    // the network doesn't change the type of the address
+    Address::from_script(script_pubkey, Network::Bitcoin)
+        .ok()
+        .and_then(|address| address.address_type())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use bitcoin::bip32::{DerivationPath, Fingerprint};
+    use bitcoin::hashes::Hash;
+    use bitcoin::{
+        absolute, transaction, PubkeyHash, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+        WPubkeyHash, Witness,
+    };
+    use core::str::FromStr;
+
+    const FEE: Amount = Amount::from_sat(1_000);
+
+    fn script(byte: u8) -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([byte; 20]))
+    }
+
+    fn sender_spk() -> ScriptBuf {
+        script(1)
+    }
+
+    fn receiver_spk() -> ScriptBuf {
+        script(2)
+    }
+
+    fn payee() -> ScriptBuf {
+        script(3)
+    }
+
+    fn change_spk() -> ScriptBuf {
+        script(4)
+    }
+
+    fn outpoint(byte: u8) -> OutPoint {
+        OutPoint::new(Txid::from_byte_array([byte; 32]), 0)
+    }
+
+    fn txin(outpoint: OutPoint) -> TxIn {
+        TxIn {
+            previous_output: outpoint,
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            ..Default::default()
+        }
+    }
+
+    fn txout(script_pubkey: ScriptBuf, sats: u64) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(sats),
+            script_pubkey,
+        }
+    }
+
+    fn finalize(input: &mut psbt::Input) {
+        input.final_script_witness = Some(Witness::from_slice(&[[0u8; 72], [0u8; 72]]));
+    }
+
+    /// An original PSBT spending a sender input of 100_000 sats to the payee and the sender's
+    /// change.
+    fn original() -> Psbt {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![txin(outpoint(1))],
+            output: vec![txout(payee(), 50_000), txout(change_spk(), 49_000)],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(txout(sender_spk(), 100_000));
+        finalize(&mut psbt.inputs[0]);
+        psbt
+    }
+
+    /// A proposal adding a receiver input of 20_000 sats to the payee output of [`original`].
+    fn proposal() -> Psbt {
+        let mut tx = original().unsigned_tx;
+        tx.input.push(txin(outpoint(2)));
+        tx.output[0].value += Amount::from_sat(20_000);
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[1].witness_utxo = Some(txout(receiver_spk(), 20_000));
+        finalize(&mut psbt.inputs[1]);
+        psbt
+    }
+
+    fn check(proposal: &Psbt, params: &PayjoinParams) -> Result<(), ProposalError> {
+        check_proposal(&original(), proposal, &payee(), params, |spk| {
+            spk == sender_spk().as_script() || spk == change_spk().as_script()
+        })
+    }
+
+    fn check_receiver(original: &Psbt) -> Result<(usize, Amount), OriginalPsbtError> {
+        check_original(original, |spk| spk == payee().as_script())
+    }
+
+    #[test]
+    fn test_check_original() {
+        assert_eq!(check_receiver(&original()), Ok((0, FEE)));
+
+        let mut no_inputs = original();
+        no_inputs.unsigned_tx.input.clear();
+        no_inputs.inputs.clear();
+        assert_eq!(check_receiver(&no_inputs), Err(OriginalPsbtError::NoInputs));
+
+        let mut not_finalized = original();
+        not_finalized.inputs[0].final_script_witness = None;
+        assert_eq!(
+            check_receiver(&not_finalized),
+            Err(OriginalPsbtError::NotFinalized(0))
+        );
+
+        let mut missing_utxo = original();
+        missing_utxo.inputs[0].witness_utxo = None;
+        assert_eq!(
+            check_receiver(&missing_utxo),
+            Err(OriginalPsbtError::MissingUtxo(0))
+        );
+
+        let mut owned = original();
+        owned.inputs[0].witness_utxo = Some(txout(payee(), 100_000));
+        assert_eq!(
+            check_receiver(&owned),
+            Err(OriginalPsbtError::InputOwned(outpoint(1)))
+        );
+
+        let mut no_receiver_output = original();
+        no_receiver_output.unsigned_tx.output[0].script_pubkey = script(5);
+        assert_eq!(
+            check_receiver(&no_receiver_output),
+            Err(OriginalPsbtError::NoReceiverOutput)
+        );
+
+        let mut negative_fee = original();
+        negative_fee.unsigned_tx.output[0].value = Amount::from_sat(60_000);
+        assert_eq!(
+            check_receiver(&negative_fee),
+            Err(OriginalPsbtError::NegativeFee)
+        );
+    }
+
+    #[test]
+    fn test_check_proposal() {
+        let params = PayjoinParams::default();
+        assert!(check(&proposal(), &params).is_ok());
+
+        let mut version = proposal();
+        version.unsigned_tx.version = transaction::Version::ONE;
+        assert!(matches!(
+            check(&version, &params),
+            Err(ProposalError::VersionChanged)
+        ));
+
+        let mut lock_time = proposal();
+        lock_time.unsigned_tx.lock_time = absolute::LockTime::from_consensus(100);
+        assert!(matches!(
+            check(&lock_time, &params),
+            Err(ProposalError::LockTimeChanged)
+        ));
+    }
+
+    #[test]
+    fn test_check_proposal_inputs() {
+        let params = PayjoinParams::default();
+
+        let mut missing = proposal();
+        missing.unsigned_tx.input.remove(0);
+        missing.inputs.remove(0);
+        assert!(matches!(
+            check(&missing, &params),
+            Err(ProposalError::MissingSenderInput(o)) if o == outpoint(1)
+        ));
+
+        let mut mixed_sequence = proposal();
+        mixed_sequence.unsigned_tx.input[1].sequence = Sequence::MAX;
+        assert!(matches!(
+            check(&mixed_sequence, &params),
+            Err(ProposalError::MixedSequence)
+        ));
+
+        let mut sequence = proposal();
+        for txin in &mut sequence.unsigned_tx.input {
+            txin.sequence = Sequence::MAX;
+        }
+        assert!(matches!(
+            check(&sequence, &params),
+            Err(ProposalError::SequenceChanged(o)) if o == outpoint(1)
+        ));
+
+        let mut sender_utxo = proposal();
+        sender_utxo.inputs[0].witness_utxo = Some(txout(sender_spk(), 100_000));
+        assert!(matches!(
+            check(&sender_utxo, &params),
+            Err(ProposalError::UnexpectedInputFields(o)) if o == outpoint(1)
+        ));
+
+        let mut sender_finalized = proposal();
+        finalize(&mut sender_finalized.inputs[0]);
+        assert!(matches!(
+            check(&sender_finalized, &params),
+            Err(ProposalError::UnexpectedInputFields(o)) if o == outpoint(1)
+        ));
+
+        let mut keypaths = proposal();
+        let key = bitcoin::secp256k1::PublicKey::from_str(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+        keypaths.inputs[1]
+            .bip32_derivation
+            .insert(key, (Fingerprint::default(), DerivationPath::master()));
+        assert!(matches!(
+            check(&keypaths, &params),
+            Err(ProposalError::UnexpectedInputFields(o)) if o == outpoint(2)
+        ));
+
+        let mut not_finalized = proposal();
+        not_finalized.inputs[1].final_script_witness = None;
+        assert!(matches!(
+            check(&not_finalized, &params),
+            Err(ProposalError::ReceiverInputNotFinalized(o)) if o == outpoint(2)
+        ));
+
+        let mut missing_utxo = proposal();
+        missing_utxo.inputs[1].witness_utxo = None;
+        assert!(matches!(
+            check(&missing_utxo, &params),
+            Err(ProposalError::ReceiverInputMissingUtxo(o)) if o == outpoint(2)
+        ));
+
+        let mut owned = proposal();
+        owned.inputs[1].witness_utxo = Some(txout(change_spk(), 20_000));
+        assert!(matches!(
+            check(&owned, &params),
+            Err(ProposalError::ReceiverInputOwned(o)) if o == outpoint(2)
+        ));
+
+        let mut mixed_types = proposal();
+        mixed_types.inputs[1].witness_utxo = Some(txout(
+            ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([2; 20])),
+            20_000,
+        ));
+        assert!(matches!(
+            check(&mixed_types, &params),
+            Err(ProposalError::MixedInputTypes(o)) if o == outpoint(2)
+        ));
+    }
+
+    #[test]
+    fn test_check_proposal_duplicate_input() {
+        let params = PayjoinParams::default();
+
+        // a sender input spent twice doesn't leave any input of the original missing
+        let mut duplicate_sender = proposal();
+        duplicate_sender
+            .unsigned_tx
+            .input
+            .insert(0, txin(outpoint(1)));
+        duplicate_sender.inputs.insert(0, psbt::Input::default());
+        assert!(matches!(
+            check(&duplicate_sender, &params),
+            Err(ProposalError::DuplicateInput(o)) if o == outpoint(1)
+        ));
+
+        let mut duplicate_receiver = proposal();
+        duplicate_receiver.unsigned_tx.input.push(txin(outpoint(2)));
+        duplicate_receiver
+            .inputs
+            .push(duplicate_receiver.inputs[1].clone());
+        assert!(matches!(
+            check(&duplicate_receiver, &params),
+            Err(ProposalError::DuplicateInput(o)) if o == outpoint(2)
+        ));
+    }
+
+    #[test]
+    fn test_check_proposal_outputs() {
+        let params = PayjoinParams::default();
+
+        let mut keypaths = proposal();
+        let key = bitcoin::secp256k1::PublicKey::from_str(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+        keypaths.outputs[1]
+            .bip32_derivation
+            .insert(key, (Fingerprint::default(), DerivationPath::master()));
+        assert!(matches!(
+            check(&keypaths, &params),
+            Err(ProposalError::UnexpectedOutputFields(1))
+        ));
+
+        let mut missing_change = proposal();
+        missing_change.unsigned_tx.output.remove(1);
+        missing_change.outputs.remove(1);
+        assert!(matches!(
+            check(&missing_change, &params),
+            Err(ProposalError::MissingOutput(1))
+        ));
+
+        // the payee output can only be substituted if allowed to
+        let mut substituted = proposal();
+        substituted.unsigned_tx.output[0].script_pubkey = script(5);
+        assert!(check(&substituted, &params).is_ok());
+        let no_substitution = PayjoinParams {
+            disable_output_substitution: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            check(&substituted, &no_substitution),
+            Err(ProposalError::MissingOutput(0))
+        ));
+    }
+
+    #[test]
+    fn test_check_proposal_fee() {
+        let params = PayjoinParams::default();
+
+        let mut decreased = proposal();
+        decreased.unsigned_tx.output[1].value += Amount::from_sat(500);
+        assert!(matches!(
+            check(&decreased, &params),
+            Err(ProposalError::FeeDecreased { original, proposal })
+                if original == FEE && proposal == Amount::from_sat(500)
+        ));
+
+        let mut inflated = original();
+        inflated.unsigned_tx.output[0].value = Amount::MAX_MONEY;
+        assert!(matches!(
+            check_proposal(&inflated, &proposal(), &payee(), &params, |_| false),
+            Err(ProposalError::OriginalFeeUnavailable)
+        ));
+
+        // the receiver can only take from the change output if allowed to
+        let mut contribution = proposal();
+        contribution.unsigned_tx.output[1].value -= Amount::from_sat(100);
+        assert!(matches!(
+            check(&contribution, &params),
+            Err(ProposalError::MissingOutput(1))
+        ));
+        let fee_output = PayjoinParams {
+            additional_fee_output_index: Some(1),
+            max_additional_fee_contribution: Amount::from_sat(50),
+            ..Default::default()
+        };
+        assert!(matches!(
+            check(&contribution, &fee_output),
+            Err(ProposalError::FeeContributionTooHigh(c)) if c == Amount::from_sat(100)
+        ));
+        let fee_output = PayjoinParams {
+            max_additional_fee_contribution: Amount::from_sat(100),
+            ..fee_output
+        };
+        assert!(check(&contribution, &fee_output).is_ok());
+
+        // nor more than the fee of the added input at the original feerate
+        let mut too_much = proposal();
+        too_much.unsigned_tx.output[1].value -= Amount::from_sat(5_000);
+        let fee_output = PayjoinParams {
+            max_additional_fee_contribution: Amount::from_sat(5_000),
+            ..fee_output
+        };
+        assert!(matches!(
+            check(&too_much, &fee_output),
+            Err(ProposalError::FeeContributionTooHigh(_))
+        ));
+    }
+}
@@ -0,0 +1,452 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Proof of reserves
+//!
+//! This module implements [BIP127] proofs of reserves. A proof is a transaction spending the
+//! outputs whose ownership is proven, plus a *challenge* input committing to a message, which
+//! spends an output that doesn't exist. The transaction can't be mined, but its signatures prove
+//! that the outputs could be spent when the proof was made.
+//!
+//! Proofs are built with [`Wallet::create_proof_of_reserves`], signed with [`Wallet::sign`] and
+//! checked with [`verify_proof_of_reserves`] against the current UTXO set.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bitcoin::*;
+//! # use bdk_wallet::*;
+//! # use bdk_wallet::wallet::reserves::verify_proof_of_reserves;
+//! # let mut wallet = doctest_wallet!();
+//! let message = "Proof of reserves, block 800000";
+//! let mut proof = wallet.create_proof_of_reserves(message, None)?;
+//! wallet.sign(&mut proof, SignOptions::default())?;
+//!
+//! // the verifier gets the outputs spent by the proof from its own view of the chain
+//! let utxos = wallet.list_unspent().map(|utxo| (utxo.outpoint, utxo.txout));
+//! let amount = verify_proof_of_reserves(&proof, message, utxos)?;
+//! assert_eq!(amount, wallet.balance().total());
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BIP127]: https://github.com/bitcoin/bips/blob/master/bip-0127.mediawiki
+//! [`Wallet::create_proof_of_reserves`]: crate::Wallet::create_proof_of_reserves
+//! [`Wallet::sign`]: crate::Wallet::sign
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use bdk_chain::collections::{BTreeMap, BTreeSet};
+use bitcoin::hashes::{hash160, sha256d, Hash};
+use bitcoin::opcodes::OP_TRUE;
+use bitcoin::script::Builder;
+use bitcoin::sighash::{EcdsaSighashType, TapSighashType};
+use bitcoin::{
+    absolute, psbt, transaction, Amount, OutPoint, Psbt, PubkeyHash, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Txid, Witness,
+};
+use miniscript::interpreter::KeySigPair;
+
+use super::message::verify_input;
+
+/// Errors that can be returned when creating or verifying a proof of reserves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofError {
+    /// The proof doesn't spend any output.
+    NoInputs,
+    /// The outpoint is not an unspent output of the wallet, or of the UTXO set the proof is
+    /// verified against.
+    UnknownUtxo(OutPoint),
+    /// The outpoint is spent by more than one input of the proof.
+    DuplicateInput(OutPoint),
+    /// The first input of the proof doesn't commit to the message.
+    ChallengeInputMismatch,
+    /// The proof doesn't have a single output.
+    WrongNumberOfOutputs,
+    /// The value of the output is not the value of the spent outputs.
+    InAndOutValueNotEqual,
+    /// The input at this index is not finalized.
+    NotFinalized(usize),
+    /// The input at this index has an invalid signature.
+    InvalidSignature(usize),
+    /// The input at this index is spent without any signature.
+    MissingSignature(usize),
+    /// The input at this index is signed with another sighash type than `SIGHASH_ALL`.
+    UnsupportedSighashType(usize),
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoInputs => write!(f, "the proof doesn't spend any output"),
+            Self::UnknownUtxo(outpoint) => write!(f, "unknown unspent output {}", outpoint),
+            Self::DuplicateInput(outpoint) => write!(f, "{} is spent more than once", outpoint),
+            Self::ChallengeInputMismatch => {
+                write!(f, "the challenge input doesn't commit to the message")
+            }
+            Self::WrongNumberOfOutputs => write!(f, "the proof must have a single output"),
+            Self::InAndOutValueNotEqual => {
+                write!(f, "the output value is not the value of the spent outputs")
+            }
+            Self::NotFinalized(index) => write!(f, "input {} is not finalized", index),
+            Self::InvalidSignature(index) => write!(f, "input {} has an invalid signature", index),
+            Self::MissingSignature(index) => write!(f, "input {} is not signed", index),
+            Self::UnsupportedSighashType(index) => {
+                write!(f, "input {} is not signed with SIGHASH_ALL", index)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ProofError {}
+
+/// Verify a finalized proof of reserves of `message`, returning the amount it proves.
+///
+/// `utxos` are the unspent outputs the proof is checked against, which would usually come from
+/// the verifier's view of the chain at the time the proof is made. Every output spent by the
+/// proof must be in `utxos` and spent only once, and every input must be signed with
+/// `SIGHASH_ALL`, so that the signatures commit to the message.
+pub fn verify_proof_of_reserves(
+    proof: &Psbt,
+    message: &str,
+    utxos: impl IntoIterator<Item = (OutPoint, TxOut)>,
+) -> Result<Amount, ProofError> {
+    let tx = &proof.unsigned_tx;
+    if tx.input.len() < 2 {
+        return Err(ProofError::NoInputs);
+    }
+    if tx.input[0].previous_output != challenge_txin(message).previous_output {
+        return Err(ProofError::ChallengeInputMismatch);
+    }
+    if tx.output.len() != 1 {
+        return Err(ProofError::WrongNumberOfOutputs);
+    }
+
+    check_duplicates(tx.input.iter().map(|txin| txin.previous_output))?;
+
+    let utxos = utxos.into_iter().collect::<BTreeMap<_, _>>();
+    let prevouts = core::iter::once(Ok(challenge_txout()))
+        .chain(tx.input[1..].iter().map(|txin| {
+            utxos
+                .get(&txin.previous_output)
+                .cloned()
+                .ok_or(ProofError::UnknownUtxo(txin.previous_output))
+        }))
+        .collect::<Result<Vec<_>, _>>()?;
+    let amount = prevouts.iter().map(|txout| txout.value).sum::<Amount>();
+    if tx.output[0].value != amount {
+        return Err(ProofError::InAndOutValueNotEqual);
+    }
+
+    let mut signed_tx = tx.clone();
+    for (index, (txin, input)) in signed_tx.input.iter_mut().zip(&proof.inputs).enumerate() {
+        if input.final_script_sig.is_none() && input.final_script_witness.is_none() {
+            return Err(ProofError::NotFinalized(index));
+        }
+        txin.script_sig = input.final_script_sig.clone().unwrap_or_default();
+        txin.witness = input.final_script_witness.clone().unwrap_or_default();
+    }
+    for index in 1..signed_tx.input.len() {
+        let signatures = verify_input(&signed_tx, index, &prevouts)
+            .ok_or(ProofError::InvalidSignature(index))?;
+        // an output that can be spent without a signature doesn't prove anything
+        if signatures.is_empty() {
+            return Err(ProofError::MissingSignature(index));
+        }
+        let sighash_all = signatures.iter().all(|key_sig| match key_sig {
+            KeySigPair::Ecdsa(_, sig) => sig.hash_ty == EcdsaSighashType::All,
+            KeySigPair::Schnorr(_, sig) => {
+                sig.hash_ty == TapSighashType::Default || sig.hash_ty == TapSighashType::All
+            }
+        });
+        if !sighash_all {
+            return Err(ProofError::UnsupportedSighashType(index));
+        }
+    }
+
+    Ok(amount)
+}
+
+/// Build the unsigned proof of reserves of `message`, spending the outputs of `utxos` with the
+/// given value and PSBT input.
+pub(crate) fn proof_psbt(
+    message: &str,
+    utxos: Vec<(OutPoint, Amount, psbt::Input)>,
+) -> Result<Psbt, ProofError> {
+    if utxos.is_empty() {
+        return Err(ProofError::NoInputs);
+    }
+    check_duplicates(utxos.iter().map(|(outpoint, _, _)| *outpoint))?;
+    let amount = utxos.iter().map(|(_, value, _)| *value).sum();
+    let (txins, inputs): (Vec<_>, Vec<_>) = utxos
+        .into_iter()
+        .map(|(outpoint, _, input)| {
+            let txin = TxIn {
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            };
+            (txin, input)
+        })
+        .unzip();
+
+    let tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: core::iter::once(challenge_txin(message))
+            .chain(txins)
+            .collect(),
+        output: vec![TxOut {
+            value: amount,
+            script_pubkey: unspendable_script(),
+        }],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx).expect("the transaction is unsigned");
+    // the challenge input spends an `OP_TRUE` output, so it's already final
+    psbt.inputs[0] = psbt::Input {
+        witness_utxo: Some(challenge_txout()),
+        final_script_sig: Some(ScriptBuf::new()),
+        ..Default::default()
+    };
+    for (psbt_input, input) in psbt.inputs[1..].iter_mut().zip(inputs) {
+        *psbt_input = input;
+    }
+
+    Ok(psbt)
+}
+
+/// Fail if an outpoint is spent more than once.
+fn check_duplicates(outpoints: impl IntoIterator<Item = OutPoint>) -> Result<(), ProofError> {
+    let mut spent = BTreeSet::new();
+    for outpoint in outpoints {
+        if !spent.insert(outpoint) {
+            return Err(ProofError::DuplicateInput(outpoint));
+        }
+    }
+    Ok(())
+}
+
+/// The input committing to `message`.
+fn challenge_txin(message: &str) -> TxIn {
+    let mut data = b"Proof-of-Reserves: ".to_vec();
+    data.extend_from_slice(message.as_bytes());
+    TxIn {
+        previous_output: OutPoint::new(Txid::from_raw_hash(sha256d::Hash::hash(&data)), 0),
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::new(),
+    }
+}
+
+/// The output spent by the challenge input.
+fn challenge_txout() -> TxOut {
+    TxOut {
+        value: Amount::ZERO,
+        script_pubkey: Builder::new().push_opcode(OP_TRUE).into_script(),
+    }
+}
+
+/// The script of the output of the proof, a P2PKH of an invalid public key.
+fn unspendable_script() -> ScriptBuf {
+    ScriptBuf::new_p2pkh(&PubkeyHash::from_raw_hash(hash160::Hash::hash(&[0])))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use alloc::format;
+    use alloc::vec;
+    use bitcoin::hashes::sha256;
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::sighash::SighashCache;
+    use bitcoin::{ecdsa, PublicKey};
+    use miniscript::{Miniscript, Segwitv0};
+
+    const MESSAGE: &str = "Proof of reserves";
+    const PREIMAGE: [u8; 32] = [2; 32];
+
+    fn secret_key() -> SecretKey {
+        SecretKey::from_slice(&[1; 32]).unwrap()
+    }
+
+    fn public_key() -> PublicKey {
+        PublicKey::new(secret_key().public_key(&Secp256k1::new()))
+    }
+
+    fn utxos() -> Vec<(OutPoint, TxOut)> {
+        let script_pubkey = ScriptBuf::new_p2wpkh(&public_key().wpubkey_hash().unwrap());
+        [10_000, 20_000]
+            .into_iter()
+            .enumerate()
+            .map(|(vout, value)| {
+                let txout = TxOut {
+                    value: Amount::from_sat(value),
+                    script_pubkey: script_pubkey.clone(),
+                };
+                (OutPoint::new(Txid::all_zeros(), vout as u32), txout)
+            })
+            .collect()
+    }
+
+    /// A proof of `utxos`, whose inputs are signed with `sighash_type`.
+    fn signed_proof(utxos: &[(OutPoint, TxOut)], sighash_type: EcdsaSighashType) -> Psbt {
+        let inputs = utxos
+            .iter()
+            .map(|(outpoint, txout)| {
+                let input = psbt::Input {
+                    witness_utxo: Some(txout.clone()),
+                    ..Default::default()
+                };
+                (*outpoint, txout.value, input)
+            })
+            .collect();
+        let mut proof = proof_psbt(MESSAGE, inputs).unwrap();
+
+        let secp = Secp256k1::new();
+        let mut cache = SighashCache::new(proof.unsigned_tx.clone());
+        for (index, (_, txout)) in utxos.iter().enumerate() {
+            let sighash = cache
+                .p2wpkh_signature_hash(index + 1, &txout.script_pubkey, txout.value, sighash_type)
+                .unwrap();
+            let signature = ecdsa::Signature {
+                sig: secp.sign_ecdsa(&Message::from(sighash), &secret_key()),
+                hash_ty: sighash_type,
+            };
+            proof.inputs[index + 1].final_script_witness =
+                Some(Witness::p2wpkh(&signature, &public_key().inner));
+        }
+        proof
+    }
+
+    #[test]
+    fn test_verify_proof_of_reserves() {
+        let proof = signed_proof(&utxos(), EcdsaSighashType::All);
+        assert_eq!(
+            verify_proof_of_reserves(&proof, MESSAGE, utxos()),
+            Ok(Amount::from_sat(30_000))
+        );
+        assert_eq!(
+            verify_proof_of_reserves(&proof, MESSAGE, utxos().into_iter().skip(1)),
+            Err(ProofError::UnknownUtxo(utxos()[0].0))
+        );
+    }
+
+    #[test]
+    fn test_verify_proof_of_reserves_challenge() {
+        let proof = signed_proof(&utxos(), EcdsaSighashType::All);
+        assert_eq!(
+            verify_proof_of_reserves(&proof, "Another message", utxos()),
+            Err(ProofError::ChallengeInputMismatch)
+        );
+
+        let mut no_inputs = proof.clone();
+        no_inputs.unsigned_tx.input.truncate(1);
+        no_inputs.inputs.truncate(1);
+        assert_eq!(
+            verify_proof_of_reserves(&no_inputs, MESSAGE, utxos()),
+            Err(ProofError::NoInputs)
+        );
+    }
+
+    #[test]
+    fn test_verify_proof_of_reserves_duplicate_input() {
+        let mut proof = signed_proof(&utxos(), EcdsaSighashType::All);
+        proof
+            .unsigned_tx
+            .input
+            .push(proof.unsigned_tx.input[1].clone());
+        proof.inputs.push(proof.inputs[1].clone());
+        assert_eq!(
+            verify_proof_of_reserves(&proof, MESSAGE, utxos()),
+            Err(ProofError::DuplicateInput(utxos()[0].0))
+        );
+    }
+
+    #[test]
+    fn test_verify_proof_of_reserves_outputs() {
+        let proof = signed_proof(&utxos(), EcdsaSighashType::All);
+
+        let mut tampered = proof.clone();
+        tampered.unsigned_tx.output[0].value = Amount::from_sat(40_000);
+        assert_eq!(
+            verify_proof_of_reserves(&tampered, MESSAGE, utxos()),
+            Err(ProofError::InAndOutValueNotEqual)
+        );
+
+        let mut tampered = proof;
+        tampered.unsigned_tx.output.push(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: unspendable_script(),
+        });
+        tampered.outputs.push(Default::default());
+        assert_eq!(
+            verify_proof_of_reserves(&tampered, MESSAGE, utxos()),
+            Err(ProofError::WrongNumberOfOutputs)
+        );
+    }
+
+    #[test]
+    fn test_verify_proof_of_reserves_signatures() {
+        let proof = signed_proof(&utxos(), EcdsaSighashType::All);
+
+        let mut not_finalized = proof.clone();
+        not_finalized.inputs[2].final_script_witness = None;
+        assert_eq!(
+            verify_proof_of_reserves(&not_finalized, MESSAGE, utxos()),
+            Err(ProofError::NotFinalized(2))
+        );
+
+        // the signature of the first input doesn't match the second one
+        let mut invalid = proof.clone();
+        invalid.inputs[2].final_script_witness = proof.inputs[1].final_script_witness.clone();
+        assert_eq!(
+            verify_proof_of_reserves(&invalid, MESSAGE, utxos()),
+            Err(ProofError::InvalidSignature(2))
+        );
+
+        // the signatures don't commit to the other inputs, including the challenge
+        let sighash_none = signed_proof(&utxos(), EcdsaSighashType::None);
+        assert_eq!(
+            verify_proof_of_reserves(&sighash_none, MESSAGE, utxos()),
+            Err(ProofError::UnsupportedSighashType(1))
+        );
+    }
+
+    #[test]
+    fn test_verify_proof_of_reserves_missing_signature() {
+        // an output anyone knowing the preimage can spend
+        let hash = sha256::Hash::hash(&PREIMAGE);
+        let witness_script =
+            Miniscript::<PublicKey, Segwitv0>::from_str_insane(&format!("sha256({})", hash))
+                .unwrap()
+                .encode();
+        let txout = TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: witness_script.to_p2wsh(),
+        };
+        let outpoint = OutPoint::new(Txid::all_zeros(), 2);
+        let input = psbt::Input {
+            witness_utxo: Some(txout.clone()),
+            final_script_witness: Some(Witness::from_slice(&[
+                PREIMAGE.to_vec(),
+                witness_script.into_bytes(),
+            ])),
+            ..Default::default()
+        };
+        let proof = proof_psbt(MESSAGE, vec![(outpoint, txout.value, input)]).unwrap();
+        assert_eq!(
+            verify_proof_of_reserves(&proof, MESSAGE, vec![(outpoint, txout)]),
+            Err(ProofError::MissingSignature(1))
+        );
+    }
+}
@@ -283,6 +283,22 @@ pub trait TransactionSigner: SignerCommon {
         sign_options: &SignOptions,
         secp: &SecpCtx,
     ) -> Result<(), SignerError>;
+
+    /// Sign the inputs of the psbt at `input_indexes`
+    ///
+    /// The [`Wallet`](crate::Wallet) uses it to only sign the inputs of the keychain the signer
+    /// belongs to. Signers that can't sign inputs individually sign all the inputs, which is the
+    /// default for this method, if not overridden.
+    fn sign_inputs(
+        &self,
+        psbt: &mut Psbt,
+        input_indexes: &[usize],
+        sign_options: &SignOptions,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        let _ = input_indexes;
+        self.sign_transaction(psbt, sign_options, secp)
+    }
 }
 
 impl<T: InputSigner> TransactionSigner for T {
@@ -298,6 +314,20 @@ impl<T: InputSigner> TransactionSigner for T {
 
         Ok(())
     }
+
+    fn sign_inputs(
+        &self,
+        psbt: &mut Psbt,
+        input_indexes: &[usize],
+        sign_options: &SignOptions,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        for input_index in input_indexes {
+            self.sign_input(psbt, *input_index, sign_options, secp)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl SignerCommon for SignerWrapper<DescriptorXKey<Xpriv>> {
@@ -0,0 +1,560 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Silent payments
+//!
+//! This module implements sending to [BIP352] silent payment addresses. A silent payment address
+//! contains a scan and a spend public key, and the output paying it is derived from these keys
+//! and the private keys of the inputs of the transaction, so that only the receiver can find it.
+//!
+//! Silent payment recipients are added with [`TxBuilder::add_silent_payment_recipient`]. Their
+//! outputs are derived after coin selection, which only selects inputs eligible under BIP352:
+//! P2PKH with a compressed key, P2SH-P2WPKH, P2WPKH and P2TR outputs of the wallet. The outputs
+//! paying a silent payment address are found with a [`SilentPaymentIndex`].
+//!
+//! As the outputs depend on the inputs, adding inputs to the transaction would make the receivers
+//! miss them. When bumping the fee of such a transaction with [`Wallet::build_fee_bump`], call
+//! [`TxBuilder::fixed_inputs`] so that only its original inputs are spent.
+//!
+//! ## Example
+//!
+//! ```
+//! # use std::str::FromStr;
+//! # use bitcoin::*;
+//! # use bdk_wallet::*;
+//! # use bdk_wallet::wallet::silent_payments::SilentPaymentAddress;
+//! # let mut wallet = doctest_wallet!();
+//! let address = SilentPaymentAddress::from_str("sprt1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xcrdz399")?;
+//!
+//! let mut builder = wallet.build_tx();
+//! builder.add_silent_payment_recipient(address, Amount::from_sat(50_000));
+//! let mut psbt = builder.finish()?;
+//! wallet.sign(&mut psbt, SignOptions::default())?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BIP352]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+//! [`TxBuilder::add_silent_payment_recipient`]: super::tx_builder::TxBuilder::add_silent_payment_recipient
+//! [`TxBuilder::fixed_inputs`]: super::tx_builder::TxBuilder::fixed_inputs
+//! [`SilentPaymentIndex`]: bdk_chain::silent_payments::SilentPaymentIndex
+//! [`Wallet::build_fee_bump`]: crate::Wallet::build_fee_bump
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use bdk_chain::silent_payments::{input_hash, output_tweak};
+use bitcoin::bech32::primitives::decode::{CheckedHrpstring, CheckedHrpstringError};
+use bitcoin::bech32::{Bech32m, ByteIterExt, Fe32, Fe32IterExt, Hrp};
+use bitcoin::key::{Keypair, Parity, TapTweak, TweakedPublicKey};
+use bitcoin::secp256k1::{self, PublicKey, Scalar, SecretKey, Verification, XOnlyPublicKey};
+use bitcoin::taproot::TapNodeHash;
+#[cfg(test)]
+use bitcoin::Txid;
+use bitcoin::{Network, OutPoint, ScriptBuf};
+
+use super::utils::SecpCtx;
+
+/// The length of the payload of a version 0 address, a scan and a spend public key.
+const PAYLOAD_LENGTH: usize = 66;
+
+/// A [BIP352] silent payment address.
+///
+/// [BIP352]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SilentPaymentAddress {
+    scan_key: PublicKey,
+    spend_key: PublicKey,
+    network: Network,
+}
+
+impl SilentPaymentAddress {
+    /// Create the address of a `scan_key` and a `spend_key` on `network`.
+    pub fn new(scan_key: PublicKey, spend_key: PublicKey, network: Network) -> Self {
+        SilentPaymentAddress {
+            scan_key,
+            spend_key,
+            network,
+        }
+    }
+
+    /// The scan public key of the address.
+    pub fn scan_key(&self) -> PublicKey {
+        self.scan_key
+    }
+
+    /// The spend public key of the address, which may be tweaked with a label.
+    pub fn spend_key(&self) -> PublicKey {
+        self.spend_key
+    }
+
+    /// The network of the address.
+    ///
+    /// Testnet and signet addresses share the same encoding, and are both parsed as
+    /// [`Network::Testnet`], see [`is_valid_for_network`](Self::is_valid_for_network).
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Whether the address can be paid on `network`.
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        hrp(self.network) == hrp(network)
+    }
+}
+
+impl fmt::Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hrp = hrp(self.network);
+        let payload = self
+            .scan_key
+            .serialize()
+            .into_iter()
+            .chain(self.spend_key.serialize());
+        for c in payload
+            .bytes_to_fes()
+            .with_checksum::<Bech32m>(&hrp)
+            .with_witness_version(Fe32::Q)
+            .chars()
+        {
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = SilentPaymentAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let checked = CheckedHrpstring::new::<Bech32m>(s)?;
+        let network = match checked.hrp().to_string().as_str() {
+            "sp" => Network::Bitcoin,
+            "tsp" => Network::Testnet,
+            "sprt" => Network::Regtest,
+            hrp => return Err(SilentPaymentAddressError::UnknownHrp(hrp.to_string())),
+        };
+
+        // the version and the payload are between the separator and the checksum
+        let data = &s[s.rfind('1').expect("checked") + 1..s.len() - 6];
+        let mut fes = data.chars().map(|c| Fe32::from_char(c).expect("checked"));
+        let version = fes
+            .next()
+            .ok_or(SilentPaymentAddressError::InvalidLength(0))?
+            .to_u8();
+        let payload = fes.fes_to_bytes().collect::<Vec<_>>();
+        match version {
+            0 if payload.len() != PAYLOAD_LENGTH => {
+                return Err(SilentPaymentAddressError::InvalidLength(payload.len()))
+            }
+            // later versions are backward compatible, and may only append data to the payload
+            1..=30 if payload.len() < PAYLOAD_LENGTH => {
+                return Err(SilentPaymentAddressError::InvalidLength(payload.len()))
+            }
+            31 => return Err(SilentPaymentAddressError::InvalidVersion(version)),
+            _ => {}
+        }
+
+        Ok(SilentPaymentAddress {
+            scan_key: PublicKey::from_slice(&payload[..33])?,
+            spend_key: PublicKey::from_slice(&payload[33..PAYLOAD_LENGTH])?,
+            network,
+        })
+    }
+}
+
+/// Errors that can be returned when parsing a [`SilentPaymentAddress`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SilentPaymentAddressError {
+    /// The address is not a valid bech32m string.
+    Bech32(CheckedHrpstringError),
+    /// The human readable part of the address is unknown.
+    UnknownHrp(String),
+    /// The version of the address is not supported.
+    InvalidVersion(u8),
+    /// The payload of the address doesn't have the length required by its version.
+    InvalidLength(usize),
+    /// A key of the address is invalid.
+    InvalidKey(secp256k1::Error),
+}
+
+impl fmt::Display for SilentPaymentAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bech32(e) => e.fmt(f),
+            Self::UnknownHrp(hrp) => write!(f, "unknown human readable part `{}`", hrp),
+            Self::InvalidVersion(version) => write!(f, "unsupported version {}", version),
+            Self::InvalidLength(length) => write!(f, "invalid payload length {}", length),
+            Self::InvalidKey(e) => e.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SilentPaymentAddressError {}
+
+impl From<CheckedHrpstringError> for SilentPaymentAddressError {
+    fn from(e: CheckedHrpstringError) -> Self {
+        Self::Bech32(e)
+    }
+}
+
+impl From<secp256k1::Error> for SilentPaymentAddressError {
+    fn from(e: secp256k1::Error) -> Self {
+        Self::InvalidKey(e)
+    }
+}
+
+/// Errors that can be returned when deriving the outputs of silent payment recipients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SilentPaymentError {
+    /// The address of the recipient at this index is for another network.
+    NetworkMismatch(usize),
+    /// The input doesn't spend an output type eligible under BIP352, or isn't owned by the
+    /// wallet.
+    IneligibleInput(OutPoint),
+    /// The wallet doesn't have the private key of the input.
+    MissingKey(OutPoint),
+    /// The private keys of the inputs sum to zero, or a derived tweak is invalid.
+    InvalidInputKeys,
+}
+
+impl fmt::Display for SilentPaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NetworkMismatch(index) => {
+                write!(
+                    f,
+                    "the address of recipient {} is for another network",
+                    index
+                )
+            }
+            Self::IneligibleInput(outpoint) => {
+                write!(f, "input {} is not eligible for silent payments", outpoint)
+            }
+            Self::MissingKey(outpoint) => write!(f, "missing private key of input {}", outpoint),
+            Self::InvalidInputKeys => write!(f, "invalid input private keys"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SilentPaymentError {}
+
+/// The script of an output paying a silent payment recipient before its key is derived.
+///
+/// It has the weight of the final P2TR output.
+pub(crate) fn placeholder_script() -> ScriptBuf {
+    ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(
+        XOnlyPublicKey::from_slice(&[1; 32]).expect("valid x-only key"),
+    ))
+}
+
+/// The private key used by BIP352 for a P2TR input, i.e. the key of the output key, negated if
+/// the output key has an odd y-coordinate.
+pub(crate) fn taproot_input_key(
+    secp: &SecpCtx,
+    internal_key: SecretKey,
+    merkle_root: Option<TapNodeHash>,
+) -> SecretKey {
+    let keypair = Keypair::from_secret_key(secp, &internal_key)
+        .tap_tweak(secp, merkle_root)
+        .to_inner();
+    match keypair.x_only_public_key().1 {
+        Parity::Even => keypair.secret_key(),
+        Parity::Odd => keypair.secret_key().negate(),
+    }
+}
+
+/// Derive the output keys paying `recipients` from a transaction spending `outpoints`.
+///
+/// `input_keys` are the private keys of the eligible inputs, see [`taproot_input_key`] for P2TR
+/// inputs. The returned keys are in the order of `recipients`.
+pub(crate) fn sender_output_keys<'a>(
+    secp: &SecpCtx,
+    input_keys: &[SecretKey],
+    outpoints: impl IntoIterator<Item = &'a OutPoint>,
+    recipients: &[SilentPaymentAddress],
+) -> Result<Vec<XOnlyPublicKey>, SilentPaymentError> {
+    let (first, rest) = input_keys
+        .split_first()
+        .ok_or(SilentPaymentError::InvalidInputKeys)?;
+    let input_key = rest.iter().try_fold(*first, |sum, key| {
+        sum.add_tweak(&Scalar::from(*key))
+            .map_err(|_| SilentPaymentError::InvalidInputKeys)
+    })?;
+    let input_hash = input_hash(outpoints, &input_key.public_key(secp))
+        .ok_or(SilentPaymentError::InvalidInputKeys)?;
+    let input_key = input_key
+        .mul_tweak(&input_hash)
+        .map_err(|_| SilentPaymentError::InvalidInputKeys)?;
+
+    // recipients sharing a scan key are paid with increasing values of `k`
+    let mut shared_secrets = Vec::<(PublicKey, PublicKey, u32)>::new();
+    recipients
+        .iter()
+        .map(|recipient| {
+            let position = shared_secrets
+                .iter()
+                .position(|(scan_key, _, _)| *scan_key == recipient.scan_key);
+            let (_, shared_secret, k) = match position {
+                Some(position) => &mut shared_secrets[position],
+                None => {
+                    let shared_secret = recipient
+                        .scan_key
+                        .mul_tweak(secp, &Scalar::from(input_key))
+                        .map_err(|_| SilentPaymentError::InvalidInputKeys)?;
+                    shared_secrets.push((recipient.scan_key, shared_secret, 0));
+                    shared_secrets.last_mut().expect("just pushed")
+                }
+            };
+            let output_key = output_key(secp, shared_secret, *k, &recipient.spend_key)?;
+            *k += 1;
+            Ok(output_key)
+        })
+        .collect()
+}
+
+/// The output key `B_spend + t_k·G` of the `k`-th output paying `spend_key` with
+/// `shared_secret`.
+pub(crate) fn output_key<C: Verification>(
+    secp: &secp256k1::Secp256k1<C>,
+    shared_secret: &PublicKey,
+    k: u32,
+    spend_key: &PublicKey,
+) -> Result<XOnlyPublicKey, SilentPaymentError> {
+    let tweak = output_tweak(shared_secret, k).ok_or(SilentPaymentError::InvalidInputKeys)?;
+    let output_key = spend_key
+        .add_exp_tweak(secp, &Scalar::from(tweak))
+        .map_err(|_| SilentPaymentError::InvalidInputKeys)?;
+    Ok(output_key.x_only_public_key().0)
+}
+
+/// The human readable part of the addresses of `network`.
+fn hrp(network: Network) -> Hrp {
+    let hrp = match network {
+        Network::Bitcoin => "sp",
+        Network::Regtest => "sprt",
+        _ => "tsp",
+    };
+    Hrp::parse(hrp).expect("valid human readable part")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::Secp256k1;
+
+    // test vectors from BIP352
+    const ADDRESS: &str = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv";
+    const INPUT_KEYS: [&str; 2] = [
+        "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+        "93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16",
+    ];
+    const OUTPOINTS: [&str; 2] = [
+        "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16:0",
+        "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d:0",
+    ];
+
+    fn secret_key(hex: &str) -> SecretKey {
+        SecretKey::from_str(hex).unwrap()
+    }
+
+    fn output_key(hex: &str) -> XOnlyPublicKey {
+        XOnlyPublicKey::from_str(hex).unwrap()
+    }
+
+    #[test]
+    fn test_address() {
+        let secp = Secp256k1::new();
+        let scan_key =
+            secret_key("0f694e068028a717f8af6b9411f9a133dd3565258714cc226594b34db90c1f2c");
+        let spend_key =
+            secret_key("9d6ad855ce3417ef84e836892e5a56392bfba05fa5d97ccea30e266f540e08b3");
+        let address = SilentPaymentAddress::from_str(ADDRESS).unwrap();
+        assert_eq!(address.scan_key(), scan_key.public_key(&secp));
+        assert_eq!(address.spend_key(), spend_key.public_key(&secp));
+        assert_eq!(address.network(), Network::Bitcoin);
+        assert_eq!(address.to_string(), ADDRESS);
+
+        let testnet =
+            SilentPaymentAddress::new(address.scan_key(), address.spend_key(), Network::Signet);
+        assert!(testnet.to_string().starts_with("tsp1q"));
+        let parsed = SilentPaymentAddress::from_str(&testnet.to_string()).unwrap();
+        assert_eq!(parsed.network(), Network::Testnet);
+        assert!(parsed.is_valid_for_network(Network::Signet));
+        assert!(!parsed.is_valid_for_network(Network::Bitcoin));
+    }
+
+    #[test]
+    fn test_address_errors() {
+        assert!(matches!(
+            SilentPaymentAddress::from_str(&ADDRESS.replace("sp1", "bc1")),
+            Err(SilentPaymentAddressError::Bech32(_))
+        ));
+
+        let address = SilentPaymentAddress::from_str(ADDRESS).unwrap();
+        let encode = |hrp: &str, version: Fe32, payload: &[u8]| {
+            payload
+                .iter()
+                .copied()
+                .bytes_to_fes()
+                .with_checksum::<Bech32m>(&Hrp::parse(hrp).unwrap())
+                .with_witness_version(version)
+                .chars()
+                .collect::<String>()
+        };
+        let payload = [address.scan_key.serialize(), address.spend_key.serialize()].concat();
+        assert_eq!(
+            SilentPaymentAddress::from_str(&encode("bc", Fe32::Q, &payload)),
+            Err(SilentPaymentAddressError::UnknownHrp("bc".to_string()))
+        );
+        assert_eq!(
+            SilentPaymentAddress::from_str(&encode("sp", Fe32::Q, &payload[..65])),
+            Err(SilentPaymentAddressError::InvalidLength(65))
+        );
+        assert_eq!(
+            SilentPaymentAddress::from_str(&encode("sp", Fe32::L, &payload)),
+            Err(SilentPaymentAddressError::InvalidVersion(31))
+        );
+
+        // later versions may append data to the payload
+        let extended = [payload.as_slice(), &[0; 4]].concat();
+        assert_eq!(
+            SilentPaymentAddress::from_str(&encode("sp", Fe32::P, &extended)),
+            Ok(address)
+        );
+        assert_eq!(
+            SilentPaymentAddress::from_str(&encode("sp", Fe32::Q, &extended)),
+            Err(SilentPaymentAddressError::InvalidLength(70))
+        );
+    }
+
+    #[test]
+    fn test_simple_send() {
+        let secp = Secp256k1::new();
+        let input_keys = INPUT_KEYS.map(secret_key);
+        let outpoints = OUTPOINTS.map(|op| OutPoint::from_str(op).unwrap());
+        let recipients = [SilentPaymentAddress::from_str(ADDRESS).unwrap()];
+        let expected = vec![output_key(
+            "3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1",
+        )];
+
+        assert_eq!(
+            sender_output_keys(&secp, &input_keys, &outpoints, &recipients).unwrap(),
+            expected
+        );
+        // the order of the inputs doesn't matter
+        let [first, second] = outpoints;
+        assert_eq!(
+            sender_output_keys(&secp, &input_keys, &[second, first], &recipients).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_outpoint_ordering() {
+        let secp = Secp256k1::new();
+        let input_keys = INPUT_KEYS.map(secret_key);
+        let recipients = [SilentPaymentAddress::from_str(ADDRESS).unwrap()];
+        let txid = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+
+        // outpoints are compared by their serialization, not by their vout
+        for (vouts, expected) in [
+            (
+                [3, 7],
+                "79e71baa2ba3fc66396de3a04f168c7bf24d6870ec88ca877754790c1db357b6",
+            ),
+            (
+                [1, 256],
+                "a85ef8701394b517a4b35217c4bd37ac01ebeed4b008f8d0879f9e09ba95319c",
+            ),
+        ] {
+            let outpoints = vouts.map(|vout| OutPoint::new(Txid::from_str(txid).unwrap(), vout));
+            assert_eq!(
+                sender_output_keys(&secp, &input_keys, &outpoints, &recipients).unwrap(),
+                vec![output_key(expected)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_taproot_inputs() {
+        let secp = Secp256k1::new();
+        let outpoints = OUTPOINTS.map(|op| OutPoint::from_str(op).unwrap());
+        let recipients = [SilentPaymentAddress::from_str(ADDRESS).unwrap()];
+        // the test vectors spend P2TR outputs of untweaked keys
+        let input_key = |hex: &str| {
+            let key = secret_key(hex);
+            match key.x_only_public_key(&secp).1 {
+                Parity::Even => key,
+                Parity::Odd => key.negate(),
+            }
+        };
+
+        for (keys, expected) in [
+            (
+                [
+                    "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                    "fc8716a97a48ba9a05a98ae47b5cd201a25a7fd5d8b73c203c5f7b6b6b3b6ad7",
+                ],
+                "de88bea8e7ffc9ce1af30d1132f910323c505185aec8eae361670421e749a1fb",
+            ),
+            // the second key has an odd y-coordinate
+            (
+                [
+                    "eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1",
+                    "1d37787c2b7116ee983e9f9c13269df29091b391c04db94239e0d2bc2182c3bf",
+                ],
+                "77cab7dd12b10259ee82c6ea4b509774e33e7078e7138f568092241bf26b99f1",
+            ),
+        ] {
+            let input_keys = keys.map(input_key);
+            assert_eq!(
+                sender_output_keys(&secp, &input_keys, &outpoints, &recipients).unwrap(),
+                vec![output_key(expected)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_same_scan_key() {
+        let secp = Secp256k1::new();
+        let input_keys = INPUT_KEYS.map(secret_key);
+        let outpoints = OUTPOINTS.map(|op| OutPoint::from_str(op).unwrap());
+        let address = SilentPaymentAddress::from_str(ADDRESS).unwrap();
+
+        let output_keys =
+            sender_output_keys(&secp, &input_keys, &outpoints, &[address, address]).unwrap();
+        assert_eq!(
+            output_keys[0],
+            output_key("3e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1")
+        );
+        assert_ne!(output_keys[0], output_keys[1]);
+    }
+
+    #[test]
+    fn test_taproot_input_key() {
+        let secp = Secp256k1::new();
+        let internal_key = secret_key(INPUT_KEYS[0]);
+        let merkle_root = Some(TapNodeHash::from_byte_array([1; 32]));
+        let (output_key, _) = internal_key
+            .x_only_public_key(&secp)
+            .0
+            .tap_tweak(&secp, merkle_root);
+
+        let input_key = taproot_input_key(&secp, internal_key, merkle_root);
+        assert_eq!(
+            input_key.x_only_public_key(&secp),
+            (output_key.to_inner(), Parity::Even)
+        );
+    }
+}
@@ -0,0 +1,310 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Sweeping external keys
+//!
+//! This module allows moving the funds of keys that are not part of the wallet, like a WIF key
+//! from a paper wallet or an old extended private key, to the wallet. A [`Sweep`] holds the
+//! descriptors of these keys and their secrets: its [`spks`](Sweep::spks) are scanned with a chain
+//! source, [`Wallet::build_sweep`] builds a transaction spending the outputs found, and
+//! [`Sweep::sign`] signs it. The keys are never added to the wallet's signers or persisted.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bitcoin::*;
+//! # use bdk_wallet::*;
+//! # use bdk_wallet::chain::{ConfirmationTimeHeightAnchor, TxGraph};
+//! # use bitcoin::hashes::Hash;
+//! # use bdk_wallet::wallet::sweep::Sweep;
+//! # let mut wallet = doctest_wallet!();
+//! let key = PrivateKey::from_wif("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW")?;
+//! let sweep = Sweep::from_private_key(key);
+//!
+//! // the scripts of the key are synced with a chain source, returning a graph of transactions
+//! let spks = sweep.spks();
+//! # let mut graph = TxGraph::<ConfirmationTimeHeightAnchor>::default();
+//! # let _ = graph.insert_tx(Transaction {
+//! #     version: transaction::Version::TWO,
+//! #     lock_time: absolute::LockTime::ZERO,
+//! #     input: vec![TxIn { previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0), ..Default::default() }],
+//! #     output: vec![TxOut { value: Amount::from_sat(50_000), script_pubkey: spks[1].clone() }],
+//! # });
+//!
+//! let mut builder = wallet.build_sweep(&sweep, &graph)?;
+//! builder.fee_rate(FeeRate::from_sat_per_vb(2).unwrap());
+//! let mut psbt = builder.finish()?;
+//! assert!(sweep.sign(&mut psbt, SignOptions::default())?);
+//! let tx = psbt.extract_tx()?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`Wallet::build_sweep`]: crate::Wallet::build_sweep
+
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use core::fmt;
+
+use bdk_chain::collections::BTreeMap;
+use bdk_chain::{Anchor, BlockId, ChainOracle, SpkIterator, TxGraph};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{psbt, Network, OutPoint, PrivateKey, Psbt, ScriptBuf, TxIn};
+use miniscript::descriptor::KeyMap;
+use miniscript::psbt::{PsbtInputExt, PsbtInputSatisfier};
+
+use super::signer::{SignOptions, SignerError, SignersContainer};
+use super::utils::SecpCtx;
+use super::{check_sign_options, finalize_input};
+use crate::descriptor::{
+    into_wallet_descriptor_checked, DescriptorError, DescriptorMeta, ExtendedDescriptor,
+    IntoWalletDescriptor,
+};
+use crate::psbt::PsbtUtils;
+
+/// The number of addresses of a descriptor with a wildcard that are swept by default.
+const DEFAULT_LOOKAHEAD: u32 = 100;
+
+/// Errors that can be returned when sweeping external keys.
+#[derive(Debug)]
+pub enum SweepError {
+    /// The descriptor is invalid.
+    Descriptor(DescriptorError),
+    /// The descriptor doesn't contain any secret key.
+    MissingSecretKey,
+    /// No unspent output of the swept keys was found.
+    NoUtxos,
+    /// Revealing the address receiving the funds failed.
+    Persist(anyhow::Error),
+}
+
+impl fmt::Display for SweepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Descriptor(e) => e.fmt(f),
+            Self::MissingSecretKey => write!(f, "the descriptor doesn't contain any secret key"),
+            Self::NoUtxos => write!(f, "no unspent output to sweep"),
+            Self::Persist(e) => e.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SweepError {}
+
+impl From<DescriptorError> for SweepError {
+    fn from(e: DescriptorError) -> Self {
+        Self::Descriptor(e)
+    }
+}
+
+/// External keys whose funds are swept into a wallet.
+///
+/// See the [module-level documentation](self) for an example.
+#[derive(Debug, Clone)]
+pub struct Sweep {
+    descriptors: Vec<(ExtendedDescriptor, KeyMap)>,
+    lookahead: u32,
+    secp: SecpCtx,
+}
+
+/// An unspent output of a [`Sweep`].
+#[derive(Debug, Clone)]
+pub(crate) struct SweepUtxo {
+    pub(crate) outpoint: OutPoint,
+    pub(crate) psbt_input: psbt::Input,
+    pub(crate) satisfaction_weight: usize,
+}
+
+impl Sweep {
+    /// Sweep the outputs of a single private key, e.g. a WIF key from a paper wallet.
+    ///
+    /// P2PKH outputs are swept, as well as P2WPKH, P2SH-P2WPKH and P2TR outputs if the key is
+    /// compressed.
+    pub fn from_private_key(key: PrivateKey) -> Self {
+        let wif = key.to_wif();
+        let mut descriptors = vec![format!("pkh({})", wif)];
+        if key.compressed {
+            descriptors.push(format!("wpkh({})", wif));
+            descriptors.push(format!("sh(wpkh({}))", wif));
+            descriptors.push(format!("tr({})", wif));
+        }
+
+        let secp = Secp256k1::new();
+        let descriptors = descriptors
+            .iter()
+            .map(|descriptor| {
+                into_wallet_descriptor_checked(descriptor.as_str(), &secp, key.network)
+                    .expect("the descriptors of a single key are valid")
+            })
+            .collect();
+        Sweep {
+            descriptors,
+            lookahead: DEFAULT_LOOKAHEAD,
+            secp,
+        }
+    }
+
+    /// Sweep the outputs of a descriptor containing secret keys.
+    ///
+    /// The first `100` addresses of descriptors with a wildcard are swept, see
+    /// [`Sweep::lookahead`].
+    pub fn from_descriptor<D: IntoWalletDescriptor>(
+        descriptor: D,
+        network: Network,
+    ) -> Result<Self, SweepError> {
+        let secp = Secp256k1::new();
+        let (descriptor, keymap) = into_wallet_descriptor_checked(descriptor, &secp, network)?;
+        if keymap.is_empty() {
+            return Err(SweepError::MissingSecretKey);
+        }
+
+        Ok(Sweep {
+            descriptors: vec![(descriptor, keymap)],
+            lookahead: DEFAULT_LOOKAHEAD,
+            secp,
+        })
+    }
+
+    /// Set the number of addresses swept for descriptors with a wildcard.
+    pub fn lookahead(mut self, lookahead: u32) -> Self {
+        self.lookahead = lookahead;
+        self
+    }
+
+    /// The scripts whose outputs are swept, to be synced with a chain source.
+    pub fn spks(&self) -> Vec<ScriptBuf> {
+        self.spks_by_descriptor().map(|(_, _, spk)| spk).collect()
+    }
+
+    /// Sign and finalize the inputs of `psbt` spending outputs of the swept keys.
+    ///
+    /// Returns whether all the inputs of `psbt` are finalized, like [`Wallet::sign`].
+    ///
+    /// [`Wallet::sign`]: crate::Wallet::sign
+    pub fn sign(&self, psbt: &mut Psbt, sign_options: SignOptions) -> Result<bool, SignerError> {
+        check_sign_options(psbt, &sign_options)?;
+
+        let spks = self
+            .spks_by_descriptor()
+            .map(|(descriptor, index, spk)| (spk, (descriptor, index)))
+            .collect::<BTreeMap<_, _>>();
+        let inputs = (0..psbt.inputs.len())
+            .filter_map(|n| {
+                let txout = psbt.get_utxo_for(n)?;
+                let &(descriptor, index) = spks.get(&txout.script_pubkey)?;
+                Some((n, descriptor, index))
+            })
+            .collect::<Vec<_>>();
+
+        for (i, (descriptor, keymap)) in self.descriptors.iter().enumerate() {
+            if !inputs.iter().any(|&(_, d, _)| d == i) {
+                continue;
+            }
+            // single keys sign any input they can compute a sighash for, so the inputs of other
+            // descriptors are marked as final before signing
+            let mut signed = psbt.clone();
+            for (n, psbt_input) in signed.inputs.iter_mut().enumerate() {
+                if !inputs.iter().any(|&(m, d, _)| m == n && d == i) {
+                    psbt_input.final_script_sig = Some(ScriptBuf::new());
+                }
+            }
+            let signers = SignersContainer::build(keymap.clone(), descriptor, &self.secp);
+            for signer in signers.signers() {
+                signer.sign_transaction(&mut signed, &sign_options, &self.secp)?;
+            }
+            for &(n, _, _) in inputs.iter().filter(|&&(_, d, _)| d == i) {
+                psbt.inputs[n] = signed.inputs[n].clone();
+            }
+        }
+
+        if !sign_options.try_finalize {
+            return Ok(false);
+        }
+        for &(n, descriptor, index) in &inputs {
+            if psbt.inputs[n].final_script_sig.is_some()
+                || psbt.inputs[n].final_script_witness.is_some()
+            {
+                continue;
+            }
+            let derived = self.descriptors[descriptor]
+                .0
+                .at_derivation_index(index)
+                .expect("child can't be hardened");
+            let mut tmp_input = TxIn::default();
+            if derived
+                .satisfy(&mut tmp_input, PsbtInputSatisfier::new(psbt, n))
+                .is_ok()
+            {
+                finalize_input(&mut psbt.inputs[n], tmp_input, &sign_options);
+            }
+        }
+
+        Ok(psbt
+            .inputs
+            .iter()
+            .all(|input| input.final_script_sig.is_some() || input.final_script_witness.is_some()))
+    }
+
+    /// Find the unspent outputs of the swept keys in `graph`, canonicalized with `chain`.
+    pub(crate) fn find_utxos<A: Anchor, C: ChainOracle<Error = Infallible>>(
+        &self,
+        graph: &TxGraph<A>,
+        chain: &C,
+        chain_tip: BlockId,
+    ) -> Vec<SweepUtxo> {
+        let spks = self
+            .spks_by_descriptor()
+            .map(|(descriptor, index, spk)| (spk, (descriptor, index)))
+            .collect::<BTreeMap<_, _>>();
+        let outpoints = graph.all_txouts().filter_map(|(outpoint, txout)| {
+            spks.get(&txout.script_pubkey)
+                .map(|&derivation| (derivation, outpoint))
+        });
+
+        graph
+            .filter_chain_unspents(chain, chain_tip, outpoints)
+            .map(|((descriptor, index), utxo)| {
+                let descriptor = &self.descriptors[descriptor].0;
+                let derived = descriptor
+                    .at_derivation_index(index)
+                    .expect("child can't be hardened");
+                let mut psbt_input = psbt::Input::default();
+                psbt_input
+                    .update_with_descriptor_unchecked(&derived)
+                    .expect("the descriptor has no hardened derivation steps");
+                if descriptor.is_witness() || descriptor.is_taproot() {
+                    psbt_input.witness_utxo = Some(utxo.txout.clone());
+                }
+                if !descriptor.is_taproot() {
+                    psbt_input.non_witness_utxo = graph
+                        .get_tx(utxo.outpoint.txid)
+                        .map(|tx| tx.as_ref().clone());
+                }
+                SweepUtxo {
+                    outpoint: utxo.outpoint,
+                    psbt_input,
+                    satisfaction_weight: descriptor.max_weight_to_satisfy().unwrap(),
+                }
+            })
+            .collect()
+    }
+
+    /// The scripts of the swept keys, with the index of their descriptor and their derivation
+    /// index.
+    fn spks_by_descriptor(&self) -> impl Iterator<Item = (usize, u32, ScriptBuf)> + '_ {
+        self.descriptors
+            .iter()
+            .enumerate()
+            .flat_map(move |(i, (descriptor, _))| {
+                SpkIterator::new_with_range(descriptor, 0..self.lookahead)
+                    .map(move |(index, spk)| (i, index, spk))
+            })
+    }
+}
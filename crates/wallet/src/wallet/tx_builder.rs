@@ -45,11 +45,15 @@ use core::fmt;
 
 use bitcoin::psbt::{self, Psbt};
 use bitcoin::script::PushBytes;
-use bitcoin::{absolute, Amount, FeeRate, OutPoint, ScriptBuf, Sequence, Transaction, Txid};
-
-use super::coin_selection::CoinSelectionAlgorithm;
-use super::{CreateTxError, Wallet};
-use crate::collections::{BTreeMap, HashSet};
+use bitcoin::{
+    absolute, Amount, FeeRate, OutPoint, ScriptBuf, Sequence, Transaction, Txid, Weight,
+};
+
+use super::coin_selection::{CoinSelectionAlgorithm, CoinSelectionResult};
+use super::silent_payments::SilentPaymentAddress;
+use super::uri::{PaymentUri, PaymentUriError};
+use super::{CreateTxError, UtxoLock, Wallet};
+use crate::collections::{BTreeMap, BTreeSet, HashSet};
 use crate::{KeychainKind, LocalOutput, Utxo, WeightedUtxo};
 
 /// A transaction builder
@@ -118,24 +122,31 @@ pub struct TxBuilder<'a, Cs> {
 #[derive(Default, Debug, Clone)]
 pub(crate) struct TxParams {
     pub(crate) recipients: Vec<(ScriptBuf, u64)>,
+    pub(crate) silent_payments: Vec<(SilentPaymentAddress, u64)>,
+    pub(crate) subtract_fee_from: BTreeSet<usize>,
     pub(crate) drain_wallet: bool,
     pub(crate) drain_to: Option<ScriptBuf>,
     pub(crate) fee_policy: Option<FeePolicy>,
-    pub(crate) internal_policy_path: Option<BTreeMap<String, Vec<usize>>>,
-    pub(crate) external_policy_path: Option<BTreeMap<String, Vec<usize>>>,
+    pub(crate) policy_paths: BTreeMap<KeychainKind, BTreeMap<String, Vec<usize>>>,
     pub(crate) utxos: Vec<WeightedUtxo>,
     pub(crate) unspendable: HashSet<OutPoint>,
     pub(crate) manually_selected_only: bool,
+    pub(crate) lock_selected_utxos: Option<UtxoLock>,
     pub(crate) sighash: Option<psbt::PsbtSighashType>,
     pub(crate) ordering: TxOrdering,
     pub(crate) locktime: Option<absolute::LockTime>,
     pub(crate) rbf: Option<RbfValue>,
+    pub(crate) sequence: Option<Sequence>,
     pub(crate) version: Option<Version>,
     pub(crate) change_policy: ChangeSpendPolicy,
+    pub(crate) spend_keychains: Option<BTreeSet<KeychainKind>>,
+    pub(crate) change_keychain: Option<KeychainKind>,
     pub(crate) only_witness_utxo: bool,
     pub(crate) add_global_xpubs: bool,
     pub(crate) include_output_redeem_witness_script: bool,
     pub(crate) bumping_fee: Option<PreviousFee>,
+    pub(crate) fixed_inputs: Option<Vec<OutPoint>>,
+    pub(crate) ancestor_package: Option<AncestorPackage>,
     pub(crate) current_height: Option<absolute::LockTime>,
     pub(crate) allow_dust: bool,
 }
@@ -146,6 +157,16 @@ pub(crate) struct PreviousFee {
     pub rate: FeeRate,
 }
 
+/// The unconfirmed ancestors of a transaction built with [`Wallet::build_cpfp`], whose fee is
+/// paid for by the child.
+///
+/// [`Wallet::build_cpfp`]: super::Wallet::build_cpfp
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AncestorPackage {
+    pub weight: Weight,
+    pub fee: u64,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub(crate) enum FeePolicy {
     FeeRate(FeeRate),
@@ -266,12 +287,7 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
         policy_path: BTreeMap<String, Vec<usize>>,
         keychain: KeychainKind,
     ) -> &mut Self {
-        let to_update = match keychain {
-            KeychainKind::Internal => &mut self.params.internal_policy_path,
-            KeychainKind::External => &mut self.params.external_policy_path,
-        };
-
-        *to_update = Some(policy_path);
+        self.params.policy_paths.insert(keychain, policy_path);
         self
     }
 
@@ -424,6 +440,29 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
         self
     }
 
+    /// Only spend the utxos added so far, and fail if another one is added afterwards.
+    ///
+    /// On a fee bump these are the inputs of the original transaction. This is required to bump
+    /// the fee of a transaction paying [silent payment] addresses, whose outputs are derived from
+    /// the inputs: the fee is then paid from the change, and [`finish`] fails with
+    /// [`CreateTxError::FixedInputs`] if an input is added with [`add_utxo`].
+    ///
+    /// [silent payment]: super::silent_payments
+    /// [`finish`]: Self::finish
+    /// [`add_utxo`]: Self::add_utxo
+    /// [`CreateTxError::FixedInputs`]: super::error::CreateTxError::FixedInputs
+    pub fn fixed_inputs(&mut self) -> &mut Self {
+        self.params.fixed_inputs = Some(
+            self.params
+                .utxos
+                .iter()
+                .map(|weighted_utxo| weighted_utxo.utxo.outpoint())
+                .collect(),
+        );
+        self.params.manually_selected_only = true;
+        self
+    }
+
     /// Replace the internal list of unspendable utxos with a new list
     ///
     /// It's important to note that the "must-be-spent" utxos added with [`TxBuilder::add_utxo`]
@@ -442,6 +481,21 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
         self
     }
 
+    /// Lock the inputs of the transaction with [`Wallet::lock_utxo`] once it's created
+    ///
+    /// This prevents other transactions built with the wallet from spending the same outputs
+    /// until the transaction is added to the wallet, the chain tip reaches `expiry_height` or the
+    /// outputs are unlocked with [`Wallet::unlock_utxo`]. The locks are staged by
+    /// [`finish`](Self::finish), and persisted by the next [`Wallet::commit`].
+    ///
+    /// [`Wallet::lock_utxo`]: super::Wallet::lock_utxo
+    /// [`Wallet::unlock_utxo`]: super::Wallet::unlock_utxo
+    /// [`Wallet::commit`]: super::Wallet::commit
+    pub fn lock_selected_utxos(&mut self, expiry_height: Option<u32>) -> &mut Self {
+        self.params.lock_selected_utxos = Some(UtxoLock { expiry_height });
+        self
+    }
+
     /// Sign with a specific sig hash
     ///
     /// **Use this option very carefully**
@@ -498,6 +552,29 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
         self
     }
 
+    /// Only spend outputs of the given keychains
+    ///
+    /// The UTXOs of every other keychain are effectively added to the "unspendable" list. See
+    /// [`TxBuilder::unspendable`]. This is combined with the [`ChangeSpendPolicy`], so that
+    /// `only_spend_change` together with a set of keychains without [`KeychainKind::Internal`]
+    /// can't select any UTXO.
+    pub fn spend_from_keychains(
+        &mut self,
+        keychains: impl IntoIterator<Item = KeychainKind>,
+    ) -> &mut Self {
+        self.params.spend_keychains = Some(keychains.into_iter().collect());
+        self
+    }
+
+    /// Send the change of the transaction to an address of the given `keychain`
+    ///
+    /// By default the change goes to [`KeychainKind::Internal`]. This has no effect if the
+    /// change is sent to a specific script with [`TxBuilder::drain_to`].
+    pub fn change_keychain(&mut self, keychain: KeychainKind) -> &mut Self {
+        self.params.change_keychain = Some(keychain);
+        self
+    }
+
     /// Only Fill-in the [`psbt::Input::witness_utxo`](bitcoin::psbt::Input::witness_utxo) field when spending from
     /// SegWit descriptors.
     ///
@@ -608,6 +685,75 @@ impl<'a, Cs> TxBuilder<'a, Cs> {
         self
     }
 
+    /// Add the recipient of a [BIP21] payment URI to the internal list
+    ///
+    /// Fails if the URI doesn't have an amount, in which case the address of the URI is added
+    /// with [`add_recipient`](Self::add_recipient) and the amount chosen by the user.
+    ///
+    /// [BIP21]: https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki
+    pub fn add_uri_recipient(&mut self, uri: &PaymentUri) -> Result<&mut Self, PaymentUriError> {
+        let amount = uri.amount.ok_or(PaymentUriError::MissingAmount)?;
+        Ok(self.add_recipient(uri.address.script_pubkey(), amount))
+    }
+
+    /// Add a [BIP352] silent payment recipient to the internal list
+    ///
+    /// The output paying `address` is derived from the private keys of the inputs once they are
+    /// selected, so only the inputs eligible under BIP352 are selected, and [`finish`] fails if a
+    /// manually added input is not eligible. See the [`silent_payments`] module for more.
+    ///
+    /// [BIP352]: https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki
+    /// [`finish`]: Self::finish
+    /// [`silent_payments`]: super::silent_payments
+    pub fn add_silent_payment_recipient(
+        &mut self,
+        address: SilentPaymentAddress,
+        amount: Amount,
+    ) -> &mut Self {
+        self.params.silent_payments.push((address, amount.to_sat()));
+        self
+    }
+
+    /// Subtract the fee of the transaction from the amount of the recipient at `index`
+    ///
+    /// Recipients are indexed in the order they were added, the ones added with
+    /// [`add_recipient`] coming before the silent payment recipients. When the fee is subtracted
+    /// from several recipients it is split evenly between them, the first one also paying what
+    /// can't be split. The coins are still selected at the target feerate, so their value net of
+    /// the fee to spend them must cover the amounts of the recipients.
+    ///
+    /// Like Bitcoin Core's `subtractfeefromoutputs`, the recipients pay the whole fee of the
+    /// transaction, including the fee of its inputs and change output, and the wallet only sends
+    /// their amounts. What's left of the selected coins goes to the change output, or to the
+    /// recipients when it's too small for one.
+    ///
+    /// [`finish`] fails with [`CreateTxError::InvalidSubtractFeeIndex`] if there is no recipient
+    /// at `index`, and with [`CreateTxError::OutputBelowDustLimitAfterFee`] if a recipient would
+    /// be left with a dust amount.
+    ///
+    /// [`add_recipient`]: Self::add_recipient
+    /// [`finish`]: Self::finish
+    pub fn subtract_fee_from(&mut self, index: usize) -> &mut Self {
+        self.params.subtract_fee_from.insert(index);
+        self
+    }
+
+    /// Add the payments of a [`PaymentBatch`] to the internal list of recipients
+    ///
+    /// The fee is subtracted from the payments that have [`Payment::subtract_fee`] set, as with
+    /// [`subtract_fee_from`](Self::subtract_fee_from).
+    pub fn add_payment_batch(&mut self, batch: &PaymentBatch) -> &mut Self {
+        for payment in &batch.payments {
+            if payment.subtract_fee {
+                self.params
+                    .subtract_fee_from
+                    .insert(self.params.recipients.len());
+            }
+            self.add_recipient(payment.script_pubkey.clone(), payment.amount);
+        }
+        self
+    }
+
     /// Add data as an output, using OP_RETURN
     pub fn add_data<T: AsRef<PushBytes>>(&mut self, data: &T) -> &mut Self {
         let script = ScriptBuf::new_op_return(data);
@@ -674,6 +820,19 @@ impl<'a, Cs: CoinSelectionAlgorithm> TxBuilder<'a, Cs> {
     ///
     /// [`BIP174`]: https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki
     pub fn finish(self) -> Result<Psbt, CreateTxError> {
+        self.finish_with_coin_selection().map(|(psbt, _)| psbt)
+    }
+
+    /// Finish building the transaction like [`finish`], and return the [`CoinSelectionResult`]
+    /// of its inputs along with the [`Psbt`].
+    ///
+    /// This gives access to what the coin selection algorithm reports about the selection, e.g.
+    /// the [`PrivacyScore`] of a [`PrivacyCoinSelection`].
+    ///
+    /// [`finish`]: Self::finish
+    /// [`PrivacyScore`]: super::coin_selection::PrivacyScore
+    /// [`PrivacyCoinSelection`]: super::coin_selection::PrivacyCoinSelection
+    pub fn finish_with_coin_selection(self) -> Result<(Psbt, CoinSelectionResult), CreateTxError> {
         self.wallet
             .borrow_mut()
             .create_tx(self.coin_selection, self.params)
@@ -742,6 +901,126 @@ impl fmt::Display for AddForeignUtxoError {
 #[cfg(feature = "std")]
 impl std::error::Error for AddForeignUtxoError {}
 
+/// A payment of a [`PaymentBatch`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payment {
+    /// The script paid
+    pub script_pubkey: ScriptBuf,
+    /// The amount paid
+    pub amount: Amount,
+    /// Whether the amount paid is reduced by a share of the fee of the transaction
+    pub subtract_fee: bool,
+}
+
+/// Payments, such as pending withdrawal requests, made by a single transaction
+///
+/// Payments to the same script are merged in a single output, as long as they all either pay a
+/// share of the fee or not. A batch is added to a transaction with
+/// [`TxBuilder::add_payment_batch`].
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use bitcoin::*;
+/// # use bdk_wallet::*;
+/// # use bdk_wallet::wallet::tx_builder::{Payment, PaymentBatch};
+/// # let mut wallet = doctest_wallet!();
+/// # let addr1 = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap().assume_checked();
+/// # let addr2 = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5").unwrap().assume_checked();
+/// let mut batch = PaymentBatch::new();
+/// for (address, sats) in [(&addr1, 10_000), (&addr2, 20_000), (&addr1, 5_000)] {
+///     batch.add_payment(Payment {
+///         script_pubkey: address.script_pubkey(),
+///         amount: Amount::from_sat(sats),
+///         subtract_fee: true,
+///     })?;
+/// }
+/// assert_eq!(batch.len(), 2);
+///
+/// let mut builder = wallet.build_tx();
+/// builder.add_payment_batch(&batch);
+/// let psbt = builder.finish()?;
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PaymentBatch {
+    payments: Vec<Payment>,
+}
+
+impl PaymentBatch {
+    /// Create an empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a payment to the batch, merging it with a payment to the same script if there is one
+    ///
+    /// Returns an error, without adding the payment, if the total amount of the batch overflows.
+    pub fn add_payment(&mut self, payment: Payment) -> Result<&mut Self, PaymentBatchError> {
+        // the total amount of the batch fits in an amount, so does the one of each payment
+        self.total()
+            .checked_add(payment.amount)
+            .ok_or(PaymentBatchError::AmountOverflow)?;
+        match self.payments.iter_mut().find(|p| {
+            p.script_pubkey == payment.script_pubkey && p.subtract_fee == payment.subtract_fee
+        }) {
+            Some(p) => p.amount += payment.amount,
+            None => self.payments.push(payment),
+        }
+        Ok(self)
+    }
+
+    /// Add the payments of `other` to the batch
+    ///
+    /// Returns an error, without adding any payment, if the total amount of the batch overflows.
+    pub fn merge(&mut self, other: PaymentBatch) -> Result<&mut Self, PaymentBatchError> {
+        self.total()
+            .checked_add(other.total())
+            .ok_or(PaymentBatchError::AmountOverflow)?;
+        for payment in other.payments {
+            self.add_payment(payment)?;
+        }
+        Ok(self)
+    }
+
+    /// The payments of the batch
+    pub fn payments(&self) -> &[Payment] {
+        &self.payments
+    }
+
+    /// The total amount paid by the batch, before the fee is subtracted
+    pub fn total(&self) -> Amount {
+        self.payments.iter().map(|p| p.amount).sum()
+    }
+
+    /// The number of outputs of the batch
+    pub fn len(&self) -> usize {
+        self.payments.len()
+    }
+
+    /// Whether the batch has no payments
+    pub fn is_empty(&self) -> bool {
+        self.payments.is_empty()
+    }
+}
+
+/// Errors returned when adding payments to a [`PaymentBatch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaymentBatchError {
+    /// The total amount paid by the batch overflows
+    AmountOverflow,
+}
+
+impl fmt::Display for PaymentBatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AmountOverflow => write!(f, "The total amount of the payment batch overflows"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PaymentBatchError {}
+
 /// Ordering of the transaction's inputs and outputs
 #[derive(Default, Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Clone, Copy)]
 pub enum TxOrdering {
@@ -823,7 +1102,7 @@ impl ChangeSpendPolicy {
         match self {
             ChangeSpendPolicy::ChangeAllowed => true,
             ChangeSpendPolicy::OnlyChange => utxo.keychain == KeychainKind::Internal,
-            ChangeSpendPolicy::ChangeForbidden => utxo.keychain == KeychainKind::External,
+            ChangeSpendPolicy::ChangeForbidden => utxo.keychain != KeychainKind::Internal,
         }
     }
 }
@@ -0,0 +1,451 @@
+// Bitcoin Dev Kit
+//
+// Copyright (c) 2020-2024 Bitcoin Dev Kit Developers
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Payment URIs
+//!
+//! This module implements [BIP21] `bitcoin:` URIs. A [`PaymentUri`] is parsed with
+//! [`Wallet::parse_payment_uri`], which checks that its address is valid for the network of the
+//! wallet, and is paid with [`TxBuilder::add_uri_recipient`]. URIs requesting a payment to the
+//! wallet are built from an [`AddressInfo`] and formatted with [`Display`](core::fmt::Display).
+//!
+//! ## Example
+//!
+//! ```
+//! # use bitcoin::*;
+//! # use bdk_wallet::*;
+//! # use bdk_wallet::wallet::uri::PaymentUri;
+//! # let mut wallet = doctest_wallet!();
+//! let uri = wallet.parse_payment_uri(
+//!     "bitcoin:bcrt1qc6fweuf4xjvz4x3gx3t9e0fh4hvqyu2qw4wvxm?amount=0.0005&label=Coffee",
+//! )?;
+//! assert_eq!(uri.label.as_deref(), Some("Coffee"));
+//!
+//! let mut builder = wallet.build_tx();
+//! builder.add_uri_recipient(&uri)?;
+//! let psbt = builder.finish()?;
+//!
+//! // request a payment to the wallet
+//! let mut request = PaymentUri::from(wallet.reveal_next_address(KeychainKind::External)?);
+//! request.amount = Some(Amount::from_sat(100_000));
+//! request.label = Some("Invoice #42".to_string());
+//! assert!(request.to_string().ends_with("?amount=0.001&label=Invoice%20%2342"));
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [BIP21]: https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki
+//! [`Wallet::parse_payment_uri`]: crate::Wallet::parse_payment_uri
+//! [`TxBuilder::add_uri_recipient`]: super::tx_builder::TxBuilder::add_uri_recipient
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use bitcoin::address::{self, NetworkChecked, NetworkUnchecked, NetworkValidation};
+use bitcoin::amount::ParseAmountError;
+use bitcoin::{Address, Amount, Denomination, Network};
+
+use super::AddressInfo;
+use crate::collections::BTreeMap;
+
+/// The scheme of payment URIs.
+const SCHEME: &str = "bitcoin:";
+
+/// The parameters stored in their own field of [`PaymentUri`].
+const RESERVED_PARAMS: [&str; 3] = ["amount", "label", "message"];
+
+/// A [BIP21] payment URI.
+///
+/// Like [`Address`], a URI parsed with [`FromStr`] must have its network checked with
+/// [`require_network`](PaymentUri::require_network) before it is used.
+///
+/// [BIP21]: https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentUri<V: NetworkValidation = NetworkChecked> {
+    /// The address to pay.
+    pub address: Address<V>,
+    /// The amount to pay.
+    pub amount: Option<Amount>,
+    /// The label of the address, e.g. the name of the receiver.
+    pub label: Option<String>,
+    /// A message describing the payment.
+    pub message: Option<String>,
+    /// The other parameters of the URI, like the `pj` endpoint of a [payjoin](super::payjoin)
+    /// receiver.
+    ///
+    /// They are optional, as URIs with unknown required parameters are rejected. The `amount`,
+    /// `label` and `message` parameters are only read from their own field.
+    pub params: BTreeMap<String, String>,
+}
+
+impl PaymentUri {
+    /// Create the URI of `address`, without any amount or parameter.
+    pub fn new(address: Address) -> Self {
+        PaymentUri {
+            address,
+            amount: None,
+            label: None,
+            message: None,
+            params: BTreeMap::new(),
+        }
+    }
+}
+
+impl PaymentUri<NetworkUnchecked> {
+    /// Check that the address of the URI is valid for `network`.
+    pub fn require_network(self, network: Network) -> Result<PaymentUri, PaymentUriError> {
+        let address = self.address.require_network(network).map_err(|e| match e {
+            address::Error::NetworkValidation {
+                required, found, ..
+            } => PaymentUriError::NetworkMismatch { required, found },
+            _ => unreachable!("only the network is validated"),
+        })?;
+        Ok(PaymentUri {
+            address,
+            amount: self.amount,
+            label: self.label,
+            message: self.message,
+            params: self.params,
+        })
+    }
+
+    /// Mark the network of the URI as checked, see [`Address::assume_checked`].
+    pub fn assume_checked(self) -> PaymentUri {
+        PaymentUri {
+            address: self.address.assume_checked(),
+            amount: self.amount,
+            label: self.label,
+            message: self.message,
+            params: self.params,
+        }
+    }
+}
+
+impl From<AddressInfo> for PaymentUri {
+    fn from(address_info: AddressInfo) -> Self {
+        PaymentUri::new(address_info.address)
+    }
+}
+
+impl FromStr for PaymentUri<NetworkUnchecked> {
+    type Err = PaymentUriError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // the scheme is case-insensitive
+        if !s
+            .get(..SCHEME.len())
+            .map_or(false, |scheme| scheme.eq_ignore_ascii_case(SCHEME))
+        {
+            return Err(PaymentUriError::InvalidScheme);
+        }
+        let (address, query) = match s[SCHEME.len()..].split_once('?') {
+            Some((address, query)) => (address, query),
+            None => (&s[SCHEME.len()..], ""),
+        };
+
+        let mut uri = PaymentUri {
+            address: Address::from_str(address)?,
+            amount: None,
+            label: None,
+            message: None,
+            params: BTreeMap::new(),
+        };
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            let name = percent_decode(name)
+                .ok_or_else(|| PaymentUriError::InvalidEncoding(name.to_string()))?;
+            let value = percent_decode(value)
+                .ok_or_else(|| PaymentUriError::InvalidEncoding(name.clone()))?;
+            let duplicate = match name.as_str() {
+                "amount" => {
+                    let amount = Amount::from_str_in(&value, Denomination::Bitcoin)?;
+                    uri.amount.replace(amount).is_some()
+                }
+                "label" => uri.label.replace(value).is_some(),
+                "message" => uri.message.replace(value).is_some(),
+                _ if name.starts_with("req-") => {
+                    return Err(PaymentUriError::UnknownRequiredParameter(name))
+                }
+                _ => uri.params.insert(name.clone(), value).is_some(),
+            };
+            if duplicate {
+                return Err(PaymentUriError::DuplicateParameter(name));
+            }
+        }
+
+        Ok(uri)
+    }
+}
+
+impl fmt::Display for PaymentUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SCHEME, self.address)?;
+
+        let amount = self
+            .amount
+            .map(|amount| amount.to_string_in(Denomination::Bitcoin));
+        let params = [
+            ("amount", amount.as_ref()),
+            ("label", self.label.as_ref()),
+            ("message", self.message.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?)))
+        .chain(
+            self.params
+                .iter()
+                .filter(|(name, _)| !RESERVED_PARAMS.contains(&name.as_str()))
+                .map(|(name, value)| (name.as_str(), value)),
+        );
+        for (i, (name, value)) in params.enumerate() {
+            let separator = if i == 0 { '?' } else { '&' };
+            write!(
+                f,
+                "{}{}={}",
+                separator,
+                percent_encode(name),
+                percent_encode(value)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Errors that can be returned when parsing or paying a [`PaymentUri`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaymentUriError {
+    /// The URI doesn't start with `bitcoin:`.
+    InvalidScheme,
+    /// The address of the URI is invalid.
+    Address(address::ParseError),
+    /// The address of the URI is for another network.
+    NetworkMismatch {
+        /// The network of the wallet.
+        required: Network,
+        /// The network of the address.
+        found: Network,
+    },
+    /// The amount of the URI is invalid.
+    Amount(ParseAmountError),
+    /// The parameter is not valid percent-encoded UTF-8.
+    InvalidEncoding(String),
+    /// The parameter is in the URI more than once.
+    DuplicateParameter(String),
+    /// The parameter is required, but not supported.
+    UnknownRequiredParameter(String),
+    /// The URI doesn't have an amount, so it can't be paid without choosing one.
+    MissingAmount,
+}
+
+impl fmt::Display for PaymentUriError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidScheme => write!(f, "the URI doesn't start with `bitcoin:`"),
+            Self::Address(e) => write!(f, "invalid address: {}", e),
+            Self::NetworkMismatch { required, found } => {
+                write!(f, "the address is for {} instead of {}", found, required)
+            }
+            Self::Amount(e) => write!(f, "invalid amount: {}", e),
+            Self::InvalidEncoding(name) => {
+                write!(f, "the parameter `{}` is not percent-encoded UTF-8", name)
+            }
+            Self::DuplicateParameter(name) => write!(f, "duplicate parameter `{}`", name),
+            Self::UnknownRequiredParameter(name) => {
+                write!(f, "unknown required parameter `{}`", name)
+            }
+            Self::MissingAmount => write!(f, "the URI doesn't have an amount"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PaymentUriError {}
+
+impl From<address::ParseError> for PaymentUriError {
+    fn from(e: address::ParseError) -> Self {
+        Self::Address(e)
+    }
+}
+
+impl From<ParseAmountError> for PaymentUriError {
+    fn from(e: ParseAmountError) -> Self {
+        Self::Amount(e)
+    }
+}
+
+/// Decode the `%XX` escapes of `s`, returning `None` if an escape is invalid or the result is
+/// not UTF-8.
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            // `from_str_radix` accepts a leading sign
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = core::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Escape all the characters of `s` but the unreserved characters of RFC 3986.
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&alloc::format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ADDRESS: &str = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+
+    fn parse(s: &str) -> Result<PaymentUri, PaymentUriError> {
+        PaymentUri::from_str(s)?.require_network(Network::Bitcoin)
+    }
+
+    #[test]
+    fn test_parse() {
+        let uri = parse(&format!("bitcoin:{}", ADDRESS)).unwrap();
+        assert_eq!(
+            uri,
+            PaymentUri::new(Address::from_str(ADDRESS).unwrap().assume_checked())
+        );
+
+        let uri = parse(&format!(
+            "BITCOIN:{}?amount=20.3&label=Luke-Jr&message=Donation%20for%20project%20xyz&pj=https://example.com/pj",
+            ADDRESS.to_uppercase()
+        ))
+        .unwrap();
+        assert_eq!(uri.address.to_string(), ADDRESS);
+        assert_eq!(uri.amount, Some(Amount::from_sat(2_030_000_000)));
+        assert_eq!(uri.label.as_deref(), Some("Luke-Jr"));
+        assert_eq!(uri.message.as_deref(), Some("Donation for project xyz"));
+        assert_eq!(
+            uri.params.get("pj").map(String::as_str),
+            Some("https://example.com/pj")
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse(&format!("litecoin:{}", ADDRESS)),
+            Err(PaymentUriError::InvalidScheme)
+        );
+        assert!(matches!(
+            parse("bitcoin:bc1qinvalid"),
+            Err(PaymentUriError::Address(_))
+        ));
+        assert_eq!(
+            parse("bitcoin:tb1q4525hmgw265tl3drrl8jjta7ayffu6jf68ltjd"),
+            Err(PaymentUriError::NetworkMismatch {
+                required: Network::Bitcoin,
+                found: Network::Testnet
+            })
+        );
+        assert!(matches!(
+            parse(&format!("bitcoin:{}?amount=1,5", ADDRESS)),
+            Err(PaymentUriError::Amount(_))
+        ));
+        assert_eq!(
+            parse(&format!("bitcoin:{}?label=%ZZ", ADDRESS)),
+            Err(PaymentUriError::InvalidEncoding("label".to_string()))
+        );
+        assert_eq!(
+            parse(&format!("bitcoin:{}?label=%+f", ADDRESS)),
+            Err(PaymentUriError::InvalidEncoding("label".to_string()))
+        );
+        assert_eq!(
+            parse(&format!("bitcoin:{}?amount=1&amount=2", ADDRESS)),
+            Err(PaymentUriError::DuplicateParameter("amount".to_string()))
+        );
+        assert_eq!(
+            parse(&format!(
+                "bitcoin:{}?req-somethingyoudontunderstand=50",
+                ADDRESS
+            )),
+            Err(PaymentUriError::UnknownRequiredParameter(
+                "req-somethingyoudontunderstand".to_string()
+            ))
+        );
+        // unknown optional parameters are fine
+        assert!(parse(&format!(
+            "bitcoin:{}?somethingyoudontunderstand=50",
+            ADDRESS
+        ))
+        .is_ok());
+    }
+
+    #[test]
+    fn test_display() {
+        let mut uri = PaymentUri::new(Address::from_str(ADDRESS).unwrap().assume_checked());
+        assert_eq!(uri.to_string(), format!("bitcoin:{}", ADDRESS));
+
+        uri.amount = Some(Amount::from_sat(50_000));
+        uri.label = Some("Café & co".to_string());
+        uri.message = Some("100%".to_string());
+        uri.params
+            .insert("pj".to_string(), "https://example.com/pj".to_string());
+        let s = uri.to_string();
+        assert_eq!(
+            s,
+            format!(
+                "bitcoin:{}?amount=0.0005&label=Caf%C3%A9%20%26%20co&message=100%25&pj=https%3A%2F%2Fexample.com%2Fpj",
+                ADDRESS
+            )
+        );
+        assert_eq!(parse(&s), Ok(uri));
+    }
+
+    #[test]
+    fn test_multibyte_scheme() {
+        assert_eq!(
+            parse(&format!("bitcoi€:{}", ADDRESS)),
+            Err(PaymentUriError::InvalidScheme)
+        );
+        assert_eq!(parse("€"), Err(PaymentUriError::InvalidScheme));
+    }
+
+    #[test]
+    fn test_display_reserved_params() {
+        let mut uri = PaymentUri::new(Address::from_str(ADDRESS).unwrap().assume_checked());
+        uri.amount = Some(Amount::from_sat(50_000));
+        uri.label = Some("Coffee".to_string());
+        let mut with_params = uri.clone();
+        with_params
+            .params
+            .insert("amount".to_string(), "1".to_string());
+        with_params
+            .params
+            .insert("label".to_string(), "Tea".to_string());
+        with_params
+            .params
+            .insert("message".to_string(), "Thanks".to_string());
+
+        let s = with_params.to_string();
+        assert_eq!(s, format!("bitcoin:{}?amount=0.0005&label=Coffee", ADDRESS));
+        assert_eq!(parse(&s), Ok(uri));
+    }
+}
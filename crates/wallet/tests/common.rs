@@ -17,6 +17,20 @@ use std::str::FromStr;
 /// sats are the transaction fee.
 pub fn get_funded_wallet_with_change(descriptor: &str, change: &str) -> (Wallet, bitcoin::Txid) {
     let mut wallet = Wallet::new_no_persist(descriptor, change, Network::Regtest).unwrap();
+    let txid = fund_wallet(&mut wallet);
+    (wallet, txid)
+}
+
+/// Return a fake wallet with a single descriptor that appears to be funded for testing.
+///
+/// The wallet is funded the same way as in [`get_funded_wallet_with_change`].
+pub fn get_funded_wallet_single(descriptor: &str) -> (Wallet, bitcoin::Txid) {
+    let mut wallet = Wallet::new_single_no_persist(descriptor, Network::Regtest).unwrap();
+    let txid = fund_wallet(&mut wallet);
+    (wallet, txid)
+}
+
+fn fund_wallet(wallet: &mut Wallet) -> bitcoin::Txid {
     let receive_address = wallet.peek_address(KeychainKind::External, 0).address;
     let sendto_address = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
         .expect("address")
@@ -96,7 +110,7 @@ pub fn get_funded_wallet_with_change(descriptor: &str, change: &str) -> (Wallet,
         )
         .unwrap();
 
-    (wallet, tx1.txid())
+    tx1.txid()
 }
 
 /// Return a fake wallet that appears to be funded for testing.
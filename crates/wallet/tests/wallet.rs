@@ -3,29 +3,55 @@ use std::str::FromStr;
 
 use assert_matches::assert_matches;
 use bdk_chain::collections::BTreeMap;
+use bdk_chain::local_chain::CheckPoint;
+use bdk_chain::silent_payments::SilentPaymentIndex;
 use bdk_chain::COINBASE_MATURITY;
-use bdk_chain::{BlockId, ConfirmationTime};
+use bdk_chain::{
+    BlockId, ConfirmationTime, ConfirmationTimeHeightAnchor, DescriptorExt, IndexedTxGraph, TxGraph,
+};
 use bdk_persist::PersistBackend;
 use bdk_sqlite::rusqlite::Connection;
-use bdk_wallet::descriptor::{calc_checksum, DescriptorError, IntoWalletDescriptor};
+use bdk_wallet::descriptor::bsms::{BsmsError, DescriptorRecord, KeyRecord, Token};
+use bdk_wallet::descriptor::{
+    calc_checksum, DescriptorError, IntoWalletDescriptor, WalletPolicy, WalletPolicyError,
+};
+use bdk_wallet::miniscript::psbt::PsbtExt;
 use bdk_wallet::psbt::PsbtUtils;
 use bdk_wallet::signer::{SignOptions, SignerError};
-use bdk_wallet::wallet::coin_selection::{self, LargestFirstCoinSelection};
-use bdk_wallet::wallet::error::CreateTxError;
-use bdk_wallet::wallet::tx_builder::AddForeignUtxoError;
-use bdk_wallet::wallet::NewError;
-use bdk_wallet::wallet::{AddressInfo, Balance, Wallet};
+use bdk_wallet::wallet::coin_selection::{
+    self, LargestFirstCoinSelection, LowestWasteCoinSelection, PrivacyCoinSelection, PrivacyScore,
+};
+use bdk_wallet::wallet::consolidation::{Consolidation, ConsolidationError};
+use bdk_wallet::wallet::error::{
+    BuildCancelError, BuildCpfpError, BuildFeeBumpError, CreateTxError,
+};
+use bdk_wallet::wallet::event::WalletEvent;
+use bdk_wallet::wallet::labels::{Bip329Error, Label, LabelRef};
+use bdk_wallet::wallet::message::{verify_message, MessageError, MessageSignatureFormat};
+use bdk_wallet::wallet::payjoin::{OriginalPsbtError, PayjoinParams, ProposalError};
+use bdk_wallet::wallet::reserves::{verify_proof_of_reserves, ProofError};
+use bdk_wallet::wallet::silent_payments::{SilentPaymentAddress, SilentPaymentError};
+use bdk_wallet::wallet::sweep::{Sweep, SweepError};
+use bdk_wallet::wallet::tx_builder::{
+    AddForeignUtxoError, Payment, PaymentBatch, PaymentBatchError, TxOrdering,
+};
+use bdk_wallet::wallet::uri::{PaymentUri, PaymentUriError};
+use bdk_wallet::wallet::{AddKeychainError, NewError, NewOrLoadError};
+use bdk_wallet::wallet::{AddressInfo, Balance, Update, UtxoLock, Wallet};
 use bdk_wallet::KeychainKind;
-use bitcoin::hashes::Hash;
-use bitcoin::key::Secp256k1;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::key::{Parity, Secp256k1};
 use bitcoin::psbt;
 use bitcoin::script::PushBytesBuf;
+use bitcoin::secp256k1::{PublicKey, Scalar, SecretKey, XOnlyPublicKey};
 use bitcoin::sighash::{EcdsaSighashType, TapSighashType};
 use bitcoin::taproot::TapNodeHash;
 use bitcoin::{
-    absolute, transaction, Address, Amount, BlockHash, FeeRate, Network, OutPoint, ScriptBuf,
-    Sequence, Transaction, TxIn, TxOut, Txid, Weight,
+    absolute, bip32, block, transaction, Address, Amount, Block, BlockHash, FeeRate, Network,
+    OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Weight, Witness,
 };
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
 mod common;
 use common::*;
@@ -217,7 +243,7 @@ fn new_or_load() -> anyhow::Result<()> {
                 matches!(
                     err,
                     bdk_wallet::wallet::NewOrLoadError::LoadedDescriptorDoesNotMatch { ref got, keychain }
-                    if got == &Some(got_descriptor) && keychain == KeychainKind::External
+                    if got.as_deref() == Some(&got_descriptor) && keychain == KeychainKind::External
                 ),
                 "err: {}",
                 err,
@@ -239,7 +265,7 @@ fn new_or_load() -> anyhow::Result<()> {
                 matches!(
                     err,
                     bdk_wallet::wallet::NewOrLoadError::LoadedDescriptorDoesNotMatch { ref got, keychain }
-                    if got == &Some(got_descriptor) && keychain == KeychainKind::Internal
+                    if got.as_deref() == Some(&got_descriptor) && keychain == KeychainKind::Internal
                 ),
                 "err: {}",
                 err,
@@ -271,1658 +297,1931 @@ fn new_or_load() -> anyhow::Result<()> {
 }
 
 #[test]
-fn test_error_external_and_internal_are_the_same() {
-    // identical descriptors should fail to create wallet
-    let desc = get_test_wpkh();
-    let err = Wallet::new_no_persist(desc, desc, Network::Testnet);
-    assert!(
-        matches!(&err, Err(DescriptorError::ExternalAndInternalAreTheSame)),
-        "expected same descriptors error, got {:?}",
-        err,
-    );
+fn new_or_load_single() -> anyhow::Result<()> {
+    fn run<B, F>(filename: &str, new_or_load: F) -> anyhow::Result<()>
+    where
+        B: PersistBackend<bdk_wallet::wallet::ChangeSet> + Send + Sync + 'static,
+        F: Fn(&Path) -> anyhow::Result<B>,
+    {
+        let temp_dir = tempfile::tempdir().expect("must create tempdir");
+        let file_path = temp_dir.path().join(filename);
+        let desc = get_test_wpkh();
 
-    // public + private of same descriptor should fail to create wallet
-    let desc = "wpkh(tprv8ZgxMBicQKsPdcAqYBpzAFwU5yxBUo88ggoBqu1qPcHUfSbKK1sKMLmC7EAk438btHQrSdu3jGGQa6PA71nvH5nkDexhLteJqkM4dQmWF9g/84'/1'/0'/0/*)";
-    let change_desc = "wpkh([3c31d632/84'/1'/0']tpubDCYwFkks2cg78N7eoYbBatsFEGje8vW8arSKW4rLwD1AU1s9KJMDRHE32JkvYERuiFjArrsH7qpWSpJATed5ShZbG9KsskA5Rmi6NSYgYN2/0/*)";
-    let err = Wallet::new_no_persist(desc, change_desc, Network::Testnet);
+        // init wallet when non-existent
+        let wallet_keychains: BTreeMap<_, _> = {
+            let db = new_or_load(&file_path).expect("must create db");
+            let wallet =
+                Wallet::new_or_load_single(desc, db, Network::Testnet).expect("must init wallet");
+            wallet.keychains().map(|(k, v)| (*k, v.clone())).collect()
+        };
+        assert_eq!(wallet_keychains.len(), 1);
+
+        // wrong descriptor
+        {
+            let got_descriptor = desc
+                .into_wallet_descriptor(&Secp256k1::new(), Network::Testnet)
+                .unwrap()
+                .0;
+
+            let db = new_or_load(&file_path).expect("must open db");
+            let err = Wallet::new_or_load_single(get_test_tr_single_sig(), db, Network::Testnet)
+                .expect_err("wrong descriptor");
+            assert!(
+                matches!(
+                    err,
+                    bdk_wallet::wallet::NewOrLoadError::LoadedDescriptorDoesNotMatch { ref got, keychain }
+                    if got.as_deref() == Some(&got_descriptor) && keychain == KeychainKind::External
+                ),
+                "err: {}",
+                err,
+            );
+        }
+
+        // an internal descriptor is expected but none was stored
+        {
+            let db = new_or_load(&file_path).expect("must open db");
+            let err = Wallet::new_or_load(desc, get_test_tr_single_sig(), db, Network::Testnet)
+                .expect_err("missing internal descriptor");
+            assert!(
+                matches!(
+                    err,
+                    bdk_wallet::wallet::NewOrLoadError::LoadedDescriptorDoesNotMatch {
+                        got: None,
+                        keychain: KeychainKind::Internal
+                    }
+                ),
+                "err: {}",
+                err,
+            );
+        }
+
+        // all parameters match
+        {
+            let db = new_or_load(&file_path).expect("must open db");
+            let wallet = Wallet::new_or_load_single(desc, db, Network::Testnet)
+                .expect("must recover wallet");
+            assert_eq!(wallet.network(), Network::Testnet);
+            assert!(wallet
+                .keychains()
+                .map(|(k, v)| (*k, v.clone()))
+                .eq(wallet_keychains));
+            assert_eq!(
+                wallet.get_signers(KeychainKind::External).signers().len(),
+                1
+            );
+        }
+        Ok(())
+    }
+
+    run("store.db", |path| {
+        Ok(bdk_file_store::Store::open_or_create_new(DB_MAGIC, path)?)
+    })?;
+    run("store.sqlite", |path| {
+        Ok(bdk_sqlite::Store::new(Connection::open(path)?)?)
+    })?;
+
+    // a single descriptor does not match a stored wallet that has an internal descriptor
+    let temp_dir = tempfile::tempdir().expect("must create tempdir");
+    let file_path = temp_dir.path().join("store.sqlite");
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let got_descriptor = change_desc
+        .into_wallet_descriptor(&Secp256k1::new(), Network::Testnet)
+        .unwrap()
+        .0;
+    let db = bdk_sqlite::Store::new(Connection::open(&file_path)?)?;
+    Wallet::new(desc, change_desc, db, Network::Testnet)?;
+    let db = bdk_sqlite::Store::new(Connection::open(&file_path)?)?;
+    let err = Wallet::new_or_load_single(desc, db, Network::Testnet)
+        .expect_err("unexpected internal descriptor");
     assert!(
-        matches!(err, Err(DescriptorError::ExternalAndInternalAreTheSame)),
-        "expected same descriptors error, got {:?}",
+        matches!(
+            err,
+            bdk_wallet::wallet::NewOrLoadError::LoadedDescriptorDoesNotMatch { ref got, keychain }
+            if got.as_deref() == Some(&got_descriptor) && keychain == KeychainKind::Internal
+        ),
+        "err: {}",
         err,
     );
-}
 
-#[test]
-fn test_descriptor_checksum() {
-    let (wallet, _) = get_funded_wallet_wpkh();
-    let checksum = wallet.descriptor_checksum(KeychainKind::External);
-    assert_eq!(checksum.len(), 8);
-
-    let raw_descriptor = wallet
-        .keychains()
-        .next()
-        .unwrap()
-        .1
-        .to_string()
-        .split_once('#')
-        .unwrap()
-        .0
-        .to_string();
-    assert_eq!(calc_checksum(&raw_descriptor).unwrap(), checksum);
+    Ok(())
 }
 
 #[test]
-fn test_get_funded_wallet_balance() {
-    let (wallet, _) = get_funded_wallet_wpkh();
+fn labels_are_persisted() -> anyhow::Result<()> {
+    fn run<B, FN, FR>(filename: &str, create_new: FN, recover: FR) -> anyhow::Result<()>
+    where
+        B: PersistBackend<bdk_wallet::wallet::ChangeSet> + Send + Sync + 'static,
+        FN: Fn(&Path) -> anyhow::Result<B>,
+        FR: Fn(&Path) -> anyhow::Result<B>,
+    {
+        let temp_dir = tempfile::tempdir().expect("must create tempdir");
+        let file_path = temp_dir.path().join(filename);
+        let (desc, change_desc) = get_test_wpkh_with_change_desc();
+        let tx_ref = LabelRef::Tx(Txid::all_zeros());
+        let output_ref = LabelRef::Output(OutPoint::new(Txid::all_zeros(), 1));
 
-    // The funded wallet contains a tx with a 76_000 sats input and two outputs, one spending 25_000
-    // to a foreign address and one returning 50_000 back to the wallet as change. The remaining 1000
-    // sats are the transaction fee.
-    assert_eq!(wallet.balance().confirmed, Amount::from_sat(50_000));
-}
+        // create new wallet and label a few items
+        let addr_ref = {
+            let db = create_new(&file_path).expect("must create db");
+            let mut wallet =
+                Wallet::new(desc, change_desc, db, Network::Testnet).expect("must init wallet");
+            let addr_ref = LabelRef::Addr(
+                wallet
+                    .reveal_next_address(KeychainKind::External)
+                    .unwrap()
+                    .script_pubkey(),
+            );
+            wallet.set_label(tx_ref.clone(), Label::new("rent"));
+            wallet.set_label(addr_ref.clone(), Label::new("invoice #1"));
+            wallet.set_label(
+                output_ref.clone(),
+                Label {
+                    label: "dust attack".to_string(),
+                    origin: None,
+                    spendable: Some(false),
+                },
+            );
+            assert!(wallet.commit()?);
+            assert_eq!(wallet.remove_label(&tx_ref), Some(Label::new("rent")));
+            assert!(wallet.commit()?);
+            addr_ref
+        };
 
-#[test]
-fn test_get_funded_wallet_sent_and_received() {
-    let (wallet, txid) = get_funded_wallet_wpkh();
+        // recover wallet
+        {
+            let db = recover(&file_path).expect("must recover db");
+            let wallet = Wallet::load(db).expect("must recover wallet");
+            assert_eq!(wallet.get_label(&tx_ref), None);
+            assert_eq!(wallet.get_label(&addr_ref), Some(&Label::new("invoice #1")));
+            assert_eq!(
+                wallet.get_label(&output_ref).map(|l| l.spendable),
+                Some(Some(false))
+            );
+            assert_eq!(wallet.labels().count(), 2);
+        }
 
-    let mut tx_amounts: Vec<(Txid, (Amount, Amount))> = wallet
-        .transactions()
-        .map(|ct| (ct.tx_node.txid, wallet.sent_and_received(&ct.tx_node)))
-        .collect();
-    tx_amounts.sort_by(|a1, a2| a1.0.cmp(&a2.0));
+        Ok(())
+    }
 
-    let tx = wallet.get_tx(txid).expect("transaction").tx_node.tx;
-    let (sent, received) = wallet.sent_and_received(&tx);
+    run(
+        "store.db",
+        |path| Ok(bdk_file_store::Store::create_new(DB_MAGIC, path)?),
+        |path| Ok(bdk_file_store::Store::open(DB_MAGIC, path)?),
+    )?;
+    run(
+        "store.sqlite",
+        |path| Ok(bdk_sqlite::Store::new(Connection::open(path)?)?),
+        |path| Ok(bdk_sqlite::Store::new(Connection::open(path)?)?),
+    )?;
 
-    // The funded wallet contains a tx with a 76_000 sats input and two outputs, one spending 25_000
-    // to a foreign address and one returning 50_000 back to the wallet as change. The remaining 1000
-    // sats are the transaction fee.
-    assert_eq!(sent.to_sat(), 76_000);
-    assert_eq!(received.to_sat(), 50_000);
+    Ok(())
 }
 
 #[test]
-fn test_get_funded_wallet_tx_fees() {
-    let (wallet, txid) = get_funded_wallet_wpkh();
+fn changeset_without_labels_and_utxo_locks_is_deserialized() {
+    let changeset = bdk_wallet::wallet::ChangeSet {
+        network: Some(Network::Regtest),
+        ..Default::default()
+    };
 
-    let tx = wallet.get_tx(txid).expect("transaction").tx_node.tx;
-    let tx_fee = wallet.calculate_fee(&tx).expect("transaction fee");
+    // changesets serialized before the labels and the locks were added don't have the fields
+    let mut value = serde_json::to_value(&changeset).unwrap();
+    assert!(value.as_object_mut().unwrap().remove("labels").is_some());
+    assert!(value
+        .as_object_mut()
+        .unwrap()
+        .remove("utxo_locks")
+        .is_some());
+    let deserialized: bdk_wallet::wallet::ChangeSet = serde_json::from_value(value).unwrap();
+    assert_eq!(deserialized, changeset);
+}
 
-    // The funded wallet contains a tx with a 76_000 sats input and two outputs, one spending 25_000
-    // to a foreign address and one returning 50_000 back to the wallet as change. The remaining 1000
-    // sats are the transaction fee.
-    assert_eq!(tx_fee, Amount::from_sat(1000))
+#[test]
+fn changeset_with_labels_and_utxo_locks_is_serialized_as_json() {
+    let outpoint = OutPoint::new(Txid::all_zeros(), 1);
+    let changeset = bdk_wallet::wallet::ChangeSet {
+        network: Some(Network::Regtest),
+        labels: [
+            (LabelRef::Tx(Txid::all_zeros()), Some(Label::new("payment"))),
+            (
+                LabelRef::Output(outpoint),
+                Some(Label {
+                    label: "deposit".to_string(),
+                    origin: Some("wpkh([d34db33f/84'/0'/0'])".to_string()),
+                    spendable: Some(false),
+                }),
+            ),
+            (LabelRef::Addr(ScriptBuf::new()), None),
+        ]
+        .into(),
+        utxo_locks: [
+            (
+                outpoint,
+                Some(UtxoLock {
+                    expiry_height: Some(100),
+                }),
+            ),
+            (OutPoint::new(Txid::all_zeros(), 2), None),
+        ]
+        .into(),
+        ..Default::default()
+    };
+
+    let json = serde_json::to_string(&changeset).unwrap();
+    let deserialized: bdk_wallet::wallet::ChangeSet = serde_json::from_str(&json).unwrap();
+    assert_eq!(deserialized, changeset);
 }
 
 #[test]
-fn test_get_funded_wallet_tx_fee_rate() {
-    let (wallet, txid) = get_funded_wallet_wpkh();
+fn load_file_store_written_before_labels_and_utxo_locks() -> anyhow::Result<()> {
+    use bdk_file_store::{FileError, Store, FORMAT_HEADER};
+    use bdk_wallet::wallet::LegacyChangeSet;
 
-    let tx = wallet.get_tx(txid).expect("transaction").tx_node.tx;
-    let tx_fee_rate = wallet
-        .calculate_fee_rate(&tx)
-        .expect("transaction fee rate");
+    let temp_dir = tempfile::tempdir().expect("must create tempdir");
+    let file_path = temp_dir.path().join("store.db");
+    let legacy_file_path = temp_dir.path().join("legacy_store.db");
 
-    // The funded wallet contains a tx with a 76_000 sats input and two outputs, one spending 25_000
-    // to a foreign address and one returning 50_000 back to the wallet as change. The remaining 1000
-    // sats are the transaction fee.
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let db = Store::create_new(DB_MAGIC, &file_path)?;
+    let mut wallet =
+        Wallet::new(desc, change_desc, db, Network::Testnet).expect("must init wallet");
+    wallet.insert_checkpoint(BlockId {
+        height: 1_000,
+        hash: BlockHash::all_zeros(),
+    })?;
+    let outpoint = receive_output_in_latest_block(&mut wallet, 25_000);
+    wallet.commit()?;
+    let changeset = Store::<bdk_wallet::wallet::ChangeSet>::open(DB_MAGIC, &file_path)?
+        .aggregate_changesets()?
+        .expect("must have changes");
+
+    // the same changes written in two records before the labels and the locks were added, by a
+    // store without a format header
+    let mut db = Store::create_new(DB_MAGIC, &legacy_file_path)?;
+    db.append_changeset(&LegacyChangeSet {
+        chain: changeset.chain,
+        indexed_tx_graph: Default::default(),
+        network: changeset.network,
+    })?;
+    db.append_changeset(&LegacyChangeSet {
+        chain: Default::default(),
+        indexed_tx_graph: changeset.indexed_tx_graph,
+        network: None,
+    })?;
+    drop(db);
+    let mut bytes = std::fs::read(&legacy_file_path)?;
+    bytes.drain(DB_MAGIC.len()..DB_MAGIC.len() + FORMAT_HEADER.len());
+    std::fs::write(&legacy_file_path, bytes)?;
 
-    // tx weight = 452 wu, as vbytes = (452 + 3) / 4 = 113
-    // fee_rate (sats per kwu) = fee / weight = 1000sat / 0.452kwu = 2212
-    // fee_rate (sats per vbyte ceil) = fee / vsize = 1000sat / 113vb = 9
-    assert_eq!(tx_fee_rate.to_sat_per_kwu(), 2212);
-    assert_eq!(tx_fee_rate.to_sat_per_vb_ceil(), 9);
+    assert_matches!(
+        Store::<bdk_wallet::wallet::ChangeSet>::open(DB_MAGIC, &legacy_file_path),
+        Err(FileError::MissingFormatHeader)
+    );
+    let db = Store::<bdk_wallet::wallet::ChangeSet>::migrate::<LegacyChangeSet, _>(
+        DB_MAGIC,
+        &legacy_file_path,
+    )?;
+    let mut loaded = Wallet::load(db).expect("must load wallet");
+    assert_eq!(loaded.network(), Network::Testnet);
+    assert_eq!(loaded.latest_checkpoint(), wallet.latest_checkpoint());
+    assert_eq!(
+        loaded.spk_index().last_revealed_indices(),
+        wallet.spk_index().last_revealed_indices()
+    );
+    assert_eq!(loaded.balance(), wallet.balance());
+
+    // the new records are appended after the migrated ones
+    loaded.set_label(LabelRef::Output(outpoint), Label::new("deposit"));
+    loaded.lock_utxo(outpoint, None);
+    loaded.commit()?;
+    let db = Store::open(DB_MAGIC, &legacy_file_path)?;
+    let loaded = Wallet::load(db).expect("must load wallet");
+    assert_eq!(
+        loaded.get_label(&LabelRef::Output(outpoint)),
+        Some(&Label::new("deposit"))
+    );
+    assert!(loaded.is_utxo_locked(outpoint));
+    assert_eq!(loaded.balance(), wallet.balance());
+
+    Ok(())
 }
 
 #[test]
-fn test_list_output() {
-    let (wallet, txid) = get_funded_wallet_wpkh();
-    let txos = wallet
-        .list_output()
-        .map(|op| (op.outpoint, op))
-        .collect::<std::collections::BTreeMap<_, _>>();
-    assert_eq!(txos.len(), 2);
-    for (op, txo) in txos {
-        if op.txid == txid {
-            assert_eq!(txo.txout.value.to_sat(), 50_000);
-            assert!(!txo.is_spent);
-        } else {
-            assert_eq!(txo.txout.value.to_sat(), 76_000);
-            assert!(txo.is_spent);
-        }
-    }
-}
+fn utxo_locks_are_persisted() -> anyhow::Result<()> {
+    fn run<B, FN, FR>(filename: &str, create_new: FN, recover: FR) -> anyhow::Result<()>
+    where
+        B: PersistBackend<bdk_wallet::wallet::ChangeSet> + Send + Sync + 'static,
+        FN: Fn(&Path) -> anyhow::Result<B>,
+        FR: Fn(&Path) -> anyhow::Result<B>,
+    {
+        let temp_dir = tempfile::tempdir().expect("must create tempdir");
+        let file_path = temp_dir.path().join(filename);
+        let (desc, change_desc) = get_test_wpkh_with_change_desc();
+        let outpoint0 = OutPoint::new(Txid::all_zeros(), 0);
+        let outpoint1 = OutPoint::new(Txid::all_zeros(), 1);
 
-macro_rules! assert_fee_rate {
-    ($psbt:expr, $fees:expr, $fee_rate:expr $( ,@dust_change $( $dust_change:expr )* )* $( ,@add_signature $( $add_signature:expr )* )* ) => ({
-        let psbt = $psbt.clone();
-        #[allow(unused_mut)]
-        let mut tx = $psbt.clone().extract_tx().expect("failed to extract tx");
-        $(
-            $( $add_signature )*
-                for txin in &mut tx.input {
-                    txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
-                }
-        )*
+        // create new wallet and lock a few outpoints
+        {
+            let db = create_new(&file_path).expect("must create db");
+            let mut wallet =
+                Wallet::new(desc, change_desc, db, Network::Testnet).expect("must init wallet");
+            wallet.lock_utxo(outpoint0, None);
+            wallet.lock_utxo(outpoint1, Some(100));
+            assert!(wallet.commit()?);
+            assert!(wallet.unlock_utxo(outpoint0));
+            assert!(!wallet.unlock_utxo(outpoint0));
+            assert!(wallet.commit()?);
+        }
 
-            #[allow(unused_mut)]
-        #[allow(unused_assignments)]
-        let mut dust_change = false;
-        $(
-            $( $dust_change )*
-                dust_change = true;
-        )*
+        // recover wallet
+        {
+            let db = recover(&file_path).expect("must recover db");
+            let wallet = Wallet::load(db).expect("must recover wallet");
+            assert!(!wallet.is_utxo_locked(outpoint0));
+            assert!(wallet.is_utxo_locked(outpoint1));
+            assert_eq!(
+                wallet.locked_utxos().collect::<Vec<_>>(),
+                vec![(
+                    outpoint1,
+                    UtxoLock {
+                        expiry_height: Some(100)
+                    }
+                )]
+            );
+        }
 
-            let fee_amount = psbt
-            .inputs
-            .iter()
-            .fold(Amount::ZERO, |acc, i| acc + i.witness_utxo.as_ref().unwrap().value)
-            - psbt
-            .unsigned_tx
-            .output
-            .iter()
-            .fold(Amount::ZERO, |acc, o| acc + o.value);
+        // the inputs locked when creating a transaction are staged until committed
+        let outpoint2 = {
+            let db = recover(&file_path).expect("must recover db");
+            let mut wallet = Wallet::load(db).expect("must recover wallet");
+            let outpoint2 = receive_output(
+                &mut wallet,
+                25_000,
+                ConfirmationTime::Unconfirmed { last_seen: 0 },
+            );
+            assert!(wallet.commit()?);
+            let drain_spk = wallet
+                .peek_address(KeychainKind::External, 5)
+                .script_pubkey();
+            let mut builder = wallet.build_tx();
+            builder
+                .drain_to(drain_spk)
+                .add_utxo(outpoint2)?
+                .manually_selected_only()
+                .lock_selected_utxos(None);
+            builder.finish()?;
+            assert_eq!(
+                wallet.staged().utxo_locks.get(&outpoint2),
+                Some(&Some(UtxoLock {
+                    expiry_height: None
+                }))
+            );
+            assert!(wallet.commit()?);
+            outpoint2
+        };
+        {
+            let db = recover(&file_path).expect("must recover db");
+            let wallet = Wallet::load(db).expect("must recover wallet");
+            assert!(wallet.is_utxo_locked(outpoint2));
+        }
 
-        assert_eq!(fee_amount, $fees);
+        Ok(())
+    }
 
-        let tx_fee_rate = (fee_amount / tx.weight())
-            .to_sat_per_kwu();
-        let fee_rate = $fee_rate.to_sat_per_kwu();
-        let half_default = FeeRate::BROADCAST_MIN.checked_div(2)
-            .unwrap()
-            .to_sat_per_kwu();
+    run(
+        "store.db",
+        |path| Ok(bdk_file_store::Store::create_new(DB_MAGIC, path)?),
+        |path| Ok(bdk_file_store::Store::open(DB_MAGIC, path)?),
+    )?;
+    run(
+        "store.sqlite",
+        |path| Ok(bdk_sqlite::Store::new(Connection::open(path)?)?),
+        |path| Ok(bdk_sqlite::Store::new(Connection::open(path)?)?),
+    )?;
 
-        if !dust_change {
-            assert!(tx_fee_rate >= fee_rate && tx_fee_rate - fee_rate < half_default, "Expected fee rate of {:?}, the tx has {:?}", fee_rate, tx_fee_rate);
-        } else {
-            assert!(tx_fee_rate >= fee_rate, "Expected fee rate of at least {:?}, the tx has {:?}", fee_rate, tx_fee_rate);
-        }
-    });
+    Ok(())
 }
 
-macro_rules! from_str {
-    ($e:expr, $t:ty) => {{
-        use core::str::FromStr;
-        <$t>::from_str($e).unwrap()
-    }};
+#[test]
+fn custom_keychains_are_persisted() -> anyhow::Result<()> {
+    fn run<B, FN, FR>(filename: &str, create_new: FN, recover: FR) -> anyhow::Result<()>
+    where
+        B: PersistBackend<bdk_wallet::wallet::ChangeSet> + Send + Sync + 'static,
+        FN: Fn(&Path) -> anyhow::Result<B>,
+        FR: Fn(&Path) -> anyhow::Result<B>,
+    {
+        let temp_dir = tempfile::tempdir().expect("must create tempdir");
+        let file_path = temp_dir.path().join(filename);
+        let (desc, change_desc) = get_test_wpkh_with_change_desc();
+        let custom = KeychainKind::Custom(7);
 
-    ($e:expr) => {
-        from_str!($e, _)
-    };
-}
+        let addr = {
+            let db = create_new(&file_path).expect("must create db");
+            let mut wallet =
+                Wallet::new(desc, change_desc, db, Network::Testnet).expect("must init wallet");
+            wallet.add_keychain(custom, get_test_tr_single_sig_xprv())?;
+            wallet.reveal_next_address(custom)?
+        };
 
-#[test]
-#[should_panic(expected = "NoRecipients")]
-fn test_create_tx_empty_recipients() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    wallet.build_tx().finish().unwrap();
-}
+        // recover wallet, the custom keychain is back but its private keys must be added again
+        {
+            let db = recover(&file_path).expect("must recover db");
+            let mut wallet = Wallet::load(db).expect("must recover wallet");
+            assert_eq!(wallet.keychains().count(), 3);
+            assert_eq!(wallet.derivation_index(custom), Some(0));
+            assert_eq!(wallet.peek_address(custom, 0), addr);
+            assert!(wallet.get_signers(custom).signers().is_empty());
+
+            wallet.add_keychain(custom, get_test_tr_single_sig_xprv())?;
+            assert_eq!(wallet.get_signers(custom).signers().len(), 1);
+            assert!(!wallet.commit()?, "nothing new to persist");
+        }
 
-#[test]
-#[should_panic(expected = "NoUtxosSelected")]
-fn test_create_tx_manually_selected_empty_utxos() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .manually_selected_only();
-    builder.finish().unwrap();
+        Ok(())
+    }
+
+    run(
+        "store.db",
+        |path| Ok(bdk_file_store::Store::create_new(DB_MAGIC, path)?),
+        |path| Ok(bdk_file_store::Store::open(DB_MAGIC, path)?),
+    )?;
+    run(
+        "store.sqlite",
+        |path| Ok(bdk_sqlite::Store::new(Connection::open(path)?)?),
+        |path| Ok(bdk_sqlite::Store::new(Connection::open(path)?)?),
+    )?;
+
+    Ok(())
 }
 
 #[test]
-fn test_create_tx_version_0() {
+fn test_custom_keychains() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let custom = KeychainKind::Custom(0);
+    wallet
+        .add_keychain(custom, get_test_tr_single_sig_xprv())
+        .unwrap();
+
+    let addr = wallet.reveal_next_address(custom).unwrap();
+    assert_eq!(addr.keychain, custom);
+    let tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: addr.script_pubkey(),
+            value: Amount::from_sat(30_000),
+        }],
+    };
+    let custom_outpoint = OutPoint::new(tx.txid(), 0);
+    wallet
+        .insert_tx(
+            tx,
+            ConfirmationTime::Confirmed {
+                height: 2_000,
+                time: 0,
+            },
+        )
+        .unwrap();
+
+    assert_eq!(wallet.balance().confirmed, Amount::from_sat(80_000));
+    assert_eq!(
+        wallet.balance_of_keychains([custom]).confirmed,
+        Amount::from_sat(30_000)
+    );
+    assert_eq!(
+        wallet
+            .balance_of_keychains([KeychainKind::External, custom])
+            .confirmed,
+        Amount::from_sat(80_000)
+    );
+    assert_eq!(
+        wallet
+            .list_unspent_of_keychains([custom])
+            .map(|utxo| utxo.outpoint)
+            .collect::<Vec<_>>(),
+        vec![custom_outpoint]
+    );
+
+    // only spend from the custom keychain, and send the change back to it
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .version(0);
-    assert!(matches!(builder.finish(), Err(CreateTxError::Version0)));
-}
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(10_000))
+        .spend_from_keychains([custom])
+        .change_keychain(custom);
+    let mut psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.input.len(), 1);
+    assert_eq!(psbt.unsigned_tx.input[0].previous_output, custom_outpoint);
+    let change = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .find(|txout| txout.script_pubkey != addr.script_pubkey())
+        .unwrap();
+    assert_eq!(
+        wallet.derivation_of_spk(&change.script_pubkey),
+        Some((custom, 1))
+    );
 
-#[test]
-fn test_create_tx_version_1_csv() {
-    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_csv());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    // the custom keychain signs its own inputs
+    let finalized = wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    assert!(finalized);
+
+    // not enough funds in the custom keychain alone
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .version(1);
-    assert!(matches!(builder.finish(), Err(CreateTxError::Version1Csv)));
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(40_000))
+        .spend_from_keychains([custom]);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        ))
+    );
 }
 
 #[test]
-fn test_create_tx_custom_version() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .version(42);
-    let psbt = builder.finish().unwrap();
+#[allow(deprecated)]
+fn test_keychain_kind_bytes() {
+    assert_eq!(KeychainKind::External.to_bytes(), b"e");
+    assert_eq!(KeychainKind::Internal.to_bytes(), b"i");
+    assert_eq!(KeychainKind::Custom(1).to_bytes(), b"c\x00\x00\x00\x01");
+    assert_ne!(
+        KeychainKind::Custom(0).to_bytes(),
+        KeychainKind::Custom(1).to_bytes()
+    );
 
-    assert_eq!(psbt.unsigned_tx.version.0, 42);
+    // the single byte encoding of the external and internal keychains is unchanged
+    for keychain in [KeychainKind::External, KeychainKind::Internal] {
+        assert_eq!([keychain.as_byte()], keychain.to_bytes().as_slice());
+    }
 }
 
 #[test]
-fn test_create_tx_default_locktime_is_last_sync_height() {
+fn test_sign_single_key_with_taproot_keychain() {
+    // the ECDSA signer of the `wpkh` private key only signs the inputs of its keychain instead of
+    // failing to compute the sighash of the taproot input
     let (mut wallet, _) = get_funded_wallet_wpkh();
+    let custom = KeychainKind::Custom(0);
+    wallet
+        .add_keychain(custom, get_test_tr_single_sig_xprv())
+        .unwrap();
+    let addr = wallet.reveal_next_address(custom).unwrap();
+    let tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: addr.script_pubkey(),
+            value: Amount::from_sat(30_000),
+        }],
+    };
+    wallet
+        .insert_tx(
+            tx,
+            ConfirmationTime::Confirmed {
+                height: 2_000,
+                time: 0,
+            },
+        )
+        .unwrap();
 
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let psbt = builder.finish().unwrap();
+    builder.drain_wallet().drain_to(addr.script_pubkey());
+    let mut psbt = builder.finish().unwrap();
+    assert!(psbt
+        .inputs
+        .iter()
+        .any(|input| input.tap_internal_key.is_some()));
+    assert!(psbt
+        .inputs
+        .iter()
+        .any(|input| input.tap_internal_key.is_none()));
 
-    // Since we never synced the wallet we don't have a last_sync_height
-    // we could use to try to prevent fee sniping. We default to 0.
-    assert_eq!(psbt.unsigned_tx.lock_time.to_consensus_u32(), 2_000);
+    let finalized = wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    assert!(finalized);
 }
 
 #[test]
-fn test_create_tx_fee_sniping_locktime_last_sync() {
+fn test_add_keychain_errors() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-
-    let psbt = builder.finish().unwrap();
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
 
-    // If there's no current_height we're left with using the last sync height
-    assert_eq!(
-        psbt.unsigned_tx.lock_time.to_consensus_u32(),
-        wallet.latest_checkpoint().height()
+    // adding the same descriptor again is a no-op
+    wallet.add_keychain(KeychainKind::External, desc).unwrap();
+    assert_matches!(
+        wallet.add_keychain(KeychainKind::External, get_test_tr_single_sig_xprv()),
+        Err(AddKeychainError::KeychainAlreadyExists(
+            KeychainKind::External
+        ))
     );
+    assert_matches!(
+        wallet.add_keychain(KeychainKind::Custom(1), change_desc),
+        Err(AddKeychainError::DescriptorAlreadyExists(
+            KeychainKind::Internal
+        ))
+    );
+    assert_matches!(
+        wallet.add_keychain(KeychainKind::Custom(1), "wpkh(invalid)"),
+        Err(AddKeychainError::Descriptor(_))
+    );
+    assert_eq!(wallet.keychains().count(), 2);
 }
 
 #[test]
-fn test_create_tx_default_locktime_cltv() {
-    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_cltv());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let psbt = builder.finish().unwrap();
-
-    assert_eq!(psbt.unsigned_tx.lock_time.to_consensus_u32(), 100_000);
-}
-
-#[test]
-fn test_create_tx_custom_locktime() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .current_height(630_001)
-        .nlocktime(absolute::LockTime::from_height(630_000).unwrap());
-    let psbt = builder.finish().unwrap();
+fn single_descriptor_wallet_is_persisted() -> anyhow::Result<()> {
+    fn run<B, FN, FR>(filename: &str, create_new: FN, recover: FR) -> anyhow::Result<()>
+    where
+        B: PersistBackend<bdk_wallet::wallet::ChangeSet> + Send + Sync + 'static,
+        FN: Fn(&Path) -> anyhow::Result<B>,
+        FR: Fn(&Path) -> anyhow::Result<B>,
+    {
+        let temp_dir = tempfile::tempdir().expect("must create tempdir");
+        let file_path = temp_dir.path().join(filename);
+        let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
 
-    // When we explicitly specify a nlocktime
-    // we don't try any fee sniping prevention trick
-    // (we ignore the current_height)
-    assert_eq!(psbt.unsigned_tx.lock_time.to_consensus_u32(), 630_000);
-}
+        let addr = {
+            let db = create_new(&file_path).expect("must create db");
+            let mut wallet =
+                Wallet::new_single(desc, db, Network::Testnet).expect("must init wallet");
+            wallet.reveal_next_address(KeychainKind::External)?
+        };
 
-#[test]
-fn test_create_tx_custom_locktime_compatible_with_cltv() {
-    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_cltv());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .nlocktime(absolute::LockTime::from_height(630_000).unwrap());
-    let psbt = builder.finish().unwrap();
+        {
+            let db = recover(&file_path).expect("must recover db");
+            let wallet = Wallet::load(db).expect("must recover wallet");
+            assert_eq!(wallet.keychains().count(), 1);
+            assert_eq!(wallet.peek_address(KeychainKind::External, 0), addr);
+        }
 
-    assert_eq!(psbt.unsigned_tx.lock_time.to_consensus_u32(), 630_000);
-}
+        // the change descriptor doesn't match the loaded wallet
+        let db = recover(&file_path).expect("must recover db");
+        assert_matches!(
+            Wallet::new_or_load(desc, change_desc, db, Network::Testnet),
+            Err(NewOrLoadError::LoadedDescriptorDoesNotMatch {
+                got: None,
+                keychain: KeychainKind::Internal,
+            })
+        );
 
-#[test]
-fn test_create_tx_custom_locktime_incompatible_with_cltv() {
-    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_cltv());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .nlocktime(absolute::LockTime::from_height(50000).unwrap());
-    assert!(matches!(builder.finish(),
-        Err(CreateTxError::LockTime { requested, required })
-        if requested.to_consensus_u32() == 50_000 && required.to_consensus_u32() == 100_000));
-}
+        Ok(())
+    }
 
-#[test]
-fn test_create_tx_no_rbf_csv() {
-    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_csv());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let psbt = builder.finish().unwrap();
+    run(
+        "store.db",
+        |path| Ok(bdk_file_store::Store::create_new(DB_MAGIC, path)?),
+        |path| Ok(bdk_file_store::Store::open(DB_MAGIC, path)?),
+    )?;
+    run(
+        "store.sqlite",
+        |path| Ok(bdk_sqlite::Store::new(Connection::open(path)?)?),
+        |path| Ok(bdk_sqlite::Store::new(Connection::open(path)?)?),
+    )?;
 
-    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(6));
+    Ok(())
 }
 
 #[test]
-fn test_create_tx_with_default_rbf_csv() {
-    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_csv());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    // When CSV is enabled it takes precedence over the rbf value (unless forced by the user).
-    // It will be set to the OP_CSV value, in this case 6
-    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(6));
-}
+fn test_single_descriptor_wallet() {
+    let (mut wallet, _) = get_funded_wallet_single(get_test_tr_single_sig_xprv());
+    assert_eq!(wallet.keychains().count(), 1);
 
-#[test]
-fn test_create_tx_with_custom_rbf_csv() {
-    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_csv());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    // the external keychain is used in place of the internal one
+    let addr = wallet.next_unused_address(KeychainKind::Internal).unwrap();
+    assert_eq!(addr.keychain, KeychainKind::External);
+    assert_eq!(
+        wallet.public_descriptor(KeychainKind::Internal),
+        wallet.public_descriptor(KeychainKind::External)
+    );
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .enable_rbf_with_sequence(Sequence(3));
-    assert!(matches!(builder.finish(),
-        Err(CreateTxError::RbfSequenceCsv { rbf, csv })
-        if rbf.to_consensus_u32() == 3 && csv.to_consensus_u32() == 6));
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+    let change = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .find(|txout| txout.script_pubkey != addr.script_pubkey())
+        .unwrap();
+    assert_matches!(
+        wallet.derivation_of_spk(&change.script_pubkey),
+        Some((KeychainKind::External, _))
+    );
+
+    let finalized = wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    assert!(finalized);
 }
 
 #[test]
-fn test_create_tx_no_rbf_cltv() {
-    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_cltv());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+fn test_single_descriptor_wallet_balance() {
+    let (mut wallet, _) = get_funded_wallet_single(get_test_wpkh());
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
     let mut builder = wallet.build_tx();
     builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
     let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let change = tx
+        .output
+        .iter()
+        .find(|txout| txout.script_pubkey != addr.script_pubkey())
+        .unwrap()
+        .value;
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
 
-    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFE));
+    // the change of our own transaction is trusted, an incoming payment is not
+    receive_output(
+        &mut wallet,
+        10_000,
+        ConfirmationTime::Unconfirmed { last_seen: 0 },
+    );
+    let balance = wallet.balance();
+    assert_eq!(balance.confirmed, Amount::ZERO);
+    assert_eq!(balance.trusted_pending, change);
+    assert_eq!(balance.untrusted_pending, Amount::from_sat(10_000));
 }
 
 #[test]
-fn test_create_tx_invalid_rbf_sequence() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .enable_rbf_with_sequence(Sequence(0xFFFFFFFE));
-    assert!(matches!(builder.finish(), Err(CreateTxError::RbfSequence)));
-}
+fn test_single_descriptor_watch_only_wallet() {
+    let (mut wallet, _) = get_funded_wallet_single(
+        "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)",
+    );
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
 
-#[test]
-fn test_create_tx_custom_rbf_sequence() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    // transactions can be created for an external signer
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .enable_rbf_with_sequence(Sequence(0xDEADBEEF));
-    let psbt = builder.finish().unwrap();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(psbt.inputs[0].bip32_derivation.len(), 1);
 
-    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xDEADBEEF));
+    let finalized = wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    assert!(!finalized);
 }
 
 #[test]
-fn test_create_tx_change_policy() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .do_not_spend_change();
-    assert!(builder.finish().is_ok());
+fn test_multipath_descriptor_wallet() {
+    let descriptor = "wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/<0;1>/*)";
+    let mut wallet = Wallet::new_single_no_persist(descriptor, Network::Testnet).unwrap();
+    let mut expected = Wallet::new_no_persist(
+        &descriptor.replace("<0;1>", "0"),
+        &descriptor.replace("<0;1>", "1"),
+        Network::Testnet,
+    )
+    .unwrap();
 
-    // wallet has no change, so setting `only_spend_change`
-    // should cause tx building to fail
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .only_spend_change();
-    assert!(matches!(
-        builder.finish(),
-        Err(CreateTxError::CoinSelection(
-            coin_selection::Error::InsufficientFunds { .. }
-        )),
-    ));
+    for keychain in [KeychainKind::External, KeychainKind::Internal] {
+        assert_eq!(
+            wallet.public_descriptor(keychain),
+            expected.public_descriptor(keychain)
+        );
+        assert_eq!(
+            wallet.reveal_next_address(keychain).unwrap(),
+            expected.reveal_next_address(keychain).unwrap()
+        );
+    }
+
+    // the descriptor must have exactly two paths
+    let err = Wallet::new_single_no_persist(
+        "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/<0;1;2>/*)",
+        Network::Testnet,
+    );
+    assert_matches!(err, Err(DescriptorError::MultiPathLength(3)));
+    // and it can't be given along with a change descriptor
+    let err = Wallet::new_no_persist(descriptor, descriptor, Network::Testnet);
+    assert_matches!(err, Err(DescriptorError::MultiPath));
+
+    // the wallet is loaded with both keychains
+    let temp_dir = tempfile::tempdir().expect("must create tempdir");
+    let file_path = temp_dir.path().join("bdk_wallet_multipath.db");
+    let db = bdk_sqlite::Store::new(Connection::open(&file_path).unwrap()).unwrap();
+    let mut wallet = Wallet::new_single(descriptor, db, Network::Testnet).unwrap();
+    let change = wallet.reveal_next_address(KeychainKind::Internal).unwrap();
+    wallet.commit().unwrap();
+    let db = bdk_sqlite::Store::new(Connection::open(&file_path).unwrap()).unwrap();
+    let wallet = Wallet::load(db).unwrap();
+    assert_eq!(
+        wallet.public_descriptor(KeychainKind::Internal),
+        expected.public_descriptor(KeychainKind::Internal)
+    );
+    assert_eq!(
+        wallet.derivation_index(KeychainKind::Internal),
+        Some(change.index)
+    );
 }
 
 #[test]
-fn test_create_tx_default_sequence() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let psbt = builder.finish().unwrap();
+fn test_wallet_policy() {
+    let xpub_0 = "[c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe";
+    let xpub_1 = "[73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M";
+    let policy = WalletPolicy::new(
+        "wsh(or_d(multi(2,@0/**,@1/**),and_v(v:pk(@0/<2;3>/*),older(144))))",
+        vec![xpub_0.parse().unwrap(), xpub_1.parse().unwrap()],
+    )
+    .unwrap();
 
-    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFE));
-}
+    // a wallet created from the policy has the same addresses as the expanded descriptors
+    let mut wallet = Wallet::new_single_no_persist(&policy, Network::Testnet).unwrap();
+    let descriptor = format!(
+        "wsh(or_d(multi(2,{0}/0/*,{1}/0/*),and_v(v:pk({0}/2/*),older(144))))",
+        xpub_0, xpub_1
+    );
+    let change_descriptor = format!(
+        "wsh(or_d(multi(2,{0}/1/*,{1}/1/*),and_v(v:pk({0}/3/*),older(144))))",
+        xpub_0, xpub_1
+    );
+    let mut expected =
+        Wallet::new_no_persist(&descriptor, &change_descriptor, Network::Testnet).unwrap();
+    for keychain in [KeychainKind::External, KeychainKind::Internal] {
+        assert_eq!(
+            wallet.reveal_next_address(keychain).unwrap(),
+            expected.reveal_next_address(keychain).unwrap()
+        );
+    }
 
-macro_rules! check_fee {
-    ($wallet:expr, $psbt: expr) => {{
-        let tx = $psbt.clone().extract_tx().expect("failed to extract tx");
-        let tx_fee = $wallet.calculate_fee(&tx).ok();
-        assert_eq!(tx_fee, $psbt.fee_amount());
-        tx_fee
-    }};
-}
+    // exporting either wallet gives back the policy
+    assert_eq!(WalletPolicy::export_wallet(&wallet).unwrap(), policy);
+    assert_eq!(WalletPolicy::export_wallet(&expected).unwrap(), policy);
 
-#[test]
-fn test_create_tx_drain_wallet_and_drain_to() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
+    // policies are for a single network
+    let err = Wallet::new_single_no_persist(&policy, Network::Bitcoin);
+    assert_matches!(err, Err(DescriptorError::Key(_)));
 
-    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    // the expanded descriptors can also be passed separately
+    let (policy_descriptor, policy_change_descriptor) = policy.clone().into_descriptors().unwrap();
+    let wallet = Wallet::new_no_persist(
+        policy_descriptor,
+        policy_change_descriptor,
+        Network::Testnet,
+    )
+    .unwrap();
+    assert_eq!(WalletPolicy::export_wallet(&wallet).unwrap(), policy);
+
+    // wallets without a change descriptor, or whose descriptors differ in more than their
+    // derivation steps, can't be exported
+    let wallet = Wallet::new_single_no_persist(&descriptor, Network::Testnet).unwrap();
+    assert_matches!(
+        WalletPolicy::export_wallet(&wallet),
+        Err(WalletPolicyError::MissingChangeDescriptor)
+    );
+    let wallet = Wallet::new_no_persist(
+        &descriptor,
+        &change_descriptor.replace("older(144)", "older(145)"),
+        Network::Testnet,
+    )
+    .unwrap();
+    assert_matches!(
+        WalletPolicy::export_wallet(&wallet),
+        Err(WalletPolicyError::IncompatibleDescriptors)
+    );
+    let (wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
+    assert_matches!(
+        WalletPolicy::export_wallet(&wallet),
+        Err(WalletPolicyError::IncompatibleDescriptors)
+    );
+
+    // only the public keys are exported
+    let (descriptor, change_descriptor) = get_test_tr_single_sig_xprv_with_change_desc();
+    let (wallet, _) = get_funded_wallet_with_change(descriptor, change_descriptor);
+    let policy = WalletPolicy::export_wallet(&wallet).unwrap();
+    assert_eq!(policy.template(), "tr(@0/**)");
     assert_eq!(
-        psbt.unsigned_tx.output[0].value,
-        Amount::from_sat(50_000) - fee.unwrap_or(Amount::ZERO)
+        policy.keys()[0].to_string(),
+        "tpubD6NzVbkrYhZ4WgCeJid2Zds24zATB58r1q1qTLMuApUxZUxzETADNTeP6SvZKSsXs4qhvFAC21GFjXHwgxAcDtZqzzj8JMpsFDgqyjSJHGa"
     );
 }
 
 #[test]
-fn test_create_tx_drain_wallet_and_drain_to_and_with_recipient() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")
-        .unwrap()
-        .assume_checked();
-    let drain_addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(20_000))
-        .drain_to(drain_addr.script_pubkey())
-        .drain_wallet();
-    let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
-    let outputs = psbt.unsigned_tx.output;
+fn test_bsms_setup() {
+    let secp = Secp256k1::new();
+    let path = bip32::DerivationPath::from_str("m/48h/1h/0h/2h").unwrap();
+    let token = Token::standard();
+    let signers = [
+        "tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS",
+        "tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy",
+        "tprv8ZgxMBicQKsPdpkqS7Eair4YxjcuuvDPNYmKX3sCniCf16tHEVrjjiSXEkFRnUH77yXc6ZcwHHcLNfjdi5qUvw3VDfgYiH5mNsj5izuiu2N",
+    ]
+    .map(|tprv| bip32::Xpriv::from_str(tprv).unwrap());
 
-    assert_eq!(outputs.len(), 2);
-    let main_output = outputs
-        .iter()
-        .find(|x| x.script_pubkey == addr.script_pubkey())
-        .unwrap();
-    let drain_output = outputs
+    let key_records = signers
         .iter()
-        .find(|x| x.script_pubkey == drain_addr.script_pubkey())
-        .unwrap();
-    assert_eq!(main_output.value, Amount::from_sat(20_000));
-    assert_eq!(
-        drain_output.value,
-        Amount::from_sat(30_000) - fee.unwrap_or(Amount::ZERO)
-    );
-}
+        .enumerate()
+        .map(|(i, xprv)| {
+            let description = format!("signer {}", i);
+            let record = KeyRecord::new(
+                *xprv,
+                &path,
+                token.clone(),
+                &description,
+                Network::Testnet,
+                &secp,
+            )
+            .unwrap();
+            KeyRecord::from_str(&record.to_string()).unwrap()
+        })
+        .collect::<Vec<_>>();
+    let descriptor_record = DescriptorRecord::new(2, &key_records, Network::Testnet, &secp)
+        .unwrap()
+        .to_string();
 
-#[test]
-fn test_create_tx_drain_to_and_utxos() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let utxos: Vec<_> = wallet.list_unspent().map(|u| u.outpoint).collect();
-    let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .add_utxos(&utxos)
+    let mut wallets = key_records
+        .iter()
+        .map(|key_record| {
+            let record = DescriptorRecord::from_str(&descriptor_record).unwrap();
+            record.verify(key_record.key(), Network::Testnet).unwrap();
+            // the record doesn't verify on another network
+            assert_matches!(
+                record.verify(key_record.key(), Network::Bitcoin),
+                Err(BsmsError::FirstAddressMismatch)
+            );
+            let mut wallet = Wallet::new_single_no_persist(&record, Network::Testnet).unwrap();
+            assert_eq!(
+                wallet
+                    .reveal_next_address(KeychainKind::External)
+                    .unwrap()
+                    .address,
+                record.first_address().clone().assume_checked()
+            );
+            wallet
+        })
+        .collect::<Vec<_>>();
+    let change = wallets[0]
+        .reveal_next_address(KeychainKind::Internal)
         .unwrap();
-    let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
-
-    assert_eq!(psbt.unsigned_tx.output.len(), 1);
-    assert_eq!(
-        psbt.unsigned_tx.output[0].value,
-        Amount::from_sat(50_000) - fee.unwrap_or(Amount::ZERO)
-    );
-}
-
-#[test]
-#[should_panic(expected = "NoRecipients")]
-fn test_create_tx_drain_to_no_drain_wallet_no_utxos() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let drain_addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.drain_to(drain_addr.script_pubkey());
-    builder.finish().unwrap();
+    for wallet in &mut wallets[1..] {
+        assert_eq!(
+            wallet.reveal_next_address(KeychainKind::Internal).unwrap(),
+            change
+        );
+    }
 }
 
 #[test]
-fn test_create_tx_default_fee_rate() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
+fn test_labels_bip329_round_trip() {
+    let (mut wallet, txid) = get_funded_wallet_wpkh();
+    let addr = wallet.peek_address(KeychainKind::External, 0);
+    wallet.set_label(LabelRef::Tx(txid), Label::new("salary"));
+    wallet.set_label(LabelRef::Addr(addr.script_pubkey()), Label::new("employer"));
 
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::BROADCAST_MIN, @add_signature);
-}
+    let exported = wallet.export_labels();
+    assert_eq!(exported.lines().count(), 2);
 
-#[test]
-fn test_create_tx_custom_fee_rate() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .fee_rate(FeeRate::from_sat_per_vb_unchecked(5));
-    let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
+    let (mut other, _) = get_funded_wallet_wpkh();
+    assert_eq!(other.import_labels(&exported).unwrap(), 2);
+    assert!(other.labels().eq(wallet.labels()));
+    assert_eq!(other.staged().labels.len(), 2);
 
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(5), @add_signature);
+    // an invalid record doesn't import anything
+    let (mut other, _) = get_funded_wallet_wpkh();
+    let invalid = format!("{}\n{{\"type\":\"tx\",\"ref\":\"nope\"}}", exported);
+    assert_matches!(
+        other.import_labels(&invalid),
+        Err(Bip329Error::InvalidRef { line: 3, .. })
+    );
+    assert_eq!(other.labels().count(), 0);
 }
 
 #[test]
-fn test_create_tx_absolute_fee() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .fee_absolute(Amount::from_sat(100));
-    let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
+fn test_error_external_and_internal_are_the_same() {
+    // identical descriptors should fail to create wallet
+    let desc = get_test_wpkh();
+    let err = Wallet::new_no_persist(desc, desc, Network::Testnet);
+    assert!(
+        matches!(&err, Err(DescriptorError::ExternalAndInternalAreTheSame)),
+        "expected same descriptors error, got {:?}",
+        err,
+    );
 
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(100));
-    assert_eq!(psbt.unsigned_tx.output.len(), 1);
-    assert_eq!(
-        psbt.unsigned_tx.output[0].value,
-        Amount::from_sat(50_000) - fee.unwrap_or(Amount::ZERO)
+    // public + private of same descriptor should fail to create wallet
+    let desc = "wpkh(tprv8ZgxMBicQKsPdcAqYBpzAFwU5yxBUo88ggoBqu1qPcHUfSbKK1sKMLmC7EAk438btHQrSdu3jGGQa6PA71nvH5nkDexhLteJqkM4dQmWF9g/84'/1'/0'/0/*)";
+    let change_desc = "wpkh([3c31d632/84'/1'/0']tpubDCYwFkks2cg78N7eoYbBatsFEGje8vW8arSKW4rLwD1AU1s9KJMDRHE32JkvYERuiFjArrsH7qpWSpJATed5ShZbG9KsskA5Rmi6NSYgYN2/0/*)";
+    let err = Wallet::new_no_persist(desc, change_desc, Network::Testnet);
+    assert!(
+        matches!(err, Err(DescriptorError::ExternalAndInternalAreTheSame)),
+        "expected same descriptors error, got {:?}",
+        err,
     );
 }
 
 #[test]
-fn test_create_tx_absolute_zero_fee() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .fee_absolute(Amount::ZERO);
-    let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
+fn test_descriptor_checksum() {
+    let (wallet, _) = get_funded_wallet_wpkh();
+    let checksum = wallet.descriptor_checksum(KeychainKind::External);
+    assert_eq!(checksum.len(), 8);
 
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::ZERO);
-    assert_eq!(psbt.unsigned_tx.output.len(), 1);
-    assert_eq!(
-        psbt.unsigned_tx.output[0].value,
-        Amount::from_sat(50_000) - fee.unwrap_or(Amount::ZERO)
-    );
+    let raw_descriptor = wallet
+        .keychains()
+        .next()
+        .unwrap()
+        .1
+        .to_string()
+        .split_once('#')
+        .unwrap()
+        .0
+        .to_string();
+    assert_eq!(calc_checksum(&raw_descriptor).unwrap(), checksum);
 }
 
 #[test]
-#[should_panic(expected = "InsufficientFunds")]
-fn test_create_tx_absolute_high_fee() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .fee_absolute(Amount::from_sat(60_000));
-    let _ = builder.finish().unwrap();
+fn test_get_funded_wallet_balance() {
+    let (wallet, _) = get_funded_wallet_wpkh();
+
+    // The funded wallet contains a tx with a 76_000 sats input and two outputs, one spending 25_000
+    // to a foreign address and one returning 50_000 back to the wallet as change. The remaining 1000
+    // sats are the transaction fee.
+    assert_eq!(wallet.balance().confirmed, Amount::from_sat(50_000));
 }
 
 #[test]
-fn test_create_tx_add_change() {
-    use bdk_wallet::wallet::tx_builder::TxOrdering;
+fn test_get_funded_wallet_sent_and_received() {
+    let (wallet, txid) = get_funded_wallet_wpkh();
 
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .ordering(TxOrdering::Untouched);
-    let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
+    let mut tx_amounts: Vec<(Txid, (Amount, Amount))> = wallet
+        .transactions()
+        .map(|ct| (ct.tx_node.txid, wallet.sent_and_received(&ct.tx_node)))
+        .collect();
+    tx_amounts.sort_by(|a1, a2| a1.0.cmp(&a2.0));
 
-    assert_eq!(psbt.unsigned_tx.output.len(), 2);
-    assert_eq!(psbt.unsigned_tx.output[0].value, Amount::from_sat(25_000));
-    assert_eq!(
-        psbt.unsigned_tx.output[1].value,
-        Amount::from_sat(25_000) - fee.unwrap_or(Amount::ZERO)
-    );
+    let tx = wallet.get_tx(txid).expect("transaction").tx_node.tx;
+    let (sent, received) = wallet.sent_and_received(&tx);
+
+    // The funded wallet contains a tx with a 76_000 sats input and two outputs, one spending 25_000
+    // to a foreign address and one returning 50_000 back to the wallet as change. The remaining 1000
+    // sats are the transaction fee.
+    assert_eq!(sent.to_sat(), 76_000);
+    assert_eq!(received.to_sat(), 50_000);
 }
 
 #[test]
-fn test_create_tx_skip_change_dust() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(49_800));
-    let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
+fn test_get_funded_wallet_tx_fees() {
+    let (wallet, txid) = get_funded_wallet_wpkh();
 
-    assert_eq!(psbt.unsigned_tx.output.len(), 1);
-    assert_eq!(psbt.unsigned_tx.output[0].value.to_sat(), 49_800);
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(200));
-}
+    let tx = wallet.get_tx(txid).expect("transaction").tx_node.tx;
+    let tx_fee = wallet.calculate_fee(&tx).expect("transaction fee");
 
-#[test]
-#[should_panic(expected = "InsufficientFunds")]
-fn test_create_tx_drain_to_dust_amount() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    // very high fee rate, so that the only output would be below dust
-    let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .fee_rate(FeeRate::from_sat_per_vb_unchecked(454));
-    builder.finish().unwrap();
+    // The funded wallet contains a tx with a 76_000 sats input and two outputs, one spending 25_000
+    // to a foreign address and one returning 50_000 back to the wallet as change. The remaining 1000
+    // sats are the transaction fee.
+    assert_eq!(tx_fee, Amount::from_sat(1000))
 }
 
 #[test]
-fn test_create_tx_ordering_respected() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(10_000))
-        .ordering(bdk_wallet::wallet::tx_builder::TxOrdering::Bip69Lexicographic);
-    let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
+fn test_get_funded_wallet_tx_fee_rate() {
+    let (wallet, txid) = get_funded_wallet_wpkh();
 
-    assert_eq!(psbt.unsigned_tx.output.len(), 3);
-    assert_eq!(
-        psbt.unsigned_tx.output[0].value,
-        Amount::from_sat(10_000) - fee.unwrap_or(Amount::ZERO)
-    );
-    assert_eq!(psbt.unsigned_tx.output[1].value, Amount::from_sat(10_000));
-    assert_eq!(psbt.unsigned_tx.output[2].value, Amount::from_sat(30_000));
+    let tx = wallet.get_tx(txid).expect("transaction").tx_node.tx;
+    let tx_fee_rate = wallet
+        .calculate_fee_rate(&tx)
+        .expect("transaction fee rate");
+
+    // The funded wallet contains a tx with a 76_000 sats input and two outputs, one spending 25_000
+    // to a foreign address and one returning 50_000 back to the wallet as change. The remaining 1000
+    // sats are the transaction fee.
+
+    // tx weight = 452 wu, as vbytes = (452 + 3) / 4 = 113
+    // fee_rate (sats per kwu) = fee / weight = 1000sat / 0.452kwu = 2212
+    // fee_rate (sats per vbyte ceil) = fee / vsize = 1000sat / 113vb = 9
+    assert_eq!(tx_fee_rate.to_sat_per_kwu(), 2212);
+    assert_eq!(tx_fee_rate.to_sat_per_vb_ceil(), 9);
 }
 
 #[test]
-fn test_create_tx_default_sighash() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(30_000));
-    let psbt = builder.finish().unwrap();
+fn test_list_output() {
+    let (wallet, txid) = get_funded_wallet_wpkh();
+    let txos = wallet
+        .list_output()
+        .map(|op| (op.outpoint, op))
+        .collect::<std::collections::BTreeMap<_, _>>();
+    assert_eq!(txos.len(), 2);
+    for (op, txo) in txos {
+        if op.txid == txid {
+            assert_eq!(txo.txout.value.to_sat(), 50_000);
+            assert!(!txo.is_spent);
+        } else {
+            assert_eq!(txo.txout.value.to_sat(), 76_000);
+            assert!(txo.is_spent);
+        }
+    }
+}
 
-    assert_eq!(psbt.inputs[0].sighash_type, None);
+macro_rules! assert_fee_rate {
+    ($psbt:expr, $fees:expr, $fee_rate:expr $( ,@dust_change $( $dust_change:expr )* )* $( ,@add_signature $( $add_signature:expr )* )* ) => ({
+        let psbt = $psbt.clone();
+        #[allow(unused_mut)]
+        let mut tx = $psbt.clone().extract_tx().expect("failed to extract tx");
+        $(
+            $( $add_signature )*
+                for txin in &mut tx.input {
+                    txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+                }
+        )*
+
+            #[allow(unused_mut)]
+        #[allow(unused_assignments)]
+        let mut dust_change = false;
+        $(
+            $( $dust_change )*
+                dust_change = true;
+        )*
+
+            let fee_amount = psbt
+            .inputs
+            .iter()
+            .fold(Amount::ZERO, |acc, i| acc + i.witness_utxo.as_ref().unwrap().value)
+            - psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .fold(Amount::ZERO, |acc, o| acc + o.value);
+
+        assert_eq!(fee_amount, $fees);
+
+        let tx_fee_rate = (fee_amount / tx.weight())
+            .to_sat_per_kwu();
+        let fee_rate = $fee_rate.to_sat_per_kwu();
+        let half_default = FeeRate::BROADCAST_MIN.checked_div(2)
+            .unwrap()
+            .to_sat_per_kwu();
+
+        if !dust_change {
+            assert!(tx_fee_rate >= fee_rate && tx_fee_rate - fee_rate < half_default, "Expected fee rate of {:?}, the tx has {:?}", fee_rate, tx_fee_rate);
+        } else {
+            assert!(tx_fee_rate >= fee_rate, "Expected fee rate of at least {:?}, the tx has {:?}", fee_rate, tx_fee_rate);
+        }
+    });
+}
+
+macro_rules! from_str {
+    ($e:expr, $t:ty) => {{
+        use core::str::FromStr;
+        <$t>::from_str($e).unwrap()
+    }};
+
+    ($e:expr) => {
+        from_str!($e, _)
+    };
 }
 
 #[test]
-fn test_create_tx_custom_sighash() {
+#[should_panic(expected = "NoRecipients")]
+fn test_create_tx_empty_recipients() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    wallet.build_tx().finish().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "NoUtxosSelected")]
+fn test_create_tx_manually_selected_empty_utxos() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
-        .sighash(EcdsaSighashType::Single.into());
-    let psbt = builder.finish().unwrap();
-
-    assert_eq!(
-        psbt.inputs[0].sighash_type,
-        Some(EcdsaSighashType::Single.into())
-    );
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .manually_selected_only();
+    builder.finish().unwrap();
 }
 
 #[test]
-fn test_create_tx_input_hd_keypaths() {
-    use bitcoin::bip32::{DerivationPath, Fingerprint};
-    use core::str::FromStr;
-
-    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+fn test_create_tx_version_0() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
-
-    assert_eq!(psbt.inputs[0].bip32_derivation.len(), 1);
-    assert_eq!(
-        psbt.inputs[0].bip32_derivation.values().next().unwrap(),
-        &(
-            Fingerprint::from_str("d34db33f").unwrap(),
-            DerivationPath::from_str("m/44'/0'/0'/0/0").unwrap()
-        )
-    );
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .version(0);
+    assert!(matches!(builder.finish(), Err(CreateTxError::Version0)));
 }
 
 #[test]
-fn test_create_tx_output_hd_keypaths() {
-    use bitcoin::bip32::{DerivationPath, Fingerprint};
-    use core::str::FromStr;
-
-    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+fn test_create_tx_version_1_csv() {
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_csv());
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .version(1);
+    assert!(matches!(builder.finish(), Err(CreateTxError::Version1Csv)));
+}
 
+#[test]
+fn test_create_tx_custom_version() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .version(42);
     let psbt = builder.finish().unwrap();
 
-    assert_eq!(psbt.outputs[0].bip32_derivation.len(), 1);
-    let expected_derivation_path = format!("m/44'/0'/0'/0/{}", addr.index);
-    assert_eq!(
-        psbt.outputs[0].bip32_derivation.values().next().unwrap(),
-        &(
-            Fingerprint::from_str("d34db33f").unwrap(),
-            DerivationPath::from_str(&expected_derivation_path).unwrap()
-        )
-    );
+    assert_eq!(psbt.unsigned_tx.version.0, 42);
 }
 
 #[test]
-fn test_create_tx_set_redeem_script_p2sh() {
-    use bitcoin::hex::FromHex;
+fn test_create_tx_default_locktime_is_last_sync_height() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
 
-    let (mut wallet, _) =
-        get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
     let psbt = builder.finish().unwrap();
 
-    assert_eq!(
-        psbt.inputs[0].redeem_script,
-        Some(ScriptBuf::from(
-            Vec::<u8>::from_hex(
-                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
-            )
-            .unwrap()
-        ))
-    );
-    assert_eq!(psbt.inputs[0].witness_script, None);
+    // Since we never synced the wallet we don't have a last_sync_height
+    // we could use to try to prevent fee sniping. We default to 0.
+    assert_eq!(psbt.unsigned_tx.lock_time.to_consensus_u32(), 2_000);
 }
 
 #[test]
-fn test_create_tx_set_witness_script_p2wsh() {
-    use bitcoin::hex::FromHex;
-
-    let (mut wallet, _) =
-        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+fn test_create_tx_fee_sniping_locktime_last_sync() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+
     let psbt = builder.finish().unwrap();
 
-    assert_eq!(psbt.inputs[0].redeem_script, None);
+    // If there's no current_height we're left with using the last sync height
     assert_eq!(
-        psbt.inputs[0].witness_script,
-        Some(ScriptBuf::from(
-            Vec::<u8>::from_hex(
-                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
-            )
-            .unwrap()
-        ))
+        psbt.unsigned_tx.lock_time.to_consensus_u32(),
+        wallet.latest_checkpoint().height()
     );
 }
 
 #[test]
-fn test_create_tx_set_redeem_witness_script_p2wsh_p2sh() {
-    let (mut wallet, _) =
-        get_funded_wallet("sh(wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)))");
+fn test_create_tx_default_locktime_cltv() {
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_cltv());
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
     let psbt = builder.finish().unwrap();
 
-    let script = ScriptBuf::from_hex(
-        "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac",
-    )
-    .unwrap();
-
-    assert_eq!(psbt.inputs[0].redeem_script, Some(script.to_p2wsh()));
-    assert_eq!(psbt.inputs[0].witness_script, Some(script));
+    assert_eq!(psbt.unsigned_tx.lock_time.to_consensus_u32(), 100_000);
 }
 
 #[test]
-fn test_create_tx_non_witness_utxo() {
-    let (mut wallet, _) =
-        get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+fn test_create_tx_custom_locktime() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .current_height(630_001)
+        .nlocktime(absolute::LockTime::from_height(630_000).unwrap());
     let psbt = builder.finish().unwrap();
 
-    assert!(psbt.inputs[0].non_witness_utxo.is_some());
-    assert!(psbt.inputs[0].witness_utxo.is_none());
+    // When we explicitly specify a nlocktime
+    // we don't try any fee sniping prevention trick
+    // (we ignore the current_height)
+    assert_eq!(psbt.unsigned_tx.lock_time.to_consensus_u32(), 630_000);
 }
 
 #[test]
-fn test_create_tx_only_witness_utxo() {
-    let (mut wallet, _) =
-        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+fn test_create_tx_custom_locktime_compatible_with_cltv() {
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_cltv());
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .only_witness_utxo()
-        .drain_wallet();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .nlocktime(absolute::LockTime::from_height(630_000).unwrap());
     let psbt = builder.finish().unwrap();
 
-    assert!(psbt.inputs[0].non_witness_utxo.is_none());
-    assert!(psbt.inputs[0].witness_utxo.is_some());
+    assert_eq!(psbt.unsigned_tx.lock_time.to_consensus_u32(), 630_000);
 }
 
 #[test]
-fn test_create_tx_shwpkh_has_witness_utxo() {
-    let (mut wallet, _) =
-        get_funded_wallet("sh(wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+fn test_create_tx_custom_locktime_incompatible_with_cltv() {
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_cltv());
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
-
-    assert!(psbt.inputs[0].witness_utxo.is_some());
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .nlocktime(absolute::LockTime::from_height(50000).unwrap());
+    assert!(matches!(builder.finish(),
+        Err(CreateTxError::LockTime { requested, required })
+        if requested.to_consensus_u32() == 50_000 && required.to_consensus_u32() == 100_000));
 }
 
 #[test]
-fn test_create_tx_both_non_witness_utxo_and_witness_utxo_default() {
-    let (mut wallet, _) =
-        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+fn test_create_tx_no_rbf_csv() {
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_csv());
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
     let psbt = builder.finish().unwrap();
 
-    assert!(psbt.inputs[0].non_witness_utxo.is_some());
-    assert!(psbt.inputs[0].witness_utxo.is_some());
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(6));
 }
 
 #[test]
-fn test_create_tx_add_utxo() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let small_output_tx = Transaction {
-        input: vec![],
-        output: vec![TxOut {
-            script_pubkey: wallet
-                .next_unused_address(KeychainKind::External)
-                .unwrap()
-                .script_pubkey(),
-            value: Amount::from_sat(25_000),
-        }],
-        version: transaction::Version::non_standard(0),
-        lock_time: absolute::LockTime::ZERO,
-    };
-    wallet
-        .insert_tx(
-            small_output_tx.clone(),
-            ConfirmationTime::Unconfirmed { last_seen: 0 },
-        )
-        .unwrap();
-
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+fn test_create_tx_with_default_rbf_csv() {
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_csv());
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
-        .add_utxo(OutPoint {
-            txid: small_output_tx.txid(),
-            vout: 0,
-        })
-        .unwrap();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf();
     let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-
-    assert_eq!(
-        psbt.unsigned_tx.input.len(),
-        2,
-        "should add an additional input since 25_000 < 30_000"
-    );
-    assert_eq!(
-        sent_received.0,
-        Amount::from_sat(75_000),
-        "total should be sum of both inputs"
-    );
+    // When CSV is enabled it takes precedence over the rbf value (unless forced by the user).
+    // It will be set to the OP_CSV value, in this case 6
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(6));
 }
 
 #[test]
-#[should_panic(expected = "InsufficientFunds")]
-fn test_create_tx_manually_selected_insufficient() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let small_output_tx = Transaction {
-        input: vec![],
-        output: vec![TxOut {
-            script_pubkey: wallet
-                .next_unused_address(KeychainKind::External)
-                .unwrap()
-                .script_pubkey(),
-            value: Amount::from_sat(25_000),
-        }],
-        version: transaction::Version::non_standard(0),
-        lock_time: absolute::LockTime::ZERO,
-    };
-
-    wallet
-        .insert_tx(
-            small_output_tx.clone(),
-            ConfirmationTime::Unconfirmed { last_seen: 0 },
-        )
-        .unwrap();
-
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+fn test_create_tx_with_custom_rbf_csv() {
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_csv());
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
-        .add_utxo(OutPoint {
-            txid: small_output_tx.txid(),
-            vout: 0,
-        })
-        .unwrap()
-        .manually_selected_only();
-    builder.finish().unwrap();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf_with_sequence(Sequence(3));
+    assert!(matches!(builder.finish(),
+        Err(CreateTxError::RbfSequenceCsv { rbf, csv })
+        if rbf.to_consensus_u32() == 3 && csv.to_consensus_u32() == 6));
 }
 
 #[test]
-#[should_panic(expected = "SpendingPolicyRequired(External)")]
-fn test_create_tx_policy_path_required() {
-    let (mut wallet, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
-
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+fn test_create_tx_no_rbf_cltv() {
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_cltv());
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(10_000));
-    builder.finish().unwrap();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let psbt = builder.finish().unwrap();
+
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFE));
 }
 
 #[test]
-fn test_create_tx_policy_path_no_csv() {
-    let (desc, change_desc) = get_test_wpkh_with_change_desc();
-    let mut wallet = Wallet::new_no_persist(desc, change_desc, Network::Regtest).unwrap();
-
-    let tx = Transaction {
-        version: transaction::Version::non_standard(0),
-        lock_time: absolute::LockTime::ZERO,
-        input: vec![],
-        output: vec![TxOut {
-            script_pubkey: wallet
-                .next_unused_address(KeychainKind::External)
-                .unwrap()
-                .script_pubkey(),
-            value: Amount::from_sat(50_000),
-        }],
-    };
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-
-    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
-    let root_id = external_policy.id;
-    // child #0 is just the key "A"
-    let path = vec![(root_id, vec![0])].into_iter().collect();
-
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+fn test_create_tx_invalid_rbf_sequence() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
-        .policy_path(path, KeychainKind::External);
-    let psbt = builder.finish().unwrap();
-
-    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFF));
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf_with_sequence(Sequence(0xFFFFFFFE));
+    assert!(matches!(builder.finish(), Err(CreateTxError::RbfSequence)));
 }
 
 #[test]
-fn test_create_tx_policy_path_use_csv() {
-    let (mut wallet, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
-
-    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
-    let root_id = external_policy.id;
-    // child #1 is or(pk(B),older(144))
-    let path = vec![(root_id, vec![1])].into_iter().collect();
-
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+fn test_create_tx_custom_rbf_sequence() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
-        .policy_path(path, KeychainKind::External);
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf_with_sequence(Sequence(0xDEADBEEF));
     let psbt = builder.finish().unwrap();
 
-    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(144));
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xDEADBEEF));
 }
 
 #[test]
-fn test_create_tx_policy_path_ignored_subtree_with_csv() {
-    let (mut wallet, _) = get_funded_wallet("wsh(or_d(pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu),or_i(and_v(v:pkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),older(30)),and_v(v:pkh(cMnkdebixpXMPfkcNEjjGin7s94hiehAH4mLbYkZoh9KSiNNmqC8),older(90)))))");
-
-    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
-    let root_id = external_policy.id;
-    // child #0 is pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)
-    let path = vec![(root_id, vec![0])].into_iter().collect();
+fn test_create_tx_change_policy() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .do_not_spend_change();
+    assert!(builder.finish().is_ok());
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+    // wallet has no change, so setting `only_spend_change`
+    // should cause tx building to fail
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
-        .policy_path(path, KeychainKind::External);
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .only_spend_change();
+    assert!(matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        )),
+    ));
+}
+
+#[test]
+fn test_create_tx_default_sequence() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
     let psbt = builder.finish().unwrap();
 
     assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFE));
 }
 
-#[test]
-fn test_create_tx_global_xpubs_with_origin() {
-    use bitcoin::bip32;
-    use bitcoin::hex::FromHex;
+macro_rules! check_fee {
+    ($wallet:expr, $psbt: expr) => {{
+        let tx = $psbt.clone().extract_tx().expect("failed to extract tx");
+        let tx_fee = $wallet.calculate_fee(&tx).ok();
+        assert_eq!(tx_fee, $psbt.fee_amount());
+        tx_fee
+    }};
+}
 
-    let (mut wallet, _) = get_funded_wallet("wpkh([73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
+#[test]
+fn test_create_tx_drain_wallet_and_drain_to() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .add_global_xpubs();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
 
-    let key = bip32::Xpub::from_str("tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3").unwrap();
-    let fingerprint = bip32::Fingerprint::from_hex("73756c7f").unwrap();
-    let path = bip32::DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
-
-    assert_eq!(psbt.xpub.len(), 2);
-    assert_eq!(psbt.xpub.get(&key), Some(&(fingerprint, path)));
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(
+        psbt.unsigned_tx.output[0].value,
+        Amount::from_sat(50_000) - fee.unwrap_or(Amount::ZERO)
+    );
 }
 
 #[test]
-fn test_add_foreign_utxo() {
-    let (mut wallet1, _) = get_funded_wallet_wpkh();
-    let (wallet2, _) =
-        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
-
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+fn test_create_tx_drain_wallet_and_drain_to_and_with_recipient() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")
         .unwrap()
         .assume_checked();
-    let utxo = wallet2.list_unspent().next().expect("must take!");
-    let foreign_utxo_satisfaction = wallet2
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_weight_to_satisfy()
-        .unwrap();
-
-    let psbt_input = psbt::Input {
-        witness_utxo: Some(utxo.txout.clone()),
-        ..Default::default()
-    };
-
-    let mut builder = wallet1.build_tx();
+    let drain_addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
-        .only_witness_utxo()
-        .add_foreign_utxo(utxo.outpoint, psbt_input, foreign_utxo_satisfaction)
-        .unwrap();
-    let mut psbt = builder.finish().unwrap();
-    wallet1.insert_txout(utxo.outpoint, utxo.txout);
-    let fee = check_fee!(wallet1, psbt);
-    let sent_received =
-        wallet1.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(20_000))
+        .drain_to(drain_addr.script_pubkey())
+        .drain_wallet();
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+    let outputs = psbt.unsigned_tx.output;
 
+    assert_eq!(outputs.len(), 2);
+    let main_output = outputs
+        .iter()
+        .find(|x| x.script_pubkey == addr.script_pubkey())
+        .unwrap();
+    let drain_output = outputs
+        .iter()
+        .find(|x| x.script_pubkey == drain_addr.script_pubkey())
+        .unwrap();
+    assert_eq!(main_output.value, Amount::from_sat(20_000));
     assert_eq!(
-        (sent_received.0 - sent_received.1),
-        Amount::from_sat(10_000) + fee.unwrap_or(Amount::ZERO),
-        "we should have only net spent ~10_000"
-    );
-
-    assert!(
-        psbt.unsigned_tx
-            .input
-            .iter()
-            .any(|input| input.previous_output == utxo.outpoint),
-        "foreign_utxo should be in there"
+        drain_output.value,
+        Amount::from_sat(30_000) - fee.unwrap_or(Amount::ZERO)
     );
+}
 
-    let finished = wallet1
-        .sign(
-            &mut psbt,
-            SignOptions {
-                trust_witness_utxo: true,
-                ..Default::default()
-            },
-        )
+#[test]
+fn test_create_tx_drain_to_and_utxos() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let utxos: Vec<_> = wallet.list_unspent().map(|u| u.outpoint).collect();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .add_utxos(&utxos)
         .unwrap();
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
 
-    assert!(
-        !finished,
-        "only one of the inputs should have been signed so far"
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(
+        psbt.unsigned_tx.output[0].value,
+        Amount::from_sat(50_000) - fee.unwrap_or(Amount::ZERO)
     );
-
-    let finished = wallet2
-        .sign(
-            &mut psbt,
-            SignOptions {
-                trust_witness_utxo: true,
-                ..Default::default()
-            },
-        )
-        .unwrap();
-    assert!(finished, "all the inputs should have been signed now");
 }
 
 #[test]
-#[should_panic(
-    expected = "MissingTxOut([OutPoint { txid: 0x21d7fb1bceda00ab4069fc52d06baa13470803e9050edd16f5736e5d8c4925fd, vout: 0 }])"
-)]
-fn test_calculate_fee_with_missing_foreign_utxo() {
-    let (mut wallet1, _) = get_funded_wallet_wpkh();
-    let (wallet2, _) =
-        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+#[should_panic(expected = "NoRecipients")]
+fn test_create_tx_drain_to_no_drain_wallet_no_utxos() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let drain_addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(drain_addr.script_pubkey());
+    builder.finish().unwrap();
+}
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    let utxo = wallet2.list_unspent().next().expect("must take!");
-    let foreign_utxo_satisfaction = wallet2
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_weight_to_satisfy()
-        .unwrap();
+#[test]
+fn test_create_tx_default_fee_rate() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
 
-    let psbt_input = psbt::Input {
-        witness_utxo: Some(utxo.txout.clone()),
-        ..Default::default()
-    };
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::BROADCAST_MIN, @add_signature);
+}
 
-    let mut builder = wallet1.build_tx();
+#[test]
+fn test_create_tx_custom_fee_rate() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
-        .only_witness_utxo()
-        .add_foreign_utxo(utxo.outpoint, psbt_input, foreign_utxo_satisfaction)
-        .unwrap();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(5));
     let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    wallet1.calculate_fee(&tx).unwrap();
+    let fee = check_fee!(wallet, psbt);
+
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(5), @add_signature);
 }
 
 #[test]
-fn test_add_foreign_utxo_invalid_psbt_input() {
+fn test_create_tx_lowest_waste_coin_selection() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    let outpoint = wallet.list_unspent().next().expect("must exist").outpoint;
-    let foreign_utxo_satisfaction = wallet
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_weight_to_satisfy()
-        .unwrap();
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let coin_selection = LowestWasteCoinSelection::default().rng(StdRng::seed_from_u64(42));
+    let mut builder = wallet.build_tx().coin_selection(coin_selection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(5));
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
 
-    let mut builder = wallet.build_tx();
-    let result =
-        builder.add_foreign_utxo(outpoint, psbt::Input::default(), foreign_utxo_satisfaction);
-    assert!(matches!(result, Err(AddForeignUtxoError::MissingUtxo)));
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(5), @add_signature);
 }
 
 #[test]
-fn test_add_foreign_utxo_where_outpoint_doesnt_match_psbt_input() {
-    let (mut wallet1, txid1) = get_funded_wallet_wpkh();
-    let (wallet2, txid2) =
-        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
-
-    let utxo2 = wallet2.list_unspent().next().unwrap();
-    let tx1 = wallet1.get_tx(txid1).unwrap().tx_node.tx.clone();
-    let tx2 = wallet2.get_tx(txid2).unwrap().tx_node.tx.clone();
+fn test_create_tx_privacy_coin_selection() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let kyc_outpoints = [
+        receive_output_in_latest_block(&mut wallet, 30_000),
+        receive_output_in_latest_block(&mut wallet, 31_000),
+    ];
+    for outpoint in kyc_outpoints {
+        wallet.set_label(LabelRef::Output(outpoint), Label::new("kyc"));
+    }
+    let sources = wallet
+        .labels()
+        .filter_map(|(label_ref, label)| match label_ref {
+            LabelRef::Output(outpoint) => Some((*outpoint, label.label.clone())),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
 
-    let satisfaction_weight = wallet2
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_weight_to_satisfy()
-        .unwrap();
+    // the other UTXO of the wallet is too small to fund the transaction by itself
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet
+        .build_tx()
+        .coin_selection(PrivacyCoinSelection::new().sources(sources));
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(55_000));
+    let (psbt, coin_selection) = builder.finish_with_coin_selection().unwrap();
 
-    let mut builder = wallet1.build_tx();
-    assert!(
-        builder
-            .add_foreign_utxo(
-                utxo2.outpoint,
-                psbt::Input {
-                    non_witness_utxo: Some(tx1.as_ref().clone()),
-                    ..Default::default()
-                },
-                satisfaction_weight
-            )
-            .is_err(),
-        "should fail when outpoint doesn't match psbt_input"
-    );
-    assert!(
-        builder
-            .add_foreign_utxo(
-                utxo2.outpoint,
-                psbt::Input {
-                    non_witness_utxo: Some(tx2.as_ref().clone()),
-                    ..Default::default()
-                },
-                satisfaction_weight
-            )
-            .is_ok(),
-        "should be ok when outpoint does match psbt_input"
+    let mut inputs = psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .collect::<Vec<_>>();
+    inputs.sort();
+    let mut expected = kyc_outpoints.to_vec();
+    expected.sort();
+    assert_eq!(inputs, expected);
+    // the selection spends the kyc cluster only, but the address of the wallet is left with the
+    // other utxo
+    assert_eq!(
+        coin_selection.privacy_score,
+        Some(PrivacyScore {
+            partially_spent_scripts: 1,
+            keychains: 1,
+            sources: 1,
+        })
     );
+
+    // the algorithms that don't measure privacy don't report a score
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(55_000));
+    let (_, coin_selection) = builder.finish_with_coin_selection().unwrap();
+    assert_eq!(coin_selection.privacy_score, None);
 }
 
 #[test]
-fn test_add_foreign_utxo_only_witness_utxo() {
-    let (mut wallet1, _) = get_funded_wallet_wpkh();
-    let (wallet2, txid2) =
-        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    let utxo2 = wallet2.list_unspent().next().unwrap();
+fn test_create_tx_absolute_fee() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .fee_absolute(Amount::from_sat(100));
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
 
-    let satisfaction_weight = wallet2
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_weight_to_satisfy()
-        .unwrap();
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(100));
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(
+        psbt.unsigned_tx.output[0].value,
+        Amount::from_sat(50_000) - fee.unwrap_or(Amount::ZERO)
+    );
+}
 
-    let mut builder = wallet1.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(60_000));
+#[test]
+fn test_create_tx_absolute_zero_fee() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .fee_absolute(Amount::ZERO);
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
 
-    {
-        let mut builder = builder.clone();
-        let psbt_input = psbt::Input {
-            witness_utxo: Some(utxo2.txout.clone()),
-            ..Default::default()
-        };
-        builder
-            .add_foreign_utxo(utxo2.outpoint, psbt_input, satisfaction_weight)
-            .unwrap();
-        assert!(
-            builder.finish().is_err(),
-            "psbt_input with witness_utxo should fail with only witness_utxo"
-        );
-    }
-
-    {
-        let mut builder = builder.clone();
-        let psbt_input = psbt::Input {
-            witness_utxo: Some(utxo2.txout.clone()),
-            ..Default::default()
-        };
-        builder
-            .only_witness_utxo()
-            .add_foreign_utxo(utxo2.outpoint, psbt_input, satisfaction_weight)
-            .unwrap();
-        assert!(
-            builder.finish().is_ok(),
-            "psbt_input with just witness_utxo should succeed when `only_witness_utxo` is enabled"
-        );
-    }
-
-    {
-        let mut builder = builder.clone();
-        let tx2 = wallet2.get_tx(txid2).unwrap().tx_node.tx;
-        let psbt_input = psbt::Input {
-            non_witness_utxo: Some(tx2.as_ref().clone()),
-            ..Default::default()
-        };
-        builder
-            .add_foreign_utxo(utxo2.outpoint, psbt_input, satisfaction_weight)
-            .unwrap();
-        assert!(
-            builder.finish().is_ok(),
-            "psbt_input with non_witness_utxo should succeed by default"
-        );
-    }
-}
-
-#[test]
-fn test_get_psbt_input() {
-    // this should grab a known good utxo and set the input
-    let (wallet, _) = get_funded_wallet_wpkh();
-    for utxo in wallet.list_unspent() {
-        let psbt_input = wallet.get_psbt_input(utxo, None, false).unwrap();
-        assert!(psbt_input.witness_utxo.is_some() || psbt_input.non_witness_utxo.is_some());
-    }
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::ZERO);
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(
+        psbt.unsigned_tx.output[0].value,
+        Amount::from_sat(50_000) - fee.unwrap_or(Amount::ZERO)
+    );
 }
 
 #[test]
-#[should_panic(
-    expected = "MissingKeyOrigin(\"tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3\")"
-)]
-fn test_create_tx_global_xpubs_origin_missing() {
-    let (mut wallet, _) = get_funded_wallet("wpkh(tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
+#[should_panic(expected = "InsufficientFunds")]
+fn test_create_tx_absolute_high_fee() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .add_global_xpubs();
-    builder.finish().unwrap();
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .fee_absolute(Amount::from_sat(60_000));
+    let _ = builder.finish().unwrap();
 }
 
 #[test]
-fn test_create_tx_global_xpubs_master_without_origin() {
-    use bitcoin::bip32;
-    use bitcoin::hex::FromHex;
+fn test_create_tx_add_change() {
+    use bdk_wallet::wallet::tx_builder::TxOrdering;
 
-    let (mut wallet, _) = get_funded_wallet("wpkh(tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL/0/*)");
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
         .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .add_global_xpubs();
+        .ordering(TxOrdering::Untouched);
     let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
 
-    let key = bip32::Xpub::from_str("tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL").unwrap();
-    let fingerprint = bip32::Fingerprint::from_hex("997a323b").unwrap();
-
-    assert_eq!(psbt.xpub.len(), 2);
+    assert_eq!(psbt.unsigned_tx.output.len(), 2);
+    assert_eq!(psbt.unsigned_tx.output[0].value, Amount::from_sat(25_000));
     assert_eq!(
-        psbt.xpub.get(&key),
-        Some(&(fingerprint, bip32::DerivationPath::default()))
+        psbt.unsigned_tx.output[1].value,
+        Amount::from_sat(25_000) - fee.unwrap_or(Amount::ZERO)
     );
 }
 
 #[test]
-#[should_panic(expected = "IrreplaceableTransaction")]
-fn test_bump_fee_irreplaceable_tx() {
+fn test_create_tx_skip_change_dust() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(49_800));
     let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
 
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-    wallet.build_fee_bump(txid).unwrap().finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(psbt.unsigned_tx.output[0].value.to_sat(), 49_800);
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(200));
 }
 
 #[test]
-#[should_panic(expected = "TransactionConfirmed")]
-fn test_bump_fee_confirmed_tx() {
+#[should_panic(expected = "InsufficientFunds")]
+fn test_create_tx_drain_to_dust_amount() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    // very high fee rate, so that the only output would be below dust
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let psbt = builder.finish().unwrap();
-
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.txid();
-
-    wallet
-        .insert_tx(
-            tx,
-            ConfirmationTime::Confirmed {
-                height: 42,
-                time: 42_000,
-            },
-        )
-        .unwrap();
-
-    wallet.build_fee_bump(txid).unwrap().finish().unwrap();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(454));
+    builder.finish().unwrap();
 }
 
 #[test]
-fn test_bump_fee_low_fee_rate() {
+fn test_create_tx_ordering_respected() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .enable_rbf();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(10_000))
+        .ordering(bdk_wallet::wallet::tx_builder::TxOrdering::Bip69Lexicographic);
     let psbt = builder.finish().unwrap();
-    let feerate = psbt.fee_rate().unwrap();
-
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.txid();
-
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
+    let fee = check_fee!(wallet, psbt);
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(FeeRate::BROADCAST_MIN);
-    let res = builder.finish();
-    assert_matches!(
-        res,
-        Err(CreateTxError::FeeRateTooLow { .. }),
-        "expected FeeRateTooLow error"
+    assert_eq!(psbt.unsigned_tx.output.len(), 3);
+    assert_eq!(
+        psbt.unsigned_tx.output[0].value,
+        Amount::from_sat(10_000) - fee.unwrap_or(Amount::ZERO)
     );
-
-    let required = feerate.to_sat_per_kwu() + 250; // +1 sat/vb
-    let sat_vb = required as f64 / 250.0;
-    let expect = format!("Fee rate too low: required {} sat/vb", sat_vb);
-    assert_eq!(res.unwrap_err().to_string(), expect);
+    assert_eq!(psbt.unsigned_tx.output[1].value, Amount::from_sat(10_000));
+    assert_eq!(psbt.unsigned_tx.output[2].value, Amount::from_sat(30_000));
 }
 
 #[test]
-#[should_panic(expected = "FeeTooLow")]
-fn test_bump_fee_low_abs() {
+fn test_create_tx_default_sighash() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .enable_rbf();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(30_000));
     let psbt = builder.finish().unwrap();
 
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.txid();
-
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_absolute(Amount::from_sat(10));
-    builder.finish().unwrap();
+    assert_eq!(psbt.inputs[0].sighash_type, None);
 }
 
 #[test]
-#[should_panic(expected = "FeeTooLow")]
-fn test_bump_fee_zero_abs() {
+fn test_create_tx_custom_sighash() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .enable_rbf();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .sighash(EcdsaSighashType::Single.into());
     let psbt = builder.finish().unwrap();
 
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_absolute(Amount::ZERO);
-    builder.finish().unwrap();
+    assert_eq!(
+        psbt.inputs[0].sighash_type,
+        Some(EcdsaSighashType::Single.into())
+    );
 }
 
 #[test]
-fn test_bump_fee_reduce_change() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+fn test_create_tx_input_hd_keypaths() {
+    use bitcoin::bip32::{DerivationPath, Fingerprint};
+    use core::str::FromStr;
+
+    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
-        .enable_rbf();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
-    let original_sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let original_fee = check_fee!(wallet, psbt);
 
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
+    assert_eq!(psbt.inputs[0].bip32_derivation.len(), 1);
+    assert_eq!(
+        psbt.inputs[0].bip32_derivation.values().next().unwrap(),
+        &(
+            Fingerprint::from_str("d34db33f").unwrap(),
+            DerivationPath::from_str("m/44'/0'/0'/0/0").unwrap()
+        )
+    );
+}
 
-    let feerate = FeeRate::from_sat_per_kwu(625); // 2.5 sat/vb
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(feerate).enable_rbf();
+#[test]
+fn test_create_tx_output_hd_keypaths() {
+    use bitcoin::bip32::{DerivationPath, Fingerprint};
+    use core::str::FromStr;
+
+    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
 
-    assert_eq!(sent_received.0, original_sent_received.0);
+    assert_eq!(psbt.outputs[0].bip32_derivation.len(), 1);
+    let expected_derivation_path = format!("m/44'/0'/0'/0/{}", addr.index);
     assert_eq!(
-        sent_received.1 + fee.unwrap_or(Amount::ZERO),
-        original_sent_received.1 + original_fee.unwrap_or(Amount::ZERO)
+        psbt.outputs[0].bip32_derivation.values().next().unwrap(),
+        &(
+            Fingerprint::from_str("d34db33f").unwrap(),
+            DerivationPath::from_str(&expected_derivation_path).unwrap()
+        )
     );
-    assert!(fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO));
+}
+
+#[test]
+fn test_create_tx_set_redeem_script_p2sh() {
+    use bitcoin::hex::FromHex;
+
+    let (mut wallet, _) =
+        get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(25_000)
-    );
     assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+        psbt.inputs[0].redeem_script,
+        Some(ScriptBuf::from(
+            Vec::<u8>::from_hex(
+                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
+            )
             .unwrap()
-            .value,
-        sent_received.1
+        ))
     );
+    assert_eq!(psbt.inputs[0].witness_script, None);
+}
 
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), feerate, @add_signature);
+#[test]
+fn test_create_tx_set_witness_script_p2wsh() {
+    use bitcoin::hex::FromHex;
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_absolute(Amount::from_sat(200));
-    builder.enable_rbf();
+    let (mut wallet, _) =
+        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
-
-    assert_eq!(sent_received.0, original_sent_received.0);
-    assert_eq!(
-        sent_received.1 + fee.unwrap_or(Amount::ZERO),
-        original_sent_received.1 + original_fee.unwrap_or(Amount::ZERO)
-    );
-    assert!(
-        fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO),
-        "{} > {}",
-        fee.unwrap_or(Amount::ZERO),
-        original_fee.unwrap_or(Amount::ZERO)
-    );
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(25_000)
-    );
+    assert_eq!(psbt.inputs[0].redeem_script, None);
     assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+        psbt.inputs[0].witness_script,
+        Some(ScriptBuf::from(
+            Vec::<u8>::from_hex(
+                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
+            )
             .unwrap()
-            .value,
-        sent_received.1
+        ))
     );
-
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(200));
 }
 
 #[test]
-fn test_bump_fee_reduce_single_recipient() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+fn test_create_tx_set_redeem_witness_script_p2wsh_p2sh() {
+    let (mut wallet, _) =
+        get_funded_wallet("sh(wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)))");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .enable_rbf();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
-    let tx = psbt.clone().extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let original_fee = check_fee!(wallet, psbt);
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
 
-    let feerate = FeeRate::from_sat_per_kwu(625); // 2.5 sat/vb
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .fee_rate(feerate)
-        // remove original tx drain_to address and amount
-        .set_recipients(Vec::new())
-        // set back original drain_to address
-        .drain_to(addr.script_pubkey())
-        // drain wallet output amount will be re-calculated with new fee rate
-        .drain_wallet();
-    let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
+    let script = ScriptBuf::from_hex(
+        "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac",
+    )
+    .unwrap();
 
-    assert_eq!(sent_received.0, original_sent_received.0);
-    assert!(fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO));
+    assert_eq!(psbt.inputs[0].redeem_script, Some(script.to_p2wsh()));
+    assert_eq!(psbt.inputs[0].witness_script, Some(script));
+}
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.output.len(), 1);
-    assert_eq!(
-        tx.output[0].value + fee.unwrap_or(Amount::ZERO),
-        sent_received.0
-    );
+#[test]
+fn test_create_tx_non_witness_utxo() {
+    let (mut wallet, _) =
+        get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
 
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), feerate, @add_signature);
+    assert!(psbt.inputs[0].non_witness_utxo.is_some());
+    assert!(psbt.inputs[0].witness_utxo.is_none());
 }
 
 #[test]
-fn test_bump_fee_absolute_reduce_single_recipient() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+fn test_create_tx_only_witness_utxo() {
+    let (mut wallet, _) =
+        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
         .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .enable_rbf();
+        .only_witness_utxo()
+        .drain_wallet();
     let psbt = builder.finish().unwrap();
-    let original_fee = check_fee!(wallet, psbt);
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .fee_absolute(Amount::from_sat(300))
-        // remove original tx drain_to address and amount
-        .set_recipients(Vec::new())
-        // set back original drain_to address
-        .drain_to(addr.script_pubkey())
-        // drain wallet output amount will be re-calculated with new fee rate
-        .drain_wallet();
+    assert!(psbt.inputs[0].non_witness_utxo.is_none());
+    assert!(psbt.inputs[0].witness_utxo.is_some());
+}
+
+#[test]
+fn test_create_tx_shwpkh_has_witness_utxo() {
+    let (mut wallet, _) =
+        get_funded_wallet("sh(wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
     let psbt = builder.finish().unwrap();
-    let tx = &psbt.unsigned_tx;
-    let sent_received = wallet.sent_and_received(tx);
-    let fee = check_fee!(wallet, psbt);
 
-    assert_eq!(sent_received.0, original_sent_received.0);
-    assert!(fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO));
+    assert!(psbt.inputs[0].witness_utxo.is_some());
+}
 
-    assert_eq!(tx.output.len(), 1);
-    assert_eq!(
-        tx.output[0].value + fee.unwrap_or(Amount::ZERO),
-        sent_received.0
-    );
+#[test]
+fn test_create_tx_both_non_witness_utxo_and_witness_utxo_default() {
+    let (mut wallet, _) =
+        get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
 
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(300));
+    assert!(psbt.inputs[0].non_witness_utxo.is_some());
+    assert!(psbt.inputs[0].witness_utxo.is_some());
 }
 
 #[test]
-fn test_bump_fee_drain_wallet() {
+fn test_create_tx_add_utxo() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    // receive an extra tx so that our wallet has two utxos.
-    let tx = Transaction {
-        version: transaction::Version::ONE,
-        lock_time: absolute::LockTime::ZERO,
+    let small_output_tx = Transaction {
         input: vec![],
         output: vec![TxOut {
             script_pubkey: wallet
@@ -1931,64 +2230,48 @@ fn test_bump_fee_drain_wallet() {
                 .script_pubkey(),
             value: Amount::from_sat(25_000),
         }],
+        version: transaction::Version::non_standard(0),
+        lock_time: absolute::LockTime::ZERO,
     };
     wallet
         .insert_tx(
-            tx.clone(),
-            ConfirmationTime::Confirmed {
-                height: wallet.latest_checkpoint().height(),
-                time: 42_000,
-            },
+            small_output_tx.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
         )
         .unwrap();
+
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
         .add_utxo(OutPoint {
-            txid: tx.txid(),
+            txid: small_output_tx.txid(),
             vout: 0,
         })
-        .unwrap()
-        .manually_selected_only()
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
         .unwrap();
-    assert_eq!(original_sent_received.0, Amount::from_sat(25_000));
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
 
-    // for the new feerate, it should be enough to reduce the output, but since we specify
-    // `drain_wallet` we expect to spend everything
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .drain_wallet()
-        .fee_rate(FeeRate::from_sat_per_vb_unchecked(5));
-    let psbt = builder.finish().unwrap();
-    let sent_received = wallet.sent_and_received(&psbt.extract_tx().expect("failed to extract tx"));
-
-    assert_eq!(sent_received.0, Amount::from_sat(75_000));
+    assert_eq!(
+        psbt.unsigned_tx.input.len(),
+        2,
+        "should add an additional input since 25_000 < 30_000"
+    );
+    assert_eq!(
+        sent_received.0,
+        Amount::from_sat(75_000),
+        "total should be sum of both inputs"
+    );
 }
 
 #[test]
 #[should_panic(expected = "InsufficientFunds")]
-fn test_bump_fee_remove_output_manually_selected_only() {
+fn test_create_tx_manually_selected_insufficient() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    // receive an extra tx so that our wallet has two utxos. then we manually pick only one of
-    // them, and make sure that `bump_fee` doesn't try to add more. This fails because we've
-    // told the wallet it's not allowed to add more inputs AND it can't reduce the value of the
-    // existing output. In other words, bump_fee + manually_selected_only is always an error
-    // unless there is a change output.
-    let init_tx = Transaction {
-        version: transaction::Version::ONE,
-        lock_time: absolute::LockTime::ZERO,
+    let small_output_tx = Transaction {
         input: vec![],
         output: vec![TxOut {
             script_pubkey: wallet
@@ -1997,54 +2280,52 @@ fn test_bump_fee_remove_output_manually_selected_only() {
                 .script_pubkey(),
             value: Amount::from_sat(25_000),
         }],
+        version: transaction::Version::non_standard(0),
+        lock_time: absolute::LockTime::ZERO,
     };
+
     wallet
         .insert_tx(
-            init_tx.clone(),
-            wallet
-                .transactions()
-                .last()
-                .unwrap()
-                .chain_position
-                .cloned()
-                .into(),
+            small_output_tx.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
         )
         .unwrap();
-    let outpoint = OutPoint {
-        txid: init_tx.txid(),
-        vout: 0,
-    };
+
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .add_utxo(outpoint)
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .add_utxo(OutPoint {
+            txid: small_output_tx.txid(),
+            vout: 0,
+        })
         .unwrap()
-        .manually_selected_only()
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-    assert_eq!(original_sent_received.0, Amount::from_sat(25_000));
+        .manually_selected_only();
+    builder.finish().unwrap();
+}
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .manually_selected_only()
-        .fee_rate(FeeRate::from_sat_per_vb_unchecked(255));
+#[test]
+#[should_panic(expected = "SpendingPolicyRequired(External)")]
+fn test_create_tx_policy_path_required() {
+    let (mut wallet, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(10_000));
     builder.finish().unwrap();
 }
 
 #[test]
-fn test_bump_fee_add_input() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let init_tx = Transaction {
-        version: transaction::Version::ONE,
+fn test_create_tx_policy_path_no_csv() {
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let mut wallet = Wallet::new_no_persist(desc, change_desc, Network::Regtest).unwrap();
+
+    let tx = Transaction {
+        version: transaction::Version::non_standard(0),
         lock_time: absolute::LockTime::ZERO,
         input: vec![],
         output: vec![TxOut {
@@ -2052,627 +2333,1888 @@ fn test_bump_fee_add_input() {
                 .next_unused_address(KeychainKind::External)
                 .unwrap()
                 .script_pubkey(),
-            value: Amount::from_sat(25_000),
+            value: Amount::from_sat(50_000),
         }],
     };
-    let pos = wallet
-        .transactions()
-        .last()
-        .unwrap()
-        .chain_position
-        .cloned()
-        .into();
-    wallet.insert_tx(init_tx, pos).unwrap();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+    let root_id = external_policy.id;
+    // child #0 is just the key "A"
+    let path = vec![(root_id, vec![0])].into_iter().collect();
 
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_details = wallet.sent_and_received(&tx);
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(50));
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .policy_path(path, KeychainKind::External);
     let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
-    assert_eq!(
-        sent_received.0,
-        original_details.0 + Amount::from_sat(25_000)
-    );
-    assert_eq!(
-        fee.unwrap_or(Amount::ZERO) + sent_received.1,
-        Amount::from_sat(30_000)
-    );
-
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(45_000)
-    );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
-    );
 
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(50), @add_signature);
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFF));
 }
 
 #[test]
-fn test_bump_fee_absolute_add_input() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    receive_output_in_latest_block(&mut wallet, 25_000);
+fn test_create_tx_policy_path_use_csv() {
+    let (mut wallet, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+
+    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+    let root_id = external_policy.id;
+    // child #1 is or(pk(B),older(144))
+    let path = vec![(root_id, vec![1])].into_iter().collect();
+
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
-
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_absolute(Amount::from_sat(6_000));
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .policy_path(path, KeychainKind::External);
     let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
-
-    assert_eq!(
-        sent_received.0,
-        original_sent_received.0 + Amount::from_sat(25_000)
-    );
-    assert_eq!(
-        fee.unwrap_or(Amount::ZERO) + sent_received.1,
-        Amount::from_sat(30_000)
-    );
-
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(45_000)
-    );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
-    );
 
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(6_000));
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(144));
 }
 
 #[test]
-fn test_bump_fee_no_change_add_input_and_change() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let op = receive_output_in_latest_block(&mut wallet, 25_000);
+fn test_create_tx_policy_path_ignored_subtree_with_csv() {
+    let (mut wallet, _) = get_funded_wallet("wsh(or_d(pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu),or_i(and_v(v:pkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),older(30)),and_v(v:pkh(cMnkdebixpXMPfkcNEjjGin7s94hiehAH4mLbYkZoh9KSiNNmqC8),older(90)))))");
+
+    let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+    let root_id = external_policy.id;
+    // child #0 is pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)
+    let path = vec![(root_id, vec![0])].into_iter().collect();
 
-    // initially make a tx without change by using `drain_to`
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .add_utxo(op)
-        .unwrap()
-        .manually_selected_only()
-        .enable_rbf();
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(30_000))
+        .policy_path(path, KeychainKind::External);
     let psbt = builder.finish().unwrap();
-    let original_sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let original_fee = check_fee!(wallet, psbt);
-
-    let tx = psbt.extract_tx().expect("failed to extract tx");
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
 
-    // Now bump the fees, the wallet should add an extra input and a change output, and leave
-    // the original output untouched.
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(50));
-    let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
+    assert_eq!(psbt.unsigned_tx.input[0].sequence, Sequence(0xFFFFFFFE));
+}
 
-    let original_send_all_amount = original_sent_received.0 - original_fee.unwrap_or(Amount::ZERO);
-    assert_eq!(
-        sent_received.0,
-        original_sent_received.0 + Amount::from_sat(50_000)
-    );
-    assert_eq!(
-        sent_received.1,
-        Amount::from_sat(75_000) - original_send_all_amount - fee.unwrap_or(Amount::ZERO)
-    );
+#[test]
+fn test_create_tx_global_xpubs_with_origin() {
+    use bitcoin::bip32;
+    use bitcoin::hex::FromHex;
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        original_send_all_amount
-    );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(75_000) - original_send_all_amount - fee.unwrap_or(Amount::ZERO)
-    );
+    let (mut wallet, _) = get_funded_wallet("wpkh([73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .add_global_xpubs();
+    let psbt = builder.finish().unwrap();
 
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(50), @add_signature);
+    let key = bip32::Xpub::from_str("tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3").unwrap();
+    let fingerprint = bip32::Fingerprint::from_hex("73756c7f").unwrap();
+    let path = bip32::DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
+
+    assert_eq!(psbt.xpub.len(), 2);
+    assert_eq!(psbt.xpub.get(&key), Some(&(fingerprint, path)));
 }
 
 #[test]
-fn test_bump_fee_add_input_change_dust() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    receive_output_in_latest_block(&mut wallet, 25_000);
+fn test_add_foreign_utxo() {
+    let (mut wallet1, _) = get_funded_wallet_wpkh();
+    let (wallet2, _) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let original_sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let original_fee = check_fee!(wallet, psbt);
-
-    let mut tx = psbt.extract_tx().expect("failed to extract tx");
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // to get realistic weight
-    }
-    let original_tx_weight = tx.weight();
-    assert_eq!(tx.input.len(), 1);
-    assert_eq!(tx.output.len(), 2);
-    let txid = tx.txid();
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+    let utxo = wallet2.list_unspent().next().expect("must take!");
+    let foreign_utxo_satisfaction = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
         .unwrap();
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    // We set a fee high enough that during rbf we are forced to add
-    // a new input and also that we have to remove the change
-    // that we had previously
+    let psbt_input = psbt::Input {
+        witness_utxo: Some(utxo.txout.clone()),
+        ..Default::default()
+    };
 
-    // We calculate the new weight as:
-    //   original weight
-    // + extra input weight: 160 WU = (32 (prevout) + 4 (vout) + 4 (nsequence)) * 4
-    // + input satisfaction weight: 112 WU = 106 (witness) + 2 (witness len) + (1 (script len)) * 4
-    // - change output weight: 124 WU = (8 (value) + 1 (script len) + 22 (script)) * 4
-    let new_tx_weight =
-        original_tx_weight + Weight::from_wu(160) + Weight::from_wu(112) - Weight::from_wu(124);
-    // two inputs (50k, 25k) and one output (45k) - epsilon
-    // We use epsilon here to avoid asking for a slightly too high feerate
-    let fee_abs = 50_000 + 25_000 - 45_000 - 10;
-    builder.fee_rate(Amount::from_sat(fee_abs) / new_tx_weight);
-    let psbt = builder.finish().unwrap();
+    let mut builder = wallet1.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
+        .only_witness_utxo()
+        .add_foreign_utxo(utxo.outpoint, psbt_input, foreign_utxo_satisfaction)
+        .unwrap();
+    let mut psbt = builder.finish().unwrap();
+    wallet1.insert_txout(utxo.outpoint, utxo.txout);
+    let fee = check_fee!(wallet1, psbt);
     let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
+        wallet1.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
 
     assert_eq!(
-        original_sent_received.1,
-        Amount::from_sat(5_000) - original_fee.unwrap_or(Amount::ZERO)
+        (sent_received.0 - sent_received.1),
+        Amount::from_sat(10_000) + fee.unwrap_or(Amount::ZERO),
+        "we should have only net spent ~10_000"
     );
 
-    assert_eq!(
-        sent_received.0,
-        original_sent_received.0 + Amount::from_sat(25_000)
+    assert!(
+        psbt.unsigned_tx
+            .input
+            .iter()
+            .any(|input| input.previous_output == utxo.outpoint),
+        "foreign_utxo should be in there"
     );
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(30_000));
-    assert_eq!(sent_received.1, Amount::ZERO);
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 1);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(45_000)
+    let finished = wallet1
+        .sign(
+            &mut psbt,
+            SignOptions {
+                trust_witness_utxo: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    assert!(
+        !finished,
+        "only one of the inputs should have been signed so far"
     );
 
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(140), @dust_change, @add_signature);
+    let finished = wallet2
+        .sign(
+            &mut psbt,
+            SignOptions {
+                trust_witness_utxo: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(finished, "all the inputs should have been signed now");
 }
 
 #[test]
-fn test_bump_fee_force_add_input() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let incoming_op = receive_output_in_latest_block(&mut wallet, 25_000);
+#[should_panic(
+    expected = "MissingTxOut([OutPoint { txid: 0x21d7fb1bceda00ab4069fc52d06baa13470803e9050edd16f5736e5d8c4925fd, vout: 0 }])"
+)]
+fn test_calculate_fee_with_missing_foreign_utxo() {
+    let (mut wallet1, _) = get_funded_wallet_wpkh();
+    let (wallet2, _) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
 
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let mut tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.txid();
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
-    }
-    wallet
-        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+    let utxo = wallet2.list_unspent().next().expect("must take!");
+    let foreign_utxo_satisfaction = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
         .unwrap();
-    // the new fee_rate is low enough that just reducing the change would be fine, but we force
-    // the addition of an extra input with `add_utxo()`
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
+
+    let psbt_input = psbt::Input {
+        witness_utxo: Some(utxo.txout.clone()),
+        ..Default::default()
+    };
+
+    let mut builder = wallet1.build_tx();
     builder
-        .add_utxo(incoming_op)
-        .unwrap()
-        .fee_rate(FeeRate::from_sat_per_vb_unchecked(5));
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
+        .only_witness_utxo()
+        .add_foreign_utxo(utxo.outpoint, psbt_input, foreign_utxo_satisfaction)
+        .unwrap();
     let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
-
-    assert_eq!(
-        sent_received.0,
-        original_sent_received.0 + Amount::from_sat(25_000)
-    );
-    assert_eq!(
-        fee.unwrap_or(Amount::ZERO) + sent_received.1,
-        Amount::from_sat(30_000)
-    );
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    wallet1.calculate_fee(&tx).unwrap();
+}
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(45_000)
-    );
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
-    );
+#[test]
+fn test_add_foreign_utxo_invalid_psbt_input() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let outpoint = wallet.list_unspent().next().expect("must exist").outpoint;
+    let foreign_utxo_satisfaction = wallet
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
+        .unwrap();
 
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(5), @add_signature);
+    let mut builder = wallet.build_tx();
+    let result =
+        builder.add_foreign_utxo(outpoint, psbt::Input::default(), foreign_utxo_satisfaction);
+    assert!(matches!(result, Err(AddForeignUtxoError::MissingUtxo)));
 }
 
 #[test]
-fn test_bump_fee_absolute_force_add_input() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let incoming_op = receive_output_in_latest_block(&mut wallet, 25_000);
+fn test_add_foreign_utxo_where_outpoint_doesnt_match_psbt_input() {
+    let (mut wallet1, txid1) = get_funded_wallet_wpkh();
+    let (wallet2, txid2) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
 
+    let utxo2 = wallet2.list_unspent().next().unwrap();
+    let tx1 = wallet1.get_tx(txid1).unwrap().tx_node.tx.clone();
+    let tx2 = wallet2.get_tx(txid2).unwrap().tx_node.tx.clone();
+
+    let satisfaction_weight = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
+        .unwrap();
+
+    let mut builder = wallet1.build_tx();
+    assert!(
+        builder
+            .add_foreign_utxo(
+                utxo2.outpoint,
+                psbt::Input {
+                    non_witness_utxo: Some(tx1.as_ref().clone()),
+                    ..Default::default()
+                },
+                satisfaction_weight
+            )
+            .is_err(),
+        "should fail when outpoint doesn't match psbt_input"
+    );
+    assert!(
+        builder
+            .add_foreign_utxo(
+                utxo2.outpoint,
+                psbt::Input {
+                    non_witness_utxo: Some(tx2.as_ref().clone()),
+                    ..Default::default()
+                },
+                satisfaction_weight
+            )
+            .is_ok(),
+        "should be ok when outpoint does match psbt_input"
+    );
+}
+
+#[test]
+fn test_add_foreign_utxo_only_witness_utxo() {
+    let (mut wallet1, _) = get_funded_wallet_wpkh();
+    let (wallet2, txid2) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
-    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .enable_rbf();
-    let psbt = builder.finish().unwrap();
-    let mut tx = psbt.extract_tx().expect("failed to extract tx");
-    let original_sent_received = wallet.sent_and_received(&tx);
-    let txid = tx.txid();
-    // skip saving the new utxos, we know they can't be used anyways
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
-    }
-    wallet
-        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+    let utxo2 = wallet2.list_unspent().next().unwrap();
+
+    let satisfaction_weight = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
         .unwrap();
 
-    // the new fee_rate is low enough that just reducing the change would be fine, but we force
-    // the addition of an extra input with `add_utxo()`
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    let mut builder = wallet1.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(60_000));
+
+    {
+        let mut builder = builder.clone();
+        let psbt_input = psbt::Input {
+            witness_utxo: Some(utxo2.txout.clone()),
+            ..Default::default()
+        };
+        builder
+            .add_foreign_utxo(utxo2.outpoint, psbt_input, satisfaction_weight)
+            .unwrap();
+        assert!(
+            builder.finish().is_err(),
+            "psbt_input with witness_utxo should fail with only witness_utxo"
+        );
+    }
+
+    {
+        let mut builder = builder.clone();
+        let psbt_input = psbt::Input {
+            witness_utxo: Some(utxo2.txout.clone()),
+            ..Default::default()
+        };
+        builder
+            .only_witness_utxo()
+            .add_foreign_utxo(utxo2.outpoint, psbt_input, satisfaction_weight)
+            .unwrap();
+        assert!(
+            builder.finish().is_ok(),
+            "psbt_input with just witness_utxo should succeed when `only_witness_utxo` is enabled"
+        );
+    }
+
+    {
+        let mut builder = builder.clone();
+        let tx2 = wallet2.get_tx(txid2).unwrap().tx_node.tx;
+        let psbt_input = psbt::Input {
+            non_witness_utxo: Some(tx2.as_ref().clone()),
+            ..Default::default()
+        };
+        builder
+            .add_foreign_utxo(utxo2.outpoint, psbt_input, satisfaction_weight)
+            .unwrap();
+        assert!(
+            builder.finish().is_ok(),
+            "psbt_input with non_witness_utxo should succeed by default"
+        );
+    }
+}
+
+#[test]
+fn test_get_psbt_input() {
+    // this should grab a known good utxo and set the input
+    let (wallet, _) = get_funded_wallet_wpkh();
+    for utxo in wallet.list_unspent() {
+        let psbt_input = wallet.get_psbt_input(utxo, None, false).unwrap();
+        assert!(psbt_input.witness_utxo.is_some() || psbt_input.non_witness_utxo.is_some());
+    }
+}
+
+#[test]
+#[should_panic(
+    expected = "MissingKeyOrigin(\"tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3\")"
+)]
+fn test_create_tx_global_xpubs_origin_missing() {
+    let (mut wallet, _) = get_funded_wallet("wpkh(tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
     builder
-        .add_utxo(incoming_op)
-        .unwrap()
-        .fee_absolute(Amount::from_sat(250));
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .add_global_xpubs();
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_create_tx_global_xpubs_master_without_origin() {
+    use bitcoin::bip32;
+    use bitcoin::hex::FromHex;
+
+    let (mut wallet, _) = get_funded_wallet("wpkh(tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL/0/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .add_global_xpubs();
     let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    let fee = check_fee!(wallet, psbt);
 
-    assert_eq!(
-        sent_received.0,
-        original_sent_received.0 + Amount::from_sat(25_000)
-    );
-    assert_eq!(
-        fee.unwrap_or(Amount::ZERO) + sent_received.1,
-        Amount::from_sat(30_000)
-    );
+    let key = bip32::Xpub::from_str("tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL").unwrap();
+    let fingerprint = bip32::Fingerprint::from_hex("997a323b").unwrap();
 
-    let tx = &psbt.unsigned_tx;
-    assert_eq!(tx.input.len(), 2);
-    assert_eq!(tx.output.len(), 2);
-    assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey == addr.script_pubkey())
-            .unwrap()
-            .value,
-        Amount::from_sat(45_000)
-    );
+    assert_eq!(psbt.xpub.len(), 2);
     assert_eq!(
-        tx.output
-            .iter()
-            .find(|txout| txout.script_pubkey != addr.script_pubkey())
-            .unwrap()
-            .value,
-        sent_received.1
+        psbt.xpub.get(&key),
+        Some(&(fingerprint, bip32::DerivationPath::default()))
     );
-
-    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(250));
 }
 
 #[test]
-#[should_panic(expected = "InsufficientFunds")]
-fn test_bump_fee_unconfirmed_inputs_only() {
-    // We try to bump the fee, but:
-    // - We can't reduce the change, as we have no change
-    // - All our UTXOs are unconfirmed
-    // So, we fail with "InsufficientFunds", as per RBF rule 2:
-    // The replacement transaction may only include an unconfirmed input
-    // if that input was included in one of the original transactions.
+#[should_panic(expected = "IrreplaceableTransaction")]
+fn test_bump_fee_irreplaceable_tx() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder
-        .drain_wallet()
-        .drain_to(addr.script_pubkey())
-        .enable_rbf();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
     let psbt = builder.finish().unwrap();
-    // Now we receive one transaction with 0 confirmations. We won't be able to use that for
-    // fee bumping, as it's still unconfirmed!
-    receive_output(
-        &mut wallet,
-        25_000,
-        ConfirmationTime::Unconfirmed { last_seen: 0 },
-    );
-    let mut tx = psbt.extract_tx().expect("failed to extract tx");
+
+    let tx = psbt.extract_tx().expect("failed to extract tx");
     let txid = tx.txid();
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
-    }
     wallet
         .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
         .unwrap();
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(25));
-    builder.finish().unwrap();
+    wallet.build_fee_bump(txid).unwrap().finish().unwrap();
 }
 
 #[test]
-fn test_bump_fee_unconfirmed_input() {
-    // We create a tx draining the wallet and spending one confirmed
-    // and one unconfirmed UTXO. We check that we can fee bump normally
-    // (BIP125 rule 2 only apply to newly added unconfirmed input, you can
-    // always fee bump with an unconfirmed input if it was included in the
-    // original transaction)
+#[should_panic(expected = "TransactionConfirmed")]
+fn test_bump_fee_confirmed_tx() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
-    // We receive a tx with 0 confirmations, which will be used as an input
-    // in the drain tx.
-    receive_output(&mut wallet, 25_000, ConfirmationTime::unconfirmed(0));
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder
-        .drain_wallet()
-        .drain_to(addr.script_pubkey())
-        .enable_rbf();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
     let psbt = builder.finish().unwrap();
-    let mut tx = psbt.extract_tx().expect("failed to extract tx");
+
+    let tx = psbt.extract_tx().expect("failed to extract tx");
     let txid = tx.txid();
-    for txin in &mut tx.input {
-        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
-    }
-    wallet
-        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
-        .unwrap();
 
-    let mut builder = wallet.build_fee_bump(txid).unwrap();
-    builder
-        .fee_rate(FeeRate::from_sat_per_vb_unchecked(15))
-        // remove original tx drain_to address and amount
-        .set_recipients(Vec::new())
-        // set back original drain_to address
-        .drain_to(addr.script_pubkey())
-        // drain wallet output amount will be re-calculated with new fee rate
-        .drain_wallet();
-    builder.finish().unwrap();
+    wallet
+        .insert_tx(
+            tx,
+            ConfirmationTime::Confirmed {
+                height: 42,
+                time: 42_000,
+            },
+        )
+        .unwrap();
+
+    wallet.build_fee_bump(txid).unwrap().finish().unwrap();
 }
 
 #[test]
-fn test_fee_amount_negative_drain_val() {
-    // While building the transaction, bdk would calculate the drain_value
-    // as
-    // current_delta - fee_amount - drain_fee
-    // using saturating_sub, meaning that if the result would end up negative,
-    // it'll remain to zero instead.
-    // This caused a bug in master where we would calculate the wrong fee
-    // for a transaction.
-    // See https://github.com/bitcoindevkit/bdk/issues/660
+fn test_bump_fee_low_fee_rate() {
     let (mut wallet, _) = get_funded_wallet_wpkh();
-    let send_to = Address::from_str("tb1ql7w62elx9ucw4pj5lgw4l028hmuw80sndtntxt")
-        .unwrap()
-        .assume_checked();
-    let fee_rate = FeeRate::from_sat_per_kwu(500);
-    let incoming_op = receive_output_in_latest_block(&mut wallet, 8859);
-
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(send_to.script_pubkey(), Amount::from_sat(8630))
-        .add_utxo(incoming_op)
-        .unwrap()
-        .enable_rbf()
-        .fee_rate(fee_rate);
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf();
     let psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
+    let feerate = psbt.fee_rate().unwrap();
 
-    assert_eq!(psbt.inputs.len(), 1);
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate, @add_signature);
-}
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.txid();
 
-#[test]
-fn test_sign_single_xprv() {
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
 
-    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-    assert!(finalized);
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::BROADCAST_MIN);
+    let res = builder.finish();
+    assert_matches!(
+        res,
+        Err(CreateTxError::FeeRateTooLow { .. }),
+        "expected FeeRateTooLow error"
+    );
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
-    assert_eq!(extracted.input[0].witness.len(), 2);
+    let required = feerate.to_sat_per_kwu() + 250; // +1 sat/vb
+    let sat_vb = required as f64 / 250.0;
+    let expect = format!("Fee rate too low: required {} sat/vb", sat_vb);
+    assert_eq!(res.unwrap_err().to_string(), expect);
 }
 
 #[test]
-fn test_sign_single_xprv_with_master_fingerprint_and_path() {
-    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/84h/1h/0h]tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+#[should_panic(expected = "FeeTooLow")]
+fn test_bump_fee_low_abs() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
 
-    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-    assert!(finalized);
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.txid();
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
-    assert_eq!(extracted.input[0].witness.len(), 2);
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_absolute(Amount::from_sat(10));
+    builder.finish().unwrap();
 }
 
 #[test]
-fn test_sign_single_xprv_bip44_path() {
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/44'/0'/0'/0/*)");
+#[should_panic(expected = "FeeTooLow")]
+fn test_bump_fee_zero_abs() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
 
-    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-    assert!(finalized);
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
-    assert_eq!(extracted.input[0].witness.len(), 2);
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_absolute(Amount::ZERO);
+    builder.finish().unwrap();
 }
 
 #[test]
-fn test_sign_single_xprv_sh_wpkh() {
-    let (mut wallet, _) = get_funded_wallet("sh(wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*))");
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+fn test_bump_fee_reduce_change() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let original_fee = check_fee!(wallet, psbt);
 
-    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-    assert!(finalized);
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
-    assert_eq!(extracted.input[0].witness.len(), 2);
-}
+    let feerate = FeeRate::from_sat_per_kwu(625); // 2.5 sat/vb
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(feerate).enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
 
-#[test]
-fn test_sign_single_wif() {
-    let (mut wallet, _) =
-        get_funded_wallet("wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)");
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
+    assert_eq!(sent_received.0, original_sent_received.0);
+    assert_eq!(
+        sent_received.1 + fee.unwrap_or(Amount::ZERO),
+        original_sent_received.1 + original_fee.unwrap_or(Amount::ZERO)
+    );
+    assert!(fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO));
 
-    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-    assert!(finalized);
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(25_000)
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
-    assert_eq!(extracted.input[0].witness.len(), 2);
-}
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), feerate, @add_signature);
 
-#[test]
-fn test_sign_single_xprv_no_hd_keypaths() {
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_absolute(Amount::from_sat(200));
+    builder.enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
 
-    psbt.inputs[0].bip32_derivation.clear();
-    assert_eq!(psbt.inputs[0].bip32_derivation.len(), 0);
+    assert_eq!(sent_received.0, original_sent_received.0);
+    assert_eq!(
+        sent_received.1 + fee.unwrap_or(Amount::ZERO),
+        original_sent_received.1 + original_fee.unwrap_or(Amount::ZERO)
+    );
+    assert!(
+        fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO),
+        "{} > {}",
+        fee.unwrap_or(Amount::ZERO),
+        original_fee.unwrap_or(Amount::ZERO)
+    );
 
-    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
-    assert!(finalized);
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(25_000)
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
-    assert_eq!(extracted.input[0].witness.len(), 2);
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(200));
 }
 
 #[test]
-fn test_include_output_redeem_witness_script() {
-    let desc = get_test_wpkh();
-    let change_desc = "sh(wsh(multi(1,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)))";
-    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
+fn test_bump_fee_reduce_single_recipient() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .include_output_redeem_witness_script();
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .enable_rbf();
     let psbt = builder.finish().unwrap();
-
+    let tx = psbt.clone().extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let original_fee = check_fee!(wallet, psbt);
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let feerate = FeeRate::from_sat_per_kwu(625); // 2.5 sat/vb
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .fee_rate(feerate)
+        // remove original tx drain_to address and amount
+        .set_recipients(Vec::new())
+        // set back original drain_to address
+        .drain_to(addr.script_pubkey())
+        // drain wallet output amount will be re-calculated with new fee rate
+        .drain_wallet();
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(sent_received.0, original_sent_received.0);
+    assert!(fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO));
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.output.len(), 1);
+    assert_eq!(
+        tx.output[0].value + fee.unwrap_or(Amount::ZERO),
+        sent_received.0
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), feerate, @add_signature);
+}
+
+#[test]
+fn test_bump_fee_absolute_reduce_single_recipient() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_fee = check_fee!(wallet, psbt);
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .fee_absolute(Amount::from_sat(300))
+        // remove original tx drain_to address and amount
+        .set_recipients(Vec::new())
+        // set back original drain_to address
+        .drain_to(addr.script_pubkey())
+        // drain wallet output amount will be re-calculated with new fee rate
+        .drain_wallet();
+    let psbt = builder.finish().unwrap();
+    let tx = &psbt.unsigned_tx;
+    let sent_received = wallet.sent_and_received(tx);
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(sent_received.0, original_sent_received.0);
+    assert!(fee.unwrap_or(Amount::ZERO) > original_fee.unwrap_or(Amount::ZERO));
+
+    assert_eq!(tx.output.len(), 1);
+    assert_eq!(
+        tx.output[0].value + fee.unwrap_or(Amount::ZERO),
+        sent_received.0
+    );
+
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(300));
+}
+
+#[test]
+fn test_bump_fee_drain_wallet() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    // receive an extra tx so that our wallet has two utxos.
+    let tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .next_unused_address(KeychainKind::External)
+                .unwrap()
+                .script_pubkey(),
+            value: Amount::from_sat(25_000),
+        }],
+    };
+    wallet
+        .insert_tx(
+            tx.clone(),
+            ConfirmationTime::Confirmed {
+                height: wallet.latest_checkpoint().height(),
+                time: 42_000,
+            },
+        )
+        .unwrap();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .add_utxo(OutPoint {
+            txid: tx.txid(),
+            vout: 0,
+        })
+        .unwrap()
+        .manually_selected_only()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    assert_eq!(original_sent_received.0, Amount::from_sat(25_000));
+
+    // for the new feerate, it should be enough to reduce the output, but since we specify
+    // `drain_wallet` we expect to spend everything
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .drain_wallet()
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(5));
+    let psbt = builder.finish().unwrap();
+    let sent_received = wallet.sent_and_received(&psbt.extract_tx().expect("failed to extract tx"));
+
+    assert_eq!(sent_received.0, Amount::from_sat(75_000));
+}
+
+#[test]
+#[should_panic(expected = "InsufficientFunds")]
+fn test_bump_fee_remove_output_manually_selected_only() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    // receive an extra tx so that our wallet has two utxos. then we manually pick only one of
+    // them, and make sure that `bump_fee` doesn't try to add more. This fails because we've
+    // told the wallet it's not allowed to add more inputs AND it can't reduce the value of the
+    // existing output. In other words, bump_fee + manually_selected_only is always an error
+    // unless there is a change output.
+    let init_tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .next_unused_address(KeychainKind::External)
+                .unwrap()
+                .script_pubkey(),
+            value: Amount::from_sat(25_000),
+        }],
+    };
+    wallet
+        .insert_tx(
+            init_tx.clone(),
+            wallet
+                .transactions()
+                .last()
+                .unwrap()
+                .chain_position
+                .cloned()
+                .into(),
+        )
+        .unwrap();
+    let outpoint = OutPoint {
+        txid: init_tx.txid(),
+        vout: 0,
+    };
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .add_utxo(outpoint)
+        .unwrap()
+        .manually_selected_only()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    assert_eq!(original_sent_received.0, Amount::from_sat(25_000));
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .manually_selected_only()
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(255));
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_bump_fee_add_input() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let init_tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .next_unused_address(KeychainKind::External)
+                .unwrap()
+                .script_pubkey(),
+            value: Amount::from_sat(25_000),
+        }],
+    };
+    let pos = wallet
+        .transactions()
+        .last()
+        .unwrap()
+        .chain_position
+        .cloned()
+        .into();
+    wallet.insert_tx(init_tx, pos).unwrap();
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_details = wallet.sent_and_received(&tx);
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(50));
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+    assert_eq!(
+        sent_received.0,
+        original_details.0 + Amount::from_sat(25_000)
+    );
+    assert_eq!(
+        fee.unwrap_or(Amount::ZERO) + sent_received.1,
+        Amount::from_sat(30_000)
+    );
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(45_000)
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(50), @add_signature);
+}
+
+#[test]
+fn test_bump_fee_absolute_add_input() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    receive_output_in_latest_block(&mut wallet, 25_000);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_absolute(Amount::from_sat(6_000));
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(
+        sent_received.0,
+        original_sent_received.0 + Amount::from_sat(25_000)
+    );
+    assert_eq!(
+        fee.unwrap_or(Amount::ZERO) + sent_received.1,
+        Amount::from_sat(30_000)
+    );
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(45_000)
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(6_000));
+}
+
+#[test]
+fn test_bump_fee_no_change_add_input_and_change() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let op = receive_output_in_latest_block(&mut wallet, 25_000);
+
+    // initially make a tx without change by using `drain_to`
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .add_utxo(op)
+        .unwrap()
+        .manually_selected_only()
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let original_fee = check_fee!(wallet, psbt);
+
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    // Now bump the fees, the wallet should add an extra input and a change output, and leave
+    // the original output untouched.
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(50));
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    let original_send_all_amount = original_sent_received.0 - original_fee.unwrap_or(Amount::ZERO);
+    assert_eq!(
+        sent_received.0,
+        original_sent_received.0 + Amount::from_sat(50_000)
+    );
+    assert_eq!(
+        sent_received.1,
+        Amount::from_sat(75_000) - original_send_all_amount - fee.unwrap_or(Amount::ZERO)
+    );
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        original_send_all_amount
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(75_000) - original_send_all_amount - fee.unwrap_or(Amount::ZERO)
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(50), @add_signature);
+}
+
+#[test]
+fn test_bump_fee_add_input_change_dust() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    receive_output_in_latest_block(&mut wallet, 25_000);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let original_fee = check_fee!(wallet, psbt);
+
+    let mut tx = psbt.extract_tx().expect("failed to extract tx");
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // to get realistic weight
+    }
+    let original_tx_weight = tx.weight();
+    assert_eq!(tx.input.len(), 1);
+    assert_eq!(tx.output.len(), 2);
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    // We set a fee high enough that during rbf we are forced to add
+    // a new input and also that we have to remove the change
+    // that we had previously
+
+    // We calculate the new weight as:
+    //   original weight
+    // + extra input weight: 160 WU = (32 (prevout) + 4 (vout) + 4 (nsequence)) * 4
+    // + input satisfaction weight: 112 WU = 106 (witness) + 2 (witness len) + (1 (script len)) * 4
+    // - change output weight: 124 WU = (8 (value) + 1 (script len) + 22 (script)) * 4
+    let new_tx_weight =
+        original_tx_weight + Weight::from_wu(160) + Weight::from_wu(112) - Weight::from_wu(124);
+    // two inputs (50k, 25k) and one output (45k) - epsilon
+    // We use epsilon here to avoid asking for a slightly too high feerate
+    let fee_abs = 50_000 + 25_000 - 45_000 - 10;
+    builder.fee_rate(Amount::from_sat(fee_abs) / new_tx_weight);
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(
+        original_sent_received.1,
+        Amount::from_sat(5_000) - original_fee.unwrap_or(Amount::ZERO)
+    );
+
+    assert_eq!(
+        sent_received.0,
+        original_sent_received.0 + Amount::from_sat(25_000)
+    );
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(30_000));
+    assert_eq!(sent_received.1, Amount::ZERO);
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 1);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(45_000)
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(140), @dust_change, @add_signature);
+}
+
+#[test]
+fn test_bump_fee_force_add_input() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let incoming_op = receive_output_in_latest_block(&mut wallet, 25_000);
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let mut tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.txid();
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    wallet
+        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    // the new fee_rate is low enough that just reducing the change would be fine, but we force
+    // the addition of an extra input with `add_utxo()`
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .add_utxo(incoming_op)
+        .unwrap()
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(5));
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(
+        sent_received.0,
+        original_sent_received.0 + Amount::from_sat(25_000)
+    );
+    assert_eq!(
+        fee.unwrap_or(Amount::ZERO) + sent_received.1,
+        Amount::from_sat(30_000)
+    );
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(45_000)
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), FeeRate::from_sat_per_vb_unchecked(5), @add_signature);
+}
+
+#[test]
+fn test_bump_fee_absolute_force_add_input() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let incoming_op = receive_output_in_latest_block(&mut wallet, 25_000);
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let mut tx = psbt.extract_tx().expect("failed to extract tx");
+    let original_sent_received = wallet.sent_and_received(&tx);
+    let txid = tx.txid();
+    // skip saving the new utxos, we know they can't be used anyways
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    wallet
+        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    // the new fee_rate is low enough that just reducing the change would be fine, but we force
+    // the addition of an extra input with `add_utxo()`
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .add_utxo(incoming_op)
+        .unwrap()
+        .fee_absolute(Amount::from_sat(250));
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(
+        sent_received.0,
+        original_sent_received.0 + Amount::from_sat(25_000)
+    );
+    assert_eq!(
+        fee.unwrap_or(Amount::ZERO) + sent_received.1,
+        Amount::from_sat(30_000)
+    );
+
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output.len(), 2);
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap()
+            .value,
+        Amount::from_sat(45_000)
+    );
+    assert_eq!(
+        tx.output
+            .iter()
+            .find(|txout| txout.script_pubkey != addr.script_pubkey())
+            .unwrap()
+            .value,
+        sent_received.1
+    );
+
+    assert_eq!(fee.unwrap_or(Amount::ZERO), Amount::from_sat(250));
+}
+
+#[test]
+#[should_panic(expected = "InsufficientFunds")]
+fn test_bump_fee_unconfirmed_inputs_only() {
+    // We try to bump the fee, but:
+    // - We can't reduce the change, as we have no change
+    // - All our UTXOs are unconfirmed
+    // So, we fail with "InsufficientFunds", as per RBF rule 2:
+    // The replacement transaction may only include an unconfirmed input
+    // if that input was included in one of the original transactions.
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_wallet()
+        .drain_to(addr.script_pubkey())
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    // Now we receive one transaction with 0 confirmations. We won't be able to use that for
+    // fee bumping, as it's still unconfirmed!
+    receive_output(
+        &mut wallet,
+        25_000,
+        ConfirmationTime::Unconfirmed { last_seen: 0 },
+    );
+    let mut tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.txid();
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(25));
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_bump_fee_unconfirmed_input() {
+    // We create a tx draining the wallet and spending one confirmed
+    // and one unconfirmed UTXO. We check that we can fee bump normally
+    // (BIP125 rule 2 only apply to newly added unconfirmed input, you can
+    // always fee bump with an unconfirmed input if it was included in the
+    // original transaction)
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    // We receive a tx with 0 confirmations, which will be used as an input
+    // in the drain tx.
+    receive_output(&mut wallet, 25_000, ConfirmationTime::unconfirmed(0));
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_wallet()
+        .drain_to(addr.script_pubkey())
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let mut tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.txid();
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(15))
+        // remove original tx drain_to address and amount
+        .set_recipients(Vec::new())
+        // set back original drain_to address
+        .drain_to(addr.script_pubkey())
+        // drain wallet output amount will be re-calculated with new fee rate
+        .drain_wallet();
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_cancel() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    receive_output_in_latest_block(&mut wallet, 25_000);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(2))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let original_fee = check_fee!(wallet, psbt).unwrap();
+    let mut tx = psbt.extract_tx().expect("failed to extract tx");
+    for txin in &mut tx.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let mut psbt = wallet.build_cancel(txid).unwrap().finish().unwrap();
+    let fee = check_fee!(wallet, psbt).unwrap();
+    // the fee must cover the weight of the signed replacement
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let cancel_tx = psbt.extract_tx().expect("failed to extract tx");
+
+    // the replacement spends the same inputs back to the wallet's change keychain
+    let mut inputs = tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .collect::<Vec<_>>();
+    let mut cancel_inputs = cancel_tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .collect::<Vec<_>>();
+    inputs.sort();
+    cancel_inputs.sort();
+    assert_eq!(inputs, cancel_inputs);
+    assert_eq!(cancel_tx.output.len(), 1);
+    assert_matches!(
+        wallet.derivation_of_spk(&cancel_tx.output[0].script_pubkey),
+        Some((KeychainKind::Internal, _))
+    );
+
+    // BIP125 rules 3 and 4
+    assert!(fee >= original_fee + FeeRate::BROADCAST_MIN * cancel_tx.weight());
+    assert!(fee / cancel_tx.weight() > original_fee / tx.weight());
+}
+
+#[test]
+fn test_cancel_with_descendant() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(2))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let parent_fee = check_fee!(wallet, psbt).unwrap();
+    let parent = psbt.extract_tx().expect("failed to extract tx");
+    let parent_txid = parent.txid();
+    wallet
+        .insert_tx(
+            parent.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
+        )
+        .unwrap();
+
+    // the child spends the change of the parent at a higher feerate
+    let change = parent
+        .output
+        .iter()
+        .position(|txout| wallet.is_mine(&txout.script_pubkey))
+        .unwrap();
+    let change = OutPoint::new(parent_txid, change as u32);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_utxo(change)
+        .unwrap()
+        .manually_selected_only()
+        .drain_to(addr.script_pubkey())
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(20))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let child_fee = check_fee!(wallet, psbt).unwrap();
+    let child = psbt.extract_tx().expect("failed to extract tx");
+    wallet
+        .insert_tx(
+            child.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
+        )
+        .unwrap();
+
+    // BIP125 rule 3: the replacement pays for the parent and the child it evicts
+    let mut psbt = wallet.build_cancel(parent_txid).unwrap().finish().unwrap();
+    let fee = check_fee!(wallet, psbt).unwrap();
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let cancel_tx = psbt.extract_tx().expect("failed to extract tx");
+    assert!(fee >= parent_fee + child_fee + FeeRate::BROADCAST_MIN * cancel_tx.weight());
+
+    // the replacement can't be built when the fee of a descendant is unknown
+    let grandchild = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![
+            TxIn {
+                previous_output: OutPoint::new(child.txid(), 0),
+                ..Default::default()
+            },
+            TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([1; 32]), 0),
+                ..Default::default()
+            },
+        ],
+        output: vec![TxOut {
+            value: Amount::from_sat(1_000),
+            script_pubkey: addr.script_pubkey(),
+        }],
+    };
+    let grandchild_txid = grandchild.txid();
+    wallet
+        .insert_tx(grandchild, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    assert_matches!(
+        wallet.build_cancel(parent_txid),
+        Err(BuildCancelError::FeeUnavailable(txid)) if txid == grandchild_txid
+    );
+}
+
+#[test]
+fn test_cancel_stages_change_address() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(1))
+        .enable_rbf();
+    let mut psbt = builder.finish().unwrap();
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    wallet
+        .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    let psbt = wallet.build_cancel(tx.txid()).unwrap().finish().unwrap();
+    let cancel_tx = psbt.extract_tx().expect("failed to extract tx");
+
+    // the change address of the replacement is revealed after the one of the original
+    // transaction, and is only staged
+    let descriptor_id = wallet
+        .public_descriptor(KeychainKind::Internal)
+        .descriptor_id();
+    assert_eq!(
+        wallet.derivation_of_spk(&cancel_tx.output[0].script_pubkey),
+        Some((KeychainKind::Internal, 1))
+    );
+    assert_eq!(
+        wallet
+            .staged()
+            .indexed_tx_graph
+            .indexer
+            .last_revealed
+            .get(&descriptor_id),
+        Some(&1)
+    );
+}
+
+#[test]
+fn test_cancel_errors() {
+    let (mut wallet, funding_txid) = get_funded_wallet_wpkh();
+    assert_matches!(
+        wallet.build_cancel(funding_txid),
+        Err(BuildCancelError::FeeBump(
+            BuildFeeBumpError::TransactionConfirmed(txid)
+        )) if txid == funding_txid
+    );
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let tx = builder
+        .finish()
+        .unwrap()
+        .extract_tx()
+        .expect("failed to extract tx");
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    assert_matches!(
+        wallet.build_cancel(txid),
+        Err(BuildCancelError::FeeBump(
+            BuildFeeBumpError::IrreplaceableTransaction(_)
+        ))
+    );
+}
+
+#[test]
+fn test_cpfp() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+
+    // the first tx is stuck in the mempool, and the second one spends its change
+    let mut txs = Vec::new();
+    for amount in [25_000, 10_000] {
+        let mut builder = wallet.build_tx();
+        builder
+            .add_recipient(addr.script_pubkey(), Amount::from_sat(amount))
+            .fee_rate(FeeRate::from_sat_per_vb_unchecked(1));
+        let psbt = builder.finish().unwrap();
+        let mut tx = psbt.extract_tx().expect("failed to extract tx");
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+        }
+        wallet
+            .insert_tx(tx.clone(), ConfirmationTime::Unconfirmed { last_seen: 0 })
+            .unwrap();
+        txs.push(tx);
+    }
+    let parent_txid = txs[1].txid();
+
+    let feerate = FeeRate::from_sat_per_vb_unchecked(10);
+    let mut builder = wallet.build_cpfp(parent_txid, feerate).unwrap();
+    builder.enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let child_fee = check_fee!(wallet, psbt).unwrap();
+
+    // the child only spends the parent's change and sends it to a new change address
+    let mut child = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(child.input.len(), 1);
+    assert_eq!(child.input[0].previous_output.txid, parent_txid);
+    assert_eq!(child.output.len(), 1);
+    assert!(wallet.is_mine(&child.output[0].script_pubkey));
+    for txin in &mut child.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+
+    // the whole package reaches the target feerate
+    let package_fee = txs
+        .iter()
+        .map(|tx| wallet.calculate_fee(tx).unwrap())
+        .sum::<Amount>()
+        + child_fee;
+    let package_weight = txs.iter().map(|tx| tx.weight()).sum::<Weight>() + child.weight();
+    assert!(package_fee / package_weight >= feerate);
+    assert!(child_fee > feerate * child.weight());
+}
+
+#[test]
+fn test_cpfp_confirmed_ancestors_are_not_paid_for() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let spk = wallet
+        .peek_address(KeychainKind::External, 5)
+        .script_pubkey();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(spk, Amount::from_sat(25_000))
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(1));
+    let psbt = builder.finish().unwrap();
+    let parent_fee = check_fee!(wallet, psbt).unwrap();
+    let mut parent = psbt.extract_tx().expect("failed to extract tx");
+    for txin in &mut parent.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+    let parent_txid = parent.txid();
+    wallet
+        .insert_tx(
+            parent.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
+        )
+        .unwrap();
+
+    let feerate = FeeRate::from_sat_per_vb_unchecked(5);
+    let mut builder = wallet.build_cpfp(parent_txid, feerate).unwrap();
+    builder.manually_selected_only();
+    let psbt = builder.finish().unwrap();
+    let child_fee = check_fee!(wallet, psbt).unwrap();
+    let mut child = psbt.extract_tx().expect("failed to extract tx");
+    // both outputs of the parent are ours
+    assert_eq!(child.input.len(), 2);
+    for txin in &mut child.input {
+        txin.witness.push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    }
+
+    // the confirmed funding tx is not part of the package
+    let package_feerate = (parent_fee + child_fee) / (parent.weight() + child.weight());
+    assert!(package_feerate >= feerate);
+    assert!(package_feerate < FeeRate::from_sat_per_vb_unchecked(6));
+}
+
+#[test]
+fn test_cpfp_foreign_parent() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let spent_outpoint = OutPoint::new(Txid::from_byte_array([0x01; 32]), 0);
+    // an incoming payment spending an output the wallet doesn't know about
+    let parent = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: spent_outpoint,
+            witness: Witness::from_slice(&[[0x00; P2WPKH_FAKE_WITNESS_SIZE]]),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .next_unused_address(KeychainKind::External)
+                .unwrap()
+                .script_pubkey(),
+            value: Amount::from_sat(25_000),
+        }],
+    };
+    let parent_txid = parent.txid();
+    let parent_weight = parent.weight();
+    wallet
+        .insert_tx(parent, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    // the fee of the parent is unknown, a child paying only for itself wouldn't help
+    let feerate = FeeRate::from_sat_per_vb_unchecked(10);
+    assert_matches!(
+        wallet.build_cpfp(parent_txid, feerate),
+        Err(BuildCpfpError::FeeUnavailable(txid)) if txid == parent_txid
+    );
+
+    // once the spent output is known, the child pays for the parent
+    wallet.insert_txout(
+        spent_outpoint,
+        TxOut {
+            script_pubkey: ScriptBuf::new(),
+            value: Amount::from_sat(25_100),
+        },
+    );
+    let mut builder = wallet.build_cpfp(parent_txid, feerate).unwrap();
+    builder.manually_selected_only();
+    let psbt = builder.finish().unwrap();
+    let child_fee = check_fee!(wallet, psbt).unwrap();
+    assert_eq!(psbt.unsigned_tx.input.len(), 1);
+    assert_eq!(psbt.unsigned_tx.input[0].previous_output.txid, parent_txid);
+    let mut child = psbt.extract_tx().expect("failed to extract tx");
+    child.input[0]
+        .witness
+        .push([0x00; P2WPKH_FAKE_WITNESS_SIZE]); // fake signature
+    let package_weight = parent_weight + child.weight();
+    let package_fee = Amount::from_sat(100) + child_fee;
+    assert!(package_fee >= feerate * package_weight);
+    assert!(package_fee / package_weight <= FeeRate::from_sat_per_vb_unchecked(11));
+}
+
+#[test]
+fn test_cpfp_stages_change_address() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(1));
+    let mut psbt = builder.finish().unwrap();
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let parent = psbt.extract_tx().expect("failed to extract tx");
+    wallet
+        .insert_tx(
+            parent.clone(),
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
+        )
+        .unwrap();
+
+    let psbt = wallet
+        .build_cpfp(parent.txid(), FeeRate::from_sat_per_vb_unchecked(10))
+        .unwrap()
+        .finish()
+        .unwrap();
+    let child = psbt.extract_tx().expect("failed to extract tx");
+
+    // the change address of the child is revealed after the one of the parent, and is only
+    // staged
+    let descriptor_id = wallet
+        .public_descriptor(KeychainKind::Internal)
+        .descriptor_id();
+    assert_eq!(
+        wallet.derivation_of_spk(&child.output[0].script_pubkey),
+        Some((KeychainKind::Internal, 1))
+    );
+    assert_eq!(
+        wallet
+            .staged()
+            .indexed_tx_graph
+            .indexer
+            .last_revealed
+            .get(&descriptor_id),
+        Some(&1)
+    );
+}
+
+#[test]
+fn test_cpfp_errors() {
+    let (mut wallet, funding_txid) = get_funded_wallet_wpkh();
+    let feerate = FeeRate::from_sat_per_vb_unchecked(10);
+
+    assert_matches!(
+        wallet.build_cpfp(Txid::all_zeros(), feerate),
+        Err(BuildCpfpError::TransactionNotFound(_))
+    );
+    assert_matches!(
+        wallet.build_cpfp(funding_txid, feerate),
+        Err(BuildCpfpError::TransactionConfirmed(txid)) if txid == funding_txid
+    );
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let tx = builder
+        .finish()
+        .unwrap()
+        .extract_tx()
+        .expect("failed to extract tx");
+    let txid = tx.txid();
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+    assert_matches!(
+        wallet.build_cpfp(txid, feerate),
+        Err(BuildCpfpError::NoSpendableOutput(t)) if t == txid
+    );
+}
+
+#[test]
+fn test_fee_amount_negative_drain_val() {
+    // While building the transaction, bdk would calculate the drain_value
+    // as
+    // current_delta - fee_amount - drain_fee
+    // using saturating_sub, meaning that if the result would end up negative,
+    // it'll remain to zero instead.
+    // This caused a bug in master where we would calculate the wrong fee
+    // for a transaction.
+    // See https://github.com/bitcoindevkit/bdk/issues/660
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let send_to = Address::from_str("tb1ql7w62elx9ucw4pj5lgw4l028hmuw80sndtntxt")
+        .unwrap()
+        .assume_checked();
+    let fee_rate = FeeRate::from_sat_per_kwu(500);
+    let incoming_op = receive_output_in_latest_block(&mut wallet, 8859);
+
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(send_to.script_pubkey(), Amount::from_sat(8630))
+        .add_utxo(incoming_op)
+        .unwrap()
+        .enable_rbf()
+        .fee_rate(fee_rate);
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+
+    assert_eq!(psbt.inputs.len(), 1);
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate, @add_signature);
+}
+
+#[test]
+fn test_sign_single_xprv() {
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+    assert!(finalized);
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(extracted.input[0].witness.len(), 2);
+}
+
+#[test]
+fn test_sign_single_xprv_with_master_fingerprint_and_path() {
+    let (mut wallet, _) = get_funded_wallet("wpkh([d34db33f/84h/1h/0h]tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+    assert!(finalized);
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(extracted.input[0].witness.len(), 2);
+}
+
+#[test]
+fn test_sign_single_xprv_bip44_path() {
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/44'/0'/0'/0/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+    assert!(finalized);
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(extracted.input[0].witness.len(), 2);
+}
+
+#[test]
+fn test_sign_single_xprv_sh_wpkh() {
+    let (mut wallet, _) = get_funded_wallet("sh(wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*))");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+    assert!(finalized);
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(extracted.input[0].witness.len(), 2);
+}
+
+#[test]
+fn test_sign_single_wif() {
+    let (mut wallet, _) =
+        get_funded_wallet("wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+    assert!(finalized);
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(extracted.input[0].witness.len(), 2);
+}
+
+#[test]
+fn test_sign_single_xprv_no_hd_keypaths() {
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    psbt.inputs[0].bip32_derivation.clear();
+    assert_eq!(psbt.inputs[0].bip32_derivation.len(), 0);
+
+    let finalized = wallet.sign(&mut psbt, Default::default()).unwrap();
+    assert!(finalized);
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(extracted.input[0].witness.len(), 2);
+}
+
+#[test]
+fn test_include_output_redeem_witness_script() {
+    let desc = get_test_wpkh();
+    let change_desc = "sh(wsh(multi(1,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)))";
+    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .include_output_redeem_witness_script();
+    let psbt = builder.finish().unwrap();
+
     // p2sh-p2wsh transaction should contain both witness and redeem scripts
     assert!(psbt
         .outputs
@@ -2681,120 +4223,971 @@ fn test_include_output_redeem_witness_script() {
 }
 
 #[test]
-fn test_signing_only_one_of_multiple_inputs() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
-        .unwrap()
-        .assume_checked();
+fn test_signing_only_one_of_multiple_inputs() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
+        .include_output_redeem_witness_script();
+    let mut psbt = builder.finish().unwrap();
+
+    // add another input to the psbt that is at least passable.
+    let dud_input = bitcoin::psbt::Input {
+        witness_utxo: Some(TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: miniscript::Descriptor::<bitcoin::PublicKey>::from_str(
+                "wpkh(025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357)",
+            )
+            .unwrap()
+            .script_pubkey(),
+        }),
+        ..Default::default()
+    };
+
+    psbt.inputs.push(dud_input);
+    psbt.unsigned_tx.input.push(bitcoin::TxIn::default());
+    let is_final = wallet
+        .sign(
+            &mut psbt,
+            SignOptions {
+                trust_witness_utxo: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert!(
+        !is_final,
+        "shouldn't be final since we can't sign one of the inputs"
+    );
+    assert!(
+        psbt.inputs[0].final_script_witness.is_some(),
+        "should finalized input it signed"
+    )
+}
+
+#[test]
+fn test_remove_partial_sigs_after_finalize_sign_option() {
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+
+    for remove_partial_sigs in &[true, false] {
+        let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let mut psbt = builder.finish().unwrap();
+
+        assert!(wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    remove_partial_sigs: *remove_partial_sigs,
+                    ..Default::default()
+                },
+            )
+            .unwrap());
+
+        psbt.inputs.iter().for_each(|input| {
+            if *remove_partial_sigs {
+                assert!(input.partial_sigs.is_empty())
+            } else {
+                assert!(!input.partial_sigs.is_empty())
+            }
+        });
+    }
+}
+
+#[test]
+fn test_try_finalize_sign_option() {
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+
+    for try_finalize in &[true, false] {
+        let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+        let mut builder = wallet.build_tx();
+        builder.drain_to(addr.script_pubkey()).drain_wallet();
+        let mut psbt = builder.finish().unwrap();
+
+        let finalized = wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    try_finalize: *try_finalize,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        psbt.inputs.iter().for_each(|input| {
+            if *try_finalize {
+                assert!(finalized);
+                assert!(input.final_script_sig.is_some());
+                assert!(input.final_script_witness.is_some());
+            } else {
+                assert!(!finalized);
+                assert!(input.final_script_sig.is_none());
+                assert!(input.final_script_witness.is_none());
+            }
+        });
+    }
+}
+
+#[test]
+fn test_sign_nonstandard_sighash() {
+    let sighash = EcdsaSighashType::NonePlusAnyoneCanPay;
+
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .sighash(sighash.into())
+        .drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let result = wallet.sign(&mut psbt, Default::default());
+    assert!(
+        result.is_err(),
+        "Signing should have failed because the TX uses non-standard sighashes"
+    );
+    assert_matches!(
+        result,
+        Err(SignerError::NonStandardSighash),
+        "Signing failed with the wrong error type"
+    );
+
+    // try again after opting-in
+    let result = wallet.sign(
+        &mut psbt,
+        SignOptions {
+            allow_all_sighashes: true,
+            ..Default::default()
+        },
+    );
+    assert!(result.is_ok(), "Signing should have worked");
+    assert!(
+        result.unwrap(),
+        "Should finalize the input since we can produce signatures"
+    );
+
+    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    assert_eq!(
+        *extracted.input[0].witness.to_vec()[0].last().unwrap(),
+        sighash.to_u32() as u8,
+        "The signature should have been made with the right sighash"
+    );
+}
+
+#[test]
+fn test_unused_address() {
+    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let change_desc = get_test_wpkh();
+    let mut wallet = Wallet::new_no_persist(desc, change_desc, Network::Testnet).unwrap();
+
+    // `list_unused_addresses` should be empty if we haven't revealed any
+    assert!(wallet
+        .list_unused_addresses(KeychainKind::External)
+        .next()
+        .is_none());
+
+    assert_eq!(
+        wallet
+            .next_unused_address(KeychainKind::External)
+            .unwrap()
+            .to_string(),
+        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    );
+    assert_eq!(
+        wallet
+            .list_unused_addresses(KeychainKind::External)
+            .next()
+            .unwrap()
+            .to_string(),
+        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    );
+}
+
+#[test]
+fn test_next_unused_address() {
+    let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let change = get_test_wpkh();
+    let mut wallet = Wallet::new_no_persist(descriptor, change, Network::Testnet).unwrap();
+    assert_eq!(wallet.derivation_index(KeychainKind::External), None);
+
+    assert_eq!(
+        wallet
+            .next_unused_address(KeychainKind::External)
+            .unwrap()
+            .to_string(),
+        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    );
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
+    // calling next_unused again gives same address
+    assert_eq!(
+        wallet
+            .next_unused_address(KeychainKind::External)
+            .unwrap()
+            .to_string(),
+        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    );
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
+
+    // test mark used / unused
+    assert!(wallet.mark_used(KeychainKind::External, 0));
+    let next_unused_addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    assert_eq!(next_unused_addr.index, 1);
+
+    assert!(wallet.unmark_used(KeychainKind::External, 0));
+    let next_unused_addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    assert_eq!(next_unused_addr.index, 0);
+
+    // use the above address
+    receive_output_in_latest_block(&mut wallet, 25_000);
+
+    assert_eq!(
+        wallet
+            .next_unused_address(KeychainKind::External)
+            .unwrap()
+            .to_string(),
+        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+    );
+    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(1));
+
+    // trying to mark index 0 unused should return false
+    assert!(!wallet.unmark_used(KeychainKind::External, 0));
+}
+
+#[test]
+fn test_peek_address_at_index() {
+    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+    let change_desc = get_test_wpkh();
+    let mut wallet = Wallet::new_no_persist(desc, change_desc, Network::Testnet).unwrap();
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 1).to_string(),
+        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+    );
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 0).to_string(),
+        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    );
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 2).to_string(),
+        "tb1qzntf2mqex4ehwkjlfdyy3ewdlk08qkvkvrz7x2"
+    );
+
+    // current new address is not affected
+    assert_eq!(
+        wallet
+            .reveal_next_address(KeychainKind::External)
+            .unwrap()
+            .to_string(),
+        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    );
+
+    assert_eq!(
+        wallet
+            .reveal_next_address(KeychainKind::External)
+            .unwrap()
+            .to_string(),
+        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+    );
+}
+
+#[test]
+fn test_peek_address_at_index_not_derivable() {
+    let wallet = Wallet::new_no_persist("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/1)",
+                                 get_test_wpkh(), Network::Testnet).unwrap();
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 1).to_string(),
+        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+    );
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 0).to_string(),
+        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+    );
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 2).to_string(),
+        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+    );
+}
+
+#[test]
+fn test_returns_index_and_address() {
+    let mut wallet = Wallet::new_no_persist("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)",
+                                 get_test_wpkh(), Network::Testnet).unwrap();
+
+    // new index 0
+    assert_eq!(
+        wallet.reveal_next_address(KeychainKind::External).unwrap(),
+        AddressInfo {
+            index: 0,
+            address: Address::from_str("tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a")
+                .unwrap()
+                .assume_checked(),
+            keychain: KeychainKind::External,
+        }
+    );
+
+    // new index 1
+    assert_eq!(
+        wallet.reveal_next_address(KeychainKind::External).unwrap(),
+        AddressInfo {
+            index: 1,
+            address: Address::from_str("tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7")
+                .unwrap()
+                .assume_checked(),
+            keychain: KeychainKind::External,
+        }
+    );
+
+    // peek index 25
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 25),
+        AddressInfo {
+            index: 25,
+            address: Address::from_str("tb1qsp7qu0knx3sl6536dzs0703u2w2ag6ppl9d0c2")
+                .unwrap()
+                .assume_checked(),
+            keychain: KeychainKind::External,
+        }
+    );
+
+    // new index 2
+    assert_eq!(
+        wallet.reveal_next_address(KeychainKind::External).unwrap(),
+        AddressInfo {
+            index: 2,
+            address: Address::from_str("tb1qzntf2mqex4ehwkjlfdyy3ewdlk08qkvkvrz7x2")
+                .unwrap()
+                .assume_checked(),
+            keychain: KeychainKind::External,
+        }
+    );
+}
+
+#[test]
+fn test_sending_to_bip350_bech32m_address() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(45_000));
+    builder.finish().unwrap();
+}
+
+#[test]
+fn test_get_address() {
+    use bdk_wallet::descriptor::template::Bip84;
+    let key = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy").unwrap();
+    let wallet = Wallet::new_no_persist(
+        Bip84(key, KeychainKind::External),
+        Bip84(key, KeychainKind::Internal),
+        Network::Regtest,
+    )
+    .unwrap();
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::External, 0),
+        AddressInfo {
+            index: 0,
+            address: Address::from_str("bcrt1qrhgaqu0zvf5q2d0gwwz04w0dh0cuehhqvzpp4w")
+                .unwrap()
+                .assume_checked(),
+            keychain: KeychainKind::External,
+        }
+    );
+
+    assert_eq!(
+        wallet.peek_address(KeychainKind::Internal, 0),
+        AddressInfo {
+            index: 0,
+            address: Address::from_str("bcrt1q0ue3s5y935tw7v3gmnh36c5zzsaw4n9c9smq79")
+                .unwrap()
+                .assume_checked(),
+            keychain: KeychainKind::Internal,
+        }
+    );
+}
+
+#[test]
+fn test_reveal_addresses() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let mut wallet = Wallet::new_no_persist(desc, change_desc, Network::Signet).unwrap();
+    let keychain = KeychainKind::External;
+
+    let last_revealed_addr = wallet
+        .reveal_addresses_to(keychain, 9)
+        .unwrap()
+        .last()
+        .unwrap();
+    assert_eq!(wallet.derivation_index(keychain), Some(9));
+
+    let unused_addrs = wallet.list_unused_addresses(keychain).collect::<Vec<_>>();
+    assert_eq!(unused_addrs.len(), 10);
+    assert_eq!(unused_addrs.last().unwrap(), &last_revealed_addr);
+
+    // revealing to an already revealed index returns nothing
+    let mut already_revealed = wallet.reveal_addresses_to(keychain, 9).unwrap();
+    assert!(already_revealed.next().is_none());
+}
+
+#[test]
+fn test_get_address_no_reuse() {
+    use bdk_wallet::descriptor::template::Bip84;
+    use std::collections::HashSet;
+
+    let key = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy").unwrap();
+    let mut wallet = Wallet::new_no_persist(
+        Bip84(key, KeychainKind::External),
+        Bip84(key, KeychainKind::Internal),
+        Network::Regtest,
+    )
+    .unwrap();
+
+    let mut used_set = HashSet::new();
+
+    (0..3).for_each(|_| {
+        let external_addr = wallet
+            .reveal_next_address(KeychainKind::External)
+            .unwrap()
+            .address;
+        assert!(used_set.insert(external_addr));
+
+        let internal_addr = wallet
+            .reveal_next_address(KeychainKind::Internal)
+            .unwrap()
+            .address;
+        assert!(used_set.insert(internal_addr));
+    });
+}
+
+#[test]
+fn test_taproot_remove_tapfields_after_finalize_sign_option() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree());
+
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+    let finalized = wallet.sign(&mut psbt, SignOptions::default()).unwrap();
+    assert!(finalized);
+
+    // removes tap_* from inputs
+    for input in &psbt.inputs {
+        assert!(input.tap_key_sig.is_none());
+        assert!(input.tap_script_sigs.is_empty());
+        assert!(input.tap_scripts.is_empty());
+        assert!(input.tap_key_origins.is_empty());
+        assert!(input.tap_internal_key.is_none());
+        assert!(input.tap_merkle_root.is_none());
+    }
+    // removes key origins from outputs
+    for output in &psbt.outputs {
+        assert!(output.tap_key_origins.is_empty());
+    }
+}
+
+#[test]
+fn test_taproot_psbt_populate_tap_key_origins() {
+    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
+    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
+    let addr = wallet.reveal_next_address(KeychainKind::External).unwrap();
+
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
+
+    assert_eq!(
+        psbt.inputs[0]
+            .tap_key_origins
+            .clone()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec![(
+            from_str!("0841db1dbaf949dbbda893e01a18f2cca9179cf8ea2d8e667857690502b06483"),
+            (vec![], (from_str!("f6a5cb8b"), from_str!("m/0/0")))
+        )],
+        "Wrong input tap_key_origins"
+    );
+    assert_eq!(
+        psbt.outputs[0]
+            .tap_key_origins
+            .clone()
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec![(
+            from_str!("9187c1e80002d19ddde9c5c7f5394e9a063cee8695867b58815af0562695ca21"),
+            (vec![], (from_str!("f6a5cb8b"), from_str!("m/0/1")))
+        )],
+        "Wrong output tap_key_origins"
+    );
+}
+
+#[test]
+fn test_taproot_psbt_populate_tap_key_origins_repeated_key() {
+    let (mut wallet, _) =
+        get_funded_wallet_with_change(get_test_tr_repeated_key(), get_test_tr_single_sig());
+    let addr = wallet.reveal_next_address(KeychainKind::External).unwrap();
+
+    let path = vec![("rn4nre9c".to_string(), vec![0])]
+        .into_iter()
+        .collect();
+
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .policy_path(path, KeychainKind::External);
+    let psbt = builder.finish().unwrap();
+
+    let mut input_key_origins = psbt.inputs[0]
+        .tap_key_origins
+        .clone()
+        .into_iter()
+        .collect::<Vec<_>>();
+    input_key_origins.sort();
+
+    assert_eq!(
+        input_key_origins,
+        vec![
+            (
+                from_str!("2b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3"),
+                (
+                    vec![
+                        from_str!(
+                            "858ad7a7d7f270e2c490c4d6ba00c499e46b18fdd59ea3c2c47d20347110271e"
+                        ),
+                        from_str!(
+                            "f6e927ad4492c051fe325894a4f5f14538333b55a35f099876be42009ec8f903"
+                        ),
+                    ],
+                    (FromStr::from_str("ece52657").unwrap(), vec![].into())
+                )
+            ),
+            (
+                from_str!("b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55"),
+                (
+                    vec![],
+                    (FromStr::from_str("871fd295").unwrap(), vec![].into())
+                )
+            )
+        ],
+        "Wrong input tap_key_origins"
+    );
+
+    let mut output_key_origins = psbt.outputs[0]
+        .tap_key_origins
+        .clone()
+        .into_iter()
+        .collect::<Vec<_>>();
+    output_key_origins.sort();
+
+    assert_eq!(
+        input_key_origins, output_key_origins,
+        "Wrong output tap_key_origins"
+    );
+}
+
+#[test]
+fn test_taproot_psbt_input_tap_tree() {
+    use bitcoin::hex::FromHex;
+    use bitcoin::taproot;
+
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree());
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let psbt = builder.finish().unwrap();
+
+    assert_eq!(
+        psbt.inputs[0].tap_merkle_root,
+        Some(
+            TapNodeHash::from_str(
+                "61f81509635053e52d9d1217545916167394490da2287aca4693606e43851986"
+            )
+            .unwrap()
+        ),
+    );
+    assert_eq!(
+        psbt.inputs[0].tap_scripts.clone().into_iter().collect::<Vec<_>>(),
+        vec![
+            (taproot::ControlBlock::decode(&Vec::<u8>::from_hex("c0b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55b7ef769a745e625ed4b9a4982a4dc08274c59187e73e6f07171108f455081cb2").unwrap()).unwrap(), (ScriptBuf::from_hex("208aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642ac").unwrap(), taproot::LeafVersion::TapScript)),
+            (taproot::ControlBlock::decode(&Vec::<u8>::from_hex("c0b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55b9a515f7be31a70186e3c5937ee4a70cc4b4e1efe876c1d38e408222ffc64834").unwrap()).unwrap(), (ScriptBuf::from_hex("2051494dc22e24a32fe9dcfbd7e85faf345fa1df296fb49d156e859ef345201295ac").unwrap(), taproot::LeafVersion::TapScript)),
+        ],
+    );
+    assert_eq!(
+        psbt.inputs[0].tap_internal_key,
+        Some(from_str!(
+            "b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55"
+        ))
+    );
+
+    // Since we are creating an output to the same address as the input, assert that the
+    // internal_key is the same
+    assert_eq!(
+        psbt.inputs[0].tap_internal_key,
+        psbt.outputs[0].tap_internal_key
+    );
+
+    let tap_tree: bitcoin::taproot::TapTree = serde_json::from_str(r#"[1,{"Script":["2051494dc22e24a32fe9dcfbd7e85faf345fa1df296fb49d156e859ef345201295ac",192]},1,{"Script":["208aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642ac",192]}]"#).unwrap();
+    assert_eq!(psbt.outputs[0].tap_tree, Some(tap_tree));
+}
+
+#[test]
+fn test_taproot_sign_missing_witness_utxo() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+    let witness_utxo = psbt.inputs[0].witness_utxo.take();
+
+    let result = wallet.sign(
+        &mut psbt,
+        SignOptions {
+            allow_all_sighashes: true,
+            ..Default::default()
+        },
+    );
+    assert_matches!(
+        result,
+        Err(SignerError::MissingWitnessUtxo),
+        "Signing should have failed with the correct error because the witness_utxo is missing"
+    );
+
+    // restore the witness_utxo
+    psbt.inputs[0].witness_utxo = witness_utxo;
+
+    let result = wallet.sign(
+        &mut psbt,
+        SignOptions {
+            allow_all_sighashes: true,
+            ..Default::default()
+        },
+    );
+
+    assert_matches!(
+        result,
+        Ok(true),
+        "Should finalize the input since we can produce signatures"
+    );
+}
+
+#[test]
+fn test_taproot_sign_using_non_witness_utxo() {
+    let (mut wallet, prev_txid) = get_funded_wallet(get_test_tr_single_sig());
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    psbt.inputs[0].witness_utxo = None;
+    psbt.inputs[0].non_witness_utxo =
+        Some(wallet.get_tx(prev_txid).unwrap().tx_node.as_ref().clone());
+    assert!(
+        psbt.inputs[0].non_witness_utxo.is_some(),
+        "Previous tx should be present in the database"
+    );
+
+    let result = wallet.sign(&mut psbt, Default::default());
+    assert!(result.is_ok(), "Signing should have worked");
+    assert!(
+        result.unwrap(),
+        "Should finalize the input since we can produce signatures"
+    );
+}
+
+#[test]
+fn test_taproot_foreign_utxo() {
+    let (mut wallet1, _) = get_funded_wallet_wpkh();
+    let (wallet2, _) = get_funded_wallet(get_test_tr_single_sig());
+
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let utxo = wallet2.list_unspent().next().unwrap();
+    let psbt_input = wallet2.get_psbt_input(utxo.clone(), None, false).unwrap();
+    let foreign_utxo_satisfaction = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
+        .unwrap();
+
+    assert!(
+        psbt_input.non_witness_utxo.is_none(),
+        "`non_witness_utxo` should never be populated for taproot"
+    );
+
+    let mut builder = wallet1.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
+        .add_foreign_utxo(utxo.outpoint, psbt_input, foreign_utxo_satisfaction)
+        .unwrap();
+    let psbt = builder.finish().unwrap();
+    let sent_received =
+        wallet1.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
+    wallet1.insert_txout(utxo.outpoint, utxo.txout);
+    let fee = check_fee!(wallet1, psbt);
+
+    assert_eq!(
+        sent_received.0 - sent_received.1,
+        Amount::from_sat(10_000) + fee.unwrap_or(Amount::ZERO),
+        "we should have only net spent ~10_000"
+    );
+
+    assert!(
+        psbt.unsigned_tx
+            .input
+            .iter()
+            .any(|input| input.previous_output == utxo.outpoint),
+        "foreign_utxo should be in there"
+    );
+}
+
+fn test_spend_from_wallet(mut wallet: Wallet) {
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+
+    assert!(
+        wallet.sign(&mut psbt, Default::default()).unwrap(),
+        "Unable to finalize tx"
+    );
+}
+
+//     #[test]
+//     fn test_taproot_key_spend() {
+//         let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
+//         test_spend_from_wallet(wallet);
+
+//         let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
+//         test_spend_from_wallet(wallet);
+//     }
+
+#[test]
+fn test_taproot_no_key_spend() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+
     let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(45_000))
-        .include_output_redeem_witness_script();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
     let mut psbt = builder.finish().unwrap();
 
-    // add another input to the psbt that is at least passable.
-    let dud_input = bitcoin::psbt::Input {
-        witness_utxo: Some(TxOut {
-            value: Amount::from_sat(100_000),
-            script_pubkey: miniscript::Descriptor::<bitcoin::PublicKey>::from_str(
-                "wpkh(025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357)",
+    assert!(
+        wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    sign_with_tap_internal_key: false,
+                    ..Default::default()
+                },
             )
-            .unwrap()
-            .script_pubkey(),
-        }),
-        ..Default::default()
-    };
+            .unwrap(),
+        "Unable to finalize tx"
+    );
+
+    assert!(psbt.inputs.iter().all(|i| i.tap_key_sig.is_none()));
+}
+
+#[test]
+fn test_taproot_script_spend() {
+    let (wallet, _) = get_funded_wallet(get_test_tr_with_taptree());
+    test_spend_from_wallet(wallet);
+
+    let (wallet, _) = get_funded_wallet(get_test_tr_with_taptree_xprv());
+    test_spend_from_wallet(wallet);
+}
+
+#[test]
+fn test_taproot_script_spend_sign_all_leaves() {
+    use bdk_wallet::signer::TapLeavesOptions;
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
 
-    psbt.inputs.push(dud_input);
-    psbt.unsigned_tx.input.push(bitcoin::TxIn::default());
-    let is_final = wallet
-        .sign(
-            &mut psbt,
-            SignOptions {
-                trust_witness_utxo: true,
-                ..Default::default()
-            },
-        )
-        .unwrap();
     assert!(
-        !is_final,
-        "shouldn't be final since we can't sign one of the inputs"
+        wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    tap_leaves_options: TapLeavesOptions::All,
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+        "Unable to finalize tx"
     );
-    assert!(
-        psbt.inputs[0].final_script_witness.is_some(),
-        "should finalized input it signed"
-    )
+
+    assert!(psbt
+        .inputs
+        .iter()
+        .all(|i| i.tap_script_sigs.len() == i.tap_scripts.len()));
 }
 
 #[test]
-fn test_remove_partial_sigs_after_finalize_sign_option() {
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+fn test_taproot_script_spend_sign_include_some_leaves() {
+    use bdk_wallet::signer::TapLeavesOptions;
+    use bitcoin::taproot::TapLeafHash;
 
-    for remove_partial_sigs in &[true, false] {
-        let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-        let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
-        let mut psbt = builder.finish().unwrap();
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
 
-        assert!(wallet
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+    let mut script_leaves: Vec<_> = psbt.inputs[0]
+        .tap_scripts
+        .clone()
+        .values()
+        .map(|(script, version)| TapLeafHash::from_script(script, *version))
+        .collect();
+    let included_script_leaves = vec![script_leaves.pop().unwrap()];
+    let excluded_script_leaves = script_leaves;
+
+    assert!(
+        wallet
             .sign(
                 &mut psbt,
                 SignOptions {
-                    remove_partial_sigs: *remove_partial_sigs,
+                    tap_leaves_options: TapLeavesOptions::Include(included_script_leaves.clone()),
                     ..Default::default()
                 },
             )
-            .unwrap());
+            .unwrap(),
+        "Unable to finalize tx"
+    );
 
-        psbt.inputs.iter().for_each(|input| {
-            if *remove_partial_sigs {
-                assert!(input.partial_sigs.is_empty())
-            } else {
-                assert!(!input.partial_sigs.is_empty())
-            }
-        });
-    }
+    assert!(psbt.inputs[0]
+        .tap_script_sigs
+        .iter()
+        .all(|s| included_script_leaves.contains(&s.0 .1)
+            && !excluded_script_leaves.contains(&s.0 .1)));
 }
 
 #[test]
-fn test_try_finalize_sign_option() {
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+fn test_taproot_script_spend_sign_exclude_some_leaves() {
+    use bdk_wallet::signer::TapLeavesOptions;
+    use bitcoin::taproot::TapLeafHash;
 
-    for try_finalize in &[true, false] {
-        let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-        let mut builder = wallet.build_tx();
-        builder.drain_to(addr.script_pubkey()).drain_wallet();
-        let mut psbt = builder.finish().unwrap();
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
 
-        let finalized = wallet
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+    let mut script_leaves: Vec<_> = psbt.inputs[0]
+        .tap_scripts
+        .clone()
+        .values()
+        .map(|(script, version)| TapLeafHash::from_script(script, *version))
+        .collect();
+    let included_script_leaves = [script_leaves.pop().unwrap()];
+    let excluded_script_leaves = script_leaves;
+
+    assert!(
+        wallet
             .sign(
                 &mut psbt,
                 SignOptions {
-                    try_finalize: *try_finalize,
+                    tap_leaves_options: TapLeavesOptions::Exclude(excluded_script_leaves.clone()),
                     ..Default::default()
                 },
             )
-            .unwrap();
+            .unwrap(),
+        "Unable to finalize tx"
+    );
 
-        psbt.inputs.iter().for_each(|input| {
-            if *try_finalize {
-                assert!(finalized);
-                assert!(input.final_script_sig.is_some());
-                assert!(input.final_script_witness.is_some());
-            } else {
-                assert!(!finalized);
-                assert!(input.final_script_sig.is_none());
-                assert!(input.final_script_witness.is_none());
-            }
-        });
-    }
+    assert!(psbt.inputs[0]
+        .tap_script_sigs
+        .iter()
+        .all(|s| included_script_leaves.contains(&s.0 .1)
+            && !excluded_script_leaves.contains(&s.0 .1)));
 }
 
 #[test]
-fn test_sign_nonstandard_sighash() {
-    let sighash = EcdsaSighashType::NonePlusAnyoneCanPay;
+fn test_taproot_script_spend_sign_no_leaves() {
+    use bdk_wallet::signer::TapLeavesOptions;
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+
+    wallet
+        .sign(
+            &mut psbt,
+            SignOptions {
+                tap_leaves_options: TapLeavesOptions::None,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    assert!(psbt.inputs.iter().all(|i| i.tap_script_sigs.is_empty()));
+}
+
+#[test]
+fn test_taproot_sign_derive_index_from_psbt() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
+
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    let mut psbt = builder.finish().unwrap();
+
+    // re-create the wallet with an empty db
+    let wallet_empty = Wallet::new_no_persist(
+        get_test_tr_single_sig_xprv(),
+        get_test_tr_single_sig(),
+        Network::Regtest,
+    )
+    .unwrap();
+
+    // signing with an empty db means that we will only look at the psbt to infer the
+    // derivation index
+    assert!(
+        wallet_empty.sign(&mut psbt, Default::default()).unwrap(),
+        "Unable to finalize tx"
+    );
+}
+
+#[test]
+fn test_taproot_sign_explicit_sighash_all() {
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .sighash(TapSighashType::All.into())
+        .drain_wallet();
+    let mut psbt = builder.finish().unwrap();
+
+    let result = wallet.sign(&mut psbt, Default::default());
+    assert!(
+        result.is_ok(),
+        "Signing should work because SIGHASH_ALL is safe"
+    )
+}
+
+#[test]
+fn test_taproot_sign_non_default_sighash() {
+    let sighash = TapSighashType::NonePlusAnyoneCanPay;
 
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
     let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
@@ -2803,6 +5196,8 @@ fn test_sign_nonstandard_sighash() {
         .drain_wallet();
     let mut psbt = builder.finish().unwrap();
 
+    let witness_utxo = psbt.inputs[0].witness_utxo.take();
+
     let result = wallet.sign(&mut psbt, Default::default());
     assert!(
         result.is_err(),
@@ -2822,6 +5217,27 @@ fn test_sign_nonstandard_sighash() {
             ..Default::default()
         },
     );
+    assert!(
+        result.is_err(),
+        "Signing should have failed because the witness_utxo is missing"
+    );
+    assert_matches!(
+        result,
+        Err(SignerError::MissingWitnessUtxo),
+        "Signing failed with the wrong error type"
+    );
+
+    // restore the witness_utxo
+    psbt.inputs[0].witness_utxo = witness_utxo;
+
+    let result = wallet.sign(
+        &mut psbt,
+        SignOptions {
+            allow_all_sighashes: true,
+            ..Default::default()
+        },
+    );
+
     assert!(result.is_ok(), "Signing should have worked");
     assert!(
         result.unwrap(),
@@ -2831,1204 +5247,2055 @@ fn test_sign_nonstandard_sighash() {
     let extracted = psbt.extract_tx().expect("failed to extract tx");
     assert_eq!(
         *extracted.input[0].witness.to_vec()[0].last().unwrap(),
-        sighash.to_u32() as u8,
+        sighash as u8,
         "The signature should have been made with the right sighash"
     );
 }
 
 #[test]
-fn test_unused_address() {
-    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
-    let change_desc = get_test_wpkh();
-    let mut wallet = Wallet::new_no_persist(desc, change_desc, Network::Testnet).unwrap();
+fn test_spend_coinbase() {
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let mut wallet = Wallet::new_no_persist(desc, change_desc, Network::Regtest).unwrap();
 
-    // `list_unused_addresses` should be empty if we haven't revealed any
-    assert!(wallet
-        .list_unused_addresses(KeychainKind::External)
-        .next()
-        .is_none());
+    let confirmation_height = 5;
+    wallet
+        .insert_checkpoint(BlockId {
+            height: confirmation_height,
+            hash: BlockHash::all_zeros(),
+        })
+        .unwrap();
+    let coinbase_tx = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            script_pubkey: wallet
+                .next_unused_address(KeychainKind::External)
+                .unwrap()
+                .script_pubkey(),
+            value: Amount::from_sat(25_000),
+        }],
+    };
+    wallet
+        .insert_tx(
+            coinbase_tx,
+            ConfirmationTime::Confirmed {
+                height: confirmation_height,
+                time: 30_000,
+            },
+        )
+        .unwrap();
+
+    let not_yet_mature_time = confirmation_height + COINBASE_MATURITY - 1;
+    let maturity_time = confirmation_height + COINBASE_MATURITY;
 
+    let balance = wallet.balance();
     assert_eq!(
-        wallet
-            .next_unused_address(KeychainKind::External)
-            .unwrap()
-            .to_string(),
-        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+        balance,
+        Balance {
+            immature: Amount::from_sat(25_000),
+            trusted_pending: Amount::ZERO,
+            untrusted_pending: Amount::ZERO,
+            confirmed: Amount::ZERO
+        }
+    );
+
+    // We try to create a transaction, only to notice that all
+    // our funds are unspendable
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), balance.immature / 2)
+        .current_height(confirmation_height);
+    assert!(matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds {
+                needed: _,
+                available: 0
+            }
+        ))
+    ));
+
+    // Still unspendable...
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), balance.immature / 2)
+        .current_height(not_yet_mature_time);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds {
+                needed: _,
+                available: 0
+            }
+        ))
     );
+
+    wallet
+        .insert_checkpoint(BlockId {
+            height: maturity_time,
+            hash: BlockHash::all_zeros(),
+        })
+        .unwrap();
+    let balance = wallet.balance();
     assert_eq!(
-        wallet
-            .list_unused_addresses(KeychainKind::External)
-            .next()
-            .unwrap()
-            .to_string(),
-        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+        balance,
+        Balance {
+            immature: Amount::ZERO,
+            trusted_pending: Amount::ZERO,
+            untrusted_pending: Amount::ZERO,
+            confirmed: Amount::from_sat(25_000)
+        }
     );
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), balance.confirmed / 2)
+        .current_height(maturity_time);
+    builder.finish().unwrap();
 }
 
 #[test]
-fn test_next_unused_address() {
-    let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
-    let change = get_test_wpkh();
-    let mut wallet = Wallet::new_no_persist(descriptor, change, Network::Testnet).unwrap();
-    assert_eq!(wallet.derivation_index(KeychainKind::External), None);
+fn test_allow_dust_limit() {
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_cltv());
 
-    assert_eq!(
-        wallet
-            .next_unused_address(KeychainKind::External)
-            .unwrap()
-            .to_string(),
-        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
-    );
-    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
-    // calling next_unused again gives same address
-    assert_eq!(
-        wallet
-            .next_unused_address(KeychainKind::External)
-            .unwrap()
-            .to_string(),
-        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+
+    let mut builder = wallet.build_tx();
+
+    builder.add_recipient(addr.script_pubkey(), Amount::ZERO);
+
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::OutputBelowDustLimit(0))
     );
-    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(0));
 
-    // test mark used / unused
-    assert!(wallet.mark_used(KeychainKind::External, 0));
-    let next_unused_addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    assert_eq!(next_unused_addr.index, 1);
+    let mut builder = wallet.build_tx();
 
-    assert!(wallet.unmark_used(KeychainKind::External, 0));
-    let next_unused_addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    assert_eq!(next_unused_addr.index, 0);
+    builder
+        .allow_dust(true)
+        .add_recipient(addr.script_pubkey(), Amount::ZERO);
 
-    // use the above address
-    receive_output_in_latest_block(&mut wallet, 25_000);
+    assert!(builder.finish().is_ok());
+}
 
-    assert_eq!(
-        wallet
-            .next_unused_address(KeychainKind::External)
-            .unwrap()
-            .to_string(),
-        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
-    );
-    assert_eq!(wallet.derivation_index(KeychainKind::External), Some(1));
+#[test]
+fn test_fee_rate_sign_no_grinding_high_r() {
+    // Our goal is to obtain a transaction with a signature with high-R (71 bytes
+    // instead of 70). We then check that our fee rate and fee calculation is
+    // alright.
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let fee_rate = FeeRate::from_sat_per_vb_unchecked(1);
+    let mut builder = wallet.build_tx();
+    let mut data = PushBytesBuf::try_from(vec![0]).unwrap();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .fee_rate(fee_rate)
+        .add_data(&data);
+    let mut psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
+    let (op_return_vout, _) = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .enumerate()
+        .find(|(_n, i)| i.script_pubkey.is_op_return())
+        .unwrap();
 
-    // trying to mark index 0 unused should return false
-    assert!(!wallet.unmark_used(KeychainKind::External, 0));
+    let mut sig_len: usize = 0;
+    // We try to sign many different times until we find a longer signature (71 bytes)
+    while sig_len < 71 {
+        // Changing the OP_RETURN data will make the signature change (but not the fee, until
+        // data[0] is small enough)
+        data.as_mut_bytes()[0] += 1;
+        psbt.unsigned_tx.output[op_return_vout].script_pubkey = ScriptBuf::new_op_return(&data);
+        // Clearing the previous signature
+        psbt.inputs[0].partial_sigs.clear();
+        // Signing
+        wallet
+            .sign(
+                &mut psbt,
+                SignOptions {
+                    remove_partial_sigs: false,
+                    try_finalize: false,
+                    allow_grinding: false,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        // We only have one key in the partial_sigs map, this is a trick to retrieve it
+        let key = psbt.inputs[0].partial_sigs.keys().next().unwrap();
+        sig_len = psbt.inputs[0].partial_sigs[key].sig.serialize_der().len();
+    }
+    // Actually finalizing the transaction...
+    wallet
+        .sign(
+            &mut psbt,
+            SignOptions {
+                remove_partial_sigs: false,
+                allow_grinding: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    // ...and checking that everything is fine
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate);
 }
 
 #[test]
-fn test_peek_address_at_index() {
-    let desc = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
-    let change_desc = get_test_wpkh();
-    let mut wallet = Wallet::new_no_persist(desc, change_desc, Network::Testnet).unwrap();
-
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 1).to_string(),
-        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
-    );
-
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 0).to_string(),
-        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
-    );
-
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 2).to_string(),
-        "tb1qzntf2mqex4ehwkjlfdyy3ewdlk08qkvkvrz7x2"
-    );
+fn test_fee_rate_sign_grinding_low_r() {
+    // Our goal is to obtain a transaction with a signature with low-R (70 bytes)
+    // by setting the `allow_grinding` signing option as true.
+    // We then check that our fee rate and fee calculation is alright and that our
+    // signature is 70 bytes.
+    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let fee_rate = FeeRate::from_sat_per_vb_unchecked(1);
+    let mut builder = wallet.build_tx();
+    builder
+        .drain_to(addr.script_pubkey())
+        .drain_wallet()
+        .fee_rate(fee_rate);
+    let mut psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt);
 
-    // current new address is not affected
-    assert_eq!(
-        wallet
-            .reveal_next_address(KeychainKind::External)
-            .unwrap()
-            .to_string(),
-        "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
-    );
+    wallet
+        .sign(
+            &mut psbt,
+            SignOptions {
+                remove_partial_sigs: false,
+                allow_grinding: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
 
-    assert_eq!(
-        wallet
-            .reveal_next_address(KeychainKind::External)
-            .unwrap()
-            .to_string(),
-        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
-    );
+    let key = psbt.inputs[0].partial_sigs.keys().next().unwrap();
+    let sig_len = psbt.inputs[0].partial_sigs[key].sig.serialize_der().len();
+    assert_eq!(sig_len, 70);
+    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate);
 }
 
 #[test]
-fn test_peek_address_at_index_not_derivable() {
-    let wallet = Wallet::new_no_persist("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/1)",
-                                 get_test_wpkh(), Network::Testnet).unwrap();
-
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 1).to_string(),
-        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
-    );
+fn test_taproot_load_descriptor_duplicated_keys() {
+    // Added after issue https://github.com/bitcoindevkit/bdk/issues/760
+    //
+    // Having the same key in multiple taproot leaves is safe and should be accepted by BDK
 
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 0).to_string(),
-        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
-    );
+    let (wallet, _) = get_funded_wallet(get_test_tr_dup_keys());
+    let addr = wallet.peek_address(KeychainKind::External, 0);
 
     assert_eq!(
-        wallet.peek_address(KeychainKind::External, 2).to_string(),
-        "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+        addr.to_string(),
+        "bcrt1pvysh4nmh85ysrkpwtrr8q8gdadhgdejpy6f9v424a8v9htjxjhyqw9c5s5"
     );
 }
 
 #[test]
-fn test_returns_index_and_address() {
-    let mut wallet = Wallet::new_no_persist("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)",
-                                 get_test_wpkh(), Network::Testnet).unwrap();
-
-    // new index 0
-    assert_eq!(
-        wallet.reveal_next_address(KeychainKind::External).unwrap(),
-        AddressInfo {
-            index: 0,
-            address: Address::from_str("tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a")
+/// The wallet should re-use previously allocated change addresses when the tx using them is cancelled
+fn test_tx_cancellation() {
+    macro_rules! new_tx {
+        ($wallet:expr) => {{
+            let addr = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")
                 .unwrap()
-                .assume_checked(),
-            keychain: KeychainKind::External,
-        }
-    );
+                .assume_checked();
+            let mut builder = $wallet.build_tx();
+            builder.add_recipient(addr.script_pubkey(), Amount::from_sat(10_000));
 
-    // new index 1
-    assert_eq!(
-        wallet.reveal_next_address(KeychainKind::External).unwrap(),
-        AddressInfo {
-            index: 1,
-            address: Address::from_str("tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7")
-                .unwrap()
-                .assume_checked(),
-            keychain: KeychainKind::External,
-        }
-    );
+            let psbt = builder.finish().unwrap();
 
-    // peek index 25
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 25),
-        AddressInfo {
-            index: 25,
-            address: Address::from_str("tb1qsp7qu0knx3sl6536dzs0703u2w2ag6ppl9d0c2")
-                .unwrap()
-                .assume_checked(),
-            keychain: KeychainKind::External,
-        }
-    );
+            psbt
+        }};
+    }
 
-    // new index 2
-    assert_eq!(
-        wallet.reveal_next_address(KeychainKind::External).unwrap(),
-        AddressInfo {
-            index: 2,
-            address: Address::from_str("tb1qzntf2mqex4ehwkjlfdyy3ewdlk08qkvkvrz7x2")
-                .unwrap()
-                .assume_checked(),
-            keychain: KeychainKind::External,
-        }
-    );
-}
+    let (mut wallet, _) =
+        get_funded_wallet_with_change(get_test_wpkh(), get_test_tr_single_sig_xprv());
 
-#[test]
-fn test_sending_to_bip350_bech32m_address() {
-    let (mut wallet, _) = get_funded_wallet_wpkh();
-    let addr = Address::from_str("tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c")
-        .unwrap()
-        .assume_checked();
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(45_000));
-    builder.finish().unwrap();
-}
+    let psbt1 = new_tx!(wallet);
+    let change_derivation_1 = psbt1
+        .unsigned_tx
+        .output
+        .iter()
+        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
+        .unwrap();
+    assert_eq!(change_derivation_1, (KeychainKind::Internal, 0));
 
-#[test]
-fn test_get_address() {
-    use bdk_wallet::descriptor::template::Bip84;
-    let key = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy").unwrap();
-    let wallet = Wallet::new_no_persist(
-        Bip84(key, KeychainKind::External),
-        Bip84(key, KeychainKind::Internal),
-        Network::Regtest,
-    )
-    .unwrap();
+    let psbt2 = new_tx!(wallet);
 
-    assert_eq!(
-        wallet.peek_address(KeychainKind::External, 0),
-        AddressInfo {
-            index: 0,
-            address: Address::from_str("bcrt1qrhgaqu0zvf5q2d0gwwz04w0dh0cuehhqvzpp4w")
-                .unwrap()
-                .assume_checked(),
-            keychain: KeychainKind::External,
-        }
-    );
+    let change_derivation_2 = psbt2
+        .unsigned_tx
+        .output
+        .iter()
+        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
+        .unwrap();
+    assert_eq!(change_derivation_2, (KeychainKind::Internal, 1));
 
-    assert_eq!(
-        wallet.peek_address(KeychainKind::Internal, 0),
-        AddressInfo {
-            index: 0,
-            address: Address::from_str("bcrt1q0ue3s5y935tw7v3gmnh36c5zzsaw4n9c9smq79")
-                .unwrap()
-                .assume_checked(),
-            keychain: KeychainKind::Internal,
-        }
-    );
-}
+    wallet.cancel_tx(&psbt1.extract_tx().expect("failed to extract tx"));
 
-#[test]
-fn test_reveal_addresses() {
-    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
-    let mut wallet = Wallet::new_no_persist(desc, change_desc, Network::Signet).unwrap();
-    let keychain = KeychainKind::External;
+    let psbt3 = new_tx!(wallet);
+    let change_derivation_3 = psbt3
+        .unsigned_tx
+        .output
+        .iter()
+        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
+        .unwrap();
+    assert_eq!(change_derivation_3, (KeychainKind::Internal, 0));
 
-    let last_revealed_addr = wallet
-        .reveal_addresses_to(keychain, 9)
-        .unwrap()
-        .last()
+    let psbt3 = new_tx!(wallet);
+    let change_derivation_3 = psbt3
+        .unsigned_tx
+        .output
+        .iter()
+        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
         .unwrap();
-    assert_eq!(wallet.derivation_index(keychain), Some(9));
+    assert_eq!(change_derivation_3, (KeychainKind::Internal, 2));
 
-    let unused_addrs = wallet.list_unused_addresses(keychain).collect::<Vec<_>>();
-    assert_eq!(unused_addrs.len(), 10);
-    assert_eq!(unused_addrs.last().unwrap(), &last_revealed_addr);
+    wallet.cancel_tx(&psbt3.extract_tx().expect("failed to extract tx"));
+
+    let psbt3 = new_tx!(wallet);
+    let change_derivation_4 = psbt3
+        .unsigned_tx
+        .output
+        .iter()
+        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
+        .unwrap();
+    assert_eq!(change_derivation_4, (KeychainKind::Internal, 2));
+}
 
-    // revealing to an already revealed index returns nothing
-    let mut already_revealed = wallet.reveal_addresses_to(keychain, 9).unwrap();
-    assert!(already_revealed.next().is_none());
+#[test]
+fn test_thread_safety() {
+    fn thread_safe<T: Send + Sync>() {}
+    thread_safe::<Wallet>(); // compiles only if true
 }
 
 #[test]
-fn test_get_address_no_reuse() {
-    use bdk_wallet::descriptor::template::Bip84;
-    use std::collections::HashSet;
+fn test_wallet_events() {
+    let (desc, change_desc) = get_test_wpkh_with_change_desc();
+    let mut wallet = Wallet::new_no_persist(desc, change_desc, Network::Regtest).unwrap();
+    let genesis = wallet.local_chain().tip().block_id();
+    let receive_spk = wallet
+        .reveal_next_address(KeychainKind::External)
+        .unwrap()
+        .script_pubkey();
+    let foreign_spk = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
+        .unwrap()
+        .assume_checked()
+        .script_pubkey();
+    let new_tx = |previous_output, script_pubkey: &ScriptBuf, value| Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output,
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            script_pubkey: script_pubkey.clone(),
+            value: Amount::from_sat(value),
+        }],
+    };
 
-    let key = bitcoin::bip32::Xpriv::from_str("tprv8ZgxMBicQKsPcx5nBGsR63Pe8KnRUqmbJNENAfGftF3yuXoMMoVJJcYeUw5eVkm9WBPjWYt6HMWYJNesB5HaNVBaFc1M6dRjWSYnmewUMYy").unwrap();
-    let mut wallet = Wallet::new_no_persist(
-        Bip84(key, KeychainKind::External),
-        Bip84(key, KeychainKind::Internal),
-        Network::Regtest,
-    )
-    .unwrap();
+    // receive an unconfirmed transaction
+    let tx0 = new_tx(OutPoint::new(Txid::all_zeros(), 0), &receive_spk, 50_000);
+    let events = wallet.apply_unconfirmed_txs_events([(&tx0, 100)]);
+    assert_eq!(
+        events,
+        vec![
+            WalletEvent::TxReceived {
+                txid: tx0.txid(),
+                confirmation_time: ConfirmationTime::Unconfirmed { last_seen: 100 },
+            },
+            WalletEvent::BalanceChanged {
+                old: Balance::default(),
+                new: Balance {
+                    untrusted_pending: Amount::from_sat(50_000),
+                    ..Default::default()
+                },
+            },
+        ]
+    );
+    // applying it again doesn't change anything
+    assert!(wallet
+        .apply_unconfirmed_txs_events([(&tx0, 100)])
+        .is_empty());
+
+    // it gets confirmed
+    let block = Block {
+        header: block::Header {
+            version: block::Version::ONE,
+            prev_blockhash: genesis.hash,
+            merkle_root: Hash::all_zeros(),
+            time: 1_000,
+            bits: bitcoin::CompactTarget::from_consensus(0),
+            nonce: 0,
+        },
+        txdata: vec![tx0.clone()],
+    };
+    let events = wallet.apply_block_events(&block, 1).unwrap();
+    assert_eq!(
+        events,
+        vec![
+            WalletEvent::TxConfirmed {
+                txid: tx0.txid(),
+                confirmation_time: ConfirmationTime::Confirmed {
+                    height: 1,
+                    time: 1_000,
+                },
+                previous: Some(ConfirmationTime::Unconfirmed { last_seen: 100 }),
+            },
+            WalletEvent::BalanceChanged {
+                old: Balance {
+                    untrusted_pending: Amount::from_sat(50_000),
+                    ..Default::default()
+                },
+                new: Balance {
+                    confirmed: Amount::from_sat(50_000),
+                    ..Default::default()
+                },
+            },
+        ]
+    );
 
-    let mut used_set = HashSet::new();
+    // the block is reorganized out of the chain
+    let update = Update {
+        chain: Some(
+            CheckPoint::from_block_ids([
+                genesis,
+                BlockId {
+                    height: 1,
+                    hash: BlockHash::all_zeros(),
+                },
+            ])
+            .unwrap(),
+        ),
+        ..Default::default()
+    };
+    let events = wallet.apply_update_events(update).unwrap();
+    assert_eq!(
+        events,
+        vec![
+            WalletEvent::TxReorgedOut {
+                txid: tx0.txid(),
+                previous: ConfirmationTime::Confirmed {
+                    height: 1,
+                    time: 1_000,
+                },
+            },
+            WalletEvent::BalanceChanged {
+                old: Balance {
+                    confirmed: Amount::from_sat(50_000),
+                    ..Default::default()
+                },
+                new: Balance {
+                    untrusted_pending: Amount::from_sat(50_000),
+                    ..Default::default()
+                },
+            },
+        ]
+    );
 
-    (0..3).for_each(|_| {
-        let external_addr = wallet
-            .reveal_next_address(KeychainKind::External)
-            .unwrap()
-            .address;
-        assert!(used_set.insert(external_addr));
+    // we spend the output, with a child sending back some change
+    let change_spk = wallet
+        .reveal_next_address(KeychainKind::Internal)
+        .unwrap()
+        .script_pubkey();
+    let tx1 = new_tx(OutPoint::new(tx0.txid(), 0), &change_spk, 40_000);
+    let tx2 = new_tx(OutPoint::new(tx1.txid(), 0), &foreign_spk, 30_000);
+    let events = wallet.apply_unconfirmed_txs_events([(&tx1, 200), (&tx2, 200)]);
+    assert!(events.contains(&WalletEvent::TxReceived {
+        txid: tx1.txid(),
+        confirmation_time: ConfirmationTime::Unconfirmed { last_seen: 200 },
+    }));
+    assert!(events.contains(&WalletEvent::TxReceived {
+        txid: tx2.txid(),
+        confirmation_time: ConfirmationTime::Unconfirmed { last_seen: 200 },
+    }));
+    assert!(events.contains(&WalletEvent::UtxoSpent {
+        outpoint: OutPoint::new(tx0.txid(), 0),
+        spent_by: tx1.txid(),
+    }));
+    assert_eq!(
+        events.last(),
+        Some(&WalletEvent::BalanceChanged {
+            old: Balance {
+                untrusted_pending: Amount::from_sat(50_000),
+                ..Default::default()
+            },
+            new: Balance::default(),
+        })
+    );
+    assert_eq!(events.len(), 4);
 
-        let internal_addr = wallet
-            .reveal_next_address(KeychainKind::Internal)
-            .unwrap()
-            .address;
-        assert!(used_set.insert(internal_addr));
-    });
+    // the parent is replaced, which evicts the child
+    let tx3 = new_tx(OutPoint::new(tx0.txid(), 0), &foreign_spk, 45_000);
+    let events = wallet.apply_unconfirmed_txs_events([(&tx3, 300)]);
+    assert_eq!(
+        events,
+        vec![
+            WalletEvent::TxReceived {
+                txid: tx3.txid(),
+                confirmation_time: ConfirmationTime::Unconfirmed { last_seen: 300 },
+            },
+            WalletEvent::TxReplaced {
+                txid: tx1.txid(),
+                replaced_by: vec![tx3.txid()],
+            },
+            WalletEvent::TxEvicted { txid: tx2.txid() },
+        ]
+    );
 }
 
 #[test]
-fn test_taproot_remove_tapfields_after_finalize_sign_option() {
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree());
+fn test_utxo_locks() {
+    let (mut wallet, txid) = get_funded_wallet_wpkh();
+    let utxo = OutPoint::new(txid, 0);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
 
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    // a locked utxo isn't selected
+    wallet.lock_utxo(utxo, None);
+    assert!(wallet.is_utxo_locked(utxo));
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
-    let finalized = wallet.sign(&mut psbt, SignOptions::default()).unwrap();
-    assert!(finalized);
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        ))
+    );
 
-    // removes tap_* from inputs
-    for input in &psbt.inputs {
-        assert!(input.tap_key_sig.is_none());
-        assert!(input.tap_script_sigs.is_empty());
-        assert!(input.tap_scripts.is_empty());
-        assert!(input.tap_key_origins.is_empty());
-        assert!(input.tap_internal_key.is_none());
-        assert!(input.tap_merkle_root.is_none());
-    }
-    // removes key origins from outputs
-    for output in &psbt.outputs {
-        assert!(output.tap_key_origins.is_empty());
-    }
+    // unless it's added manually
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .add_utxo(utxo)
+        .unwrap();
+    assert!(builder.finish().is_ok());
+
+    // the lock expires when the chain tip reaches the expiry height
+    let tip_height = wallet.latest_checkpoint().height();
+    wallet.lock_utxo(utxo, Some(tip_height + 1));
+    assert!(wallet.is_utxo_locked(utxo));
+    wallet
+        .insert_checkpoint(BlockId {
+            height: tip_height + 1,
+            hash: BlockHash::all_zeros(),
+        })
+        .unwrap();
+    assert!(!wallet.is_utxo_locked(utxo));
+    assert_eq!(wallet.locked_utxos().count(), 0);
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    assert!(builder.finish().is_ok());
 }
 
 #[test]
-fn test_taproot_psbt_populate_tap_key_origins() {
-    let (desc, change_desc) = get_test_tr_single_sig_xprv_with_change_desc();
-    let (mut wallet, _) = get_funded_wallet_with_change(desc, change_desc);
-    let addr = wallet.reveal_next_address(KeychainKind::External).unwrap();
+fn test_lock_selected_utxos() {
+    let (mut wallet, txid) = get_funded_wallet_wpkh();
+    let utxo = OutPoint::new(txid, 0);
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
 
     let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .lock_selected_utxos(None);
     let psbt = builder.finish().unwrap();
-
+    assert!(wallet.is_utxo_locked(utxo));
     assert_eq!(
-        psbt.inputs[0]
-            .tap_key_origins
-            .clone()
-            .into_iter()
-            .collect::<Vec<_>>(),
-        vec![(
-            from_str!("0841db1dbaf949dbbda893e01a18f2cca9179cf8ea2d8e667857690502b06483"),
-            (vec![], (from_str!("f6a5cb8b"), from_str!("m/0/0")))
-        )],
-        "Wrong input tap_key_origins"
+        wallet.staged().utxo_locks.get(&utxo),
+        Some(&Some(UtxoLock {
+            expiry_height: None
+        }))
     );
-    assert_eq!(
-        psbt.outputs[0]
-            .tap_key_origins
-            .clone()
-            .into_iter()
-            .collect::<Vec<_>>(),
-        vec![(
-            from_str!("9187c1e80002d19ddde9c5c7f5394e9a063cee8695867b58815af0562695ca21"),
-            (vec![], (from_str!("f6a5cb8b"), from_str!("m/0/1")))
-        )],
-        "Wrong output tap_key_origins"
+
+    // a second transaction can't spend the same utxo
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        ))
     );
+
+    // the lock is released once the transaction is added to the wallet
+    wallet
+        .insert_tx(
+            psbt.unsigned_tx,
+            ConfirmationTime::Unconfirmed { last_seen: 0 },
+        )
+        .unwrap();
+    assert!(!wallet.is_utxo_locked(utxo));
+    assert_eq!(wallet.staged().utxo_locks.get(&utxo), Some(&None));
 }
 
 #[test]
-fn test_taproot_psbt_populate_tap_key_origins_repeated_key() {
-    let (mut wallet, _) =
-        get_funded_wallet_with_change(get_test_tr_repeated_key(), get_test_tr_single_sig());
-    let addr = wallet.reveal_next_address(KeychainKind::External).unwrap();
-
-    let path = vec![("rn4nre9c".to_string(), vec![0])]
-        .into_iter()
-        .collect();
+fn test_lock_selected_utxos_foreign_utxo() {
+    let (mut wallet1, txid) = get_funded_wallet_wpkh();
+    let (wallet2, _) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let foreign_utxo = wallet2.list_unspent().next().unwrap();
+    let foreign_utxo_satisfaction = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
+        .unwrap();
+    let psbt_input = psbt::Input {
+        witness_utxo: Some(foreign_utxo.txout.clone()),
+        ..Default::default()
+    };
 
-    let mut builder = wallet.build_tx();
+    let mut builder = wallet1.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .policy_path(path, KeychainKind::External);
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
+        .only_witness_utxo()
+        .add_foreign_utxo(foreign_utxo.outpoint, psbt_input, foreign_utxo_satisfaction)
+        .unwrap()
+        .lock_selected_utxos(None);
     let psbt = builder.finish().unwrap();
+    assert!(psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .any(|txin| txin.previous_output == foreign_utxo.outpoint));
+
+    // only the utxo of the wallet is locked
+    let utxo = OutPoint::new(txid, 0);
+    assert!(wallet1.is_utxo_locked(utxo));
+    assert!(!wallet1.is_utxo_locked(foreign_utxo.outpoint));
+    assert!(wallet1.staged().utxo_locks.contains_key(&utxo));
+    assert!(!wallet1
+        .staged()
+        .utxo_locks
+        .contains_key(&foreign_utxo.outpoint));
+}
+
+/// Build a payjoin between a funded sender and receiver, returning the sender, the original PSBT
+/// paying 10_000 sats to `payee`, and the receiver's proposal contributing one of its inputs.
+fn payjoin_proposal() -> (Wallet, psbt::Psbt, ScriptBuf, psbt::Psbt) {
+    let (mut sender, _) = get_funded_wallet_wpkh();
+    let (mut receiver, _) = get_funded_wallet_single("wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/0/*)");
+    let payee = receiver
+        .reveal_next_address(KeychainKind::External)
+        .unwrap()
+        .script_pubkey();
 
-    let mut input_key_origins = psbt.inputs[0]
-        .tap_key_origins
-        .clone()
-        .into_iter()
-        .collect::<Vec<_>>();
-    input_key_origins.sort();
+    let mut builder = sender.build_tx();
+    builder
+        .add_recipient(payee.clone(), Amount::from_sat(10_000))
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(2));
+    let mut original = builder.finish().unwrap();
+    assert!(sender.sign(&mut original, SignOptions::default()).unwrap());
 
-    assert_eq!(
-        input_key_origins,
-        vec![
-            (
-                from_str!("2b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3"),
-                (
-                    vec![
-                        from_str!(
-                            "858ad7a7d7f270e2c490c4d6ba00c499e46b18fdd59ea3c2c47d20347110271e"
-                        ),
-                        from_str!(
-                            "f6e927ad4492c051fe325894a4f5f14538333b55a35f099876be42009ec8f903"
-                        ),
-                    ],
-                    (FromStr::from_str("ece52657").unwrap(), vec![].into())
-                )
-            ),
-            (
-                from_str!("b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55"),
-                (
-                    vec![],
-                    (FromStr::from_str("871fd295").unwrap(), vec![].into())
-                )
-            )
-        ],
-        "Wrong input tap_key_origins"
-    );
+    let receiver_utxo = receiver.list_unspent().next().unwrap().outpoint;
+    let mut builder = receiver.build_payjoin_proposal(&original).unwrap();
+    builder.add_utxo(receiver_utxo).unwrap();
+    let mut proposal = builder.finish().unwrap();
+    assert!(receiver
+        .finalize_payjoin_proposal(&mut proposal, SignOptions::default())
+        .unwrap());
 
-    let mut output_key_origins = psbt.outputs[0]
-        .tap_key_origins
-        .clone()
-        .into_iter()
-        .collect::<Vec<_>>();
-    output_key_origins.sort();
+    (sender, original, payee, proposal)
+}
+
+#[test]
+fn test_payjoin() {
+    let (sender, original, payee, proposal) = payjoin_proposal();
+    let original_tx = original.clone().extract_tx().unwrap();
+    assert_eq!(proposal.inputs.len(), original.inputs.len() + 1);
+    assert!(proposal
+        .outputs
+        .iter()
+        .all(|output| output.bip32_derivation.is_empty()));
+
+    let payjoin = sender
+        .sign_payjoin_proposal(
+            &original,
+            proposal,
+            &payee,
+            &PayjoinParams::default(),
+            SignOptions::default(),
+        )
+        .unwrap();
+    let payjoin_tx = payjoin.clone().extract_tx().unwrap();
 
+    // the receiver pays for its own input, the sender's balance changes the same way
+    assert!(payjoin.fee_amount().unwrap() > original.fee_amount().unwrap());
+    assert!(payjoin.fee_rate().unwrap() >= original.fee_rate().unwrap());
     assert_eq!(
-        input_key_origins, output_key_origins,
-        "Wrong output tap_key_origins"
+        sender.sent_and_received(&payjoin_tx),
+        sender.sent_and_received(&original_tx)
     );
+    let received = payjoin_tx
+        .output
+        .iter()
+        .find(|txout| txout.script_pubkey == payee)
+        .unwrap()
+        .value;
+    assert!(received > Amount::from_sat(10_000));
 }
 
 #[test]
-fn test_taproot_psbt_input_tap_tree() {
-    use bitcoin::hex::FromHex;
-    use bitcoin::taproot;
+fn test_payjoin_original_checks() {
+    let (mut sender, original, _, _) = payjoin_proposal();
+    let (mut receiver, _) = get_funded_wallet_single("wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/0/*)");
 
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    // the wallet can't receive a payjoin spending its own outputs
+    assert_matches!(
+        sender.build_payjoin_proposal(&original),
+        Err(OriginalPsbtError::InputOwned(_))
+    );
 
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let psbt = builder.finish().unwrap();
+    let mut no_inputs = original.clone();
+    no_inputs.unsigned_tx.input.clear();
+    no_inputs.inputs.clear();
+    assert_matches!(
+        receiver.build_payjoin_proposal(&no_inputs),
+        Err(OriginalPsbtError::NoInputs)
+    );
 
-    assert_eq!(
-        psbt.inputs[0].tap_merkle_root,
-        Some(
-            TapNodeHash::from_str(
-                "61f81509635053e52d9d1217545916167394490da2287aca4693606e43851986"
-            )
+    let mut unsigned = original.clone();
+    unsigned.inputs[0].final_script_sig = None;
+    unsigned.inputs[0].final_script_witness = None;
+    assert_matches!(
+        receiver.build_payjoin_proposal(&unsigned),
+        Err(OriginalPsbtError::NotFinalized(0))
+    );
+
+    let mut missing_utxo = original.clone();
+    missing_utxo.inputs[0].witness_utxo = None;
+    missing_utxo.inputs[0].non_witness_utxo = None;
+    assert_matches!(
+        receiver.build_payjoin_proposal(&missing_utxo),
+        Err(OriginalPsbtError::MissingUtxo(0))
+    );
+
+    let (mut other, _) = get_funded_wallet_single(get_test_tr_single_sig());
+    assert_matches!(
+        other.build_payjoin_proposal(&original),
+        Err(OriginalPsbtError::NoReceiverOutput)
+    );
+}
+
+#[test]
+fn test_payjoin_proposal_checks() {
+    let (sender, original, payee, proposal) = payjoin_proposal();
+    let sign = |proposal: &psbt::Psbt, params: &PayjoinParams| {
+        sender.sign_payjoin_proposal(
+            &original,
+            proposal.clone(),
+            &payee,
+            params,
+            SignOptions::default(),
+        )
+    };
+    let params = PayjoinParams::default();
+    let sender_input = proposal
+        .unsigned_tx
+        .input
+        .iter()
+        .position(|txin| txin.previous_output == original.unsigned_tx.input[0].previous_output)
+        .unwrap();
+    let receiver_input = 1 - sender_input;
+    let output_index = |psbt: &psbt::Psbt, is_payee: bool| {
+        psbt.unsigned_tx
+            .output
+            .iter()
+            .position(|txout| (txout.script_pubkey == payee) == is_payee)
             .unwrap()
-        ),
+    };
+    let change = output_index(&proposal, false);
+
+    let mut invalid = proposal.clone();
+    invalid.unsigned_tx.version = transaction::Version::TWO;
+    assert_matches!(sign(&invalid, &params), Err(ProposalError::VersionChanged));
+
+    let mut invalid = proposal.clone();
+    invalid.unsigned_tx.input[receiver_input].sequence = Sequence::MAX;
+    assert_matches!(sign(&invalid, &params), Err(ProposalError::MixedSequence));
+
+    let mut invalid = proposal.clone();
+    for txin in &mut invalid.unsigned_tx.input {
+        txin.sequence = Sequence::MAX;
+    }
+    assert_matches!(
+        sign(&invalid, &params),
+        Err(ProposalError::SequenceChanged(_))
     );
-    assert_eq!(
-        psbt.inputs[0].tap_scripts.clone().into_iter().collect::<Vec<_>>(),
-        vec![
-            (taproot::ControlBlock::decode(&Vec::<u8>::from_hex("c0b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55b7ef769a745e625ed4b9a4982a4dc08274c59187e73e6f07171108f455081cb2").unwrap()).unwrap(), (ScriptBuf::from_hex("208aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642ac").unwrap(), taproot::LeafVersion::TapScript)),
-            (taproot::ControlBlock::decode(&Vec::<u8>::from_hex("c0b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55b9a515f7be31a70186e3c5937ee4a70cc4b4e1efe876c1d38e408222ffc64834").unwrap()).unwrap(), (ScriptBuf::from_hex("2051494dc22e24a32fe9dcfbd7e85faf345fa1df296fb49d156e859ef345201295ac").unwrap(), taproot::LeafVersion::TapScript)),
-        ],
+
+    let mut invalid = proposal.clone();
+    invalid.inputs[sender_input].witness_utxo = original.inputs[0].witness_utxo.clone();
+    assert_matches!(
+        sign(&invalid, &params),
+        Err(ProposalError::UnexpectedInputFields(_))
     );
-    assert_eq!(
-        psbt.inputs[0].tap_internal_key,
-        Some(from_str!(
-            "b511bd5771e47ee27558b1765e87b541668304ec567721c7b880edc0a010da55"
-        ))
+
+    let mut invalid = proposal.clone();
+    invalid.inputs[receiver_input].final_script_sig = None;
+    invalid.inputs[receiver_input].final_script_witness = None;
+    assert_matches!(
+        sign(&invalid, &params),
+        Err(ProposalError::ReceiverInputNotFinalized(_))
     );
 
-    // Since we are creating an output to the same address as the input, assert that the
-    // internal_key is the same
-    assert_eq!(
-        psbt.inputs[0].tap_internal_key,
-        psbt.outputs[0].tap_internal_key
+    let mut invalid = proposal.clone();
+    invalid.unsigned_tx.input.remove(sender_input);
+    invalid.inputs.remove(sender_input);
+    assert_matches!(
+        sign(&invalid, &params),
+        Err(ProposalError::MissingSenderInput(_))
     );
 
-    let tap_tree: bitcoin::taproot::TapTree = serde_json::from_str(r#"[1,{"Script":["2051494dc22e24a32fe9dcfbd7e85faf345fa1df296fb49d156e859ef345201295ac",192]},1,{"Script":["208aee2b8120a5f157f1223f72b5e62b825831a27a9fdf427db7cc697494d4a642ac",192]}]"#).unwrap();
-    assert_eq!(psbt.outputs[0].tap_tree, Some(tap_tree));
-}
+    let mut invalid = proposal.clone();
+    let duplicate = invalid.unsigned_tx.input[sender_input].clone();
+    invalid.unsigned_tx.input.push(duplicate);
+    invalid.inputs.push(psbt::Input::default());
+    assert_matches!(
+        sign(&invalid, &params),
+        Err(ProposalError::DuplicateInput(_))
+    );
 
-#[test]
-fn test_taproot_sign_missing_witness_utxo() {
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
-    let witness_utxo = psbt.inputs[0].witness_utxo.take();
+    // the payee output can only be decreased if output substitution is allowed
+    let mut decreased_payee = proposal.clone();
+    decreased_payee.unsigned_tx.output[output_index(&proposal, true)].value =
+        Amount::from_sat(5_000);
+    let no_substitution = PayjoinParams {
+        disable_output_substitution: true,
+        ..Default::default()
+    };
+    assert_matches!(
+        sign(&decreased_payee, &no_substitution),
+        Err(ProposalError::MissingOutput(_))
+    );
 
-    let result = wallet.sign(
-        &mut psbt,
-        SignOptions {
-            allow_all_sighashes: true,
-            ..Default::default()
-        },
+    // the receiver can only take from the change output if allowed to
+    let mut contribution = proposal.clone();
+    contribution.unsigned_tx.output[change].value -= Amount::from_sat(100);
+    assert_matches!(
+        sign(&contribution, &params),
+        Err(ProposalError::MissingOutput(_))
     );
+    let original_change = output_index(&original, false);
+    let fee_output = PayjoinParams {
+        additional_fee_output_index: Some(original_change),
+        max_additional_fee_contribution: Amount::from_sat(50),
+        ..Default::default()
+    };
     assert_matches!(
-        result,
-        Err(SignerError::MissingWitnessUtxo),
-        "Signing should have failed with the correct error because the witness_utxo is missing"
+        sign(&contribution, &fee_output),
+        Err(ProposalError::FeeContributionTooHigh(_))
     );
+    let fee_output = PayjoinParams {
+        max_additional_fee_contribution: Amount::from_sat(100),
+        ..fee_output
+    };
+    assert!(sign(&contribution, &fee_output).is_ok());
 
-    // restore the witness_utxo
-    psbt.inputs[0].witness_utxo = witness_utxo;
+    let mut decreased_fee = proposal.clone();
+    decreased_fee.unsigned_tx.output[change].value += Amount::from_sat(10_000);
+    assert_matches!(
+        sign(&decreased_fee, &params),
+        Err(ProposalError::FeeDecreased { .. })
+    );
 
-    let result = wallet.sign(
-        &mut psbt,
-        SignOptions {
-            allow_all_sighashes: true,
-            ..Default::default()
-        },
+    let high_fee_rate = PayjoinParams {
+        min_fee_rate: Some(FeeRate::from_sat_per_vb_unchecked(100)),
+        ..Default::default()
+    };
+    assert_matches!(
+        sign(&proposal, &high_fee_rate),
+        Err(ProposalError::FeeRateTooLow { .. })
     );
 
+    // the fee of an original PSBT whose outputs are worth more than its inputs is unknown
+    let mut inflated = original.clone();
+    inflated.unsigned_tx.output[output_index(&original, true)].value = Amount::MAX_MONEY;
     assert_matches!(
-        result,
-        Ok(true),
-        "Should finalize the input since we can produce signatures"
+        sender.sign_payjoin_proposal(
+            &inflated,
+            proposal.clone(),
+            &payee,
+            &params,
+            SignOptions::default()
+        ),
+        Err(ProposalError::OriginalFeeUnavailable)
     );
 }
 
 #[test]
-fn test_taproot_sign_using_non_witness_utxo() {
-    let (mut wallet, prev_txid) = get_funded_wallet(get_test_tr_single_sig());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let mut builder = wallet.build_tx();
-    builder.drain_to(addr.script_pubkey()).drain_wallet();
-    let mut psbt = builder.finish().unwrap();
+fn test_payjoin_non_rbf_sequence() {
+    let (mut sender, _) = get_funded_wallet_wpkh();
+    let (mut receiver, _) = get_funded_wallet_single("wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/0/*)");
+    let payee = receiver
+        .reveal_next_address(KeychainKind::External)
+        .unwrap()
+        .script_pubkey();
 
-    psbt.inputs[0].witness_utxo = None;
-    psbt.inputs[0].non_witness_utxo =
-        Some(wallet.get_tx(prev_txid).unwrap().tx_node.as_ref().clone());
-    assert!(
-        psbt.inputs[0].non_witness_utxo.is_some(),
-        "Previous tx should be present in the database"
-    );
+    // a final locktime with a non final sequence, which the receiver wouldn't pick by default
+    let mut builder = sender.build_tx();
+    builder
+        .add_recipient(payee.clone(), Amount::from_sat(10_000))
+        .nlocktime(absolute::LockTime::ZERO)
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(2));
+    let mut original = builder.finish().unwrap();
+    for txin in &mut original.unsigned_tx.input {
+        txin.sequence = Sequence::ENABLE_LOCKTIME_NO_RBF;
+    }
+    assert!(sender.sign(&mut original, SignOptions::default()).unwrap());
 
-    let result = wallet.sign(&mut psbt, Default::default());
-    assert!(result.is_ok(), "Signing should have worked");
-    assert!(
-        result.unwrap(),
-        "Should finalize the input since we can produce signatures"
-    );
+    let receiver_utxo = receiver.list_unspent().next().unwrap().outpoint;
+    let mut builder = receiver.build_payjoin_proposal(&original).unwrap();
+    builder.add_utxo(receiver_utxo).unwrap();
+    let mut proposal = builder.finish().unwrap();
+    assert!(proposal
+        .unsigned_tx
+        .input
+        .iter()
+        .all(|txin| txin.sequence == Sequence::ENABLE_LOCKTIME_NO_RBF));
+    assert!(receiver
+        .finalize_payjoin_proposal(&mut proposal, SignOptions::default())
+        .unwrap());
+    assert!(sender
+        .sign_payjoin_proposal(
+            &original,
+            proposal,
+            &payee,
+            &PayjoinParams::default(),
+            SignOptions::default(),
+        )
+        .is_ok());
 }
 
 #[test]
-fn test_taproot_foreign_utxo() {
-    let (mut wallet1, _) = get_funded_wallet_wpkh();
-    let (wallet2, _) = get_funded_wallet(get_test_tr_single_sig());
+fn test_sign_message() {
+    let message = "Hello World";
+    for descriptor in [
+        get_test_wpkh(),
+        get_test_tr_single_sig_xprv(),
+        "sh(wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))",
+        "pkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)",
+        "pkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/44'/1'/0'/0/*)",
+    ] {
+        let wallet = Wallet::new_single_no_persist(descriptor, Network::Regtest).unwrap();
+        let address = wallet.peek_address(KeychainKind::External, 3).address;
+
+        let signature = wallet
+            .sign_message(message, &address, MessageSignatureFormat::Full)
+            .unwrap();
+        assert!(verify_message(&address, message, &signature).unwrap());
+        assert!(!verify_message(&address, "Goodbye World", &signature).unwrap());
+
+        let simple = wallet.sign_message(message, &address, MessageSignatureFormat::Simple);
+        let legacy = wallet.sign_message(message, &address, MessageSignatureFormat::Legacy);
+        match address.address_type() {
+            Some(bitcoin::AddressType::P2pkh) => {
+                assert_matches!(simple, Err(MessageError::UnsupportedAddress));
+                assert!(verify_message(&address, message, &legacy.unwrap()).unwrap());
+            }
+            Some(bitcoin::AddressType::P2sh) => {
+                assert_matches!(simple, Err(MessageError::UnsupportedAddress));
+                assert_matches!(legacy, Err(MessageError::UnsupportedAddress));
+            }
+            _ => {
+                assert!(verify_message(&address, message, &simple.unwrap()).unwrap());
+                assert_matches!(legacy, Err(MessageError::UnsupportedAddress));
+            }
+        }
+    }
+}
 
-    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+#[test]
+fn test_sign_message_errors() {
+    let (wallet, _) = get_funded_wallet_wpkh();
+    let address = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
         .unwrap()
         .assume_checked();
-    let utxo = wallet2.list_unspent().next().unwrap();
-    let psbt_input = wallet2.get_psbt_input(utxo.clone(), None, false).unwrap();
-    let foreign_utxo_satisfaction = wallet2
-        .get_descriptor_for_keychain(KeychainKind::External)
-        .max_weight_to_satisfy()
+    assert_matches!(
+        wallet.sign_message("Hello World", &address, MessageSignatureFormat::Simple),
+        Err(MessageError::NotMine)
+    );
+
+    // a watch-only wallet can't sign
+    let wallet = Wallet::new_single_no_persist(
+        "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*)",
+        Network::Regtest,
+    )
+    .unwrap();
+    let address = wallet.peek_address(KeychainKind::External, 0).address;
+    assert_matches!(
+        wallet.sign_message("Hello World", &address, MessageSignatureFormat::Simple),
+        Err(MessageError::NotFinalized)
+    );
+}
+
+#[test]
+fn test_proof_of_reserves() {
+    let message = "Proof of reserves";
+    for descriptor in [get_test_wpkh(), get_test_tr_single_sig()] {
+        let (wallet, _) = get_funded_wallet(descriptor);
+        let utxos = wallet
+            .list_unspent()
+            .map(|utxo| (utxo.outpoint, utxo.txout))
+            .collect::<Vec<_>>();
+
+        let mut proof = wallet.create_proof_of_reserves(message, None).unwrap();
+        assert_eq!(proof.inputs.len(), utxos.len() + 1);
+        assert_matches!(
+            verify_proof_of_reserves(&proof, message, utxos.clone()),
+            Err(ProofError::NotFinalized(1))
+        );
+        assert!(wallet.sign(&mut proof, SignOptions::default()).unwrap());
+        assert_eq!(
+            verify_proof_of_reserves(&proof, message, utxos.clone()),
+            Ok(wallet.balance().total())
+        );
+
+        // the proof can't be mined
+        assert!(wallet.calculate_fee(&proof.unsigned_tx).is_err());
+
+        assert_matches!(
+            verify_proof_of_reserves(&proof, "Another message", utxos.clone()),
+            Err(ProofError::ChallengeInputMismatch)
+        );
+        assert_matches!(
+            verify_proof_of_reserves(&proof, message, vec![]),
+            Err(ProofError::UnknownUtxo(_))
+        );
+        let mut tampered = proof.clone();
+        tampered.unsigned_tx.output[0].script_pubkey = ScriptBuf::new();
+        assert_matches!(
+            verify_proof_of_reserves(&tampered, message, utxos.clone()),
+            Err(ProofError::InvalidSignature(1))
+        );
+        let mut tampered = proof.clone();
+        tampered.unsigned_tx.output[0].value = Amount::ZERO;
+        assert_matches!(
+            verify_proof_of_reserves(&tampered, message, utxos),
+            Err(ProofError::InAndOutValueNotEqual)
+        );
+    }
+}
+
+#[test]
+fn test_proof_of_reserves_chosen_utxos() {
+    let (wallet, txid) = get_funded_wallet_wpkh();
+    let message = "Proof of reserves";
+    let utxo = wallet.get_utxo(OutPoint::new(txid, 0)).unwrap();
+
+    let mut proof = wallet
+        .create_proof_of_reserves(message, Some(&[utxo.outpoint]))
         .unwrap();
+    assert!(wallet.sign(&mut proof, SignOptions::default()).unwrap());
+    assert_eq!(
+        verify_proof_of_reserves(&proof, message, [(utxo.outpoint, utxo.txout.clone())]),
+        Ok(utxo.txout.value)
+    );
 
-    assert!(
-        psbt_input.non_witness_utxo.is_none(),
-        "`non_witness_utxo` should never be populated for taproot"
+    let unknown = OutPoint::new(txid, 1);
+    assert_matches!(
+        wallet.create_proof_of_reserves(message, Some(&[unknown])),
+        Err(ProofError::UnknownUtxo(outpoint)) if outpoint == unknown
+    );
+    assert_matches!(
+        wallet.create_proof_of_reserves(message, Some(&[])),
+        Err(ProofError::NoInputs)
     );
 
-    let mut builder = wallet1.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), Amount::from_sat(60_000))
-        .add_foreign_utxo(utxo.outpoint, psbt_input, foreign_utxo_satisfaction)
+    // signatures that don't commit to the challenge input are rejected
+    let mut proof = wallet
+        .create_proof_of_reserves(message, Some(&[utxo.outpoint]))
         .unwrap();
-    let psbt = builder.finish().unwrap();
-    let sent_received =
-        wallet1.sent_and_received(&psbt.clone().extract_tx().expect("failed to extract tx"));
-    wallet1.insert_txout(utxo.outpoint, utxo.txout);
-    let fee = check_fee!(wallet1, psbt);
+    proof.inputs[1].sighash_type = Some(EcdsaSighashType::AllPlusAnyoneCanPay.into());
+    let sign_options = SignOptions {
+        allow_all_sighashes: true,
+        ..Default::default()
+    };
+    assert!(wallet.sign(&mut proof, sign_options).unwrap());
+    assert_matches!(
+        verify_proof_of_reserves(&proof, message, [(utxo.outpoint, utxo.txout)]),
+        Err(ProofError::UnsupportedSighashType(1))
+    );
+}
 
-    assert_eq!(
-        sent_received.0 - sent_received.1,
-        Amount::from_sat(10_000) + fee.unwrap_or(Amount::ZERO),
-        "we should have only net spent ~10_000"
+#[test]
+fn test_proof_of_reserves_duplicate_input() {
+    let (wallet, txid) = get_funded_wallet_wpkh();
+    let message = "Proof of reserves";
+    let utxo = wallet.get_utxo(OutPoint::new(txid, 0)).unwrap();
+
+    assert_matches!(
+        wallet.create_proof_of_reserves(message, Some(&[utxo.outpoint, utxo.outpoint])),
+        Err(ProofError::DuplicateInput(outpoint)) if outpoint == utxo.outpoint
     );
 
-    assert!(
-        psbt.unsigned_tx
-            .input
-            .iter()
-            .any(|input| input.previous_output == utxo.outpoint),
-        "foreign_utxo should be in there"
+    // the same output listed twice isn't counted twice
+    let mut proof = wallet
+        .create_proof_of_reserves(message, Some(&[utxo.outpoint]))
+        .unwrap();
+    assert!(wallet.sign(&mut proof, SignOptions::default()).unwrap());
+    let txin = proof.unsigned_tx.input[1].clone();
+    let input = proof.inputs[1].clone();
+    proof.unsigned_tx.input.push(txin);
+    proof.inputs.push(input);
+    proof.unsigned_tx.output[0].value = utxo.txout.value * 2;
+    assert_matches!(
+        verify_proof_of_reserves(&proof, message, [(utxo.outpoint, utxo.txout)]),
+        Err(ProofError::DuplicateInput(outpoint)) if outpoint == utxo.outpoint
     );
 }
 
-fn test_spend_from_wallet(mut wallet: Wallet) {
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+#[test]
+fn test_proof_of_reserves_unsigned_input() {
+    let (wallet, txid) = get_funded_wallet_wpkh();
+    let message = "Proof of reserves";
 
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    // an output spent with a preimage, whose witness has no signature
+    let preimage = [1; 32];
+    let descriptor = format!("wsh(sha256({}))", sha256::Hash::hash(&preimage));
+    let script = miniscript::Descriptor::<bitcoin::PublicKey>::from_str(&descriptor)
+        .unwrap()
+        .explicit_script()
+        .unwrap();
+    let outpoint = OutPoint::new(txid, 1);
+    let txout = TxOut {
+        value: Amount::from_sat(100_000),
+        script_pubkey: ScriptBuf::new_p2wsh(&script.wscript_hash()),
+    };
+
+    let mut proof = wallet
+        .create_proof_of_reserves(message, Some(&[OutPoint::new(txid, 0)]))
+        .unwrap();
+    proof.unsigned_tx.input[1].previous_output = outpoint;
+    proof.unsigned_tx.output[0].value = txout.value;
+    proof.inputs[1] = psbt::Input {
+        witness_utxo: Some(txout.clone()),
+        final_script_witness: Some(bitcoin::Witness::from_slice(&[
+            &preimage,
+            script.as_bytes(),
+        ])),
+        ..Default::default()
+    };
+    assert_matches!(
+        verify_proof_of_reserves(&proof, message, [(outpoint, txout)]),
+        Err(ProofError::MissingSignature(1))
+    );
+}
+
+/// A graph with a confirmed transaction paying `outputs`, as returned by a chain source.
+fn sweep_graph(wallet: &Wallet, outputs: Vec<TxOut>) -> TxGraph<ConfirmationTimeHeightAnchor> {
+    let tx = Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Hash::hash(b"sweep"), 0),
+            ..Default::default()
+        }],
+        output: outputs,
+    };
+    let mut graph = TxGraph::default();
+    let _ = graph.insert_anchor(
+        tx.txid(),
+        ConfirmationTimeHeightAnchor {
+            anchor_block: wallet.latest_checkpoint().block_id(),
+            confirmation_height: wallet.latest_checkpoint().height(),
+            confirmation_time: 0,
+        },
+    );
+    let _ = graph.insert_tx(tx);
+    graph
+}
+
+#[test]
+fn test_sweep_private_key() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let balance = wallet.balance();
+    let secret_key = bitcoin::secp256k1::SecretKey::from_slice(&[0x01; 32]).unwrap();
+    let key = bitcoin::PrivateKey::new(secret_key, Network::Testnet);
+    let sweep = Sweep::from_private_key(key);
+    // pkh, wpkh, sh(wpkh) and tr
+    let spks = sweep.spks();
+    assert_eq!(spks.len(), 4);
+
+    let graph = sweep_graph(
+        &wallet,
+        vec![
+            TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: spks[0].clone(),
+            },
+            TxOut {
+                value: Amount::from_sat(20_000),
+                script_pubkey: spks[2].clone(),
+            },
+            TxOut {
+                value: Amount::from_sat(30_000),
+                script_pubkey: spks[3].clone(),
+            },
+        ],
+    );
+    let mut builder = wallet.build_sweep(&sweep, &graph).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(2));
     let mut psbt = builder.finish().unwrap();
+    assert!(sweep.sign(&mut psbt, SignOptions::default()).unwrap());
 
+    let fee = psbt.fee().unwrap();
+    let tx = psbt.extract(&Secp256k1::new()).expect("valid signatures");
+    assert_eq!(tx.input.len(), 3);
+    assert_eq!(tx.output.len(), 1);
+    assert!(wallet.is_mine(&tx.output[0].script_pubkey));
+    assert_eq!(tx.output[0].value, Amount::from_sat(60_000) - fee);
     assert!(
-        wallet.sign(&mut psbt, Default::default()).unwrap(),
-        "Unable to finalize tx"
+        fee >= FeeRate::from_sat_per_vb_unchecked(2)
+            .fee_wu(tx.weight())
+            .unwrap()
     );
-}
-
-//     #[test]
-//     fn test_taproot_key_spend() {
-//         let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
-//         test_spend_from_wallet(wallet);
 
-//         let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
-//         test_spend_from_wallet(wallet);
-//     }
+    // the swept key is not part of the wallet
+    assert!(spks.into_iter().all(|spk| !wallet.is_mine(&spk)));
+    assert_eq!(wallet.balance(), balance);
+}
 
 #[test]
-fn test_taproot_no_key_spend() {
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let mut psbt = builder.finish().unwrap();
+fn test_sweep_descriptor() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let sweep = Sweep::from_descriptor(
+        "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/0/*)",
+        Network::Testnet,
+    )
+    .unwrap()
+    .lookahead(10);
+    let spks = sweep.spks();
+    assert_eq!(spks.len(), 10);
 
-    assert!(
-        wallet
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    sign_with_tap_internal_key: false,
-                    ..Default::default()
-                },
-            )
-            .unwrap(),
-        "Unable to finalize tx"
+    let graph = sweep_graph(
+        &wallet,
+        vec![
+            TxOut {
+                value: Amount::from_sat(25_000),
+                script_pubkey: spks[3].clone(),
+            },
+            TxOut {
+                value: Amount::from_sat(25_000),
+                script_pubkey: spks[7].clone(),
+            },
+        ],
     );
+    let mut builder = wallet.build_sweep(&sweep, &graph).unwrap();
+    builder.fee_rate(FeeRate::from_sat_per_vb_unchecked(1));
+    let mut psbt = builder.finish().unwrap();
+    assert!(sweep.sign(&mut psbt, SignOptions::default()).unwrap());
 
-    assert!(psbt.inputs.iter().all(|i| i.tap_key_sig.is_none()));
+    let fee = psbt.fee().unwrap();
+    let tx = psbt.extract(&Secp256k1::new()).expect("valid signatures");
+    assert_eq!(tx.input.len(), 2);
+    assert_eq!(tx.output[0].value, Amount::from_sat(50_000) - fee);
 }
 
 #[test]
-fn test_taproot_script_spend() {
-    let (wallet, _) = get_funded_wallet(get_test_tr_with_taptree());
-    test_spend_from_wallet(wallet);
+fn test_sweep_errors() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    assert_matches!(
+        Sweep::from_descriptor(
+            "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)",
+            Network::Testnet,
+        ),
+        Err(SweepError::MissingSecretKey)
+    );
 
-    let (wallet, _) = get_funded_wallet(get_test_tr_with_taptree_xprv());
-    test_spend_from_wallet(wallet);
+    let secret_key = bitcoin::secp256k1::SecretKey::from_slice(&[0x01; 32]).unwrap();
+    let key = bitcoin::PrivateKey::new(secret_key, Network::Testnet);
+    let sweep = Sweep::from_private_key(key);
+    assert_matches!(
+        wallet.build_sweep(&sweep, &TxGraph::<ConfirmationTimeHeightAnchor>::default()),
+        Err(SweepError::NoUtxos)
+    );
+
+    // outputs that are already spent are not swept
+    let mut graph = sweep_graph(
+        &wallet,
+        vec![TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: sweep.spks()[1].clone(),
+        }],
+    );
+    let funding_txid = graph.full_txs().next().unwrap().txid;
+    let _ = graph.insert_tx(Transaction {
+        version: transaction::Version::TWO,
+        lock_time: absolute::LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(funding_txid, 0),
+            ..Default::default()
+        }],
+        output: vec![],
+    });
+    assert_matches!(wallet.build_sweep(&sweep, &graph), Err(SweepError::NoUtxos));
+}
+
+/// The output key of a silent payment to `scan_secret` and `spend_key`, as computed by the
+/// receiver from the public keys of the inputs.
+fn silent_payment_output_key(
+    scan_secret: &SecretKey,
+    spend_key: PublicKey,
+    outpoints: &[OutPoint],
+    input_keys: &[PublicKey],
+) -> XOnlyPublicKey {
+    let tagged_hash = |tag: &[u8], data: &[&[u8]]| {
+        let tag = sha256::Hash::hash(tag);
+        let mut engine = sha256::Hash::engine();
+        engine.input(tag.as_ref());
+        engine.input(tag.as_ref());
+        for data in data {
+            engine.input(data);
+        }
+        Scalar::from_be_bytes(sha256::Hash::from_engine(engine).to_byte_array()).unwrap()
+    };
+    let secp = Secp256k1::new();
+    let input_key = PublicKey::combine_keys(&input_keys.iter().collect::<Vec<_>>()).unwrap();
+    let outpoint = outpoints
+        .iter()
+        .map(bitcoin::consensus::serialize)
+        .min()
+        .unwrap();
+    let input_hash = tagged_hash(b"BIP0352/Inputs", &[&outpoint, &input_key.serialize()]);
+    let shared_secret = input_key
+        .mul_tweak(&secp, &Scalar::from(*scan_secret))
+        .unwrap()
+        .mul_tweak(&secp, &input_hash)
+        .unwrap();
+    let tweak = tagged_hash(
+        b"BIP0352/SharedSecret",
+        &[&shared_secret.serialize(), &0u32.to_be_bytes()],
+    );
+    spend_key
+        .add_exp_tweak(&secp, &tweak)
+        .unwrap()
+        .x_only_public_key()
+        .0
+}
+
+fn silent_payment_keys() -> (SecretKey, SecretKey, SilentPaymentAddress) {
+    let secp = Secp256k1::new();
+    let scan_secret = SecretKey::from_slice(&[0x02; 32]).unwrap();
+    let spend_secret = SecretKey::from_slice(&[0x03; 32]).unwrap();
+    let address = SilentPaymentAddress::new(
+        scan_secret.public_key(&secp),
+        spend_secret.public_key(&secp),
+        Network::Regtest,
+    );
+    (scan_secret, spend_secret, address)
 }
 
 #[test]
-fn test_taproot_script_spend_sign_all_leaves() {
-    use bdk_wallet::signer::TapLeavesOptions;
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+fn test_silent_payment_recipient() {
+    let secp = Secp256k1::new();
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let (scan_secret, spend_secret, address) = silent_payment_keys();
+    let addr = Address::from_str("bcrt1qc6fweuf4xjvz4x3gx3t9e0fh4hvqyu2qw4wvxm")
+        .unwrap()
+        .assume_checked();
 
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    builder
+        .ordering(bdk_wallet::wallet::tx_builder::TxOrdering::Untouched)
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(10_000))
+        .add_silent_payment_recipient(address, Amount::from_sat(25_000));
     let mut psbt = builder.finish().unwrap();
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let tx = psbt.extract(&secp).expect("valid signatures");
 
-    assert!(
-        wallet
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    tap_leaves_options: TapLeavesOptions::All,
-                    ..Default::default()
-                },
-            )
-            .unwrap(),
-        "Unable to finalize tx"
-    );
-
-    assert!(psbt
-        .inputs
+    let output = &tx.output[1];
+    assert_eq!(output.value, Amount::from_sat(25_000));
+    assert!(output.script_pubkey.is_p2tr());
+    let key = bitcoin::PrivateKey::from_wif("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW")
+        .unwrap()
+        .public_key(&secp);
+    let outpoints = tx
+        .input
         .iter()
-        .all(|i| i.tap_script_sigs.len() == i.tap_scripts.len()));
+        .map(|txin| txin.previous_output)
+        .collect::<Vec<_>>();
+    let output_key = silent_payment_output_key(
+        &scan_secret,
+        spend_secret.public_key(&secp),
+        &outpoints,
+        &vec![key.inner; outpoints.len()],
+    );
+    assert_eq!(output.script_pubkey.as_bytes()[2..], output_key.serialize());
 }
 
 #[test]
-fn test_taproot_script_spend_sign_include_some_leaves() {
-    use bdk_wallet::signer::TapLeavesOptions;
-    use bitcoin::taproot::TapLeafHash;
-
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+fn test_silent_payment_recipient_taproot() {
+    let secp = Secp256k1::new();
+    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_xprv());
+    let (scan_secret, spend_secret, address) = silent_payment_keys();
 
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
+    builder.add_silent_payment_recipient(address, Amount::from_sat(25_000));
     let mut psbt = builder.finish().unwrap();
-    let mut script_leaves: Vec<_> = psbt.inputs[0]
-        .tap_scripts
-        .clone()
-        .values()
-        .map(|(script, version)| TapLeafHash::from_script(script, *version))
-        .collect();
-    let included_script_leaves = vec![script_leaves.pop().unwrap()];
-    let excluded_script_leaves = script_leaves;
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let tx = psbt.extract(&secp).expect("valid signatures");
 
-    assert!(
-        wallet
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    tap_leaves_options: TapLeavesOptions::Include(included_script_leaves.clone()),
-                    ..Default::default()
-                },
+    // the public key of a taproot input is the output key of the spent output
+    let (outpoints, input_keys): (Vec<_>, Vec<_>) = tx
+        .input
+        .iter()
+        .map(|txin| {
+            let prevout = wallet.get_utxo(txin.previous_output).unwrap().txout;
+            let output_key =
+                XOnlyPublicKey::from_slice(&prevout.script_pubkey.as_bytes()[2..]).unwrap();
+            (
+                txin.previous_output,
+                PublicKey::from_x_only_public_key(output_key, Parity::Even),
             )
-            .unwrap(),
-        "Unable to finalize tx"
+        })
+        .unzip();
+    let output_key = silent_payment_output_key(
+        &scan_secret,
+        spend_secret.public_key(&secp),
+        &outpoints,
+        &input_keys,
     );
-
-    assert!(psbt.inputs[0]
-        .tap_script_sigs
+    let output = tx
+        .output
         .iter()
-        .all(|s| included_script_leaves.contains(&s.0 .1)
-            && !excluded_script_leaves.contains(&s.0 .1)));
+        .find(|txout| txout.value == Amount::from_sat(25_000))
+        .unwrap();
+    assert_eq!(output.script_pubkey.as_bytes()[2..], output_key.serialize());
 }
 
 #[test]
-fn test_taproot_script_spend_sign_exclude_some_leaves() {
-    use bdk_wallet::signer::TapLeavesOptions;
-    use bitcoin::taproot::TapLeafHash;
+fn test_silent_payment_recipient_errors() {
+    let (_, _, address) = silent_payment_keys();
 
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let mainnet =
+        SilentPaymentAddress::new(address.scan_key(), address.spend_key(), Network::Bitcoin);
+    let mut builder = wallet.build_tx();
+    builder.add_silent_payment_recipient(mainnet, Amount::from_sat(25_000));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::SilentPayment(
+            SilentPaymentError::NetworkMismatch(0)
+        ))
+    );
 
     let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let mut psbt = builder.finish().unwrap();
-    let mut script_leaves: Vec<_> = psbt.inputs[0]
-        .tap_scripts
-        .clone()
-        .values()
-        .map(|(script, version)| TapLeafHash::from_script(script, *version))
-        .collect();
-    let included_script_leaves = [script_leaves.pop().unwrap()];
-    let excluded_script_leaves = script_leaves;
+    builder.add_silent_payment_recipient(address, Amount::from_sat(100));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::OutputBelowDustLimit(0))
+    );
 
-    assert!(
-        wallet
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    tap_leaves_options: TapLeavesOptions::Exclude(excluded_script_leaves.clone()),
-                    ..Default::default()
-                },
-            )
-            .unwrap(),
-        "Unable to finalize tx"
+    // scripts with several keys are not eligible inputs
+    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_csv());
+    let mut builder = wallet.build_tx();
+    builder.add_silent_payment_recipient(address, Amount::from_sat(25_000));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        ))
     );
 
-    assert!(psbt.inputs[0]
-        .tap_script_sigs
-        .iter()
-        .all(|s| included_script_leaves.contains(&s.0 .1)
-            && !excluded_script_leaves.contains(&s.0 .1)));
+    // neither are foreign inputs
+    let (mut wallet1, _) = get_funded_wallet_wpkh();
+    let (wallet2, _) =
+        get_funded_wallet("wpkh(cVbZ8ovhye9AoAHFsqobCf7LxbXDAECy9Kb8TZdfsDYMZGBUyCnm)");
+    let utxo = wallet2.list_unspent().next().unwrap();
+    let psbt_input = psbt::Input {
+        witness_utxo: Some(utxo.txout.clone()),
+        ..Default::default()
+    };
+    let satisfaction_weight = wallet2
+        .get_descriptor_for_keychain(KeychainKind::External)
+        .max_weight_to_satisfy()
+        .unwrap();
+    let mut builder = wallet1.build_tx();
+    builder
+        .add_silent_payment_recipient(address, Amount::from_sat(25_000))
+        .only_witness_utxo()
+        .add_foreign_utxo(utxo.outpoint, psbt_input, satisfaction_weight)
+        .unwrap();
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::SilentPayment(SilentPaymentError::IneligibleInput(outpoint)))
+            if outpoint == utxo.outpoint
+    );
 }
 
 #[test]
-fn test_taproot_script_spend_sign_no_leaves() {
-    use bdk_wallet::signer::TapLeavesOptions;
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_with_taptree_both_priv());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+fn test_bump_fee_silent_payment() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let (_, _, address) = silent_payment_keys();
+    let outpoint = receive_output_in_latest_block(&mut wallet, 25_000);
+
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_silent_payment_recipient(address, Amount::from_sat(25_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.txid();
+    let silent_payment_output = tx
+        .output
+        .iter()
+        .find(|txout| !wallet.is_mine(&txout.script_pubkey))
+        .unwrap()
+        .clone();
+    let inputs = tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .collect::<Vec<_>>();
+    assert!(!inputs.contains(&outpoint));
+    wallet
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
+        .unwrap();
+
+    // the fee is paid from the change, so the silent payment output stays valid
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .fixed_inputs()
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(50));
+    let psbt = builder.finish().unwrap();
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(
+        tx.input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<_>>(),
+        inputs
+    );
+    assert!(tx.output.contains(&silent_payment_output));
+
+    // no input is added to pay a higher fee
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .fixed_inputs()
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(300));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds { .. }
+        ))
+    );
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .fixed_inputs()
+        .add_utxo(outpoint)
+        .unwrap()
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(50));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::FixedInputs(input))
+            if input == outpoint
+    );
+}
 
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let mut psbt = builder.finish().unwrap();
+#[test]
+fn test_bump_fee_add_input_p2tr_recipient() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let outpoint = receive_output_in_latest_block(&mut wallet, 25_000);
+    let addr = Address::from_str("tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c")
+        .unwrap()
+        .assume_checked();
 
+    let mut builder = wallet.build_tx().coin_selection(LargestFirstCoinSelection);
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(49_000))
+        .enable_rbf();
+    let psbt = builder.finish().unwrap();
+    let tx = psbt.extract_tx().expect("failed to extract tx");
+    let txid = tx.txid();
+    assert!(!tx.input.iter().any(|txin| txin.previous_output == outpoint));
     wallet
-        .sign(
-            &mut psbt,
-            SignOptions {
-                tap_leaves_options: TapLeavesOptions::None,
-                ..Default::default()
-            },
-        )
+        .insert_tx(tx, ConfirmationTime::Unconfirmed { last_seen: 0 })
         .unwrap();
 
-    assert!(psbt.inputs.iter().all(|i| i.tap_script_sigs.is_empty()));
+    // a payment to a taproot address can add inputs to pay a higher fee
+    let mut builder = wallet.build_fee_bump(txid).unwrap();
+    builder
+        .add_utxo(outpoint)
+        .unwrap()
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(50));
+    let psbt = builder.finish().unwrap();
+    let tx = &psbt.unsigned_tx;
+    assert_eq!(tx.input.len(), 2);
+    assert!(tx.input.iter().any(|txin| txin.previous_output == outpoint));
+    assert!(tx
+        .output
+        .iter()
+        .any(|txout| txout.script_pubkey == addr.script_pubkey()
+            && txout.value == Amount::from_sat(49_000)));
 }
 
 #[test]
-fn test_taproot_sign_derive_index_from_psbt() {
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig_xprv());
-
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-
-    let mut builder = wallet.build_tx();
-    builder.add_recipient(addr.script_pubkey(), Amount::from_sat(25_000));
-    let mut psbt = builder.finish().unwrap();
-
-    // re-create the wallet with an empty db
-    let wallet_empty = Wallet::new_no_persist(
-        get_test_tr_single_sig_xprv(),
-        get_test_tr_single_sig(),
+fn test_silent_payment_index() {
+    let secp = Secp256k1::new();
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let (scan_secret, spend_secret, address) = silent_payment_keys();
+    let mut receiver = IndexedTxGraph::<ConfirmationTimeHeightAnchor, _>::new(
+        SilentPaymentIndex::new(scan_secret, address.spend_key()),
+    );
+    let _ = receiver.index.add_label(1);
+    let labelled_address = SilentPaymentAddress::new(
+        address.scan_key(),
+        receiver.index.label_spend_key(1),
         Network::Regtest,
-    )
-    .unwrap();
-
-    // signing with an empty db means that we will only look at the psbt to infer the
-    // derivation index
-    assert!(
-        wallet_empty.sign(&mut psbt, Default::default()).unwrap(),
-        "Unable to finalize tx"
     );
-}
 
-#[test]
-fn test_taproot_sign_explicit_sighash_all() {
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .sighash(TapSighashType::All.into())
-        .drain_wallet();
+        .add_silent_payment_recipient(address, Amount::from_sat(10_000))
+        .add_silent_payment_recipient(labelled_address, Amount::from_sat(20_000));
     let mut psbt = builder.finish().unwrap();
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let tx = psbt.extract(&secp).expect("valid signatures");
 
-    let result = wallet.sign(&mut psbt, Default::default());
-    assert!(
-        result.is_ok(),
-        "Signing should work because SIGHASH_ALL is safe"
-    )
+    for txin in &tx.input {
+        let prevout = wallet.get_utxo(txin.previous_output).unwrap().txout;
+        let _ = receiver.insert_txout(txin.previous_output, prevout);
+    }
+    let changeset = receiver.batch_insert_relevant([(&tx, [])]);
+    assert_eq!(changeset.indexer.outputs.len(), 2);
+    for (outpoint, output) in receiver.index.outputs() {
+        let txout = &tx.output[outpoint.vout as usize];
+        let expected_label = match txout.value.to_sat() {
+            10_000 => None,
+            20_000 => Some(1),
+            _ => panic!("unexpected output"),
+        };
+        assert_eq!(output.label, expected_label);
+        let key = output.secret_key(&spend_secret).x_only_public_key(&secp).0;
+        assert_eq!(txout.script_pubkey.as_bytes()[2..], key.serialize());
+    }
 }
 
 #[test]
-fn test_taproot_sign_non_default_sighash() {
-    let sighash = TapSighashType::NonePlusAnyoneCanPay;
+fn test_payment_uri() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let uri = wallet
+        .parse_payment_uri(
+            "bitcoin:bcrt1qc6fweuf4xjvz4x3gx3t9e0fh4hvqyu2qw4wvxm?amount=0.00025&message=Order%2042",
+        )
+        .unwrap();
+    assert_eq!(uri.message.as_deref(), Some("Order 42"));
 
-    let (mut wallet, _) = get_funded_wallet(get_test_tr_single_sig());
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
     let mut builder = wallet.build_tx();
-    builder
-        .drain_to(addr.script_pubkey())
-        .sighash(sighash.into())
-        .drain_wallet();
-    let mut psbt = builder.finish().unwrap();
-
-    let witness_utxo = psbt.inputs[0].witness_utxo.take();
+    builder.add_uri_recipient(&uri).unwrap();
+    let psbt = builder.finish().unwrap();
+    assert!(psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .any(|txout| txout.script_pubkey == uri.address.script_pubkey()
+            && txout.value == Amount::from_sat(25_000)));
 
-    let result = wallet.sign(&mut psbt, Default::default());
-    assert!(
-        result.is_err(),
-        "Signing should have failed because the TX uses non-standard sighashes"
-    );
+    let mut no_amount = uri.clone();
+    no_amount.amount = None;
     assert_matches!(
-        result,
-        Err(SignerError::NonStandardSighash),
-        "Signing failed with the wrong error type"
-    );
-
-    // try again after opting-in
-    let result = wallet.sign(
-        &mut psbt,
-        SignOptions {
-            allow_all_sighashes: true,
-            ..Default::default()
-        },
-    );
-    assert!(
-        result.is_err(),
-        "Signing should have failed because the witness_utxo is missing"
+        wallet.build_tx().add_uri_recipient(&no_amount),
+        Err(PaymentUriError::MissingAmount)
     );
     assert_matches!(
-        result,
-        Err(SignerError::MissingWitnessUtxo),
-        "Signing failed with the wrong error type"
+        wallet.parse_payment_uri("bitcoin:bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"),
+        Err(PaymentUriError::NetworkMismatch {
+            required: Network::Regtest,
+            found: Network::Bitcoin,
+        })
     );
 
-    // restore the witness_utxo
-    psbt.inputs[0].witness_utxo = witness_utxo;
-
-    let result = wallet.sign(
-        &mut psbt,
-        SignOptions {
-            allow_all_sighashes: true,
-            ..Default::default()
-        },
+    // the URI of an address of the wallet is parsed back
+    let address = wallet.reveal_next_address(KeychainKind::External).unwrap();
+    let mut request = PaymentUri::from(address);
+    request.amount = Some(Amount::from_sat(1_500));
+    request.label = Some("Alice".to_string());
+    assert_eq!(
+        wallet.parse_payment_uri(&request.to_string()).unwrap(),
+        request
     );
+    assert!(wallet.is_mine(&request.address.script_pubkey()));
+}
 
-    assert!(result.is_ok(), "Signing should have worked");
-    assert!(
-        result.unwrap(),
-        "Should finalize the input since we can produce signatures"
+#[test]
+fn test_consolidation() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let deposits = (0..20)
+        .map(|i| receive_output_in_latest_block(&mut wallet, 10_000 + i))
+        .collect::<Vec<_>>();
+    // too small to be spent at 5 sat/vb
+    let dust = receive_output_in_latest_block(&mut wallet, 300);
+    let unconfirmed = receive_output(
+        &mut wallet,
+        20_000,
+        ConfirmationTime::Unconfirmed { last_seen: 0 },
     );
+    let locked = receive_output_in_latest_block(&mut wallet, 30_000);
+    wallet.lock_utxo(locked, None);
+
+    let fee_rate = FeeRate::from_sat_per_vb_unchecked(5);
+    let long_term_feerate = FeeRate::from_sat_per_vb_unchecked(20);
+    let consolidations = wallet.plan_consolidations(fee_rate, long_term_feerate, Weight::MAX_BLOCK);
+    assert_eq!(consolidations.len(), 1);
+    let consolidation = &consolidations[0];
+    // the deposits, and the 50_000 sats output of the funded wallet
+    assert_eq!(consolidation.utxos.len(), 21);
+    assert_eq!(consolidation.utxos[..20], deposits[..]);
+    assert!(consolidation.savings > Amount::ZERO);
+    for outpoint in [dust, unconfirmed, locked] {
+        assert!(!consolidation.utxos.contains(&outpoint));
+    }
 
-    let extracted = psbt.extract_tx().expect("failed to extract tx");
+    let mut psbt = wallet
+        .build_consolidation(consolidation)
+        .unwrap()
+        .finish()
+        .unwrap();
+    let fee = psbt.fee().unwrap();
+    assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+    let tx = psbt.extract_tx().expect("valid fee rate");
+    assert_eq!(tx.input.len(), 21);
+    assert_eq!(tx.output.len(), 1);
     assert_eq!(
-        *extracted.input[0].witness.to_vec()[0].last().unwrap(),
-        sighash as u8,
-        "The signature should have been made with the right sighash"
+        wallet.derivation_of_spk(&tx.output[0].script_pubkey),
+        Some((KeychainKind::Internal, 0))
+    );
+    // the address is staged, not committed
+    let descriptor_id = wallet
+        .public_descriptor(KeychainKind::Internal)
+        .descriptor_id();
+    assert_eq!(
+        wallet
+            .staged()
+            .indexed_tx_graph
+            .indexer
+            .last_revealed
+            .get(&descriptor_id),
+        Some(&0)
     );
+    assert!(tx.weight() <= consolidation.weight);
+    assert!(fee >= fee_rate.fee_wu(tx.weight()).unwrap());
 }
 
 #[test]
-fn test_spend_coinbase() {
-    let (desc, change_desc) = get_test_wpkh_with_change_desc();
-    let mut wallet = Wallet::new_no_persist(desc, change_desc, Network::Regtest).unwrap();
+fn test_consolidation_max_weight() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    for i in 0..20 {
+        receive_output_in_latest_block(&mut wallet, 10_000 + i);
+    }
 
-    let confirmation_height = 5;
-    wallet
-        .insert_checkpoint(BlockId {
-            height: confirmation_height,
-            hash: BlockHash::all_zeros(),
+    let max_weight = Weight::from_wu(2_000);
+    let consolidations = wallet.plan_consolidations(
+        FeeRate::from_sat_per_vb_unchecked(1),
+        FeeRate::from_sat_per_vb_unchecked(20),
+        max_weight,
+    );
+    assert!(consolidations.len() > 1);
+    let mut spent = consolidations
+        .iter()
+        .flat_map(|consolidation| {
+            assert!(consolidation.weight <= max_weight);
+            assert!(consolidation.utxos.len() >= 2);
+            consolidation.utxos.clone()
         })
-        .unwrap();
-    let coinbase_tx = Transaction {
-        version: transaction::Version::ONE,
-        lock_time: absolute::LockTime::ZERO,
-        input: vec![TxIn {
-            previous_output: OutPoint::null(),
-            ..Default::default()
-        }],
-        output: vec![TxOut {
-            script_pubkey: wallet
-                .next_unused_address(KeychainKind::External)
-                .unwrap()
-                .script_pubkey(),
-            value: Amount::from_sat(25_000),
-        }],
-    };
-    wallet
-        .insert_tx(
-            coinbase_tx,
-            ConfirmationTime::Confirmed {
-                height: confirmation_height,
-                time: 30_000,
-            },
-        )
-        .unwrap();
+        .collect::<Vec<_>>();
+    let count = spent.len();
+    spent.sort();
+    spent.dedup();
+    assert_eq!(spent.len(), count);
+
+    for consolidation in &consolidations {
+        let mut psbt = wallet
+            .build_consolidation(consolidation)
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert!(wallet.sign(&mut psbt, SignOptions::default()).unwrap());
+        assert!(psbt.extract_tx().unwrap().weight() <= max_weight);
+    }
+}
 
-    let not_yet_mature_time = confirmation_height + COINBASE_MATURITY - 1;
-    let maturity_time = confirmation_height + COINBASE_MATURITY;
+#[test]
+fn test_consolidation_not_worth_it() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    for i in 0..20 {
+        receive_output_in_latest_block(&mut wallet, 10_000 + i);
+    }
 
-    let balance = wallet.balance();
-    assert_eq!(
-        balance,
-        Balance {
-            immature: Amount::from_sat(25_000),
-            trusted_pending: Amount::ZERO,
-            untrusted_pending: Amount::ZERO,
-            confirmed: Amount::ZERO
-        }
+    // spending the UTXOs is cheaper in the long term
+    let consolidations = wallet.plan_consolidations(
+        FeeRate::from_sat_per_vb_unchecked(20),
+        FeeRate::from_sat_per_vb_unchecked(20),
+        Weight::MAX_BLOCK,
+    );
+    assert!(consolidations.is_empty());
+
+    let consolidation = Consolidation {
+        utxos: vec![OutPoint::new(Txid::all_zeros(), 0)],
+        fee_rate: FeeRate::from_sat_per_vb_unchecked(1),
+        weight: Weight::ZERO,
+        fee: Amount::ZERO,
+        savings: Amount::ZERO,
+    };
+    assert_matches!(
+        wallet.build_consolidation(&consolidation),
+        Err(ConsolidationError::UnknownUtxo(outpoint)) if outpoint == consolidation.utxos[0]
     );
+}
 
-    // We try to create a transaction, only to notice that all
-    // our funds are unspendable
+#[test]
+fn test_create_tx_subtract_fee_from_recipient() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
     let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
         .unwrap()
         .assume_checked();
+    let fee_rate = FeeRate::from_sat_per_vb_unchecked(5);
     let mut builder = wallet.build_tx();
     builder
-        .add_recipient(addr.script_pubkey(), balance.immature / 2)
-        .current_height(confirmation_height);
-    assert!(matches!(
-        builder.finish(),
-        Err(CreateTxError::CoinSelection(
-            coin_selection::Error::InsufficientFunds {
-                needed: _,
-                available: 0
-            }
-        ))
-    ));
-
-    // Still unspendable...
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), balance.immature / 2)
-        .current_height(not_yet_mature_time);
-    assert_matches!(
-        builder.finish(),
-        Err(CreateTxError::CoinSelection(
-            coin_selection::Error::InsufficientFunds {
-                needed: _,
-                available: 0
-            }
-        ))
-    );
-
-    wallet
-        .insert_checkpoint(BlockId {
-            height: maturity_time,
-            hash: BlockHash::all_zeros(),
-        })
-        .unwrap();
-    let balance = wallet.balance();
-    assert_eq!(
-        balance,
-        Balance {
-            immature: Amount::ZERO,
-            trusted_pending: Amount::ZERO,
-            untrusted_pending: Amount::ZERO,
-            confirmed: Amount::from_sat(25_000)
-        }
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .subtract_fee_from(0)
+        .fee_rate(fee_rate)
+        .ordering(TxOrdering::Untouched);
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt).unwrap();
+
+    // the change output doesn't pay for the fee
+    assert_eq!(psbt.unsigned_tx.output.len(), 2);
+    assert_eq!(
+        psbt.unsigned_tx.output[0].value,
+        Amount::from_sat(25_000) - fee
     );
-    let mut builder = wallet.build_tx();
-    builder
-        .add_recipient(addr.script_pubkey(), balance.confirmed / 2)
-        .current_height(maturity_time);
-    builder.finish().unwrap();
+    assert_eq!(psbt.unsigned_tx.output[1].value, Amount::from_sat(25_000));
+    assert_fee_rate!(psbt, fee, fee_rate, @add_signature);
 }
 
 #[test]
-fn test_allow_dust_limit() {
-    let (mut wallet, _) = get_funded_wallet(get_test_single_sig_cltv());
-
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
+fn test_create_tx_subtract_fee_send_all() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let fee_rate = FeeRate::from_sat_per_vb_unchecked(5);
 
+    // the coins are selected at the target feerate, so the balance must also cover the fee of
+    // the input spending it
     let mut builder = wallet.build_tx();
-
-    builder.add_recipient(addr.script_pubkey(), Amount::ZERO);
-
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(50_000))
+        .subtract_fee_from(0)
+        .fee_rate(fee_rate);
     assert_matches!(
         builder.finish(),
-        Err(CreateTxError::OutputBelowDustLimit(0))
+        Err(CreateTxError::CoinSelection(
+            coin_selection::Error::InsufficientFunds {
+                needed: 50_340,
+                available: 50_000
+            }
+        ))
     );
 
+    // the recipient pays the 213 sats of the transaction without inputs and the 340 sats of the
+    // input, and receives the 340 sats left, too few for a change output
     let mut builder = wallet.build_tx();
-
     builder
-        .allow_dust(true)
-        .add_recipient(addr.script_pubkey(), Amount::ZERO);
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(49_660))
+        .subtract_fee_from(0)
+        .fee_rate(fee_rate);
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt).unwrap();
 
-    assert!(builder.finish().is_ok());
+    assert_eq!(fee, Amount::from_sat(553));
+    assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    assert_eq!(psbt.unsigned_tx.output[0].value, Amount::from_sat(49_447));
+    assert_fee_rate!(psbt, fee, fee_rate, @add_signature);
 }
 
 #[test]
-fn test_fee_rate_sign_no_grinding_high_r() {
-    // Our goal is to obtain a transaction with a signature with high-R (71 bytes
-    // instead of 70). We then check that our fee rate and fee calculation is
-    // alright.
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let fee_rate = FeeRate::from_sat_per_vb_unchecked(1);
+fn test_create_tx_subtract_fee_change_of_input_fee() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let fee_rate = FeeRate::from_sat_per_vb_unchecked(5);
+
+    // the 540 sats left are enough for a change output once the recipient pays for the input
+    // and for the 155 sats of the change output
     let mut builder = wallet.build_tx();
-    let mut data = PushBytesBuf::try_from(vec![0]).unwrap();
     builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(49_460))
+        .subtract_fee_from(0)
         .fee_rate(fee_rate)
-        .add_data(&data);
-    let mut psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
-    let (op_return_vout, _) = psbt
-        .unsigned_tx
-        .output
-        .iter()
-        .enumerate()
-        .find(|(_n, i)| i.script_pubkey.is_op_return())
-        .unwrap();
+        .ordering(TxOrdering::Untouched);
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt).unwrap();
 
-    let mut sig_len: usize = 0;
-    // We try to sign many different times until we find a longer signature (71 bytes)
-    while sig_len < 71 {
-        // Changing the OP_RETURN data will make the signature change (but not the fee, until
-        // data[0] is small enough)
-        data.as_mut_bytes()[0] += 1;
-        psbt.unsigned_tx.output[op_return_vout].script_pubkey = ScriptBuf::new_op_return(&data);
-        // Clearing the previous signature
-        psbt.inputs[0].partial_sigs.clear();
-        // Signing
-        wallet
-            .sign(
-                &mut psbt,
-                SignOptions {
-                    remove_partial_sigs: false,
-                    try_finalize: false,
-                    allow_grinding: false,
-                    ..Default::default()
-                },
-            )
-            .unwrap();
-        // We only have one key in the partial_sigs map, this is a trick to retrieve it
-        let key = psbt.inputs[0].partial_sigs.keys().next().unwrap();
-        sig_len = psbt.inputs[0].partial_sigs[key].sig.serialize_der().len();
-    }
-    // Actually finalizing the transaction...
-    wallet
-        .sign(
-            &mut psbt,
-            SignOptions {
-                remove_partial_sigs: false,
-                allow_grinding: false,
-                ..Default::default()
-            },
-        )
-        .unwrap();
-    // ...and checking that everything is fine
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate);
+    assert_eq!(fee, Amount::from_sat(708));
+    assert_eq!(psbt.unsigned_tx.output.len(), 2);
+    assert_eq!(psbt.unsigned_tx.output[0].value, Amount::from_sat(48_752));
+    assert_eq!(psbt.unsigned_tx.output[1].value, Amount::from_sat(540));
+    assert_fee_rate!(psbt, fee, fee_rate, @add_signature);
 }
 
 #[test]
-fn test_fee_rate_sign_grinding_low_r() {
-    // Our goal is to obtain a transaction with a signature with low-R (70 bytes)
-    // by setting the `allow_grinding` signing option as true.
-    // We then check that our fee rate and fee calculation is alright and that our
-    // signature is 70 bytes.
-    let (mut wallet, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-    let addr = wallet.next_unused_address(KeychainKind::External).unwrap();
-    let fee_rate = FeeRate::from_sat_per_vb_unchecked(1);
+fn test_create_tx_subtract_fee_from_several_recipients() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr1 = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let addr2 = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")
+        .unwrap()
+        .assume_checked();
+    let addr3 = Address::from_str("bcrt1q3qtze4ys45tgdvguj66zrk4fu6hq3a3v9pfly5")
+        .unwrap()
+        .assume_checked();
+    let fee_rate = FeeRate::from_sat_per_vb_unchecked(7);
     let mut builder = wallet.build_tx();
     builder
-        .drain_to(addr.script_pubkey())
-        .drain_wallet()
-        .fee_rate(fee_rate);
-    let mut psbt = builder.finish().unwrap();
-    let fee = check_fee!(wallet, psbt);
-
-    wallet
-        .sign(
-            &mut psbt,
-            SignOptions {
-                remove_partial_sigs: false,
-                allow_grinding: true,
-                ..Default::default()
-            },
-        )
-        .unwrap();
+        .add_recipient(addr1.script_pubkey(), Amount::from_sat(20_000))
+        .add_recipient(addr2.script_pubkey(), Amount::from_sat(5_000))
+        .add_recipient(addr3.script_pubkey(), Amount::from_sat(10_000))
+        .subtract_fee_from(0)
+        .subtract_fee_from(2)
+        .fee_rate(fee_rate)
+        .ordering(TxOrdering::Untouched);
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt).unwrap();
 
-    let key = psbt.inputs[0].partial_sigs.keys().next().unwrap();
-    let sig_len = psbt.inputs[0].partial_sigs[key].sig.serialize_der().len();
-    assert_eq!(sig_len, 70);
-    assert_fee_rate!(psbt, fee.unwrap_or(Amount::ZERO), fee_rate);
+    let outputs = &psbt.unsigned_tx.output;
+    let share1 = Amount::from_sat(20_000) - outputs[0].value;
+    let share3 = Amount::from_sat(10_000) - outputs[2].value;
+    assert_eq!(outputs[1].value, Amount::from_sat(5_000));
+    assert_eq!(outputs[3].value, Amount::from_sat(15_000));
+    assert_eq!(share1 + share3, fee);
+    assert_eq!(share3, fee / 2);
+    assert_eq!(share1, fee / 2 + fee % 2);
+    assert_fee_rate!(psbt, fee, fee_rate, @add_signature);
 }
 
 #[test]
-fn test_taproot_load_descriptor_duplicated_keys() {
-    // Added after issue https://github.com/bitcoindevkit/bdk/issues/760
-    //
-    // Having the same key in multiple taproot leaves is safe and should be accepted by BDK
+fn test_create_tx_subtract_fee_errors() {
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
 
-    let (wallet, _) = get_funded_wallet(get_test_tr_dup_keys());
-    let addr = wallet.peek_address(KeychainKind::External, 0);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .subtract_fee_from(1);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::InvalidSubtractFeeIndex(1))
+    );
 
-    assert_eq!(
-        addr.to_string(),
-        "bcrt1pvysh4nmh85ysrkpwtrr8q8gdadhgdejpy6f9v424a8v9htjxjhyqw9c5s5"
+    // the fee would leave the first recipient with a dust amount
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(1_000))
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(25_000))
+        .subtract_fee_from(0)
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(5));
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::OutputBelowDustLimitAfterFee(0))
+    );
+
+    // or with nothing at all
+    let mut builder = wallet.build_tx();
+    builder
+        .add_recipient(addr.script_pubkey(), Amount::from_sat(1_000))
+        .subtract_fee_from(0)
+        .fee_rate(FeeRate::from_sat_per_vb_unchecked(20))
+        .allow_dust(true);
+    assert_matches!(
+        builder.finish(),
+        Err(CreateTxError::OutputBelowDustLimitAfterFee(0))
     );
 }
 
 #[test]
-/// The wallet should re-use previously allocated change addresses when the tx using them is cancelled
-fn test_tx_cancellation() {
-    macro_rules! new_tx {
-        ($wallet:expr) => {{
-            let addr = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")
-                .unwrap()
-                .assume_checked();
-            let mut builder = $wallet.build_tx();
-            builder.add_recipient(addr.script_pubkey(), Amount::from_sat(10_000));
-
-            let psbt = builder.finish().unwrap();
-
-            psbt
-        }};
-    }
-
-    let (mut wallet, _) =
-        get_funded_wallet_with_change(get_test_wpkh(), get_test_tr_single_sig_xprv());
+fn test_payment_batch() {
+    let addr1 = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+        .unwrap()
+        .assume_checked();
+    let addr2 = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")
+        .unwrap()
+        .assume_checked();
+    let payment = |addr: &Address, sats, subtract_fee| Payment {
+        script_pubkey: addr.script_pubkey(),
+        amount: Amount::from_sat(sats),
+        subtract_fee,
+    };
 
-    let psbt1 = new_tx!(wallet);
-    let change_derivation_1 = psbt1
-        .unsigned_tx
-        .output
-        .iter()
-        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
+    let mut batch = PaymentBatch::new();
+    batch
+        .add_payment(payment(&addr1, 10_000, true))
+        .unwrap()
+        .add_payment(payment(&addr2, 8_000, false))
         .unwrap();
-    assert_eq!(change_derivation_1, (KeychainKind::Internal, 0));
-
-    let psbt2 = new_tx!(wallet);
-
-    let change_derivation_2 = psbt2
-        .unsigned_tx
-        .output
-        .iter()
-        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
+    let mut pending = PaymentBatch::new();
+    pending
+        .add_payment(payment(&addr1, 5_000, true))
+        .unwrap()
+        .add_payment(payment(&addr2, 2_000, true))
         .unwrap();
-    assert_eq!(change_derivation_2, (KeychainKind::Internal, 1));
-
-    wallet.cancel_tx(&psbt1.extract_tx().expect("failed to extract tx"));
+    batch.merge(pending).unwrap();
 
-    let psbt3 = new_tx!(wallet);
-    let change_derivation_3 = psbt3
-        .unsigned_tx
-        .output
-        .iter()
-        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
-        .unwrap();
-    assert_eq!(change_derivation_3, (KeychainKind::Internal, 0));
+    // the payments to the same script paying the fee are merged
+    assert_eq!(
+        batch.payments(),
+        &[
+            payment(&addr1, 15_000, true),
+            payment(&addr2, 8_000, false),
+            payment(&addr2, 2_000, true),
+        ]
+    );
+    assert_eq!(batch.total(), Amount::from_sat(25_000));
 
-    let psbt3 = new_tx!(wallet);
-    let change_derivation_3 = psbt3
-        .unsigned_tx
-        .output
-        .iter()
-        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
+    // the total amount of the batch can't overflow
+    let mut overflowing = batch.clone();
+    assert_eq!(
+        overflowing.add_payment(payment(&addr1, u64::MAX - 10_000, true)),
+        Err(PaymentBatchError::AmountOverflow)
+    );
+    let mut large = PaymentBatch::new();
+    large
+        .add_payment(payment(&addr2, u64::MAX - 10_000, false))
         .unwrap();
-    assert_eq!(change_derivation_3, (KeychainKind::Internal, 2));
-
-    wallet.cancel_tx(&psbt3.extract_tx().expect("failed to extract tx"));
+    assert_eq!(
+        overflowing.merge(large),
+        Err(PaymentBatchError::AmountOverflow)
+    );
+    assert_eq!(overflowing, batch);
 
-    let psbt3 = new_tx!(wallet);
-    let change_derivation_4 = psbt3
-        .unsigned_tx
-        .output
-        .iter()
-        .find_map(|txout| wallet.derivation_of_spk(&txout.script_pubkey))
-        .unwrap();
-    assert_eq!(change_derivation_4, (KeychainKind::Internal, 2));
-}
+    let (mut wallet, _) = get_funded_wallet_wpkh();
+    let fee_rate = FeeRate::from_sat_per_vb_unchecked(4);
+    let mut builder = wallet.build_tx();
+    builder
+        .add_payment_batch(&batch)
+        .fee_rate(fee_rate)
+        .ordering(TxOrdering::Untouched);
+    let psbt = builder.finish().unwrap();
+    let fee = check_fee!(wallet, psbt).unwrap();
 
-#[test]
-fn test_thread_safety() {
-    fn thread_safe<T: Send + Sync>() {}
-    thread_safe::<Wallet>(); // compiles only if true
+    let outputs = &psbt.unsigned_tx.output;
+    assert_eq!(outputs.len(), 4);
+    assert_eq!(outputs[1].value, Amount::from_sat(8_000));
+    assert_eq!(outputs[3].value, Amount::from_sat(25_000));
+    assert_eq!(
+        Amount::from_sat(17_000) - outputs[0].value - outputs[2].value,
+        fee
+    );
+    assert_fee_rate!(psbt, fee, fee_rate, @add_signature);
 }
@@ -1,7 +1,7 @@
 pub use anyhow;
 use anyhow::Context;
 use bdk_coin_select::{coin_select_bnb, CoinSelector, CoinSelectorOpt, WeightedValue};
-use bdk_file_store::Store;
+use bdk_file_store::{FileError, Store};
 use serde::{de::DeserializeOwned, Serialize};
 use std::{cmp::Reverse, collections::BTreeMap, path::PathBuf, sync::Mutex, time::Duration};
 
@@ -723,6 +723,10 @@ where
 
     let mut db_backend = match Store::<C>::open_or_create_new(db_magic, &args.db_path) {
         Ok(db_backend) => db_backend,
+        // the files written before the format header was introduced must be migrated
+        Err(FileError::MissingFormatHeader) => {
+            Store::<C>::migrate::<C, _>(db_magic, &args.db_path)?
+        }
         // we cannot return `err` directly as it has lifetime `'m`
         Err(err) => return Err(anyhow::anyhow!("failed to init db backend: {:?}", err)),
     };
@@ -6,16 +6,22 @@ const PARALLEL_REQUESTS: usize = 1;
 use std::{collections::BTreeSet, io::Write, str::FromStr};
 
 use bdk_esplora::{esplora_client, EsploraExt};
-use bdk_file_store::Store;
+use bdk_file_store::{FileError, Store};
 use bdk_wallet::{
     bitcoin::{Address, Amount, Network},
+    wallet::{ChangeSet, LegacyChangeSet},
     KeychainKind, SignOptions, Wallet,
 };
 
 fn main() -> Result<(), anyhow::Error> {
     let db_path = std::env::temp_dir().join("bdk-esplora-example");
-    let db =
-        Store::<bdk_wallet::wallet::ChangeSet>::open_or_create_new(DB_MAGIC.as_bytes(), db_path)?;
+    let db = match Store::<ChangeSet>::open_or_create_new(DB_MAGIC.as_bytes(), &db_path) {
+        // the files written before the format header was introduced must be migrated
+        Err(FileError::MissingFormatHeader) => {
+            Store::migrate::<LegacyChangeSet, _>(DB_MAGIC.as_bytes(), &db_path)?
+        }
+        db => db?,
+    };
     let external_descriptor = "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/0/*)";
     let internal_descriptor = "wpkh(tprv8ZgxMBicQKsPdy6LMhUtFHAgpocR8GC6QmwMSFpZs7h6Eziw3SpThFfczTDh5rW2krkqffa11UpX3XkeTTB2FvzZKWXqPY54Y6Rq4AQ5R8L/84'/1'/0'/1/*)";
 
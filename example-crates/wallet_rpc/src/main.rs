@@ -2,10 +2,10 @@ use bdk_bitcoind_rpc::{
     bitcoincore_rpc::{Auth, Client, RpcApi},
     Emitter,
 };
-use bdk_file_store::Store;
+use bdk_file_store::{FileError, Store};
 use bdk_wallet::{
     bitcoin::{Block, Network, Transaction},
-    wallet::Wallet,
+    wallet::{ChangeSet, LegacyChangeSet, Wallet},
 };
 use clap::{self, Parser};
 use std::{path::PathBuf, sync::mpsc::sync_channel, thread::spawn, time::Instant};
@@ -86,15 +86,15 @@ fn main() -> anyhow::Result<()> {
     );
 
     let start_load_wallet = Instant::now();
-    let mut wallet = Wallet::new_or_load(
-        &args.descriptor,
-        &args.change_descriptor,
-        Store::<bdk_wallet::wallet::ChangeSet>::open_or_create_new(
-            DB_MAGIC.as_bytes(),
-            args.db_path,
-        )?,
-        args.network,
-    )?;
+    let db = match Store::<ChangeSet>::open_or_create_new(DB_MAGIC.as_bytes(), &args.db_path) {
+        // the files written before the format header was introduced must be migrated
+        Err(FileError::MissingFormatHeader) => {
+            Store::migrate::<LegacyChangeSet, _>(DB_MAGIC.as_bytes(), &args.db_path)?
+        }
+        db => db?,
+    };
+    let mut wallet =
+        Wallet::new_or_load(&args.descriptor, &args.change_descriptor, db, args.network)?;
     println!(
         "Loaded wallet in {}s",
         start_load_wallet.elapsed().as_secs_f32()
@@ -6,6 +6,14 @@ extern crate std;
 #[macro_use]
 extern crate alloc;
 extern crate bdk_chain;
+#[cfg(feature = "wallet")]
+extern crate bdk_wallet;
+
+#[cfg(all(test, feature = "wallet"))]
+#[macro_use]
+extern crate assert_matches;
+#[cfg(all(test, feature = "wallet"))]
+extern crate proptest;
 
 use alloc::vec::Vec;
 use bdk_chain::{
@@ -21,6 +29,9 @@ pub use coin_selector::*;
 mod bnb;
 pub use bnb::*;
 
+#[cfg(feature = "wallet")]
+pub mod wallet;
+
 /// Txin "base" fields include `outpoint` (32+4) and `nSequence` (4). This does not include
 /// `scriptSigLen` or `scriptSig`.
 pub const TXIN_BASE_WEIGHT: u32 = (32 + 4 + 4) * 4;
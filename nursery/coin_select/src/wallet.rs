@@ -0,0 +1,613 @@
+//! Metric based coin selection for `bdk_wallet`
+//!
+//! [`MetricCoinSelection`] adapts the [`CoinSelector`] to the [`CoinSelectionAlgorithm`] trait of
+//! `bdk_wallet`, so that it can be used with [`TxBuilder::coin_selection`]. It runs a branch and
+//! bound search over the optional UTXOs, scoring each candidate selection with a
+//! [`SelectionMetric`].
+//!
+//! [`TxBuilder::coin_selection`]: bdk_wallet::TxBuilder::coin_selection
+
+use super::*;
+
+use bdk_wallet::wallet::coin_selection::{
+    decide_change, CoinSelectionAlgorithm, CoinSelectionResult, Error,
+};
+use bdk_wallet::WeightedUtxo;
+use bitcoin::consensus::encode::serialize;
+use bitcoin::{FeeRate, Script, TxIn, Weight};
+
+/// The default maximum number of rounds of the branch and bound search.
+const DEFAULT_ROUNDS: usize = 100_000;
+
+/// The metric minimized by [`MetricCoinSelection`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionMetric {
+    /// Minimize the fee paid by the transaction, including the cost of the change output
+    LowestFee,
+    /// Avoid a change output, minimizing the excess given to the miners
+    ///
+    /// If no selection can avoid a change output, the largest UTXOs are selected instead.
+    Changeless,
+    /// Minimize the waste of the selection, as defined by Bitcoin Core
+    ///
+    /// The waste compares the cost of spending the selected UTXOs and the change output now with
+    /// the cost of spending them at `long_term_feerate`, plus the excess given to the miners when
+    /// there's no change output.
+    Waste {
+        /// The feerate expected to be paid in the long term
+        long_term_feerate: FeeRate,
+    },
+}
+
+/// Coin selection backed by the metric based [`CoinSelector`]
+///
+/// Unlike the `BranchAndBoundCoinSelection` of `bdk_wallet`, the change output is sized from the
+/// actual drain script, and the [`SelectionMetric`] decides which selection is the best.
+///
+/// ## Example
+///
+/// ```
+/// # #[macro_use] extern crate bdk_wallet;
+/// # extern crate bdk_coin_select;
+/// # use std::str::FromStr;
+/// # use bdk_wallet::bitcoin::*;
+/// use bdk_coin_select::wallet::MetricCoinSelection;
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+///
+/// # let mut wallet = doctest_wallet!();
+/// let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt")?
+///     .require_network(Network::Testnet)?;
+/// let psbt = {
+///     let mut builder = wallet
+///         .build_tx()
+///         .coin_selection(MetricCoinSelection::lowest_fee());
+///     builder.add_recipient(to_address.script_pubkey(), Amount::from_sat(50_000));
+///     builder.finish()?
+/// };
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MetricCoinSelection {
+    metric: SelectionMetric,
+    drain_satisfaction_weight: usize,
+    rounds: usize,
+}
+
+impl MetricCoinSelection {
+    /// Create a new instance minimizing `metric`
+    pub fn new(metric: SelectionMetric) -> Self {
+        Self {
+            metric,
+            // signature len (1WU) + signature and sighash (72WU)
+            // + pubkey len (1WU) + pubkey (33WU)
+            drain_satisfaction_weight: 1 + 72 + 1 + 33,
+            rounds: DEFAULT_ROUNDS,
+        }
+    }
+
+    /// Create a new instance minimizing the fee, see [`SelectionMetric::LowestFee`]
+    pub fn lowest_fee() -> Self {
+        Self::new(SelectionMetric::LowestFee)
+    }
+
+    /// Create a new instance avoiding change, see [`SelectionMetric::Changeless`]
+    pub fn changeless() -> Self {
+        Self::new(SelectionMetric::Changeless)
+    }
+
+    /// Create a new instance minimizing the waste, see [`SelectionMetric::Waste`]
+    pub fn waste(long_term_feerate: FeeRate) -> Self {
+        Self::new(SelectionMetric::Waste { long_term_feerate })
+    }
+
+    /// Set the satisfaction weight of the change output, used to estimate the cost of spending
+    /// it in the future
+    ///
+    /// Defaults to the satisfaction weight of a P2WPKH output.
+    pub fn drain_satisfaction_weight(mut self, weight: usize) -> Self {
+        self.drain_satisfaction_weight = weight;
+        self
+    }
+
+    /// Set the maximum number of rounds of the branch and bound search
+    pub fn rounds(mut self, rounds: usize) -> Self {
+        self.rounds = rounds;
+        self
+    }
+}
+
+impl Default for MetricCoinSelection {
+    fn default() -> Self {
+        Self::lowest_fee()
+    }
+}
+
+impl CoinSelectionAlgorithm for MetricCoinSelection {
+    fn coin_select(
+        &self,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        target_amount: u64,
+        drain_script: &Script,
+    ) -> Result<CoinSelectionResult, Error> {
+        let required_count = required_utxos.len();
+        let groups = required_utxos
+            .into_iter()
+            .chain(optional_utxos)
+            .map(|u| OutputGroup::new(u, fee_rate))
+            .collect::<Vec<_>>();
+        // the wallet weighs inputs with `TxIn::segwit_weight`, the segwit header is already part
+        // of `target_amount`
+        let candidates = groups
+            .iter()
+            .map(|group| WeightedValue {
+                value: group.weighted_utxo.utxo.txout().value.to_sat(),
+                weight: input_weight(group.weighted_utxo.satisfaction_weight),
+                input_count: 1,
+                is_segwit: false,
+            })
+            .collect::<Vec<_>>();
+
+        let target_feerate = sat_per_wu(fee_rate);
+        let opts = CoinSelectorOpt {
+            target_value: Some(target_amount),
+            max_extra_target: 0,
+            target_feerate,
+            long_term_feerate: match self.metric {
+                SelectionMetric::Waste { long_term_feerate } => Some(sat_per_wu(long_term_feerate)),
+                _ => None,
+            },
+            min_absolute_fee: 0,
+            base_weight: 0,
+            drain_weight: ((serialize(drain_script).len() + 8) * 4) as u32,
+            spend_drain_weight: input_weight(self.drain_satisfaction_weight),
+            min_drain_value: drain_script.dust_value().to_sat(),
+        };
+
+        let mut selector = CoinSelector::new(&candidates, &opts);
+        (0..required_count).for_each(|index| {
+            selector.select(index);
+        });
+
+        let selected = match self.metric {
+            SelectionMetric::LowestFee => select_bnb(selector.clone(), self.rounds, false),
+            SelectionMetric::Changeless => select_bnb(selector.clone(), self.rounds, true),
+            SelectionMetric::Waste { .. } => coin_select_bnb(self.rounds, selector.clone()),
+        }
+        .unwrap_or(selector);
+
+        // the wallet rounds up the fee of each input, which can cost a few more sats than what
+        // the selector estimated
+        let selected_value = |selector: &CoinSelector| {
+            selector
+                .selected_indexes()
+                .map(|index| groups[index].effective_value)
+                .sum::<i64>()
+        };
+        let selected = select_largest_first(selected, |selector| {
+            selected_value(selector) >= target_amount as i64
+        })
+        .ok_or_else(|| {
+            // the needed amount assumes that all the UTXOs worth spending are selected
+            let (fees, available) = groups
+                .iter()
+                .enumerate()
+                .filter(|(index, group)| *index < required_count || group.effective_value > 0)
+                .fold((0, 0), |(fees, value), (_, group)| {
+                    (
+                        fees + group.fee,
+                        value + group.weighted_utxo.utxo.txout().value.to_sat(),
+                    )
+                });
+            Error::InsufficientFunds {
+                needed: target_amount + fees,
+                available,
+            }
+        })?;
+
+        let remaining_amount = (selected_value(&selected) - target_amount as i64) as u64;
+        let excess = decide_change(remaining_amount, fee_rate, drain_script);
+
+        let (required, optional): (Vec<_>, Vec<_>) = groups
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| selected.is_selected(*index))
+            .partition(|(index, _)| *index < required_count);
+
+        let selected = optional.into_iter().chain(required).map(|(_, group)| group);
+        let (fee_amount, selected) = selected.fold((0, Vec::new()), |(fee, mut utxos), group| {
+            utxos.push(group.weighted_utxo.utxo);
+            (fee + group.fee, utxos)
+        });
+        Ok(CoinSelectionResult {
+            selected,
+            fee_amount,
+            excess,
+            privacy_score: None,
+        })
+    }
+}
+
+/// A UTXO with the fee of spending it, as computed by the wallet
+struct OutputGroup {
+    weighted_utxo: WeightedUtxo,
+    fee: u64,
+    effective_value: i64,
+}
+
+impl OutputGroup {
+    fn new(weighted_utxo: WeightedUtxo, fee_rate: FeeRate) -> Self {
+        let fee = (fee_rate
+            * Weight::from_wu(u64::from(input_weight(weighted_utxo.satisfaction_weight))))
+        .to_sat();
+        let effective_value = weighted_utxo.utxo.txout().value.to_sat() as i64 - fee as i64;
+        OutputGroup {
+            weighted_utxo,
+            fee,
+            effective_value,
+        }
+    }
+}
+
+/// Weight of an input spent with `satisfaction_weight`, as counted by the wallet
+fn input_weight(satisfaction_weight: usize) -> u32 {
+    (TxIn::default().segwit_weight().to_wu() + satisfaction_weight as u64) as u32
+}
+
+fn sat_per_wu(fee_rate: FeeRate) -> f32 {
+    fee_rate.to_sat_per_kwu() as f32 / 1000.0
+}
+
+/// Branch and bound search over the unselected candidates of `selector` minimizing the fee, or
+/// the excess of changeless selections if `changeless` is set
+///
+/// The candidates of positive effective value are explored by descending effective value.
+fn select_bnb(selector: CoinSelector, rounds: usize, changeless: bool) -> Option<CoinSelector> {
+    let feerate = selector.opts.target_feerate;
+    let mut pool = selector
+        .unselected()
+        .filter(|(_, candidate)| candidate.effective_value(feerate) > 0)
+        .collect::<Vec<_>>();
+    pool.sort_by_key(|(_, candidate)| -candidate.effective_value(feerate));
+
+    let target_abs = selector.opts.target_value.unwrap_or(0);
+    let target_eff = selector.effective_target();
+    let strategy = move |bnb: &Bnb<i64>| -> (BranchStrategy, Option<i64>) {
+        // backtrack if the remaining candidates can't reach the target
+        if bnb.selection.selected_absolute_value() + bnb.rem_abs < target_abs
+            || bnb.selection.selected_effective_value() + bnb.rem_eff < target_eff
+        {
+            return (BranchStrategy::SkipBoth, None);
+        }
+        // backtrack if adding candidates can only make the selection worse: the fee can't be
+        // lower than the one of the current weight, and the excess only grows
+        let lower_bound = if changeless {
+            bnb.selection.current_excess()
+        } else {
+            (bnb.selection.current_weight() as f32 * feerate).ceil() as i64
+        };
+        if lower_bound > bnb.best_score {
+            return (BranchStrategy::SkipBoth, None);
+        }
+
+        let at_end = bnb.pool_pos >= bnb.pool.len();
+        let selection = match bnb.selection.finish() {
+            Ok(selection) => selection,
+            Err(_) if at_end => return (BranchStrategy::SkipBoth, None),
+            Err(_) => return (BranchStrategy::Continue, None),
+        };
+        let can_drain = selection
+            .excess_strategies
+            .contains_key(&ExcessStrategyKind::ToDrain);
+
+        if changeless {
+            let score = Some(selection.excess as i64).filter(|_| !can_drain);
+            (BranchStrategy::SkipBoth, score)
+        } else {
+            let score = selection
+                .excess_strategies
+                .values()
+                .map(|strategy| strategy.fee as i64)
+                .min();
+            // adding a candidate may allow creating a change output, which is cheaper than
+            // giving the excess to the miners
+            if at_end || can_drain {
+                (BranchStrategy::SkipBoth, score)
+            } else {
+                (BranchStrategy::Continue, score)
+            }
+        }
+    };
+
+    Bnb::new(selector, pool, i64::MAX)
+        .into_iter(&strategy)
+        .take(rounds)
+        .reduce(|best, current| current.or(best))?
+}
+
+/// Select the unselected candidates of positive effective value by descending effective value,
+/// until `is_enough` is satisfied
+fn select_largest_first<'a>(
+    mut selector: CoinSelector<'a>,
+    is_enough: impl Fn(&CoinSelector) -> bool,
+) -> Option<CoinSelector<'a>> {
+    let feerate = selector.opts.target_feerate;
+    let mut unselected = selector
+        .unselected()
+        .filter(|(_, candidate)| candidate.effective_value(feerate) > 0)
+        .collect::<Vec<_>>();
+    unselected.sort_by_key(|(_, candidate)| -candidate.effective_value(feerate));
+    let mut unselected = unselected
+        .into_iter()
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>()
+        .into_iter();
+
+    while !is_enough(&selector) {
+        selector.select(unselected.next()?);
+    }
+    Some(selector)
+}
+
+#[cfg(test)]
+mod test {
+    use bdk_chain::ConfirmationTime;
+    use bdk_wallet::wallet::coin_selection::{
+        BranchAndBoundCoinSelection, Excess, LargestFirstCoinSelection, OldestFirstCoinSelection,
+    };
+    use bdk_wallet::{KeychainKind, LocalOutput, Utxo};
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, TxOut, Txid, WPubkeyHash};
+    use proptest::prelude::*;
+
+    use super::*;
+
+    // signature len (1WU) + signature and sighash (72WU)
+    // + pubkey len (1WU) + pubkey (33WU)
+    const P2WPKH_SATISFACTION_SIZE: usize = 1 + 72 + 1 + 33;
+
+    fn utxos(values: &[u64]) -> Vec<WeightedUtxo> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| WeightedUtxo {
+                satisfaction_weight: P2WPKH_SATISFACTION_SIZE,
+                utxo: Utxo::Local(LocalOutput {
+                    outpoint: OutPoint::new(Txid::all_zeros(), index as u32),
+                    txout: TxOut {
+                        value: Amount::from_sat(*value),
+                        script_pubkey: ScriptBuf::new(),
+                    },
+                    keychain: KeychainKind::External,
+                    is_spent: false,
+                    derivation_index: 42,
+                    confirmation_time: ConfirmationTime::Confirmed {
+                        height: index as u32,
+                        time: 0,
+                    },
+                }),
+            })
+            .collect()
+    }
+
+    fn drain_script() -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros())
+    }
+
+    // total fee paid by the transaction, including the excess given to the miners
+    fn total_fee(result: &CoinSelectionResult) -> u64 {
+        result.fee_amount
+            + match result.excess {
+                Excess::NoChange {
+                    remaining_amount, ..
+                } => remaining_amount,
+                Excess::Change { fee, .. } => fee,
+            }
+    }
+
+    // at 10 sat/vb, an input costs 680 sats and a change output 310 sats
+    const FEE_RATE: FeeRate = FeeRate::from_sat_per_kwu(2500);
+
+    #[test]
+    fn test_metric_coin_selection_changeless() {
+        let result = MetricCoinSelection::changeless()
+            .coin_select(
+                vec![],
+                utxos(&[100_000, 50_000, 30_000, 20_000]),
+                FEE_RATE,
+                80_000 - 2 * 680,
+                &drain_script(),
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 2);
+        assert_eq!(result.selected_amount(), 80_000);
+        assert_matches!(
+            result.excess,
+            Excess::NoChange {
+                remaining_amount: 0,
+                ..
+            }
+        );
+    }
+
+    #[test]
+    fn test_metric_coin_selection_lowest_fee() {
+        // a single input with a change output is cheaper than two inputs without
+        let result = MetricCoinSelection::lowest_fee()
+            .coin_select(
+                vec![],
+                utxos(&[100_000, 50_000, 30_000]),
+                FEE_RATE,
+                80_000 - 2 * 680,
+                &drain_script(),
+            )
+            .unwrap();
+
+        assert_eq!(result.selected_amount(), 100_000);
+        assert_matches!(result.excess, Excess::Change { fee: 310, .. });
+        assert_eq!(total_fee(&result), 680 + 310);
+
+        // unless the excess of the changeless selection is lower than the change output cost
+        let result = MetricCoinSelection::lowest_fee()
+            .coin_select(
+                vec![],
+                utxos(&[100_000, 50_100]),
+                FEE_RATE,
+                50_000 - 680,
+                &drain_script(),
+            )
+            .unwrap();
+
+        assert_eq!(result.selected_amount(), 50_100);
+        assert_eq!(total_fee(&result), 680 + 100);
+    }
+
+    #[test]
+    fn test_metric_coin_selection_waste() {
+        let result = MetricCoinSelection::waste(FeeRate::from_sat_per_vb_unchecked(1))
+            .coin_select(
+                vec![],
+                utxos(&[100_000, 50_000, 30_000, 20_000]),
+                FEE_RATE,
+                50_000 - 680,
+                &drain_script(),
+            )
+            .unwrap();
+
+        assert_eq!(result.selected_amount(), 50_000);
+        assert_matches!(
+            result.excess,
+            Excess::NoChange {
+                remaining_amount: 0,
+                ..
+            }
+        );
+    }
+
+    #[test]
+    fn test_metric_coin_selection_required() {
+        let utxos = utxos(&[100_000, 50_000, 30_000]);
+        let result = MetricCoinSelection::lowest_fee()
+            .coin_select(
+                utxos[..1].to_vec(),
+                utxos[1..].to_vec(),
+                FEE_RATE,
+                120_000,
+                &drain_script(),
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 2);
+        assert!(result.selected.contains(&utxos[0].utxo));
+    }
+
+    #[test]
+    fn test_metric_coin_selection_insufficient_funds() {
+        let result = MetricCoinSelection::lowest_fee().coin_select(
+            vec![],
+            utxos(&[100_000, 50_000, 500]),
+            FEE_RATE,
+            150_000,
+            &drain_script(),
+        );
+
+        // the 500 sats UTXO costs more than its value
+        assert_matches!(
+            result,
+            Err(Error::InsufficientFunds {
+                needed: 151_360,
+                available: 150_000,
+            })
+        );
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig {
+            cases: 64,
+            ..Default::default()
+        })]
+
+        #[test]
+        fn prop_metric_coin_selection_succeeds_like_largest_first(
+            values in prop::collection::vec(1_000..500_000_u64, 1..10),
+            target_amount in 1_000..1_000_000_u64,
+            fee_rate in 1..50_u64,
+        ) {
+            let fee_rate = FeeRate::from_sat_per_vb_unchecked(fee_rate);
+            let utxos = utxos(&values);
+            let largest_first = LargestFirstCoinSelection
+                .coin_select(vec![], utxos.clone(), fee_rate, target_amount, &drain_script());
+
+            for algorithm in [
+                MetricCoinSelection::lowest_fee(),
+                MetricCoinSelection::changeless(),
+                MetricCoinSelection::waste(FeeRate::from_sat_per_vb_unchecked(5)),
+            ] {
+                let result = algorithm
+                    .coin_select(vec![], utxos.clone(), fee_rate, target_amount, &drain_script());
+                prop_assert_eq!(result.is_ok(), largest_first.is_ok());
+                if let Ok(result) = result {
+                    prop_assert!(
+                        result.selected_amount() >= target_amount + result.fee_amount
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn prop_lowest_fee_is_not_beaten(
+            values in prop::collection::vec(1_000..500_000_u64, 1..10),
+            target_amount in 1_000..1_000_000_u64,
+            fee_rate in 1..50_u64,
+        ) {
+            let fee_rate = FeeRate::from_sat_per_vb_unchecked(fee_rate);
+            let utxos = utxos(&values);
+            let lowest_fee = MetricCoinSelection::lowest_fee()
+                .coin_select(vec![], utxos.clone(), fee_rate, target_amount, &drain_script());
+
+            let results = [
+                LargestFirstCoinSelection
+                    .coin_select(vec![], utxos.clone(), fee_rate, target_amount, &drain_script()),
+                OldestFirstCoinSelection
+                    .coin_select(vec![], utxos.clone(), fee_rate, target_amount, &drain_script()),
+                BranchAndBoundCoinSelection::default()
+                    .coin_select(vec![], utxos.clone(), fee_rate, target_amount, &drain_script()),
+            ];
+            for result in results.iter().flatten() {
+                let lowest_fee = lowest_fee.as_ref().expect("other algorithms succeeded");
+                prop_assert!(total_fee(lowest_fee) <= total_fee(result));
+            }
+        }
+
+        #[test]
+        fn prop_changeless_finds_branch_and_bound_matches(
+            values in prop::collection::vec(1_000..500_000_u64, 1..10),
+            target_amount in 1_000..1_000_000_u64,
+            fee_rate in 1..50_u64,
+        ) {
+            let fee_rate = FeeRate::from_sat_per_vb_unchecked(fee_rate);
+            let utxos = utxos(&values);
+            let branch_and_bound = BranchAndBoundCoinSelection::default()
+                .coin_select(vec![], utxos.clone(), fee_rate, target_amount, &drain_script());
+
+            if let Ok(CoinSelectionResult {
+                excess: Excess::NoChange { remaining_amount: bnb_remaining, .. },
+                ..
+            }) = branch_and_bound
+            {
+                let changeless = MetricCoinSelection::changeless()
+                    .coin_select(vec![], utxos, fee_rate, target_amount, &drain_script())
+                    .expect("branch and bound succeeded");
+                match changeless.excess {
+                    Excess::NoChange { remaining_amount, .. } => {
+                        prop_assert!(remaining_amount <= bnb_remaining)
+                    }
+                    Excess::Change { .. } => prop_assert!(false, "changeless selection has change"),
+                }
+            }
+        }
+    }
+}